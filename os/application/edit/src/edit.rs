@@ -0,0 +1,311 @@
+#![no_std]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use io::file::{set_termios, termios, window_size};
+use io::read::{read_byte, read_escape_sequence, read_utf8, EscapeKey};
+use io::{print, println};
+use runtime::{env, fs};
+use runtime::fs::File;
+use syscall::error::Errno;
+use syscall::file::{STDIN, STDOUT};
+use syscall::ioctl::{Termios, WindowSize};
+
+/// Used, if the size of the terminal is unknown (e.g. for a serial console).
+const DEFAULT_SIZE: WindowSize = WindowSize { columns: 80, rows: 25 };
+/// Number of spaces inserted by Tab.
+const TAB_WIDTH: usize = 4;
+const HELP: &str = "^S Save  ^Q Quit  ^K Cut line  ^U Paste line";
+
+/// Key pressed by the user.
+#[derive(Copy, Clone, PartialEq)]
+enum Key {
+    Char(char),
+    Control(u8),
+    Enter,
+    Backspace,
+    Tab,
+    /// Keys without a character are sent as escape sequences.
+    Escape(EscapeKey),
+    Unknown,
+}
+
+/// Text being edited and the state of the screen.
+struct Editor {
+    path: String,
+    lines: Vec<Vec<char>>,
+    /// Position of the cursor in the text (line and character).
+    row: usize,
+    column: usize,
+    /// First line and first column shown on the screen.
+    top: usize,
+    left: usize,
+    size: WindowSize,
+    modified: bool,
+    /// Lines removed with Ctrl+K, which are inserted again by Ctrl+U.
+    cut: Vec<Vec<char>>,
+    /// The previous key has been Ctrl+K (otherwise cutting a line starts a new block).
+    cutting: bool,
+    message: String,
+}
+
+/// Small editor in the style of nano. The terminal is switched to raw mode, so that every key press is read immediately,
+/// and the screen is drawn with ANSI escape sequences.
+#[no_mangle]
+pub fn main() {
+    let Some(path) = env::args().into_iter().nth(1) else {
+        println!("Usage: edit <file>");
+        return;
+    };
+
+    if !path.starts_with('/') {
+        println!("edit: Paths must be absolute");
+        return;
+    }
+
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(Errno::NotFound) => String::new(),
+        Err(err) => {
+            println!("edit: Failed to open '{}' ({:?})", path, err);
+            return;
+        }
+    };
+
+    let Ok(settings) = termios(STDIN) else {
+        println!("edit: Standard input is not a terminal");
+        return;
+    };
+
+    set_termios(STDIN, Termios::raw()).ok();
+    let mut editor = Editor::new(path, &text);
+    editor.run();
+
+    // Clear the screen and restore the line discipline for the shell
+    print!("\x1b[2J\x1b[H");
+    set_termios(STDIN, settings).ok();
+}
+
+impl Editor {
+    fn new(path: String, text: &str) -> Self {
+        let mut lines = text.lines().map(|line| line.chars().collect()).collect::<Vec<Vec<char>>>();
+        if lines.is_empty() {
+            lines.push(Vec::new());
+        }
+
+        let size = window_size(STDOUT).unwrap_or(DEFAULT_SIZE);
+        return Self { path, lines, row: 0, column: 0, top: 0, left: 0, size, modified: false, cut: Vec::new(), cutting: false, message: String::from(HELP) };
+    }
+
+    /// Number of screen rows showing text (the last two rows show the status and messages).
+    fn text_rows(&self) -> usize {
+        return usize::max(self.size.rows as usize, 3) - 2;
+    }
+
+    fn run(&mut self) {
+        let mut quit_confirmed = false;
+        loop {
+            self.draw();
+            let Some(key) = read_key() else {
+                return;
+            };
+
+            let message = core::mem::take(&mut self.message);
+            match key {
+                Key::Control(b'q') if !self.modified || quit_confirmed => return,
+                Key::Control(b'q') => {
+                    self.message = String::from("Unsaved changes! Press ^Q again to quit without saving");
+                    quit_confirmed = true;
+                    continue;
+                }
+                Key::Control(b's') => self.save(),
+                Key::Control(b'k') => self.cut_line(),
+                Key::Control(b'u') => self.paste(),
+                Key::Char(c) => self.insert(c),
+                Key::Tab => (0..TAB_WIDTH).for_each(|_| self.insert(' ')),
+                Key::Enter => self.split_line(),
+                Key::Backspace => self.backspace(),
+                Key::Escape(EscapeKey::Delete) => self.delete(),
+                Key::Escape(EscapeKey::Up) => self.row = self.row.saturating_sub(1),
+                Key::Escape(EscapeKey::Down) => self.row = usize::min(self.row + 1, self.lines.len() - 1),
+                Key::Escape(EscapeKey::Left) => self.left_key(),
+                Key::Escape(EscapeKey::Right) => self.right_key(),
+                Key::Escape(EscapeKey::Home) => self.column = 0,
+                Key::Escape(EscapeKey::End) => self.column = self.lines[self.row].len(),
+                Key::Escape(EscapeKey::PageUp) => self.row = self.row.saturating_sub(self.text_rows()),
+                Key::Escape(EscapeKey::PageDown) => self.row = usize::min(self.row + self.text_rows(), self.lines.len() - 1),
+                _ => self.message = message
+            }
+
+            quit_confirmed = false;
+            self.cutting = key == Key::Control(b'k');
+            self.column = usize::min(self.column, self.lines[self.row].len());
+        }
+    }
+
+    fn insert(&mut self, c: char) {
+        self.lines[self.row].insert(self.column, c);
+        self.column += 1;
+        self.modified = true;
+    }
+
+    fn split_line(&mut self) {
+        let rest = self.lines[self.row].split_off(self.column);
+        self.lines.insert(self.row + 1, rest);
+        self.row += 1;
+        self.column = 0;
+        self.modified = true;
+    }
+
+    /// Remove the character in front of the cursor (joining the line with the previous one at its start).
+    fn backspace(&mut self) {
+        if self.column > 0 {
+            self.column -= 1;
+            self.lines[self.row].remove(self.column);
+        } else if self.row > 0 {
+            let line = self.lines.remove(self.row);
+            self.row -= 1;
+            self.column = self.lines[self.row].len();
+            self.lines[self.row].extend(line);
+        } else {
+            return;
+        }
+
+        self.modified = true;
+    }
+
+    /// Remove the character under the cursor (joining the next line with this one at its end).
+    fn delete(&mut self) {
+        if self.column < self.lines[self.row].len() {
+            self.lines[self.row].remove(self.column);
+        } else if self.row + 1 < self.lines.len() {
+            let line = self.lines.remove(self.row + 1);
+            self.lines[self.row].extend(line);
+        } else {
+            return;
+        }
+
+        self.modified = true;
+    }
+
+    fn left_key(&mut self) {
+        if self.column > 0 {
+            self.column -= 1;
+        } else if self.row > 0 {
+            self.row -= 1;
+            self.column = self.lines[self.row].len();
+        }
+    }
+
+    fn right_key(&mut self) {
+        if self.column < self.lines[self.row].len() {
+            self.column += 1;
+        } else if self.row + 1 < self.lines.len() {
+            self.row += 1;
+            self.column = 0;
+        }
+    }
+
+    /// Remove the line under the cursor. Lines cut one after another are collected, so that they can be pasted as a block.
+    fn cut_line(&mut self) {
+        if !self.cutting {
+            self.cut.clear();
+        }
+
+        let line = if self.lines.len() > 1 { self.lines.remove(self.row) } else { core::mem::take(&mut self.lines[0]) };
+        self.cut.push(line);
+        self.row = usize::min(self.row, self.lines.len() - 1);
+        self.column = 0;
+        self.modified = true;
+    }
+
+    fn paste(&mut self) {
+        for (index, line) in self.cut.iter().enumerate() {
+            self.lines.insert(self.row + index, line.clone());
+        }
+
+        self.row += self.cut.len();
+        self.row = usize::min(self.row, self.lines.len() - 1);
+        self.column = 0;
+        self.modified = !self.cut.is_empty() || self.modified;
+    }
+
+    fn save(&mut self) {
+        let mut text = String::new();
+        for line in self.lines.iter() {
+            text.extend(line.iter());
+            text.push('\n');
+        }
+
+        let result = File::create(&self.path).and_then(|file| {
+            file.write_all(text.as_bytes())?;
+            file.sync()
+        });
+
+        self.message = match result {
+            Ok(()) => {
+                self.modified = false;
+                format!("Wrote {} lines to '{}'", self.lines.len(), self.path)
+            }
+            Err(err) => format!("Failed to save '{}' ({:?})", self.path, err)
+        };
+    }
+
+    /// Redraw the whole screen, after scrolling so that the cursor is visible.
+    fn draw(&mut self) {
+        let rows = self.text_rows();
+        let columns = usize::max(self.size.columns as usize, 2) - 1;
+        if self.row < self.top {
+            self.top = self.row;
+        } else if self.row >= self.top + rows {
+            self.top = self.row + 1 - rows;
+        }
+        if self.column < self.left {
+            self.left = self.column;
+        } else if self.column >= self.left + columns {
+            self.left = self.column + 1 - columns;
+        }
+
+        // The screen is built in one string and written at once, so that it does not flicker
+        let mut screen = String::from("\x1b[H");
+        for row in self.top..self.top + rows {
+            if let Some(line) = self.lines.get(row) {
+                screen.extend(line.iter().skip(self.left).take(columns));
+            } else {
+                screen.push('~');
+            }
+
+            screen.push_str("\x1b[K\n");
+        }
+
+        let status = format!(" {}{}  Line {}/{}, Column {}", self.path, if self.modified { " (modified)" } else { "" }, self.row + 1, self.lines.len(), self.column + 1);
+        let padding = vec![' '; columns.saturating_sub(status.chars().count())];
+        screen.push_str("\x1b[7m");
+        screen.extend(status.chars().take(columns).chain(padding));
+        screen.push_str("\x1b[0m\x1b[K\n");
+        screen.extend(self.message.chars().take(columns));
+        screen.push_str("\x1b[K");
+
+        screen.push_str(&format!("\x1b[{};{}H", self.row - self.top + 1, self.column - self.left + 1));
+        print!("{}", screen);
+    }
+}
+
+/// Read the next key press. Returns `None` at the end of the input.
+fn read_key() -> Option<Key> {
+    let key = match read_byte()? {
+        b'\n' | b'\r' => Key::Enter,
+        0x08 | 0x7f => Key::Backspace,
+        b'\t' => Key::Tab,
+        0x1b => Key::Escape(read_escape_sequence()?),
+        byte @ 0x01..=0x1a => Key::Control(byte - 1 + b'a'),
+        byte if byte >= 0x20 => read_utf8(byte).map_or(Key::Unknown, Key::Char),
+        _ => Key::Unknown
+    };
+
+    return Some(key);
+}
@@ -5,7 +5,7 @@ extern crate alloc;
 use concurrent::{process, thread};
 #[allow(unused_imports)]
 use runtime::*;
-use io::{print, println};
+use io::println;
 
 #[no_mangle]
 pub fn main() {
@@ -0,0 +1,241 @@
+#![no_std]
+
+extern crate alloc;
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering::Relaxed;
+use core::time::Duration;
+use concurrent::{process, signal};
+use concurrent::signal::Signal;
+use io::file::{dup, dup2, poll, set_descriptor_flags};
+use io::mqueue;
+use io::println;
+#[allow(unused_imports)]
+use runtime::*;
+use runtime::fs;
+use runtime::fs::File;
+use runtime::process::{spawn, spawn_background, Child};
+use runtime::time::{sleep, Instant};
+use syscall::error::Errno;
+use syscall::file::{DescriptorFlags, OpenFlags, PollDescriptor, PollEvents, STDERR, STDIN, STDOUT};
+use syscall::mqueue::QueueAttributes;
+use syscall::process::INIT_CONTROL_QUEUE;
+
+/// Services started by init, one per line: '<name> <terminal> <action> <command> [<arguments>]'.
+/// The terminal (e.g. '/dev/tty2') is connected to the standard descriptors of the service ('-' keeps the console of init).
+const INITTAB: &str = "/etc/inittab";
+/// Services are checked this often, while no control message arrives.
+const POLL_INTERVAL_MS: usize = 500;
+/// A service exiting earlier than this after being started has probably crashed right away.
+const MIN_RUNTIME: Duration = Duration::from_secs(1);
+/// Respawning is given up after this many quick exits in a row, so that a broken service does not keep the system busy.
+const MAX_QUICK_EXITS: usize = 5;
+/// Time given to a quickly exiting service, before it is respawned.
+const RESPAWN_DELAY: Duration = Duration::from_millis(500);
+
+/// Requested by a control message or by `Signal::Terminate` (e.g. when the power button has been pressed).
+const NO_REQUEST: usize = 0;
+const SHUTDOWN_REQUEST: usize = 1;
+const REBOOT_REQUEST: usize = 2;
+static REQUEST: AtomicUsize = AtomicUsize::new(NO_REQUEST);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Action {
+    /// Start at boot and wait until it has exited, before starting the next service (e.g. the startup script).
+    Wait,
+    /// Start at boot without waiting for it.
+    Once,
+    /// Start at boot and start again each time it exits (e.g. a shell on a virtual console).
+    Respawn,
+    /// Run during shutdown (before the kernel terminates all remaining processes) and wait until it has exited.
+    Shutdown,
+}
+
+struct Service {
+    name: String,
+    terminal: Option<String>,
+    action: Action,
+    command: String,
+    arguments: Vec<String>,
+    child: Option<Child>,
+    started: Instant,
+    quick_exits: usize,
+}
+
+/// First user process, started by the kernel. It starts the services configured in `INITTAB`, restarts them, when they exit,
+/// and shuts the system down in an orderly way, when "shutdown" or "reboot" is sent to `INIT_CONTROL_QUEUE`.
+#[no_mangle]
+pub fn main() {
+    signal::ignore(Signal::Interrupt);
+    signal::ignore(Signal::Hangup);
+    signal::set_handler(Signal::Terminate, |_| { REQUEST.compare_exchange(NO_REQUEST, SHUTDOWN_REQUEST, Relaxed, Relaxed).ok(); });
+
+    let mut services = match fs::read_to_string(INITTAB) {
+        Ok(inittab) => parse(&inittab),
+        Err(err) => {
+            println!("init: Failed to read '{}' ({:?})", INITTAB, err);
+            Vec::new()
+        }
+    };
+
+    let queue = mqueue::open(INIT_CONTROL_QUEUE, OpenFlags::READ | OpenFlags::CREATE | OpenFlags::CLOSE_ON_EXEC, Some(QueueAttributes::new(4, 64)));
+    if let Err(err) = queue {
+        println!("init: Failed to create control queue '{}' ({:?})", INIT_CONTROL_QUEUE, err);
+    }
+
+    for service in services.iter_mut().filter(|service| service.action != Action::Shutdown) {
+        service.start();
+        if service.action == Action::Wait {
+            if let Some(child) = service.child.take() {
+                child.wait();
+            }
+        }
+    }
+
+    while REQUEST.load(Relaxed) == NO_REQUEST {
+        match queue {
+            Ok(queue) => receive_request(queue),
+            Err(_) => sleep(Duration::from_millis(POLL_INTERVAL_MS as u64))
+        }
+
+        for service in services.iter_mut().filter(|service| service.action == Action::Respawn) {
+            service.supervise();
+        }
+    }
+
+    // The kernel terminates the remaining processes (including the services), before powering off
+    for service in services.iter_mut().filter(|service| service.action == Action::Shutdown) {
+        service.start();
+        if let Some(child) = service.child.take() {
+            child.wait();
+        }
+    }
+
+    let result = if REQUEST.load(Relaxed) == REBOOT_REQUEST { process::reboot() } else { process::shutdown() };
+    if let Err(err) = result {
+        println!("init: Failed to power off ({:?})", err);
+    }
+}
+
+/// Wait up to `POLL_INTERVAL_MS` for a control message and note the requested action.
+fn receive_request(queue: usize) {
+    let mut descriptors = [PollDescriptor::new(queue, PollEvents::READABLE)];
+    if !matches!(poll(&mut descriptors, Some(POLL_INTERVAL_MS)), Ok(1..)) {
+        return;
+    }
+
+    let mut buffer = [0u8; 64];
+    let request = match mqueue::receive(queue, &mut buffer) {
+        Ok((length, _)) => &buffer[..length],
+        Err(_) => return
+    };
+
+    match request.trim_ascii() {
+        b"shutdown" => REQUEST.store(SHUTDOWN_REQUEST, Relaxed),
+        b"reboot" => REQUEST.store(REBOOT_REQUEST, Relaxed),
+        _ => println!("init: Unknown request '{}'", String::from_utf8_lossy(request))
+    }
+}
+
+/// Parse the lines of `INITTAB`. Empty lines and lines starting with '#' are ignored, invalid lines are reported and skipped.
+fn parse(inittab: &str) -> Vec<Service> {
+    let mut services = Vec::new();
+    for (number, line) in inittab.lines().enumerate().map(|(index, line)| (index + 1, line.trim())) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let words = line.split_whitespace().collect::<Vec<&str>>();
+        let action = match words.get(2) {
+            Some(&"wait") => Action::Wait,
+            Some(&"once") => Action::Once,
+            Some(&"respawn") => Action::Respawn,
+            Some(&"shutdown") => Action::Shutdown,
+            _ => {
+                println!("init: Invalid entry in line {} of '{}'", number, INITTAB);
+                continue;
+            }
+        };
+
+        let Some(command) = words.get(3) else {
+            println!("init: Missing command in line {} of '{}'", number, INITTAB);
+            continue;
+        };
+
+        services.push(Service {
+            name: words[0].to_string(),
+            terminal: Some(words[1]).filter(|terminal| *terminal != "-").map(ToString::to_string),
+            action,
+            command: command.to_string(),
+            arguments: words[4..].iter().map(ToString::to_string).collect(),
+            child: None,
+            started: Instant::now(),
+            quick_exits: 0
+        });
+    }
+
+    return services;
+}
+
+impl Service {
+    fn start(&mut self) {
+        let arguments = self.arguments.iter().map(String::as_str).collect::<Vec<&str>>();
+        let child = match &self.terminal {
+            Some(terminal) => on_terminal(terminal, || spawn(&self.command, &arguments)),
+            None => spawn_background(&self.command, &arguments)
+        };
+
+        match child {
+            Ok(child) => {
+                self.child = Some(child);
+                self.started = Instant::now();
+            }
+            Err(err) => println!("init: Failed to start service '{}' ({:?})", self.name, err)
+        }
+    }
+
+    /// Restart the service, if it has exited. Services, which fail to start or exit right away too often, are given up.
+    fn supervise(&mut self) {
+        if self.quick_exits >= MAX_QUICK_EXITS || self.child.as_ref().is_some_and(Child::is_running) {
+            return;
+        }
+
+        if self.child.is_none() || self.started.elapsed() < MIN_RUNTIME {
+            self.quick_exits += 1;
+            if self.quick_exits == MAX_QUICK_EXITS {
+                println!("init: Service '{}' is respawning too fast (Giving up)", self.name);
+                return;
+            }
+
+            sleep(RESPAWN_DELAY);
+        } else {
+            self.quick_exits = 0;
+        }
+
+        self.start();
+    }
+}
+
+/// Call `f` with the standard descriptors connected to `terminal`, so that an application started by it runs on that terminal.
+/// The standard descriptors of init are restored afterward.
+fn on_terminal(terminal: &str, f: impl FnOnce() -> Result<Child, Errno>) -> Result<Child, Errno> {
+    let file = File::with_flags(terminal, OpenFlags::READ_WRITE | OpenFlags::CLOSE_ON_EXEC)?;
+    let mut saved = Vec::new();
+    for fd in [STDIN, STDOUT, STDERR] {
+        let copy = File::from_descriptor(dup(fd)?);
+        set_descriptor_flags(copy.descriptor(), DescriptorFlags::CLOSE_ON_EXEC)?;
+        saved.push((fd, copy));
+    }
+
+    let result = [STDIN, STDOUT, STDERR].iter()
+        .try_for_each(|fd| dup2(file.descriptor(), *fd, DescriptorFlags::empty()).map(|_| ()))
+        .and_then(|_| f());
+
+    for (fd, copy) in saved {
+        dup2(copy.descriptor(), fd, DescriptorFlags::empty()).ok();
+    }
+
+    return result;
+}
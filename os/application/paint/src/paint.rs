@@ -0,0 +1,79 @@
+#![no_std]
+
+extern crate alloc;
+
+use graphic::color::{Color, BLACK, WHITE};
+use graphic::surface::Surface;
+use io::file::{close, create_window, framebuffer_info, map_file, open, present_window, read_input_event, write};
+use io::println;
+#[allow(unused_imports)]
+use runtime::*;
+use syscall::file::OpenFlags;
+use syscall::input::{InputEventType, ABSOLUTE_X, ABSOLUTE_Y, BUTTON_LEFT, BUTTON_RIGHT};
+use syscall::ioctl::WindowGeometry;
+
+const WIDTH: u32 = 400;
+const HEIGHT: u32 = 300;
+const KEY_ESCAPE: u16 = 1;
+
+/// Simple drawing program for the window system: Draw with the left mouse button, clear with the right one and exit with escape.
+#[no_mangle]
+pub fn main() {
+    let fd = match open("/dev/window", OpenFlags::READ_WRITE) {
+        Ok(fd) => fd,
+        Err(error) => {
+            println!("paint: Failed to open '/dev/window' ({:?})", error);
+            return;
+        }
+    };
+
+    if let Err(error) = create_window(fd, WindowGeometry { x: 100, y: 100, width: WIDTH, height: HEIGHT }) {
+        println!("paint: Failed to create window ({:?})", error);
+        return;
+    }
+
+    write(fd, b"Paint").ok();
+    let info = framebuffer_info(fd).expect("paint: Failed to get window format");
+    let size = (info.pitch * info.height) as usize;
+    let buffer = map_file(fd, 0, size).expect("paint: Failed to map window surface");
+
+    let mut surface = Surface::new(info.width, info.height, info.bpp);
+    let pen = Color::from_rgb(0x0000ff, info.bpp);
+    surface.clear(&WHITE);
+    surface.draw_string(4, 4, &BLACK, &WHITE, "Left: draw, Right: clear, Esc: exit");
+    present(&mut surface, buffer, info.pitch, fd);
+
+    let (mut x, mut y) = (0, 0);
+    let mut drawing = false;
+    while let Ok(event) = read_input_event(fd) {
+        match (event.typ, event.code) {
+            (InputEventType::Absolute, ABSOLUTE_X) | (InputEventType::Absolute, ABSOLUTE_Y) => {
+                let (last_x, last_y) = (x, y);
+                if event.code == ABSOLUTE_X {
+                    x = event.value;
+                } else {
+                    y = event.value;
+                }
+
+                if drawing {
+                    surface.draw_line(last_x, last_y, x, y, &pen);
+                }
+            }
+            (InputEventType::Key, BUTTON_LEFT) => drawing = event.value != 0,
+            (InputEventType::Key, BUTTON_RIGHT) if event.value == 1 => surface.clear(&WHITE),
+            (InputEventType::Key, KEY_ESCAPE) => break,
+            (InputEventType::Sync, _) => present(&mut surface, buffer, info.pitch, fd),
+            _ => {}
+        }
+    }
+
+    close(fd).ok();
+}
+
+fn present(surface: &mut Surface, buffer: *mut u8, pitch: u32, fd: usize) {
+    surface.present_with(surface.width(), surface.height(), pitch, |offset, data| {
+        unsafe { buffer.add(offset).copy_from_nonoverlapping(data.as_ptr(), data.len()); }
+    });
+
+    present_window(fd).ok();
+}
@@ -0,0 +1,190 @@
+#![no_std]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use io::file::{audio_format, close, fsync, open, read, seek, set_audio_format, write};
+use io::read::read as read_char;
+use io::{print, println};
+#[allow(unused_imports)]
+use runtime::*;
+use syscall::error::Errno;
+use syscall::file::{OpenFlags, SeekWhence};
+use syscall::ioctl::AudioFormat;
+
+const AUDIO_DEVICE: &str = "/dev/audio0";
+/// Number of frames read from the file and converted at once.
+const CHUNK_FRAMES: usize = 4096;
+const TONE_FREQUENCY: u32 = 440;
+const TONE_AMPLITUDE: i16 = 8000;
+
+/// Format of the samples in a WAV file ('fmt ' chunk).
+struct WavFormat {
+    channels: u16,
+    sample_rate: u32,
+    bits_per_sample: u16,
+}
+
+/// Play a WAV file (uncompressed PCM with 8 or 16 bits per sample, mono or stereo) on the first audio device.
+/// Without a file name, a test tone is played instead.
+#[no_mangle]
+pub fn main() {
+    print!("WAV file (empty for test tone): ");
+    let mut path = String::new();
+    loop {
+        match read_char() {
+            '\n' => break,
+            c => path.push(c)
+        }
+    }
+
+    let audio = match open(AUDIO_DEVICE, OpenFlags::WRITE) {
+        Ok(fd) => fd,
+        Err(error) => {
+            println!("play: Failed to open '{}' ({:?})", AUDIO_DEVICE, error);
+            return;
+        }
+    };
+
+    let result = if path.is_empty() { play_tone(audio) } else { play_file(audio, path.trim()) };
+    if let Err(error) = result {
+        println!("play: {:?}", error);
+    }
+
+    // Wait until all samples have been played
+    fsync(audio).ok();
+    close(audio).ok();
+}
+
+/// Play a square wave for one second in the current format of the device.
+fn play_tone(audio: usize) -> Result<(), Errno> {
+    let sample_rate = audio_format(audio)?.sample_rate;
+    let period = (sample_rate / TONE_FREQUENCY) as usize;
+
+    let mut samples = Vec::with_capacity(sample_rate as usize * 4);
+    for frame in 0..sample_rate as usize {
+        let value = if frame % period < period / 2 { TONE_AMPLITUDE } else { -TONE_AMPLITUDE };
+        samples.extend_from_slice(&value.to_le_bytes()); // Left
+        samples.extend_from_slice(&value.to_le_bytes()); // Right
+    }
+
+    write(audio, &samples)?;
+    return Ok(());
+}
+
+fn play_file(audio: usize, path: &str) -> Result<(), Errno> {
+    let file = open(path, OpenFlags::READ)?;
+    let result = play_wav(audio, file);
+    close(file).ok();
+
+    return result;
+}
+
+fn play_wav(audio: usize, file: usize) -> Result<(), Errno> {
+    let mut header = [0u8; 12];
+    read_exact(file, &mut header)?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        println!("play: Not a WAV file");
+        return Err(Errno::InvalidArgument);
+    }
+
+    // Chunks are searched for the format, followed by the samples
+    let mut format = None;
+    loop {
+        let mut chunk = [0u8; 8];
+        read_exact(file, &mut chunk)?;
+        let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as usize;
+
+        match &chunk[0..4] {
+            b"fmt " => {
+                let mut data = vec![0u8; size];
+                read_exact(file, &mut data)?;
+                format = Some(parse_format(&data)?);
+            }
+            b"data" => match &format {
+                Some(format) => return play_samples(audio, file, format, size),
+                None => {
+                    println!("play: Missing format chunk");
+                    return Err(Errno::InvalidArgument);
+                }
+            }
+            // Chunks are padded to an even size
+            _ => { seek(file, (size + size % 2) as isize, SeekWhence::Current)?; }
+        }
+    }
+}
+
+fn parse_format(data: &[u8]) -> Result<WavFormat, Errno> {
+    if data.len() < 16 || u16::from_le_bytes([data[0], data[1]]) != 1 {
+        println!("play: Only uncompressed PCM is supported");
+        return Err(Errno::InvalidArgument);
+    }
+
+    let format = WavFormat {
+        channels: u16::from_le_bytes([data[2], data[3]]),
+        sample_rate: u32::from_le_bytes([data[4], data[5], data[6], data[7]]),
+        bits_per_sample: u16::from_le_bytes([data[14], data[15]]),
+    };
+
+    if !(1..=2).contains(&format.channels) || (format.bits_per_sample != 8 && format.bits_per_sample != 16) {
+        println!("play: Unsupported format ({} channels, {} bits per sample)", format.channels, format.bits_per_sample);
+        return Err(Errno::InvalidArgument);
+    }
+
+    return Ok(format);
+}
+
+/// Convert `size` bytes of samples to 16-bit stereo and write them to the audio device.
+fn play_samples(audio: usize, file: usize, format: &WavFormat, size: usize) -> Result<(), Errno> {
+    set_audio_format(audio, AudioFormat { sample_rate: format.sample_rate, channels: 2, bits_per_sample: 16 })?;
+    println!("Playing {} Hz, {} channel(s), {} bits", format.sample_rate, format.channels, format.bits_per_sample);
+
+    let frame_size = (format.channels * format.bits_per_sample / 8) as usize;
+    let mut input = vec![0u8; CHUNK_FRAMES * frame_size];
+    let mut output = Vec::with_capacity(CHUNK_FRAMES * 4);
+    let mut remaining = size - size % frame_size;
+
+    while remaining > 0 {
+        let length = input.len().min(remaining);
+        let count = read(file, &mut input[..length])?;
+        if count < frame_size {
+            break; // File is shorter than announced
+        }
+
+        output.clear();
+        for frame in input[..count - count % frame_size].chunks(frame_size) {
+            let left = sample(format, frame, 0);
+            let right = if format.channels == 2 { sample(format, frame, 1) } else { left };
+            output.extend_from_slice(&left.to_le_bytes());
+            output.extend_from_slice(&right.to_le_bytes());
+        }
+
+        write(audio, &output)?;
+        remaining -= count - count % frame_size;
+    }
+
+    return Ok(());
+}
+
+/// Read the sample of `channel` from `frame` as signed 16-bit value (8-bit samples are unsigned).
+fn sample(format: &WavFormat, frame: &[u8], channel: usize) -> i16 {
+    return if format.bits_per_sample == 8 {
+        (frame[channel] as i16 - 128) << 8
+    } else {
+        i16::from_le_bytes([frame[channel * 2], frame[channel * 2 + 1]])
+    };
+}
+
+fn read_exact(file: usize, buffer: &mut [u8]) -> Result<(), Errno> {
+    let mut position = 0;
+    while position < buffer.len() {
+        match read(file, &mut buffer[position..])? {
+            0 => return Err(Errno::IoError), // Unexpected end of file
+            count => position += count
+        }
+    }
+
+    return Ok(());
+}
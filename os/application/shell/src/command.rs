@@ -0,0 +1,211 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Application or built-in command with its redirections (e.g. 'sort < input > output').
+pub struct Command {
+    pub words: Vec<String>,
+    pub input: Option<String>,
+    pub output: Option<String>,
+}
+
+/// Commands connected by '|', so that the output of each command becomes the input of the next one.
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+    /// Started with '&' (the shell does not wait for it).
+    pub background: bool,
+    /// Command line of the pipeline (e.g. for listing jobs).
+    pub text: String,
+}
+
+enum Token {
+    Word(String),
+    Pipe,
+    Input,
+    Output,
+    And,
+    Background,
+}
+
+/// Split a command line into pipelines. Fails with a description of the syntax error (e.g. a missing file name after '>').
+/// '&&' is rejected, since applications do not return an exit status, which could decide on running the next pipeline.
+pub fn parse(line: &str) -> Result<Vec<Pipeline>, &'static str> {
+    let mut pipelines = Vec::new();
+    let mut commands = Vec::new();
+    let mut command = Command { words: Vec::new(), input: None, output: None };
+    let mut text = String::new();
+
+    let mut tokens = tokenize(line).into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Word(word) => {
+                push_text(&mut text, &word);
+                command.words.push(word);
+            }
+            Token::Input | Token::Output => {
+                let Some(Token::Word(path)) = tokens.next() else {
+                    return Err("Missing file name after redirection");
+                };
+
+                push_text(&mut text, if matches!(token, Token::Input) { "<" } else { ">" });
+                push_text(&mut text, &path);
+                if matches!(token, Token::Input) { command.input = Some(path) } else { command.output = Some(path) };
+            }
+            Token::Pipe => {
+                if command.words.is_empty() {
+                    return Err("Missing command before '|'");
+                }
+
+                push_text(&mut text, "|");
+                commands.push(command);
+                command = Command { words: Vec::new(), input: None, output: None };
+            }
+            Token::And => return Err("'&&' is not supported (applications do not return an exit status)"),
+            Token::Background => {
+                if command.words.is_empty() {
+                    return Err("Missing command before '&'");
+                }
+
+                commands.push(command);
+                command = Command { words: Vec::new(), input: None, output: None };
+                pipelines.push(Pipeline { commands, background: true, text });
+
+                commands = Vec::new();
+                text = String::new();
+            }
+        }
+    }
+
+    if command.words.is_empty() {
+        if !commands.is_empty() {
+            return Err("Missing command at the end of the line");
+        }
+    } else {
+        commands.push(command);
+        pipelines.push(Pipeline { commands, background: false, text });
+    }
+
+    return Ok(pipelines);
+}
+
+/// Operators are recognized without surrounding whitespace (e.g. 'hello|cat').
+/// Text in single or double quotes belongs to the current word, including whitespace and operators (e.g. 'echo "a | b"'),
+/// but variables are still expanded in it (see `Variables::expand()`). An unterminated quote extends to the end of the line.
+/// A '#' at the start of a word starts a comment, which extends to the end of the line (e.g. the interpreter line of a script).
+fn tokenize(line: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    // Set for quoted words, so that empty quotes (e.g. "") are kept as an empty word
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let token = match c {
+            '\'' | '"' => {
+                chars.by_ref().take_while(|next| *next != c).for_each(|next| word.push(next));
+                quoted = true;
+                continue;
+            }
+            '|' => Some(Token::Pipe),
+            '<' => Some(Token::Input),
+            '>' => Some(Token::Output),
+            '&' if chars.peek() == Some(&'&') => {
+                chars.next();
+                Some(Token::And)
+            }
+            '&' => Some(Token::Background),
+            '#' if word.is_empty() && !quoted => break,
+            c if c.is_whitespace() => None,
+            c => {
+                word.push(c);
+                continue;
+            }
+        };
+
+        if !word.is_empty() || quoted {
+            tokens.push(Token::Word(word));
+            word = String::new();
+            quoted = false;
+        }
+        if let Some(token) = token {
+            tokens.push(token);
+        }
+    }
+
+    if !word.is_empty() || quoted {
+        tokens.push(Token::Word(word));
+    }
+
+    return tokens;
+}
+
+fn push_text(text: &mut String, word: &str) {
+    if !text.is_empty() {
+        text.push(' ');
+    }
+
+    text.push_str(word);
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use super::{parse, Command};
+
+    fn words(command: &Command) -> Vec<&str> {
+        return command.words.iter().map(|word| word.as_str()).collect();
+    }
+
+    #[test]
+    fn pipes() {
+        let pipelines = parse("cat file|sort | uniq").unwrap();
+        assert_eq!(pipelines.len(), 1);
+        let commands = &pipelines[0].commands;
+        assert_eq!(commands.iter().map(words).collect::<Vec<_>>(), [vec!["cat", "file"], vec!["sort"], vec!["uniq"]]);
+        assert_eq!(pipelines[0].text, "cat file | sort | uniq");
+        assert!(!pipelines[0].background);
+
+        assert!(parse("| sort").is_err());
+        assert!(parse("cat file |").is_err());
+    }
+
+    #[test]
+    fn redirections() {
+        let pipelines = parse("sort <input >output").unwrap();
+        let command = &pipelines[0].commands[0];
+        assert_eq!(words(command), ["sort"]);
+        assert_eq!(command.input.as_deref(), Some("input"));
+        assert_eq!(command.output.as_deref(), Some("output"));
+
+        assert!(parse("sort >").is_err());
+        assert!(parse("sort < | cat").is_err());
+    }
+
+    #[test]
+    fn background() {
+        let pipelines = parse("server & client").unwrap();
+        assert_eq!(pipelines.len(), 2);
+        assert!(pipelines[0].background);
+        assert!(!pipelines[1].background);
+        assert_eq!(words(&pipelines[1].commands[0]), ["client"]);
+
+        assert!(parse("server &").unwrap()[0].background);
+        assert!(parse("& server").is_err());
+    }
+
+    #[test]
+    fn and_is_rejected() {
+        assert!(parse("make && run").is_err());
+    }
+
+    #[test]
+    fn quoting() {
+        let pipelines = parse("echo \"a | b\" 'c > d' \"\" x'y z'").unwrap();
+        assert_eq!(pipelines.len(), 1);
+        let command = &pipelines[0].commands[0];
+        assert_eq!(words(command), ["echo", "a | b", "c > d", "", "xy z"]);
+        assert_eq!(command.output, None);
+
+        assert_eq!(words(&parse("echo '#not a comment' # comment").unwrap()[0].commands[0]), ["echo", "#not a comment"]);
+        assert_eq!(words(&parse("echo \"unterminated").unwrap()[0].commands[0]), ["echo", "unterminated"]);
+    }
+}
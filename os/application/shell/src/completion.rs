@@ -0,0 +1,70 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use runtime::env;
+use runtime::fs::read_dir;
+use syscall::file::FileType;
+use crate::variable::DEFAULT_PATH;
+
+/// Words, which may replace the word in front of the cursor (on Tab).
+pub struct Completion {
+    /// Index of the first character of the word in the line.
+    pub start: usize,
+    /// Complete words (sorted), each followed by ' ' or by '/' for directories, so that typing can continue right away.
+    pub candidates: Vec<String>,
+}
+
+/// Complete the word in front of `cursor`. The first word of a command is completed to a built-in command or an application
+/// found in 'PATH', while words containing a '/' are completed to files (paths are always absolute).
+pub fn complete(line: &[char], cursor: usize, builtins: &[&str]) -> Completion {
+    let start = line[..cursor].iter().rposition(|c| is_separator(*c)).map_or(0, |index| index + 1);
+    let word = line[start..cursor].iter().collect::<String>();
+    let first_word = line[..start].iter().rev().find(|c| !c.is_whitespace()).is_none_or(|c| *c == '|' || *c == '&');
+
+    let mut candidates = if word.contains('/') {
+        files(&word)
+    } else if first_word {
+        commands(&word, builtins)
+    } else {
+        Vec::new()
+    };
+
+    candidates.sort();
+    candidates.dedup();
+    return Completion { start, candidates };
+}
+
+/// Operators end a word like whitespace (see `command::parse()`).
+fn is_separator(c: char) -> bool {
+    return c.is_whitespace() || c == '|' || c == '<' || c == '>' || c == '&';
+}
+
+fn files(word: &str) -> Vec<String> {
+    let (directory, prefix) = word.rsplit_once('/').unwrap_or(("", word));
+    let Ok(entries) = read_dir(if directory.is_empty() { "/" } else { directory }) else {
+        return Vec::new();
+    };
+
+    return entries.iter()
+        .filter(|entry| entry.name().starts_with(prefix))
+        .map(|entry| format!("{}/{}{}", directory, entry.name(), if entry.typ == FileType::Directory { '/' } else { ' ' }))
+        .collect();
+}
+
+fn commands(word: &str, builtins: &[&str]) -> Vec<String> {
+    let mut commands = builtins.iter()
+        .filter(|builtin| builtin.starts_with(word))
+        .map(|builtin| format!("{} ", builtin))
+        .collect::<Vec<String>>();
+
+    let path = env::var("PATH").unwrap_or_else(|| String::from(DEFAULT_PATH));
+    for directory in path.split(':').filter(|directory| !directory.is_empty()) {
+        for entry in read_dir(directory).unwrap_or_default() {
+            if entry.typ == FileType::Regular && entry.name().starts_with(word) {
+                commands.push(format!("{} ", entry.name()));
+            }
+        }
+    }
+
+    return commands;
+}
@@ -0,0 +1,229 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use io::file::{dup, dup2, set_descriptor_flags, stat};
+use io::println;
+use runtime::env;
+use runtime::fs::{pipe, File};
+use runtime::process::{spawn, spawn_background, Child};
+use syscall::error::Errno;
+use syscall::file::{DescriptorFlags, FileType, OpenFlags, STDIN, STDOUT};
+use crate::command::{Command, Pipeline};
+use crate::variable::{Variables, DEFAULT_PATH};
+
+/// Pipeline running in the background (started with '&').
+struct Job {
+    id: usize,
+    children: Vec<Child>,
+    text: String,
+}
+
+/// Runs the pipelines entered by the user and keeps track of the ones running in the background.
+/// Jobs are numbered from 1 and a new job gets the number following the highest one in use.
+pub struct Jobs {
+    jobs: Vec<Job>,
+}
+
+impl Jobs {
+    pub const fn new() -> Self {
+        return Self { jobs: Vec::new() };
+    }
+
+    /// Run all pipelines of a command line one after another.
+    /// `builtin` runs a built-in command and returns `false`, if there is no such command.
+    pub fn run(&mut self, pipelines: Vec<Pipeline>, variables: &mut Variables, builtin: impl Fn(&[&str]) -> bool) {
+        for pipeline in pipelines {
+            let (children, result) = self.start(&pipeline, variables, &builtin);
+            if let Err(message) = &result {
+                println!("{}", message);
+            }
+
+            if pipeline.background && !children.is_empty() {
+                let id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+                println!("[{}] {}", id, pipeline.text);
+                self.jobs.push(Job { id, children, text: pipeline.text });
+            } else {
+                for child in children {
+                    child.wait();
+                }
+            }
+        }
+    }
+
+    /// Report the background jobs, which have finished since the last call (called before showing the prompt).
+    pub fn reap(&mut self) {
+        self.jobs.retain(|job| {
+            let running = job.children.iter().any(Child::is_running);
+            if !running {
+                println!("[{}] Done     {}", job.id, job.text);
+            }
+
+            running
+        });
+    }
+
+    /// Start all commands of `pipeline` and return the applications, which have been started (built-in commands run immediately).
+    /// If a command cannot be started, the ones started before keep running, but the remaining ones are not started.
+    fn start(&mut self, pipeline: &Pipeline, variables: &mut Variables, builtin: &impl Fn(&[&str]) -> bool) -> (Vec<Child>, Result<(), String>) {
+        let mut children = Vec::new();
+        let mut pipes = Vec::new();
+        for _ in 1..pipeline.commands.len() {
+            match pipe() {
+                Ok(pipe) => pipes.push(pipe),
+                Err(err) => return (children, Err(format!("pipe: {:?}", err)))
+            }
+        }
+
+        // Commands are started from the last to the first one, so that a built-in command can write into a pipe,
+        // whose reader is already running (it would block forever otherwise, once the pipe is full)
+        for (index, command) in pipeline.commands.iter().enumerate().rev() {
+            let input = index.checked_sub(1).map(|previous| &pipes[previous].0);
+            let output = pipes.get(index).map(|(_, writer)| writer);
+            match self.start_command(command, input, output, pipeline.background, variables, builtin) {
+                Ok(child) => children.extend(child),
+                Err(message) => return (children, Err(message))
+            }
+        }
+
+        // The shell's ends of the pipes are closed here, so that each reader sees the end of the data, once its writer has exited
+        return (children, Ok(()));
+    }
+
+    /// Start `command` with its standard input and output connected to the given pipe ends (redirections to files take precedence).
+    /// Variables are expanded right before starting the command, so that it sees variables set by previous commands on the same line.
+    /// Returns `None` for a built-in command, which has already finished.
+    fn start_command(&mut self, command: &Command, input: Option<&File>, output: Option<&File>, background: bool, variables: &mut Variables, builtin: &impl Fn(&[&str]) -> bool) -> Result<Option<Child>, String> {
+        let input_file = command.input.as_deref().map(|path| open(&variables.expand(path), OpenFlags::READ)).transpose()?;
+        let output_file = command.output.as_deref().map(|path| open(&variables.expand(path), OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE)).transpose()?;
+        let input = input_file.as_ref().or(input);
+        let output = output_file.as_ref().or(output);
+
+        // Words, which expand to nothing (e.g. an undefined variable), are dropped
+        let words = command.words.iter().map(|word| variables.expand(word)).filter(|word| !word.is_empty()).collect::<Vec<String>>();
+        let words = words.iter().map(String::as_str).collect::<Vec<&str>>();
+        let Some(&name) = words.first() else {
+            return Ok(None);
+        };
+
+        let result = with_redirection(input, output, || {
+            if variables.builtin(&words) || self.builtin(&words) || builtin(&words) {
+                return Ok(None);
+            }
+            let Some(path) = find_application(name) else {
+                return Err(String::from("Command not found!"));
+            };
+
+            let child = if background { spawn_background(&path, &words[1..]) } else { spawn(&path, &words[1..]) };
+            return child.map(Some).map_err(|err| match err {
+                Errno::NotFound => String::from("Command not found!"),
+                err => format!("{:?}", err)
+            });
+        });
+
+        return match result {
+            Ok(Ok(child)) => Ok(child),
+            Ok(Err(message)) => Err(format!("{}: {}", name, message)),
+            Err(err) => Err(format!("{}: Redirection failed: {:?}", name, err))
+        };
+    }
+
+    /// Built-in commands for job control. Returns `false`, if `words` is not one of them.
+    fn builtin(&mut self, words: &[&str]) -> bool {
+        match words {
+            ["jobs"] => self.list(),
+            ["wait"] => self.wait(None),
+            ["wait", id] => match id.trim_start_matches('%').parse() {
+                Ok(id) => self.wait(Some(id)),
+                Err(_) => println!("Usage: wait [<job>]")
+            },
+            _ => return false
+        }
+
+        return true;
+    }
+
+    /// Built-in command 'jobs': List the jobs running in the background.
+    fn list(&mut self) {
+        self.reap();
+        for job in self.jobs.iter() {
+            println!("[{}] Running  {}", job.id, job.text);
+        }
+    }
+
+    /// Built-in command 'wait [<job>]': Wait for a job running in the background (for the last one started, if no job is given).
+    fn wait(&mut self, id: Option<usize>) {
+        let index = match id {
+            Some(id) => self.jobs.iter().position(|job| job.id == id),
+            None => self.jobs.len().checked_sub(1)
+        };
+
+        let Some(index) = index else {
+            println!("wait: No such job");
+            return;
+        };
+
+        let job = self.jobs.remove(index);
+        println!("{}", job.text);
+        for child in job.children {
+            child.wait();
+        }
+    }
+}
+
+/// Resolve the name of an application to its path by searching the directories in 'PATH' (separated by ':') in order.
+/// Names containing a '/' are used as they are.
+fn find_application(name: &str) -> Option<String> {
+    if name.contains('/') {
+        return Some(String::from(name));
+    }
+
+    let path = env::var("PATH").unwrap_or_else(|| String::from(DEFAULT_PATH));
+    return path.split(':')
+        .filter(|directory| !directory.is_empty())
+        .map(|directory| format!("{}/{}", directory.trim_end_matches('/'), name))
+        .find(|path| stat(path).is_ok_and(|status| status.typ == FileType::Regular));
+}
+
+/// Open a file for redirection. The descriptor is not inherited, since it is duplicated onto a standard descriptor for the command.
+fn open(path: &str, flags: OpenFlags) -> Result<File, String> {
+    return File::with_flags(path, flags | OpenFlags::CLOSE_ON_EXEC).map_err(|err| format!("{}: {:?}", path, err));
+}
+
+/// Run `f` with the standard input and output of the shell replaced by `input` and `output` (if given), so that
+/// built-in commands use them and applications started by `f` inherit them. The original descriptors are restored afterward.
+fn with_redirection<T>(input: Option<&File>, output: Option<&File>, f: impl FnOnce() -> T) -> Result<T, Errno> {
+    let saved_input = input.map(|file| redirect(file, STDIN)).transpose()?;
+    let saved_output = match output.map(|file| redirect(file, STDOUT)).transpose() {
+        Ok(saved) => saved,
+        Err(err) => {
+            if let Some(saved) = saved_input {
+                restore(saved, STDIN);
+            }
+
+            return Err(err);
+        }
+    };
+
+    let result = f();
+    if let Some(saved) = saved_output {
+        restore(saved, STDOUT);
+    }
+    if let Some(saved) = saved_input {
+        restore(saved, STDIN);
+    }
+
+    return Ok(result);
+}
+
+/// Let `fd` refer to `file` and return a copy of the original descriptor (which is not inherited by applications).
+fn redirect(file: &File, fd: usize) -> Result<File, Errno> {
+    let saved = File::from_descriptor(dup(fd)?);
+    set_descriptor_flags(saved.descriptor(), DescriptorFlags::CLOSE_ON_EXEC)?;
+    dup2(file.descriptor(), fd, DescriptorFlags::empty())?;
+
+    return Ok(saved);
+}
+
+fn restore(saved: File, fd: usize) {
+    dup2(saved.descriptor(), fd, DescriptorFlags::empty()).ok();
+}
@@ -0,0 +1,236 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use io::{print, println};
+use io::file::{set_termios, termios};
+use io::read::{read_byte, read_escape_sequence, read_utf8, EscapeKey};
+use runtime::fs;
+use runtime::fs::File;
+use syscall::file::{OpenFlags, STDIN};
+use syscall::ioctl::Termios;
+use crate::completion;
+
+/// Entered lines are appended to this file, so that the history survives restarting the shell (but not rebooting, since '/tmp' is a tmpfs).
+const HISTORY_PATH: &str = "/tmp/.shell_history";
+/// Number of lines kept in the history (older lines are dropped, when the history is loaded).
+const HISTORY_SIZE: usize = 100;
+
+const INTERRUPT: u8 = 0x03;
+const BACKSPACE: u8 = 0x08;
+const TAB: u8 = 0x09;
+const KILL: u8 = 0x15;
+const ESCAPE: u8 = 0x1b;
+const DELETE: u8 = 0x7f;
+
+/// Reads command lines from the terminal with the line discipline switched to raw mode, so that the line can be edited
+/// with the arrow keys, lines from the history can be recalled with Up and Down and words can be completed with Tab.
+pub struct LineEditor {
+    history: Vec<String>,
+    /// Index of the line from the history, which is being edited (`history.len()` for a new line).
+    position: usize,
+    /// New line, which has been entered before going up in the history.
+    draft: Vec<char>,
+    line: Vec<char>,
+    cursor: usize,
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        return Self { history: load_history(), position: 0, draft: Vec::new(), line: Vec::new(), cursor: 0 };
+    }
+
+    /// Show `prompt` and read a line. Returns `None` at the end of the input (e.g. after the remote side has closed the session).
+    /// `builtins` are offered for completion in addition to the applications found in 'PATH'.
+    pub fn read_line(&mut self, prompt: &str, builtins: &[&str]) -> Option<String> {
+        // Applications expect the settings, which have been active before (e.g. canonical mode), so they are restored afterward
+        let settings = termios(STDIN).ok();
+        if settings.is_some() {
+            set_termios(STDIN, Termios::raw()).ok();
+        }
+
+        self.line.clear();
+        self.cursor = 0;
+        self.position = self.history.len();
+        self.draft.clear();
+        print!("{}", prompt);
+
+        let line = self.edit(prompt, builtins);
+        if let Some(settings) = settings {
+            set_termios(STDIN, settings).ok();
+        }
+
+        if let Some(line) = &line {
+            self.add_history(line);
+        }
+
+        return line;
+    }
+
+    fn edit(&mut self, prompt: &str, builtins: &[&str]) -> Option<String> {
+        loop {
+            match read_byte()? {
+                b'\n' | b'\r' => {
+                    println!("");
+                    return Some(self.line.iter().collect());
+                }
+                INTERRUPT => {
+                    println!("^C");
+                    self.line.clear();
+                    self.cursor = 0;
+                    self.position = self.history.len();
+                    print!("{}", prompt);
+                }
+                BACKSPACE | DELETE if self.cursor > 0 => {
+                    self.move_to(self.cursor - 1);
+                    self.line.remove(self.cursor);
+                    self.redraw(self.cursor);
+                }
+                KILL => {
+                    self.move_to(0);
+                    self.line.clear();
+                    self.redraw(0);
+                }
+                TAB => self.complete(prompt, builtins),
+                ESCAPE => self.escape_sequence()?,
+                byte if byte >= 0x20 && byte != DELETE => {
+                    if let Some(c) = read_utf8(byte) {
+                        self.line.insert(self.cursor, c);
+                        self.cursor += 1;
+                        print!("{}", c);
+                        self.redraw(self.cursor);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Handle the sequence sent for a key without a character (see `keymap::escape_sequence()` in the kernel).
+    fn escape_sequence(&mut self) -> Option<()> {
+        match read_escape_sequence()? {
+            EscapeKey::Up => self.recall(self.position.checked_sub(1)),
+            EscapeKey::Down => self.recall(Some(self.position + 1).filter(|position| *position <= self.history.len())),
+            EscapeKey::Right if self.cursor < self.line.len() => self.move_to(self.cursor + 1),
+            EscapeKey::Left if self.cursor > 0 => self.move_to(self.cursor - 1),
+            EscapeKey::Home => self.move_to(0),
+            EscapeKey::End => self.move_to(self.line.len()),
+            EscapeKey::Delete if self.cursor < self.line.len() => {
+                self.line.remove(self.cursor);
+                self.redraw(self.cursor);
+            }
+            _ => {}
+        }
+
+        return Some(());
+    }
+
+    /// Replace the line by the line at `position` in the history (or by the new line, if `position` is `history.len()`).
+    fn recall(&mut self, position: Option<usize>) {
+        let Some(position) = position else {
+            return;
+        };
+
+        if self.position == self.history.len() {
+            self.draft = self.line.clone();
+        }
+
+        self.position = position;
+        self.move_to(0);
+        self.line = match self.history.get(position) {
+            Some(line) => line.chars().collect(),
+            None => self.draft.clone()
+        };
+
+        print!("{}", self.line.iter().collect::<String>());
+        self.cursor = self.line.len();
+        self.redraw(self.cursor);
+    }
+
+    fn complete(&mut self, prompt: &str, builtins: &[&str]) {
+        let completion = completion::complete(&self.line, self.cursor, builtins);
+        let Some(first) = completion.candidates.first() else {
+            return;
+        };
+
+        // Complete as far as all candidates agree, and show them, if there is nothing left to complete
+        let common = completion.candidates.iter().fold(first.chars().count(), |length, candidate| {
+            first.chars().zip(candidate.chars()).take(length).take_while(|(a, b)| a == b).count()
+        });
+
+        let typed = self.cursor - completion.start;
+        if common > typed {
+            let insertion = first.chars().skip(typed).take(common - typed).collect::<Vec<char>>();
+            print!("{}", insertion.iter().collect::<String>());
+            self.line.splice(self.cursor..self.cursor, insertion.iter().copied());
+            self.cursor += insertion.len();
+            self.redraw(self.cursor);
+        } else if completion.candidates.len() > 1 {
+            println!("");
+            let names = completion.candidates.iter()
+                .map(|candidate| candidate.trim_end().rsplit('/').find(|name| !name.is_empty()).unwrap_or(candidate))
+                .collect::<Vec<&str>>();
+            println!("{}", names.join("  "));
+
+            let cursor = self.cursor;
+            print!("{}{}", prompt, self.line.iter().collect::<String>());
+            self.cursor = self.line.len();
+            self.move_to(cursor);
+        }
+    }
+
+    /// Move the cursor on the screen and in the line to `position`.
+    fn move_to(&mut self, position: usize) {
+        if position < self.cursor {
+            print!("\x1b[{}D", self.cursor - position);
+        } else if position > self.cursor {
+            print!("{}", self.line[self.cursor..position].iter().collect::<String>());
+        }
+
+        self.cursor = position;
+    }
+
+    /// Rewrite the line starting at `from` (where the cursor on the screen is), erase the rest of the screen line
+    /// and move the cursor back to where it has been.
+    fn redraw(&mut self, from: usize) {
+        let rest = self.line[from..].iter().collect::<String>();
+        print!("{}\x1b[K", rest);
+        if !rest.is_empty() {
+            print!("\x1b[{}D", self.line.len() - from);
+        }
+    }
+
+    /// Add `line` to the history (unless it is empty or repeats the previous line) and append it to the history file.
+    fn add_history(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.last().is_some_and(|last| last == line) {
+            return;
+        }
+
+        self.history.push(String::from(line));
+        if self.history.len() > HISTORY_SIZE {
+            self.history.remove(0);
+        }
+
+        if let Ok(file) = File::with_flags(HISTORY_PATH, OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::APPEND | OpenFlags::CLOSE_ON_EXEC) {
+            file.write_all(format!("{}\n", line).as_bytes()).ok();
+        }
+    }
+}
+
+/// Load the last `HISTORY_SIZE` lines from the history file (rewriting it without the older lines, so that it does not grow forever).
+fn load_history() -> Vec<String> {
+    let Ok(text) = fs::read_to_string(HISTORY_PATH) else {
+        return Vec::new();
+    };
+
+    let lines = text.lines().map(String::from).collect::<Vec<String>>();
+    if lines.len() <= HISTORY_SIZE {
+        return lines;
+    }
+
+    let lines = lines[lines.len() - HISTORY_SIZE..].to_vec();
+    let mut text = lines.join("\n");
+    text.push('\n');
+    fs::write(HISTORY_PATH, text.as_bytes()).ok();
+
+    return lines;
+}
@@ -1,33 +1,367 @@
-#![no_std]
+// Unit tests (e.g. of the command line parser) run on the host with the standard library
+#![cfg_attr(not(test), no_std)]
 
 extern crate alloc;
 
+mod command;
+mod completion;
+mod job;
+mod line;
+mod tftp;
+mod variable;
+
+use alloc::format;
 use alloc::string::String;
-use concurrent::thread;
+use alloc::vec::Vec;
+use concurrent::{process, signal};
+use concurrent::signal::Signal;
 #[allow(unused_imports)]
 use runtime::*;
-use io::{print, println};
-use io::read::read;
+use runtime::{env, fs};
+use io::{mqueue, print, println};
+use io::file::{close, keyboard_layout, open, read as read_file, set_keyboard_layout, unlink, write as write_file};
+use io::net::{add_route, delete_route, set_interface_address, socket, start_capture, stop_capture};
+use syscall::error::Errno;
+use syscall::file::{DescriptorFlags, OpenFlags, STDIN};
+use syscall::ioctl::{encode_interface_name, InterfaceAddress, KeyboardLayout, RouteEntry};
+use syscall::net::{Protocol, SocketType};
+use syscall::process::INIT_CONTROL_QUEUE;
+use crate::job::Jobs;
+use crate::line::LineEditor;
+use crate::variable::Variables;
+
+/// Hardware inventory from SMBIOS, generated by the kernel.
+const HWINFO_PATH: &str = "/proc/hwinfo";
+/// Network interfaces and routing table, generated by the kernel.
+const INTERFACES_PATH: &str = "/proc/interfaces";
+const ROUTES_PATH: &str = "/proc/routes";
+/// Frames captured on the network interfaces in pcap format.
+const PCAP_PATH: &str = "/dev/pcap";
+/// Files downloaded with 'tftp' are stored in a tmpfs, so they are lost on reboot.
+const DOWNLOAD_DIRECTORY: &str = "/tmp";
+/// Names of all built-in commands (offered for completion).
+const BUILTINS: [&str; 14] = ["echo", "export", "hwinfo", "ifconfig", "jobs", "keymap", "pcap", "reboot", "route", "set", "shutdown", "tftp", "unset", "wait"];
 
-#[no_mangle]
+#[cfg_attr(not(test), no_mangle)]
 pub fn main() {
-    let mut command = String::new();
-    print!("> ");
+    let mut jobs = Jobs::new();
+    let mut variables = Variables::new();
+
+    // Started with the path of a script (e.g. by '#!/bin/shell' in its first line), the shell runs the script and exits
+    if let Some(script) = env::args().get(1) {
+        match fs::read_to_string(script) {
+            Ok(text) => text.lines().for_each(|line| run(&mut jobs, &mut variables, line)),
+            Err(err) => println!("shell: Failed to read '{}' ({:?})", script, err)
+        }
+
+        return;
+    }
+
+    // Ctrl+C interrupts the application running in the foreground, but not the shell itself
+    signal::ignore(Signal::Interrupt);
+
+    let mut editor = LineEditor::new();
+    while let Some(line) = editor.read_line("> ", &BUILTINS) {
+        run(&mut jobs, &mut variables, &line);
+        jobs.reap();
+    }
+}
 
+fn run(jobs: &mut Jobs, variables: &mut Variables, line: &str) {
+    match command::parse(line) {
+        Ok(pipelines) => jobs.run(pipelines, variables, builtin),
+        Err(err) => println!("Syntax error: {}", err)
+    }
+}
+
+/// Run the built-in command `words[0]`. Returns `false`, if there is no such command.
+fn builtin(words: &[&str]) -> bool {
+    let mut args = words.iter().copied();
+    match args.next() {
+        Some("echo") => println!("{}", args.collect::<Vec<&str>>().join(" ")),
+        Some("keymap") => keymap(args.next()),
+        Some("hwinfo") => print_file("hwinfo", HWINFO_PATH),
+        Some("ifconfig") => ifconfig(args.collect()),
+        Some("route") => route(args.collect()),
+        Some("tftp") => tftp(args.collect()),
+        Some("pcap") => pcap(args.collect()),
+        Some("shutdown") => shutdown(),
+        Some("reboot") => reboot(),
+        _ => return false
+    }
+
+    return true;
+}
+
+/// Built-in command 'keymap [layout]': Switch the keyboard layout or show the current one.
+fn keymap(name: Option<&str>) {
+    let result = match name {
+        Some(name) => match KeyboardLayout::from_name(name) {
+            Some(layout) => set_keyboard_layout(STDIN, layout),
+            None => {
+                let names = KeyboardLayout::ALL.map(|layout| layout.name());
+                println!("Unknown keyboard layout! Available layouts: {}", names.join(", "));
+                return;
+            }
+        },
+        None => keyboard_layout(STDIN).map(|layout| println!("{}", layout.name()))
+    };
+
+    if let Err(err) = result {
+        println!("keymap: {:?}", err);
+    }
+}
+
+/// Print a file generated by the kernel (e.g. for the built-in command 'hwinfo', which shows the hardware detected by the firmware).
+fn print_file(command: &str, path: &str) {
+    let fd = match open(path, OpenFlags::READ) {
+        Ok(fd) => fd,
+        Err(err) => {
+            println!("{}: {:?}", command, err);
+            return;
+        }
+    };
+
+    let mut buffer = [0u8; 512];
     loop {
-        match read() {
-            '\n' => {
-                if !command.is_empty() {
-                    match thread::start_application(command.as_str()) {
-                        Some(app) => app.join(),
-                        None => println!("Command not found!")
-                    }
-                }
-
-                command.clear();
-                print!("> ")
+        match read_file(fd, &mut buffer) {
+            Ok(0) => break,
+            Ok(count) => print!("{}", String::from_utf8_lossy(&buffer[..count])),
+            Err(err) => {
+                println!("{}: {:?}", command, err);
+                break;
+            }
+        }
+    }
+
+    close(fd).ok();
+}
+
+/// Built-in command 'ifconfig [<interface> <address>/<prefix length> | <interface> none]':
+/// Show the network interfaces or configure the address of an interface by hand (replacing the address assigned by DHCP).
+fn ifconfig(args: Vec<&str>) {
+    let (name, cidr) = match args.as_slice() {
+        [] => return print_file("ifconfig", INTERFACES_PATH),
+        [name, "none"] => (*name, Some(([0; 4], 0))),
+        [name, cidr] => (*name, parse_cidr(cidr)),
+        _ => (args[0], None)
+    };
+
+    let (Some(interface), Some((address, prefix_length))) = (encode_interface_name(name), cidr) else {
+        println!("Usage: ifconfig [<interface> <address>/<prefix length> | <interface> none]");
+        return;
+    };
+
+    let result = with_socket(|fd| set_interface_address(fd, InterfaceAddress { interface, address, prefix_length }));
+    if let Err(err) = result {
+        println!("ifconfig: {:?}", err);
+    }
+}
+
+/// Built-in command 'route [add <network>/<prefix length> [via <gateway>] dev <interface> | del <network>/<prefix length>]':
+/// Show the routing table or add or remove a route (the network 'default' stands for '0.0.0.0/0').
+fn route(args: Vec<&str>) {
+    let result = match args.as_slice() {
+        [] => return print_file("route", ROUTES_PATH),
+        ["add", destination, "via", gateway, "dev", interface] => route_entry(destination, Some(gateway), interface)
+            .map(|entry| with_socket(|fd| add_route(fd, entry))),
+        ["add", destination, "dev", interface] => route_entry(destination, None, interface)
+            .map(|entry| with_socket(|fd| add_route(fd, entry))),
+        ["del", destination] => route_entry(destination, None, "")
+            .map(|entry| with_socket(|fd| delete_route(fd, entry))),
+        _ => None
+    };
+
+    match result {
+        Some(Ok(())) => {}
+        Some(Err(err)) => println!("route: {:?}", err),
+        None => println!("Usage: route [add <network>/<prefix length> [via <gateway>] dev <interface> | del <network>/<prefix length>]")
+    }
+}
+
+fn route_entry(destination: &str, gateway: Option<&str>, interface: &str) -> Option<RouteEntry> {
+    let (destination, prefix_length) = if destination == "default" { ([0; 4], 0) } else { parse_cidr(destination)? };
+    let gateway = match gateway {
+        Some(gateway) => parse_address(gateway)?,
+        None => [0; 4]
+    };
+
+    return Some(RouteEntry { destination, prefix_length, gateway, interface: encode_interface_name(interface)? });
+}
+
+/// Built-in command 'tftp get <file> [<server>]': Download a file from a TFTP server into `DOWNLOAD_DIRECTORY`
+/// (e.g. an application, which can be started from there afterwards). Without a server, the default gateway is asked.
+fn tftp(args: Vec<&str>) {
+    let (file, server) = match args.as_slice() {
+        ["get", file] => (*file, default_gateway()),
+        ["get", file, server] => (*file, parse_address(server)),
+        _ => (args.first().copied().unwrap_or(""), None)
+    };
+
+    let name = file.rsplit('/').next().unwrap_or(file);
+    let (Some(server), false) = (server, name.is_empty()) else {
+        println!("Usage: tftp get <file> [<server>] (the default gateway is used, if no server is given)");
+        return;
+    };
+
+    let path = format!("{}/{}", DOWNLOAD_DIRECTORY, name);
+    let fd = match open(&path, OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE) {
+        Ok(fd) => fd,
+        Err(err) => {
+            println!("tftp: {:?}", err);
+            return;
+        }
+    };
+
+    let result = tftp::get(server, file, fd);
+    close(fd).ok();
+
+    match result {
+        Ok(size) => println!("Received {} bytes into '{}'", size, path),
+        Err(err) => {
+            println!("tftp: {}", err);
+            unlink(&path).ok();
+        }
+    }
+}
+
+/// Built-in command 'pcap start [<interface>] | stop [<file>]': Capture the frames sent and received on an interface
+/// (or on all interfaces) and store them in a file, which can be analyzed with Wireshark or tcpdump.
+/// Without a file, the captured frames remain readable from `PCAP_PATH`.
+fn pcap(args: Vec<&str>) {
+    let result = match args.as_slice() {
+        ["start"] => Some(with_pcap(|fd| start_capture(fd, None))),
+        ["start", interface] => encode_interface_name(interface).map(|name| with_pcap(|fd| start_capture(fd, Some(name)))),
+        ["stop"] => Some(with_pcap(stop_capture)),
+        ["stop", file] => Some(with_pcap(|fd| {
+            stop_capture(fd)?;
+            save_capture(fd, file)
+        })),
+        _ => None
+    };
+
+    match result {
+        Some(Ok(())) => {}
+        Some(Err(err)) => println!("pcap: {:?}", err),
+        None => println!("Usage: pcap start [<interface>] | stop [<file>]")
+    }
+}
+
+fn with_pcap(request: impl FnOnce(usize) -> Result<(), Errno>) -> Result<(), Errno> {
+    let fd = open(PCAP_PATH, OpenFlags::READ)?;
+    let result = request(fd);
+    close(fd).ok();
+
+    return result;
+}
+
+/// Copy the pcap file header and all captured frames from `fd` into the file at `path`.
+fn save_capture(fd: usize, path: &str) -> Result<(), Errno> {
+    let file = open(path, OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE)?;
+    let mut buffer = [0u8; 4096];
+    let result = loop {
+        match read_file(fd, &mut buffer) {
+            Ok(0) => break Ok(()),
+            Ok(count) => if let Err(err) = write_all(file, &buffer[..count]) {
+                break Err(err);
             },
-            c => command.push(char::from_u32(c as u32).unwrap())
+            Err(err) => break Err(err)
+        }
+    };
+
+    close(file).ok();
+    return result;
+}
+
+fn write_all(fd: usize, mut data: &[u8]) -> Result<(), Errno> {
+    while !data.is_empty() {
+        let count = write_file(fd, data)?;
+        data = &data[count..];
+    }
+
+    return Ok(());
+}
+
+/// Gateway of the default route, as listed in the routing table generated by the kernel.
+fn default_gateway() -> Option<[u8; 4]> {
+    let routes = read_text(ROUTES_PATH).ok()?;
+    return routes.lines().find_map(|line| match line.split_whitespace().collect::<Vec<&str>>().as_slice() {
+        ["default", gateway, _] => parse_address(gateway),
+        _ => None
+    });
+}
+
+fn read_text(path: &str) -> Result<String, Errno> {
+    let fd = open(path, OpenFlags::READ)?;
+    let mut text = Vec::new();
+    let mut buffer = [0u8; 512];
+    let result = loop {
+        match read_file(fd, &mut buffer) {
+            Ok(0) => break Ok(String::from_utf8_lossy(&text).into_owned()),
+            Ok(count) => text.extend_from_slice(&buffer[..count]),
+            Err(err) => break Err(err)
         }
+    };
+
+    close(fd).ok();
+    return result;
+}
+
+/// Network configuration is changed via control requests on a socket (like on Linux).
+fn with_socket(request: impl FnOnce(usize) -> Result<(), Errno>) -> Result<(), Errno> {
+    let fd = socket(SocketType::Datagram, Protocol::Udp, DescriptorFlags::empty())?;
+    let result = request(fd);
+    close(fd).ok();
+
+    return result;
+}
+
+/// Parse an address in dotted decimal notation (e.g. '10.0.2.15').
+fn parse_address(text: &str) -> Option<[u8; 4]> {
+    let mut address = [0; 4];
+    let mut parts = text.split('.');
+    for byte in address.iter_mut() {
+        *byte = parts.next()?.parse().ok()?;
+    }
+
+    return if parts.next().is_none() { Some(address) } else { None };
+}
+
+/// Parse an address with prefix length (e.g. '10.0.2.0/24').
+fn parse_cidr(text: &str) -> Option<([u8; 4], u8)> {
+    let (address, prefix_length) = text.split_once('/')?;
+    let prefix_length = prefix_length.parse().ok().filter(|&length| length <= 32)?;
+
+    return Some((parse_address(address)?, prefix_length));
+}
+
+/// Built-in command 'shutdown': Write all modified data back and power off the system.
+/// The init process is asked to do this, so that it can stop its services first (the system call is used, if it is not running).
+fn shutdown() {
+    if request_init("shutdown").is_ok() {
+        return;
     }
-}
\ No newline at end of file
+
+    if let Err(err) = process::shutdown() {
+        println!("shutdown: {:?}", err);
+    }
+}
+
+/// Built-in command 'reboot': Write all modified data back and restart the system (see `shutdown()`).
+fn reboot() {
+    if request_init("reboot").is_ok() {
+        return;
+    }
+
+    if let Err(err) = process::reboot() {
+        println!("reboot: {:?}", err);
+    }
+}
+
+fn request_init(request: &str) -> Result<(), Errno> {
+    let queue = mqueue::open(INIT_CONTROL_QUEUE, OpenFlags::WRITE, None)?;
+    let result = mqueue::send(queue, request.as_bytes(), 0);
+    close(queue).ok();
+
+    return result;
+}
@@ -0,0 +1,139 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use io::file::{close, poll};
+use io::net::{receive_from, send_to, socket};
+use syscall::error::Errno;
+use syscall::file::{DescriptorFlags, PollDescriptor, PollEvents};
+use syscall::net::{Protocol, SocketAddress, SocketType};
+use crate::write_all;
+
+/// Port, on which TFTP servers receive requests.
+pub const PORT: u16 = 69;
+
+const OPCODE_READ_REQUEST: u16 = 1;
+const OPCODE_DATA: u16 = 3;
+const OPCODE_ACKNOWLEDGMENT: u16 = 4;
+const OPCODE_ERROR: u16 = 5;
+
+/// Size of a data block. A shorter block ends the transfer.
+const BLOCK_SIZE: usize = 512;
+const HEADER_SIZE: usize = 4;
+
+/// The last packet is sent again, if the server does not answer within this time.
+const TIMEOUT_MS: usize = 1000;
+const MAX_RETRANSMISSIONS: usize = 5;
+
+pub enum Error {
+    Io(Errno),
+    /// Error packet sent by the server (e.g. 'File not found').
+    Server(String),
+    TimedOut,
+}
+
+impl From<Errno> for Error {
+    fn from(err: Errno) -> Self {
+        return Error::Io(err);
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{:?}", err),
+            Error::Server(message) => write!(f, "Server error: {}", message),
+            Error::TimedOut => write!(f, "Server does not respond")
+        }
+    }
+}
+
+/// Download `file` from the TFTP server at `server` (RFC 1350, binary mode) and write it to the open file `fd`.
+/// Returns the number of bytes received.
+pub fn get(server: [u8; 4], file: &str, fd: usize) -> Result<usize, Error> {
+    let socket = socket(SocketType::Datagram, Protocol::Udp, DescriptorFlags::CLOSE_ON_EXEC)?;
+    let result = receive_file(socket, server, file, fd);
+    close(socket).ok();
+
+    return result;
+}
+
+fn receive_file(socket: usize, server: [u8; 4], file: &str, fd: usize) -> Result<usize, Error> {
+    let mut packet = Vec::new();
+    packet.extend_from_slice(&OPCODE_READ_REQUEST.to_be_bytes());
+    packet.extend_from_slice(file.as_bytes());
+    packet.push(0);
+    packet.extend_from_slice(b"octet");
+    packet.push(0);
+
+    // The server answers from a new port, to which all further packets of the transfer are sent
+    let mut peer = SocketAddress::new(server, PORT);
+    let mut connected = false;
+    let mut expected_block: u16 = 1;
+    let mut size = 0;
+    let mut retransmissions = 0;
+    let mut buffer = [0u8; HEADER_SIZE + BLOCK_SIZE];
+
+    send_to(socket, &packet, peer)?;
+    loop {
+        if !wait_readable(socket)? {
+            retransmissions += 1;
+            if retransmissions > MAX_RETRANSMISSIONS {
+                return Err(Error::TimedOut);
+            }
+
+            send_to(socket, &packet, peer)?;
+            continue;
+        }
+
+        let (count, source) = receive_from(socket, &mut buffer)?;
+        if count < HEADER_SIZE || source.address != server || (connected && source != peer) {
+            continue;
+        }
+
+        let opcode = u16::from_be_bytes([buffer[0], buffer[1]]);
+        let block = u16::from_be_bytes([buffer[2], buffer[3]]);
+        match opcode {
+            OPCODE_ERROR => {
+                let message = buffer[HEADER_SIZE..count].split(|byte| *byte == 0).next().unwrap_or(&[]);
+                return Err(Error::Server(String::from_utf8_lossy(message).into_owned()));
+            }
+            OPCODE_DATA if block == expected_block => {
+                peer = source;
+                connected = true;
+
+                let data = &buffer[HEADER_SIZE..count];
+                write_all(fd, data)?;
+                size += data.len();
+
+                packet = acknowledgment(block);
+                send_to(socket, &packet, peer)?;
+                retransmissions = 0;
+
+                if data.len() < BLOCK_SIZE {
+                    return Ok(size);
+                }
+
+                // Block numbers wrap around for files larger than 32 MiB
+                expected_block = expected_block.wrapping_add(1);
+            }
+            // Our acknowledgment has been lost, so the server has sent the previous block again
+            OPCODE_DATA if connected && block == expected_block.wrapping_sub(1) => {
+                send_to(socket, &packet, peer)?;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn acknowledgment(block: u16) -> Vec<u8> {
+    let mut packet = Vec::from(OPCODE_ACKNOWLEDGMENT.to_be_bytes());
+    packet.extend_from_slice(&block.to_be_bytes());
+
+    return packet;
+}
+
+/// Wait up to `TIMEOUT_MS` for a datagram. Returns `false`, if none has been received in time.
+fn wait_readable(socket: usize) -> Result<bool, Errno> {
+    let mut descriptors = [PollDescriptor::new(socket, PollEvents::READABLE)];
+    return poll(&mut descriptors, Some(TIMEOUT_MS)).map(|ready| ready > 0);
+}
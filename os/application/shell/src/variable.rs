@@ -0,0 +1,131 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use io::println;
+use runtime::env;
+
+/// Search path for applications, if the environment of the shell does not contain 'PATH'.
+pub const DEFAULT_PATH: &str = "/bin";
+
+/// Shell variables, which are only visible to the shell itself (set with 'set').
+/// Exported variables are stored in the environment of the shell process, so that applications inherit them.
+pub struct Variables {
+    local: BTreeMap<String, String>,
+}
+
+impl Variables {
+    pub fn new() -> Self {
+        if env::var("PATH").is_none() {
+            env::set_var("PATH", DEFAULT_PATH).ok();
+        }
+
+        return Self { local: BTreeMap::new() };
+    }
+
+    /// Value of the shell variable or environment variable `name`.
+    pub fn get(&self, name: &str) -> Option<String> {
+        return self.local.get(name).cloned().or_else(|| env::var(name));
+    }
+
+    /// Replace '$NAME' and '${NAME}' in `word` by the value of the variable (undefined variables are replaced by nothing).
+    /// A '$', which is not followed by a name, is kept.
+    pub fn expand(&self, word: &str) -> String {
+        let mut result = String::new();
+        let mut rest = word;
+
+        while let Some(start) = rest.find('$') {
+            result.push_str(&rest[..start]);
+            rest = &rest[start + 1..];
+
+            let (name, length) = if let Some(braced) = rest.strip_prefix('{') {
+                match braced.find('}') {
+                    Some(end) => (&braced[..end], end + 2),
+                    None => ("", 0)
+                }
+            } else {
+                let end = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+                (&rest[..end], end)
+            };
+
+            if name.is_empty() {
+                result.push('$');
+                continue;
+            }
+
+            result.push_str(&self.get(name).unwrap_or_default());
+            rest = &rest[length..];
+        }
+
+        result.push_str(rest);
+        return result;
+    }
+
+    /// Built-in commands for variables. Returns `false`, if `words` is not one of them.
+    pub fn builtin(&mut self, words: &[&str]) -> bool {
+        match words {
+            ["set"] => self.list(),
+            ["set", assignments @ ..] => assignments.iter().for_each(|assignment| self.set(assignment)),
+            ["export"] => env::vars().iter().for_each(|(name, value)| println!("export {}={}", name, value)),
+            ["export", assignments @ ..] => assignments.iter().for_each(|assignment| self.export(assignment)),
+            ["unset", names @ ..] => names.iter().for_each(|name| self.unset(name)),
+            _ => return false
+        }
+
+        return true;
+    }
+
+    /// Built-in command 'set [<name>=<value> ...]': Set shell variables or list all variables.
+    /// A variable, which has already been exported, is changed in the environment.
+    fn set(&mut self, assignment: &str) {
+        let Some((name, value)) = assignment.split_once('=') else {
+            println!("Usage: set [<name>=<value> ...]");
+            return;
+        };
+
+        if env::var(name).is_some() {
+            set_environment(name, value);
+        } else if is_name(name) {
+            self.local.insert(String::from(name), String::from(value));
+        } else {
+            println!("set: Invalid variable name '{}'", name);
+        }
+    }
+
+    fn list(&self) {
+        let mut variables = env::vars().into_iter().collect::<BTreeMap<String, String>>();
+        variables.extend(self.local.iter().map(|(name, value)| (name.clone(), value.clone())));
+
+        for (name, value) in variables {
+            println!("{}={}", name, value);
+        }
+    }
+
+    /// Built-in command 'export [<name>[=<value>] ...]': Move shell variables into the environment
+    /// (so that applications started afterward inherit them) or list the environment.
+    fn export(&mut self, assignment: &str) {
+        let (name, value) = match assignment.split_once('=') {
+            Some((name, value)) => (name, Some(String::from(value))),
+            None => (assignment, None)
+        };
+
+        let local = self.local.remove(name);
+        let value = value.or(local).or_else(|| env::var(name)).unwrap_or_default();
+        set_environment(name, &value);
+    }
+
+    /// Built-in command 'unset <name> ...': Remove shell variables and environment variables.
+    fn unset(&mut self, name: &str) {
+        self.local.remove(name);
+        env::remove_var(name).ok();
+    }
+}
+
+fn set_environment(name: &str, value: &str) {
+    if !is_name(name) || env::set_var(name, value).is_err() {
+        println!("export: Invalid variable name '{}'", name);
+    }
+}
+
+/// Variable names consist of letters, digits and '_' (like in other shells), so that they can be expanded.
+fn is_name(name: &str) -> bool {
+    return !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+}
@@ -0,0 +1,13 @@
+; Entry point of applications, which are linked against the shared runtime ('/lib/libruntime.so').
+; The entry point must lie inside the executable, so it jumps to the runtime's entry function via the PLT.
+
+[GLOBAL _start]
+[EXTERN entry]
+
+[SECTION .note.GNU-stack noalloc noexec nowrite progbits]
+
+[SECTION .text]
+[BITS 64]
+
+_start:
+    jmp entry wrt ..plt
@@ -0,0 +1,25 @@
+#![no_std]
+
+extern crate alloc;
+
+use core::arch::x86_64::_rdtsc;
+use core::hint::black_box;
+use concurrent::process;
+#[allow(unused_imports)]
+use runtime::*;
+use io::println;
+
+const ITERATIONS: usize = 100000;
+
+/// Measure the round trip of a minimal system call (started by the kernel's `bench` command).
+/// There is no system call for reading the system time yet, so only cycles are reported.
+#[no_mangle]
+pub fn main() {
+    let start = unsafe { _rdtsc() };
+    for _ in 0..ITERATIONS {
+        black_box(process::current());
+    }
+    let cycles = unsafe { _rdtsc() } - start;
+
+    println!("BENCH name=syscall_roundtrip iterations={} cycles_per_op={}", ITERATIONS, cycles / ITERATIONS as u64);
+}
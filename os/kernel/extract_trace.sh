@@ -0,0 +1,15 @@
+#!/bin/bash
+
+# Extract a kernel trace (written by 'trace dump') from a serial log into a JSON file,
+# which can be opened with 'chrome://tracing' or https://ui.perfetto.dev
+
+if [ $# -lt 1 ]; then
+  printf "Usage: %s <serial log> [output file]\\n" "$0"
+  exit 1
+fi
+
+readonly LOG_FILE=$1
+readonly OUTPUT_FILE=${2:-trace.json}
+
+sed -n '/=== TRACE BEGIN ===/,/=== TRACE END ===/{//!p}' "${LOG_FILE}" | tr -d '\r' > "${OUTPUT_FILE}"
+printf "Trace written to '%s'\\n" "${OUTPUT_FILE}"
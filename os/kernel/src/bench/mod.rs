@@ -0,0 +1,168 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use core::arch::x86_64::_rdtsc;
+use core::hint::black_box;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering::Relaxed;
+use log::{info, warn};
+use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::structures::paging::page::PageRange;
+use x86_64::VirtAddr;
+use crate::memory::{MemorySpace, physical};
+use crate::memory::r#virtual::AddressSpace;
+use crate::process::process::load_application;
+use crate::process::thread::Thread;
+use crate::sync::Mutex;
+use crate::{built_info, scheduler, timer};
+
+/// Microbenchmark for kernel subsystems. Runs `iterations` operations and returns the number of operations performed.
+struct Benchmark {
+    name: &'static str,
+    iterations: usize,
+    func: fn(usize) -> usize,
+}
+
+/// Application in the initial ramdisk ('/bin'), which measures the system call round trip from user mode (see 'os/application/syscall_bench').
+/// It reports its result in the same format as the kernel benchmarks.
+const SYSCALL_BENCHMARK_APP: &str = "syscall_bench";
+
+/// Start of the region used for benchmark mappings (far above the identity mapped physical memory).
+const BENCH_REGION: u64 = 0x0000_2000_0000_0000;
+/// Maximum number of messages in the queue of the IPC benchmark, before the sender has to wait for the receiver.
+const IPC_QUEUE_CAPACITY: usize = 64;
+/// Number of pages mapped at once by the batch mapping benchmark.
+const MAP_BATCH_PAGES: u64 = 64;
+
+static BENCHMARKS: [Benchmark; 4] = [
+    Benchmark { name: "context_switch", iterations: 10000, func: context_switch },
+    Benchmark { name: "ipc_throughput", iterations: 100000, func: ipc_throughput },
+    Benchmark { name: "page_map_unmap", iterations: 10000, func: page_map_unmap },
+    Benchmark { name: "page_map_iter", iterations: 1000, func: page_map_iter },
+];
+
+/// Iterations left for the partner thread of the current benchmark.
+static PARTNER_ITERATIONS: AtomicUsize = AtomicUsize::new(0);
+static IPC_QUEUE: Mutex<VecDeque<u64>> = Mutex::new(VecDeque::new());
+
+/// Run all benchmarks, whose name starts with `filter` (or all, if `filter` is empty).
+/// Each result is logged as one line (after the usual log prefix), so that it can be parsed by scripts:
+/// `BENCH name=<name> iterations=<n> total_ns=<ns> ns_per_op=<ns> cycles_per_op=<cycles> commit=<hash>`
+pub fn run(filter: &str) {
+    let commit = built_info::GIT_COMMIT_HASH_SHORT.unwrap_or("unknown");
+
+    for benchmark in BENCHMARKS.iter().filter(|benchmark| benchmark.name.starts_with(filter)) {
+        let start_ns = timer().read().systime_ns();
+        let start_cycles = unsafe { _rdtsc() };
+
+        let operations = (benchmark.func)(benchmark.iterations);
+
+        let cycles = unsafe { _rdtsc() } - start_cycles;
+        let ns = timer().read().systime_ns() - start_ns;
+
+        info!("BENCH name={} iterations={} total_ns={} ns_per_op={} cycles_per_op={} commit={}",
+              benchmark.name, operations, ns, ns / operations, cycles / operations as u64, commit);
+    }
+
+    // System calls can only be issued from user mode
+    if "syscall_roundtrip".starts_with(filter) {
+        match load_application(SYSCALL_BENCHMARK_APP, &[]) {
+            Ok(thread) => {
+                scheduler().ready(Rc::clone(&thread));
+                thread.join();
+            }
+            Err(_) => warn!("BENCH name=syscall_roundtrip skipped (Application [{}] not found)", SYSCALL_BENCHMARK_APP)
+        }
+    }
+}
+
+/// Two threads switch back and forth. Each iteration consists of two context switches.
+fn context_switch(iterations: usize) -> usize {
+    PARTNER_ITERATIONS.store(iterations, Relaxed);
+    let partner = Thread::new_kernel_thread(Box::new(|| {
+        for _ in 0..PARTNER_ITERATIONS.load(Relaxed) {
+            scheduler().switch_thread();
+        }
+    }));
+
+    scheduler().ready(Rc::clone(&partner));
+    for _ in 0..iterations {
+        scheduler().switch_thread();
+    }
+
+    partner.join();
+    return iterations * 2;
+}
+
+/// A sender thread passes messages through a bounded queue to the receiving (current) thread.
+/// Each thread yields the processor, if the queue is full or empty respectively.
+fn ipc_throughput(iterations: usize) -> usize {
+    IPC_QUEUE.lock().clear();
+    PARTNER_ITERATIONS.store(iterations, Relaxed);
+
+    let sender = Thread::new_kernel_thread(Box::new(|| {
+        let mut message = 0;
+        while message < PARTNER_ITERATIONS.load(Relaxed) as u64 {
+            {
+                let mut queue = IPC_QUEUE.lock();
+                while queue.len() < IPC_QUEUE_CAPACITY && message < PARTNER_ITERATIONS.load(Relaxed) as u64 {
+                    queue.push_back(message);
+                    message += 1;
+                }
+            }
+
+            scheduler().switch_thread();
+        }
+    }));
+
+    scheduler().ready(Rc::clone(&sender));
+
+    let mut received = 0;
+    while received < iterations {
+        {
+            let mut queue = IPC_QUEUE.lock();
+            while let Some(message) = queue.pop_front() {
+                black_box(message);
+                received += 1;
+            }
+        }
+
+        if received < iterations {
+            scheduler().switch_thread();
+        }
+    }
+
+    sender.join();
+    return received;
+}
+
+/// Map and unmap a single page in a fresh address space. Each iteration consists of one map and one unmap operation.
+fn page_map_unmap(iterations: usize) -> usize {
+    let address_space = AddressSpace::new(4);
+    let start = Page::from_start_address(VirtAddr::new(BENCH_REGION)).unwrap();
+    let pages = PageRange { start, end: start + 1 };
+
+    for _ in 0..iterations {
+        address_space.map(pages, MemorySpace::User, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+        address_space.unmap(pages);
+    }
+
+    return iterations * 2;
+}
+
+/// Map a batch of pages to one frame (as device memory, so that unmapping does not free it) and unmap them again.
+/// Each iteration consists of `MAP_BATCH_PAGES` operations.
+fn page_map_iter(iterations: usize) -> usize {
+    let address_space = AddressSpace::new(4);
+    let frame = physical::alloc(1);
+    let start = Page::from_start_address(VirtAddr::new(BENCH_REGION)).unwrap();
+    let pages = PageRange { start, end: start + MAP_BATCH_PAGES };
+
+    for _ in 0..iterations {
+        address_space.map_iter(MemorySpace::Device, pages.map(|page| (frame.start, page, PageTableFlags::PRESENT | PageTableFlags::WRITABLE)));
+        address_space.unmap(pages);
+    }
+
+    unsafe { physical::free(frame); }
+    return iterations * MAP_BATCH_PAGES as usize;
+}
@@ -0,0 +1,301 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use log::warn;
+use syscall::error::Errno;
+use crate::block::queue::RequestQueue;
+use crate::block::Result;
+use crate::process::thread::Thread;
+use crate::process::wait_queue::WaitQueue;
+use crate::sync::Mutex;
+use crate::{block_cache, scheduler};
+
+/// Maximum amount of cached data in bytes (used by the global cache, see `block_cache()`).
+pub const DEFAULT_CAPACITY: usize = 4 * 1024 * 1024;
+/// On a cache miss, up to this many following blocks are read together with the requested one.
+const READ_AHEAD_BLOCKS: usize = 8;
+/// Interval, in which the flush thread writes dirty blocks back to their devices.
+const FLUSH_INTERVAL_MS: usize = 5000;
+
+/// Blocks are identified by the address of their request queue and the block number.
+type Key = (usize, u64);
+
+/// Cache for blocks of all block devices, sitting between filesystems and the request queues.
+/// Written blocks are only marked as dirty and written back by the flush thread (or `flush()`).
+/// If the cache is full, the least recently used clean block is evicted.
+pub struct BlockCache {
+    capacity: usize,
+    state: Mutex<CacheState>,
+    /// Notified, when blocks have been read (or failed to be read), so that threads waiting for the same blocks continue.
+    loaded: WaitQueue,
+}
+
+struct CacheState {
+    entries: BTreeMap<Key, Entry>,
+    /// Keys of all loaded entries, ordered by their last access.
+    lru: BTreeMap<u64, Key>,
+    tick: u64,
+    size: usize,
+}
+
+struct Entry {
+    queue: Arc<RequestQueue>,
+    /// None, while the block is being read from the device.
+    data: Option<Vec<u8>>,
+    dirty: bool,
+    /// Number of writes of this block, which have been submitted, but not completed yet.
+    /// The block must not be evicted in the meantime, since reading it again could overtake the write (see `RequestQueue`).
+    pending_writes: usize,
+    last_used: u64,
+}
+
+/// Start the flush thread, which periodically writes dirty blocks back.
+pub fn init() {
+    scheduler().ready(Thread::new_kernel_thread(Box::new(|| {
+        loop {
+            scheduler().sleep(FLUSH_INTERVAL_MS);
+            if let Err(err) = block_cache().flush() {
+                warn!("Failed to write back cached blocks (Error: {:?})", err);
+            }
+        }
+    })));
+}
+
+impl BlockCache {
+    pub const fn new(capacity: usize) -> Self {
+        Self { capacity, state: Mutex::new(CacheState { entries: BTreeMap::new(), lru: BTreeMap::new(), tick: 0, size: 0 }), loaded: WaitQueue::new() }
+    }
+
+    /// Copy `buffer.len()` bytes at `offset` inside `block` into `buffer`.
+    pub fn read(&self, queue: &Arc<RequestQueue>, block: u64, offset: usize, buffer: &mut [u8]) -> Result<()> {
+        check_range(queue, block, offset, buffer.len())?;
+        let key = key(queue, block);
+
+        loop {
+            {
+                let mut state = self.state.lock();
+                match state.entries.get(&key).map(|entry| entry.data.is_some()) {
+                    Some(true) => {
+                        state.touch(key);
+                        let data = state.entries[&key].data.as_ref().unwrap();
+                        buffer.copy_from_slice(&data[offset..offset + buffer.len()]);
+
+                        return Ok(());
+                    }
+                    Some(false) => {} // Another thread is reading the block
+                    None => {
+                        let count = state.insert_placeholders(queue, block, READ_AHEAD_BLOCKS);
+                        drop(state);
+                        self.load(queue, block, count)?;
+                        continue;
+                    }
+                }
+            }
+
+            self.loaded.wait_until(|| !self.is_loading(key), None);
+        }
+    }
+
+    /// Copy `data` to `offset` inside `block` and mark the block as dirty.
+    pub fn write(&self, queue: &Arc<RequestQueue>, block: u64, offset: usize, data: &[u8]) -> Result<()> {
+        check_range(queue, block, offset, data.len())?;
+        let key = key(queue, block);
+
+        loop {
+            {
+                let mut state = self.state.lock();
+                match state.entries.get_mut(&key) {
+                    Some(entry) if entry.data.is_some() => {
+                        entry.data.as_mut().unwrap()[offset..offset + data.len()].copy_from_slice(data);
+                        entry.dirty = true;
+                        state.touch(key);
+
+                        return Ok(());
+                    }
+                    Some(_) => {} // Another thread is reading the block
+                    None if offset == 0 && data.len() == queue.block_size() => {
+                        // The whole block is overwritten, so it does not need to be read first
+                        state.entries.insert(key, Entry { queue: Arc::clone(queue), data: Some(Vec::from(data)), dirty: true, pending_writes: 0, last_used: 0 });
+                        state.size += data.len();
+                        state.touch(key);
+                        if !state.evict(self.capacity) {
+                            drop(state);
+                            self.make_room();
+                        }
+
+                        return Ok(());
+                    }
+                    None => {
+                        let count = state.insert_placeholders(queue, block, 1);
+                        drop(state);
+                        self.load(queue, block, count)?;
+                        continue;
+                    }
+                }
+            }
+
+            self.loaded.wait_until(|| !self.is_loading(key), None);
+        }
+    }
+
+    /// Write all dirty blocks back to their devices.
+    pub fn flush(&self) -> Result<()> {
+        return self.write_back(|_| true);
+    }
+
+    /// Write all dirty blocks of a single device back and flush its write cache.
+    pub fn flush_device(&self, queue: &Arc<RequestQueue>) -> Result<()> {
+        let device = key(queue, 0).0;
+        self.write_back(|key| key.0 == device)?;
+
+        return queue.flush();
+    }
+
+    /// Number of bytes currently cached.
+    pub fn size(&self) -> usize {
+        return self.state.lock().size;
+    }
+
+    /// Read `count` blocks, for which placeholders have been inserted, and fill in their data.
+    fn load(&self, queue: &Arc<RequestQueue>, start: u64, count: usize) -> Result<()> {
+        let result = queue.read(start, count);
+        let mut state = self.state.lock();
+
+        match result {
+            Ok(data) => {
+                let block_size = queue.block_size();
+                for (index, block_data) in data.chunks_exact(block_size).enumerate() {
+                    let key = key(queue, start + index as u64);
+                    state.entries.get_mut(&key).unwrap().data = Some(Vec::from(block_data));
+                    state.size += block_size;
+                    state.touch(key);
+                }
+
+                let full = !state.evict(self.capacity);
+                drop(state);
+                self.loaded.notify_all();
+                if full {
+                    self.make_room();
+                }
+
+                return Ok(());
+            }
+            Err(err) => {
+                for block in start..start + count as u64 {
+                    state.entries.remove(&key(queue, block));
+                }
+
+                drop(state);
+                self.loaded.notify_all();
+                return Err(err);
+            }
+        }
+    }
+
+    /// Check if `key` is a placeholder, whose data is currently being read by another thread.
+    fn is_loading(&self, key: Key) -> bool {
+        return self.state.lock().entries.get(&key).is_some_and(|entry| entry.data.is_none());
+    }
+
+    /// Write back dirty blocks, so that they can be evicted.
+    /// Errors are ignored here, since failed blocks stay dirty and are retried (and reported) by the flush thread.
+    fn make_room(&self) {
+        let _ = self.flush();
+    }
+
+    fn write_back(&self, filter: impl Fn(&Key) -> bool) -> Result<()> {
+        let dirty = {
+            let mut state = self.state.lock();
+            state.entries.iter_mut()
+                .filter(|(key, entry)| entry.dirty && filter(key))
+                .map(|(key, entry)| {
+                    entry.dirty = false;
+                    entry.pending_writes += 1;
+                    (*key, Arc::clone(&entry.queue), entry.data.clone().unwrap())
+                })
+                .collect::<Vec<(Key, Arc<RequestQueue>, Vec<u8>)>>()
+        };
+
+        // Submit all writes before waiting, so that the request queues can merge adjacent blocks
+        let requests = dirty.into_iter()
+            .map(|(key, queue, data)| {
+                let request = queue.submit_write(key.1, data);
+                (key, queue, request)
+            })
+            .collect::<Vec<_>>();
+
+        let mut result = Ok(());
+        for (key, queue, request) in requests {
+            let write_result = request.and_then(|request| queue.wait(&request));
+
+            let mut state = self.state.lock();
+            let entry = state.entries.get_mut(&key).unwrap();
+            entry.pending_writes -= 1;
+            if let Err(err) = write_result {
+                // Keep the block dirty, so that writing it back is tried again later
+                entry.dirty = true;
+                result = Err(err);
+            }
+        }
+
+        self.state.lock().evict(self.capacity);
+        return result;
+    }
+}
+
+impl CacheState {
+    fn touch(&mut self, key: Key) {
+        let entry = self.entries.get_mut(&key).unwrap();
+        self.lru.remove(&entry.last_used);
+
+        self.tick += 1;
+        entry.last_used = self.tick;
+        self.lru.insert(self.tick, key);
+    }
+
+    /// Insert placeholders for `block` and up to `max_count - 1` following blocks, that are not cached yet.
+    /// Returns the number of inserted placeholders.
+    fn insert_placeholders(&mut self, queue: &Arc<RequestQueue>, block: u64, max_count: usize) -> usize {
+        let mut count = 0;
+        while count < max_count && block + (count as u64) < queue.block_count() && !self.entries.contains_key(&key(queue, block + count as u64)) {
+            self.entries.insert(key(queue, block + count as u64), Entry { queue: Arc::clone(queue), data: None, dirty: false, pending_writes: 0, last_used: 0 });
+            count += 1;
+        }
+
+        return count;
+    }
+
+    /// Remove least recently used clean blocks, until the cache size is below `capacity`.
+    /// Returns false, if this is not possible, because too many blocks are dirty or being written back.
+    fn evict(&mut self, capacity: usize) -> bool {
+        while self.size > capacity {
+            let victim = self.lru.iter()
+                .find(|(_, key)| !self.entries[*key].dirty && self.entries[*key].pending_writes == 0)
+                .map(|(tick, key)| (*tick, *key));
+
+            match victim {
+                Some((tick, key)) => {
+                    self.lru.remove(&tick);
+                    let entry = self.entries.remove(&key).unwrap();
+                    self.size -= entry.data.map_or(0, |data| data.len());
+                }
+                None => return false
+            }
+        }
+
+        return true;
+    }
+}
+
+fn key(queue: &Arc<RequestQueue>, block: u64) -> Key {
+    return (Arc::as_ptr(queue) as usize, block);
+}
+
+fn check_range(queue: &Arc<RequestQueue>, block: u64, offset: usize, length: usize) -> Result<()> {
+    if block >= queue.block_count() || offset.checked_add(length).map_or(true, |end| end > queue.block_size()) {
+        return Err(Errno::InvalidArgument);
+    }
+
+    return Ok(());
+}
@@ -0,0 +1,134 @@
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use log::info;
+use syscall::error::Errno;
+use syscall::file::FileType;
+use crate::block::queue::RequestQueue;
+use crate::fs::devfs;
+use crate::fs::devfs::Device;
+use crate::block_cache;
+use crate::sync::RwLock;
+
+pub mod cache;
+pub mod queue;
+pub mod ramdisk;
+
+pub type Result<T> = core::result::Result<T, Errno>;
+
+/// Common interface of all storage drivers (e.g. AHCI, NVMe or a ramdisk).
+/// Filesystems do not use drivers directly, but access them via the `RequestQueue` returned by `register()`.
+pub trait BlockDevice: Send + Sync {
+    /// Size of a single block in bytes.
+    fn block_size(&self) -> usize;
+
+    /// Capacity of the device in blocks.
+    fn block_count(&self) -> u64;
+
+    /// Read `buffer.len() / block_size()` contiguous blocks, starting at block `start`.
+    /// The queue guarantees, that the range lies inside the device and `buffer` has a size of a multiple of the block size.
+    fn read_blocks(&self, start: u64, buffer: &mut [u8]) -> Result<()>;
+
+    /// Write `buffer.len() / block_size()` contiguous blocks, starting at block `start`.
+    fn write_blocks(&self, start: u64, buffer: &[u8]) -> Result<()>;
+
+    /// Make sure, that all written data has reached persistent storage.
+    fn flush(&self) -> Result<()> {
+        return Ok(());
+    }
+}
+
+static DEVICES: RwLock<BTreeMap<String, Arc<RequestQueue>>> = RwLock::new(BTreeMap::new());
+
+/// Block device node in '/dev', allowing byte-granular access (partial blocks are read, modified and written back).
+struct BlockDeviceNode {
+    queue: Arc<RequestQueue>,
+}
+
+/// Make a block device available under `name` (e.g. 'ata0' or 'nvme0n1') and as '/dev/<name>'.
+pub fn register(name: &str, device: Arc<dyn BlockDevice>) -> Result<Arc<RequestQueue>> {
+    let mut devices = DEVICES.write();
+    if devices.contains_key(name) {
+        return Err(Errno::AlreadyExists);
+    }
+
+    let queue = Arc::new(RequestQueue::new(device));
+    devfs::register(name, FileType::BlockDevice, Arc::new(BlockDeviceNode { queue: Arc::clone(&queue) }))?;
+    devices.insert(name.to_string(), Arc::clone(&queue));
+    info!("Registered block device [{}] with [{}] blocks of [{}] bytes", name, queue.block_count(), queue.block_size());
+
+    return Ok(queue);
+}
+
+pub fn device(name: &str) -> Option<Arc<RequestQueue>> {
+    return DEVICES.read().get(name).cloned();
+}
+
+pub fn devices() -> Vec<String> {
+    return DEVICES.read().keys().cloned().collect();
+}
+
+impl BlockDeviceNode {
+    /// Calculate the range of blocks touched by `length` bytes at `offset`, limited to the device capacity.
+    fn block_range(&self, offset: usize, length: usize) -> Option<(u64, usize, usize)> {
+        let block_size = self.queue.block_size();
+        let capacity = self.queue.block_count() as usize * block_size;
+        if offset >= capacity || length == 0 {
+            return None;
+        }
+
+        let length = length.min(capacity - offset);
+        let start = offset / block_size;
+        let end = (offset + length).div_ceil(block_size);
+
+        return Some((start as u64, end - start, length));
+    }
+}
+
+impl Device for BlockDeviceNode {
+    fn read(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        let (start, count, length) = match self.block_range(offset, buffer.len()) {
+            Some(range) => range,
+            None => return Ok(0)
+        };
+
+        let data = self.queue.read(start, count)?;
+        let data_offset = offset % self.queue.block_size();
+        buffer[..length].copy_from_slice(&data[data_offset..data_offset + length]);
+
+        return Ok(length);
+    }
+
+    fn write(&self, offset: usize, buffer: &[u8]) -> Result<usize> {
+        let (start, count, length) = match self.block_range(offset, buffer.len()) {
+            Some(range) => range,
+            None => return Err(Errno::NoSpace)
+        };
+
+        let block_size = self.queue.block_size();
+        let data_offset = offset % block_size;
+        let mut data = if data_offset == 0 && length % block_size == 0 {
+            Vec::from(&buffer[..length])
+        } else {
+            // Partial blocks at the beginning or end need to be read first
+            let mut data = self.queue.read(start, count)?;
+            data[data_offset..data_offset + length].copy_from_slice(&buffer[..length]);
+            data
+        };
+
+        data.truncate(count * block_size);
+        self.queue.write(start, data)?;
+
+        return Ok(length);
+    }
+
+    fn size(&self) -> usize {
+        return self.queue.block_count() as usize * self.queue.block_size();
+    }
+
+    /// Write back cached blocks of the device (e.g. written by a filesystem) and flush its write cache.
+    fn sync(&self) -> Result<()> {
+        return block_cache().flush_device(&self.queue);
+    }
+}
@@ -0,0 +1,251 @@
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem;
+use core::sync::atomic::{AtomicBool, AtomicU64};
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use syscall::error::Errno;
+use crate::block::{BlockDevice, Result};
+use crate::process::wait_queue::WaitQueue;
+use crate::sync::Mutex;
+
+/// Requests for adjacent blocks are merged into a single transfer, up to this many blocks.
+const MAX_MERGED_BLOCKS: usize = 256;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Operation {
+    Read,
+    Write,
+}
+
+/// A single read or write of contiguous blocks. Once completed, the status is set and the buffer contains the read data.
+pub struct Request {
+    operation: Operation,
+    start: u64,
+    count: usize,
+    buffer: Mutex<Vec<u8>>,
+    status: Mutex<Option<Result<()>>>,
+}
+
+/// Collects requests for a block device and passes them to the driver in elevator order (ascending block numbers,
+/// continuing from the last position and wrapping around), merging requests for adjacent blocks on the way.
+/// Requests are dispatched by the first thread waiting for one of them, so submitting several requests
+/// before waiting (e.g. for read-ahead) gives the queue the chance to merge them.
+/// Requests for overlapping blocks may be reordered, so a write must be waited for, before reading the same blocks again.
+pub struct RequestQueue {
+    device: Arc<dyn BlockDevice>,
+    pending: Mutex<Vec<Arc<Request>>>,
+    /// Set, while a thread passes requests to the driver. Other threads block on `dispatched` in the meantime.
+    dispatching: AtomicBool,
+    dispatched: WaitQueue,
+    head: AtomicU64,
+}
+
+struct DispatchGuard<'a> {
+    queue: &'a RequestQueue,
+}
+
+impl Request {
+    pub fn operation(&self) -> Operation {
+        self.operation
+    }
+
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_completed(&self) -> bool {
+        return self.status.lock().is_some();
+    }
+
+    fn end(&self) -> u64 {
+        return self.start + self.count as u64;
+    }
+
+    fn complete(&self, status: Result<()>) {
+        self.status.lock().replace(status);
+    }
+}
+
+impl RequestQueue {
+    pub fn new(device: Arc<dyn BlockDevice>) -> Self {
+        Self { device, pending: Mutex::new(Vec::new()), dispatching: AtomicBool::new(false), dispatched: WaitQueue::new(), head: AtomicU64::new(0) }
+    }
+
+    pub fn block_size(&self) -> usize {
+        return self.device.block_size();
+    }
+
+    pub fn block_count(&self) -> u64 {
+        return self.device.block_count();
+    }
+
+    /// Queue a read of `count` blocks, starting at block `start`, without waiting for its completion.
+    pub fn submit_read(&self, start: u64, count: usize) -> Result<Arc<Request>> {
+        return self.submit(Operation::Read, start, vec![0; count * self.block_size()]);
+    }
+
+    /// Queue a write of `data` (a multiple of the block size), starting at block `start`, without waiting for its completion.
+    pub fn submit_write(&self, start: u64, data: Vec<u8>) -> Result<Arc<Request>> {
+        if data.len() % self.block_size() != 0 {
+            return Err(Errno::InvalidArgument);
+        }
+
+        return self.submit(Operation::Write, start, data);
+    }
+
+    /// Block, until `request` has been completed, and return its buffer (containing the data for reads).
+    pub fn wait(&self, request: &Arc<Request>) -> Result<Vec<u8>> {
+        loop {
+            if let Some(status) = *request.status.lock() {
+                status?;
+                return Ok(mem::take(&mut *request.buffer.lock()));
+            }
+
+            // The request may have been submitted after the current dispatcher has taken the pending requests,
+            // so it is dispatched by the first thread waking up after the current dispatcher has finished
+            match self.try_lock_dispatch() {
+                Some(_guard) => self.dispatch(),
+                None => {
+                    self.dispatched.wait_until(|| request.is_completed() || !self.dispatching.load(Relaxed), None);
+                }
+            }
+        }
+    }
+
+    pub fn read(&self, start: u64, count: usize) -> Result<Vec<u8>> {
+        let request = self.submit_read(start, count)?;
+        return self.wait(&request);
+    }
+
+    pub fn write(&self, start: u64, data: Vec<u8>) -> Result<()> {
+        let request = self.submit_write(start, data)?;
+        return self.wait(&request).map(|_| ());
+    }
+
+    /// Wait for all pending requests and flush the write cache of the device.
+    pub fn flush(&self) -> Result<()> {
+        {
+            let _guard = self.lock_dispatch();
+            self.dispatch();
+        }
+
+        return self.device.flush();
+    }
+
+    fn submit(&self, operation: Operation, start: u64, buffer: Vec<u8>) -> Result<Arc<Request>> {
+        let count = buffer.len() / self.block_size();
+        if count == 0 {
+            return Err(Errno::InvalidArgument);
+        }
+        if start.checked_add(count as u64).map_or(true, |end| end > self.block_count()) {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let request = Arc::new(Request { operation, start, count, buffer: Mutex::new(buffer), status: Mutex::new(None) });
+        self.pending.lock().push(Arc::clone(&request));
+
+        return Ok(request);
+    }
+
+    fn lock_dispatch(&self) -> DispatchGuard<'_> {
+        while self.dispatching.swap(true, Acquire) {
+            self.dispatched.wait_until(|| !self.dispatching.load(Relaxed), None);
+        }
+
+        return DispatchGuard { queue: self };
+    }
+
+    fn try_lock_dispatch(&self) -> Option<DispatchGuard<'_>> {
+        if self.dispatching.swap(true, Acquire) {
+            return None;
+        }
+
+        return Some(DispatchGuard { queue: self });
+    }
+
+    /// Pass all pending requests to the driver (must only be called with a `DispatchGuard` held).
+    fn dispatch(&self) {
+        let mut batch = mem::take(&mut *self.pending.lock());
+        if batch.is_empty() {
+            return;
+        }
+
+        // Elevator order: Serve all requests at or behind the current head position first, then wrap around
+        let head = self.head.load(Relaxed);
+        batch.sort_by_key(|request| (request.start < head, request.start));
+
+        let mut index = 0;
+        while index < batch.len() {
+            let mut end = index + 1;
+            let mut blocks = batch[index].count;
+            while end < batch.len() && batch[end].operation == batch[index].operation && batch[end].start == batch[end - 1].end()
+                && blocks + batch[end].count <= MAX_MERGED_BLOCKS {
+                blocks += batch[end].count;
+                end += 1;
+            }
+
+            self.execute(&batch[index..end]);
+            self.head.store(batch[end - 1].end(), Relaxed);
+            index = end;
+        }
+    }
+
+    /// Execute a group of requests for adjacent blocks with the same operation as a single transfer.
+    fn execute(&self, requests: &[Arc<Request>]) {
+        let start = requests[0].start;
+        if requests.len() == 1 {
+            let request = &requests[0];
+            let mut buffer = request.buffer.lock();
+            let status = match request.operation {
+                Operation::Read => self.device.read_blocks(start, &mut buffer),
+                Operation::Write => self.device.write_blocks(start, &buffer)
+            };
+
+            drop(buffer);
+            request.complete(status);
+            return;
+        }
+
+        let status = match requests[0].operation {
+            Operation::Read => {
+                let block_size = self.block_size();
+                let mut data = vec![0; requests.iter().map(|request| request.count).sum::<usize>() * block_size];
+                let status = self.device.read_blocks(start, &mut data);
+
+                if status.is_ok() {
+                    let mut offset = 0;
+                    for request in requests {
+                        let mut buffer = request.buffer.lock();
+                        let length = buffer.len();
+                        buffer.copy_from_slice(&data[offset..offset + length]);
+                        offset += length;
+                    }
+                }
+
+                status
+            }
+            Operation::Write => {
+                let data = requests.iter()
+                    .flat_map(|request| request.buffer.lock().clone())
+                    .collect::<Vec<u8>>();
+                self.device.write_blocks(start, &data)
+            }
+        };
+
+        for request in requests {
+            request.complete(status);
+        }
+    }
+}
+
+impl Drop for DispatchGuard<'_> {
+    fn drop(&mut self) {
+        self.queue.dispatching.store(false, Release);
+        self.queue.dispatched.notify_all();
+    }
+}
@@ -0,0 +1,48 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::block::{BlockDevice, Result};
+use crate::sync::RwLock;
+
+/// Block device, that keeps its contents in memory (e.g. for testing filesystems without a disk).
+pub struct Ramdisk {
+    block_size: usize,
+    data: RwLock<Vec<u8>>,
+}
+
+impl Ramdisk {
+    pub fn new(block_count: usize, block_size: usize) -> Self {
+        Self { block_size, data: RwLock::new(vec![0; block_count * block_size]) }
+    }
+
+    /// Create a ramdisk with the contents of an image (its size is rounded up to a multiple of `block_size`).
+    pub fn from_image(image: &[u8], block_size: usize) -> Self {
+        let mut data = Vec::from(image);
+        data.resize(image.len().div_ceil(block_size) * block_size, 0);
+
+        Self { block_size, data: RwLock::new(data) }
+    }
+}
+
+impl BlockDevice for Ramdisk {
+    fn block_size(&self) -> usize {
+        return self.block_size;
+    }
+
+    fn block_count(&self) -> u64 {
+        return (self.data.read().len() / self.block_size) as u64;
+    }
+
+    fn read_blocks(&self, start: u64, buffer: &mut [u8]) -> Result<()> {
+        let offset = start as usize * self.block_size;
+        buffer.copy_from_slice(&self.data.read()[offset..offset + buffer.len()]);
+
+        return Ok(());
+    }
+
+    fn write_blocks(&self, start: u64, buffer: &[u8]) -> Result<()> {
+        let offset = start as usize * self.block_size;
+        self.data.write()[offset..offset + buffer.len()].copy_from_slice(buffer);
+
+        return Ok(());
+    }
+}
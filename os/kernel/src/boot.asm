@@ -7,6 +7,12 @@
 ; Kernel constants
 STACK_SIZE equ 0x10000
 
+; Segments of the temporary GDT used by switch_paging_levels
+SWITCH_CODE64_SELECTOR equ 0x08
+SWITCH_CODE32_SELECTOR equ 0x10
+SWITCH_DATA_SELECTOR equ 0x18
+CR4_LA57 equ (1 << 12)
+
 ; Multiboot2 constants
 MULTIBOOT2_HEADER_MAGIC equ 0xe85250d6
 MULTIBOOT2_HEADER_ARCHITECTURE equ 0
@@ -155,8 +161,108 @@ clear_bss:
     mov esi, ebx
     call start
 
+; Switch between 4-level and 5-level paging and load the page tables at the same time
+; (called from Rust as 'switch_paging_levels(root_table: u64, five_levels: u32)' with interrupts disabled).
+; CR4.LA57 can only be changed while paging is disabled, which requires leaving long mode via compatibility mode.
+; So this code, the stack and the root table must be identity mapped below 4 GiB (in the old and in the new page tables).
+[GLOBAL switch_paging_levels]
+switch_paging_levels:
+    ; The upper halves of all registers and r8-r15 are undefined after leaving 64-bit mode
+    push rbx
+    push rbp
+    push r12
+    push r13
+    push r14
+    push r15
+
+    sgdt [switch_saved_gdtr]
+    mov [switch_saved_cs], cs
+    mov [switch_saved_ss], ss
+    mov [switch_saved_ds], ds
+    mov ebx, esi
+    lgdt [switch_gdtr]
+
+    ; Far return into the 32-bit code segment (compatibility mode)
+    push SWITCH_CODE32_SELECTOR
+    mov rax, switch_compatibility_mode
+    push rax
+    retfq
+
+[BITS 32]
+switch_compatibility_mode:
+    mov ax, SWITCH_DATA_SELECTOR
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+
+    ; Disable paging (deactivates long mode)
+    mov eax, cr0
+    and eax, 0x7fffffff
+    mov cr0, eax
+
+    mov eax, cr4
+    and eax, ~CR4_LA57
+    test ebx, ebx
+    jz .set_cr4
+    or eax, CR4_LA57
+.set_cr4:
+    mov cr4, eax
+    mov cr3, edi
+
+    ; Enable paging again (EFER.LME is still set, so long mode is activated again)
+    mov eax, cr0
+    or eax, 0x80000000
+    mov cr0, eax
+    jmp SWITCH_CODE64_SELECTOR:switch_long_mode
+
+[BITS 64]
+switch_long_mode:
+    mov esp, esp ; Clear the upper half of the stack pointer
+    lgdt [switch_saved_gdtr]
+
+    ; Reload the segment registers from the kernel GDT
+    movzx rax, word [switch_saved_cs]
+    push rax
+    mov rax, switch_done
+    push rax
+    retfq
+switch_done:
+    mov ax, [switch_saved_ss]
+    mov ss, ax
+    mov ax, [switch_saved_ds]
+    mov ds, ax
+    mov es, ax
+
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbp
+    pop rbx
+    ret
+
+; Temporary GDT with flat segments for switch_paging_levels
+align 8
+switch_gdt:
+    dq 0
+    dq 0x00af9a000000ffff ; 64-bit code
+    dq 0x00cf9a000000ffff ; 32-bit code
+    dq 0x00cf92000000ffff ; Data
+switch_gdtr:
+    dw switch_gdtr - switch_gdt - 1
+    dq switch_gdt
+
 [SECTION .bss]
 
+switch_saved_gdtr:
+    resb 10
+switch_saved_cs:
+    resw 1
+switch_saved_ss:
+    resw 1
+switch_saved_ds:
+    resw 1
+
 global init_stack:data (init_stack.end - init_stack)
 init_stack:
 	  resb STACK_SIZE
@@ -1,22 +1,34 @@
 use alloc::boxed::Box;
-use crate::interrupt::interrupt_dispatcher;
+use crate::bench;
+use crate::cpu::features;
+use crate::cpu::features::Feature;
+use crate::random;
+use crate::smbios;
+use crate::debug::{backtrace, gdb, profiler, trace, watchdog, SerialWriter, TerminalWriter};
+use crate::interrupt::{deferred, interrupt_dispatcher};
+use crate::interrupt::exception::{double_fault_stack_end, DOUBLE_FAULT_STACK_INDEX};
+use crate::device::serial;
+use crate::device::serial::{BaudRate, ComPort};
 use crate::syscall::syscall_dispatcher;
 use crate::process::thread::Thread;
 use alloc::format;
 use alloc::rc::Rc;
+use alloc::sync::Arc;
 use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 use core::ffi::c_void;
 use core::mem::size_of;
 use core::ops::Deref;
 use core::ptr;
 use chrono::DateTime;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use multiboot2::{BootInformation, BootInformationHeader, EFIMemoryMapTag, MemoryAreaType, MemoryMapTag, Tag};
 use uefi::prelude::*;
 use uefi::table::boot::{MemoryMap, PAGE_SIZE};
 use uefi::table::Runtime;
 use uefi_raw::table::boot::MemoryType;
-use x86_64::instructions::interrupts;
+use graphic::font::Font;
+use x86_64::instructions::{interrupts, tlb};
 use x86_64::instructions::segmentation::{Segment, CS, DS, ES, FS, GS, SS};
 use x86_64::instructions::tables::load_tss;
 use x86_64::{PhysAddr, VirtAddr};
@@ -26,9 +38,32 @@ use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame};
 use x86_64::PrivilegeLevel::Ring0;
 use x86_64::structures::paging::frame::PhysFrameRange;
 use x86_64::structures::paging::page::PageRange;
-use crate::{allocator, apic, built_info, efi_system_table, gdt, init_acpi_tables, init_apic, init_efi_system_table, init_initrd, init_keyboard, init_serial_port, init_terminal, initrd, logger, memory, ps2_devices, scheduler, serial_port, terminal, timer, tss};
+use crate::{allocator, apic, built_info, efi_system_table, gdt, init_acpi_tables, init_apic, init_efi_system_table, init_initrd, init_keyboard, init_pci, init_serial_port, init_serial_terminal, init_terminal, initrd, logger, memory, ps2_devices, scheduler, serial_port, set_console_font, terminal, tss};
 use crate::memory::MemorySpace;
-use crate::process::process::create_process;
+use crate::process::process::{create_process, load_application, load_application_on_console, set_init_process};
+use crate::fs::initramfs::Initramfs;
+use crate::fs::iso9660::Iso9660;
+use crate::fs::tmpfs;
+use crate::fs::tmpfs::Tmpfs;
+use crate::fs::devfs;
+use crate::fs::devfs::Devfs;
+use crate::fs::procfs::Procfs;
+use crate::device::{ac97, ahci, ata, compositor, nvme, pci, power, qemu_cfg, rtl8139};
+use crate::device::usb::xhci;
+use crate::device::framebuffer::FramebufferDevice;
+use crate::device::virtio::{gpu, rng};
+use crate::device::pseudo::{NullDevice, RandomDevice, ZeroDevice};
+use crate::device::serial::SerialDevice;
+use crate::device::ps2::MouseDevice;
+use crate::device::terminal::{TerminalDevice, CONSOLE_COUNT};
+use crate::fs::vfs;
+use crate::block;
+use crate::net;
+use crate::net::capture::PcapDevice;
+use crate::net::inet;
+use crate::net::inet::dhcp;
+use crate::net::telnet;
+use syscall::file::FileType;
 
 extern "C" {
     static ___KERNEL_DATA_START__: u64;
@@ -36,15 +71,22 @@ extern "C" {
 }
 
 const INIT_HEAP_PAGES: usize = 0x400;
+/// First user process, which starts and supervises all services (e.g. a shell on each virtual console) as configured in '/etc/inittab'.
+const INIT_APPLICATION: &str = "/bin/init";
+/// Framebuffers at least this wide use a 16x32 console font by default (e.g. 2560x1440 or 4K).
+const HIGH_RESOLUTION_WIDTH: u32 = 2560;
 
 #[no_mangle]
 pub extern "C" fn start(multiboot2_magic: u32, multiboot2_addr: *const BootInformationHeader) {
+    // Initialize serial port for early log messages and panics (works without heap)
+    serial::init_early_serial();
+
     // Initialize logger
     if logger().lock().init().is_err() {
         panic!("Failed to initialize logger!")
     }
 
-    // Log messages and panics are now working, but cannot use format string until the heap is initialized later on
+    // Log messages and panics are now working (via the early serial port)
     info!("Welcome to hhuTOSr early boot environment!");
 
     // Get multiboot information
@@ -94,23 +136,60 @@ pub extern "C" fn start(multiboot2_magic: u32, multiboot2_addr: *const BootInfor
     info!("Initializing GDT");
     init_gdt();
 
+    // Setup interrupt descriptor table, so that exceptions during memory initialization are reported
+    info!("Initializing IDT");
+    interrupt_dispatcher::setup_idt();
+
     // The bootloader marks the kernel image region as available, so we need to reserve it manually
     unsafe { memory::physical::reserve(kernel_image_region()); }
 
-    // and initialize kernel heap, after which format strings may be used in logs and panics.
+    // and initialize kernel heap
     info!("Initializing kernel heap");
     let heap_region = memory::physical::alloc(INIT_HEAP_PAGES);
     unsafe { allocator().init(&heap_region); }
     debug!("Kernel heap is initialized [0x{:x} - 0x{:x}]", heap_region.start.start_address().as_u64(), heap_region.end.start_address().as_u64());
+
+    // QEMU may pass further options via fw_cfg, which are appended to the kernel command line (see `qemu_cfg::boot_options()`)
+    let mut cmdline = String::from(multiboot.command_line_tag().and_then(|tag| tag.cmdline().ok()).unwrap_or(""));
+    if let Some(options) = qemu_cfg::boot_options() {
+        cmdline.push(' ');
+        cmdline.push_str(&options);
+    }
+
+    // Apply log level settings from the kernel command line (e.g. 'loglevel=info,memory::virtual=trace')
+    if let Some(spec) = cmdline.split_whitespace().find_map(|arg| arg.strip_prefix("loglevel=")) {
+        if logger().lock().configure(spec).is_err() {
+            warn!("Invalid log level settings [{}]", spec);
+        }
+    }
     debug!("Page frame allocator:\n{}", memory::physical::dump());
 
+    // Detect optional CPU features (needs the heap for vendor and brand strings)
+    info!("Detecting CPU features");
+    features::init();
+
+    // Seed the random number generator (needs the CPU features for RDSEED)
+    info!("Initializing random number generator");
+    random::init();
+
     // Initialize virtual memory management
-    info!("Initializing paging");
+    // 5-level paging is used, if the CPU supports it ('paging=4' forces 4-level paging, e.g. for comparing both)
+    let five_levels = features::has(Feature::La57) && !cmdline.split_whitespace().any(|arg| arg == "paging=4");
+    info!("Initializing paging ({} levels)", if five_levels { 5 } else { 4 });
+    memory::r#virtual::init_paging(five_levels);
     let kernel_process = create_process();
-    kernel_process.address_space().load();
+    memory::r#virtual::load_kernel_address_space(&kernel_process.address_space());
 
     // Initialize serial port and enable serial logging
-    init_serial_port();
+    // The serial port may also be used as terminal (e.g. 'console=serial' or 'console=serial,57600')
+    let serial_console = cmdline.split_whitespace()
+        .filter_map(|arg| arg.strip_prefix("console=serial"))
+        .find(|option| option.is_empty() || option.starts_with(','));
+    let serial_speed = serial_console.and_then(|option| option.strip_prefix(','))
+        .and_then(|rate| rate.parse::<u32>().ok())
+        .and_then(BaudRate::from_rate)
+        .unwrap_or(BaudRate::Baud115200);
+    init_serial_port(serial_speed);
     if let Some(serial) = serial_port() {
         logger().lock().register(serial);
     }
@@ -122,7 +201,10 @@ pub extern "C" fn start(multiboot2_magic: u32, multiboot2_addr: *const BootInfor
 
     let fb_start_page = Page::from_start_address(VirtAddr::new(fb_info.address())).expect("Framebuffer address is not page aligned!");
     let fb_end_page = Page::from_start_address(VirtAddr::new(fb_info.address() + (fb_info.height() * fb_info.pitch()) as u64).align_up(PAGE_SIZE as u64)).unwrap();
-    kernel_process.address_space().map(PageRange { start: fb_start_page, end: fb_end_page }, MemorySpace::Kernel, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+    // The framebuffer is only written, so it is mapped write-combining (it may already be mapped, so the TLB is flushed)
+    memory::pat::init();
+    kernel_process.address_space().map(PageRange { start: fb_start_page, end: fb_end_page }, MemorySpace::Kernel, PageTableFlags::PRESENT | PageTableFlags::WRITABLE | memory::pat::WRITE_COMBINING_FLAGS);
+    tlb::flush_all();
 
     init_terminal(fb_info.address() as *mut u8, fb_info.pitch(), fb_info.width(), fb_info.height(), fb_info.bpp());
     logger().lock().register(terminal());
@@ -158,24 +240,22 @@ pub extern "C" fn start(multiboot2_magic: u32, multiboot2_addr: *const BootInfor
     init_acpi_tables(rsdp_addr);
 
     // Initialize interrupts
-    info!("Initializing IDT");
-    interrupt_dispatcher::setup_idt();
     info!("Initializing system calls");
     syscall_dispatcher::init();
     init_apic();
 
-    // Initialize timer
-    {
-        info!("Initializing timer");
-        let mut timer = timer().write();
-        timer.interrupt_rate(1);
-        timer.plugin();
-    }
+    // Initialize timer (the APIC timer is used as system tick, while the PIT is only needed for calibrating it)
+    info!("Initializing timer");
+    apic().start_timer(1);
 
     // Enable interrupts
     info!("Enabling interrupts");
     interrupts::enable();
 
+    // Start NMI watchdog (needs a running APIC timer and enabled interrupts)
+    info!("Initializing NMI watchdog");
+    watchdog::init();
+
     // Initialize EFI runtime service (if available and not done already during memory initialization)
     if efi_system_table().is_none() {
         if let Some(sdt_tag) = multiboot.efi_sdt64_tag() {
@@ -193,21 +273,154 @@ pub extern "C" fn start(multiboot2_magic: u32, multiboot2_addr: *const BootInfor
         info!("EFI runtime services available (Vendor: [{}], UEFI version: [{}])", system_table.firmware_vendor(), system_table.uefi_revision());
     }
 
+    // Read hardware inventory from SMBIOS (needs the EFI system table to locate the entry point on EFI systems)
+    info!("Reading SMBIOS tables");
+    smbios::init();
+
+    // Start worker thread for deferred interrupt work
+    info!("Initializing deferred work queue");
+    deferred::init();
+
+    // Start thread for writing back cached blocks
+    info!("Initializing block cache");
+    block::cache::init();
+
+    // Register loopback network interface (NIC drivers register their own interfaces)
+    info!("Initializing network interfaces");
+    net::init();
+
+    // Install protocol stack (Ethernet, ARP, IPv4, ICMP, TCP and UDP)
+    info!("Initializing network protocol stack");
+    inet::init();
+
+    // Scan PCI bus
+    info!("Scanning PCI bus");
+    init_pci();
+
+    // Bind drivers to PCI devices (disks are registered as block devices, other devices are registered in devfs)
+    info!("Probing PCI drivers");
+    pci::driver::register(&ata::DRIVER);
+    pci::driver::register(&ahci::DRIVER);
+    pci::driver::register(&nvme::DRIVER);
+    pci::driver::register(&gpu::DRIVER);
+    pci::driver::register(&rng::DRIVER);
+    pci::driver::register(&ac97::DRIVER);
+    pci::driver::register(&xhci::DRIVER);
+    pci::driver::register(&rtl8139::DRIVER);
+    pci::driver::probe_all();
+
+    // Request addresses for the network interfaces registered by the drivers (configured in the background)
+    info!("Configuring network interfaces via DHCP");
+    dhcp::init();
+
+    // Parse remaining ACPI tables for power management (shutdown and reboot)
+    info!("Initializing ACPI power management");
+    power::init();
+
     // Initialize keyboard
     info!("Initializing PS/2 devices");
     init_keyboard();
     ps2_devices().keyboard().plugin();
+    if let Some(mouse) = ps2_devices().mouse() {
+        mouse.plugin();
+        devfs::register("mouse", FileType::CharDevice, Arc::new(MouseDevice::new(mouse))).unwrap();
+    }
 
     // Enable serial port interrupts
     if let Some(serial) = serial_port() {
         serial.plugin();
     }
 
-    // Load initial ramdisk
-    let initrd_tag = multiboot.module_tags()
-        .find(|module| module.cmdline().is_ok_and(|name| name == "initrd"))
-        .expect("Initrd not found!");
-    init_initrd(initrd_tag);
+    // Start GDB stub on the first serial port, that is not used for logging
+    let gdb_port = [ComPort::Com1, ComPort::Com2, ComPort::Com3, ComPort::Com4].into_iter()
+        .filter(|port| serial_port().map_or(true, |serial| serial.port() != *port))
+        .find(|port| serial::check_port(*port));
+    if let Some(port) = gdb_port {
+        gdb::init(port);
+    }
+
+    // Search block devices for a CD image (ISO9660), which may carry applications and data
+    let mut cdrom = block::devices().into_iter()
+        .find_map(|name| Iso9660::new(block::device(&name).unwrap()).ok().map(|iso| (name, iso)));
+
+    // Mount initial ramdisk as root filesystem
+    // When booting from a CD image (El Torito), the initial ramdisk is optional and the image itself is used instead
+    match multiboot.module_tags().find(|module| module.cmdline().is_ok_and(|name| name == "initrd")) {
+        Some(initrd_tag) => {
+            init_initrd(initrd_tag);
+            let initramfs = Initramfs::new(initrd());
+            info!("Initial ramdisk contains [{}] files", initramfs.file_count());
+            vfs::mount("initrd", "/", Arc::new(initramfs)).expect("Failed to mount initial ramdisk!");
+        }
+        None => {
+            let (name, iso) = cdrom.take().expect("Neither initrd nor CD image found!");
+            info!("Using CD image on [{}] as root filesystem (Rock Ridge: [{}])", name, iso.has_rock_ridge());
+            vfs::mount(&name, "/", Arc::new(iso)).expect("Failed to mount CD image!");
+        }
+    }
+
+    if let Some((name, iso)) = cdrom {
+        if let Err(err) = vfs::mount(&name, "/cdrom", Arc::new(iso)) {
+            warn!("Failed to mount CD image on [{}] at [/cdrom] (Error: {:?})", name, err);
+        }
+    }
+
+    // Mount in-memory filesystem for temporary files (size limit can be set in KiB, e.g. 'tmpfs_size=8192')
+    let tmpfs_size = cmdline.split_whitespace()
+        .find_map(|arg| arg.strip_prefix("tmpfs_size="))
+        .and_then(|size| size.parse::<usize>().ok())
+        .map_or(tmpfs::DEFAULT_SIZE, |size| size * 1024);
+    if let Err(err) = vfs::mount("tmpfs", "/tmp", Arc::new(Tmpfs::new(tmpfs_size))) {
+        warn!("Failed to mount tmpfs at [/tmp] (Error: {:?})", err);
+    }
+
+    // Mount device filesystem and register devices, that are always present (drivers register their own devices)
+    match vfs::mount("devfs", "/dev", Arc::new(Devfs)) {
+        Ok(()) => {
+            devfs::register("null", FileType::CharDevice, Arc::new(NullDevice)).unwrap();
+            devfs::register("zero", FileType::CharDevice, Arc::new(ZeroDevice)).unwrap();
+            devfs::register("random", FileType::CharDevice, Arc::new(RandomDevice)).unwrap();
+            devfs::register("pcap", FileType::CharDevice, Arc::new(PcapDevice)).unwrap();
+            devfs::register("tty", FileType::CharDevice, Arc::new(TerminalDevice::new(0))).unwrap();
+            // The serial terminal replaces all consoles, so only the first one is available as device node
+            let consoles = if serial_console.is_some() { 1 } else { CONSOLE_COUNT };
+            for console in 0..consoles {
+                devfs::register(&format!("tty{}", console + 1), FileType::CharDevice, Arc::new(TerminalDevice::new(console))).unwrap();
+            }
+            devfs::register("fb0", FileType::CharDevice, Arc::new(FramebufferDevice::new(fb_info.address() as *mut u8, fb_info.width(), fb_info.height(), fb_info.pitch(), fb_info.bpp()))).unwrap();
+            compositor::init(fb_info.address() as *mut u8, fb_info.pitch(), fb_info.width(), fb_info.height(), fb_info.bpp()).unwrap();
+            if let Some(serial) = serial_port() {
+                devfs::register("ttyS0", FileType::CharDevice, Arc::new(SerialDevice::new(serial))).unwrap();
+            }
+        }
+        Err(err) => warn!("Failed to mount devfs at [/dev] (Error: {:?})", err)
+    }
+
+    // Mount process information filesystem
+    if let Err(err) = vfs::mount("procfs", "/proc", Arc::new(Procfs)) {
+        warn!("Failed to mount procfs at [/proc] (Error: {:?})", err);
+    }
+
+    // Load console font from the root filesystem (e.g. 'font=/usr/share/fonts/ter-u32n.psf')
+    // Without a font given, the built-in font is drawn twice as large on high-resolution framebuffers
+    let font = match cmdline.split_whitespace().find_map(|arg| arg.strip_prefix("font=")) {
+        Some(path) => match vfs::read_all(path) {
+            Ok(data) => Font::from_psf(&data).or_else(|| {
+                warn!("Font [{}] is not a valid PSF font", path);
+                None
+            }),
+            Err(err) => {
+                warn!("Failed to load font [{}] (Error: {:?})", path, err);
+                None
+            }
+        },
+        None if fb_info.width() >= HIGH_RESOLUTION_WIDTH => Some(Font::builtin(2)),
+        None => None
+    };
+    if let Some(font) = font {
+        info!("Using [{}x{}] console font", font.width(), font.height());
+        set_console_font(font);
+    }
 
     // Ready terminal read thread
     scheduler().ready(Thread::new_kernel_thread(Box::new(|| {
@@ -219,15 +432,54 @@ pub extern "C" fn start(multiboot2_magic: u32, multiboot2_addr: *const BootInfor
             match terminal.read_byte() {
                 -1 => panic!("Terminal input stream closed!"),
                 0x0a => {
-                    match initrd().entries().find(|entry| entry.filename().as_str() == command) {
-                        Some(app) => {
-                            let thread = Thread::new_user_thread(app.data());
-                            scheduler().ready(Rc::clone(&thread));
-                            thread.join();
+                    if command == "backtrace" {
+                        backtrace::print(&mut TerminalWriter, backtrace::frame_pointer());
+                    } else if command == "dmesg" {
+                        let kernel_log = logger().lock().kernel_log();
+                        print!("{}", kernel_log);
+                    } else if command == "lockstat" {
+                        #[cfg(feature = "lockdep")]
+                        crate::sync::lockdep::print_statistics(&mut TerminalWriter);
+                        #[cfg(not(feature = "lockdep"))]
+                        println!("Lock statistics are only available, if the kernel is built with the 'lockdep' feature!");
+                    } else if command == "trace" || command.starts_with("trace ") {
+                        let subcommand = command["trace".len()..].trim();
+                        match subcommand.split_once(' ').unwrap_or((subcommand, "")) {
+                            ("on", "") => trace::enable(),
+                            ("off", "") => trace::disable(),
+                            ("clear", "") => trace::clear(),
+                            ("dump", "") => trace::export(&mut SerialWriter),
+                            ("filter", modules) => trace::set_filter(modules),
+                            _ => println!("Usage: trace on|off|clear|dump|filter [module,...]")
                         }
-                        None => {
-                            if !command.is_empty() {
-                                println!("Command not found!");
+                    } else if command == "bench" || command.starts_with("bench ") {
+                        bench::run(command.trim_start_matches("bench").trim());
+                    } else if command == "profile" || command.starts_with("profile ") {
+                        match command.trim_start_matches("profile").trim() {
+                            "" => profile(5),
+                            seconds => match seconds.parse::<usize>() {
+                                Ok(seconds) => profile(seconds),
+                                Err(_) => println!("Usage: profile [seconds]")
+                            }
+                        }
+                    } else if command == "loglevel" {
+                        let settings = logger().lock().level_settings();
+                        println!("{}", settings);
+                    } else if let Some(spec) = command.strip_prefix("loglevel ") {
+                        if logger().lock().configure(spec).is_err() {
+                            println!("Invalid log level settings! (Usage: loglevel [target=]level[,...])");
+                        }
+                    } else {
+                        let words = command.split_whitespace().collect::<Vec<&str>>();
+                        match load_application(words.first().copied().unwrap_or(""), words.get(1..).unwrap_or(&[])) {
+                            Ok(thread) => {
+                                scheduler().ready(Rc::clone(&thread));
+                                thread.join();
+                            }
+                            Err(_) => {
+                                if !command.is_empty() {
+                                    println!("Command not found!");
+                                }
                             }
                         }
                     }
@@ -241,26 +493,72 @@ pub extern "C" fn start(multiboot2_magic: u32, multiboot2_addr: *const BootInfor
     })));
 
     // Ready shell thread
-    /*scheduler().ready(Thread::new_user_thread(initrd().entries()
-        .find(|entry| entry.filename().as_str() == "shell")
-        .expect("Shell application not available!")
-        .data()));*/
+    /*scheduler().ready(load_application("shell").expect("Shell application not available!"));*/
+
+    // Accept remote shell sessions (each one gets a shell on its own remote console)
+    // The service is unauthenticated, so it must be enabled explicitly with 'telnet'
+    if cmdline.split_whitespace().any(|arg| arg == "telnet") {
+        telnet::init();
+    }
 
     // Disable terminal logging
     logger().lock().remove(terminal());
+
+    // Switch to serial terminal (serial logging is disabled as well, so that log messages do not mess up the shell)
+    if serial_console.is_some() {
+        if let Some(serial) = serial_port() {
+            logger().lock().remove(serial);
+            init_serial_terminal();
+        } else {
+            warn!("Serial console requested, but no serial port available!");
+        }
+    }
+
     terminal().clear();
 
     println!(include_str!("banner.txt"), version, git_ref.rsplit("/").next().unwrap_or(git_ref), git_commit, build_date,
              built_info::RUSTC_VERSION.split_once("(").unwrap_or((built_info::RUSTC_VERSION, "")).0.trim(), bootloader_name);
 
+    // Run kernel tests instead of starting the system (exits QEMU)
+    // They run in a kernel thread, so that tests can start threads of their own (the scheduler does not return)
+    #[cfg(feature = "test")]
+    {
+        scheduler().ready(Thread::new_kernel_thread(Box::new(|| crate::test::run())));
+        info!("Starting scheduler for kernel tests");
+        scheduler().start();
+    }
+
+    // Start the init process on the first console, which starts all services (e.g. a shell on each further virtual console)
+    match load_application_on_console(INIT_APPLICATION, 0) {
+        Ok(thread) => {
+            set_init_process(&thread.process());
+            scheduler().ready(thread);
+        }
+        Err(err) => warn!("Failed to start init process [{}] (Error: {:?})", INIT_APPLICATION, err)
+    }
+
     info!("Starting scheduler");
-    apic().start_timer(10);
     scheduler().start();
 }
 
+/// Sample the whole system for the given amount of seconds and print the profile to the terminal.
+fn profile(seconds: usize) {
+    if profiler::start(profiler::DEFAULT_PERIOD).is_none() {
+        println!("Profiling is not possible without performance counters or NMI watchdog!");
+        return;
+    }
+
+    println!("Profiling for [{}] seconds...", seconds);
+    scheduler().sleep(seconds * 1000);
+    profiler::stop();
+
+    profiler::report(&mut TerminalWriter);
+}
+
 fn init_gdt() {
     let mut gdt = gdt().lock();
-    let tss = tss().lock();
+    let mut tss = tss().lock();
+    tss.interrupt_stack_table[DOUBLE_FAULT_STACK_INDEX as usize] = double_fault_stack_end();
 
     gdt.add_entry(Descriptor::kernel_code_segment());
     gdt.add_entry(Descriptor::kernel_data_segment());
@@ -0,0 +1,143 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+use log::info;
+use raw_cpuid::CpuId;
+use spin::Once;
+use x86_64::registers::control::{Cr4, Cr4Flags, Efer, EferFlags};
+
+/// Optional CPU features, the kernel is interested in.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Feature {
+    /// No-execute bit in page table entries.
+    Nx,
+    /// 1 GiB pages in the page directory pointer table.
+    HugePages,
+    /// Process-context identifiers, tagging TLB entries with the address space they belong to.
+    Pcid,
+    /// `rdfsbase`, `wrfsbase`, `rdgsbase` and `wrgsbase` instructions.
+    FsGsBase,
+    Xsave,
+    Avx,
+    RdRand,
+    RdSeed,
+    /// Time stamp counter running at a constant rate in all power states.
+    InvariantTsc,
+    X2Apic,
+    /// 5-level paging with 57-bit virtual addresses.
+    La57,
+}
+
+/// Identification and features of the CPU, read via CPUID once at boot (see `init()`).
+pub struct CpuInfo {
+    vendor: String,
+    brand: String,
+    family: u8,
+    model: u8,
+    stepping: u8,
+    features: u32,
+}
+
+static CPU_INFO: Once<CpuInfo> = Once::new();
+
+impl Feature {
+    pub const ALL: [Feature; 11] = [Feature::Nx, Feature::HugePages, Feature::Pcid, Feature::FsGsBase, Feature::Xsave,
+        Feature::Avx, Feature::RdRand, Feature::RdSeed, Feature::InvariantTsc, Feature::X2Apic, Feature::La57];
+
+    /// Name of the feature, as used by Linux in '/proc/cpuinfo'.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Feature::Nx => "nx",
+            Feature::HugePages => "pdpe1gb",
+            Feature::Pcid => "pcid",
+            Feature::FsGsBase => "fsgsbase",
+            Feature::Xsave => "xsave",
+            Feature::Avx => "avx",
+            Feature::RdRand => "rdrand",
+            Feature::RdSeed => "rdseed",
+            Feature::InvariantTsc => "constant_tsc",
+            Feature::X2Apic => "x2apic",
+            Feature::La57 => "la57",
+        }
+    }
+}
+
+impl CpuInfo {
+    fn detect() -> Self {
+        let cpuid = CpuId::new();
+        let feature_info = cpuid.get_feature_info();
+        let extended_info = cpuid.get_extended_processor_and_feature_identifiers();
+        let extended_features = cpuid.get_extended_feature_info();
+        let power_info = cpuid.get_advanced_power_mgmt_info();
+
+        let supported = |feature: Feature| match feature {
+            Feature::Nx => extended_info.as_ref().is_some_and(|info| info.has_execute_disable()),
+            Feature::HugePages => extended_info.as_ref().is_some_and(|info| info.has_1gib_pages()),
+            Feature::Pcid => feature_info.as_ref().is_some_and(|info| info.has_pcid()),
+            Feature::FsGsBase => extended_features.as_ref().is_some_and(|info| info.has_fsgsbase()),
+            Feature::Xsave => feature_info.as_ref().is_some_and(|info| info.has_xsave()),
+            Feature::Avx => feature_info.as_ref().is_some_and(|info| info.has_avx()),
+            Feature::RdRand => feature_info.as_ref().is_some_and(|info| info.has_rdrand()),
+            Feature::RdSeed => extended_features.as_ref().is_some_and(|info| info.has_rdseed()),
+            Feature::InvariantTsc => power_info.as_ref().is_some_and(|info| info.has_invariant_tsc()),
+            Feature::X2Apic => feature_info.as_ref().is_some_and(|info| info.has_x2apic()),
+            Feature::La57 => extended_features.as_ref().is_some_and(|info| info.has_la57()),
+        };
+
+        return Self {
+            vendor: cpuid.get_vendor_info().map_or(String::from("Unknown"), |vendor| vendor.as_str().to_string()),
+            brand: cpuid.get_processor_brand_string().map_or(String::from("Unknown"), |brand| brand.as_str().trim().to_string()),
+            family: feature_info.as_ref().map_or(0, |info| info.family_id()),
+            model: feature_info.as_ref().map_or(0, |info| info.model_id()),
+            stepping: feature_info.as_ref().map_or(0, |info| info.stepping_id()),
+            features: Feature::ALL.iter().filter(|feature| supported(**feature)).fold(0, |flags, feature| flags | 1 << *feature as u32),
+        };
+    }
+
+    pub fn has(&self, feature: Feature) -> bool {
+        return self.features & (1 << feature as u32) != 0;
+    }
+
+    /// Names of all supported features, separated by spaces.
+    pub fn flags(&self) -> String {
+        let names = Feature::ALL.iter().filter(|feature| self.has(**feature)).map(|feature| feature.name());
+        return names.collect::<Vec<&str>>().join(" ");
+    }
+
+    /// Content of '/proc/cpuinfo' (in the format used by Linux, so that existing tools can parse it).
+    pub fn describe(&self) -> String {
+        let mut description = String::new();
+        writeln!(description, "vendor_id\t: {}", self.vendor).unwrap();
+        writeln!(description, "cpu family\t: {}", self.family).unwrap();
+        writeln!(description, "model\t\t: {}", self.model).unwrap();
+        writeln!(description, "model name\t: {}", self.brand).unwrap();
+        writeln!(description, "stepping\t: {}", self.stepping).unwrap();
+        writeln!(description, "flags\t\t: {}", self.flags()).unwrap();
+
+        return description;
+    }
+}
+
+/// Run CPUID, log a summary and enable optional features, which do not need further support by the kernel
+/// (the no-execute bit and the FS/GS base instructions). Needs the kernel heap.
+pub fn init() {
+    let info = CPU_INFO.call_once(CpuInfo::detect);
+    info!("CPU: [{}] (Vendor: [{}], Family: [{}], Model: [{}], Stepping: [{}])", info.brand, info.vendor, info.family, info.model, info.stepping);
+    info!("CPU features: [{}]", info.flags());
+
+    if info.has(Feature::Nx) {
+        unsafe { Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE)); }
+    }
+    if info.has(Feature::FsGsBase) {
+        unsafe { Cr4::update(|flags| flags.insert(Cr4Flags::FSGSBASE)); }
+    }
+}
+
+pub fn cpu_info() -> &'static CpuInfo {
+    return CPU_INFO.get().expect("Trying to access CPU information before initialization!");
+}
+
+/// Check for an optional CPU feature (always false before `init()` has been called).
+pub fn has(feature: Feature) -> bool {
+    return CPU_INFO.get().is_some_and(|info| info.has(feature));
+}
@@ -0,0 +1,58 @@
+use core::arch::asm;
+use core::fmt::Write;
+use crate::symbols;
+
+/// Maximum number of frames, that are walked (protects against endless loops in corrupted frame pointer chains).
+const MAX_FRAMES: usize = 32;
+
+/// Read the frame pointer of the calling function.
+#[inline(always)]
+pub fn frame_pointer() -> u64 {
+    let rbp: u64;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)); }
+
+    return rbp;
+}
+
+/// Walk the frame pointer chain, starting at `rbp`, and call `visit` with the return address of each frame.
+/// The kernel is compiled with frame pointers (see 'hhu_tosr.json'), so each frame starts with the saved rbp of its caller, followed by the return address.
+/// Frames of interrupt handlers point to the interrupted instruction, since the CPU pushes it right before the handler saves rbp.
+/// This function does not allocate memory, so that it can be used in exception and NMI handlers.
+pub fn walk(rbp: u64, mut visit: impl FnMut(u64)) {
+    let mut rbp = rbp;
+
+    for _ in 0..MAX_FRAMES {
+        if rbp == 0 || rbp % 8 != 0 {
+            break;
+        }
+
+        let frame = rbp as *const u64;
+        let return_address = unsafe { frame.add(1).read_volatile() };
+        if return_address == 0 {
+            break;
+        }
+
+        visit(return_address);
+
+        // The stack grows downwards, so the frame of the caller must be located at a higher address
+        let next_rbp = unsafe { frame.read_volatile() };
+        if next_rbp <= rbp {
+            break;
+        }
+
+        rbp = next_rbp;
+    }
+}
+
+/// Print one line per frame to `writer`, resolving addresses to function names, if possible.
+pub fn print(writer: &mut impl Write, rbp: u64) {
+    walk(rbp, |address| print_address(writer, address));
+}
+
+/// Print a single backtrace line for `address`.
+pub fn print_address(writer: &mut impl Write, address: u64) {
+    let _ = match symbols::resolve(address) {
+        Some((name, offset)) => writeln!(writer, "  0x{:016x} <{}+0x{:x}>", address, name, offset),
+        None => writeln!(writer, "  0x{:016x}", address)
+    };
+}
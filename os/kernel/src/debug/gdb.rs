@@ -0,0 +1,528 @@
+use alloc::boxed::Box;
+use core::arch::asm;
+use core::sync::atomic::AtomicU16;
+use core::sync::atomic::Ordering::Relaxed;
+use log::info;
+use x86_64::instructions::port::Port;
+use x86_64::VirtAddr;
+use crate::device::serial::{ComPort, SerialPort};
+use crate::interrupt::interrupt_dispatcher::InterruptVector;
+use crate::interrupt::interrupt_handler::InterruptHandler;
+use crate::process::process::{current_process, kernel_process};
+use crate::{apic, idt, interrupt_dispatcher, scheduler};
+
+/// Maximum size of a packet payload (announced to GDB via 'qSupported').
+const PACKET_SIZE: usize = 0x1000;
+const SIGTRAP: &[u8] = b"S05";
+const TRAP_FLAG: u64 = 1 << 8;
+const CTRL_C: u8 = 0x03;
+
+/// Registers in the order, in which GDB expects them for x86_64 (rip and eflags follow after r15).
+const NUM_GENERAL_REGISTERS: usize = 16;
+
+/// Registers saved by `trap_entry()` (in reverse push order), followed by the interrupt stack frame pushed by the CPU.
+#[repr(C)]
+struct TrapFrame {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    r11: u64,
+    r10: u64,
+    r9: u64,
+    r8: u64,
+    rbp: u64,
+    rdi: u64,
+    rsi: u64,
+    rdx: u64,
+    rcx: u64,
+    rbx: u64,
+    rax: u64,
+    rip: u64,
+    cs: u64,
+    rflags: u64,
+    rsp: u64,
+    ss: u64,
+}
+
+/// Serial connection to GDB. All I/O is polled, since interrupts are disabled, while the stub is active.
+struct Connection {
+    data_reg: Port<u8>,
+    line_status_reg: Port<u8>,
+}
+
+/// Response packets are assembled in place, since the heap may be locked, when a breakpoint is hit.
+struct Response {
+    buffer: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+struct GdbInterruptHandler {
+    port: u16,
+}
+
+/// I/O port of the serial port, that is used to talk to GDB (0 if the stub is disabled).
+static GDB_PORT: AtomicU16 = AtomicU16::new(0);
+
+/// Start the GDB stub on `port`. Breakpoint and debug exceptions are handled by the stub from now on,
+/// and sending Ctrl+C from GDB interrupts the kernel.
+pub fn init(port: ComPort) {
+    let mut serial = SerialPort::new(port);
+    serial.init_write_only();
+
+    {
+        let mut idt = idt().lock();
+        unsafe {
+            idt.debug.set_handler_addr(VirtAddr::new(trap_entry as u64));
+            idt.breakpoint.set_handler_addr(VirtAddr::new(trap_entry as u64));
+        }
+    }
+
+    let vector = match port {
+        ComPort::Com1 | ComPort::Com3 => InterruptVector::Com1,
+        ComPort::Com2 | ComPort::Com4 => InterruptVector::Com2,
+    };
+
+    interrupt_dispatcher().assign(vector, Box::new(GdbInterruptHandler { port: port as u16 }));
+    apic().allow(vector);
+    unsafe { Port::<u8>::new(port as u16 + 1).write(0x01); } // Enable receive interrupts
+
+    GDB_PORT.store(port as u16, Relaxed);
+    info!("GDB stub is listening on [{:?}]", port);
+}
+
+pub fn is_enabled() -> bool {
+    return GDB_PORT.load(Relaxed) != 0;
+}
+
+/// Stop execution and hand control over to GDB (e.g. on panic or if the magic key combination has been pressed).
+/// Does nothing, if the stub is not enabled.
+pub fn breakpoint() {
+    if is_enabled() {
+        unsafe { asm!("int3"); }
+    }
+}
+
+impl InterruptHandler for GdbInterruptHandler {
+    fn trigger(&mut self) {
+        let mut data_reg = Port::<u8>::new(self.port);
+        let mut line_status_reg = Port::<u8>::new(self.port + 5);
+
+        unsafe {
+            while line_status_reg.read() & 0x01 == 0x01 {
+                if data_reg.read() == CTRL_C {
+                    breakpoint();
+                }
+            }
+        }
+    }
+}
+
+/// Entry point for breakpoint and debug exceptions. All general purpose registers are saved,
+/// so that GDB can read and modify them, before execution is resumed via `iretq`.
+#[naked]
+unsafe extern "C" fn trap_entry() {
+    asm!(
+    "push rax",
+    "push rbx",
+    "push rcx",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push rbp",
+    "push r8",
+    "push r9",
+    "push r10",
+    "push r11",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+
+    // Stack is 16-byte aligned here (CPU aligns it before pushing the 5 qword interrupt frame, plus 15 registers)
+    "mov rdi, rsp",
+    "call {}",
+
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop r11",
+    "pop r10",
+    "pop r9",
+    "pop r8",
+    "pop rbp",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop rcx",
+    "pop rbx",
+    "pop rax",
+    "iretq",
+    sym handle_trap,
+    options(noreturn)
+    );
+}
+
+extern "C" fn handle_trap(frame: *mut TrapFrame) {
+    let frame = unsafe { frame.as_mut().unwrap() };
+    let port = GDB_PORT.load(Relaxed);
+    if port == 0 {
+        panic!("Breakpoint: Unexpected trap at [0x{:016x}] without GDB stub!", frame.rip);
+    }
+
+    frame.rflags &= !TRAP_FLAG;
+
+    let mut connection = Connection::new(port);
+    let mut packet = [0u8; PACKET_SIZE];
+    let mut response = Response::new();
+    connection.send_packet(SIGTRAP);
+
+    loop {
+        let len = connection.receive_packet(&mut packet);
+        let packet = &packet[..len];
+        response.clear();
+
+        match packet.first() {
+            Some(b'?') => response.push_bytes(SIGTRAP),
+            Some(b'g') => read_registers(frame, &mut response),
+            Some(b'G') => {
+                write_registers(frame, &packet[1..]);
+                response.push_bytes(b"OK");
+            }
+            Some(b'p') => match parse_hex(&packet[1..]).and_then(|index| register(frame, index as usize)) {
+                Some((value, size)) => response.push_hex_le(value, size),
+                None => response.push_bytes(b"E00")
+            },
+            Some(b'P') => match write_register(frame, &packet[1..]) {
+                Some(()) => response.push_bytes(b"OK"),
+                None => response.push_bytes(b"E00")
+            },
+            Some(b'm') => read_memory(&packet[1..], &mut response),
+            Some(b'M') => write_memory(&packet[1..], &mut response),
+            Some(b'c') => {
+                set_resume_address(frame, &packet[1..]);
+                return;
+            }
+            Some(b's') => {
+                set_resume_address(frame, &packet[1..]);
+                frame.rflags |= TRAP_FLAG;
+                return;
+            }
+            Some(b'D') | Some(b'k') => {
+                connection.send_packet(b"OK");
+                return;
+            }
+            Some(b'q') if packet.starts_with(b"qSupported") => response.push_bytes(b"PacketSize=1000"),
+            Some(b'q') if packet.starts_with(b"qAttached") => response.push_bytes(b"1"),
+            Some(b'q') if packet.starts_with(b"qC") => response.push_bytes(b"QC0"),
+            _ => {} // Unsupported commands are answered with an empty packet
+        }
+
+        connection.send_packet(response.as_bytes());
+    }
+}
+
+fn register(frame: &mut TrapFrame, index: usize) -> Option<(u64, usize)> {
+    let value = match index {
+        0 => frame.rax,
+        1 => frame.rbx,
+        2 => frame.rcx,
+        3 => frame.rdx,
+        4 => frame.rsi,
+        5 => frame.rdi,
+        6 => frame.rbp,
+        7 => frame.rsp,
+        8 => frame.r8,
+        9 => frame.r9,
+        10 => frame.r10,
+        11 => frame.r11,
+        12 => frame.r12,
+        13 => frame.r13,
+        14 => frame.r14,
+        15 => frame.r15,
+        16 => frame.rip,
+        17 => return Some((frame.rflags, 4)),
+        18 => return Some((frame.cs, 4)),
+        19 => return Some((frame.ss, 4)),
+        _ => return None
+    };
+
+    return Some((value, 8));
+}
+
+fn register_mut(frame: &mut TrapFrame, index: usize) -> Option<&mut u64> {
+    return match index {
+        0 => Some(&mut frame.rax),
+        1 => Some(&mut frame.rbx),
+        2 => Some(&mut frame.rcx),
+        3 => Some(&mut frame.rdx),
+        4 => Some(&mut frame.rsi),
+        5 => Some(&mut frame.rdi),
+        6 => Some(&mut frame.rbp),
+        7 => Some(&mut frame.rsp),
+        8 => Some(&mut frame.r8),
+        9 => Some(&mut frame.r9),
+        10 => Some(&mut frame.r10),
+        11 => Some(&mut frame.r11),
+        12 => Some(&mut frame.r12),
+        13 => Some(&mut frame.r13),
+        14 => Some(&mut frame.r14),
+        15 => Some(&mut frame.r15),
+        16 => Some(&mut frame.rip),
+        17 => Some(&mut frame.rflags),
+        _ => None // Segment registers cannot be changed
+    };
+}
+
+fn read_registers(frame: &mut TrapFrame, response: &mut Response) {
+    for index in 0..=NUM_GENERAL_REGISTERS + 3 {
+        let (value, size) = register(frame, index).unwrap();
+        response.push_hex_le(value, size);
+    }
+}
+
+fn write_registers(frame: &mut TrapFrame, data: &[u8]) {
+    for index in 0..=NUM_GENERAL_REGISTERS + 1 {
+        let size = if index <= NUM_GENERAL_REGISTERS { 8 } else { 4 };
+        let offset = if index <= NUM_GENERAL_REGISTERS { index * 16 } else { (NUM_GENERAL_REGISTERS + 1) * 16 };
+
+        if let Some(value) = data.get(offset..offset + size * 2).and_then(parse_hex_le) {
+            *register_mut(frame, index).unwrap() = value;
+        }
+    }
+}
+
+/// Packet format: 'P<index>=<value in target byte order>'
+fn write_register(frame: &mut TrapFrame, data: &[u8]) -> Option<()> {
+    let separator = data.iter().position(|byte| *byte == b'=')?;
+    let index = parse_hex(&data[..separator])? as usize;
+    let value = parse_hex_le(&data[separator + 1..])?;
+
+    *register_mut(frame, index)? = value;
+    return Some(());
+}
+
+fn set_resume_address(frame: &mut TrapFrame, data: &[u8]) {
+    if let Some(address) = parse_hex(data) {
+        frame.rip = address;
+    }
+}
+
+/// Packet format: 'm<address>,<length>'
+fn read_memory(data: &[u8], response: &mut Response) {
+    let (address, len) = match parse_address_and_length(data) {
+        Some(args) => args,
+        None => return response.push_bytes(b"E01")
+    };
+
+    if len * 2 > PACKET_SIZE || !is_mapped(address, len) {
+        return response.push_bytes(b"E14");
+    }
+
+    for i in 0..len {
+        let byte = unsafe { (address as *const u8).add(i).read_volatile() };
+        response.push_hex_le(byte as u64, 1);
+    }
+}
+
+/// Packet format: 'M<address>,<length>:<data>'
+fn write_memory(data: &[u8], response: &mut Response) {
+    let separator = match data.iter().position(|byte| *byte == b':') {
+        Some(separator) => separator,
+        None => return response.push_bytes(b"E01")
+    };
+
+    let (address, len) = match parse_address_and_length(&data[..separator]) {
+        Some(args) => args,
+        None => return response.push_bytes(b"E01")
+    };
+
+    let bytes = &data[separator + 1..];
+    if bytes.len() < len * 2 || !is_mapped(address, len) {
+        return response.push_bytes(b"E14");
+    }
+
+    for i in 0..len {
+        let byte = parse_hex(&bytes[i * 2..i * 2 + 2]).unwrap_or(0) as u8;
+        unsafe { (address as *mut u8).add(i).write_volatile(byte); }
+    }
+
+    response.push_bytes(b"OK");
+}
+
+/// Check via the page tables, if the given range is accessible (GDB often reads from arbitrary addresses).
+fn is_mapped(address: u64, len: usize) -> bool {
+    // The scheduler might be locked by the interrupted code, so we fall back to the kernel address space in that case
+    let process = if scheduler().is_locked() { kernel_process() } else { Some(current_process()) };
+    let process = match process {
+        Some(process) => process,
+        None => return false
+    };
+
+    let address_space = process.address_space();
+    let end = address.saturating_add(len as u64);
+    let mut page = address & !0xfff;
+
+    while page < end {
+        if VirtAddr::try_new(page).is_err() || address_space.translate(VirtAddr::new(page)).is_none() {
+            return false;
+        }
+
+        page += 0x1000;
+    }
+
+    return true;
+}
+
+fn parse_address_and_length(data: &[u8]) -> Option<(u64, usize)> {
+    let separator = data.iter().position(|byte| *byte == b',')?;
+    return Some((parse_hex(&data[..separator])?, parse_hex(&data[separator + 1..])? as usize));
+}
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    return match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None
+    };
+}
+
+/// Parse a big-endian hex number (used for addresses and lengths).
+fn parse_hex(data: &[u8]) -> Option<u64> {
+    if data.is_empty() || data.len() > 16 {
+        return None;
+    }
+
+    let mut value = 0;
+    for byte in data {
+        value = (value << 4) | hex_digit(*byte)? as u64;
+    }
+
+    return Some(value);
+}
+
+/// Parse a hex encoded value in target byte order (used for register values).
+fn parse_hex_le(data: &[u8]) -> Option<u64> {
+    if data.is_empty() || data.len() % 2 != 0 || data.len() > 16 {
+        return None;
+    }
+
+    let mut value = 0;
+    for (i, pair) in data.chunks(2).enumerate() {
+        let byte = (hex_digit(pair[0])? << 4) | hex_digit(pair[1])?;
+        value |= (byte as u64) << (i * 8);
+    }
+
+    return Some(value);
+}
+
+impl Connection {
+    fn new(port: u16) -> Self {
+        Self { data_reg: Port::new(port), line_status_reg: Port::new(port + 5) }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        unsafe {
+            while self.line_status_reg.read() & 0x01 != 0x01 {
+                core::hint::spin_loop();
+            }
+
+            return self.data_reg.read();
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        unsafe {
+            while self.line_status_reg.read() & 0x20 != 0x20 {
+                core::hint::spin_loop();
+            }
+
+            self.data_reg.write(byte);
+        }
+    }
+
+    /// Receive a packet ('$<data>#<checksum>') and acknowledge it. Returns the length of the payload.
+    fn receive_packet(&mut self, buffer: &mut [u8; PACKET_SIZE]) -> usize {
+        loop {
+            while self.read_byte() != b'$' {}
+
+            let mut len = 0;
+            let mut checksum: u8 = 0;
+            loop {
+                let byte = self.read_byte();
+                if byte == b'#' {
+                    break;
+                }
+
+                if len < PACKET_SIZE {
+                    buffer[len] = byte;
+                    len += 1;
+                }
+
+                checksum = checksum.wrapping_add(byte);
+            }
+
+            let expected = [self.read_byte(), self.read_byte()];
+            if parse_hex(&expected) == Some(checksum as u64) {
+                self.write_byte(b'+');
+                return len;
+            }
+
+            self.write_byte(b'-');
+        }
+    }
+
+    /// Send a packet and wait for GDB to acknowledge it (retransmit on '-').
+    fn send_packet(&mut self, data: &[u8]) {
+        let checksum = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+
+        loop {
+            self.write_byte(b'$');
+            for byte in data {
+                self.write_byte(*byte);
+            }
+
+            self.write_byte(b'#');
+            self.write_byte(HEX_DIGITS[(checksum >> 4) as usize]);
+            self.write_byte(HEX_DIGITS[(checksum & 0x0f) as usize]);
+
+            match self.read_byte() {
+                b'+' => return,
+                b'-' => continue,
+                _ => return // GDB may have sent a new packet without acknowledgement (e.g. in no-ack mode)
+            }
+        }
+    }
+}
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+impl Response {
+    const fn new() -> Self {
+        Self { buffer: [0; PACKET_SIZE], len: 0 }
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        let len = bytes.len().min(PACKET_SIZE - self.len);
+        self.buffer[self.len..self.len + len].copy_from_slice(&bytes[..len]);
+        self.len += len;
+    }
+
+    /// Append `size` bytes of `value` in target (little endian) byte order.
+    fn push_hex_le(&mut self, value: u64, size: usize) {
+        for i in 0..size {
+            let byte = (value >> (i * 8)) as u8;
+            self.push_bytes(&[HEX_DIGITS[(byte >> 4) as usize], HEX_DIGITS[(byte & 0x0f) as usize]]);
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        return &self.buffer[..self.len];
+    }
+}
@@ -0,0 +1,56 @@
+use core::fmt;
+use core::fmt::Write;
+use stream::OutputStream;
+use crate::device::serial::EarlySerialWriter;
+use crate::{serial_port, serial_terminal_enabled, terminal, terminal_initialized};
+
+pub mod backtrace;
+pub mod gdb;
+pub mod profiler;
+pub mod trace;
+pub mod watchdog;
+
+/// Writes directly to the serial port (if available) without locking or allocating memory.
+/// Falls back to the early serial port, as long as the serial port driver is not initialized.
+pub struct SerialWriter;
+
+/// Writes to the terminal (if initialized).
+pub struct TerminalWriter;
+
+/// Writes to the serial port and the terminal (if initialized), used for panic messages.
+pub struct PanicWriter;
+
+impl Write for SerialWriter {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        if let Some(serial) = serial_port() {
+            serial.write_str(string);
+        } else {
+            EarlySerialWriter.write_str(string)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Write for TerminalWriter {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        if terminal_initialized() {
+            terminal().write_str(string);
+        }
+
+        Ok(())
+    }
+}
+
+impl Write for PanicWriter {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        SerialWriter.write_str(string)?;
+
+        // Avoid printing everything twice, if the serial port is used as terminal
+        if serial_terminal_enabled() {
+            return Ok(());
+        }
+
+        TerminalWriter.write_str(string)
+    }
+}
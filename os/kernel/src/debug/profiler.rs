@@ -0,0 +1,302 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize};
+use core::sync::atomic::Ordering::Relaxed;
+use log::info;
+use raw_cpuid::CpuId;
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::idt::InterruptStackFrame;
+use crate::debug::{backtrace, watchdog};
+use crate::process::process::find_process;
+use crate::process::scheduler::{current_process_id, current_thread_id};
+use crate::{apic, symbols};
+
+const IA32_PMC0: u32 = 0xc1;
+const IA32_PERFEVTSEL0: u32 = 0x186;
+const IA32_PERF_GLOBAL_STATUS: u32 = 0x38e;
+const IA32_PERF_GLOBAL_CTRL: u32 = 0x38f;
+const IA32_PERF_GLOBAL_OVF_CTRL: u32 = 0x390;
+
+/// Architectural event 'UnHalted Core Cycles' (event 0x3c, umask 0x00), counted in user and kernel mode with overflow interrupt enabled.
+const EVENT_UNHALTED_CORE_CYCLES: u64 = 0x3c;
+const EVTSEL_USR: u64 = 1 << 16;
+const EVTSEL_OS: u64 = 1 << 17;
+const EVTSEL_INT: u64 = 1 << 20;
+const EVTSEL_EN: u64 = 1 << 22;
+
+/// Number of core cycles between two samples (roughly 200 samples per second on a 2 GHz core).
+pub const DEFAULT_PERIOD: u64 = 10_000_000;
+const MAX_SAMPLES: usize = 4096;
+/// Number of kernel return addresses, that are recorded per sample for the top-down profile.
+const CALLCHAIN_DEPTH: usize = 8;
+/// Number of functions shown in each profile.
+const REPORT_ENTRIES: usize = 20;
+
+#[derive(Copy, Clone)]
+struct Sample {
+    rip: u64,
+    process: usize,
+    thread: usize,
+    user: bool,
+    callchain: [u64; CALLCHAIN_DEPTH],
+    callchain_len: usize,
+}
+
+struct SampleBuffer {
+    samples: [Sample; MAX_SAMPLES],
+    len: usize,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum SampleSource {
+    /// Overflow interrupts of the first performance monitoring counter.
+    PerformanceCounter,
+    /// NMIs of the watchdog, used if the CPU has no architectural performance monitoring (e.g. QEMU without KVM).
+    Watchdog,
+}
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+static USE_PMU: AtomicBool = AtomicBool::new(false);
+static PERIOD: AtomicU64 = AtomicU64::new(DEFAULT_PERIOD);
+static PMU_VERSION: AtomicU8 = AtomicU8::new(0);
+static COUNTER_WIDTH: AtomicU8 = AtomicU8::new(0);
+/// Samples, that could not be recorded, because the buffer was full or locked.
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+// A plain spinlock is used, since samples are recorded in NMI context, where no lock debugging is possible.
+static SAMPLES: spin::Mutex<SampleBuffer> = spin::Mutex::new(SampleBuffer::new());
+
+/// Start sampling. Previously recorded samples are discarded.
+/// Returns the source of the samples, or None if neither performance counters nor the watchdog are available.
+pub fn start(period: u64) -> Option<SampleSource> {
+    if RUNNING.load(Relaxed) {
+        stop();
+    }
+
+    SAMPLES.lock().len = 0;
+    DROPPED.store(0, Relaxed);
+
+    let pmu = CpuId::new().get_performance_monitoring_info()
+        .filter(|info| info.version_id() > 0 && info.number_of_counters() > 0);
+
+    match pmu {
+        Some(info) => {
+            PMU_VERSION.store(info.version_id(), Relaxed);
+            COUNTER_WIDTH.store(info.counter_bit_width(), Relaxed);
+            PERIOD.store(period, Relaxed);
+            USE_PMU.store(true, Relaxed);
+            RUNNING.store(true, Relaxed);
+
+            apic().enable_performance_counter_nmi();
+            unsafe {
+                reload_counter();
+                Msr::new(IA32_PERFEVTSEL0).write(EVENT_UNHALTED_CORE_CYCLES | EVTSEL_USR | EVTSEL_OS | EVTSEL_INT | EVTSEL_EN);
+                if info.version_id() >= 2 {
+                    let mut global_ctrl = Msr::new(IA32_PERF_GLOBAL_CTRL);
+                    global_ctrl.write(global_ctrl.read() | 0x01);
+                }
+            }
+
+            info!("Profiler started (Source: [Performance counter], Period: [{} cycles])", period);
+            return Some(SampleSource::PerformanceCounter);
+        }
+        None => {
+            if !watchdog::is_enabled() {
+                return None;
+            }
+
+            USE_PMU.store(false, Relaxed);
+            RUNNING.store(true, Relaxed);
+
+            info!("Profiler started (Source: [Watchdog NMI], Interval: [{} ms])", watchdog::WATCHDOG_INTERVAL_MS);
+            return Some(SampleSource::Watchdog);
+        }
+    }
+}
+
+pub fn stop() {
+    RUNNING.store(false, Relaxed);
+
+    if USE_PMU.load(Relaxed) {
+        unsafe {
+            Msr::new(IA32_PERFEVTSEL0).write(0);
+            if PMU_VERSION.load(Relaxed) >= 2 {
+                let mut global_ctrl = Msr::new(IA32_PERF_GLOBAL_CTRL);
+                global_ctrl.write(global_ctrl.read() & !0x01);
+                Msr::new(IA32_PERF_GLOBAL_OVF_CTRL).write(0x01);
+            }
+        }
+    }
+
+    info!("Profiler stopped ([{}] samples, [{}] dropped)", SAMPLES.lock().len, DROPPED.load(Relaxed));
+}
+
+/// Called by the NMI handler. Returns true, if the NMI has been generated by a performance counter overflow.
+/// If the watchdog is used as sample source, a sample is recorded, but false is returned, so that the watchdog still handles the NMI.
+pub fn handle_nmi(frame: &InterruptStackFrame) -> bool {
+    if !RUNNING.load(Relaxed) {
+        return false;
+    }
+
+    if !USE_PMU.load(Relaxed) {
+        record(frame);
+        return false;
+    }
+
+    if !counter_overflowed() {
+        return false;
+    }
+
+    record(frame);
+    unsafe {
+        reload_counter();
+        if PMU_VERSION.load(Relaxed) >= 2 {
+            Msr::new(IA32_PERF_GLOBAL_OVF_CTRL).write(0x01);
+        }
+    }
+
+    // The local APIC masks the performance counter entry, when delivering the overflow interrupt
+    apic().enable_performance_counter_nmi();
+    return true;
+}
+
+/// Print a flat profile (samples per function) and a top-down profile (samples inside a function, including its callees).
+/// User addresses are resolved with the symbols of their process, as long as it is still alive.
+pub fn report(writer: &mut impl Write) {
+    let samples = {
+        let buffer = SAMPLES.lock();
+        buffer.samples[..buffer.len].to_vec()
+    };
+
+    if samples.is_empty() {
+        let _ = writeln!(writer, "No samples recorded!");
+        return;
+    }
+
+    let mut flat = BTreeMap::<String, usize>::new();
+    let mut inclusive = BTreeMap::<String, usize>::new();
+    let mut processes = BTreeMap::<usize, (usize, usize)>::new(); // Process id -> (user samples, kernel samples)
+    let mut threads = Vec::<usize>::new();
+
+    for sample in samples.iter() {
+        let function = resolve(sample.rip, sample.user, sample.process);
+        *flat.entry(function.clone()).or_insert(0) += 1;
+
+        // Count each function only once per sample, even if it appears multiple times in the callchain (e.g. recursion)
+        let mut seen = Vec::from([function]);
+        for address in sample.callchain[..sample.callchain_len].iter() {
+            let caller = resolve(*address, false, sample.process);
+            if !seen.contains(&caller) {
+                seen.push(caller);
+            }
+        }
+
+        for function in seen {
+            *inclusive.entry(function).or_insert(0) += 1;
+        }
+
+        let counts = processes.entry(sample.process).or_insert((0, 0));
+        if sample.user { counts.0 += 1; } else { counts.1 += 1; }
+
+        if !threads.contains(&sample.thread) {
+            threads.push(sample.thread);
+        }
+    }
+
+    let total = samples.len();
+    let _ = writeln!(writer, "Samples: [{}] from [{}] threads, dropped: [{}]", total, threads.len(), DROPPED.load(Relaxed));
+
+    let _ = writeln!(writer, "\nProcess       User     Kernel");
+    for (process, (user, kernel)) in processes.iter() {
+        let _ = writeln!(writer, "{:>7} {:>10} {:>10}", process, user, kernel);
+    }
+
+    let _ = writeln!(writer, "\nFlat profile:\n   Self       %  Function");
+    print_profile(writer, flat, total);
+
+    let _ = writeln!(writer, "\nTop-down profile:\n  Total       %  Function");
+    print_profile(writer, inclusive, total);
+}
+
+fn print_profile(writer: &mut impl Write, profile: BTreeMap<String, usize>, total: usize) {
+    let mut entries = profile.into_iter().collect::<Vec<(String, usize)>>();
+    entries.sort_by(|a, b| b.1.cmp(&a.1));
+
+    for (function, count) in entries.iter().take(REPORT_ENTRIES) {
+        let permille = (count * 1000) / total;
+        let _ = writeln!(writer, "{:>7} {:>5}.{}%  {}", count, permille / 10, permille % 10, function);
+    }
+}
+
+fn resolve(address: u64, user: bool, process: usize) -> String {
+    if user {
+        return match find_process(process).and_then(|process| process.resolve_symbol(address)) {
+            Some((name, _)) => format!("[{}] {}", process, name),
+            None => format!("[{}] 0x{:016x}", process, address)
+        };
+    }
+
+    return match symbols::resolve(address) {
+        Some((name, _)) => String::from(name),
+        None => format!("0x{:016x}", address)
+    };
+}
+
+fn record(frame: &InterruptStackFrame) {
+    let rip = frame.instruction_pointer.as_u64();
+    let user = frame.code_segment & 0x03 == 0x03;
+    let mut sample = Sample { rip, process: current_process_id(), thread: current_thread_id(), user, callchain: [0; CALLCHAIN_DEPTH], callchain_len: 0 };
+
+    // Skip the frames of the NMI handler and record the callers of the interrupted kernel function.
+    // User stacks are not walked, since their frame pointers can not be trusted.
+    if !user {
+        let mut interrupted_frame_found = false;
+        backtrace::walk(backtrace::frame_pointer(), |address| {
+            if !interrupted_frame_found {
+                interrupted_frame_found = address == rip;
+            } else if sample.callchain_len < CALLCHAIN_DEPTH {
+                sample.callchain[sample.callchain_len] = address;
+                sample.callchain_len += 1;
+            }
+        });
+    }
+
+    // Never spin in NMI context, since the buffer may be locked by the interrupted code
+    match SAMPLES.try_lock() {
+        Some(mut buffer) if buffer.len < MAX_SAMPLES => {
+            let index = buffer.len;
+            buffer.samples[index] = sample;
+            buffer.len += 1;
+        }
+        _ => { DROPPED.fetch_add(1, Relaxed); }
+    }
+}
+
+fn counter_overflowed() -> bool {
+    unsafe {
+        if PMU_VERSION.load(Relaxed) >= 2 {
+            return Msr::new(IA32_PERF_GLOBAL_STATUS).read() & 0x01 != 0;
+        }
+
+        // Version 1 has no global status register, but the counter starts at a negative value,
+        // so its most significant bit is cleared after an overflow.
+        let width = COUNTER_WIDTH.load(Relaxed);
+        return Msr::new(IA32_PMC0).read() & (1 << (width - 1)) == 0;
+    }
+}
+
+unsafe fn reload_counter() {
+    let width = COUNTER_WIDTH.load(Relaxed);
+    let mask = if width >= 64 { u64::MAX } else { (1 << width) - 1 };
+
+    Msr::new(IA32_PMC0).write(PERIOD.load(Relaxed).wrapping_neg() & mask);
+}
+
+impl SampleBuffer {
+    const fn new() -> Self {
+        Self { samples: [Sample { rip: 0, process: 0, thread: 0, user: false, callchain: [0; CALLCHAIN_DEPTH], callchain_len: 0 }; MAX_SAMPLES], len: 0 }
+    }
+}
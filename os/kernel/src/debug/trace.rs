@@ -0,0 +1,204 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::any::type_name;
+use core::arch::x86_64::_rdtsc;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
+use core::sync::atomic::Ordering::Relaxed;
+use x86_64::instructions::interrupts;
+use crate::process::scheduler::current_thread_id;
+use crate::timer;
+
+/// Record entry and exit of the surrounding function, while tracing is enabled.
+/// The exit event is recorded, when the current scope is left.
+#[macro_export]
+macro_rules! trace_function {
+    () => {
+        let _trace_guard = {
+            fn marker() {}
+            $crate::debug::trace::enter(module_path!(), marker)
+        };
+    };
+}
+
+/// Only the bootstrap processor is used so far, but the buffers are already separated per CPU,
+/// so that tracing does not need a global lock, once application processors are started.
+const MAX_CPUS: usize = 4;
+const EVENTS_PER_CPU: usize = 2048;
+/// Prefix of all module paths inside the kernel, which is omitted in filters (e.g. 'memory::virtual').
+const MODULE_PREFIX: &str = "kernel::";
+
+#[derive(Copy, Clone, PartialEq)]
+enum EventKind {
+    Entry, Exit
+}
+
+#[derive(Copy, Clone)]
+struct TraceEvent {
+    timestamp: u64,
+    function: &'static str,
+    thread: usize,
+    kind: EventKind,
+}
+
+/// Ring buffer for trace events. The oldest events are overwritten, once it is full.
+struct TraceBuffer {
+    events: [TraceEvent; EVENTS_PER_CPU],
+    next: usize,
+    len: usize,
+}
+
+pub struct TraceGuard {
+    function: Option<&'static str>,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Events, that could not be recorded, because the buffer was locked (e.g. by an interrupted trace point).
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+/// Reference point for converting TSC values to system time (set when tracing is enabled).
+static START_TSC: AtomicU64 = AtomicU64::new(0);
+static START_NS: AtomicU64 = AtomicU64::new(0);
+
+// Plain spinlocks are used, so that the lock debugging can be traced as well.
+static BUFFERS: [spin::Mutex<TraceBuffer>; MAX_CPUS] = [const { spin::Mutex::new(TraceBuffer::new()) }; MAX_CPUS];
+static FILTER: spin::RwLock<Vec<String>> = spin::RwLock::new(Vec::new());
+
+pub fn enable() {
+    START_NS.store(timer().read().systime_ns() as u64, Relaxed);
+    START_TSC.store(unsafe { _rdtsc() }, Relaxed);
+    ENABLED.store(true, Relaxed);
+}
+
+pub fn disable() {
+    ENABLED.store(false, Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    return ENABLED.load(Relaxed);
+}
+
+/// Only trace functions in the given modules (comma separated, e.g. 'memory,process::scheduler').
+/// An empty list enables tracing for all modules.
+pub fn set_filter(modules: &str) {
+    let modules = modules.split(',')
+        .map(|module| module.trim())
+        .filter(|module| !module.is_empty())
+        .map(|module| module.strip_prefix(MODULE_PREFIX).unwrap_or(module).to_string())
+        .collect();
+
+    interrupts::without_interrupts(|| *FILTER.write() = modules);
+}
+
+/// Discard all recorded events.
+pub fn clear() {
+    for buffer in &BUFFERS {
+        interrupts::without_interrupts(|| {
+            let mut buffer = buffer.lock();
+            buffer.next = 0;
+            buffer.len = 0;
+        });
+    }
+
+    DROPPED.store(0, Relaxed);
+}
+
+/// Called by `trace_function!()`. The function name is derived from the type name of `marker`,
+/// which is a function defined inside the traced function.
+pub fn enter<F>(module: &'static str, _marker: F) -> TraceGuard {
+    if !is_enabled() || !is_traced(module) {
+        return TraceGuard { function: None };
+    }
+
+    let name = type_name::<F>();
+    let function = name.strip_suffix("::marker").unwrap_or(name);
+    record(function, EventKind::Entry);
+
+    return TraceGuard { function: Some(function) };
+}
+
+impl Drop for TraceGuard {
+    fn drop(&mut self) {
+        if let Some(function) = self.function {
+            record(function, EventKind::Exit);
+        }
+    }
+}
+
+/// Write all recorded events in the Chrome trace event format, which can be loaded into 'chrome://tracing' or Perfetto.
+/// The output is enclosed in marker lines, so that it can be extracted from a serial log (see 'extract_trace.sh').
+pub fn export(writer: &mut impl Write) {
+    let was_enabled = is_enabled();
+    disable();
+
+    // Convert TSC to microseconds, based on the system time that passed since tracing has been enabled
+    let elapsed_ns = (timer().read().systime_ns() as u64).saturating_sub(START_NS.load(Relaxed));
+    let elapsed_cycles = unsafe { _rdtsc() }.saturating_sub(START_TSC.load(Relaxed));
+    let cycles_per_us = if elapsed_ns >= 1000 { (elapsed_cycles / (elapsed_ns / 1000)).max(1) } else { 1 };
+
+    let _ = writeln!(writer, "=== TRACE BEGIN ===");
+    let _ = writeln!(writer, "{{\"displayTimeUnit\":\"ns\",\"traceEvents\":[");
+
+    let mut first = true;
+    for (cpu, buffer) in BUFFERS.iter().enumerate() {
+        let buffer = interrupts::without_interrupts(|| buffer.lock().snapshot());
+        for event in buffer {
+            let timestamp = event.timestamp.saturating_sub(START_TSC.load(Relaxed));
+            let phase = if event.kind == EventKind::Entry { "B" } else { "E" };
+
+            let _ = writeln!(writer, "{}{{\"name\":\"{}\",\"ph\":\"{}\",\"ts\":{}.{:0>3},\"pid\":{},\"tid\":{}}}",
+                             if first { "" } else { "," }, event.function, phase,
+                             timestamp / cycles_per_us, (timestamp % cycles_per_us) * 1000 / cycles_per_us, cpu, event.thread);
+            first = false;
+        }
+    }
+
+    let _ = writeln!(writer, "],\"otherData\":{{\"dropped\":{}}}}}", DROPPED.load(Relaxed));
+    let _ = writeln!(writer, "=== TRACE END ===");
+
+    if was_enabled {
+        ENABLED.store(true, Relaxed);
+    }
+}
+
+fn is_traced(module: &str) -> bool {
+    let module = module.strip_prefix(MODULE_PREFIX).unwrap_or(module);
+    return match FILTER.try_read() {
+        Some(filter) => filter.is_empty() || filter.iter().any(|prefix| {
+            module.starts_with(prefix.as_str()) && (module.len() == prefix.len() || module[prefix.len()..].starts_with("::"))
+        }),
+        None => false // Filter is being changed right now
+    };
+}
+
+fn record(function: &'static str, kind: EventKind) {
+    let event = TraceEvent { timestamp: unsafe { _rdtsc() }, function, thread: current_thread_id(), kind };
+
+    interrupts::without_interrupts(|| {
+        match BUFFERS[cpu_index()].try_lock() {
+            Some(mut buffer) => buffer.push(event),
+            None => { DROPPED.fetch_add(1, Relaxed); }
+        }
+    });
+}
+
+fn cpu_index() -> usize {
+    return 0;
+}
+
+impl TraceBuffer {
+    const fn new() -> Self {
+        Self { events: [TraceEvent { timestamp: 0, function: "", thread: 0, kind: EventKind::Entry }; EVENTS_PER_CPU], next: 0, len: 0 }
+    }
+
+    fn push(&mut self, event: TraceEvent) {
+        self.events[self.next] = event;
+        self.next = (self.next + 1) % EVENTS_PER_CPU;
+        self.len = (self.len + 1).min(EVENTS_PER_CPU);
+    }
+
+    /// Copy all events (oldest first), so that the buffer is not locked while exporting.
+    fn snapshot(&self) -> Vec<TraceEvent> {
+        let start = (self.next + EVENTS_PER_CPU - self.len) % EVENTS_PER_CPU;
+        return (0..self.len).map(|i| self.events[(start + i) % EVENTS_PER_CPU]).collect();
+    }
+}
@@ -0,0 +1,86 @@
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicUsize};
+use core::sync::atomic::Ordering::Relaxed;
+use log::info;
+use x86_64::structures::idt::InterruptStackFrame;
+use crate::debug::{backtrace, SerialWriter};
+use crate::{allocator, apic, logger, scheduler, symbols, timer, tss};
+
+/// The PIT is not needed after the APIC timer has been calibrated, so it is used to generate periodic NMIs.
+/// Its interrupt is routed through the IO APIC in NMI delivery mode and thus also fires, while interrupts are disabled.
+pub const WATCHDOG_INTERVAL_MS: usize = 25;
+
+/// A core is considered locked up, if its APIC timer has not ticked for this long.
+const LOCKUP_THRESHOLD_MS: usize = 2000;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static LAST_TICKS: AtomicUsize = AtomicUsize::new(0);
+static STALLED_MS: AtomicUsize = AtomicUsize::new(0);
+
+/// Start the NMI watchdog. Must be called after the APIC timer has been started.
+pub fn init() {
+    LAST_TICKS.store(apic().timer_ticks(), Relaxed);
+    timer().write().interrupt_rate(WATCHDOG_INTERVAL_MS);
+    apic().route_irq_as_nmi(0); // The PIT is connected to legacy IRQ 0
+    ENABLED.store(true, Relaxed);
+
+    info!("NMI watchdog enabled (Lockup threshold: [{} ms])", LOCKUP_THRESHOLD_MS);
+}
+
+pub fn is_enabled() -> bool {
+    return ENABLED.load(Relaxed);
+}
+
+/// Called by the NMI handler. Returns false, if the NMI has not been generated by the watchdog.
+pub fn check(frame: &InterruptStackFrame) -> bool {
+    if !ENABLED.load(Relaxed) {
+        return false;
+    }
+
+    let ticks = apic().timer_ticks();
+    if LAST_TICKS.swap(ticks, Relaxed) != ticks {
+        STALLED_MS.store(0, Relaxed);
+        return true;
+    }
+
+    let stalled_ms = STALLED_MS.fetch_add(WATCHDOG_INTERVAL_MS, Relaxed) + WATCHDOG_INTERVAL_MS;
+    if stalled_ms >= LOCKUP_THRESHOLD_MS {
+        ENABLED.store(false, Relaxed);
+        report_lockup(frame, stalled_ms);
+        panic!("Watchdog: Hard lockup detected!");
+    }
+
+    return true;
+}
+
+fn report_lockup(frame: &InterruptStackFrame, stalled_ms: usize) {
+    // Write directly to the serial port, since the logger and the terminal may be locked by the stuck code
+    let mut writer = SerialWriter;
+
+    let _ = writeln!(writer, "\nWatchdog: Hard lockup detected on local APIC [{}] (No timer interrupt for [{} ms])!", apic().id(), stalled_ms);
+    let _ = writeln!(writer, "RIP: [0x{:016x}], RSP: [0x{:016x}], RFLAGS: [0x{:016x}]", frame.instruction_pointer.as_u64(), frame.stack_pointer.as_u64(), frame.cpu_flags);
+    if let Some((name, offset)) = symbols::resolve(frame.instruction_pointer.as_u64()) {
+        let _ = writeln!(writer, "Stuck in: <{}+0x{:x}>", name, offset);
+    }
+
+    let _ = writeln!(writer, "Backtrace:");
+    backtrace::print(&mut writer, backtrace::frame_pointer());
+
+    let _ = writeln!(writer, "Held locks:");
+    let locks = [
+        ("Logger", logger().is_locked()),
+        ("Kernel heap", allocator().is_locked()),
+        ("Scheduler", scheduler().is_locked()),
+        ("Timer", timer().writer_count() > 0),
+        ("TSS", tss().is_locked()),
+    ];
+
+    for (name, locked) in locks {
+        if locked {
+            let _ = writeln!(writer, "  {}", name);
+        }
+    }
+
+    #[cfg(feature = "lockdep")]
+    crate::sync::lockdep::print_held_locks(&mut writer);
+}
@@ -0,0 +1,393 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::sync::Arc;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicUsize};
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use log::info;
+use syscall::error::Errno;
+use syscall::file::{FileType, PollEvents};
+use syscall::ioctl::{AudioFormat, AudioVolume, IoctlRequest};
+use x86_64::instructions::port::Port;
+use crate::device::pci::driver::{PciDriver, PciId};
+use crate::device::pci::{CommandFlag, PciDevice};
+use crate::fs::devfs::Device;
+use crate::fs::{devfs, Result};
+use crate::interrupt::deferred;
+use crate::interrupt::interrupt_handler::InterruptHandler;
+use crate::memory::{physical, PAGE_SIZE};
+use crate::process::wait_queue::{WaitQueue, Waiter};
+use crate::sync::{Mutex, MutexGuard};
+use crate::{apic, interrupt_dispatcher, scheduler, timer};
+
+/// Controllers of the Intel ICH family (82801AA is emulated by QEMU's '-device AC97').
+const DEVICE_IDS: [PciId; 8] = [
+    PciId::device(0x8086, 0x2415), PciId::device(0x8086, 0x2425), PciId::device(0x8086, 0x2445), PciId::device(0x8086, 0x2485),
+    PciId::device(0x8086, 0x24c5), PciId::device(0x8086, 0x24d5), PciId::device(0x8086, 0x266e), PciId::device(0x8086, 0x27de)
+];
+
+/// The buffer descriptor list always has 32 entries, which are used as a ring.
+const BUFFER_COUNT: usize = 32;
+/// Each buffer holds one page of samples (1024 stereo frames, about 21 ms at 48 kHz).
+const BUFFER_SIZE: usize = PAGE_SIZE;
+/// Only 16-bit stereo samples are supported.
+const FRAME_SIZE: usize = 4;
+const DEFAULT_SAMPLE_RATE: u32 = 48000;
+const MIN_SAMPLE_RATE: u32 = 8000;
+const RESET_TIMEOUT_MS: usize = 1000;
+
+// Registers of the native audio mixer (first I/O BAR)
+const MIXER_RESET: u16 = 0x00;
+const MASTER_VOLUME: u16 = 0x02;
+const PCM_OUT_VOLUME: u16 = 0x18;
+const EXTENDED_AUDIO_ID: u16 = 0x28;
+const EXTENDED_AUDIO_CONTROL: u16 = 0x2a;
+const PCM_FRONT_DAC_RATE: u16 = 0x2c;
+
+const VOLUME_MUTE: u16 = 1 << 15;
+/// The master volume is an attenuation in steps of 1.5 dB (only 5 bits are supported by all codecs).
+const VOLUME_MAX_ATTENUATION: u16 = 0x1f;
+/// Gain of 0 dB for the PCM output (values below amplify the signal).
+const PCM_OUT_DEFAULT_GAIN: u16 = 0x0808;
+const EXTENDED_AUDIO_VARIABLE_RATE: u16 = 1 << 0;
+
+// Registers of the native audio bus master (second I/O BAR), relative to the PCM output box
+const PCM_OUT: u16 = 0x10;
+const BUFFER_LIST_ADDRESS: u16 = 0x00;
+const CURRENT_INDEX: u16 = 0x04;
+const LAST_VALID_INDEX: u16 = 0x05;
+const STATUS: u16 = 0x06;
+const CONTROL: u16 = 0x0b;
+const GLOBAL_CONTROL: u16 = 0x2c;
+const GLOBAL_STATUS: u16 = 0x30;
+
+const STATUS_HALTED: u16 = 1 << 0;
+const STATUS_LAST_BUFFER: u16 = 1 << 2;
+const STATUS_COMPLETION: u16 = 1 << 3;
+const STATUS_FIFO_ERROR: u16 = 1 << 4;
+
+const CONTROL_RUN: u8 = 1 << 0;
+const CONTROL_RESET: u8 = 1 << 1;
+const CONTROL_LAST_BUFFER_INTERRUPT: u8 = 1 << 2;
+const CONTROL_FIFO_ERROR_INTERRUPT: u8 = 1 << 3;
+const CONTROL_COMPLETION_INTERRUPT: u8 = 1 << 4;
+
+/// Writing 1 releases the codec from cold reset.
+const GLOBAL_CONTROL_COLD_RESET: u32 = 1 << 1;
+const GLOBAL_STATUS_PRIMARY_READY: u32 = 1 << 8;
+
+/// Raise an interrupt, when the buffer has been played.
+const DESCRIPTOR_INTERRUPT: u16 = 1 << 15;
+
+/// Entry of the buffer descriptor list. The length is given in samples (not frames).
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct BufferDescriptor {
+    address: u32,
+    samples: u16,
+    flags: u16,
+}
+
+/// AC'97 audio controller, which plays 16-bit stereo samples from a ring of DMA buffers.
+/// Writers fill the next free buffer and append it to the list of valid buffers, while the controller is playing the previous ones.
+struct Ac97 {
+    mixer_base: u16,
+    bus_master_base: u16,
+    variable_rate: bool,
+    /// Protects the buffers and the format. It is never held while blocking (see `Ac97::wait_until()`).
+    stream: Mutex<Stream>,
+    /// Notified by the kernel worker thread, when the controller has finished a buffer.
+    buffer_done: WaitQueue,
+    notification_pending: AtomicBool,
+}
+
+struct Stream {
+    /// Physical (identity mapped) address of the buffer descriptor list, followed by the buffers.
+    memory: u64,
+    /// Index of the buffer, which is filled next.
+    next: usize,
+    sample_rate: u32,
+    volume: AudioVolume,
+}
+
+struct Ac97InterruptHandler {
+    controller: Arc<Ac97>,
+}
+
+/// '/dev/audio0', '/dev/audio1', ...: Samples written to the device are played in the `AudioFormat` set via `IoctlRequest::SetAudioFormat`.
+/// Writing blocks, while all buffers are queued. `fsync()` waits, until all written samples have been played.
+struct AudioDevice {
+    controller: Arc<Ac97>,
+}
+
+/// Driver for AC'97 audio controllers, which registers them as '/dev/audio0', '/dev/audio1', ...
+pub static DRIVER: PciDriver = PciDriver { name: "ac97", ids: &DEVICE_IDS, probe };
+
+/// Number of controllers, which have been probed (used for naming their devices).
+static CONTROLLER_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+fn probe(device: &Arc<PciDevice>) -> Result<()> {
+    device.set_command_flag(CommandFlag::IoSpace, true);
+    device.set_command_flag(CommandFlag::BusMaster, true);
+
+    let controller = Arc::new(Ac97::new(device.bar(0) as u16, device.bar(1) as u16)?);
+    let irq = device.interrupt_line();
+    interrupt_dispatcher().assign_irq(irq, Box::new(Ac97InterruptHandler { controller: Arc::clone(&controller) }));
+    apic().allow_irq(irq);
+
+    info!("AC'97 audio controller [{}]: IRQ: [{}], Variable sample rate: [{}]", device.address(), irq, controller.variable_rate);
+
+    let name = format!("audio{}", CONTROLLER_COUNT.fetch_add(1, Relaxed));
+    return devfs::register(&name, FileType::CharDevice, Arc::new(AudioDevice { controller }));
+}
+
+impl Ac97 {
+    fn new(mixer_base: u16, bus_master_base: u16) -> Result<Self> {
+        // The controller can only access the lower 4 GiB
+        let frames = physical::alloc(BUFFER_COUNT + 1);
+        if frames.end.start_address().as_u64() > u32::MAX as u64 {
+            unsafe { physical::free(frames); }
+            return Err(Errno::NotSupported);
+        }
+
+        let memory = frames.start.start_address().as_u64();
+        unsafe { ptr::write_bytes(memory as *mut u8, 0, PAGE_SIZE); }
+
+        let mut controller = Self {
+            mixer_base, bus_master_base, variable_rate: false,
+            stream: Mutex::new(Stream { memory, next: 0, sample_rate: DEFAULT_SAMPLE_RATE, volume: AudioVolume { left: 100, right: 100 } }),
+            buffer_done: WaitQueue::new(),
+            notification_pending: AtomicBool::new(false)
+        };
+
+        controller.reset()?;
+        return Ok(controller);
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        // Release the codec from cold reset and wait until it is ready
+        self.write_bus_master_u32(GLOBAL_CONTROL, GLOBAL_CONTROL_COLD_RESET);
+        let start = timer().read().systime_ms();
+        while self.read_bus_master_u32(GLOBAL_STATUS) & GLOBAL_STATUS_PRIMARY_READY == 0 {
+            if timer().read().systime_ms() - start > RESET_TIMEOUT_MS {
+                return Err(Errno::IoError);
+            }
+
+            core::hint::spin_loop();
+        }
+
+        // Reset the mixer to its defaults and the PCM output box, which clears all of its registers
+        self.write_mixer(MIXER_RESET, 0);
+        self.write_bus_master_u8(PCM_OUT + CONTROL, CONTROL_RESET);
+        let start = timer().read().systime_ms();
+        while self.read_bus_master_u8(PCM_OUT + CONTROL) & CONTROL_RESET != 0 {
+            if timer().read().systime_ms() - start > RESET_TIMEOUT_MS {
+                return Err(Errno::IoError);
+            }
+
+            core::hint::spin_loop();
+        }
+
+        if self.read_mixer(EXTENDED_AUDIO_ID) & EXTENDED_AUDIO_VARIABLE_RATE != 0 {
+            self.write_mixer(EXTENDED_AUDIO_CONTROL, self.read_mixer(EXTENDED_AUDIO_CONTROL) | EXTENDED_AUDIO_VARIABLE_RATE);
+            self.variable_rate = true;
+        }
+
+        let stream = self.stream.lock();
+        self.write_mixer(PCM_OUT_VOLUME, PCM_OUT_DEFAULT_GAIN);
+        self.apply_volume(stream.volume);
+        self.write_bus_master_u32(PCM_OUT + BUFFER_LIST_ADDRESS, stream.memory as u32);
+
+        return Ok(());
+    }
+
+    fn format(&self) -> AudioFormat {
+        return AudioFormat { sample_rate: self.stream.lock().sample_rate, channels: 2, bits_per_sample: 16 };
+    }
+
+    fn set_format(&self, format: AudioFormat) -> Result<()> {
+        if format.channels != 2 || format.bits_per_sample != 16 || format.sample_rate < MIN_SAMPLE_RATE || format.sample_rate > DEFAULT_SAMPLE_RATE {
+            return Err(Errno::InvalidArgument);
+        }
+        if !self.variable_rate && format.sample_rate != DEFAULT_SAMPLE_RATE {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let mut stream = self.stream.lock();
+        if self.variable_rate {
+            self.write_mixer(PCM_FRONT_DAC_RATE, format.sample_rate as u16);
+            // The codec may round to the nearest supported rate
+            stream.sample_rate = self.read_mixer(PCM_FRONT_DAC_RATE) as u32;
+        }
+
+        return Ok(());
+    }
+
+    fn set_volume(&self, volume: AudioVolume) -> Result<()> {
+        if volume.left > 100 || volume.right > 100 {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let mut stream = self.stream.lock();
+        stream.volume = volume;
+        self.apply_volume(volume);
+
+        return Ok(());
+    }
+
+    fn apply_volume(&self, volume: AudioVolume) {
+        let attenuation = |percent: u8| (100 - percent as u16) * VOLUME_MAX_ATTENUATION / 100;
+        let mut value = attenuation(volume.left) << 8 | attenuation(volume.right);
+        if volume.left == 0 && volume.right == 0 {
+            value |= VOLUME_MUTE;
+        }
+
+        self.write_mixer(MASTER_VOLUME, value);
+    }
+
+    /// Copy `samples` into the next free buffer and append it to the buffers, which are played.
+    /// Blocks, while all buffers are queued.
+    fn queue(&self, samples: &[u8]) {
+        let mut stream = self.wait_until(|stream| self.buffer_free(stream.next));
+
+        let buffer = stream.memory + ((stream.next + 1) * BUFFER_SIZE) as u64;
+        unsafe {
+            ptr::copy_nonoverlapping(samples.as_ptr(), buffer as *mut u8, samples.len());
+            let descriptor = (stream.memory as *mut BufferDescriptor).add(stream.next);
+            descriptor.write_volatile(BufferDescriptor { address: buffer as u32, samples: (samples.len() / 2) as u16, flags: DESCRIPTOR_INTERRUPT });
+        }
+
+        // Playback continues automatically, if the controller has stopped at the previous last valid buffer
+        self.write_bus_master_u8(PCM_OUT + LAST_VALID_INDEX, stream.next as u8);
+        let control = self.read_bus_master_u8(PCM_OUT + CONTROL);
+        if control & CONTROL_RUN == 0 {
+            self.write_bus_master_u8(PCM_OUT + CONTROL, CONTROL_RUN | CONTROL_COMPLETION_INTERRUPT | CONTROL_LAST_BUFFER_INTERRUPT | CONTROL_FIFO_ERROR_INTERRUPT);
+        }
+
+        stream.next = (stream.next + 1) % BUFFER_COUNT;
+    }
+
+    /// The buffer at `index` may be filled, unless the controller is currently playing it.
+    fn buffer_free(&self, index: usize) -> bool {
+        return !self.playing() || self.read_bus_master_u8(PCM_OUT + CURRENT_INDEX) as usize != index;
+    }
+
+    fn playing(&self) -> bool {
+        let running = self.read_bus_master_u8(PCM_OUT + CONTROL) & CONTROL_RUN != 0;
+        return running && self.read_bus_master_u16(PCM_OUT + STATUS) & STATUS_HALTED == 0;
+    }
+
+    /// Block, until `condition` is true and return the locked stream. The condition is checked again after each buffer, that has been played.
+    /// The stream is unlocked while blocking, so that the format and volume can still be accessed by other threads.
+    fn wait_until(&self, condition: impl Fn(&Stream) -> bool) -> MutexGuard<'_, Stream> {
+        loop {
+            // Registering before checking the condition ensures, that no notification is lost in between
+            let waiter = Waiter::new();
+            self.buffer_done.register(&waiter);
+            let stream = self.stream.lock();
+            if condition(&stream) {
+                return stream;
+            }
+
+            drop(stream);
+            scheduler().block_on(&waiter, None);
+        }
+    }
+
+    fn read_mixer(&self, register: u16) -> u16 {
+        return unsafe { Port::<u16>::new(self.mixer_base + register).read() };
+    }
+
+    fn write_mixer(&self, register: u16, value: u16) {
+        unsafe { Port::<u16>::new(self.mixer_base + register).write(value); }
+    }
+
+    fn read_bus_master_u8(&self, register: u16) -> u8 {
+        return unsafe { Port::<u8>::new(self.bus_master_base + register).read() };
+    }
+
+    fn write_bus_master_u8(&self, register: u16, value: u8) {
+        unsafe { Port::<u8>::new(self.bus_master_base + register).write(value); }
+    }
+
+    fn read_bus_master_u16(&self, register: u16) -> u16 {
+        return unsafe { Port::<u16>::new(self.bus_master_base + register).read() };
+    }
+
+    fn write_bus_master_u16(&self, register: u16, value: u16) {
+        unsafe { Port::<u16>::new(self.bus_master_base + register).write(value); }
+    }
+
+    fn read_bus_master_u32(&self, register: u16) -> u32 {
+        return unsafe { Port::<u32>::new(self.bus_master_base + register).read() };
+    }
+
+    fn write_bus_master_u32(&self, register: u16, value: u32) {
+        unsafe { Port::<u32>::new(self.bus_master_base + register).write(value); }
+    }
+}
+
+impl InterruptHandler for Ac97InterruptHandler {
+    fn trigger(&mut self) {
+        // The interrupt line may be shared with other devices
+        let status = self.controller.read_bus_master_u16(PCM_OUT + STATUS) & (STATUS_LAST_BUFFER | STATUS_COMPLETION | STATUS_FIFO_ERROR);
+        if status == 0 {
+            return;
+        }
+
+        // Status bits are cleared by writing 1. Waiting writers are woken up by the kernel worker thread.
+        self.controller.write_bus_master_u16(PCM_OUT + STATUS, status);
+        if !self.controller.notification_pending.swap(true, Acquire) {
+            let controller = Arc::clone(&self.controller);
+            deferred::schedule_work(Box::new(move || {
+                controller.notification_pending.store(false, Release);
+                controller.buffer_done.notify_all();
+            }));
+        }
+    }
+}
+
+impl Device for AudioDevice {
+    fn write(&self, _offset: usize, buffer: &[u8]) -> Result<usize> {
+        // Only whole frames are played
+        let length = buffer.len() - buffer.len() % FRAME_SIZE;
+        // The stream is only locked per buffer, so concurrent writes may be interleaved
+        for samples in buffer[..length].chunks(BUFFER_SIZE) {
+            self.controller.queue(samples);
+        }
+
+        return Ok(length);
+    }
+
+    fn ioctl(&self, request: usize, arg: usize) -> Result<usize> {
+        return match IoctlRequest::try_from(request) {
+            Ok(IoctlRequest::GetAudioFormat) => {
+                unsafe { (arg as *mut AudioFormat).write(self.controller.format()); }
+                Ok(0)
+            }
+            Ok(IoctlRequest::SetAudioFormat) => self.controller.set_format(unsafe { (arg as *const AudioFormat).read() }).map(|_| 0),
+            Ok(IoctlRequest::GetAudioVolume) => {
+                unsafe { (arg as *mut AudioVolume).write(self.controller.stream.lock().volume); }
+                Ok(0)
+            }
+            Ok(IoctlRequest::SetAudioVolume) => self.controller.set_volume(unsafe { (arg as *const AudioVolume).read() }).map(|_| 0),
+            _ => Err(Errno::NotATerminal)
+        };
+    }
+
+    fn sync(&self) -> Result<()> {
+        drop(self.controller.wait_until(|_| !self.controller.playing()));
+
+        return Ok(());
+    }
+
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        // Registered before checking, like in `Ac97::wait_until()`
+        if let Some(waiter) = waiter {
+            self.controller.buffer_done.register(waiter);
+        }
+
+        let next = self.controller.stream.lock().next;
+        return if self.controller.buffer_free(next) { events & PollEvents::WRITABLE } else { PollEvents::empty() };
+    }
+}
@@ -0,0 +1,457 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicUsize};
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use log::{info, warn};
+use syscall::error::Errno;
+use x86_64::structures::paging::page::PageRange;
+use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::VirtAddr;
+use crate::block;
+use crate::block::{BlockDevice, Result};
+use crate::device::ata::IdentifyData;
+use crate::device::pci::driver::{PciDriver, PciId};
+use crate::device::pci::{msi, CapabilityId, CommandFlag, PciDevice};
+use crate::interrupt::interrupt_handler::InterruptHandler;
+use crate::memory::{physical, MemorySpace, PAGE_SIZE};
+use crate::process::process::current_process;
+use crate::process::wait_queue::WaitQueue;
+use crate::{apic, interrupt_dispatcher, timer};
+
+const SECTOR_SIZE: usize = 512;
+/// Data is transferred via a bounce buffer of this size per port (allowing up to 256 sectors per command).
+const BUFFER_PAGES: usize = 32;
+const MAX_PORTS: usize = 32;
+/// Commands, that do not complete in time, are aborted and the port is restarted.
+const COMMAND_TIMEOUT_MS: usize = 5000;
+const PORT_TIMEOUT_MS: usize = 500;
+
+// Offsets of the HBA registers (ABAR, located in memory space via BAR 5)
+const HBA_CAPABILITIES: u64 = 0x00;
+const HBA_GLOBAL_CONTROL: u64 = 0x04;
+const HBA_INTERRUPT_STATUS: u64 = 0x08;
+const HBA_PORTS_IMPLEMENTED: u64 = 0x0c;
+const HBA_PORT_REGISTERS: u64 = 0x100;
+const HBA_PORT_REGISTERS_SIZE: u64 = 0x80;
+
+const GLOBAL_CONTROL_INTERRUPT_ENABLE: u32 = 1 << 1;
+const GLOBAL_CONTROL_AHCI_ENABLE: u32 = 1 << 31;
+const CAPABILITIES_64_BIT: u32 = 1 << 31;
+
+// Offsets of the port registers
+const PORT_COMMAND_LIST: u64 = 0x00;
+const PORT_COMMAND_LIST_HIGH: u64 = 0x04;
+const PORT_FIS: u64 = 0x08;
+const PORT_FIS_HIGH: u64 = 0x0c;
+const PORT_INTERRUPT_STATUS: u64 = 0x10;
+const PORT_INTERRUPT_ENABLE: u64 = 0x14;
+const PORT_COMMAND: u64 = 0x18;
+const PORT_TASK_FILE: u64 = 0x20;
+const PORT_SIGNATURE: u64 = 0x24;
+const PORT_SATA_STATUS: u64 = 0x28;
+const PORT_SATA_ERROR: u64 = 0x30;
+const PORT_COMMAND_ISSUE: u64 = 0x38;
+
+const PORT_COMMAND_START: u32 = 1 << 0;
+const PORT_COMMAND_FIS_RECEIVE: u32 = 1 << 4;
+const PORT_COMMAND_FIS_RUNNING: u32 = 1 << 14;
+const PORT_COMMAND_LIST_RUNNING: u32 = 1 << 15;
+
+const PORT_INTERRUPT_DEVICE_TO_HOST: u32 = 1 << 0;
+const PORT_INTERRUPT_PIO_SETUP: u32 = 1 << 1;
+const PORT_INTERRUPT_TASK_FILE_ERROR: u32 = 1 << 30;
+
+const TASK_FILE_ERROR: u32 = 1 << 0;
+const TASK_FILE_DATA_REQUEST: u32 = 1 << 3;
+const TASK_FILE_BUSY: u32 = 1 << 7;
+
+const SATA_STATUS_PRESENT: u32 = 0x03;
+const SATA_STATUS_ACTIVE: u32 = 0x01;
+/// Signature of plain SATA drives (ATAPI drives, port multipliers, etc. are not supported).
+const SIGNATURE_ATA: u32 = 0x00000101;
+
+// Layout of the page with the command structures of a port (only command slot 0 is used, since commands are serialized)
+const COMMAND_LIST_OFFSET: usize = 0x000;
+const FIS_OFFSET: usize = 0x400;
+const COMMAND_TABLE_OFFSET: usize = 0x500;
+const PRDT_OFFSET: usize = COMMAND_TABLE_OFFSET + 0x80;
+
+const FIS_TYPE_HOST_TO_DEVICE: u8 = 0x27;
+const FIS_COMMAND: u8 = 1 << 7;
+const FIS_LBA_MODE: u8 = 1 << 6;
+/// Length of a host-to-device register FIS in double words.
+const FIS_LENGTH: u32 = 5;
+const COMMAND_HEADER_WRITE: u32 = 1 << 6;
+const PRD_INTERRUPT: u32 = 1 << 31;
+
+#[derive(Copy, Clone, PartialEq)]
+#[repr(u8)]
+enum Command {
+    ReadDmaExt = 0x25,
+    WriteDmaExt = 0x35,
+    FlushCacheExt = 0xea,
+    Identify = 0xec,
+}
+
+/// Memory mapped register block of the HBA or one of its ports.
+#[derive(Copy, Clone)]
+struct Registers {
+    base: u64,
+}
+
+/// A port of an AHCI controller with a connected drive. Commands are executed one after another in slot 0 (no NCQ).
+struct Port {
+    registers: Registers,
+    /// Page with command list, received FIS and command table.
+    memory: u64,
+    /// Bounce buffer for transfers (physical memory is identity mapped, so its address can be given to the HBA directly).
+    buffer: u64,
+    /// Set while a thread executes commands on this port (see `lock()`).
+    busy: AtomicBool,
+    idle: WaitQueue,
+    /// Set by the interrupt handler, when the port has signaled completion or an error.
+    interrupt: AtomicBool,
+    interrupt_received: WaitQueue,
+}
+
+/// Serializes commands on a port. Commands block until they complete, so waiting threads block as well instead of spinning.
+struct PortGuard<'a> {
+    port: &'a Port,
+}
+
+/// SATA drive, connected to an AHCI controller (e.g. QEMU's 'q35' machine or '-device ahci').
+pub struct AhciDrive {
+    port: Arc<Port>,
+    sector_count: u64,
+    model: String,
+}
+
+struct AhciInterruptHandler {
+    registers: Registers,
+    ports: Vec<(usize, Arc<Port>)>,
+}
+
+/// Detect drives on all AHCI controllers and register them as block devices ('sata0', 'sata1', ...).
+/// Driver for AHCI controllers, which registers the connected SATA drives as block devices ('sata0', 'sata1', ...).
+pub static DRIVER: PciDriver = PciDriver { name: "ahci", ids: &[PciId::class(0x01, 0x06)], probe };
+
+/// Number of drives found on all controllers (used for naming the drives).
+static DRIVE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+fn probe(controller: &Arc<PciDevice>) -> Result<()> {
+    let registers = Registers { base: controller.bar(5) };
+    let size = HBA_PORT_REGISTERS + MAX_PORTS as u64 * HBA_PORT_REGISTERS_SIZE;
+    let start_page = Page::containing_address(VirtAddr::new(registers.base));
+    let end_page = Page::containing_address(VirtAddr::new(registers.base + size - 1)) + 1;
+    current_process().address_space().map(PageRange { start: start_page, end: end_page }, MemorySpace::Kernel, PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE);
+
+    controller.set_command_flag(CommandFlag::MemorySpace, true);
+    controller.set_command_flag(CommandFlag::BusMaster, true);
+    registers.write(HBA_GLOBAL_CONTROL, registers.read(HBA_GLOBAL_CONTROL) | GLOBAL_CONTROL_AHCI_ENABLE);
+
+    let supports_64_bit = registers.read(HBA_CAPABILITIES) & CAPABILITIES_64_BIT != 0;
+    let implemented = registers.read(HBA_PORTS_IMPLEMENTED);
+    let mut ports = Vec::new();
+
+    for number in 0..MAX_PORTS {
+        if implemented & (1 << number) == 0 {
+            continue;
+        }
+
+        let port_registers = Registers { base: registers.base + HBA_PORT_REGISTERS + number as u64 * HBA_PORT_REGISTERS_SIZE };
+        let status = port_registers.read(PORT_SATA_STATUS);
+        if status & 0x0f != SATA_STATUS_PRESENT || (status >> 8) & 0x0f != SATA_STATUS_ACTIVE {
+            continue; // No drive connected or link not active
+        }
+        if port_registers.read(PORT_SIGNATURE) != SIGNATURE_ATA {
+            continue;
+        }
+
+        match Port::new(port_registers, supports_64_bit) {
+            Ok(port) => ports.push((number, Arc::new(port))),
+            Err(err) => warn!("Failed to initialize AHCI port [{}] (Error: {:?})", number, err)
+        }
+    }
+
+    // All ports share a single interrupt, so the handler checks, which ports have signaled it
+    let handler = Box::new(AhciInterruptHandler { registers, ports: ports.clone() });
+    if controller.find_capability(CapabilityId::Msi).is_some() {
+        msi::request_msi(controller, msi::default_target(), handler);
+    } else {
+        let irq = controller.interrupt_line();
+        interrupt_dispatcher().assign_irq(irq, handler);
+        apic().allow_irq(irq);
+    }
+    registers.write(HBA_GLOBAL_CONTROL, registers.read(HBA_GLOBAL_CONTROL) | GLOBAL_CONTROL_INTERRUPT_ENABLE);
+
+    for (number, port) in ports {
+        let drive = match AhciDrive::identify(port) {
+            Ok(drive) => drive,
+            Err(err) => {
+                warn!("Failed to identify drive at AHCI port [{}] (Error: {:?})", number, err);
+                continue;
+            }
+        };
+
+        let name = format!("sata{}", DRIVE_COUNT.fetch_add(1, Relaxed));
+        info!("Found SATA drive [{}] at port [{}]: Model: [{}], Sectors: [{}]", name, number, drive.model, drive.sector_count);
+
+        if let Err(err) = block::register(&name, Arc::new(drive)) {
+            warn!("Failed to register SATA drive [{}] (Error: {:?})", name, err);
+        }
+    }
+
+    return Ok(());
+}
+
+impl Registers {
+    fn read(&self, offset: u64) -> u32 {
+        return unsafe { ((self.base + offset) as *const u32).read_volatile() };
+    }
+
+    fn write(&self, offset: u64, value: u32) {
+        unsafe { ((self.base + offset) as *mut u32).write_volatile(value); }
+    }
+
+    /// Wait until all bits in `mask` are cleared.
+    fn wait_cleared(&self, offset: u64, mask: u32, timeout_ms: usize) -> Result<()> {
+        let start = timer().read().systime_ms();
+        while self.read(offset) & mask != 0 {
+            if timer().read().systime_ms() - start > timeout_ms {
+                return Err(Errno::IoError);
+            }
+
+            core::hint::spin_loop();
+        }
+
+        return Ok(());
+    }
+}
+
+impl Port {
+    fn new(registers: Registers, supports_64_bit: bool) -> Result<Self> {
+        let memory_frames = physical::alloc(1);
+        let buffer_frames = physical::alloc(BUFFER_PAGES);
+        if !supports_64_bit && buffer_frames.end.start_address().as_u64().max(memory_frames.end.start_address().as_u64()) > u32::MAX as u64 {
+            unsafe {
+                physical::free(memory_frames);
+                physical::free(buffer_frames);
+            }
+
+            return Err(Errno::NotSupported); // The HBA can only access the lower 4 GiB
+        }
+
+        let memory = memory_frames.start.start_address().as_u64();
+        let buffer = buffer_frames.start.start_address().as_u64();
+
+        unsafe { ptr::write_bytes(memory as *mut u8, 0, PAGE_SIZE); }
+        let port = Self { registers, memory, buffer, busy: AtomicBool::new(false), idle: WaitQueue::new(), interrupt: AtomicBool::new(false), interrupt_received: WaitQueue::new() };
+
+        port.stop()?;
+        registers.write(PORT_COMMAND_LIST, (memory + COMMAND_LIST_OFFSET as u64) as u32);
+        registers.write(PORT_COMMAND_LIST_HIGH, ((memory + COMMAND_LIST_OFFSET as u64) >> 32) as u32);
+        registers.write(PORT_FIS, (memory + FIS_OFFSET as u64) as u32);
+        registers.write(PORT_FIS_HIGH, ((memory + FIS_OFFSET as u64) >> 32) as u32);
+        registers.write(PORT_INTERRUPT_ENABLE, PORT_INTERRUPT_DEVICE_TO_HOST | PORT_INTERRUPT_PIO_SETUP | PORT_INTERRUPT_TASK_FILE_ERROR);
+        port.start()?;
+
+        return Ok(port);
+    }
+
+    fn lock(&self) -> PortGuard<'_> {
+        while self.busy.swap(true, Acquire) {
+            self.idle.wait_until(|| !self.busy.load(Relaxed), None);
+        }
+
+        return PortGuard { port: self };
+    }
+
+    /// Stop processing the command list and receiving FISes (required before changing the command structures).
+    fn stop(&self) -> Result<()> {
+        let command = self.registers.read(PORT_COMMAND);
+        self.registers.write(PORT_COMMAND, command & !PORT_COMMAND_START);
+        self.registers.wait_cleared(PORT_COMMAND, PORT_COMMAND_LIST_RUNNING, PORT_TIMEOUT_MS)?;
+
+        let command = self.registers.read(PORT_COMMAND);
+        self.registers.write(PORT_COMMAND, command & !PORT_COMMAND_FIS_RECEIVE);
+        return self.registers.wait_cleared(PORT_COMMAND, PORT_COMMAND_FIS_RUNNING, PORT_TIMEOUT_MS);
+    }
+
+    fn start(&self) -> Result<()> {
+        // Errors and interrupts from a previous command are cleared by writing ones
+        self.registers.write(PORT_SATA_ERROR, 0xffffffff);
+        self.registers.write(PORT_INTERRUPT_STATUS, 0xffffffff);
+        self.registers.wait_cleared(PORT_TASK_FILE, TASK_FILE_BUSY | TASK_FILE_DATA_REQUEST, PORT_TIMEOUT_MS)?;
+
+        let command = self.registers.read(PORT_COMMAND);
+        self.registers.write(PORT_COMMAND, command | PORT_COMMAND_FIS_RECEIVE);
+        self.registers.write(PORT_COMMAND, command | PORT_COMMAND_FIS_RECEIVE | PORT_COMMAND_START);
+
+        return Ok(());
+    }
+
+    /// Execute `command` in slot 0, transferring `sectors` sectors between the drive and the bounce buffer, and wait for its completion.
+    fn execute(&self, command: Command, lba: u64, sectors: usize) -> Result<()> {
+        let memory = self.memory as *mut u8;
+        let table = self.memory + COMMAND_TABLE_OFFSET as u64;
+
+        unsafe {
+            // Command header: FIS length, direction and number of PRD entries
+            let header = memory.add(COMMAND_LIST_OFFSET) as *mut u32;
+            let write = if command == Command::WriteDmaExt { COMMAND_HEADER_WRITE } else { 0 };
+            let prd_count = if sectors > 0 { 1 } else { 0 };
+            header.write_volatile(FIS_LENGTH | write | prd_count << 16);
+            header.add(1).write_volatile(0);
+            header.add(2).write_volatile(table as u32);
+            header.add(3).write_volatile((table >> 32) as u32);
+
+            // Host-to-device register FIS, carrying the command like the task file registers of an ATA drive
+            let fis = memory.add(COMMAND_TABLE_OFFSET);
+            ptr::write_bytes(fis, 0, 0x40);
+            let lba_bytes = lba.to_le_bytes();
+            let count_bytes = (sectors as u16).to_le_bytes();
+            let values = [FIS_TYPE_HOST_TO_DEVICE, FIS_COMMAND, command as u8, 0,
+                lba_bytes[0], lba_bytes[1], lba_bytes[2], FIS_LBA_MODE,
+                lba_bytes[3], lba_bytes[4], lba_bytes[5], 0,
+                count_bytes[0], count_bytes[1], 0, 0];
+            for (index, value) in values.iter().enumerate() {
+                fis.add(index).write_volatile(*value);
+            }
+
+            // A single PRD entry covers the whole bounce buffer (byte count is stored minus one)
+            let prd = memory.add(PRDT_OFFSET) as *mut u32;
+            prd.write_volatile(self.buffer as u32);
+            prd.add(1).write_volatile((self.buffer >> 32) as u32);
+            prd.add(2).write_volatile(0);
+            prd.add(3).write_volatile((sectors.max(1) * SECTOR_SIZE - 1) as u32 | PRD_INTERRUPT);
+        }
+
+        self.interrupt.store(false, Release);
+        self.registers.write(PORT_COMMAND_ISSUE, 1);
+
+        let result = self.wait_completion();
+        if result.is_err() {
+            // The port stops processing commands after an error and needs to be restarted
+            self.stop().and_then(|_| self.start())?;
+        }
+
+        return result;
+    }
+
+    /// Wait for slot 0 to complete. The thread blocks, until the interrupt handler signals a change,
+    /// but the registers are checked after the timeout in any case, so that a missed interrupt does not fail a completed command.
+    fn wait_completion(&self) -> Result<()> {
+        let start = timer().read().systime_ms();
+        loop {
+            if self.registers.read(PORT_INTERRUPT_STATUS) & PORT_INTERRUPT_TASK_FILE_ERROR != 0
+                || self.registers.read(PORT_TASK_FILE) & TASK_FILE_ERROR != 0 {
+                return Err(Errno::IoError);
+            }
+            if self.registers.read(PORT_COMMAND_ISSUE) & 1 == 0 {
+                return Ok(());
+            }
+            let elapsed = timer().read().systime_ms() - start;
+            if elapsed >= COMMAND_TIMEOUT_MS {
+                return Err(Errno::IoError);
+            }
+
+            self.interrupt_received.wait_until(|| self.interrupt.swap(false, Acquire), Some(COMMAND_TIMEOUT_MS - elapsed));
+        }
+    }
+
+    /// Copy data from the bounce buffer (must only be called with `lock` held).
+    fn read_buffer(&self, target: &mut [u8]) {
+        unsafe { ptr::copy_nonoverlapping(self.buffer as *const u8, target.as_mut_ptr(), target.len()); }
+    }
+
+    /// Copy data into the bounce buffer (must only be called with `lock` held).
+    fn write_buffer(&self, source: &[u8]) {
+        unsafe { ptr::copy_nonoverlapping(source.as_ptr(), self.buffer as *mut u8, source.len()); }
+    }
+}
+
+impl AhciDrive {
+    fn identify(port: Arc<Port>) -> Result<Self> {
+        let info = {
+            let _guard = port.lock();
+            port.execute(Command::Identify, 0, 1)?;
+
+            let mut raw = [0u8; SECTOR_SIZE];
+            port.read_buffer(&mut raw);
+
+            let mut data = [0u16; 256];
+            for (word, bytes) in data.iter_mut().zip(raw.chunks_exact(2)) {
+                *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+            }
+
+            IdentifyData::parse(&data)
+        };
+
+        return Ok(Self { port, sector_count: info.sector_count, model: info.model });
+    }
+}
+
+impl BlockDevice for AhciDrive {
+    fn block_size(&self) -> usize {
+        return SECTOR_SIZE;
+    }
+
+    fn block_count(&self) -> u64 {
+        return self.sector_count;
+    }
+
+    fn read_blocks(&self, start: u64, buffer: &mut [u8]) -> Result<()> {
+        let _guard = self.port.lock();
+        for (index, chunk) in buffer.chunks_mut(BUFFER_PAGES * PAGE_SIZE).enumerate() {
+            let lba = start + (index * BUFFER_PAGES * PAGE_SIZE / SECTOR_SIZE) as u64;
+            self.port.execute(Command::ReadDmaExt, lba, chunk.len() / SECTOR_SIZE)?;
+            self.port.read_buffer(chunk);
+        }
+
+        return Ok(());
+    }
+
+    fn write_blocks(&self, start: u64, buffer: &[u8]) -> Result<()> {
+        let _guard = self.port.lock();
+        for (index, chunk) in buffer.chunks(BUFFER_PAGES * PAGE_SIZE).enumerate() {
+            let lba = start + (index * BUFFER_PAGES * PAGE_SIZE / SECTOR_SIZE) as u64;
+            self.port.write_buffer(chunk);
+            self.port.execute(Command::WriteDmaExt, lba, chunk.len() / SECTOR_SIZE)?;
+        }
+
+        return Ok(());
+    }
+
+    fn flush(&self) -> Result<()> {
+        let _guard = self.port.lock();
+        return self.port.execute(Command::FlushCacheExt, 0, 0);
+    }
+}
+
+impl InterruptHandler for AhciInterruptHandler {
+    fn trigger(&mut self) {
+        let pending = self.registers.read(HBA_INTERRUPT_STATUS);
+        for (number, port) in self.ports.iter() {
+            if pending & (1 << number) != 0 {
+                // Port interrupts need to be cleared before the global one (errors remain visible in the task file register)
+                let status = port.registers.read(PORT_INTERRUPT_STATUS);
+                port.registers.write(PORT_INTERRUPT_STATUS, status);
+                port.interrupt.store(true, Release);
+                port.interrupt_received.notify_all_from_interrupt();
+            }
+        }
+
+        self.registers.write(HBA_INTERRUPT_STATUS, pending);
+    }
+}
+
+impl Drop for PortGuard<'_> {
+    fn drop(&mut self) {
+        self.port.busy.store(false, Release);
+        self.port.idle.notify_all();
+    }
+}
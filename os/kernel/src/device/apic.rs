@@ -1,37 +1,110 @@
 use alloc::boxed::Box;
-use crate::interrupt::interrupt_dispatcher::InterruptVector;
+use crate::interrupt::interrupt_dispatcher::{InterruptVector, DYNAMIC_VECTOR_START};
 use acpi::madt::Madt;
 use acpi::platform::interrupt::{InterruptSourceOverride, NmiSource, Polarity, TriggerMode};
 use acpi::InterruptModel;
 use alloc::vec::Vec;
-use log::info;
+use core::sync::atomic::{AtomicBool, AtomicUsize};
+use core::sync::atomic::Ordering::Relaxed;
+use log::{info, warn};
 use raw_cpuid::CpuId;
-use spin::Mutex;
+use crate::sync::Mutex;
 use x2apic::ioapic::{IoApic, IrqFlags, IrqMode, RedirectionTableEntry};
 use x2apic::lapic::{LocalApic, LocalApicBuilder, TimerDivide, TimerMode};
 use x86_64::structures::paging::page::PageRange;
 use x86_64::VirtAddr;
+use x86_64::instructions::interrupts;
+use x86_64::registers::model_specific::Msr;
 use x86_64::structures::paging::{Page, PageTableFlags};
-use crate::{acpi_tables, allocator, apic, interrupt_dispatcher, scheduler};
-use crate::device::pit;
+use crate::{acpi_tables, allocator, apic, interrupt_dispatcher, scheduler, timer};
+use crate::cpu::features;
+use crate::cpu::features::Feature;
+use crate::device::{pic, pit};
+use crate::interrupt::deferred::Tasklet;
 use crate::interrupt::interrupt_handler::InterruptHandler;
 use crate::memory::MemorySpace;
 use crate::process::process::current_process;
 
 pub struct Apic {
     local_apic: Mutex<LocalApic>,
-    io_apic: Mutex<IoApic>,
+    /// Local APIC registers are accessed via MSRs instead of MMIO (see `X2APIC_MSR_BASE`).
+    x2apic: bool,
+    io_apics: Vec<IoApicDevice>,
+    /// Destination of IO APIC and MSI interrupts (see `destination()`).
+    interrupt_destination: u8,
     irq_overrides: Vec<InterruptSourceOverride>,
     nmi_sources: Vec<NmiSource>,
-    timer_ticks_per_ms: usize
+    timer_ticks_per_ms: usize,
+    timer_period_ns: AtomicUsize,
+    timer_interval_ns: AtomicUsize,
+    timer_partial_ns: AtomicUsize,
+    timer_one_shot: AtomicBool,
+    timer_ticks: AtomicUsize
+}
+
+/// An IO APIC handles the global system interrupts `gsi_base` to `gsi_base + num_entries - 1`.
+struct IoApicDevice {
+    io_apic: Mutex<IoApic>,
+    gsi_base: u32,
+    num_entries: u32
+}
+
+const IA32_APIC_BASE: u32 = 0x1b;
+/// In x2APIC mode, the local APIC registers are accessed via MSRs, starting at this address.
+const X2APIC_MSR_BASE: u32 = 0x800;
+/// In physical destination mode, an 8-bit destination of 0xff addresses all local APICs.
+const BROADCAST_DESTINATION: u8 = 0xff;
+
+/// Time slice, after which the scheduler is called by the APIC timer interrupt handler.
+pub const SCHEDULER_QUANTUM_MS: usize = 10;
+
+struct ApicTimerInterruptHandler {
+    pending_ns: usize,
+    ns_since_switch: usize
 }
 
-#[derive(Default)]
-struct ApicTimerInterruptHandler {}
+static TIMER_TASKLET: Tasklet = Tasklet::new(run_expired_timers);
+
+fn run_expired_timers() {
+    let now_ms = timer().read().systime_ms();
+    crate::timer::process_expired(now_ms);
+}
 
 impl InterruptHandler for ApicTimerInterruptHandler {
     fn trigger(&mut self) {
-        scheduler().switch_thread();
+        let apic = apic();
+        let elapsed_ns = apic.timer_interval_ns.load(Relaxed) + apic.timer_partial_ns.swap(0, Relaxed);
+        apic.timer_ticks.fetch_add(1, Relaxed);
+
+        // A one-shot timer has expired -> Fall back to periodic ticks
+        if apic.timer_one_shot.swap(false, Relaxed) {
+            apic.program_timer(TimerMode::Periodic, apic.timer_period_ns.load(Relaxed));
+        }
+
+        // The system time is kept by the APIC timer, since the PIT is only used for calibration
+        self.pending_ns += elapsed_ns;
+        if let Some(mut timer) = timer().try_write() {
+            timer.advance_systime(self.pending_ns);
+            self.pending_ns = 0;
+        }
+
+        // Expired kernel timers are executed as bottom half with interrupts enabled
+        TIMER_TASKLET.schedule();
+
+        // Sleeping threads are checked on every tick and preempt the current thread, when they are woken up
+        let woken_up = scheduler().check_sleeping_threads();
+
+        self.ns_since_switch += elapsed_ns;
+        if woken_up || self.ns_since_switch >= SCHEDULER_QUANTUM_MS * 1000000 {
+            self.ns_since_switch = 0;
+            scheduler().switch_thread();
+        }
+    }
+}
+
+impl ApicTimerInterruptHandler {
+    pub const fn new() -> Self {
+        Self { pending_ns: 0, ns_since_switch: 0 }
     }
 }
 
@@ -59,24 +132,30 @@ impl Apic {
             info!("CPU [{}] is the bootstrap processor", cpu_info.boot_processor.processor_uid);
         }
 
-        // Read physical APIC MMIO base address and map it to the kernel address space
-        // Needs to be executed in unsafe block; APIC availability has been checked before, so this should work.
-        let apic_page = Page::from_start_address(VirtAddr::new(madt.local_apic_address as u64)).expect("Local Apic MMIO address is not page aligned!");
+        // The local APIC is used in x2APIC mode if available, which accesses its registers via MSRs and supports 32-bit ids.
+        // Otherwise, the physical APIC MMIO base address is read from the MADT and mapped to the kernel address space.
+        let x2apic = features::has(Feature::X2Apic);
         let address_space = current_process().address_space();
-        address_space.map(PageRange { start: apic_page, end: apic_page + 1 }, MemorySpace::Kernel, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
-
-        let local_apic_mutex = Mutex::new(LocalApicBuilder::new()
-                .timer_vector(InterruptVector::ApicTimer as usize)
-                .error_vector(InterruptVector::ApicError as usize)
-                .spurious_vector(InterruptVector::Spurious as usize)
-                .set_xapic_base(apic_page.start_address().as_u64())
-                .build()
-                .unwrap_or_else(|err| panic!("Failed to initialize Local APIC ({})!", err)),
-        );
-
-        let io_apic_mutex;
+        let mut builder = LocalApicBuilder::new();
+        builder.timer_vector(InterruptVector::ApicTimer as usize)
+            .error_vector(InterruptVector::ApicError as usize)
+            .spurious_vector(InterruptVector::Spurious as usize);
+
+        if x2apic {
+            info!("Using local APIC in x2APIC mode");
+        } else {
+            info!("Using local APIC in xAPIC mode (MMIO base: [0x{:x}])", madt.local_apic_address);
+            let apic_page = Page::from_start_address(VirtAddr::new(madt.local_apic_address as u64)).expect("Local Apic MMIO address is not page aligned!");
+            address_space.map(PageRange { start: apic_page, end: apic_page + 1 }, MemorySpace::Kernel, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+            builder.set_xapic_base(apic_page.start_address().as_u64());
+        }
+
+        let local_apic_mutex = Mutex::new(builder.build().unwrap_or_else(|err| panic!("Failed to initialize Local APIC ({})!", err)));
+
+        let mut io_apics = Vec::<IoApicDevice>::new();
         let mut irq_overrides = Vec::<InterruptSourceOverride>::new();
         let mut nmi_sources = Vec::<NmiSource>::new();
+        let interrupt_destination;
 
         {
             let mut local_apic = local_apic_mutex.lock();
@@ -89,20 +168,10 @@ impl Apic {
                 InterruptModel::Apic(apic_desc) => {
                     info!("[{}] IO {} detected", apic_desc.io_apics.len(), if apic_desc.io_apics.len() == 1 { "APIC" } else { "APICs" });
 
-                    if apic_desc.io_apics.len() > 1 {
-                        panic!("More than one IO APIC found!");
+                    if apic_desc.io_apics.is_empty() {
+                        panic!("No IO APIC described by MADT!");
                     }
 
-                    let io_apic_desc = apic_desc.io_apics.get(0).unwrap_or_else(|| panic!("No IO APIC described by MADT!"));
-
-                    info!("Initializing IO APIC");
-                    let io_apic_page = Page::from_start_address(VirtAddr::new(io_apic_desc.address as u64)).expect("IO Apic MMIO address is not page aligned!");
-                    address_space.map(PageRange { start: io_apic_page, end: io_apic_page + 1 }, MemorySpace::Kernel, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
-                    unsafe { io_apic_mutex = Mutex::new(IoApic::new(io_apic_page.start_address().as_u64())); } // Needs to be executed in unsafe block; Since exactly one IO APIC has been detected, this should work
-
-                    let mut io_apic = io_apic_mutex.lock();
-                    unsafe { io_apic.init(io_apic_desc.global_system_interrupt_base as u8); }
-
                     // Read and store IRQ override entries
                     info!(
                     "[{}] interrupt source {} detected", apic_desc.interrupt_source_overrides.len(), if apic_desc.interrupt_source_overrides.len() == 1 { "override" } else { "overrides" }
@@ -114,74 +183,45 @@ impl Apic {
                     }
 
                     // Read and store non-maskable interrupts sources
-                    info!("[{}] NMI {} detected", apic_desc.interrupt_source_overrides.len(), if apic_desc.interrupt_source_overrides.len() == 1 { "source" } else { "sources" });
+                    info!("[{}] NMI {} detected", apic_desc.nmi_sources.len(), if apic_desc.nmi_sources.len() == 1 { "source" } else { "sources" });
 
                     for nmi_source in apic_desc.nmi_sources.iter() {
                         info!("NMI source [{}], Polarity: [{:?}], Trigger: [{:?}]", nmi_source.global_system_interrupt, nmi_source.polarity, nmi_source.trigger_mode);
                         nmi_sources.push(NmiSource { global_system_interrupt: nmi_source.global_system_interrupt, polarity: nmi_source.polarity, trigger_mode: nmi_source.trigger_mode });
                     }
 
-                    // Initialize redirection table with regards to IRQ override entries
-                    // Needs to be executed in unsafe block; At this point, the IO APIC has been initialized successfully, so we can assume, that reading the MSR works.
-                    for i in io_apic_desc.global_system_interrupt_base as u8..unsafe { io_apic.max_table_entry() } {
-                        let mut entry = RedirectionTableEntry::default();
-                        let mut flags = IrqFlags::MASKED;
-
-                        entry.set_mode(IrqMode::Fixed);
-
-                        // Needs to be executed in unsafe block; At this point, the APIC has been initialized successfully, so we can assume, that reading the MSR works.
-                        entry.set_dest(unsafe { local_apic.id() } as u8);
-
-                        match override_for_target(&irq_overrides, i) {
-                            None => entry.set_vector(i + InterruptVector::Pit as u8),
-                            Some(irq_override) => {
-                                if irq_override.polarity == Polarity::ActiveLow {
-                                    flags |= IrqFlags::LOW_ACTIVE;
-                                }
-                                if irq_override.trigger_mode == TriggerMode::Level {
-                                    flags |= IrqFlags::LEVEL_TRIGGERED;
-                                }
-
-                                entry.set_vector(irq_override.isa_source + InterruptVector::Pit as u8, );
-                            }
-                        }
+                    // Needs to be executed in unsafe block; At this point, the APIC has been initialized successfully, so we can assume, that reading the MSR works.
+                    interrupt_destination = destination(unsafe { local_apic.id() });
 
-                        entry.set_flags(flags);
+                    for io_apic_desc in apic_desc.io_apics.iter() {
+                        info!("Initializing IO APIC [{}] (GSI base: [{}])", io_apic_desc.id, io_apic_desc.global_system_interrupt_base);
+                        let io_apic_page = Page::from_start_address(VirtAddr::new(io_apic_desc.address as u64)).expect("IO Apic MMIO address is not page aligned!");
+                        address_space.map(PageRange { start: io_apic_page, end: io_apic_page + 1 }, MemorySpace::Kernel, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
 
-                        // Needs to be executed in unsafe block; Tables entries have been initialized in IoApic::init(), so writing them works.
-                        unsafe { io_apic.set_table_entry(i, entry); }
-                    }
+                        // Needs to be executed in unsafe block; The MMIO region has been described by the MADT and is mapped now, so this should work
+                        let mut io_apic = unsafe { IoApic::new(io_apic_page.start_address().as_u64()) };
+                        unsafe { io_apic.init(InterruptVector::Pit as u8); }
 
-                    // Set entries for non-maskable interrupts
-                    for nmi in nmi_sources.iter() {
-                        let mut entry = RedirectionTableEntry::default();
-                        let mut flags = IrqFlags::empty();
+                        // Initialize redirection table with regards to IRQ override entries and NMI sources
+                        let gsi_base = io_apic_desc.global_system_interrupt_base;
+                        let num_entries = unsafe { io_apic.max_table_entry() } as u32 + 1;
+                        for pin in 0..num_entries {
+                            let entry = redirection_entry(&irq_overrides, &nmi_sources, gsi_base + pin, interrupt_destination);
 
-                        if nmi.polarity == Polarity::ActiveLow {
-                            flags |= IrqFlags::LOW_ACTIVE;
-                        }
-                        if nmi.trigger_mode == TriggerMode::Level {
-                            flags |= IrqFlags::LEVEL_TRIGGERED;
+                            // Needs to be executed in unsafe block; Tables entries have been initialized in IoApic::init(), so writing them works.
+                            unsafe { io_apic.set_table_entry(pin as u8, entry); }
                         }
 
-                        entry.set_mode(IrqMode::NonMaskable);
-                        entry.set_vector(0);
-                        entry.set_flags(flags);
-
-                        // Needs to be executed in unsafe block; At this point, the APIC has been initialized successfully, so we can assume, that reading the MSR works.
-                        entry.set_dest(unsafe { local_apic.id() } as u8);
-
-                        // Needs to be executed in unsafe block; Tables entries have been initialized in IoApic::init(), so writing them works.
-                        unsafe { io_apic.set_table_entry(nmi.global_system_interrupt as u8, entry); }
-
-                        // Needs to be executed in unsafe block; We just disable the APIC timer here to prevent it from firing interrupts before calibration
-                        let apic_timer_target = target_gsi(&irq_overrides, InterruptVector::ApicTimer as u8 - InterruptVector::Pit as u8);
-                        unsafe { io_apic.disable_irq(apic_timer_target) }
+                        io_apics.push(IoApicDevice { io_apic: Mutex::new(io_apic), gsi_base, num_entries });
                     }
                 }
                 _ => panic!("No APIC described by MADT!"),
             }
 
+            // All legacy IRQs are routed through the IO APICs now -> Disable the 8259 PICs
+            info!("Disabling legacy PICs");
+            pic::disable();
+
             // Initialization is finished -> Enable Local Apic
             unsafe {
                 info!("Enabling local APIC [{}]", local_apic.id());
@@ -195,48 +235,185 @@ impl Apic {
 
         return Self {
             local_apic: local_apic_mutex,
-            io_apic: io_apic_mutex,
+            x2apic,
+            io_apics,
+            interrupt_destination,
             irq_overrides,
             nmi_sources,
-            timer_ticks_per_ms
+            timer_ticks_per_ms,
+            timer_period_ns: AtomicUsize::new(0),
+            timer_interval_ns: AtomicUsize::new(0),
+            timer_partial_ns: AtomicUsize::new(0),
+            timer_one_shot: AtomicBool::new(false),
+            timer_ticks: AtomicUsize::new(0)
         };
     }
 
+    /// Unmask the interrupt line, that is delivered to `vector`.
+    /// Local APIC interrupts are not routed through an IO APIC, so there is nothing to do for them.
     pub fn allow(&self, vector: InterruptVector) {
-        let target = target_gsi(&self.irq_overrides, vector as u8 - InterruptVector::Pit as u8);
-        if is_nmi(&self.nmi_sources, target) {
-            panic!("Trying to mask a non-maskable interrupt");
+        if vector >= InterruptVector::Cmci {
+            return;
+        }
+        if vector < InterruptVector::Pit {
+            panic!("APIC: Trying to allow exception vector [{:?}]!", vector);
         }
 
-        unsafe { self.io_apic.lock().enable_irq(target); }
+        self.allow_irq(vector as u8 - InterruptVector::Pit as u8);
     }
 
-    pub fn end_of_interrupt(&self) {
-        let mut local_apic = self.local_apic.try_lock();
-        while local_apic.is_none() {
-            // It its extremely unlikely, that the local APIC is locked during an interrupt,
-            // but if it happens, the whole system would hang, trying to send an EOI.
-            unsafe { self.local_apic.force_unlock(); }
-            local_apic = self.local_apic.try_lock();
+    /// Unmask legacy IRQ `irq` (compatibility layer for drivers, which only know their ISA IRQ number).
+    /// The IRQ is translated to a global system interrupt with regards to the interrupt source overrides.
+    pub fn allow_irq(&self, irq: u8) {
+        let gsi = target_gsi(&self.irq_overrides, irq);
+        if is_nmi(&self.nmi_sources, gsi) {
+            panic!("Trying to mask a non-maskable interrupt");
         }
 
-        unsafe { local_apic.unwrap().end_of_interrupt(); }
+        let (io_apic, pin) = self.io_apic_for_gsi(gsi).unwrap_or_else(|| panic!("APIC: No IO APIC handles global system interrupt [{}]!", gsi));
+        unsafe { io_apic.lock().enable_irq(pin); }
     }
 
-    pub fn start_timer(&self, interval_ms: usize) {
-        let mut local_apic = self.local_apic.lock();
+    /// Deliver legacy IRQ `irq` as non-maskable interrupt (used by the NMI watchdog).
+    pub fn route_irq_as_nmi(&self, irq: u8) {
+        let gsi = target_gsi(&self.irq_overrides, irq);
+        let (io_apic, pin) = self.io_apic_for_gsi(gsi).unwrap_or_else(|| panic!("APIC: No IO APIC handles global system interrupt [{}]!", gsi));
+
+        let mut entry = redirection_entry(&self.irq_overrides, &self.nmi_sources, gsi, self.destination());
+        let mut flags = entry.flags();
+        flags.remove(IrqFlags::MASKED);
+
+        entry.set_mode(IrqMode::NonMaskable);
+        entry.set_vector(0);
+        entry.set_flags(flags);
+
+        unsafe { io_apic.lock().set_table_entry(pin, entry); }
+    }
+
+    /// Deliver performance counter overflows as non-maskable interrupt (used by the profiler).
+    /// The CPU masks this entry on each overflow, so it needs to be called again after handling an overflow.
+    /// Accesses the register directly (without locking), since it is also called from the NMI handler.
+    pub fn enable_performance_counter_nmi(&self) {
+        const LVT_PERFORMANCE_COUNTER: u64 = 0x340;
+        const DELIVERY_MODE_NMI: u32 = 0x400;
 
         unsafe {
-            local_apic.set_timer_divide(TimerDivide::Div256); // Div256 is labelled wrong and actually means Div1
-            local_apic.set_timer_mode(TimerMode::Periodic);
-            local_apic.set_timer_initial((self.timer_ticks_per_ms * interval_ms) as u32);
-            local_apic.enable_timer();
+            if self.x2apic {
+                Msr::new(X2APIC_MSR_BASE + (LVT_PERFORMANCE_COUNTER >> 4) as u32).write(DELIVERY_MODE_NMI as u64);
+            } else {
+                // The MMIO page is identity mapped during initialization
+                let apic_base = Msr::new(IA32_APIC_BASE).read();
+                let register = ((apic_base & 0xfffff000) + LVT_PERFORMANCE_COUNTER) as *mut u32;
+                register.write_volatile(DELIVERY_MODE_NMI);
+            }
         }
+    }
+
+    /// Id of the local APIC of the current core (32 bits wide in x2APIC mode, 8 bits otherwise).
+    pub fn id(&self) -> u32 {
+        return self.with_local_apic(|local_apic| unsafe { local_apic.id() });
+    }
+
+    /// Id of the local APIC of the bootstrap processor as destination for IO APIC and MSI interrupts (see `destination()`).
+    pub fn destination(&self) -> u8 {
+        return self.interrupt_destination;
+    }
+
+    pub fn end_of_interrupt(&self) {
+        self.with_local_apic(|local_apic| unsafe { local_apic.end_of_interrupt() });
+    }
 
-        interrupt_dispatcher().assign(InterruptVector::ApicTimer, Box::new(ApicTimerInterruptHandler::default()));
+    /// Start the APIC timer as periodic system tick, firing every `interval_ms` milliseconds.
+    /// The timer interrupt handler keeps the system time and calls the scheduler every `SCHEDULER_QUANTUM_MS` milliseconds.
+    pub fn start_timer(&self, interval_ms: usize) {
+        self.set_timer_interval(interval_ms);
+
+        interrupt_dispatcher().assign(InterruptVector::ApicTimer, Box::new(ApicTimerInterruptHandler::new()));
         apic().allow(InterruptVector::ApicTimer);
     }
 
+    /// Change the interval of the periodic system tick (e.g. to reduce the tick rate on an idle core).
+    pub fn set_timer_interval(&self, interval_ms: usize) {
+        if interval_ms == 0 || interval_ms > SCHEDULER_QUANTUM_MS {
+            panic!("APIC: Invalid timer interval [{} ms]!", interval_ms);
+        }
+
+        self.timer_period_ns.store(interval_ms * 1000000, Relaxed);
+        self.program_timer(TimerMode::Periodic, interval_ms * 1000000);
+    }
+
+    /// Let the next timer interrupt occur after `delay_ms` milliseconds without any ticks in between (tickless operation).
+    pub fn timer_one_shot(&self, delay_ms: usize) {
+        self.timer_one_shot_ns(delay_ms * 1000000);
+    }
+
+    /// Let the next timer interrupt occur after `delay_ns` nanoseconds, which allows for sub-tick accuracy.
+    /// After the one-shot interrupt has fired, the timer falls back to periodic mode.
+    pub fn timer_one_shot_ns(&self, delay_ns: usize) {
+        if delay_ns == 0 {
+            panic!("APIC: Invalid one-shot delay [0 ns]!");
+        }
+
+        // The time, which has already passed in the current interval, would otherwise be lost for the system time
+        self.timer_partial_ns.fetch_add(self.timer_elapsed_ns(), Relaxed);
+        self.timer_one_shot.store(true, Relaxed);
+        self.program_timer(TimerMode::OneShot, delay_ns);
+    }
+
+    /// Number of APIC timer interrupts, that have occurred since the timer has been started (the kernel only runs on the boot core).
+    pub fn timer_ticks(&self) -> usize {
+        return self.timer_ticks.load(Relaxed);
+    }
+
+    /// Interval of the periodic system tick in nanoseconds.
+    pub fn timer_period_ns(&self) -> usize {
+        return self.timer_period_ns.load(Relaxed);
+    }
+
+    /// Time in nanoseconds, that has passed since the last timer interrupt.
+    pub fn timer_elapsed_ns(&self) -> usize {
+        let interval_ns = self.timer_interval_ns.load(Relaxed);
+        let initial = self.timer_ticks_per_ns(interval_ns);
+        let current = self.with_local_apic(|local_apic| unsafe { local_apic.timer_current() }) as usize;
+
+        return if current > initial { 0 } else { (initial - current) * 1000000 / self.timer_ticks_per_ms };
+    }
+
+    fn program_timer(&self, mode: TimerMode, interval_ns: usize) {
+        self.with_local_apic(|local_apic| {
+            self.timer_interval_ns.store(interval_ns, Relaxed);
+
+            unsafe {
+                local_apic.disable_timer();
+                local_apic.set_timer_divide(TimerDivide::Div256); // Div256 is labelled wrong and actually means Div1
+                local_apic.set_timer_mode(mode);
+                local_apic.set_timer_initial(self.timer_ticks_per_ns(interval_ns) as u32);
+                local_apic.enable_timer();
+            }
+        });
+    }
+
+    fn timer_ticks_per_ns(&self, ns: usize) -> usize {
+        let ticks = (self.timer_ticks_per_ms * ns) / 1000000;
+        return if ticks == 0 { 1 } else { ticks };
+    }
+
+    fn io_apic_for_gsi(&self, gsi: u32) -> Option<(&Mutex<IoApic>, u8)> {
+        for device in self.io_apics.iter() {
+            if gsi >= device.gsi_base && gsi < device.gsi_base + device.num_entries {
+                return Some((&device.io_apic, (gsi - device.gsi_base) as u8));
+            }
+        }
+
+        return None;
+    }
+
+    /// The local APIC is only locked with interrupts disabled, so that an interrupt handler (e.g. of the timer)
+    /// never finds it locked by the code it has interrupted.
+    fn with_local_apic<R>(&self, f: impl FnOnce(&mut LocalApic) -> R) -> R {
+        return interrupts::without_interrupts(|| f(&mut self.local_apic.lock()));
+    }
+
     fn calibrate_timer(local_apic: &mut LocalApic) -> usize {
         unsafe {
             // Set APIC timer to count down from 0xffffffff
@@ -258,10 +435,68 @@ impl Apic {
     }
 }
 
-fn target_gsi(irq_overrides: &Vec<InterruptSourceOverride>, source_irq: u8) -> u8 {
+/// IO APIC redirection entries and MSI addresses only have an 8-bit destination field.
+/// Larger x2APIC ids could only be reached with interrupt remapping (not supported), so these interrupts are broadcast instead.
+/// Only the bootstrap processor is running, so it is the only one receiving them.
+fn destination(apic_id: u32) -> u8 {
+    return match u8::try_from(apic_id) {
+        Ok(id) => id,
+        Err(_) => {
+            warn!("Local APIC id [{}] can not be used as interrupt destination -> Broadcasting IO APIC and MSI interrupts", apic_id);
+            BROADCAST_DESTINATION
+        }
+    };
+}
+
+fn redirection_entry(irq_overrides: &Vec<InterruptSourceOverride>, nmi_sources: &Vec<NmiSource>, gsi: u32, dest: u8) -> RedirectionTableEntry {
+    let mut entry = RedirectionTableEntry::default();
+    entry.set_dest(dest);
+
+    // Non-maskable interrupts are always enabled
+    for nmi in nmi_sources.iter() {
+        if nmi.global_system_interrupt == gsi {
+            entry.set_mode(IrqMode::NonMaskable);
+            entry.set_vector(0);
+            entry.set_flags(irq_flags(nmi.polarity, nmi.trigger_mode));
+
+            return entry;
+        }
+    }
+
+    // All other interrupts are masked, until a driver allows them
+    entry.set_mode(IrqMode::Fixed);
+    match override_for_target(irq_overrides, gsi) {
+        Some(irq_override) => {
+            entry.set_vector(irq_override.isa_source + InterruptVector::Pit as u8);
+            entry.set_flags(irq_flags(irq_override.polarity, irq_override.trigger_mode) | IrqFlags::MASKED);
+        }
+        None => {
+            // Interrupts beyond the static vector range cannot be used and stay masked forever
+            let vector = gsi + InterruptVector::Pit as u32;
+            entry.set_vector(if vector < DYNAMIC_VECTOR_START as u32 { vector as u8 } else { InterruptVector::Spurious as u8 });
+            entry.set_flags(IrqFlags::MASKED);
+        }
+    }
+
+    return entry;
+}
+
+fn irq_flags(polarity: Polarity, trigger_mode: TriggerMode) -> IrqFlags {
+    let mut flags = IrqFlags::empty();
+    if polarity == Polarity::ActiveLow {
+        flags |= IrqFlags::LOW_ACTIVE;
+    }
+    if trigger_mode == TriggerMode::Level {
+        flags |= IrqFlags::LEVEL_TRIGGERED;
+    }
+
+    return flags;
+}
+
+fn target_gsi(irq_overrides: &Vec<InterruptSourceOverride>, source_irq: u8) -> u32 {
     match override_for_source(irq_overrides, source_irq) {
-        None => source_irq,
-        Some(irq_override) => irq_override.global_system_interrupt as u8,
+        None => source_irq as u32,
+        Some(irq_override) => irq_override.global_system_interrupt,
     }
 }
 
@@ -275,9 +510,9 @@ fn override_for_source(irq_overrides: &Vec<InterruptSourceOverride>, source_irq:
     return None;
 }
 
-fn override_for_target(irq_overrides: &Vec<InterruptSourceOverride>, target_gsi: u8) -> Option<&InterruptSourceOverride> {
+fn override_for_target(irq_overrides: &Vec<InterruptSourceOverride>, target_gsi: u32) -> Option<&InterruptSourceOverride> {
     for irq_override in irq_overrides.iter() {
-        if irq_override.global_system_interrupt as u8 == target_gsi {
+        if irq_override.global_system_interrupt == target_gsi {
             return Some(irq_override);
         }
     }
@@ -285,9 +520,9 @@ fn override_for_target(irq_overrides: &Vec<InterruptSourceOverride>, target_gsi:
     return None;
 }
 
-fn is_nmi(nmi_sources: &Vec<NmiSource>, gsi: u8) -> bool {
+fn is_nmi(nmi_sources: &Vec<NmiSource>, gsi: u32) -> bool {
     for nmi in nmi_sources.iter() {
-        if nmi.global_system_interrupt == gsi as u32 {
+        if nmi.global_system_interrupt == gsi {
             return true;
         }
     }
@@ -0,0 +1,371 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use log::{info, warn};
+use syscall::error::Errno;
+use x86_64::instructions::port::Port;
+use crate::block;
+use crate::block::{BlockDevice, Result};
+use crate::device::pci::driver::{PciDriver, PciId};
+use crate::device::pci::PciDevice;
+use crate::interrupt::interrupt_handler::InterruptHandler;
+use crate::process::wait_queue::WaitQueue;
+use crate::{apic, interrupt_dispatcher, timer};
+
+const SECTOR_SIZE: usize = 512;
+/// A single command transfers at most 256 sectors (a sector count of 0 means 256 for 28-bit commands).
+const MAX_SECTORS_PER_COMMAND: usize = 256;
+const LBA28_LIMIT: u64 = 1 << 28;
+/// Drives, that do not raise an interrupt in time, are polled instead.
+const INTERRUPT_TIMEOUT_MS: usize = 1000;
+
+// Legacy ports and IRQs of the primary and secondary channel (used in compatibility mode)
+const LEGACY_CHANNELS: [(u16, u16, u8); 2] = [(0x1f0, 0x3f6, 14), (0x170, 0x376, 15)];
+
+// Offsets of the command block registers
+const DATA: u16 = 0;
+const SECTOR_COUNT: u16 = 2;
+const LBA_LOW: u16 = 3;
+const LBA_MID: u16 = 4;
+const LBA_HIGH: u16 = 5;
+const DRIVE_SELECT: u16 = 6;
+const STATUS_COMMAND: u16 = 7;
+
+const STATUS_ERROR: u8 = 1 << 0;
+const STATUS_DATA_REQUEST: u8 = 1 << 3;
+const STATUS_DRIVE_FAULT: u8 = 1 << 5;
+const STATUS_BUSY: u8 = 1 << 7;
+
+#[derive(Copy, Clone)]
+#[repr(u8)]
+enum Command {
+    ReadSectors = 0x20,
+    ReadSectorsExt = 0x24,
+    WriteSectors = 0x30,
+    WriteSectorsExt = 0x34,
+    FlushCache = 0xe7,
+    FlushCacheExt = 0xea,
+    Identify = 0xec,
+}
+
+/// One of the two channels of an IDE controller. Both drives of a channel share its registers and interrupt.
+struct Channel {
+    io_base: u16,
+    control_base: u16,
+    /// Set while a thread issues commands on this channel (see `lock()`).
+    busy: AtomicBool,
+    idle: WaitQueue,
+    /// Set by the interrupt handler, when the drive has finished (a part of) a command.
+    interrupt: AtomicBool,
+    interrupt_received: WaitQueue,
+}
+
+/// Serializes commands on a channel. Commands block until their interrupts arrive, so waiting threads block as well instead of spinning.
+struct ChannelGuard<'a> {
+    channel: &'a Channel,
+}
+
+/// ATA hard disk, accessed via programmed I/O (the default disk type of QEMU's 'pc' machine, e.g. '-hda').
+pub struct AtaDrive {
+    channel: Arc<Channel>,
+    /// 0 for master, 1 for slave.
+    drive: u8,
+    sector_count: u64,
+    lba48: bool,
+    model: String,
+}
+
+/// Drive information returned by the IDENTIFY command (also used by the AHCI driver, since SATA drives speak the same command set).
+pub struct IdentifyData {
+    pub model: String,
+    pub sector_count: u64,
+    pub lba48: bool,
+}
+
+struct AtaInterruptHandler {
+    channel: Arc<Channel>,
+}
+
+/// Driver for IDE controllers, which registers the connected ATA drives as block devices ('ata0' - 'ata3').
+pub static DRIVER: PciDriver = PciDriver { name: "ata", ids: &[PciId::class(0x01, 0x01)], probe };
+
+/// Only the first IDE controller is used, since the channels of further controllers in compatibility mode would share the legacy ports.
+static PROBED: AtomicBool = AtomicBool::new(false);
+
+fn probe(controller: &Arc<PciDevice>) -> Result<()> {
+    if PROBED.swap(true, Relaxed) {
+        return Err(Errno::NoDevice);
+    }
+
+    let (_, _, prog_if) = controller.class();
+    for (index, (legacy_io, legacy_control, legacy_irq)) in LEGACY_CHANNELS.iter().enumerate() {
+        // Bit 0 (primary) and 2 (secondary) of the programming interface are set, if a channel runs in native mode
+        let (io_base, control_base, irq) = if prog_if & (1 << (index * 2)) != 0 {
+            (controller.bar(index as u8 * 2) as u16, controller.bar(index as u8 * 2 + 1) as u16 + 2, controller.interrupt_line())
+        } else {
+            (*legacy_io, *legacy_control, *legacy_irq)
+        };
+
+        let channel = Arc::new(Channel { io_base, control_base, busy: AtomicBool::new(false), idle: WaitQueue::new(), interrupt: AtomicBool::new(false), interrupt_received: WaitQueue::new() });
+        if channel.read(STATUS_COMMAND) == 0xff {
+            continue; // Floating bus -> No drives connected
+        }
+
+        interrupt_dispatcher().assign_irq(irq, Box::new(AtaInterruptHandler { channel: Arc::clone(&channel) }));
+        apic().allow_irq(irq);
+        unsafe { Port::<u8>::new(channel.control_base).write(0); } // Enable interrupts
+
+        for drive in 0..2 {
+            if let Some(drive) = AtaDrive::identify(&channel, drive) {
+                let name = format!("ata{}", index * 2 + drive.drive as usize);
+                info!("Found ATA drive [{}]: Model: [{}], Sectors: [{}], LBA48: [{}]", name, drive.model, drive.sector_count, drive.lba48);
+
+                if let Err(err) = block::register(&name, Arc::new(drive)) {
+                    warn!("Failed to register ATA drive [{}] (Error: {:?})", name, err);
+                }
+            }
+        }
+    }
+
+    return Ok(());
+}
+
+impl Channel {
+    fn lock(&self) -> ChannelGuard<'_> {
+        while self.busy.swap(true, Acquire) {
+            self.idle.wait_until(|| !self.busy.load(Relaxed), None);
+        }
+
+        return ChannelGuard { channel: self };
+    }
+
+    fn read(&self, register: u16) -> u8 {
+        return unsafe { Port::<u8>::new(self.io_base + register).read() };
+    }
+
+    fn write(&self, register: u16, value: u8) {
+        unsafe { Port::<u8>::new(self.io_base + register).write(value); }
+    }
+
+    /// Reading the alternate status register does not acknowledge interrupts.
+    fn alternate_status(&self) -> u8 {
+        return unsafe { Port::<u8>::new(self.control_base).read() };
+    }
+
+    /// The drive needs about 400ns to update its status after a command, which is spent by reading the alternate status four times.
+    fn delay(&self) {
+        for _ in 0..4 {
+            self.alternate_status();
+        }
+    }
+
+    fn select(&self, drive: u8, lba_bits: u8) {
+        self.write(DRIVE_SELECT, 0xe0 | (drive << 4) | (lba_bits & 0x0f));
+        self.delay();
+    }
+
+    /// Poll until the drive is not busy anymore and check for errors.
+    fn wait_ready(&self) -> Result<u8> {
+        let start = timer().read().systime_ms();
+        loop {
+            let status = self.alternate_status();
+            if status & STATUS_BUSY == 0 {
+                return if status & (STATUS_ERROR | STATUS_DRIVE_FAULT) != 0 { Err(Errno::IoError) } else { Ok(status) };
+            }
+            if timer().read().systime_ms() - start > INTERRUPT_TIMEOUT_MS {
+                return Err(Errno::IoError);
+            }
+
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Block until the drive raises an interrupt or `INTERRUPT_TIMEOUT_MS` have passed.
+    fn block_until_interrupt(&self) {
+        self.interrupt_received.wait_until(|| self.interrupt.swap(false, Acquire), Some(INTERRUPT_TIMEOUT_MS));
+    }
+
+    /// Wait for the interrupt signaling that the drive is ready for the next data transfer.
+    /// Falls back to polling, if no interrupt arrives (e.g. if interrupts are disabled).
+    fn wait_interrupt(&self) -> Result<()> {
+        self.block_until_interrupt();
+
+        let status = self.wait_ready()?;
+        self.read(STATUS_COMMAND); // Acknowledge interrupt, if it has been missed
+
+        return if status & STATUS_DATA_REQUEST != 0 { Ok(()) } else { Err(Errno::IoError) };
+    }
+}
+
+impl AtaDrive {
+    fn identify(channel: &Arc<Channel>, drive: u8) -> Option<Self> {
+        let _guard = channel.lock();
+        channel.select(drive, 0);
+        channel.write(SECTOR_COUNT, 0);
+        channel.write(LBA_LOW, 0);
+        channel.write(LBA_MID, 0);
+        channel.write(LBA_HIGH, 0);
+        channel.write(STATUS_COMMAND, Command::Identify as u8);
+        channel.delay();
+
+        if channel.read(STATUS_COMMAND) == 0 {
+            return None; // Drive does not exist
+        }
+
+        // ATAPI and SATA devices abort the command and set the signature in the LBA registers
+        if channel.wait_ready().is_err() || channel.read(LBA_MID) != 0 || channel.read(LBA_HIGH) != 0 {
+            return None;
+        }
+        if channel.read(STATUS_COMMAND) & STATUS_DATA_REQUEST == 0 {
+            return None;
+        }
+
+        let mut data = [0u16; 256];
+        let mut data_port = Port::<u16>::new(channel.io_base + DATA);
+        for word in data.iter_mut() {
+            *word = unsafe { data_port.read() };
+        }
+        channel.interrupt.store(false, Release);
+
+        let info = IdentifyData::parse(&data);
+        return Some(Self { channel: Arc::clone(channel), drive, sector_count: info.sector_count, lba48: info.lba48, model: info.model });
+    }
+
+    /// Select the drive and issue a read or write command for `count` sectors at `lba` (28-bit or 48-bit addressing, as needed).
+    fn issue(&self, lba: u64, count: usize, write: bool) {
+        let channel = &self.channel;
+        let extended = lba + count as u64 > LBA28_LIMIT;
+        channel.interrupt.store(false, Release);
+
+        if extended {
+            channel.select(self.drive, 0);
+            channel.write(SECTOR_COUNT, (count >> 8) as u8);
+            channel.write(LBA_LOW, (lba >> 24) as u8);
+            channel.write(LBA_MID, (lba >> 32) as u8);
+            channel.write(LBA_HIGH, (lba >> 40) as u8);
+        } else {
+            channel.select(self.drive, (lba >> 24) as u8);
+        }
+
+        channel.write(SECTOR_COUNT, count as u8); // 256 is written as 0
+        channel.write(LBA_LOW, lba as u8);
+        channel.write(LBA_MID, (lba >> 8) as u8);
+        channel.write(LBA_HIGH, (lba >> 16) as u8);
+
+        let command = match (write, extended) {
+            (false, false) => Command::ReadSectors,
+            (false, true) => Command::ReadSectorsExt,
+            (true, false) => Command::WriteSectors,
+            (true, true) => Command::WriteSectorsExt
+        };
+        channel.write(STATUS_COMMAND, command as u8);
+        channel.delay();
+    }
+}
+
+impl IdentifyData {
+    pub fn parse(data: &[u16; 256]) -> Self {
+        // Model name is stored as string with swapped bytes in words 27 - 46
+        let model = data[27..47].iter()
+            .flat_map(|word| word.to_be_bytes())
+            .map(|byte| byte as char)
+            .collect::<String>();
+
+        let lba48 = data[83] & (1 << 10) != 0;
+        let sector_count = if lba48 {
+            data[100] as u64 | (data[101] as u64) << 16 | (data[102] as u64) << 32 | (data[103] as u64) << 48
+        } else {
+            data[60] as u64 | (data[61] as u64) << 16
+        };
+
+        return Self { model: String::from(model.trim()), sector_count, lba48 };
+    }
+}
+
+impl BlockDevice for AtaDrive {
+    fn block_size(&self) -> usize {
+        return SECTOR_SIZE;
+    }
+
+    fn block_count(&self) -> u64 {
+        return self.sector_count;
+    }
+
+    fn read_blocks(&self, start: u64, buffer: &mut [u8]) -> Result<()> {
+        let _guard = self.channel.lock();
+        let mut data_port = Port::<u16>::new(self.channel.io_base + DATA);
+
+        for (index, chunk) in buffer.chunks_mut(MAX_SECTORS_PER_COMMAND * SECTOR_SIZE).enumerate() {
+            self.issue(start + (index * MAX_SECTORS_PER_COMMAND) as u64, chunk.len() / SECTOR_SIZE, false);
+
+            // The drive raises an interrupt for each sector, when its data is ready to be read
+            for sector in chunk.chunks_mut(SECTOR_SIZE) {
+                self.channel.wait_interrupt()?;
+                for word in sector.chunks_exact_mut(2) {
+                    word.copy_from_slice(&unsafe { data_port.read() }.to_le_bytes());
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    fn write_blocks(&self, start: u64, buffer: &[u8]) -> Result<()> {
+        let _guard = self.channel.lock();
+        let mut data_port = Port::<u16>::new(self.channel.io_base + DATA);
+
+        for (index, chunk) in buffer.chunks(MAX_SECTORS_PER_COMMAND * SECTOR_SIZE).enumerate() {
+            self.issue(start + (index * MAX_SECTORS_PER_COMMAND) as u64, chunk.len() / SECTOR_SIZE, true);
+
+            // The first sector is written as soon as the drive is ready, each following one after an interrupt
+            for (number, sector) in chunk.chunks(SECTOR_SIZE).enumerate() {
+                if number == 0 {
+                    if self.channel.wait_ready()? & STATUS_DATA_REQUEST == 0 {
+                        return Err(Errno::IoError);
+                    }
+                } else {
+                    self.channel.wait_interrupt()?;
+                }
+
+                for word in sector.chunks_exact(2) {
+                    unsafe { data_port.write(u16::from_le_bytes([word[0], word[1]])); }
+                }
+            }
+
+            // Wait for the interrupt after the last sector, signaling that the data has been written
+            self.channel.block_until_interrupt();
+            self.channel.wait_ready()?;
+        }
+
+        return Ok(());
+    }
+
+    fn flush(&self) -> Result<()> {
+        let _guard = self.channel.lock();
+        self.channel.select(self.drive, 0);
+        self.channel.write(STATUS_COMMAND, if self.lba48 { Command::FlushCacheExt } else { Command::FlushCache } as u8);
+        self.channel.delay();
+        self.channel.wait_ready()?;
+
+        return Ok(());
+    }
+}
+
+impl InterruptHandler for AtaInterruptHandler {
+    fn trigger(&mut self) {
+        // Reading the status register acknowledges the interrupt (errors are checked by the waiting thread)
+        self.channel.read(STATUS_COMMAND);
+        self.channel.interrupt.store(true, Release);
+        self.channel.interrupt_received.notify_all_from_interrupt();
+    }
+}
+
+impl Drop for ChannelGuard<'_> {
+    fn drop(&mut self) {
+        self.channel.busy.store(false, Release);
+        self.channel.idle.notify_all();
+    }
+}
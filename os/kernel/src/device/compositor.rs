@@ -0,0 +1,504 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ptr;
+use core::slice;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering::Relaxed;
+use graphic::color;
+use graphic::color::Color;
+use graphic::lfb::{LFB, CHAR_HEIGHT, CHAR_WIDTH};
+use graphic::surface::Surface;
+use spin::Once;
+use syscall::error::Errno;
+use syscall::file::{FileType, OpenFlags, PollEvents};
+use syscall::input::{InputEvent, InputEventType, ABSOLUTE_X, ABSOLUTE_Y, BUTTON_LEFT, BUTTON_MIDDLE, RELATIVE_X, RELATIVE_Y};
+use syscall::ioctl::{FramebufferInfo, IoctlRequest, WindowGeometry};
+use x86_64::structures::paging::frame::PhysFrameRange;
+use x86_64::structures::paging::{PageTableFlags, PhysFrame};
+use crate::device::input;
+use crate::fs::devfs::Device;
+use crate::fs::poll::Pollable;
+use crate::fs::{devfs, File, Metadata, Result};
+use crate::memory::{physical, PAGE_SIZE};
+use crate::process::thread::Thread;
+use crate::process::wait_queue::{WaitQueue, Waiter};
+use crate::sync::Mutex;
+use crate::{scheduler, set_consoles_visible};
+
+/// Oldest events are dropped, if an application does not read the events of its window.
+const EVENT_CAPACITY: usize = 256;
+const TITLE_HEIGHT: u32 = 20;
+const BORDER: u32 = 1;
+/// Part of a window, which always stays on the screen, so that it can be moved back.
+const MIN_VISIBLE: u32 = 32;
+
+const BACKGROUND_COLOR: Color = Color { red: 0, green: 85, blue: 128, alpha: 255 };
+const FOCUSED_COLOR: Color = color::BLUE;
+const UNFOCUSED_COLOR: Color = Color { red: 85, green: 85, blue: 85, alpha: 255 };
+
+/// Mouse pointer ('X' is drawn black, '.' is drawn white).
+const POINTER: [&str; 14] = [
+    "X",
+    "XX",
+    "X.X",
+    "X..X",
+    "X...X",
+    "X....X",
+    "X.....X",
+    "X......X",
+    "X.......X",
+    "X....XXXXX",
+    "X..X..X",
+    "X.X X..X",
+    "XX  X..X",
+    "     XX",
+];
+
+static COMPOSITOR: Once<Compositor> = Once::new();
+
+/// Window system inside the kernel. Applications create windows by opening '/dev/window' (see `IoctlRequest::CreateWindow`),
+/// draw into the surface of their window, which is shared memory mapped into the application (see `sys_map_file()`),
+/// and read input events from the opened file, while their window has the focus.
+/// The compositor thread draws the windows in their stacking order onto the framebuffer, replacing the consoles while windows exist.
+/// It sleeps, until input arrives or the screen is damaged (e.g. by presenting a window).
+/// Clicking a window raises it and gives it the focus, while dragging its title bar moves it.
+struct Compositor {
+    lfb: LFB,
+    /// Windows from bottom to top. The topmost window has the focus.
+    windows: Mutex<Vec<Arc<Window>>>,
+    /// Set, when the screen needs to be composed again.
+    damaged: AtomicBool,
+    /// Notified, when the screen has been damaged (see `damage()`).
+    damage_queue: WaitQueue,
+}
+
+struct Window {
+    title: Mutex<String>,
+    /// Position of the upper left corner of the title bar.
+    position: Mutex<(u32, u32)>,
+    /// Format of the surface, which has the same pixel format as the framebuffer.
+    info: FramebufferInfo,
+    surface: Arc<SurfaceMemory>,
+    events: Mutex<VecDeque<InputEvent>>,
+    readable: WaitQueue,
+}
+
+/// Memory of a surface. It is shared with the processes, which have mapped it (see `File::mapped_memory()`),
+/// so that it is only freed, when it is neither shown nor mapped anymore.
+struct SurfaceMemory {
+    frames: PhysFrameRange,
+}
+
+/// An opened '/dev/window'. The window is removed from the screen, when the file is closed (even if its surface is still mapped).
+struct WindowFile {
+    window: Once<Arc<Window>>,
+}
+
+struct WindowDevice;
+
+struct Pointer {
+    x: u32,
+    y: u32,
+    moved: bool,
+    /// Window, which is being moved by dragging its title bar, and the position of the pointer inside the window.
+    drag: Option<(Arc<Window>, u32, u32)>,
+}
+
+/// Register '/dev/window' and start the compositor thread. The framebuffer memory must already be mapped (see `boot.rs`).
+pub fn init(buffer: *mut u8, pitch: u32, width: u32, height: u32, bpp: u8) -> Result<()> {
+    COMPOSITOR.call_once(|| Compositor { lfb: LFB::new(buffer, pitch, width, height, bpp), windows: Mutex::new(Vec::new()),
+        damaged: AtomicBool::new(false), damage_queue: WaitQueue::new() });
+    devfs::register("window", FileType::CharDevice, Arc::new(WindowDevice))?;
+
+    scheduler().ready(Thread::new_kernel_thread(Box::new(|| compositor().run())));
+    return Ok(());
+}
+
+fn compositor() -> &'static Compositor {
+    return COMPOSITOR.get().expect("Trying to access compositor before initialization!");
+}
+
+impl Compositor {
+    fn run(&self) {
+        let readers = input::devices().iter().map(|device| device.open()).collect::<Vec<_>>();
+        let mut pointer = Pointer { x: self.lfb.width() / 2, y: self.lfb.height() / 2, moved: false, drag: None };
+        let mut screen = Surface::new(self.lfb.width(), self.lfb.height(), self.lfb.bpp());
+        let mut visible = false;
+        // Events since the last `InputEventType::Sync` event, which are delivered to the focused window
+        let mut group = Vec::new();
+
+        loop {
+            // The waiter is registered before checking for input and damage, so that no notification is missed
+            let waiter = Waiter::new();
+            self.damage_queue.register(&waiter);
+            let mut input = false;
+
+            for reader in &readers {
+                if reader.poll(PollEvents::READABLE, Some(&waiter)).is_empty() {
+                    continue;
+                }
+
+                // The waiter has not been registered for this reader, so the loop must not block, before checking it again
+                input = true;
+                while let Some(event) = reader.try_read_event() {
+                    self.handle_event(&mut pointer, &mut group, event);
+                }
+            }
+
+            let windows = self.windows.lock().clone();
+            if windows.is_empty() {
+                if visible {
+                    set_consoles_visible(true);
+                    visible = false;
+                }
+            } else {
+                if !visible {
+                    set_consoles_visible(false);
+                    self.damaged.store(true, Relaxed);
+                    visible = true;
+                }
+
+                if self.damaged.swap(false, Relaxed) {
+                    self.compose(&mut screen, &windows, &pointer);
+                    screen.present(&self.lfb);
+                }
+            }
+
+            // Windows may be dropped here, so that their memory is freed without holding the lock
+            drop(windows);
+            if !input {
+                scheduler().block_on(&waiter, None);
+            }
+
+            waiter.wake(); // Woken waiters are removed from the wait queues, they have been registered in
+        }
+    }
+
+    /// Mark the screen as damaged and wake up the compositor thread, so that the screen is composed again.
+    fn damage(&self) {
+        self.damaged.store(true, Relaxed);
+        self.damage_queue.notify_all();
+    }
+
+    fn handle_event(&self, pointer: &mut Pointer, group: &mut Vec<InputEvent>, event: InputEvent) {
+        match event.typ {
+            InputEventType::Relative if event.code == RELATIVE_X => {
+                pointer.x = (pointer.x as i64 + event.value as i64).clamp(0, self.lfb.width() as i64 - 1) as u32;
+                pointer.moved = true;
+            }
+            InputEventType::Relative if event.code == RELATIVE_Y => {
+                pointer.y = (pointer.y as i64 + event.value as i64).clamp(0, self.lfb.height() as i64 - 1) as u32;
+                pointer.moved = true;
+            }
+            InputEventType::Key if event.code == BUTTON_LEFT && event.value == 1 => {
+                if let Some(window) = self.raise_window_at(pointer.x, pointer.y) {
+                    let (x, y) = *window.position.lock();
+                    if pointer.y < y + TITLE_HEIGHT {
+                        pointer.drag = Some((window, pointer.x - x, pointer.y - y));
+                    } else {
+                        group.push(event);
+                    }
+                }
+            }
+            InputEventType::Key if event.code == BUTTON_LEFT && event.value == 0 && pointer.drag.is_some() => {
+                pointer.drag = None;
+            }
+            InputEventType::Key if (BUTTON_LEFT..=BUTTON_MIDDLE).contains(&event.code) => {
+                if self.focused_window().is_some_and(|window| window.content_position(pointer.x, pointer.y).is_some()) {
+                    group.push(event);
+                }
+            }
+            InputEventType::Sync => self.synchronize(pointer, group),
+            _ => group.push(event)
+        }
+    }
+
+    /// Handle the movement of the pointer and deliver the events of the group to the focused window.
+    fn synchronize(&self, pointer: &mut Pointer, group: &mut Vec<InputEvent>) {
+        if pointer.moved {
+            pointer.moved = false;
+            self.damaged.store(true, Relaxed);
+
+            match &pointer.drag {
+                Some((window, offset_x, offset_y)) => {
+                    let x = pointer.x.saturating_sub(*offset_x).min(self.lfb.width().saturating_sub(MIN_VISIBLE));
+                    let y = pointer.y.saturating_sub(*offset_y).min(self.lfb.height().saturating_sub(TITLE_HEIGHT));
+                    *window.position.lock() = (x, y);
+                }
+                None => {
+                    let position = self.focused_window().and_then(|window| window.content_position(pointer.x, pointer.y));
+                    if let Some((x, y)) = position {
+                        group.push(InputEvent::new(InputEventType::Absolute, ABSOLUTE_X, x as i32));
+                        group.push(InputEvent::new(InputEventType::Absolute, ABSOLUTE_Y, y as i32));
+                    }
+                }
+            }
+        }
+
+        if !group.is_empty() {
+            group.push(InputEvent::new(InputEventType::Sync, 0, 0));
+            if let Some(window) = self.focused_window() {
+                window.push_events(group);
+            }
+
+            group.clear();
+        }
+    }
+
+    fn focused_window(&self) -> Option<Arc<Window>> {
+        return self.windows.lock().last().cloned();
+    }
+
+    /// Move the topmost window at the given position to the top of the stack, so that it gets the focus.
+    fn raise_window_at(&self, x: u32, y: u32) -> Option<Arc<Window>> {
+        let mut windows = self.windows.lock();
+        let index = windows.iter().rposition(|window| window.contains(x, y))?;
+        let window = windows.remove(index);
+        windows.push(Arc::clone(&window));
+        self.damaged.store(true, Relaxed);
+
+        return Some(window);
+    }
+
+    fn compose(&self, screen: &mut Surface, windows: &[Arc<Window>], pointer: &Pointer) {
+        screen.clear(&BACKGROUND_COLOR);
+
+        for (index, window) in windows.iter().enumerate() {
+            let (x, y) = *window.position.lock();
+            let info = window.info;
+            let frame_color = if index == windows.len() - 1 { FOCUSED_COLOR } else { UNFOCUSED_COLOR };
+
+            screen.fill_rect(x, y, info.width + 2 * BORDER, TITLE_HEIGHT + info.height + BORDER, &frame_color);
+            let title = window.title.lock().chars().take((info.width / CHAR_WIDTH) as usize).collect::<String>();
+            screen.draw_string(x + BORDER + 2, y + (TITLE_HEIGHT - CHAR_HEIGHT) / 2, &color::WHITE.bright(), &frame_color, &title);
+            screen.blit_data(window.surface(), info.width, info.height, info.pitch, x + BORDER, y + TITLE_HEIGHT);
+        }
+
+        for (row, line) in POINTER.iter().enumerate() {
+            for (column, pixel) in line.bytes().enumerate() {
+                let color = match pixel {
+                    b'X' => color::BLACK,
+                    b'.' => color::WHITE.bright(),
+                    _ => continue
+                };
+
+                screen.draw_pixel((pointer.x as usize + column) as i32, (pointer.y as usize + row) as i32, &color);
+            }
+        }
+    }
+}
+
+impl Window {
+    /// Check, if the given screen position is inside the window (including its title bar).
+    fn contains(&self, x: u32, y: u32) -> bool {
+        let (window_x, window_y) = *self.position.lock();
+        return x >= window_x && x < window_x + self.info.width + 2 * BORDER && y >= window_y && y < window_y + TITLE_HEIGHT + self.info.height + BORDER;
+    }
+
+    /// Convert the given screen position into a position on the surface of the window, if it is inside the surface.
+    fn content_position(&self, x: u32, y: u32) -> Option<(u32, u32)> {
+        let (window_x, window_y) = *self.position.lock();
+        let (content_x, content_y) = (x.checked_sub(window_x + BORDER)?, y.checked_sub(window_y + TITLE_HEIGHT)?);
+        if content_x >= self.info.width || content_y >= self.info.height {
+            return None;
+        }
+
+        return Some((content_x, content_y));
+    }
+
+    fn surface(&self) -> &[u8] {
+        let start = self.surface.frames.start.start_address().as_u64() as *const u8;
+        return unsafe { slice::from_raw_parts(start, (self.info.pitch * self.info.height) as usize) };
+    }
+
+    fn push_events(&self, new_events: &[InputEvent]) {
+        let mut events = self.events.lock();
+        for event in new_events {
+            if events.len() == EVENT_CAPACITY {
+                events.pop_front();
+            }
+            events.push_back(*event);
+        }
+        drop(events);
+
+        self.readable.notify_all();
+    }
+}
+
+impl Drop for SurfaceMemory {
+    fn drop(&mut self) {
+        unsafe { physical::free(self.frames); }
+    }
+}
+
+impl WindowFile {
+    fn window(&self) -> Result<&Arc<Window>> {
+        return self.window.get().ok_or(Errno::InvalidArgument);
+    }
+
+    fn create_window(&self, geometry: WindowGeometry) -> Result<()> {
+        let compositor = compositor();
+        let lfb = &compositor.lfb;
+        if geometry.width == 0 || geometry.height == 0 || geometry.width > lfb.width() || geometry.height > lfb.height() {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let pitch = geometry.width * (lfb.bpp() as u32).div_ceil(8);
+        let mut created = false;
+        self.window.call_once(|| {
+            // The surface is cleared, so that no data of other processes is leaked
+            let frames = physical::alloc(((pitch * geometry.height) as usize).div_ceil(PAGE_SIZE));
+            unsafe { (frames.start.start_address().as_u64() as *mut u8).write_bytes(0, (frames.end - frames.start) as usize * PAGE_SIZE); }
+
+            let x = geometry.x.min(lfb.width().saturating_sub(MIN_VISIBLE));
+            let y = geometry.y.min(lfb.height().saturating_sub(TITLE_HEIGHT));
+            created = true;
+
+            Arc::new(Window {
+                title: Mutex::new(String::new()),
+                position: Mutex::new((x, y)),
+                info: FramebufferInfo { width: geometry.width, height: geometry.height, pitch, bpp: lfb.bpp() },
+                surface: Arc::new(SurfaceMemory { frames }),
+                events: Mutex::new(VecDeque::new()),
+                readable: WaitQueue::new(),
+            })
+        });
+
+        if !created {
+            return Err(Errno::AlreadyExists);
+        }
+
+        compositor.windows.lock().push(Arc::clone(self.window()?));
+        compositor.damage();
+        return Ok(());
+    }
+
+    /// Return the next event, blocking until one is available.
+    fn read_event(&self, window: &Window) -> InputEvent {
+        let mut events = window.events.lock();
+        loop {
+            if let Some(event) = events.pop_front() {
+                return event;
+            }
+
+            window.readable.wait(events);
+            events = window.events.lock();
+        }
+    }
+}
+
+impl Drop for WindowFile {
+    fn drop(&mut self) {
+        if let Some(window) = self.window.get() {
+            let compositor = compositor();
+            compositor.windows.lock().retain(|other| !Arc::ptr_eq(other, window));
+            compositor.damage();
+        }
+    }
+}
+
+impl File for WindowFile {
+    /// Read whole `InputEvent` records (like from '/dev/input/eventN'), blocking until at least one is available.
+    fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+        let window = self.window()?;
+        if buffer.len() < size_of::<InputEvent>() {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let mut count = 0;
+        for chunk in buffer.chunks_exact_mut(size_of::<InputEvent>()) {
+            // Only the first event is waited for
+            let event = if count == 0 {
+                self.read_event(window)
+            } else {
+                match window.events.lock().pop_front() {
+                    Some(event) => event,
+                    None => break
+                }
+            };
+
+            unsafe { ptr::copy_nonoverlapping(ptr::from_ref(&event) as *const u8, chunk.as_mut_ptr(), size_of::<InputEvent>()); }
+            count += size_of::<InputEvent>();
+        }
+
+        return Ok(count);
+    }
+
+    /// Writing sets the title of the window.
+    fn write(&self, buffer: &[u8]) -> Result<usize> {
+        *self.window()?.title.lock() = String::from_utf8_lossy(buffer).into_owned();
+        compositor().damage();
+
+        return Ok(buffer.len());
+    }
+
+    fn stat(&self) -> Result<Metadata> {
+        return Ok(Metadata { inode: ptr::from_ref(self) as u64, typ: FileType::CharDevice, size: 0, created_ms: 0, modified_ms: 0 });
+    }
+
+    fn ioctl(&self, request: usize, arg: usize) -> Result<usize> {
+        return match IoctlRequest::try_from(request) {
+            Ok(IoctlRequest::CreateWindow) => {
+                self.create_window(unsafe { (arg as *const WindowGeometry).read() })?;
+                Ok(0)
+            }
+            Ok(IoctlRequest::PresentWindow) => {
+                self.window()?;
+                compositor().damage();
+                Ok(0)
+            }
+            Ok(IoctlRequest::GetFramebufferInfo) => {
+                unsafe { (arg as *mut FramebufferInfo).write(self.window()?.info); }
+                Ok(0)
+            }
+            _ => Err(Errno::NotATerminal)
+        };
+    }
+
+    /// The surface of the window can be mapped (the upper left pixel is at offset 0).
+    fn mmap(&self, offset: usize, length: usize) -> Result<(PhysFrameRange, PageTableFlags)> {
+        let frames = self.window()?.surface.frames;
+        let size = (frames.end - frames.start) as usize * PAGE_SIZE;
+        if offset % PAGE_SIZE != 0 || offset >= size || length > size - offset {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let start = frames.start + (offset / PAGE_SIZE) as u64;
+        return Ok((PhysFrame::range(start, start + length.div_ceil(PAGE_SIZE) as u64), PageTableFlags::empty()));
+    }
+
+    /// Mappings keep only the surface alive, so that closing the file still removes the window from the screen.
+    fn mapped_memory(&self) -> Option<Arc<dyn Send + Sync>> {
+        return self.window.get().map(|window| Arc::clone(&window.surface) as Arc<dyn Send + Sync>);
+    }
+}
+
+impl Pollable for WindowFile {
+    fn poll(&self, _events: PollEvents, waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        let window = match self.window.get() {
+            Some(window) => window,
+            None => return PollEvents::empty()
+        };
+
+        let events = window.events.lock();
+        if !events.is_empty() {
+            return PollEvents::READABLE;
+        }
+
+        if let Some(waiter) = waiter {
+            window.readable.register(waiter);
+        }
+
+        return PollEvents::empty();
+    }
+}
+
+impl Device for WindowDevice {
+    fn open(&self, _flags: OpenFlags) -> Result<Option<Arc<dyn File>>> {
+        return Ok(Some(Arc::new(WindowFile { window: Once::new() })));
+    }
+}
@@ -0,0 +1,74 @@
+use core::ptr;
+use syscall::error::Errno;
+use syscall::ioctl::{FramebufferInfo, IoctlRequest};
+use x86_64::PhysAddr;
+use x86_64::structures::paging::frame::PhysFrameRange;
+use x86_64::structures::paging::{PageTableFlags, PhysFrame};
+use crate::fs::devfs::Device;
+use crate::fs::Result;
+use crate::memory::{pat, PAGE_SIZE};
+
+/// '/dev/fb0': Raw access to the linear framebuffer. The offset is the byte position inside the framebuffer memory.
+/// Writing to the framebuffer bypasses the terminal, which may overwrite the changes at any time.
+/// The resolution and pixel format can be queried via `IoctlRequest::GetFramebufferInfo`.
+/// For drawing without a system call per frame, the framebuffer memory can be mapped write-combining into user space (see `sys_map_file()`).
+pub struct FramebufferDevice {
+    address: usize,
+    size: usize,
+    info: FramebufferInfo,
+}
+
+impl FramebufferDevice {
+    /// The framebuffer memory must already be mapped (see `boot.rs`).
+    pub fn new(address: *mut u8, width: u32, height: u32, pitch: u32, bpp: u8) -> Self {
+        Self { address: address as usize, size: pitch as usize * height as usize, info: FramebufferInfo { width, height, pitch, bpp } }
+    }
+}
+
+impl Device for FramebufferDevice {
+    fn read(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        if offset >= self.size {
+            return Ok(0);
+        }
+
+        let count = buffer.len().min(self.size - offset);
+        unsafe { ptr::copy_nonoverlapping((self.address + offset) as *const u8, buffer.as_mut_ptr(), count); }
+
+        return Ok(count);
+    }
+
+    fn write(&self, offset: usize, buffer: &[u8]) -> Result<usize> {
+        if offset >= self.size {
+            return Err(Errno::NoSpace);
+        }
+
+        let count = buffer.len().min(self.size - offset);
+        unsafe { ptr::copy_nonoverlapping(buffer.as_ptr(), (self.address + offset) as *mut u8, count); }
+
+        return Ok(count);
+    }
+
+    fn ioctl(&self, request: usize, arg: usize) -> Result<usize> {
+        return match IoctlRequest::try_from(request) {
+            Ok(IoctlRequest::GetFramebufferInfo) => {
+                unsafe { (arg as *mut FramebufferInfo).write(self.info); }
+                Ok(0)
+            }
+            _ => Err(Errno::NotATerminal)
+        };
+    }
+
+    fn size(&self) -> usize {
+        return self.size;
+    }
+
+    fn mmap(&self, offset: usize, length: usize) -> Result<(PhysFrameRange, PageTableFlags)> {
+        // The framebuffer is identity mapped, so its address is also the physical address
+        if offset % PAGE_SIZE != 0 || offset >= self.size || length > self.size.next_multiple_of(PAGE_SIZE) - offset {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let start = PhysFrame::from_start_address(PhysAddr::new((self.address + offset) as u64)).map_err(|_| Errno::InvalidArgument)?;
+        return Ok((PhysFrame::range(start, start + length.div_ceil(PAGE_SIZE) as u64), pat::WRITE_COMBINING_FLAGS));
+    }
+}
@@ -0,0 +1,206 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicUsize};
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use log::info;
+use syscall::error::Errno;
+use syscall::file::{FileType, OpenFlags, PollEvents};
+use syscall::input::InputEvent;
+use x86_64::instructions::interrupts;
+use crate::fs::devfs::Device;
+use crate::fs::{devfs, poll, File, Metadata, Result};
+use crate::fs::poll::Pollable;
+use crate::interrupt::deferred;
+use crate::process::wait_queue::{WaitQueue, Waiter};
+use crate::sync::Mutex;
+use crate::timer;
+
+/// Events, which have been pushed by an interrupt handler, but not distributed to the readers yet.
+const PENDING_CAPACITY: usize = 128;
+/// Oldest events are dropped, if a reader does not keep up.
+const READER_CAPACITY: usize = 256;
+
+static NEXT_NUMBER: AtomicUsize = AtomicUsize::new(0);
+/// All registered input devices (e.g. for the compositor, which reads from all of them).
+static DEVICES: Mutex<Vec<Arc<InputDevice>>> = Mutex::new(Vec::new());
+
+/// Input device (e.g. keyboard or mouse), available as '/dev/input/eventN'.
+/// Drivers push `InputEvent`s, which are delivered to every file opened on the device node, each having its own queue.
+pub struct InputDevice {
+    name: &'static str,
+    /// Locked by interrupt handlers, so it must only be locked with interrupts disabled in thread context.
+    /// The capacity is reserved upfront, so that the queue never grows in an interrupt handler
+    /// (only scheduling the notification of the readers allocates a work item, once per batch of events).
+    pending: Mutex<VecDeque<InputEvent>>,
+    notification_pending: AtomicBool,
+    readers: Mutex<Vec<Weak<InputReader>>>,
+}
+
+/// An opened input device node. Reading returns whole `InputEvent` records and blocks, until at least one event is available.
+pub struct InputReader {
+    events: Mutex<VecDeque<InputEvent>>,
+    readable: WaitQueue,
+}
+
+struct InputDeviceNode {
+    device: Arc<InputDevice>,
+}
+
+/// Create an input device called `name` (e.g. 'PS/2 keyboard') and make it available as the next '/dev/input/eventN'.
+pub fn register(name: &'static str) -> Arc<InputDevice> {
+    let device = Arc::new(InputDevice {
+        name,
+        pending: Mutex::new(VecDeque::with_capacity(PENDING_CAPACITY)),
+        notification_pending: AtomicBool::new(false),
+        readers: Mutex::new(Vec::new()),
+    });
+
+    DEVICES.lock().push(Arc::clone(&device));
+    let path = format!("input/event{}", NEXT_NUMBER.fetch_add(1, Relaxed));
+    match devfs::register(&path, FileType::CharDevice, Arc::new(InputDeviceNode { device: Arc::clone(&device) })) {
+        Ok(()) => info!("Registered input device [{}] as [/dev/{}]", name, path),
+        Err(err) => panic!("Failed to register input device [{}] (Error: {:?})", name, err)
+    }
+
+    return device;
+}
+
+pub fn devices() -> Vec<Arc<InputDevice>> {
+    return DEVICES.lock().clone();
+}
+
+impl InputDevice {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Queue events for all readers. May be called from interrupt handlers, so the readers are served by the kernel worker thread.
+    /// Related events should be pushed together, followed by an `InputEventType::Sync` event.
+    /// The events are stamped with the current time here, so that the time of distributing them to the readers does not matter.
+    pub fn push(self: &Arc<Self>, events: &[InputEvent]) {
+        let time_ms = timer().read().systime_ms() as u64;
+        interrupts::without_interrupts(|| {
+            let mut pending = self.pending.lock();
+            for event in events {
+                if pending.len() == PENDING_CAPACITY {
+                    pending.pop_front();
+                }
+                pending.push_back(InputEvent { time_ms, ..*event });
+            }
+        });
+
+        if !self.notification_pending.swap(true, Acquire) {
+            let device = Arc::clone(self);
+            deferred::schedule_work(Box::new(move || {
+                device.notification_pending.store(false, Release);
+                device.distribute();
+            }));
+        }
+    }
+
+    /// Create a reader, which receives all events pushed from now on.
+    pub fn open(&self) -> Arc<InputReader> {
+        let reader = Arc::new(InputReader { events: Mutex::new(VecDeque::new()), readable: WaitQueue::new() });
+
+        let mut readers = self.readers.lock();
+        readers.retain(|reader| reader.strong_count() > 0);
+        readers.push(Arc::downgrade(&reader));
+
+        return reader;
+    }
+
+    /// Move all pending events into the queues of the readers.
+    fn distribute(&self) {
+        let events = interrupts::without_interrupts(|| self.pending.lock().drain(..).collect::<Vec<InputEvent>>());
+        let readers = self.readers.lock().iter().filter_map(Weak::upgrade).collect::<Vec<Arc<InputReader>>>();
+        for reader in readers {
+            let mut queue = reader.events.lock();
+            for event in &events {
+                if queue.len() == READER_CAPACITY {
+                    queue.pop_front();
+                }
+                queue.push_back(*event);
+            }
+            drop(queue);
+
+            reader.readable.notify_all();
+        }
+    }
+}
+
+impl InputReader {
+    /// Return the next event, blocking until one is available.
+    pub fn read_event(&self) -> InputEvent {
+        let mut events = self.events.lock();
+        loop {
+            if let Some(event) = events.pop_front() {
+                return event;
+            }
+
+            self.readable.wait(events);
+            events = self.events.lock();
+        }
+    }
+
+    pub fn try_read_event(&self) -> Option<InputEvent> {
+        return self.events.lock().pop_front();
+    }
+}
+
+impl File for InputReader {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+        if buffer.len() < size_of::<InputEvent>() {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let mut count = 0;
+        for chunk in buffer.chunks_exact_mut(size_of::<InputEvent>()) {
+            // Only the first event is waited for
+            let event = if count == 0 {
+                self.read_event()
+            } else {
+                match self.try_read_event() {
+                    Some(event) => event,
+                    None => break
+                }
+            };
+
+            unsafe { ptr::copy_nonoverlapping(ptr::from_ref(&event) as *const u8, chunk.as_mut_ptr(), size_of::<InputEvent>()); }
+            count += size_of::<InputEvent>();
+        }
+
+        return Ok(count);
+    }
+
+    fn write(&self, _buffer: &[u8]) -> Result<usize> {
+        return Err(Errno::BadDescriptor);
+    }
+
+    fn stat(&self) -> Result<Metadata> {
+        return Ok(Metadata { inode: ptr::from_ref(self) as u64, typ: FileType::CharDevice, size: 0, created_ms: 0, modified_ms: 0 });
+    }
+}
+
+impl Pollable for InputReader {
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        let queued = self.events.lock();
+        let ready = if queued.is_empty() { PollEvents::empty() } else { PollEvents::READABLE };
+
+        return poll::ready_or_register(ready, events, waiter, &[(PollEvents::READABLE, &self.readable)]);
+    }
+}
+
+impl Device for InputDeviceNode {
+    fn open(&self, flags: OpenFlags) -> Result<Option<Arc<dyn File>>> {
+        if flags.contains(OpenFlags::WRITE) {
+            return Err(Errno::InvalidArgument);
+        }
+
+        return Ok(Some(self.device.open()));
+    }
+}
@@ -0,0 +1,156 @@
+use pc_keyboard::layouts::{AnyLayout, Azerty, De105Key, Uk105Key, Us104Key};
+use pc_keyboard::{DecodedKey, HandleControl, KeyCode, Keyboard, ScancodeSet1};
+use syscall::ioctl::KeyboardLayout;
+
+/// Accents, which are combined with the next character after typing a dead key.
+/// Each entry contains the dead keys of all layouts for the accent, the base characters and the resulting characters.
+const COMPOSITIONS: [(&str, &str, &str); 5] = [
+    ("`", "aeiouAEIOU", "àèìòùÀÈÌÒÙ"),
+    ("´'", "aeiouyAEIOUYcC", "áéíóúýÁÉÍÓÚÝçÇ"),
+    ("^", "aeiouAEIOU", "âêîôûÂÊÎÔÛ"),
+    ("¨\"", "aeiouyAEIOU", "äëïöüÿÄËÏÖÜ"),
+    ("~", "anoANO", "ãñõÃÑÕ"),
+];
+
+/// Translates scancodes (set 1) into characters for the selected `KeyboardLayout`.
+/// Characters are decoded by `pc_keyboard`, while AltGr combinations and dead keys are handled on top of it,
+/// so that they work the same for all layouts.
+pub struct Keymap {
+    layout: KeyboardLayout,
+    decoder: Keyboard<AnyLayout, ScancodeSet1>,
+    /// Dead key, which has been typed and waits for the next character.
+    dead_key: Option<char>,
+    alt_gr_pressed: bool,
+    extended: bool,
+}
+
+impl Keymap {
+    pub fn new(layout: KeyboardLayout) -> Self {
+        Self {
+            layout,
+            decoder: Keyboard::new(ScancodeSet1::new(), any_layout(layout), HandleControl::MapLettersToUnicode),
+            dead_key: None,
+            alt_gr_pressed: false,
+            extended: false,
+        }
+    }
+
+    pub fn layout(&self) -> KeyboardLayout {
+        return self.layout;
+    }
+
+    /// Switch to `layout`. A pending dead key and the state of the modifier keys are discarded.
+    pub fn set_layout(&mut self, layout: KeyboardLayout) {
+        *self = Keymap::new(layout);
+    }
+
+    /// Feed a scancode into the decoder and pass the typed characters (if any) to `output`.
+    /// A dead key followed by a character, which can not be combined with it, results in two characters.
+    /// Keys without a character (e.g. arrow keys) are passed as VT100 escape sequences (see `escape_sequence()`).
+    pub fn decode(&mut self, scancode: u8, mut output: impl FnMut(char)) {
+        // The right alt key (AltGr) is sent as extended scancode
+        let extended = core::mem::replace(&mut self.extended, scancode == 0xe0);
+        if extended && scancode & 0x7f == 0x38 {
+            self.alt_gr_pressed = scancode & 0x80 == 0;
+        }
+
+        let c = match self.decoder.add_byte(scancode) {
+            Ok(Some(event)) => match self.decoder.process_keyevent(event) {
+                Some(DecodedKey::Unicode(c)) => c,
+                Some(DecodedKey::RawKey(key)) => {
+                    if let Some(sequence) = escape_sequence(key) {
+                        sequence.chars().for_each(output);
+                    }
+                    return;
+                }
+                None => return
+            },
+            _ => return
+        };
+
+        if self.alt_gr_pressed {
+            output(alt_gr(self.layout, c).unwrap_or(c));
+            return;
+        }
+
+        match self.dead_key.take() {
+            Some(dead_key) => match compose(dead_key, c) {
+                Some(composed) => output(composed),
+                // Typing the dead key twice or followed by a space produces the accent itself
+                None if c == dead_key || c == ' ' => output(dead_key),
+                None => {
+                    output(dead_key);
+                    output(c);
+                }
+            },
+            None if dead_keys(self.layout).contains(c) => self.dead_key = Some(c),
+            None => output(c)
+        }
+    }
+}
+
+fn any_layout(layout: KeyboardLayout) -> AnyLayout {
+    return match layout {
+        KeyboardLayout::German => AnyLayout::De105Key(De105Key),
+        KeyboardLayout::Us | KeyboardLayout::UsInternational => AnyLayout::Us104Key(Us104Key),
+        KeyboardLayout::Uk => AnyLayout::Uk105Key(Uk105Key),
+        KeyboardLayout::French => AnyLayout::Azerty(Azerty),
+    };
+}
+
+fn dead_keys(layout: KeyboardLayout) -> &'static str {
+    return match layout {
+        KeyboardLayout::German => "^´`",
+        KeyboardLayout::UsInternational => "'\"`^~",
+        KeyboardLayout::French => "^¨",
+        KeyboardLayout::Us | KeyboardLayout::Uk => "",
+    };
+}
+
+/// Return the character for AltGr and the key, which produces `c` without AltGr.
+/// Combinations already handled by `pc_keyboard` are passed through, since their characters are not found here.
+fn alt_gr(layout: KeyboardLayout, c: char) -> Option<char> {
+    let (keys, results) = match layout {
+        KeyboardLayout::German => ("qe237890ß+<m", "@€²³{[]}\\~|µ"),
+        KeyboardLayout::UsInternational => ("12345qwertyuiopasdlzcnm,", "¡²³¤€äåé®þüúíóöáßðøæ©ñµç"),
+        KeyboardLayout::Uk => ("4aeiou", "€áéíóú"),
+        KeyboardLayout::French => ("é\"'(-è_çà)=e", "~#{[|`\\^@]}€"),
+        KeyboardLayout::Us => ("", ""),
+    };
+
+    return keys.chars().position(|key| key == c).and_then(|index| results.chars().nth(index));
+}
+
+/// Input sequences sent by a VT100 compatible terminal (as expected by programs reading from a terminal).
+fn escape_sequence(key: KeyCode) -> Option<&'static str> {
+    return match key {
+        KeyCode::ArrowUp => Some("\x1b[A"),
+        KeyCode::ArrowDown => Some("\x1b[B"),
+        KeyCode::ArrowRight => Some("\x1b[C"),
+        KeyCode::ArrowLeft => Some("\x1b[D"),
+        KeyCode::Home => Some("\x1b[H"),
+        KeyCode::End => Some("\x1b[F"),
+        KeyCode::Insert => Some("\x1b[2~"),
+        KeyCode::Delete => Some("\x1b[3~"),
+        KeyCode::PageUp => Some("\x1b[5~"),
+        KeyCode::PageDown => Some("\x1b[6~"),
+        KeyCode::F1 => Some("\x1bOP"),
+        KeyCode::F2 => Some("\x1bOQ"),
+        KeyCode::F3 => Some("\x1bOR"),
+        KeyCode::F4 => Some("\x1bOS"),
+        KeyCode::F5 => Some("\x1b[15~"),
+        KeyCode::F6 => Some("\x1b[17~"),
+        KeyCode::F7 => Some("\x1b[18~"),
+        KeyCode::F8 => Some("\x1b[19~"),
+        KeyCode::F9 => Some("\x1b[20~"),
+        KeyCode::F10 => Some("\x1b[21~"),
+        KeyCode::F11 => Some("\x1b[23~"),
+        KeyCode::F12 => Some("\x1b[24~"),
+        _ => None
+    };
+}
+
+fn compose(dead_key: char, c: char) -> Option<char> {
+    let (_, bases, results) = COMPOSITIONS.iter().find(|(dead_keys, _, _)| dead_keys.contains(dead_key))?;
+    return bases.chars().position(|base| base == c).and_then(|index| results.chars().nth(index));
+}
@@ -2,25 +2,42 @@ use crate::device::terminal::Terminal;
 use graphic::ansi::COLOR_TABLE_256;
 use graphic::buffered_lfb::BufferedLFB;
 use graphic::color::Color;
+use graphic::font::Font;
 use graphic::lfb::LFB;
-use graphic::{color, lfb};
+use graphic::color;
 use stream::{InputStream, OutputStream};
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::vec;
 use alloc::vec::Vec;
 use anstyle_parse::{Params, ParamsIter, Parser, Perform, Utf8Parser};
 use core::cell::RefCell;
+use core::cmp::min;
 use core::mem::size_of;
 use core::ptr;
-use pc_keyboard::layouts::{AnyLayout, De105Key};
-use pc_keyboard::{DecodedKey, HandleControl, Keyboard, ScancodeSet1};
-use spin::Mutex;
-use crate::{ps2_devices, scheduler, speaker};
+use syscall::input::Modifiers;
+use syscall::ioctl::{KeyRepeat, KeyboardLayout, WindowSize};
+use crate::device::keymap::Keymap;
+use crate::fs::Result;
+use spin::Once;
+use crate::sync::{Mutex, MutexGuard};
+use crate::device::speaker;
+use crate::device::speaker::Note;
+use crate::{ps2_devices, scheduler, try_ps2_devices};
+use crate::process::wait_queue::Waiter;
 
 const CURSOR: char = if let Some(cursor) = char::from_u32(0x2588) { cursor } else { '_' };
 const TAB_SPACES: u16 = 8;
+/// Number of screens, which are kept in the scrollback buffer of each console.
+const SCROLLBACK_SCREENS: usize = 8;
+/// Played on the PC speaker for the bell character (without blocking the terminal).
+const BELL: [Note; 2] = [Note { frequency: 440, duration_ms: 250 }, Note { frequency: 880, duration_ms: 250 }];
 
 struct CursorState {
     pos: (u16, u16),
     saved_pos: (u16, u16),
+    /// Hidden cursors are not blinked by the `CursorThread` (see ESC[?25l).
+    hidden: bool,
 }
 
 struct ColorState {
@@ -38,22 +55,41 @@ struct ColorState {
 struct DisplayState {
     size: (u16, u16),
     lfb: BufferedLFB,
+    font: Font,
     char_buffer: Vec<Character>,
+    /// Only the active console is drawn to the screen, while the others only draw into their buffers.
+    active: bool,
+    /// Lines, which have been scrolled off the top of the screen (oldest first).
+    scrollback: VecDeque<Vec<Character>>,
+    /// Number of lines, the view is scrolled back into `scrollback` (0 shows the current output).
+    scroll_offset: usize,
 }
 
+/// A virtual console on the framebuffer. Each console has its own buffer, which is shown once it becomes active (see `crate::switch_console()`).
 pub struct LFBTerminal {
+    console: usize,
     display: Mutex<DisplayState>,
     cursor: Mutex<CursorState>,
     color: Mutex<ColorState>,
     parser: Mutex<RefCell<Parser>>,
-    decoder: Mutex<Keyboard<AnyLayout, ScancodeSet1>>,
+    /// UTF-8 encoded characters, which have been decoded, but not read yet.
+    /// Keyboard input is only decoded by the active console, so that typed characters stay with the console they have been typed on.
+    pending_input: Mutex<VecDeque<u8>>,
 }
 
+/// Blinks the cursor of the active console.
 pub struct CursorThread {
-    terminal: &'static LFBTerminal,
+    consoles: &'static [LFBTerminal],
     visible: bool,
 }
 
+/// Shared by all consoles, since the keyboard is.
+static KEYMAP: Once<Mutex<Keymap>> = Once::new();
+
+fn keymap() -> &'static Mutex<Keymap> {
+    return KEYMAP.call_once(|| Mutex::new(Keymap::new(KeyboardLayout::German)));
+}
+
 #[derive(Copy, Clone)]
 struct Character {
     value: char,
@@ -63,7 +99,7 @@ struct Character {
 
 impl CursorState {
     pub const fn new() -> Self {
-        Self { pos: (0, 0), saved_pos: (0, 0) }
+        Self { pos: (0, 0), saved_pos: (0, 0), hidden: false }
     }
 }
 
@@ -75,10 +111,11 @@ impl ColorState {
 }
 
 impl DisplayState {
-    pub fn new(buffer: *mut u8, pitch: u32, width: u32, height: u32, bpp: u8) -> Self {
+    pub fn new(buffer: *mut u8, pitch: u32, width: u32, height: u32, bpp: u8, active: bool) -> Self {
         let raw_lfb = LFB::new(buffer, pitch, width, height, bpp);
         let mut lfb = BufferedLFB::new(raw_lfb);
-        let size = ((width / lfb::CHAR_WIDTH) as u16, (height / lfb::CHAR_HEIGHT) as u16);
+        let font = Font::builtin(1);
+        let size = ((width / font.width()) as u16, (height / font.height()) as u16);
 
         let mut char_buffer = Vec::with_capacity(size.0 as usize * size.1 as usize * size_of::<Character>());
         for _ in 0..char_buffer.capacity() {
@@ -86,29 +123,94 @@ impl DisplayState {
         }
 
         lfb.lfb().clear();
-        lfb.flush();
+        let mut display = Self { size, lfb, font, char_buffer, active, scrollback: VecDeque::new(), scroll_offset: 0 };
+        display.flush();
+
+        return display;
+    }
+
+    /// Width and height of a character on the screen in pixels.
+    fn char_size(&self) -> (u32, u32) {
+        return (self.font.width(), self.font.height());
+    }
+
+    /// Copy the changed area of the buffer to the screen, unless the console is inactive or scrolled back.
+    fn flush(&mut self) {
+        if self.active && self.scroll_offset == 0 {
+            self.lfb.flush();
+        }
+    }
 
-        Self { size, lfb, char_buffer }
+    /// Return to the current output, if the view is scrolled back.
+    fn reset_view(&mut self) {
+        if self.scroll_offset > 0 {
+            self.scroll_offset = 0;
+            self.lfb.invalidate();
+            self.flush();
+        }
+    }
+
+    /// Move the top line of the screen into the scrollback buffer, dropping the oldest line if it is full.
+    fn save_top_line(&mut self) {
+        let columns = self.size.0 as usize;
+        let mut line = if self.scrollback.len() >= SCROLLBACK_SCREENS * self.size.1 as usize {
+            self.scrollback.pop_front().unwrap()
+        } else {
+            Vec::with_capacity(columns)
+        };
+
+        line.clear();
+        line.extend_from_slice(&self.char_buffer[..columns]);
+        self.scrollback.push_back(line);
+    }
+
+    /// Draw the screen from the scrollback buffer and the character buffer according to `scroll_offset`.
+    /// Drawing goes directly to the screen, so that the buffer keeps the current output for `reset_view()`.
+    fn draw_scrollback(&mut self) {
+        let (char_width, char_height) = self.char_size();
+        let columns = self.size.0 as usize;
+        let first = self.scrollback.len() - self.scroll_offset;
+
+        for row in 0..self.size.1 as usize {
+            let line = match self.scrollback.get(first + row) {
+                Some(line) => &line[..],
+                None => {
+                    let start = (first + row - self.scrollback.len()) * columns;
+                    &self.char_buffer[start..start + columns]
+                }
+            };
+
+            for (column, character) in line.iter().enumerate() {
+                let value = if character.value == '\0' { ' ' } else { character.value };
+                self.lfb.direct_lfb().draw_glyph(&self.font, column as u32 * char_width, row as u32 * char_height, &character.fg_color, &character.bg_color, value);
+            }
+        }
     }
 }
 
 impl CursorThread {
-    pub const fn new(terminal: &'static LFBTerminal) -> Self {
+    pub const fn new(consoles: &'static [LFBTerminal]) -> Self {
         Self {
-            terminal,
+            consoles,
             visible: true,
         }
     }
 
     pub fn run(&mut self) {
         loop {
-            {
-                let mut display = self.terminal.display.lock();
-                let cursor = self.terminal.cursor.lock();
+            for terminal in self.consoles {
+                let mut display = terminal.display.lock();
+                if !display.active || display.scroll_offset > 0 {
+                    continue;
+                }
+
+                let display = &mut *display;
+                let cursor = terminal.cursor.lock();
                 let character = display.char_buffer[(cursor.pos.1 * display.size.0 + cursor.pos.0) as usize];
+                let (char_width, char_height) = display.char_size();
 
-                display.lfb.direct_lfb().draw_char(cursor.pos.0 as u32 * lfb::CHAR_WIDTH, cursor.pos.1 as u32 * lfb::CHAR_HEIGHT,
-                    &character.fg_color, &character.bg_color, if self.visible { character.value } else { CURSOR });
+                display.lfb.direct_lfb().draw_glyph(&display.font, cursor.pos.0 as u32 * char_width, cursor.pos.1 as u32 * char_height,
+                    &character.fg_color, &character.bg_color, if self.visible || cursor.hidden { character.value } else { CURSOR });
                 self.visible = !self.visible;
             }
 
@@ -122,54 +224,27 @@ unsafe impl Sync for LFBTerminal {}
 
 impl OutputStream for LFBTerminal {
     fn write_byte(&self, b: u8) {
-        let parser = self.parser.lock().clone();
-        // advance() passes a mutable terminal reference to methods in 'Perform' trait,
-        // but for LFBTerminal, none of these methods actually need a mutable reference,
-        // so it is safe to just construct a mutable reference here.
-        unsafe { parser.borrow_mut().advance(ptr::from_ref(self).cast_mut().as_mut().unwrap(), b); }
-        self.parser.lock().swap(&parser);
+        self.write_bytes(&[b]);
     }
 
     fn write_str(&self, string: &str) {
-        let parser = self.parser.lock().clone();
-        for b in string.bytes() {
-            // advance() passes a mutable terminal reference to methods in 'Perform' trait,
-            // but for LFBTerminal, none of these methods actually need a mutable reference,
-            // so it is safe to just construct a mutable reference here.
-            unsafe { parser.borrow_mut().advance(ptr::from_ref(self).cast_mut().as_mut().unwrap(), b); }
-        }
-
-        self.parser.lock().swap(&parser);
+        self.write_bytes(string.as_bytes());
     }
 }
 
 impl InputStream for LFBTerminal {
     fn read_byte(&self) -> i16 {
-        let keyboard = ps2_devices().keyboard();
-        let read_byte;
-
         loop {
-            let mut decoder = self.decoder.lock();
-            let scancode = keyboard.read_byte();
-            if scancode == -1 {
-                panic!("Keyboard stream closed!");
-            }
+            // Registered before checking for input, so that a scancode received in the meantime wakes us up
+            let waiter = Waiter::new();
+            ps2_devices().keyboard().input_available().register(&waiter);
 
-            if let Ok(Some(event)) = decoder.add_byte(scancode as u8) {
-                if let Some(key) = decoder.process_keyevent(event) {
-                    match key {
-                        DecodedKey::Unicode(c) => {
-                            read_byte = c;
-                            break;
-                        }
-                        _ => {}
-                    }
-                }
+            if let Some(byte) = self.decoded_input().pop_front() {
+                return byte as i16;
             }
-        }
 
-        self.write_byte(read_byte as u8);
-        return read_byte as i16;
+            scheduler().block_on(&waiter, None);
+        }
     }
 }
 
@@ -181,18 +256,189 @@ impl Terminal for LFBTerminal {
 
         LFBTerminal::clear_screen(&mut display, &mut color);
         LFBTerminal::position(&mut display, &mut cursor, &mut color, (0, 0));
+        display.flush();
+    }
+
+    fn has_input(&self) -> bool {
+        return !self.decoded_input().is_empty();
+    }
+
+    fn window_size(&self) -> WindowSize {
+        let size = self.display.lock().size;
+        return WindowSize { columns: size.0, rows: size.1 };
+    }
+
+    fn keyboard_layout(&self) -> Result<KeyboardLayout> {
+        return Ok(keymap().lock().layout());
+    }
+
+    fn set_keyboard_layout(&self, layout: KeyboardLayout) -> Result<()> {
+        keymap().lock().set_layout(layout);
+        return Ok(());
+    }
+
+    fn keyboard_modifiers(&self) -> Result<Modifiers> {
+        return Ok(ps2_devices().keyboard().modifiers());
+    }
+
+    fn key_repeat(&self) -> Result<KeyRepeat> {
+        return Ok(ps2_devices().keyboard().key_repeat());
+    }
+
+    fn set_key_repeat(&self, repeat: KeyRepeat) -> Result<()> {
+        return ps2_devices().keyboard().set_key_repeat(repeat);
     }
 }
 
 impl LFBTerminal {
-    pub fn new(buffer: *mut u8, pitch: u32, width: u32, height: u32, bpp: u8) -> Self {
+    /// Create the virtual console with the number `console`. Only the first console is active initially.
+    pub fn new(console: usize, buffer: *mut u8, pitch: u32, width: u32, height: u32, bpp: u8) -> Self {
         Self {
-            display: Mutex::new(DisplayState::new(buffer, pitch, width, height, bpp)),
+            console,
+            display: Mutex::new(DisplayState::new(buffer, pitch, width, height, bpp, console == 0)),
             cursor: Mutex::new(CursorState::new()),
             color: Mutex::new(ColorState::new()),
             parser: Mutex::new(RefCell::new(Parser::<Utf8Parser>::new())),
-            decoder: Mutex::new(Keyboard::new(ScancodeSet1::new(), AnyLayout::De105Key(De105Key), HandleControl::Ignore))
+            pending_input: Mutex::new(VecDeque::new())
+        }
+    }
+
+    /// Lock the pending input, after decoding received scancodes until it contains a byte of input.
+    /// Inactive consoles do not consume scancodes, but wait until they are switched to (see `set_active()`).
+    /// Scancodes, which do not result in a character (e.g. key releases), are consumed here.
+    fn decoded_input(&self) -> MutexGuard<'_, VecDeque<u8>> {
+        let active = self.is_active();
+        let mut pending = self.pending_input.lock();
+        if active {
+            let keyboard = ps2_devices().keyboard();
+            while pending.is_empty() {
+                match keyboard.try_read_byte() {
+                    Some(scancode) => self.decode(scancode, &mut pending),
+                    None => break
+                }
+            }
+        }
+
+        return pending;
+    }
+
+    /// Readers of all consoles wait for keyboard input (see `read_byte()`), so they also wait there for other input.
+    fn notify_readers() {
+        if let Some(ps2) = try_ps2_devices() {
+            ps2.keyboard().input_available().notify_all();
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        return self.display.lock().active;
+    }
+
+    /// Show this console on the screen (redrawing it from its buffer) or stop drawing it to the screen.
+    pub fn set_active(&self, active: bool) {
+        let mut display = self.display.lock();
+        display.active = active;
+        display.scroll_offset = 0;
+        display.lfb.invalidate();
+        display.flush();
+        drop(display);
+
+        // Readers of an inactive console are waiting to be switched to
+        if active {
+            LFBTerminal::notify_readers();
+        }
+    }
+
+    /// Scroll the view `lines` further back into the scrollback buffer (or forward for negative values).
+    /// The view returns to the current output as soon as something is written.
+    pub fn scroll_back(&self, lines: isize) {
+        let mut display = self.display.lock();
+        let offset = (display.scroll_offset as isize + lines).clamp(0, display.scrollback.len() as isize) as usize;
+        if !display.active || offset == display.scroll_offset {
+            return;
+        }
+
+        display.scroll_offset = offset;
+        if offset == 0 {
+            display.lfb.invalidate();
+            display.flush();
+        } else {
+            display.draw_scrollback();
+        }
+    }
+
+    /// Switch to another font, which changes the number of columns and rows. The text is kept as far as it fits,
+    /// with the line containing the cursor staying visible. The scrollback buffer is cleared, since its lines have the old width.
+    pub fn set_font(&self, font: Font) {
+        let mut display = self.display.lock();
+        let mut cursor = self.cursor.lock();
+        let color = self.color.lock();
+
+        let old_size = display.size;
+        let (width, height) = (display.lfb.lfb().width(), display.lfb.lfb().height());
+        let size = ((width / font.width()) as u16, (height / font.height()) as u16);
+        if size.0 == 0 || size.1 == 0 {
+            return;
+        }
+
+        // Drop lines from the top, if the cursor would be below the new last row
+        let skip = (cursor.pos.1 + 1).saturating_sub(size.1);
+        let mut char_buffer = vec![Character { value: '\0', fg_color: color.fg_color, bg_color: color.bg_color }; size.0 as usize * size.1 as usize];
+        for row in 0..min(old_size.1 - skip, size.1) as usize {
+            let columns = min(old_size.0, size.0) as usize;
+            let start = (row + skip as usize) * old_size.0 as usize;
+            char_buffer[row * size.0 as usize..row * size.0 as usize + columns].copy_from_slice(&display.char_buffer[start..start + columns]);
         }
+
+        cursor.pos = (min(cursor.pos.0, size.0 - 1), cursor.pos.1 - skip);
+        cursor.saved_pos = (min(cursor.saved_pos.0, size.0 - 1), min(cursor.saved_pos.1, size.1 - 1));
+        display.font = font;
+        display.size = size;
+        display.char_buffer = char_buffer;
+        display.scrollback.clear();
+        display.scroll_offset = 0;
+
+        // Redraw the whole screen with the new font
+        display.lfb.fill_rect(0, 0, width, height, &color.bg_color);
+        let display = &mut *display;
+        let (char_width, char_height) = display.char_size();
+        for (index, character) in display.char_buffer.iter().enumerate() {
+            let value = if character.value == '\0' { ' ' } else { character.value };
+            let (column, row) = ((index % size.0 as usize) as u32, (index / size.0 as usize) as u32);
+            display.lfb.draw_char(&display.font, column * char_width, row * char_height, &character.fg_color, &character.bg_color, value);
+        }
+
+        display.lfb.invalidate();
+        display.flush();
+    }
+
+    /// Draw `bytes` into the buffer and copy the changed area to the screen afterward,
+    /// so that a long output (e.g. scrolling many lines) only updates the screen once.
+    fn write_bytes(&self, bytes: &[u8]) {
+        let cursor_pos = self.cursor.lock().pos;
+        self.display.lock().reset_view();
+
+        let parser = self.parser.lock().clone();
+        for b in bytes {
+            // advance() passes a mutable terminal reference to methods in 'Perform' trait,
+            // but for LFBTerminal, none of these methods actually need a mutable reference,
+            // so it is safe to just construct a mutable reference here.
+            unsafe { parser.borrow_mut().advance(ptr::from_ref(self).cast_mut().as_mut().unwrap(), *b); }
+        }
+        self.parser.lock().swap(&parser);
+
+        // The cursor is drawn directly to the screen, so it is removed from its previous position
+        let mut display = self.display.lock();
+        let (char_width, char_height) = display.char_size();
+        display.lfb.mark_dirty(cursor_pos.0 as u32 * char_width, cursor_pos.1 as u32 * char_height, char_width, char_height);
+        display.flush();
+    }
+
+    /// Feed a scancode into the keyboard decoder and append the typed characters (if any) to `pending`.
+    fn decode(&self, scancode: u8, pending: &mut VecDeque<u8>) {
+        keymap().lock().decode(scancode, |c| {
+            let mut buffer = [0; 4];
+            pending.extend(c.encode_utf8(&mut buffer).as_bytes());
+        });
     }
 
     fn print_char(&self, c: char) {
@@ -230,11 +476,14 @@ impl LFBTerminal {
     }
 
     fn print_char_at(display: &mut DisplayState, color: &mut ColorState, c: char, pos: (u16, u16)) -> bool {
-        display.lfb.lfb().draw_char(pos.0 as u32 * lfb::CHAR_WIDTH, pos.1 as u32 * lfb::CHAR_HEIGHT, &color.fg_color, &color.bg_color, c)
-            && display.lfb.direct_lfb().draw_char(pos.0 as u32 * lfb::CHAR_WIDTH, pos.1 as u32 * lfb::CHAR_HEIGHT, &color.fg_color, &color.bg_color, c)
+        let (char_width, char_height) = display.char_size();
+        display.lfb.draw_char(&display.font, pos.0 as u32 * char_width, pos.1 as u32 * char_height, &color.fg_color, &color.bg_color, c)
     }
 
     fn scroll_up(display: &mut DisplayState, color: &mut ColorState) {
+        let (char_width, char_height) = display.char_size();
+        display.save_top_line();
+
         unsafe {
             let char_ptr = display.char_buffer.as_ptr() as *mut u8;
             char_ptr.copy_from(char_ptr.offset(display.size.0 as isize * size_of::<Character>() as isize),
@@ -249,9 +498,8 @@ impl LFBTerminal {
         });
 
         let size = display.size;
-        display.lfb.lfb().scroll_up(lfb::CHAR_HEIGHT);
-        display.lfb.lfb().fill_rect(0, (size.1 - 1) as u32 * lfb::CHAR_HEIGHT, size.0 as u32 * lfb::CHAR_WIDTH, lfb::CHAR_HEIGHT, &color.bg_color);
-        display.lfb.flush();
+        display.lfb.scroll_up(char_height);
+        display.lfb.fill_rect(0, (size.1 - 1) as u32 * char_height, size.0 as u32 * char_width, char_height, &color.bg_color);
     }
 
     fn position(display: &mut DisplayState, cursor: &mut CursorState, color: &mut ColorState, pos: (u16, u16)) {
@@ -264,9 +512,7 @@ impl LFBTerminal {
     }
 
     fn handle_bell() {
-        let mut speaker = speaker().lock();
-        speaker.play(440, 250);
-        speaker.play(880, 250);
+        speaker::play_melody(&BELL);
     }
 
     fn handle_tab(display: &mut DisplayState, cursor: &mut CursorState, color: &mut ColorState) {
@@ -278,9 +524,10 @@ impl LFBTerminal {
     }
 
     fn clear_screen(display: &mut DisplayState, color: &mut ColorState) {
+        let (char_width, char_height) = display.char_size();
         // Clear screen
         let size = display.size;
-        display.lfb.lfb().fill_rect(0, 0, size.0 as u32 * lfb::CHAR_WIDTH, size.1 as u32 * lfb::CHAR_HEIGHT, &color.bg_color);
+        display.lfb.fill_rect(0, 0, size.0 as u32 * char_width, size.1 as u32 * char_height, &color.bg_color);
 
         // Clear character buffer
         display.char_buffer.iter_mut().for_each(|item| {
@@ -288,19 +535,18 @@ impl LFBTerminal {
             item.fg_color = color.fg_color;
             item.bg_color = color.bg_color;
         });
-
-        display.lfb.flush();
     }
 
     fn clear_screen_to_cursor(display: &mut DisplayState, cursor: &mut CursorState, color: &mut ColorState) {
+        let (char_width, char_height) = display.char_size();
         let pos = cursor.pos;
         let size = display.size;
 
         // Clear from start of line to cursor
-        display.lfb.lfb().fill_rect(0, pos.1 as u32 * lfb::CHAR_HEIGHT, pos.0 as u32 * lfb::CHAR_WIDTH, lfb::CHAR_HEIGHT, &color.bg_color);
+        display.lfb.fill_rect(0, pos.1 as u32 * char_height, pos.0 as u32 * char_width, char_height, &color.bg_color);
 
         // Clear from start of screen to line before cursor
-        display.lfb.lfb().fill_rect(0, 0, size.0 as u32 * lfb::CHAR_WIDTH, pos.1 as u32 * lfb::CHAR_HEIGHT, &color.bg_color);
+        display.lfb.fill_rect(0, 0, size.0 as u32 * char_width, pos.1 as u32 * char_height, &color.bg_color);
 
         // Clear character buffer from beginning of screen to cursor
         display.char_buffer.iter_mut().enumerate()
@@ -310,19 +556,18 @@ impl LFBTerminal {
                 item.1.fg_color = color.fg_color;
                 item.1.bg_color = color.bg_color;
             });
-
-        display.lfb.flush();
     }
 
     fn clear_screen_from_cursor(display: &mut DisplayState, cursor: &mut CursorState, color: &mut ColorState) {
+        let (char_width, char_height) = display.char_size();
         let pos = cursor.pos;
         let size = display.size;
 
         // Clear from cursor to end of line
-        display.lfb.lfb().fill_rect(pos.0 as u32 * lfb::CHAR_WIDTH, pos.1 as u32 * lfb::CHAR_HEIGHT, (size.0 - pos.0) as u32 * lfb::CHAR_WIDTH, lfb::CHAR_HEIGHT, &color.bg_color);
+        display.lfb.fill_rect(pos.0 as u32 * char_width, pos.1 as u32 * char_height, (size.0 - pos.0) as u32 * char_width, char_height, &color.bg_color);
 
         // Clear from next line to end of screen
-        display.lfb.lfb().fill_rect(0, (pos.1 + 1) as u32 * lfb::CHAR_HEIGHT, size.0 as u32 * lfb::CHAR_WIDTH, (size.1 - pos.1 - 1) as u32 * lfb::CHAR_HEIGHT, &color.bg_color);
+        display.lfb.fill_rect(0, (pos.1 + 1) as u32 * char_height, size.0 as u32 * char_width, (size.1 - pos.1 - 1) as u32 * char_height, &color.bg_color);
 
         // Clear character buffer from cursor to end of screen
         display.char_buffer.iter_mut().skip((pos.1 * size.0 + pos.0) as usize)
@@ -331,34 +576,32 @@ impl LFBTerminal {
                 item.fg_color = color.fg_color;
                 item.bg_color = color.bg_color;
             });
-
-        display.lfb.flush();
     }
 
     fn clear_line(display: &mut DisplayState, cursor: &mut CursorState, color: &mut ColorState) {
+        let (char_width, char_height) = display.char_size();
         let pos = cursor.pos;
         let size = display.size;
 
         // Clear line in lfb
-        display.lfb.lfb().fill_rect(0, pos.1 as u32 * lfb::CHAR_HEIGHT, size.0 as u32 * lfb::CHAR_WIDTH, lfb::CHAR_HEIGHT, &color.bg_color);
+        display.lfb.fill_rect(0, pos.1 as u32 * char_height, size.0 as u32 * char_width, char_height, &color.bg_color);
         // Clear line in character buffer
         display.char_buffer.iter_mut().skip((pos.1 * size.0) as usize).enumerate()
             .filter(|item| item.0 < size.0 as usize)
             .for_each(|item| {
-                item.1.value = 'a';
+                item.1.value = '\0';
                 item.1.fg_color = color.fg_color;
                 item.1.bg_color = color.bg_color;
             });
-
-        display.lfb.flush();
     }
 
     fn clear_line_to_cursor(display: &mut DisplayState, cursor: &mut CursorState, color: &mut ColorState) {
+        let (char_width, char_height) = display.char_size();
         let pos = cursor.pos;
         let size = display.size;
 
         // Clear line in lfb
-        display.lfb.lfb().fill_rect(0, pos.1 as u32 * lfb::CHAR_HEIGHT, pos.0 as u32 * lfb::CHAR_WIDTH, lfb::CHAR_HEIGHT, &color.bg_color);
+        display.lfb.fill_rect(0, pos.1 as u32 * char_height, pos.0 as u32 * char_width, char_height, &color.bg_color);
 
         // Clear line in character buffer
         display.char_buffer.iter_mut().skip((pos.1 * size.0) as usize).enumerate()
@@ -368,16 +611,15 @@ impl LFBTerminal {
                 item.1.fg_color = color.fg_color;
                 item.1.bg_color = color.bg_color;
             });
-
-        display.lfb.flush();
     }
 
     fn clear_line_from_cursor(display: &mut DisplayState, cursor: &mut CursorState, color: &mut ColorState) {
+        let (char_width, char_height) = display.char_size();
         let pos = cursor.pos;
         let size = display.size;
 
         // Clear line in lfb
-        display.lfb.lfb().fill_rect(pos.0 as u32 * lfb::CHAR_WIDTH, pos.1 as u32 * lfb::CHAR_HEIGHT, (size.0 - pos.0) as u32 * lfb::CHAR_WIDTH, lfb::CHAR_HEIGHT, &color.bg_color);
+        display.lfb.fill_rect(pos.0 as u32 * char_width, pos.1 as u32 * char_height, (size.0 - pos.0) as u32 * char_width, char_height, &color.bg_color);
 
         // Clear line in character buffer
         display.char_buffer.iter_mut().skip((pos.1 * size.0 + pos.0) as usize).enumerate()
@@ -387,8 +629,6 @@ impl LFBTerminal {
                 item.1.fg_color = color.fg_color;
                 item.1.bg_color = color.bg_color;
             });
-
-        display.lfb.flush();
     }
 
     fn handle_ansi_color(color: &mut ColorState, params: &Params) {
@@ -406,7 +646,11 @@ impl LFBTerminal {
                         color.fg_bright = false;
                     }
                 }
-                40..=49 => {
+                49 => {
+                    color.bg_base_color = color::BLACK;
+                    color.bg_bright = false;
+                }
+                40..=48 => {
                     if let Some(col) = ansi_color(code - 40, &mut iter) {
                         color.bg_base_color = col;
                         color.bg_bright = false;
@@ -487,84 +731,36 @@ impl LFBTerminal {
     }
 
     fn handle_ansi_cursor_sequence(display: &mut DisplayState, cursor: &mut CursorState, color: &mut ColorState, code: u8, params: &Params) {
-        let mut iter = params.iter();
+        let (columns, rows) = display.size;
+        let (column, row) = cursor.pos;
+        // Counts default to 1, while positions start at 1
+        let count = ansi_param(params, 0, 1);
+
         match code {
-            0x41 => {
-                // Move cursor up
-                let param = iter.next();
-                if param.is_some() {
-                    let y_move = param.unwrap()[0];
-                    let row = cursor.pos.1 - if y_move == 0 { 1 } else { y_move };
-                    LFBTerminal::position(display, cursor, color, (cursor.pos.0, if row > 0 { row } else { 0 }));
-                }
-            }
-            0x42 => {
-                // Move cursor down
-                let param = iter.next();
-                if param.is_some() {
-                    let y_move = param.unwrap()[0];
-                    let row = cursor.pos.1 + if y_move == 0 { 1 } else { y_move };
-                    LFBTerminal::position(display, cursor, color, (cursor.pos.0, if row < display.size.1 { row } else { display.size.1 - 1 }));
-                };
-            }
-            0x43 => {
-                // Move cursor right
-                let param = iter.next();
-                if param.is_some() {
-                    let x_move = param.unwrap()[0];
-                    let column = cursor.pos.0 + if x_move == 0 { 1 } else { x_move };
-                    LFBTerminal::position(display, cursor, color, (if column < display.size.0 { column } else { display.size.0 - 1 }, cursor.pos.1));
-                };
-            }
-            0x44 => {
-                // Move cursor left
-                let param = iter.next();
-                if param.is_some() {
-                    let x_move = param.unwrap()[0];
-                    let column = cursor.pos.0 - if x_move == 0 { 1 } else { x_move };
-                    LFBTerminal::position(display, cursor, color, (if column > 0 { column } else { 0 }, cursor.pos.1));
-                };
-            }
-            0x45 => {
-                // Move cursor to start of next line
-                let param = iter.next();
-                if param.is_some() {
-                    let row = cursor.pos.1 + param.unwrap()[0] + 1;
-                    LFBTerminal::position(display, cursor, color, (0, if row < display.size.1 { row } else { display.size.1 - 1 }));
-                };
-            }
-            0x46 => {
-                // Move cursor to start of previous line
-                let param = iter.next();
-                if param.is_some() {
-                    let row = cursor.pos.1 - param.unwrap()[0] - 1;LFBTerminal::position(display, cursor, color, (0, if row > 0 { row } else { 0 }));
-                };
-            }
-            0x47 => {
-                // Move cursor to column
-                let param = iter.next();
-                if param.is_some() {
-                    let column = param.unwrap()[0];
-                    LFBTerminal::position(display, cursor, color, (if column < display.size.0 { column } else { display.size.0 - 1 }, cursor.pos.1));
-                }
-            }
+            // Move cursor up
+            0x41 => LFBTerminal::position(display, cursor, color, (column, row.saturating_sub(count))),
+            // Move cursor down
+            0x42 => LFBTerminal::position(display, cursor, color, (column, row.saturating_add(count).min(rows - 1))),
+            // Move cursor right
+            0x43 => LFBTerminal::position(display, cursor, color, (column.saturating_add(count).min(columns - 1), row)),
+            // Move cursor left
+            0x44 => LFBTerminal::position(display, cursor, color, (column.saturating_sub(count), row)),
+            // Move cursor to start of next line
+            0x45 => LFBTerminal::position(display, cursor, color, (0, row.saturating_add(count).min(rows - 1))),
+            // Move cursor to start of previous line
+            0x46 => LFBTerminal::position(display, cursor, color, (0, row.saturating_sub(count))),
+            // Move cursor to column
+            0x47 | 0x60 => LFBTerminal::position(display, cursor, color, ((count - 1).min(columns - 1), row)),
+            // Move cursor to row
+            0x64 => LFBTerminal::position(display, cursor, color, (column, (count - 1).min(rows - 1))),
             0x48 | 0x66 => {
-                // Set cursor position
-                let param1 = iter.next();
-                let param2 = iter.next();
-
-                if param1.is_some() && param2.is_some() {
-                    let column = param1.unwrap()[0];
-                    let row = param2.unwrap()[0];
-
-                    LFBTerminal::position(display, cursor, color, (if column > display.size.0 { display.size.0 - 1 } else { column }, if row > display.size.1 { display.size.1 - 1 } else { row }));
-                } else {
-                    LFBTerminal::position(display, cursor, color, (0, 0));
-                }
+                // Set cursor position (row first)
+                let column = ansi_param(params, 1, 1);
+                LFBTerminal::position(display, cursor, color, ((column - 1).min(columns - 1), (count - 1).min(rows - 1)));
             }
             0x73 => {
                 // Save cursor position
-                cursor.saved_pos = (cursor.pos.0, cursor.pos.1);
+                cursor.saved_pos = cursor.pos;
             }
             0x75 => {
                 // Restore cursor position
@@ -574,6 +770,14 @@ impl LFBTerminal {
         }
     }
 
+    /// Answer a device status report request (ESC[6n) by queueing the cursor position (ESC[row;columnR) as input.
+    fn report_cursor_position(&self) {
+        let pos = self.cursor.lock().pos;
+        let report = format!("\x1b[{};{}R", pos.1 + 1, pos.0 + 1);
+        self.pending_input.lock().extend(report.bytes());
+        LFBTerminal::notify_readers();
+    }
+
     fn handle_ansi_erase_sequence(display: &mut DisplayState, cursor: &mut CursorState, color: &mut ColorState, code: u8, params: &Params) {
         let mut iter = params.iter();
         let param = iter.next();
@@ -604,6 +808,14 @@ impl LFBTerminal {
     }
 }
 
+/// Return the parameter at `index` or `default`, if it is missing or 0.
+fn ansi_param(params: &Params, index: usize, default: u16) -> u16 {
+    return match params.iter().nth(index) {
+        Some(param) if param[0] > 0 => param[0],
+        _ => default
+    };
+}
+
 fn ansi_color(code: u16, iter: &mut ParamsIter) -> Option<Color> {
     match code {
         0 => Some(color::BLACK),
@@ -655,6 +867,11 @@ impl Perform for LFBTerminal {
     fn execute(&mut self, byte: u8) {
         match byte {
             0x07 => LFBTerminal::handle_bell(),
+            0x08 => {
+                // Backspace only moves the cursor (erasing is done by writing "\x08 \x08")
+                let mut cursor = self.cursor.lock();
+                cursor.pos.0 = cursor.pos.0.saturating_sub(1);
+            }
             0x09 => LFBTerminal::handle_tab(
                 &mut self.display.lock(),
                 &mut self.cursor.lock(),
@@ -673,14 +890,36 @@ impl Perform for LFBTerminal {
 
     fn osc_dispatch(&mut self, _params: &[&[u8]], _bell_terminated: bool) {}
 
-    fn csi_dispatch(&mut self, params: &Params, _intermediates: &[u8], _ignore: bool, action: u8) {
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], _ignore: bool, action: u8) {
         match action {
-            0x41..=0x48 | 0x66 | 0x6e | 0x73 | 0x75 => LFBTerminal::handle_ansi_cursor_sequence(&mut self.display.lock(), &mut self.cursor.lock(), &mut self.color.lock(), action, params),
+            // Show (ESC[?25h) or hide (ESC[?25l) the cursor
+            0x68 | 0x6c if intermediates == b"?" => {
+                if params.iter().any(|param| param[0] == 25) {
+                    self.cursor.lock().hidden = action == 0x6c;
+                }
+            }
+            0x6e if ansi_param(params, 0, 0) == 6 => self.report_cursor_position(),
+            0x41..=0x48 | 0x60 | 0x64 | 0x66 | 0x73 | 0x75 => LFBTerminal::handle_ansi_cursor_sequence(&mut self.display.lock(), &mut self.cursor.lock(), &mut self.color.lock(), action, params),
             0x4a | 0x4b => LFBTerminal::handle_ansi_erase_sequence(&mut self.display.lock(), &mut self.cursor.lock(), &mut self.color.lock(), action, params),
             0x6d => LFBTerminal::handle_ansi_color(&mut self.color.lock(), params),
             _ => {}
         }
     }
 
-    fn esc_dispatch(&mut self, _intermediates: &[u8], _ignore: bool, _byte: u8) {}
+    fn esc_dispatch(&mut self, _intermediates: &[u8], _ignore: bool, byte: u8) {
+        match byte {
+            // Save (ESC 7) and restore (ESC 8) cursor position
+            0x37 => {
+                let mut cursor = self.cursor.lock();
+                cursor.saved_pos = cursor.pos;
+            }
+            0x38 => {
+                let mut display = self.display.lock();
+                let mut cursor = self.cursor.lock();
+                let saved_pos = cursor.saved_pos;
+                LFBTerminal::position(&mut display, &mut cursor, &mut self.color.lock(), saved_pos);
+            }
+            _ => {}
+        }
+    }
 }
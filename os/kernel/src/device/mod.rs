@@ -1,9 +1,25 @@
 pub mod apic;
+pub mod ata;
+pub mod ahci;
+pub mod ac97;
+pub mod nvme;
+pub mod pci;
+pub mod pic;
 pub mod pit;
+pub mod power;
 pub mod ps2;
 pub mod qemu_cfg;
+pub mod input;
+pub mod keymap;
 pub mod speaker;
 #[macro_use]
 pub mod terminal;
+pub mod tty;
 pub mod lfb_terminal;
 pub mod serial;
+pub mod pseudo;
+pub mod rtl8139;
+pub mod framebuffer;
+pub mod compositor;
+pub mod virtio;
+pub mod usb;
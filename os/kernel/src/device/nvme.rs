@@ -0,0 +1,481 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicUsize};
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use log::{info, warn};
+use syscall::error::Errno;
+use x86_64::structures::paging::page::PageRange;
+use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::VirtAddr;
+use crate::block;
+use crate::block::{BlockDevice, Result};
+use crate::device::pci::driver::{PciDriver, PciId};
+use crate::device::pci::{msi, CommandFlag, PciDevice};
+use crate::interrupt::interrupt_handler::InterruptHandler;
+use crate::memory::{physical, MemorySpace, PAGE_SIZE};
+use crate::process::process::current_process;
+use crate::process::wait_queue::WaitQueue;
+use crate::sync::Mutex;
+use crate::{scheduler, timer};
+
+/// Number of entries in the admin and the I/O queues (each submission queue fills exactly one page).
+const QUEUE_SIZE: usize = 64;
+const SUBMISSION_ENTRY_SIZE: usize = 64;
+const COMPLETION_ENTRY_SIZE: usize = 16;
+/// Data is transferred via a bounce buffer of this size (further limited by the maximum transfer size of the controller).
+const BUFFER_PAGES: usize = 32;
+const COMMAND_TIMEOUT_MS: usize = 5000;
+
+// Offsets of the controller registers (located in memory space via BAR 0)
+const CAPABILITIES: u64 = 0x00;
+const CONFIGURATION: u64 = 0x14;
+const STATUS: u64 = 0x1c;
+const ADMIN_QUEUE_ATTRIBUTES: u64 = 0x24;
+const ADMIN_SUBMISSION_QUEUE: u64 = 0x28;
+const ADMIN_COMPLETION_QUEUE: u64 = 0x30;
+const DOORBELLS: u64 = 0x1000;
+
+const CONFIGURATION_ENABLE: u32 = 1 << 0;
+/// Submission and completion entry sizes as powers of two (64 and 16 bytes).
+const CONFIGURATION_ENTRY_SIZES: u32 = 6 << 16 | 4 << 20;
+const STATUS_READY: u32 = 1 << 0;
+const STATUS_FATAL: u32 = 1 << 1;
+
+const ADMIN_QUEUE: u16 = 0;
+const IO_QUEUE: u16 = 1;
+
+// Fields of the completion queue entry and the queue creation commands
+const COMPLETION_PHASE: u32 = 1 << 16;
+const COMPLETION_STATUS_SHIFT: u32 = 17;
+const QUEUE_PHYSICALLY_CONTIGUOUS: u32 = 1 << 0;
+const QUEUE_INTERRUPTS_ENABLED: u32 = 1 << 1;
+
+// Values for the CNS field of the identify command
+const IDENTIFY_NAMESPACE: u32 = 0x00;
+const IDENTIFY_CONTROLLER: u32 = 0x01;
+const IDENTIFY_ACTIVE_NAMESPACES: u32 = 0x02;
+
+#[derive(Copy, Clone)]
+#[repr(u8)]
+enum AdminCommand {
+    CreateSubmissionQueue = 0x01,
+    CreateCompletionQueue = 0x05,
+    Identify = 0x06,
+}
+
+#[derive(Copy, Clone)]
+#[repr(u8)]
+enum IoCommand {
+    Flush = 0x00,
+    Write = 0x01,
+    Read = 0x02,
+}
+
+/// Memory mapped registers of an NVMe controller.
+#[derive(Copy, Clone)]
+struct Registers {
+    base: u64,
+    /// Distance between doorbell registers in bytes.
+    doorbell_stride: u64,
+}
+
+/// A submission queue with its associated completion queue. Commands are executed one at a time.
+struct QueuePair {
+    state: Mutex<QueueState>,
+    /// Set, if the queue has an interrupt vector (otherwise, it is polled).
+    interrupts: AtomicBool,
+    /// Notified by the interrupt handler, when a completion entry has been posted.
+    completion_posted: WaitQueue,
+}
+
+struct QueueState {
+    id: u16,
+    registers: Registers,
+    submission: u64,
+    completion: u64,
+    tail: usize,
+    head: usize,
+    /// Completion entries are new, if their phase bit matches this value (it is inverted on each wrap around).
+    phase: bool,
+    next_command_id: u16,
+}
+
+struct Controller {
+    admin: Arc<QueuePair>,
+    io: Arc<QueuePair>,
+    /// Bounce buffer (physical memory is identity mapped, so its address can be given to the controller directly).
+    buffer: u64,
+    /// Page with pointers to the pages of the bounce buffer, used for transfers covering more than two pages.
+    prp_list: u64,
+    /// Maximum number of bytes per command.
+    max_transfer: usize,
+    /// Set while a thread uses the bounce buffer (see `lock_buffer()`).
+    buffer_busy: AtomicBool,
+    buffer_idle: WaitQueue,
+}
+
+/// Serializes the use of the bounce buffer. Commands block until they complete, so waiting threads block as well instead of spinning.
+struct BufferGuard<'a> {
+    controller: &'a Controller,
+}
+
+/// Namespace of an NVMe controller (e.g. QEMU's '-device nvme'), accessed via the I/O queue of its controller.
+pub struct NvmeNamespace {
+    controller: Arc<Controller>,
+    id: u32,
+    block_size: usize,
+    block_count: u64,
+}
+
+struct NvmeInterruptHandler {
+    queue: Arc<QueuePair>,
+}
+
+/// Driver for NVMe controllers, which registers their namespaces as block devices ('nvme0n1', 'nvme0n2', ...).
+pub static DRIVER: PciDriver = PciDriver { name: "nvme", ids: &[PciId::class_with_prog_if(0x01, 0x08, 0x02)], probe };
+
+/// Number of controllers, which have been probed successfully (used for naming their namespaces).
+static CONTROLLER_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+fn probe(device: &Arc<PciDevice>) -> Result<()> {
+    let controller = Arc::new(Controller::new(device)?);
+    let namespaces = controller.identify_namespaces()?;
+    let index = CONTROLLER_COUNT.fetch_add(1, Relaxed);
+
+    for (id, block_size, block_count) in namespaces {
+        let name = format!("nvme{}n{}", index, id);
+        info!("Found NVMe namespace [{}]: Block size: [{}], Blocks: [{}]", name, block_size, block_count);
+
+        let namespace = NvmeNamespace { controller: Arc::clone(&controller), id, block_size, block_count };
+        if let Err(err) = block::register(&name, Arc::new(namespace)) {
+            warn!("Failed to register NVMe namespace [{}] (Error: {:?})", name, err);
+        }
+    }
+
+    return Ok(());
+}
+
+impl Registers {
+    fn read_u32(&self, offset: u64) -> u32 {
+        return unsafe { ((self.base + offset) as *const u32).read_volatile() };
+    }
+
+    fn read_u64(&self, offset: u64) -> u64 {
+        return unsafe { ((self.base + offset) as *const u64).read_volatile() };
+    }
+
+    fn write_u32(&self, offset: u64, value: u32) {
+        unsafe { ((self.base + offset) as *mut u32).write_volatile(value); }
+    }
+
+    fn write_u64(&self, offset: u64, value: u64) {
+        unsafe { ((self.base + offset) as *mut u64).write_volatile(value); }
+    }
+
+    fn submission_doorbell(&self, queue: u16) -> u64 {
+        return DOORBELLS + (2 * queue as u64) * self.doorbell_stride;
+    }
+
+    fn completion_doorbell(&self, queue: u16) -> u64 {
+        return DOORBELLS + (2 * queue as u64 + 1) * self.doorbell_stride;
+    }
+
+    /// Wait until the ready bit of the status register has the value `ready`.
+    fn wait_ready(&self, ready: bool, timeout_ms: usize) -> Result<()> {
+        let start = timer().read().systime_ms();
+        while (self.read_u32(STATUS) & STATUS_READY != 0) != ready {
+            if self.read_u32(STATUS) & STATUS_FATAL != 0 || timer().read().systime_ms() - start > timeout_ms {
+                return Err(Errno::IoError);
+            }
+
+            core::hint::spin_loop();
+        }
+
+        return Ok(());
+    }
+}
+
+impl QueuePair {
+    fn new(id: u16, registers: Registers) -> Self {
+        let submission = alloc_zeroed_page();
+        let completion = alloc_zeroed_page();
+        let state = QueueState { id, registers, submission, completion, tail: 0, head: 0, phase: true, next_command_id: 0 };
+
+        return Self { state: Mutex::new(state), interrupts: AtomicBool::new(false), completion_posted: WaitQueue::new() };
+    }
+
+    /// Submit `command` (only the command id is filled in here) and wait for its completion.
+    /// Returns the command specific result (dword 0 of the completion entry).
+    fn execute(&self, mut command: [u32; 16]) -> Result<u32> {
+        let mut state = self.state.lock();
+        let registers = state.registers;
+
+        command[0] |= (state.next_command_id as u32) << 16;
+        state.next_command_id = state.next_command_id.wrapping_add(1);
+
+        unsafe {
+            let entry = (state.submission + (state.tail * SUBMISSION_ENTRY_SIZE) as u64) as *mut u32;
+            for (index, value) in command.iter().enumerate() {
+                entry.add(index).write_volatile(*value);
+            }
+        }
+
+        state.tail = (state.tail + 1) % QUEUE_SIZE;
+        registers.write_u32(registers.submission_doorbell(state.id), state.tail as u32);
+
+        // The thread blocks until the interrupt handler signals a completion (the entry is checked once more after the timeout,
+        // in case the interrupt has been missed). Queues without interrupts are polled.
+        let entry = (state.completion + (state.head * COMPLETION_ENTRY_SIZE) as u64) as *const u32;
+        let phase = state.phase;
+        let completed = || (unsafe { entry.add(3).read_volatile() } & COMPLETION_PHASE != 0) == phase;
+        if self.interrupts.load(Relaxed) {
+            if !self.completion_posted.wait_until(completed, Some(COMMAND_TIMEOUT_MS)) {
+                return Err(Errno::IoError);
+            }
+        } else {
+            let start = timer().read().systime_ms();
+            while !completed() {
+                if timer().read().systime_ms() - start > COMMAND_TIMEOUT_MS {
+                    return Err(Errno::IoError);
+                }
+
+                scheduler().switch_thread();
+            }
+        }
+
+        let result = unsafe { entry.read_volatile() };
+        let status = unsafe { entry.add(3).read_volatile() } >> COMPLETION_STATUS_SHIFT;
+
+        state.head += 1;
+        if state.head == QUEUE_SIZE {
+            state.head = 0;
+            state.phase = !state.phase;
+        }
+        registers.write_u32(registers.completion_doorbell(state.id), state.head as u32);
+
+        return if status == 0 { Ok(result) } else { Err(Errno::IoError) };
+    }
+
+    fn submission_address(&self) -> u64 {
+        return self.state.lock().submission;
+    }
+
+    fn completion_address(&self) -> u64 {
+        return self.state.lock().completion;
+    }
+}
+
+impl Controller {
+    fn new(device: &PciDevice) -> Result<Self> {
+        device.set_command_flag(CommandFlag::MemorySpace, true);
+        device.set_command_flag(CommandFlag::BusMaster, true);
+
+        // The doorbell stride is only known after reading the capabilities, so the first page is mapped separately
+        let base = device.bar(0);
+        map_registers(base, DOORBELLS);
+        let capabilities = Registers { base, doorbell_stride: 0 }.read_u64(CAPABILITIES);
+        let registers = Registers { base, doorbell_stride: 4 << ((capabilities >> 32) & 0x0f) };
+        map_registers(base, registers.completion_doorbell(IO_QUEUE) + 4);
+
+        if (capabilities >> 48) & 0x0f != 0 {
+            return Err(Errno::NotSupported); // Minimum memory page size is larger than 4 KiB
+        }
+
+        // Disable the controller to configure the admin queue (the timeout is given in units of 500 ms)
+        let timeout_ms = ((capabilities >> 24) & 0xff) as usize * 500;
+        registers.write_u32(CONFIGURATION, registers.read_u32(CONFIGURATION) & !CONFIGURATION_ENABLE);
+        registers.wait_ready(false, timeout_ms)?;
+
+        let admin = Arc::new(QueuePair::new(ADMIN_QUEUE, registers));
+        let io = Arc::new(QueuePair::new(IO_QUEUE, registers));
+        registers.write_u32(ADMIN_QUEUE_ATTRIBUTES, ((QUEUE_SIZE - 1) << 16 | (QUEUE_SIZE - 1)) as u32);
+        registers.write_u64(ADMIN_SUBMISSION_QUEUE, admin.submission_address());
+        registers.write_u64(ADMIN_COMPLETION_QUEUE, admin.completion_address());
+
+        // Each queue gets its own MSI-X vector (table entry 0 for the admin queue and entry 1 for the I/O queue)
+        let target = msi::default_target();
+        let admin_interrupts = msi::request_msix(device, ADMIN_QUEUE, target, Box::new(NvmeInterruptHandler { queue: Arc::clone(&admin) })).is_some();
+        let io_interrupts = admin_interrupts && msi::request_msix(device, IO_QUEUE, target, Box::new(NvmeInterruptHandler { queue: Arc::clone(&io) })).is_some();
+        admin.interrupts.store(admin_interrupts, Relaxed);
+        io.interrupts.store(io_interrupts, Relaxed);
+        if !io_interrupts {
+            info!("NVMe controller [{}] does not support enough MSI-X vectors -> Polling for completions", device.address());
+        }
+
+        registers.write_u32(CONFIGURATION, CONFIGURATION_ENTRY_SIZES | CONFIGURATION_ENABLE);
+        registers.wait_ready(true, timeout_ms)?;
+
+        let buffer = physical::alloc(BUFFER_PAGES).start.start_address().as_u64();
+        let prp_list = alloc_zeroed_page();
+        for page in 1..BUFFER_PAGES {
+            unsafe { (prp_list as *mut u64).add(page - 1).write(buffer + (page * PAGE_SIZE) as u64); }
+        }
+
+        let mut controller = Self { admin, io, buffer, prp_list, max_transfer: BUFFER_PAGES * PAGE_SIZE, buffer_busy: AtomicBool::new(false), buffer_idle: WaitQueue::new() };
+
+        // The maximum data transfer size is given as power of two in units of the minimum page size (0 means unlimited)
+        let mut identify = [0u8; PAGE_SIZE];
+        controller.identify(IDENTIFY_CONTROLLER, 0, &mut identify)?;
+        if identify[77] != 0 {
+            controller.max_transfer = controller.max_transfer.min(PAGE_SIZE << identify[77]);
+        }
+
+        let model = String::from(String::from_utf8_lossy(&identify[24..64]).trim());
+        info!("NVMe controller [{}]: Model: [{}], Interrupts: [{}]", device.address(), model, if io_interrupts { "MSI-X" } else { "Polling" });
+
+        // Create the I/O completion queue before the submission queue, which refers to it
+        let interrupt_flags = if io_interrupts { QUEUE_INTERRUPTS_ENABLED | (IO_QUEUE as u32) << 16 } else { 0 };
+        controller.admin_command(AdminCommand::CreateCompletionQueue, 0, controller.io.completion_address(),
+            [((QUEUE_SIZE - 1) << 16) as u32 | IO_QUEUE as u32, QUEUE_PHYSICALLY_CONTIGUOUS | interrupt_flags])?;
+        controller.admin_command(AdminCommand::CreateSubmissionQueue, 0, controller.io.submission_address(),
+            [((QUEUE_SIZE - 1) << 16) as u32 | IO_QUEUE as u32, QUEUE_PHYSICALLY_CONTIGUOUS | (IO_QUEUE as u32) << 16])?;
+
+        return Ok(controller);
+    }
+
+    /// Return id, block size and block count of all active namespaces.
+    fn identify_namespaces(&self) -> Result<Vec<(u32, usize, u64)>> {
+        let mut list = [0u8; PAGE_SIZE];
+        self.identify(IDENTIFY_ACTIVE_NAMESPACES, 0, &mut list)?;
+
+        let mut namespaces = Vec::new();
+        for id in list.chunks_exact(4).map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).take_while(|id| *id != 0) {
+            let mut data = [0u8; PAGE_SIZE];
+            self.identify(IDENTIFY_NAMESPACE, id, &mut data)?;
+
+            // The formatted LBA size selects one of the LBA formats, which contain the block size as power of two
+            let block_count = u64::from_le_bytes(data[0..8].try_into().unwrap());
+            let format = 128 + (data[26] & 0x0f) as usize * 4;
+            let block_size = 1usize << data[format + 2];
+            if block_count > 0 && block_size <= self.max_transfer {
+                namespaces.push((id, block_size, block_count));
+            }
+        }
+
+        return Ok(namespaces);
+    }
+
+    fn lock_buffer(&self) -> BufferGuard<'_> {
+        while self.buffer_busy.swap(true, Acquire) {
+            self.buffer_idle.wait_until(|| !self.buffer_busy.load(Relaxed), None);
+        }
+
+        return BufferGuard { controller: self };
+    }
+
+    fn identify(&self, cns: u32, namespace: u32, target: &mut [u8; PAGE_SIZE]) -> Result<()> {
+        let _guard = self.lock_buffer();
+        self.admin_command(AdminCommand::Identify, namespace, self.buffer, [cns, 0])?;
+        unsafe { ptr::copy_nonoverlapping(self.buffer as *const u8, target.as_mut_ptr(), PAGE_SIZE); }
+
+        return Ok(());
+    }
+
+    fn admin_command(&self, command: AdminCommand, namespace: u32, address: u64, arguments: [u32; 2]) -> Result<u32> {
+        let mut entry = [0u32; 16];
+        entry[0] = command as u32;
+        entry[1] = namespace;
+        entry[6] = address as u32;
+        entry[7] = (address >> 32) as u32;
+        entry[10] = arguments[0];
+        entry[11] = arguments[1];
+
+        return self.admin.execute(entry);
+    }
+
+    /// Transfer `length` bytes between the bounce buffer and the namespace (must only be called with the buffer locked).
+    fn io_command(&self, command: IoCommand, namespace: u32, start: u64, blocks: usize, length: usize) -> Result<()> {
+        // The first page is given by PRP 1, the second one by PRP 2 and more pages by a list referenced by PRP 2
+        let pages = length.div_ceil(PAGE_SIZE);
+        let prp2 = match pages {
+            0 | 1 => 0,
+            2 => self.buffer + PAGE_SIZE as u64,
+            _ => self.prp_list
+        };
+
+        let mut entry = [0u32; 16];
+        entry[0] = command as u32;
+        entry[1] = namespace;
+        entry[6] = self.buffer as u32;
+        entry[7] = (self.buffer >> 32) as u32;
+        entry[8] = prp2 as u32;
+        entry[9] = (prp2 >> 32) as u32;
+        entry[10] = start as u32;
+        entry[11] = (start >> 32) as u32;
+        entry[12] = blocks.saturating_sub(1) as u32; // Number of blocks is zero based
+
+        return self.io.execute(entry).map(|_| ());
+    }
+}
+
+impl BlockDevice for NvmeNamespace {
+    fn block_size(&self) -> usize {
+        return self.block_size;
+    }
+
+    fn block_count(&self) -> u64 {
+        return self.block_count;
+    }
+
+    fn read_blocks(&self, start: u64, buffer: &mut [u8]) -> Result<()> {
+        let controller = &self.controller;
+        let _guard = controller.lock_buffer();
+
+        for (index, chunk) in buffer.chunks_mut(controller.max_transfer).enumerate() {
+            let block = start + (index * controller.max_transfer / self.block_size) as u64;
+            controller.io_command(IoCommand::Read, self.id, block, chunk.len() / self.block_size, chunk.len())?;
+            unsafe { ptr::copy_nonoverlapping(controller.buffer as *const u8, chunk.as_mut_ptr(), chunk.len()); }
+        }
+
+        return Ok(());
+    }
+
+    fn write_blocks(&self, start: u64, buffer: &[u8]) -> Result<()> {
+        let controller = &self.controller;
+        let _guard = controller.lock_buffer();
+
+        for (index, chunk) in buffer.chunks(controller.max_transfer).enumerate() {
+            let block = start + (index * controller.max_transfer / self.block_size) as u64;
+            unsafe { ptr::copy_nonoverlapping(chunk.as_ptr(), controller.buffer as *mut u8, chunk.len()); }
+            controller.io_command(IoCommand::Write, self.id, block, chunk.len() / self.block_size, chunk.len())?;
+        }
+
+        return Ok(());
+    }
+
+    fn flush(&self) -> Result<()> {
+        let _guard = self.controller.lock_buffer();
+        return self.controller.io_command(IoCommand::Flush, self.id, 0, 0, 0);
+    }
+}
+
+impl InterruptHandler for NvmeInterruptHandler {
+    fn trigger(&mut self) {
+        // MSI-X interrupts are edge triggered and need no acknowledgement at the controller
+        self.queue.completion_posted.notify_all_from_interrupt();
+    }
+}
+
+impl Drop for BufferGuard<'_> {
+    fn drop(&mut self) {
+        self.controller.buffer_busy.store(false, Release);
+        self.controller.buffer_idle.notify_all();
+    }
+}
+
+fn map_registers(base: u64, size: u64) {
+    let start_page = Page::containing_address(VirtAddr::new(base));
+    let end_page = Page::containing_address(VirtAddr::new(base + size - 1)) + 1;
+    current_process().address_space().map(PageRange { start: start_page, end: end_page }, MemorySpace::Kernel, PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE);
+}
+
+fn alloc_zeroed_page() -> u64 {
+    let address = physical::alloc(1).start.start_address().as_u64();
+    unsafe { ptr::write_bytes(address as *mut u8, 0, PAGE_SIZE); }
+
+    return address;
+}
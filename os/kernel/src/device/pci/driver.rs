@@ -0,0 +1,76 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use log::{info, warn};
+use syscall::error::Errno;
+use crate::device::pci::{PciDevice, PowerState};
+use crate::pci_bus;
+use crate::sync::Mutex;
+
+/// Functions, a driver is responsible for. Fields, which are `None`, match any value.
+#[derive(Copy, Clone, Debug)]
+pub struct PciId {
+    vendor_id: Option<u16>,
+    device_id: Option<u16>,
+    class: Option<(u8, u8)>,
+    prog_if: Option<u8>,
+}
+
+/// Driver for PCI functions. Drivers are registered with `register()` and bound to matching functions by `probe_all()`.
+pub struct PciDriver {
+    pub name: &'static str,
+    pub ids: &'static [PciId],
+    /// Initialize the function and register its devices (e.g. as block devices).
+    /// Returning `Errno::NoDevice` lets the next matching driver probe the function.
+    pub probe: fn(&Arc<PciDevice>) -> Result<(), Errno>,
+}
+
+/// Drivers in the order of registration (earlier drivers are probed first).
+static DRIVERS: Mutex<Vec<&'static PciDriver>> = Mutex::new(Vec::new());
+
+impl PciId {
+    pub const fn device(vendor_id: u16, device_id: u16) -> Self {
+        Self { vendor_id: Some(vendor_id), device_id: Some(device_id), class: None, prog_if: None }
+    }
+
+    pub const fn class(class: u8, subclass: u8) -> Self {
+        Self { vendor_id: None, device_id: None, class: Some((class, subclass)), prog_if: None }
+    }
+
+    pub const fn class_with_prog_if(class: u8, subclass: u8, prog_if: u8) -> Self {
+        Self { vendor_id: None, device_id: None, class: Some((class, subclass)), prog_if: Some(prog_if) }
+    }
+
+    fn matches(&self, device: &PciDevice) -> bool {
+        let (class, subclass, prog_if) = device.class();
+        return self.vendor_id.map_or(true, |id| id == device.vendor_id())
+            && self.device_id.map_or(true, |id| id == device.device_id())
+            && self.class.map_or(true, |id| id == (class, subclass))
+            && self.prog_if.map_or(true, |id| id == prog_if);
+    }
+}
+
+pub fn register(driver: &'static PciDriver) {
+    DRIVERS.lock().push(driver);
+}
+
+/// Let the registered drivers probe all functions, which have not been claimed yet.
+/// Each function is bound to the first matching driver, whose probe function succeeds.
+pub fn probe_all() {
+    let drivers = DRIVERS.lock().clone();
+    for device in pci_bus().devices().iter().filter(|device| device.driver().is_none()) {
+        for driver in drivers.iter().filter(|driver| driver.ids.iter().any(|id| id.matches(device))) {
+            // Functions might have been put into a low power state by the firmware
+            device.set_power_state(PowerState::D0);
+
+            match (driver.probe)(device) {
+                Ok(()) => {
+                    info!("PCI device [{}] bound to driver [{}]", device.address(), driver.name);
+                    device.driver.call_once(|| driver.name);
+                    break;
+                }
+                Err(Errno::NoDevice) => continue,
+                Err(err) => warn!("Driver [{}] failed to probe PCI device [{}] (Error: {:?})", driver.name, device.address(), err)
+            }
+        }
+    }
+}
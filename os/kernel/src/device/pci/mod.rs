@@ -0,0 +1,447 @@
+use acpi::mcfg::PciConfigRegions;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+use log::{info, warn};
+use spin::Once;
+use x86_64::instructions::port::Port;
+use x86_64::structures::paging::page::PageRange;
+use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::VirtAddr;
+use crate::memory::MemorySpace;
+use crate::process::process::current_process;
+use crate::sync::Mutex;
+use crate::device::pit::Timer;
+use crate::{acpi_tables, scheduler};
+
+pub mod driver;
+pub mod msi;
+
+const CONFIG_ADDRESS_PORT: u16 = 0xcf8;
+const CONFIG_DATA_PORT: u16 = 0xcfc;
+
+const MAX_BUS: u8 = 255;
+const MAX_DEVICES: u8 = 32;
+const MAX_FUNCTIONS: u8 = 8;
+const INVALID_VENDOR: u16 = 0xffff;
+
+// Offsets in the common configuration space header
+const VENDOR_ID: u8 = 0x00;
+const DEVICE_ID: u8 = 0x02;
+const COMMAND: u8 = 0x04;
+const STATUS: u8 = 0x06;
+const PROG_IF: u8 = 0x09;
+const SUBCLASS: u8 = 0x0a;
+const CLASS: u8 = 0x0b;
+const HEADER_TYPE: u8 = 0x0e;
+const BAR0: u8 = 0x10;
+const CAPABILITIES_POINTER: u8 = 0x34;
+const INTERRUPT_LINE: u8 = 0x3c;
+
+const STATUS_CAPABILITIES_LIST: u16 = 1 << 4;
+/// Capabilities are located in the 192 bytes after the header and are at least 4 bytes large.
+const MAX_CAPABILITIES: usize = 48;
+const HEADER_TYPE_MULTIFUNCTION: u8 = 1 << 7;
+
+// Offsets in the power management capability
+const POWER_CONTROL: u8 = 0x04;
+const POWER_STATE_MASK: u16 = 0x03;
+/// Devices need up to 10 ms to return from D3hot to D0.
+const POWER_STATE_DELAY_MS: usize = 10;
+
+// Offsets in the PCI Express capability
+const PCIE_CAPABILITIES: u8 = 0x02;
+const PCIE_LINK_STATUS: u8 = 0x12;
+
+/// Each function has 4 KiB of configuration space in the ECAM region of its bus.
+const ECAM_DEVICE_SHIFT: u64 = 15;
+const ECAM_FUNCTION_SHIFT: u64 = 12;
+
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(u16)]
+pub enum CommandFlag {
+    IoSpace = 1 << 0,
+    MemorySpace = 1 << 1,
+    BusMaster = 1 << 2,
+    InterruptDisable = 1 << 10,
+}
+
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(u8)]
+pub enum CapabilityId {
+    PowerManagement = 0x01,
+    Msi = 0x05,
+    VendorSpecific = 0x09,
+    PciExpress = 0x10,
+    MsiX = 0x11,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(u8)]
+pub enum PowerState {
+    D0 = 0,
+    D1 = 1,
+    D2 = 2,
+    D3Hot = 3,
+}
+
+/// Entry of the capability list of a function (the offset is relative to the start of its configuration space).
+#[derive(Copy, Clone, Debug)]
+pub struct Capability {
+    pub id: u8,
+    pub offset: u8,
+}
+
+/// Properties of a PCI Express function, read from its PCI Express capability.
+#[derive(Copy, Clone, Debug)]
+pub struct PcieInfo {
+    /// Device/port type (e.g. 0 for an endpoint or 4 for a root port).
+    pub port_type: u8,
+    /// Generation of the current link speed (1 for 2.5 GT/s, 2 for 5 GT/s, ...).
+    pub link_speed: u8,
+    /// Number of lanes of the current link.
+    pub link_width: u8,
+}
+
+/// Location of a function in the PCI configuration space.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+pub struct PciDevice {
+    address: PciAddress,
+    vendor_id: u16,
+    device_id: u16,
+    class: u8,
+    subclass: u8,
+    prog_if: u8,
+    capabilities: Vec<Capability>,
+    /// Name of the driver, which has claimed the function (see `driver::probe_all()`).
+    driver: Once<&'static str>,
+}
+
+pub struct PciBus {
+    devices: Vec<Arc<PciDevice>>,
+}
+
+/// Config space is accessed via an address and a data port, which must not be interleaved.
+/// This mechanism is used, if the firmware does not provide memory mapped configuration space (ECAM).
+static CONFIG_PORTS: Mutex<(Port<u32>, Port<u32>)> = Mutex::new((Port::new(CONFIG_ADDRESS_PORT), Port::new(CONFIG_DATA_PORT)));
+
+/// Physical start address of the ECAM region of each bus (segment group 0), as described by the ACPI MCFG table.
+/// Config space in these regions is identity mapped page by page, while the bus is scanned.
+static ECAM: Once<Vec<Option<u64>>> = Once::new();
+
+impl PciAddress {
+    const fn new(bus: u8, device: u8, function: u8) -> Self {
+        Self { bus, device, function }
+    }
+
+    fn config_address(&self, offset: u8) -> u32 {
+        return 0x80000000 | (self.bus as u32) << 16 | (self.device as u32) << 11 | (self.function as u32) << 8 | (offset & 0xfc) as u32;
+    }
+
+    /// Address of the configuration space of this function in its ECAM region (if available).
+    fn ecam_address(&self) -> Option<u64> {
+        let base = ECAM.get()?.get(self.bus as usize).copied().flatten()?;
+        return Some(base + ((self.device as u64) << ECAM_DEVICE_SHIFT | (self.function as u64) << ECAM_FUNCTION_SHIFT));
+    }
+
+    /// Map the configuration space of this function, if it is accessed via ECAM.
+    fn map_ecam(&self) {
+        if let Some(address) = self.ecam_address() {
+            let page = Page::containing_address(VirtAddr::new(address));
+            current_process().address_space().map(PageRange { start: page, end: page + 1 }, MemorySpace::Kernel, PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE);
+        }
+    }
+}
+
+impl Display for PciAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+impl PciBus {
+    /// Scan all buses for devices (brute force, since bridges are not configured by us anyway).
+    /// Configuration space is accessed via ECAM, if the ACPI tables contain an MCFG table, or via I/O ports otherwise.
+    pub fn scan() -> Self {
+        init_ecam();
+        let mut devices = Vec::new();
+
+        for bus in 0..=MAX_BUS {
+            for device in 0..MAX_DEVICES {
+                let first = PciAddress::new(bus, device, 0);
+                first.map_ecam();
+                if read_u16(first, VENDOR_ID) == INVALID_VENDOR {
+                    continue;
+                }
+
+                let functions = if read_u8(first, HEADER_TYPE) & HEADER_TYPE_MULTIFUNCTION != 0 { MAX_FUNCTIONS } else { 1 };
+                for function in 0..functions {
+                    let address = PciAddress::new(bus, device, function);
+                    address.map_ecam();
+                    if read_u16(address, VENDOR_ID) != INVALID_VENDOR {
+                        devices.push(Arc::new(PciDevice::new(address)));
+                    }
+                }
+            }
+        }
+
+        info!("[{}] PCI {} detected", devices.len(), if devices.len() == 1 { "function" } else { "functions" });
+        for device in devices.iter() {
+            let capabilities = device.capabilities.iter().map(|capability| capability_name(capability.id)).collect::<Vec<_>>().join(", ");
+            let link = match device.pcie_info() {
+                Some(info) if info.link_width > 0 => format!(", Link: [Gen {} x{}]", info.link_speed, info.link_width),
+                _ => String::new()
+            };
+
+            info!("PCI device [{}]: Vendor: [0x{:04x}], Device: [0x{:04x}], Class: [0x{:02x}:0x{:02x}:0x{:02x}], Capabilities: [{}]{}",
+                device.address, device.vendor_id, device.device_id, device.class, device.subclass, device.prog_if, capabilities, link);
+        }
+
+        return Self { devices };
+    }
+
+    pub fn devices(&self) -> &Vec<Arc<PciDevice>> {
+        return &self.devices;
+    }
+
+    pub fn find_device(&self, vendor_id: u16, device_id: u16) -> Option<Arc<PciDevice>> {
+        return self.devices.iter().find(|device| device.vendor_id == vendor_id && device.device_id == device_id).cloned();
+    }
+
+    pub fn find_class(&self, class: u8, subclass: u8) -> Vec<Arc<PciDevice>> {
+        return self.devices.iter().filter(|device| device.class == class && device.subclass == subclass).cloned().collect();
+    }
+}
+
+impl PciDevice {
+    fn new(address: PciAddress) -> Self {
+        Self {
+            address,
+            vendor_id: read_u16(address, VENDOR_ID),
+            device_id: read_u16(address, DEVICE_ID),
+            class: read_u8(address, CLASS),
+            subclass: read_u8(address, SUBCLASS),
+            prog_if: read_u8(address, PROG_IF),
+            capabilities: read_capabilities(address),
+            driver: Once::new(),
+        }
+    }
+
+    pub fn address(&self) -> PciAddress {
+        return self.address;
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        return self.vendor_id;
+    }
+
+    pub fn device_id(&self) -> u16 {
+        return self.device_id;
+    }
+
+    pub fn class(&self) -> (u8, u8, u8) {
+        return (self.class, self.subclass, self.prog_if);
+    }
+
+    pub fn interrupt_line(&self) -> u8 {
+        return self.read_u8(INTERRUPT_LINE);
+    }
+
+    /// Physical address of a memory BAR (64-bit BARs occupy two consecutive slots) or port of an I/O BAR.
+    pub fn bar(&self, index: u8) -> u64 {
+        let offset = BAR0 + index * 4;
+        let low = self.read_u32(offset);
+
+        return if low & 0x01 != 0 {
+            (low & 0xfffffffc) as u64 // I/O space
+        } else if (low >> 1) & 0x03 == 0x02 {
+            (self.read_u32(offset + 4) as u64) << 32 | (low & 0xfffffff0) as u64 // 64-bit memory space
+        } else {
+            (low & 0xfffffff0) as u64 // 32-bit memory space
+        };
+    }
+
+    pub fn set_command_flag(&self, flag: CommandFlag, enabled: bool) {
+        let command = self.read_u16(COMMAND);
+        self.write_u16(COMMAND, if enabled { command | flag as u16 } else { command & !(flag as u16) });
+    }
+
+    /// Entries of the capability list (read once, while the bus is scanned).
+    pub fn capabilities(&self) -> &[Capability] {
+        return &self.capabilities;
+    }
+
+    /// Config space offset of the first capability with the given id.
+    pub fn find_capability(&self, id: CapabilityId) -> Option<u8> {
+        return self.find_capabilities(id).first().copied();
+    }
+
+    /// Config space offsets of all capabilities with the given id (e.g. vendor specific capabilities, which may occur multiple times).
+    pub fn find_capabilities(&self, id: CapabilityId) -> Vec<u8> {
+        return self.capabilities.iter().filter(|capability| capability.id == id as u8).map(|capability| capability.offset).collect();
+    }
+
+    /// Current power state or None, if the function does not support power management (and is always in D0).
+    pub fn power_state(&self) -> Option<PowerState> {
+        let capability = self.find_capability(CapabilityId::PowerManagement)?;
+        return Some(match self.read_u16(capability + POWER_CONTROL) & POWER_STATE_MASK {
+            0 => PowerState::D0,
+            1 => PowerState::D1,
+            2 => PowerState::D2,
+            _ => PowerState::D3Hot
+        });
+    }
+
+    /// Switch the function to `state` (ignored, if it does not support power management).
+    pub fn set_power_state(&self, state: PowerState) {
+        let capability = match self.find_capability(CapabilityId::PowerManagement) {
+            Some(capability) => capability,
+            None => return
+        };
+
+        let control = self.read_u16(capability + POWER_CONTROL);
+        if control & POWER_STATE_MASK == state as u16 {
+            return;
+        }
+
+        self.write_u16(capability + POWER_CONTROL, (control & !POWER_STATE_MASK) | state as u16);
+
+        // Drivers are probed during boot, before the scheduler is running
+        if scheduler().is_initialized() {
+            scheduler().sleep(POWER_STATE_DELAY_MS);
+        } else {
+            Timer::wait(POWER_STATE_DELAY_MS);
+        }
+    }
+
+    /// Port type and link of a PCI Express function or None for conventional PCI functions.
+    pub fn pcie_info(&self) -> Option<PcieInfo> {
+        let capability = self.find_capability(CapabilityId::PciExpress)?;
+        let link_status = self.read_u16(capability + PCIE_LINK_STATUS);
+
+        return Some(PcieInfo {
+            port_type: ((self.read_u16(capability + PCIE_CAPABILITIES) >> 4) & 0x0f) as u8,
+            link_speed: (link_status & 0x0f) as u8,
+            link_width: ((link_status >> 4) & 0x3f) as u8,
+        });
+    }
+
+    /// Name of the driver, which has claimed this function.
+    pub fn driver(&self) -> Option<&'static str> {
+        return self.driver.get().copied();
+    }
+
+    pub fn read_u32(&self, offset: u8) -> u32 {
+        return read_u32(self.address, offset);
+    }
+
+    pub fn read_u16(&self, offset: u8) -> u16 {
+        return read_u16(self.address, offset);
+    }
+
+    pub fn read_u8(&self, offset: u8) -> u8 {
+        return read_u8(self.address, offset);
+    }
+
+    pub fn write_u32(&self, offset: u8, value: u32) {
+        write_u32(self.address, offset, value);
+    }
+
+    pub fn write_u16(&self, offset: u8, value: u16) {
+        let shift = (offset & 0x02) * 8;
+        let old = self.read_u32(offset);
+        self.write_u32(offset, (old & !(0xffff << shift)) | (value as u32) << shift);
+    }
+}
+
+/// Read the bus base addresses of the ECAM regions from the MCFG table (only segment group 0 is supported).
+fn init_ecam() {
+    let tables = acpi_tables().lock();
+    let regions = match PciConfigRegions::new(&tables) {
+        Ok(regions) => regions,
+        Err(_) => {
+            info!("No MCFG table found -> Accessing PCI configuration space via I/O ports");
+            return;
+        }
+    };
+
+    let buses = (0..=MAX_BUS).map(|bus| regions.physical_address(0, bus, 0, 0)).collect::<Vec<_>>();
+    let count = buses.iter().filter(|base| base.is_some()).count();
+    if count == 0 {
+        warn!("MCFG table does not describe segment group 0 -> Accessing PCI configuration space via I/O ports");
+        return;
+    }
+
+    info!("Accessing PCI configuration space via ECAM ([{}] {})", count, if count == 1 { "bus" } else { "buses" });
+    ECAM.call_once(|| buses);
+}
+
+fn read_capabilities(address: PciAddress) -> Vec<Capability> {
+    let mut capabilities = Vec::new();
+    if read_u16(address, STATUS) & STATUS_CAPABILITIES_LIST == 0 {
+        return capabilities;
+    }
+
+    // The number of entries is limited, so that a broken list with a loop does not hang the scan
+    let mut offset = read_u8(address, CAPABILITIES_POINTER) & 0xfc;
+    while offset != 0 && capabilities.len() < MAX_CAPABILITIES {
+        capabilities.push(Capability { id: read_u8(address, offset), offset });
+        offset = read_u8(address, offset + 1) & 0xfc;
+    }
+
+    return capabilities;
+}
+
+fn capability_name(id: u8) -> &'static str {
+    return match id {
+        0x01 => "PM",
+        0x05 => "MSI",
+        0x09 => "Vendor",
+        0x10 => "PCIe",
+        0x11 => "MSI-X",
+        _ => "?"
+    };
+}
+
+fn read_u32(address: PciAddress, offset: u8) -> u32 {
+    if let Some(base) = address.ecam_address() {
+        return unsafe { ((base + (offset & 0xfc) as u64) as *const u32).read_volatile() };
+    }
+
+    let mut ports = CONFIG_PORTS.lock();
+    unsafe {
+        ports.0.write(address.config_address(offset));
+        return ports.1.read();
+    }
+}
+
+fn write_u32(address: PciAddress, offset: u8, value: u32) {
+    if let Some(base) = address.ecam_address() {
+        unsafe { ((base + (offset & 0xfc) as u64) as *mut u32).write_volatile(value); }
+        return;
+    }
+
+    let mut ports = CONFIG_PORTS.lock();
+    unsafe {
+        ports.0.write(address.config_address(offset));
+        ports.1.write(value);
+    }
+}
+
+fn read_u16(address: PciAddress, offset: u8) -> u16 {
+    return (read_u32(address, offset) >> ((offset & 0x02) * 8)) as u16;
+}
+
+fn read_u8(address: PciAddress, offset: u8) -> u8 {
+    return (read_u32(address, offset) >> ((offset & 0x03) * 8)) as u8;
+}
@@ -0,0 +1,122 @@
+use alloc::boxed::Box;
+use x86_64::structures::paging::page::PageRange;
+use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::VirtAddr;
+use crate::device::pci::{CapabilityId, CommandFlag, PciDevice};
+use crate::interrupt::interrupt_handler::InterruptHandler;
+use crate::memory::MemorySpace;
+use crate::process::process::current_process;
+use crate::{apic, interrupt_dispatcher};
+
+/// Messages are written to this address range, which is decoded by the local APICs.
+const MSI_ADDRESS_BASE: u32 = 0xfee00000;
+
+// Offsets in the MSI capability structure
+const MSI_CONTROL: u8 = 0x02;
+const MSI_ADDRESS_LOW: u8 = 0x04;
+const MSI_ADDRESS_HIGH: u8 = 0x08;
+const MSI_DATA_32: u8 = 0x08;
+const MSI_DATA_64: u8 = 0x0c;
+
+const MSI_CONTROL_ENABLE: u16 = 1 << 0;
+const MSI_CONTROL_MULTIPLE_MESSAGES: u16 = 0x07 << 4;
+const MSI_CONTROL_64_BIT: u16 = 1 << 7;
+
+// Offsets in the MSI-X capability structure
+const MSIX_CONTROL: u8 = 0x02;
+const MSIX_TABLE: u8 = 0x04;
+
+const MSIX_CONTROL_TABLE_SIZE: u16 = 0x07ff;
+const MSIX_CONTROL_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_CONTROL_ENABLE: u16 = 1 << 15;
+
+const MSIX_ENTRY_SIZE: u64 = 16;
+const MSIX_ENTRY_VECTOR_MASKED: u32 = 1 << 0;
+
+/// Allocate a vector, assign `handler` to it and let the device signal it via MSI to the local APIC `apic_id`.
+/// Returns the allocated vector or None, if the device does not support MSI.
+pub fn request_msi(device: &PciDevice, apic_id: u8, handler: Box<dyn InterruptHandler>) -> Option<u8> {
+    device.find_capability(CapabilityId::Msi)?;
+
+    let vector = interrupt_dispatcher().allocate_vector(handler);
+    enable_msi(device, vector, apic_id);
+    return Some(vector);
+}
+
+/// Allocate a vector, assign `handler` to it and let the device signal it via MSI-X table entry `entry` to the local APIC `apic_id`.
+/// Devices with multiple queues can request one vector per queue this way.
+/// Returns the allocated vector or None, if the device does not support MSI-X or the entry does not exist.
+pub fn request_msix(device: &PciDevice, entry: u16, apic_id: u8, handler: Box<dyn InterruptHandler>) -> Option<u8> {
+    if entry >= msix_table_size(device)? {
+        return None;
+    }
+
+    let vector = interrupt_dispatcher().allocate_vector(handler);
+    enable_msix(device, entry, vector, apic_id);
+    return Some(vector);
+}
+
+/// Number of entries in the MSI-X table or None, if the device does not support MSI-X.
+pub fn msix_table_size(device: &PciDevice) -> Option<u16> {
+    let capability = device.find_capability(CapabilityId::MsiX)?;
+    return Some((device.read_u16(capability + MSIX_CONTROL) & MSIX_CONTROL_TABLE_SIZE) + 1);
+}
+
+/// Configure the MSI capability to send a single message with `vector` to the local APIC `apic_id`.
+pub fn enable_msi(device: &PciDevice, vector: u8, apic_id: u8) {
+    let capability = device.find_capability(CapabilityId::Msi).expect("MSI: Device does not support message signaled interrupts!");
+    let control = device.read_u16(capability + MSI_CONTROL);
+
+    device.write_u32(capability + MSI_ADDRESS_LOW, message_address(apic_id));
+    if control & MSI_CONTROL_64_BIT != 0 {
+        device.write_u32(capability + MSI_ADDRESS_HIGH, 0);
+        device.write_u16(capability + MSI_DATA_64, message_data(vector) as u16);
+    } else {
+        device.write_u16(capability + MSI_DATA_32, message_data(vector) as u16);
+    }
+
+    // Only one message is used, so that the vector does not need to be aligned
+    device.write_u16(capability + MSI_CONTROL, (control & !MSI_CONTROL_MULTIPLE_MESSAGES) | MSI_CONTROL_ENABLE);
+    device.set_command_flag(CommandFlag::InterruptDisable, true);
+}
+
+/// Configure MSI-X table entry `entry` to send `vector` to the local APIC `apic_id` and unmask it.
+pub fn enable_msix(device: &PciDevice, entry: u16, vector: u8, apic_id: u8) {
+    let capability = device.find_capability(CapabilityId::MsiX).expect("MSI-X: Device does not support MSI-X!");
+    let table_info = device.read_u32(capability + MSIX_TABLE);
+    let table_address = device.bar((table_info & 0x07) as u8) + (table_info & !0x07) as u64;
+    let entry_address = table_address + entry as u64 * MSIX_ENTRY_SIZE;
+
+    // Mask all vectors, while the table is modified
+    let control = device.read_u16(capability + MSIX_CONTROL);
+    device.write_u16(capability + MSIX_CONTROL, control | MSIX_CONTROL_ENABLE | MSIX_CONTROL_FUNCTION_MASK);
+
+    // The MSI-X table is located in memory space and needs to be mapped to be accessible
+    let entry_page = Page::containing_address(VirtAddr::new(entry_address));
+    current_process().address_space().map(PageRange { start: entry_page, end: entry_page + 1 }, MemorySpace::Kernel, PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE);
+    device.set_command_flag(CommandFlag::MemorySpace, true);
+
+    unsafe {
+        let entry_ptr = entry_address as *mut u32;
+        entry_ptr.write_volatile(message_address(apic_id));
+        entry_ptr.add(1).write_volatile(0);
+        entry_ptr.add(2).write_volatile(message_data(vector));
+        entry_ptr.add(3).write_volatile(entry_ptr.add(3).read_volatile() & !MSIX_ENTRY_VECTOR_MASKED);
+    }
+
+    device.write_u16(capability + MSIX_CONTROL, (control | MSIX_CONTROL_ENABLE) & !MSIX_CONTROL_FUNCTION_MASK);
+    device.set_command_flag(CommandFlag::InterruptDisable, true);
+}
+
+/// Id of the local APIC, that should be used as default target for message signaled interrupts.
+pub fn default_target() -> u8 {
+    return apic().destination();
+}
+
+fn message_address(apic_id: u8) -> u32 {
+    return MSI_ADDRESS_BASE | (apic_id as u32) << 12;
+}
+
+fn message_data(vector: u8) -> u32 {
+    return vector as u32; // Fixed delivery mode, edge triggered
+}
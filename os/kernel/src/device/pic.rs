@@ -0,0 +1,57 @@
+use x86_64::instructions::port::PortWriteOnly;
+
+const MASTER_COMMAND_PORT: u16 = 0x20;
+const MASTER_DATA_PORT: u16 = 0x21;
+const SLAVE_COMMAND_PORT: u16 = 0xa0;
+const SLAVE_DATA_PORT: u16 = 0xa1;
+const WAIT_PORT: u16 = 0x80;
+
+const ICW1_INIT: u8 = 0x11; // Initialization with ICW4
+const ICW4_8086_MODE: u8 = 0x01;
+
+/// The PICs are remapped to unused vectors, so that spurious interrupts cannot be mistaken for CPU exceptions or IO APIC interrupts.
+const MASTER_VECTOR_OFFSET: u8 = 0xe0;
+const SLAVE_VECTOR_OFFSET: u8 = 0xe8;
+
+/// Remap and mask both legacy 8259 PICs, since all legacy IRQs are routed through the IO APIC.
+pub fn disable() {
+    let mut master_command = PortWriteOnly::<u8>::new(MASTER_COMMAND_PORT);
+    let mut master_data = PortWriteOnly::<u8>::new(MASTER_DATA_PORT);
+    let mut slave_command = PortWriteOnly::<u8>::new(SLAVE_COMMAND_PORT);
+    let mut slave_data = PortWriteOnly::<u8>::new(SLAVE_DATA_PORT);
+
+    unsafe {
+        // ICW1: Start initialization sequence
+        master_command.write(ICW1_INIT);
+        io_wait();
+        slave_command.write(ICW1_INIT);
+        io_wait();
+
+        // ICW2: Vector offsets
+        master_data.write(MASTER_VECTOR_OFFSET);
+        io_wait();
+        slave_data.write(SLAVE_VECTOR_OFFSET);
+        io_wait();
+
+        // ICW3: Slave is connected to IRQ2 of the master
+        master_data.write(0x04);
+        io_wait();
+        slave_data.write(0x02);
+        io_wait();
+
+        // ICW4: 8086 mode
+        master_data.write(ICW4_8086_MODE);
+        io_wait();
+        slave_data.write(ICW4_8086_MODE);
+        io_wait();
+
+        // Mask all interrupts
+        master_data.write(0xff);
+        slave_data.write(0xff);
+    }
+}
+
+/// Writing to an unused port takes long enough for the PIC to process the previous command.
+unsafe fn io_wait() {
+    PortWriteOnly::<u8>::new(WAIT_PORT).write(0);
+}
@@ -4,7 +4,7 @@ use crate::interrupt::interrupt_handler::InterruptHandler;
 use alloc::boxed::Box;
 use core::arch::asm;
 use core::hint::spin_loop;
-use spin::Mutex;
+use crate::sync::Mutex;
 use x86_64::instructions::port::{Port, PortWriteOnly};
 use crate::{apic, interrupt_dispatcher, timer};
 
@@ -73,6 +73,7 @@ impl Timer {
         }
     }
 
+    #[allow(dead_code)]
     pub fn plugin(&self) {
         interrupt_dispatcher().assign(InterruptVector::Pit, Box::new(TimerInterruptHandler::new()));
         apic().allow(InterruptVector::Pit);
@@ -82,6 +83,10 @@ impl Timer {
         return self.systime_ns / 1000000;
     }
 
+    pub fn systime_ns(&self) -> usize {
+        return self.systime_ns;
+    }
+
     pub fn wait(ms: usize) {
         let end_time = timer().read().systime_ms() + ms;
         while timer().read().systime_ms() < end_time {
@@ -89,8 +94,13 @@ impl Timer {
         }
     }
 
+    /// Used by the APIC timer, which replaces the PIT as system tick source.
+    pub fn advance_systime(&mut self, ns: usize) {
+        self.systime_ns += ns;
+    }
+
     fn inc_systime(&mut self) {
-        self.systime_ns += self.interval_ns;
+        self.advance_systime(self.interval_ns);
     }
 }
 
@@ -0,0 +1,407 @@
+use acpi::address::{AddressSpace, GenericAddress};
+use acpi::fadt::Fadt;
+use acpi::madt::Madt;
+use acpi::mcfg::Mcfg;
+use acpi::HpetInfo;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::ptr;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering::Relaxed;
+use log::{error, info, warn};
+use spin::Once;
+use syscall::signal::Signal;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::instructions::tables::lidt;
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::VirtAddr;
+use crate::fs::vfs;
+use crate::interrupt::deferred;
+use crate::interrupt::interrupt_handler::InterruptHandler;
+use crate::process::process::{current_process, init_process, kernel_process, processes};
+use crate::{acpi_tables, apic, interrupt_dispatcher, scheduler};
+
+/// Command for the keyboard controller to pulse the reset line of the CPU.
+const KEYBOARD_CONTROLLER_PORT: u16 = 0x64;
+const KEYBOARD_CONTROLLER_RESET: u8 = 0xfe;
+
+/// Bits in the PM1 control registers.
+const SCI_ENABLE: u16 = 1 << 0;
+const SLEEP_ENABLE: u16 = 1 << 13;
+const SLEEP_TYPE_SHIFT: u16 = 10;
+/// Sleep types are 3 bits wide.
+const MAX_SLEEP_TYPE: u8 = 0x07;
+
+/// Bit in the PM1 status and enable registers for the fixed power button event.
+const POWER_BUTTON: u16 = 1 << 8;
+
+/// Time given to user processes for exiting after `Signal::Terminate`, before the system is powered off anyway.
+const TERMINATE_TIMEOUT_MS: usize = 5000;
+const TERMINATE_POLL_INTERVAL_MS: usize = 100;
+
+/// Registers and values needed for entering the soft-off state (S5) and resetting the system, taken from the FADT and DSDT.
+struct PowerManagement {
+    pm1a_control: Option<u16>,
+    pm1b_control: Option<u16>,
+    smi_command: u16,
+    acpi_enable: u8,
+    /// PM1 event blocks, consisting of a status register, followed by an enable register (each `pm1_event_length / 2` bytes long).
+    pm1a_event: Option<u16>,
+    pm1b_event: Option<u16>,
+    pm1_event_length: u8,
+    /// General purpose event block 0 and its length (status registers, followed by the same number of enable registers).
+    gpe0: Option<(u16, u8)>,
+    /// ISA IRQ of the system control interrupt, which signals fixed and general purpose events.
+    sci_interrupt: u8,
+    /// Values for SLP_TYPa and SLP_TYPb to enter S5 (None, if the DSDT does not define '\_S5').
+    s5_sleep_types: Option<(u16, u16)>,
+    /// Reset register and the value to write into it (None, if not supported).
+    reset: Option<(GenericAddress, u8)>,
+}
+
+/// Handler for the system control interrupt. Only fixed events are handled, since general purpose events
+/// would need their AML control methods ('\_GPE._Lxx' and '\_GPE._Exx') to be executed, for which there is no interpreter.
+struct SciHandler;
+
+static POWER_MANAGEMENT: Once<PowerManagement> = Once::new();
+/// Set, once the power button has been pressed, so that pressing it again does not start a second shutdown.
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Parse the FADT and DSDT for shutting down and resetting the system and enable the power button event.
+/// Other tables (MADT, HPET, MCFG) are parsed by their respective drivers and are only logged here.
+pub fn init() {
+    let tables = acpi_tables().lock();
+    info!("ACPI tables: MADT [{}], FADT [{}], MCFG [{}]",
+        present(tables.find_table::<Madt>().is_ok()), present(tables.find_table::<Fadt>().is_ok()), present(tables.find_table::<Mcfg>().is_ok()));
+
+    match HpetInfo::new(&tables) {
+        Ok(hpet) => info!("HPET available (Address: [0x{:x}], Comparators: [{}], 64-bit counter: [{}])", hpet.base_address, hpet.num_comparators(), hpet.main_counter_is_64bits()),
+        Err(_) => info!("HPET not available")
+    }
+
+    let fadt = match tables.find_table::<Fadt>() {
+        Ok(fadt) => fadt,
+        Err(_) => {
+            warn!("FADT not available (Shutdown and reboot via ACPI are not supported)");
+            return;
+        }
+    };
+
+    let s5_sleep_types = tables.dsdt().ok().and_then(|dsdt| {
+        let aml = unsafe { core::slice::from_raw_parts(dsdt.address as *const u8, dsdt.length as usize) };
+        find_s5_sleep_types(aml)
+    });
+
+    let reset = fadt.reset_register().ok()
+        .filter(|register| register.address != 0)
+        .map(|register| (register, fadt.reset_value));
+
+    let power_management = PowerManagement {
+        pm1a_control: fadt.pm1a_control_block().ok().and_then(io_port),
+        pm1b_control: fadt.pm1b_control_block().ok().flatten().and_then(io_port),
+        smi_command: fadt.smi_cmd_port as u16,
+        acpi_enable: fadt.acpi_enable,
+        pm1a_event: fadt.pm1a_event_block().ok().and_then(io_port),
+        pm1b_event: fadt.pm1b_event_block().ok().flatten().and_then(io_port),
+        pm1_event_length: fadt.pm1_event_length,
+        gpe0: fadt.gpe0_block().ok().flatten().and_then(io_port).map(|port| (port, fadt.gpe0_block_length)),
+        sci_interrupt: fadt.sci_interrupt as u8,
+        s5_sleep_types,
+        reset,
+    };
+
+    match power_management.s5_sleep_types {
+        Some((typ_a, typ_b)) => info!("ACPI soft-off available (SLP_TYPa: [{}], SLP_TYPb: [{}])", typ_a, typ_b),
+        None => warn!("ACPI soft-off not available ('\\_S5' not found in DSDT)")
+    }
+    match power_management.reset {
+        Some((register, value)) => info!("ACPI reset register available (Address: [0x{:x}], Value: [0x{:x}])", register.address, value),
+        None => info!("ACPI reset register not available (Falling back to keyboard controller)")
+    }
+
+    drop(fadt);
+    drop(tables);
+
+    let power = POWER_MANAGEMENT.call_once(|| power_management);
+    if power.pm1a_event.is_none() {
+        warn!("PM1 event block not available (Power button is not supported)");
+        return;
+    }
+
+    // No general purpose events are handled, so they are all disabled to prevent unhandled interrupts
+    power.enable_acpi_mode();
+    report_ignored_gpes(&power.disable_gpes());
+    power.write_pm1_status(0xffff);
+
+    interrupt_dispatcher().assign_irq(power.sci_interrupt, Box::new(SciHandler));
+    apic().allow_irq(power.sci_interrupt);
+    power.write_pm1_enable(POWER_BUTTON);
+
+    info!("ACPI power button enabled (SCI: [{}])", power.sci_interrupt);
+}
+
+/// Write all modified data back and power off the system by entering the sleep state S5.
+/// Halts the CPU, if this is not supported (e.g. no ACPI or the DSDT does not define '\_S5').
+pub fn shutdown() -> ! {
+    prepare("Shutting down");
+
+    if let Some(power) = POWER_MANAGEMENT.get() {
+        if let (Some(pm1a_control), Some((typ_a, typ_b))) = (power.pm1a_control, power.s5_sleep_types) {
+            power.enable_acpi_mode();
+
+            unsafe {
+                let mut port = Port::<u16>::new(pm1a_control);
+                let value = port.read() & !(0x7 << SLEEP_TYPE_SHIFT);
+                port.write(value | (typ_a << SLEEP_TYPE_SHIFT) | SLEEP_ENABLE);
+
+                if let Some(pm1b_control) = power.pm1b_control {
+                    let mut port = Port::<u16>::new(pm1b_control);
+                    let value = port.read() & !(0x7 << SLEEP_TYPE_SHIFT);
+                    port.write(value | (typ_b << SLEEP_TYPE_SHIFT) | SLEEP_ENABLE);
+                }
+            }
+        }
+    }
+
+    error!("Failed to power off the system (It is now safe to turn it off manually)");
+    halt();
+}
+
+/// Write all modified data back and reset the system.
+/// The ACPI reset register is tried first, followed by the keyboard controller and finally a triple fault.
+pub fn reboot() -> ! {
+    prepare("Rebooting");
+
+    if let Some((register, value)) = POWER_MANAGEMENT.get().and_then(|power| power.reset) {
+        match register.address_space {
+            AddressSpace::SystemIo => unsafe { Port::<u8>::new(register.address as u16).write(value) },
+            // Physical memory is identity mapped
+            AddressSpace::SystemMemory => unsafe { ptr::write_volatile(register.address as *mut u8, value) },
+            _ => warn!("Unsupported address space for ACPI reset register [{:?}]", register.address_space)
+        }
+    }
+
+    unsafe { Port::<u8>::new(KEYBOARD_CONTROLLER_PORT).write(KEYBOARD_CONTROLLER_RESET); }
+
+    // Any interrupt with an empty IDT results in a triple fault, which resets the CPU
+    unsafe {
+        lidt(&DescriptorTablePointer { limit: 0, base: VirtAddr::zero() });
+        asm!("int3");
+    }
+
+    halt();
+}
+
+/// Called by the worker thread, after the power button has been pressed.
+/// If the init process is running, it is asked to shut the system down in an orderly way via `Signal::Terminate`
+/// (stopping its services first). Otherwise, all user processes are terminated directly before the system is powered off.
+fn power_button_shutdown() {
+    if let Some(init) = init_process() {
+        info!("Power button pressed (Asking init process to shut down)");
+        init.raise(Signal::Terminate);
+        return;
+    }
+
+    info!("Power button pressed");
+    terminate_user_processes();
+    shutdown();
+}
+
+/// Ask all user processes except the current one to exit via `Signal::Terminate` and wait up to `TERMINATE_TIMEOUT_MS` for them.
+pub fn terminate_user_processes() {
+    let kernel_process_id = kernel_process().map(|process| process.id());
+    let current_process_id = current_process().id();
+    let others = || processes().into_iter().filter(|process| Some(process.id()) != kernel_process_id && process.id() != current_process_id).collect::<Vec<_>>();
+
+    info!("Terminating [{}] user processes", others().len());
+    for process in others() {
+        process.raise(Signal::Terminate);
+    }
+
+    // Signals are only delivered, when returning from a system call, so processes blocking forever are not waited for
+    let mut waited_ms = 0;
+    while !others().is_empty() && waited_ms < TERMINATE_TIMEOUT_MS {
+        scheduler().sleep(TERMINATE_POLL_INTERVAL_MS);
+        waited_ms += TERMINATE_POLL_INTERVAL_MS;
+    }
+
+    if !others().is_empty() {
+        warn!("[{}] user processes did not exit in time", others().len());
+    }
+}
+
+impl InterruptHandler for SciHandler {
+    fn trigger(&mut self) {
+        let power = match POWER_MANAGEMENT.get() {
+            Some(power) => power,
+            None => return
+        };
+
+        // Status bits are cleared by writing 1 to them
+        let status = power.read_pm1_status();
+        power.write_pm1_status(status);
+
+        if status & POWER_BUTTON != 0 && !SHUTDOWN_REQUESTED.swap(true, Relaxed) {
+            deferred::schedule_work(Box::new(power_button_shutdown));
+        }
+
+        // Logging locks the logger, which may be held by the interrupted thread, so the events are reported by the worker thread
+        let ignored = power.disable_gpes();
+        if !ignored.is_empty() {
+            deferred::schedule_work(Box::new(move || report_ignored_gpes(&ignored)));
+        }
+    }
+}
+
+impl PowerManagement {
+    /// Hand over power management from the firmware (SMM) to the OS, if this has not been done yet.
+    /// Without SCI_EN being set, writing the sleep registers may have no effect.
+    fn enable_acpi_mode(&self) {
+        let pm1a_control = match self.pm1a_control {
+            Some(port) => port,
+            None => return
+        };
+
+        let mut port = Port::<u16>::new(pm1a_control);
+        if unsafe { port.read() } & SCI_ENABLE != 0 || self.smi_command == 0 || self.acpi_enable == 0 {
+            return;
+        }
+
+        unsafe { Port::<u8>::new(self.smi_command).write(self.acpi_enable); }
+        for _ in 0..1000000 {
+            if unsafe { port.read() } & SCI_ENABLE != 0 {
+                return;
+            }
+
+            core::hint::spin_loop();
+        }
+
+        warn!("Failed to enable ACPI mode");
+    }
+
+    /// Status and enable registers are 16 bits wide, if the PM1 event blocks are 4 bytes long (as on all known chipsets).
+    fn read_pm1_status(&self) -> u16 {
+        let mut status = 0;
+        for block in [self.pm1a_event, self.pm1b_event].into_iter().flatten() {
+            status |= unsafe { Port::<u16>::new(block).read() };
+        }
+
+        return status;
+    }
+
+    fn write_pm1_status(&self, value: u16) {
+        for block in [self.pm1a_event, self.pm1b_event].into_iter().flatten() {
+            unsafe { Port::<u16>::new(block).write(value); }
+        }
+    }
+
+    fn write_pm1_enable(&self, value: u16) {
+        for block in [self.pm1a_event, self.pm1b_event].into_iter().flatten() {
+            unsafe { Port::<u16>::new(block + self.pm1_event_length as u16 / 2).write(value); }
+        }
+    }
+
+    /// Disable all general purpose events and clear their status, warning about events, which have occurred.
+    /// Returns the pending events, which have been acknowledged without handling them, as (register, events) pairs.
+    fn disable_gpes(&self) -> Vec<(u16, u8)> {
+        let mut ignored = Vec::new();
+        if let Some((block, length)) = self.gpe0 {
+            let registers = length as u16 / 2;
+            for register in 0..registers {
+                unsafe {
+                    Port::<u8>::new(block + registers + register).write(0);
+
+                    let mut status = Port::<u8>::new(block + register);
+                    let events = status.read();
+                    if events != 0 {
+                        status.write(events);
+                        ignored.push((register, events));
+                    }
+                }
+            }
+        }
+
+        return ignored;
+    }
+}
+
+fn report_ignored_gpes(ignored: &[(u16, u8)]) {
+    for (register, events) in ignored {
+        warn!("Ignoring general purpose events [0x{:02x}] in register [{}] (AML event handlers are not supported)", events, register);
+    }
+}
+
+/// Write back all modified data, before the system is powered off or reset.
+fn prepare(action: &str) {
+    info!("{} (Writing back modified data)", action);
+    if let Err(err) = vfs::sync() {
+        error!("Failed to write back modified data (Error: {:?})", err);
+    }
+
+    interrupts::disable();
+}
+
+fn halt() -> ! {
+    interrupts::disable();
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+fn present(available: bool) -> &'static str {
+    return if available { "yes" } else { "no" };
+}
+
+/// PM1 control blocks are located in I/O space on x86.
+fn io_port(register: GenericAddress) -> Option<u16> {
+    return match register.address_space {
+        AddressSpace::SystemIo if register.address != 0 => Some(register.address as u16),
+        _ => None
+    };
+}
+
+/// Search the AML code of the DSDT for the definition of the package '\_S5' and return its first two elements (SLP_TYPa and SLP_TYPb).
+/// Expected encoding: NameOp (0x08), ['\'], '_S5_', PackageOp (0x12), PkgLength, NumElements, followed by the elements,
+/// which are encoded as ZeroOp (0x00), OneOp (0x01), BytePrefix (0x0a) followed by the value or (by some firmware) as raw byte.
+/// Other encodings (e.g. a method call or a wider integer) are not supported, so `None` is returned instead of a guessed value.
+fn find_s5_sleep_types(aml: &[u8]) -> Option<(u16, u16)> {
+    let position = aml.windows(4).position(|window| window == b"_S5_")?;
+    let name_op = match position {
+        0 => return None,
+        _ if aml[position - 1] == 0x08 => true,
+        _ => position >= 2 && aml[position - 1] == b'\\' && aml[position - 2] == 0x08
+    };
+    if !name_op {
+        return None;
+    }
+
+    let mut index = position + 4;
+    if *aml.get(index)? != 0x12 {
+        return None;
+    }
+
+    // Skip PackageOp, PkgLength (bits 6-7 of the lead byte contain the number of following bytes) and NumElements
+    let pkg_length_bytes = (*aml.get(index + 1)? >> 6) as usize + 1;
+    index += 1 + pkg_length_bytes + 1;
+
+    let mut read_element = || -> Option<u16> {
+        let value = match *aml.get(index)? {
+            0x0a => {
+                index += 1;
+                *aml.get(index)?
+            }
+            // ZeroOp and OneOp have the same value as their raw bytes, while larger bytes are other opcodes
+            value if value <= MAX_SLEEP_TYPE => value,
+            _ => return None
+        };
+
+        index += 1;
+        return if value <= MAX_SLEEP_TYPE { Some(value as u16) } else { None };
+    };
+
+    let typ_a = read_element()?;
+    let typ_b = read_element()?;
+    return Some((typ_a, typ_b));
+}
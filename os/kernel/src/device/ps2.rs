@@ -1,40 +1,376 @@
+use crate::debug::gdb;
+use crate::device::input::{self, InputDevice, InputReader};
+use crate::device::terminal;
+use crate::device::tty;
+use crate::fs::devfs::Device;
+use crate::fs::{File, Metadata};
+use crate::fs::poll::Pollable;
 use crate::interrupt::interrupt_dispatcher::InterruptVector;
 use crate::interrupt::interrupt_handler::InterruptHandler;
+use crate::interrupt::deferred;
+use crate::process::thread::Thread;
+use crate::process::wait_queue::{WaitQueue, Waiter};
 use stream::InputStream;
 use alloc::boxed::Box;
-use log::info;
+use alloc::sync::Arc;
+use core::mem::size_of;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8};
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use log::{info, warn};
 use nolock::queues::mpmc::bounded::scq::{Receiver, Sender};
 use nolock::queues::{mpmc, DequeueError};
-use ps2::error::{ControllerError, KeyboardError};
+use ps2::error::{ControllerError, KeyboardError, MouseError};
 use ps2::flags::{ControllerConfigFlags, KeyboardLedFlags};
-use ps2::{Controller, KeyboardType};
-use spin::Mutex;
-use crate::{apic, interrupt_dispatcher, ps2_devices};
+use ps2::{Controller, KeyboardType, MouseType};
+use syscall::error::Errno;
+use syscall::file::{OpenFlags, PollEvents};
+use syscall::ioctl::KeyRepeat;
+use syscall::input::{InputEvent, InputEventType, Modifiers, MouseButtons, MouseEvent, BUTTON_LEFT, BUTTON_MIDDLE, BUTTON_RIGHT, RELATIVE_WHEEL, RELATIVE_X, RELATIVE_Y};
+use x86_64::instructions::interrupts;
+use crate::sync::Mutex;
+use crate::{active_console, apic, interrupt_dispatcher, ps2_devices, scheduler, scroll_console, switch_console, timer};
 
 const KEYBOARD_BUFFER_CAPACITY: usize = 128;
+const DEFAULT_KEY_REPEAT: KeyRepeat = KeyRepeat { delay_ms: 500, rate: 20 };
+const MAX_KEY_REPEAT_DELAY_MS: u32 = 10000;
+const MAX_KEY_REPEAT_RATE: u32 = 100;
+
+/// Key codes (see `ScancodeDecoder::key_code()`) of the modifier and lock keys and of keys used in kernel key combinations.
+const KEY_LEFT_CTRL: u16 = 29;
+const KEY_G: u16 = 34;
+const KEY_LEFT_SHIFT: u16 = 42;
+const KEY_C: u16 = 46;
+const KEY_RIGHT_SHIFT: u16 = 54;
+const KEY_LEFT_ALT: u16 = 56;
+const KEY_CAPS_LOCK: u16 = 58;
+const KEY_F1: u16 = 59;
+const KEY_F4: u16 = 62;
+const KEY_NUM_LOCK: u16 = 69;
+const KEY_SCROLL_LOCK: u16 = 70;
+const KEY_RIGHT_CTRL: u16 = 97;
+const KEY_RIGHT_ALT: u16 = 100;
+const KEY_PAGE_UP: u16 = 104;
+const KEY_PAGE_DOWN: u16 = 109;
+const KEY_LEFT_META: u16 = 125;
+const KEY_RIGHT_META: u16 = 126;
 
 pub struct PS2 {
     controller: Mutex<Controller>,
     keyboard: Keyboard,
+    /// None, if no mouse is connected to the second port.
+    mouse: Option<Mouse>,
 }
 
 pub struct Keyboard {
+    /// Raw scancodes for the terminal.
     buffer: (Receiver<u8>, Sender<u8>),
+    /// Notified, when scancodes have been stored in `buffer`, so that terminals waiting for input read them.
+    input_available: WaitQueue,
+    input_notification_pending: AtomicBool,
+    /// Key events for '/dev/input/eventN'.
+    input: Arc<InputDevice>,
+    /// Bits of `Modifiers`, updated by the interrupt handler.
+    modifiers: AtomicU8,
+    /// Keys are repeated in software by the key repeat thread (see `Keyboard::repeat_keys()`),
+    /// while repeated scancodes sent by the keyboard itself are dropped, so that the controller's settings do not matter.
+    repeat_delay_ms: AtomicU32,
+    repeat_rate: AtomicU32,
+    /// Locked by the interrupt handler, so it must only be locked with interrupts disabled in thread context.
+    repeat: Mutex<Option<Repeat>>,
+    /// Notified, when a key has been pressed or the settings have changed, so that the key repeat thread recalculates its deadline.
+    repeat_changed: WaitQueue,
+    repeat_notification_pending: AtomicBool,
+    /// Locked by the interrupt handler, so it must only be locked with interrupts disabled in thread context.
+    decoder: Mutex<ScancodeDecoder>,
+}
+
+/// The key, which is currently held down and repeated.
+struct Repeat {
+    code: u16,
+    /// Scancode of the key press (without the 0xe0 prefix of extended scancodes).
+    scancode: u8,
+    extended: bool,
+    /// Time of the next repetition.
+    deadline_ms: usize,
+}
+
+pub struct Mouse {
+    input: Arc<InputDevice>,
+    /// Mice with a scroll wheel send 4 byte packets instead of 3 (see `PS2::init_mouse()`).
+    packet_size: usize,
+}
+
+/// '/dev/mouse': Compatibility layer on top of the mouse's input device.
+/// Each opened file reads whole `MouseEvent` records, combining the input events up to a `InputEventType::Sync` event.
+pub struct MouseDevice {
+    mouse: &'static Mouse,
+}
+
+struct MouseFile {
+    reader: Arc<InputReader>,
+    /// Buttons are reported as state in `MouseEvent`, but only as changes by the input device.
+    buttons: Mutex<MouseButtons>,
+}
+
+/// Assembles the bytes of a packet, which are received with one interrupt each.
+struct MouseInterruptHandler {
+    packet: [u8; 4],
+    received: usize,
+    buttons: MouseButtons,
 }
 
-#[derive(Default)]
 struct KeyboardInterruptHandler;
 
+/// Tracks the keys, which are held down, the lock keys and the prefix bytes of extended scancodes.
+#[derive(Default)]
+struct ScancodeDecoder {
+    /// One bit per key code.
+    pressed: u128,
+    caps_lock: bool,
+    num_lock: bool,
+    extended: bool,
+    /// Remaining bytes of the pause key sequence (E1 1D 45 E1 9D C5), which are passed on to the terminal without decoding.
+    skip: usize,
+}
+
 impl Keyboard {
     fn new(buffer_cap: usize) -> Self {
         Self {
             buffer: mpmc::bounded::scq::queue(buffer_cap),
+            input_available: WaitQueue::new(),
+            input_notification_pending: AtomicBool::new(false),
+            input: input::register("PS/2 keyboard"),
+            modifiers: AtomicU8::new(0),
+            repeat_delay_ms: AtomicU32::new(DEFAULT_KEY_REPEAT.delay_ms),
+            repeat_rate: AtomicU32::new(DEFAULT_KEY_REPEAT.rate),
+            repeat: Mutex::new(None),
+            repeat_changed: WaitQueue::new(),
+            repeat_notification_pending: AtomicBool::new(false),
+            decoder: Mutex::new(ScancodeDecoder::default()),
         }
     }
 
     pub fn plugin(&self) {
-        interrupt_dispatcher().assign(InterruptVector::Keyboard, Box::new(KeyboardInterruptHandler::default()));
+        interrupt_dispatcher().assign(InterruptVector::Keyboard, Box::new(KeyboardInterruptHandler));
         apic().allow(InterruptVector::Keyboard);
+
+        scheduler().ready(Thread::new_kernel_thread(Box::new(|| {
+            ps2_devices().keyboard().repeat_keys();
+        })));
+    }
+
+    /// Return the next scancode, if one has been received (without blocking).
+    pub fn try_read_byte(&self) -> Option<u8> {
+        return self.buffer.0.try_dequeue().ok();
+    }
+
+    /// Terminals register here to wait for scancodes. Waiters must register before checking `try_read_byte()`,
+    /// so that scancodes received in the meantime are not missed.
+    pub fn input_available(&self) -> &WaitQueue {
+        return &self.input_available;
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        return Modifiers::from_bits_truncate(self.modifiers.load(Relaxed));
+    }
+
+    pub fn key_repeat(&self) -> KeyRepeat {
+        return KeyRepeat { delay_ms: self.repeat_delay_ms.load(Relaxed), rate: self.repeat_rate.load(Relaxed) };
+    }
+
+    pub fn set_key_repeat(&self, repeat: KeyRepeat) -> Result<(), Errno> {
+        if repeat.delay_ms > MAX_KEY_REPEAT_DELAY_MS || repeat.rate > MAX_KEY_REPEAT_RATE {
+            return Err(Errno::InvalidArgument);
+        }
+
+        self.repeat_delay_ms.store(repeat.delay_ms, Relaxed);
+        self.repeat_rate.store(repeat.rate, Relaxed);
+        self.repeat_changed.notify_all();
+        return Ok(());
+    }
+
+    /// Store raw scancode bytes for the terminal. The oldest bytes are dropped, if the buffer is full.
+    fn enqueue(&self, bytes: &[u8]) {
+        for &byte in bytes {
+            while self.buffer.1.try_enqueue(byte).is_err() {
+                if self.buffer.0.try_dequeue().is_err() {
+                    panic!("Keyboard: Failed to store received byte in buffer!");
+                }
+            }
+        }
+
+        // Like the key repeat thread, waiting terminals are woken up by the kernel worker thread
+        if !bytes.is_empty() && !self.input_notification_pending.swap(true, Acquire) {
+            deferred::schedule_work(Box::new(|| {
+                let keyboard = ps2_devices().keyboard();
+                keyboard.input_notification_pending.store(false, Release);
+                keyboard.input_available.notify_all();
+            }));
+        }
+    }
+
+    fn enqueue_scancode(&self, scancode: u8, extended: bool) {
+        if extended {
+            self.enqueue(&[0xe0, scancode]);
+        } else {
+            self.enqueue(&[scancode]);
+        }
+    }
+
+    /// Called by the interrupt handler for each key press or release (except for repeated presses).
+    fn report_key(&self, code: u16, scancode: u8, extended: bool, pressed: bool) {
+        self.enqueue_scancode(scancode, extended);
+        self.input.push(&[InputEvent::new(InputEventType::Key, code, pressed as i32), InputEvent::new(InputEventType::Sync, 0, 0)]);
+
+        let mut repeat = self.repeat.lock();
+        if pressed && !is_modifier(code) {
+            let delay = self.repeat_delay_ms.load(Relaxed) as usize;
+            *repeat = Some(Repeat { code, scancode, extended, deadline_ms: timer().read().systime_ms() + delay });
+            drop(repeat);
+
+            // Wait queues can not be accessed from interrupt handlers, so the key repeat thread is woken up by the kernel worker thread
+            if !self.repeat_notification_pending.swap(true, Acquire) {
+                deferred::schedule_work(Box::new(|| {
+                    let keyboard = ps2_devices().keyboard();
+                    keyboard.repeat_notification_pending.store(false, Release);
+                    keyboard.repeat_changed.notify_all();
+                }));
+            }
+        } else if !pressed && repeat.as_ref().is_some_and(|repeat| repeat.code == code) {
+            *repeat = None;
+        }
+    }
+
+    /// Decode a scancode byte (set 1), received from the PS/2 keyboard or translated by another keyboard driver (e.g. for USB keyboards).
+    /// Must be called with interrupts disabled, so that the bytes of different keyboards are not interleaved.
+    pub fn receive(&self, data: u8) {
+        let mut decoder = self.decoder.lock();
+        if decoder.skip > 0 {
+            decoder.skip -= 1;
+            self.enqueue(&[data]);
+            return;
+        }
+
+        match data {
+            // Passed on together with the next byte, so that a dropped repetition does not leave a dangling prefix
+            0xe0 => {
+                decoder.extended = true;
+                return;
+            }
+            0xe1 => {
+                decoder.skip = 5;
+                self.enqueue(&[data]);
+                return;
+            }
+            // Acknowledge and resend responses to commands
+            0xfa | 0xfe => return,
+            _ => {}
+        }
+
+        let extended = core::mem::replace(&mut decoder.extended, false);
+        let scancode = data & 0x7f;
+        let pressed = data & 0x80 == 0;
+        let code = match ScancodeDecoder::key_code(scancode, extended) {
+            Some(code) => code,
+            None => {
+                self.enqueue_scancode(data, extended);
+                return;
+            }
+        };
+
+        // Keys held down are repeated by the keyboard itself, which is replaced by the key repeat thread
+        if pressed && decoder.is_pressed(code) {
+            return;
+        }
+
+        decoder.pressed = if pressed { decoder.pressed | (1 << code) } else { decoder.pressed & !(1 << code) };
+        match code {
+            KEY_CAPS_LOCK if pressed => decoder.caps_lock = !decoder.caps_lock,
+            KEY_NUM_LOCK if pressed => decoder.num_lock = !decoder.num_lock,
+            _ => {}
+        }
+
+        let modifiers = decoder.modifiers();
+        self.modifiers.store(modifiers.bits(), Relaxed);
+
+        // Key combinations handled by the kernel are not passed on
+        if pressed {
+            let alt = modifiers.intersects(Modifiers::ALT | Modifiers::ALT_GR);
+            match code {
+                // Break into the GDB stub
+                KEY_G if modifiers.contains(Modifiers::CTRL) && alt && gdb::is_enabled() => {
+                    gdb::breakpoint();
+                    return;
+                }
+                // Interrupt the foreground process of the active console (unless Ctrl+C is read as character, see `TermiosFlags::SIGNALS`)
+                KEY_C if modifiers.contains(Modifiers::CTRL) && !alt && tty::signals_enabled(active_console()) => {
+                    deferred::schedule_work(Box::new(|| tty::interrupt(active_console())));
+                    return;
+                }
+                // Switch to another virtual console (which redraws the whole screen, so it is not done by the interrupt handler)
+                KEY_F1..=KEY_F4 if modifiers.contains(Modifiers::ALT) => {
+                    deferred::schedule_work(Box::new(move || switch_console((code - KEY_F1) as usize)));
+                    return;
+                }
+                // Scroll through the scrollback buffer of the active console by half a screen
+                KEY_PAGE_UP | KEY_PAGE_DOWN if modifiers.contains(Modifiers::SHIFT) => {
+                    deferred::schedule_work(Box::new(move || {
+                        let lines = (crate::terminal().window_size().rows / 2).max(1) as isize;
+                        scroll_console(if code == KEY_PAGE_UP { lines } else { -lines });
+                    }));
+                    return;
+                }
+                _ => {}
+            }
+        }
+
+        self.report_key(code, data, extended, pressed);
+        terminal::notify_input();
+    }
+
+    /// Executed by the key repeat thread: Repeat the key, which has been pressed last, as long as it is held down.
+    fn repeat_keys(&self) {
+        loop {
+            // Registered before checking, so that a key press in between is not missed
+            let waiter = Waiter::new();
+            self.repeat_changed.register(&waiter);
+
+            let settings = self.key_repeat();
+            let now = timer().read().systime_ms();
+            let (deadline, repeated) = interrupts::without_interrupts(|| {
+                let mut repeat = self.repeat.lock();
+                if settings.rate == 0 {
+                    *repeat = None;
+                }
+
+                let Some(current) = repeat.as_mut() else {
+                    return (None, false);
+                };
+
+                let repeated = now >= current.deadline_ms;
+                if repeated {
+                    self.report_repeat(current);
+                    // Repetitions are not caught up, if the thread has been delayed
+                    current.deadline_ms = now + 1000 / settings.rate as usize;
+                }
+
+                (Some(current.deadline_ms), repeated)
+            });
+
+            if repeated {
+                terminal::notify_input();
+            }
+
+            scheduler().block_on(&waiter, deadline.map(|deadline| deadline.saturating_sub(now) * 1000000));
+            waiter.wake();
+        }
+    }
+
+    /// Must be called with interrupts disabled, so that the bytes of an extended scancode are not separated by the interrupt handler.
+    fn report_repeat(&self, repeat: &Repeat) {
+        self.enqueue_scancode(repeat.scancode, repeat.extended);
+        self.input.push(&[InputEvent::new(InputEventType::Key, repeat.code, 2), InputEvent::new(InputEventType::Sync, 0, 0)]);
     }
 }
 
@@ -50,28 +386,264 @@ impl InputStream for Keyboard {
     }
 }
 
+impl ScancodeDecoder {
+    /// Translate a scancode (set 1, without the 0xe0 prefix) into a key code.
+    /// Scancodes of set 1 are equal to the key codes, except for the extended ones.
+    fn key_code(scancode: u8, extended: bool) -> Option<u16> {
+        if !extended {
+            return Some(scancode as u16);
+        }
+
+        return match scancode {
+            0x1c => Some(96),  // Keypad enter
+            0x1d => Some(KEY_RIGHT_CTRL),
+            0x35 => Some(98),  // Keypad slash
+            0x37 => Some(99),  // Print screen
+            0x38 => Some(KEY_RIGHT_ALT),
+            0x47 => Some(102), // Home
+            0x48 => Some(103), // Up
+            0x49 => Some(KEY_PAGE_UP),
+            0x4b => Some(105), // Left
+            0x4d => Some(106), // Right
+            0x4f => Some(107), // End
+            0x50 => Some(108), // Down
+            0x51 => Some(KEY_PAGE_DOWN),
+            0x52 => Some(110), // Insert
+            0x53 => Some(111), // Delete
+            0x5b => Some(KEY_LEFT_META),
+            0x5c => Some(KEY_RIGHT_META),
+            0x5d => Some(127), // Menu
+            // Fake shift presses, sent around some extended keys (e.g. print screen), and unsupported keys
+            _ => None
+        };
+    }
+
+    fn is_pressed(&self, code: u16) -> bool {
+        return self.pressed & (1 << code) != 0;
+    }
+
+    fn modifiers(&self) -> Modifiers {
+        let mut modifiers = Modifiers::empty();
+        modifiers.set(Modifiers::SHIFT, self.is_pressed(KEY_LEFT_SHIFT) || self.is_pressed(KEY_RIGHT_SHIFT));
+        modifiers.set(Modifiers::CTRL, self.is_pressed(KEY_LEFT_CTRL) || self.is_pressed(KEY_RIGHT_CTRL));
+        modifiers.set(Modifiers::ALT, self.is_pressed(KEY_LEFT_ALT));
+        modifiers.set(Modifiers::ALT_GR, self.is_pressed(KEY_RIGHT_ALT));
+        modifiers.set(Modifiers::META, self.is_pressed(KEY_LEFT_META) || self.is_pressed(KEY_RIGHT_META));
+        modifiers.set(Modifiers::CAPS_LOCK, self.caps_lock);
+        modifiers.set(Modifiers::NUM_LOCK, self.num_lock);
+
+        return modifiers;
+    }
+}
+
 impl InterruptHandler for KeyboardInterruptHandler {
     fn trigger(&mut self) {
-        if let Some(mut controller) = ps2_devices().controller.try_lock() {
-            if let Ok(data) = controller.read_data() {
-                let keyboard = ps2_devices().keyboard();
-                while keyboard.buffer.1.try_enqueue(data).is_err() {
-                    if keyboard.buffer.0.try_dequeue().is_err() {
-                        panic!("Keyboard: Failed to store received byte in buffer!");
-                    }
+        let data = match ps2_devices().controller.try_lock() {
+            Some(mut controller) => match controller.read_data() {
+                Ok(data) => data,
+                Err(_) => return
+            },
+            None => panic!("Keyboard: Controller is locked during interrupt!")
+        };
+
+        ps2_devices().keyboard().receive(data);
+    }
+}
+
+/// Modifier and lock keys are not repeated.
+fn is_modifier(code: u16) -> bool {
+    return matches!(code, KEY_LEFT_CTRL | KEY_RIGHT_CTRL | KEY_LEFT_SHIFT | KEY_RIGHT_SHIFT | KEY_LEFT_ALT | KEY_RIGHT_ALT
+        | KEY_LEFT_META | KEY_RIGHT_META | KEY_CAPS_LOCK | KEY_NUM_LOCK | KEY_SCROLL_LOCK);
+}
+
+impl Mouse {
+    fn new(packet_size: usize) -> Self {
+        Self {
+            input: input::register("PS/2 mouse"),
+            packet_size,
+        }
+    }
+
+    pub fn plugin(&self) {
+        interrupt_dispatcher().assign(InterruptVector::Mouse, Box::new(MouseInterruptHandler { packet: [0; 4], received: 0, buttons: MouseButtons::empty() }));
+        apic().allow(InterruptVector::Mouse);
+    }
+
+    pub fn has_scroll_wheel(&self) -> bool {
+        return self.packet_size == 4;
+    }
+}
+
+impl MouseDevice {
+    pub fn new(mouse: &'static Mouse) -> Self {
+        Self { mouse }
+    }
+}
+
+impl Device for MouseDevice {
+    fn open(&self, flags: OpenFlags) -> Result<Option<Arc<dyn File>>, Errno> {
+        if flags.contains(OpenFlags::WRITE) {
+            return Err(Errno::InvalidArgument);
+        }
+
+        return Ok(Some(Arc::new(MouseFile { reader: self.mouse.input.open(), buttons: Mutex::new(MouseButtons::empty()) })));
+    }
+}
+
+impl MouseFile {
+    /// Combine input events up to the next `InputEventType::Sync` event into a `MouseEvent`.
+    /// Returns `None` without blocking, if `blocking` is false and no event is available.
+    fn read_event(&self, blocking: bool) -> Option<MouseEvent> {
+        let mut event = MouseEvent { dx: 0, dy: 0, wheel: 0, buttons: *self.buttons.lock() };
+        let mut started = false;
+
+        loop {
+            // Once the first input event of a record has been consumed, the rest of the record is waited for
+            let input = if blocking || started {
+                self.reader.read_event()
+            } else {
+                self.reader.try_read_event()?
+            };
+            started = true;
+
+            match input.typ {
+                InputEventType::Sync => {
+                    *self.buttons.lock() = event.buttons;
+                    return Some(event);
+                }
+                InputEventType::Relative => match input.code {
+                    RELATIVE_X => event.dx = event.dx.saturating_add(input.value as i16),
+                    RELATIVE_Y => event.dy = event.dy.saturating_add(input.value as i16),
+                    RELATIVE_WHEEL => event.wheel = event.wheel.saturating_add(input.value as i8),
+                    _ => {}
+                },
+                InputEventType::Key => {
+                    let button = match input.code {
+                        BUTTON_LEFT => MouseButtons::LEFT,
+                        BUTTON_RIGHT => MouseButtons::RIGHT,
+                        BUTTON_MIDDLE => MouseButtons::MIDDLE,
+                        _ => MouseButtons::empty()
+                    };
+                    event.buttons.set(button, input.value != 0);
                 }
             }
-        } else {
-            panic!("Keyboard: Controller is locked during interrupt!");
         }
     }
 }
 
+impl File for MouseFile {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, Errno> {
+        if buffer.len() < size_of::<MouseEvent>() {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let mut count = 0;
+        for chunk in buffer.chunks_exact_mut(size_of::<MouseEvent>()) {
+            // Only the first event is waited for
+            let event = match self.read_event(count == 0) {
+                Some(event) => event,
+                None => break
+            };
+
+            unsafe { ptr::copy_nonoverlapping(ptr::from_ref(&event) as *const u8, chunk.as_mut_ptr(), size_of::<MouseEvent>()); }
+            count += size_of::<MouseEvent>();
+        }
+
+        return Ok(count);
+    }
+
+    fn write(&self, _buffer: &[u8]) -> Result<usize, Errno> {
+        return Err(Errno::BadDescriptor);
+    }
+
+    fn stat(&self) -> Result<Metadata, Errno> {
+        return self.reader.stat();
+    }
+}
+
+impl Pollable for MouseFile {
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        return self.reader.poll(events, waiter);
+    }
+}
+
+impl InterruptHandler for MouseInterruptHandler {
+    fn trigger(&mut self) {
+        let data = match ps2_devices().controller.try_lock() {
+            Some(mut controller) => match controller.read_data() {
+                Ok(data) => data,
+                Err(_) => return
+            },
+            None => panic!("Mouse: Controller is locked during interrupt!")
+        };
+
+        // The first byte of a packet always has bit 3 set, which allows to resynchronize after a lost byte
+        if self.received == 0 && data & 0x08 == 0 {
+            return;
+        }
+
+        let mouse = ps2_devices().mouse().expect("Mouse: Interrupt without mouse!");
+        self.packet[self.received] = data;
+        self.received += 1;
+
+        if self.received == mouse.packet_size {
+            self.received = 0;
+            if let Some(event) = decode_packet(&self.packet[..mouse.packet_size]) {
+                self.report(&mouse.input, event);
+            }
+        }
+    }
+}
+
+impl MouseInterruptHandler {
+    /// Push the changes described by `event` to the input device (at most 3 movements, 3 buttons and a sync event).
+    fn report(&mut self, input: &Arc<InputDevice>, event: MouseEvent) {
+        let mut events = [InputEvent::new(InputEventType::Sync, 0, 0); 7];
+        let mut count = 0;
+
+        for (code, value) in [(RELATIVE_X, event.dx as i32), (RELATIVE_Y, event.dy as i32), (RELATIVE_WHEEL, event.wheel as i32)] {
+            if value != 0 {
+                events[count] = InputEvent::new(InputEventType::Relative, code, value);
+                count += 1;
+            }
+        }
+
+        for (code, button) in [(BUTTON_LEFT, MouseButtons::LEFT), (BUTTON_RIGHT, MouseButtons::RIGHT), (BUTTON_MIDDLE, MouseButtons::MIDDLE)] {
+            if event.buttons.contains(button) != self.buttons.contains(button) {
+                events[count] = InputEvent::new(InputEventType::Key, code, event.buttons.contains(button) as i32);
+                count += 1;
+            }
+        }
+
+        self.buttons = event.buttons;
+        // The last entry is left as sync event
+        input.push(&events[..=count]);
+    }
+}
+
+/// Convert a movement packet into an event. Packets with overflowing movement values are dropped.
+pub fn decode_packet(packet: &[u8]) -> Option<MouseEvent> {
+    let flags = packet[0];
+    if flags & 0xc0 != 0 {
+        return None;
+    }
+
+    // Movement values are 9 bit two's complement numbers, with the sign bits being part of the first byte
+    let dx = packet[1] as i16 - (((flags as i16) << 4) & 0x100);
+    let dy = packet[2] as i16 - (((flags as i16) << 3) & 0x100);
+    // The wheel movement is a 4 bit two's complement number
+    let wheel = packet.get(3).map_or(0, |&wheel| ((wheel << 4) as i8) >> 4);
+
+    // PS/2 mice report upward movement as positive, while screen coordinates grow downwards
+    return Some(MouseEvent { dx, dy: -dy, wheel, buttons: MouseButtons::from_bits_truncate(flags & 0x07) });
+}
+
 impl PS2 {
     pub fn new() -> Self {
         Self {
             controller: unsafe { Mutex::new(Controller::new()) },
             keyboard: Keyboard::new(KEYBOARD_BUFFER_CAPACITY),
+            mouse: None,
         }
     }
 
@@ -115,14 +687,14 @@ impl PS2 {
             controller.write_config(config)?;
             info!("First port enabled");
         } else {
-            panic!("No keyboard detected!");
+            warn!("No keyboard detected!");
         }
 
         // Check if mouse is present
         if controller.test_mouse().is_ok() {
             // Enable mouse
             info!("Second port detected");
-            controller.enable_keyboard()?;
+            controller.enable_mouse()?;
             config.set(ControllerConfigFlags::DISABLE_MOUSE, false);
             config.set(ControllerConfigFlags::ENABLE_MOUSE_INTERRUPT, true);
             controller.write_config(config)?;
@@ -137,9 +709,7 @@ impl PS2 {
         let mut controller = self.controller.lock();
 
         // Perform self test on keyboard
-        if controller.keyboard().reset_and_self_test().is_err() {
-            panic!("Keyboard is not working!");
-        }
+        controller.keyboard().reset_and_self_test()?;
         info!("Keyboard has been reset and self test result is OK");
 
         // Enable keyboard translation if needed
@@ -170,7 +740,34 @@ impl PS2 {
         return Ok(());
     }
 
+    pub fn init_mouse(&mut self) -> Result<(), MouseError> {
+        info!("Initializing mouse");
+        let mut controller = self.controller.lock();
+        controller.mouse().reset_and_self_test()?;
+
+        // Mice with a scroll wheel switch to 4 byte packets after this magic sequence of sample rates
+        controller.mouse().set_sample_rate(200)?;
+        controller.mouse().set_sample_rate(100)?;
+        controller.mouse().set_sample_rate(80)?;
+        let packet_size = match controller.mouse().get_mouse_type()? {
+            MouseType::Standard => 3,
+            _ => 4
+        };
+        info!("Mouse has [{}] scroll wheel", if packet_size == 4 { "a" } else { "no" });
+
+        controller.mouse().set_sample_rate(100)?;
+        controller.mouse().enable_data_reporting()?;
+        drop(controller);
+
+        self.mouse = Some(Mouse::new(packet_size));
+        return Ok(());
+    }
+
     pub fn keyboard(&self) -> &Keyboard {
         return &self.keyboard;
     }
+
+    pub fn mouse(&self) -> Option<&Mouse> {
+        return self.mouse.as_ref();
+    }
 }
@@ -0,0 +1,46 @@
+use crate::fs::devfs::Device;
+use crate::fs::Result;
+use crate::random;
+
+/// '/dev/null': Discards everything written to it and returns end of file on read.
+pub struct NullDevice;
+
+/// '/dev/zero': Returns an infinite stream of zero bytes.
+pub struct ZeroDevice;
+
+/// '/dev/random': Returns cryptographically secure random bytes from the kernel CSPRNG (see `random`).
+/// Data written to it is mixed into the generator state.
+pub struct RandomDevice;
+
+impl Device for NullDevice {
+    fn read(&self, _offset: usize, _buffer: &mut [u8]) -> Result<usize> {
+        return Ok(0);
+    }
+
+    fn write(&self, _offset: usize, buffer: &[u8]) -> Result<usize> {
+        return Ok(buffer.len());
+    }
+}
+
+impl Device for ZeroDevice {
+    fn read(&self, _offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        buffer.fill(0);
+        return Ok(buffer.len());
+    }
+
+    fn write(&self, _offset: usize, buffer: &[u8]) -> Result<usize> {
+        return Ok(buffer.len());
+    }
+}
+
+impl Device for RandomDevice {
+    fn read(&self, _offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        random::fill(buffer);
+        return Ok(buffer.len());
+    }
+
+    fn write(&self, _offset: usize, buffer: &[u8]) -> Result<usize> {
+        random::add_entropy(buffer);
+        return Ok(buffer.len());
+    }
+}
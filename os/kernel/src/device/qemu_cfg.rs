@@ -1,31 +1,189 @@
-use crate::device::qemu_cfg::Selector::Signature;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
 use x86_64::instructions::port::{PortReadOnly, PortWriteOnly};
+use crate::device::qemu_cfg::Selector::{FileDirectory, Id, Signature};
+use crate::memory::{physical, PAGE_SIZE};
+use crate::sync::Mutex;
 
 const SELECTOR_PORT: u16 = 0x510;
 const DATA_PORT: u16 = 0x511;
+/// The DMA address is written as big endian 64-bit value, whose lower half (at port 0x518) starts the transfer.
+const DMA_ADDRESS_PORT: u16 = 0x514;
+const DEBUG_EXIT_PORT: u16 = 0xf4;
+
+const FEATURE_DMA: u32 = 1 << 1;
+const DMA_CONTROL_ERROR: u32 = 1 << 0;
+const DMA_CONTROL_READ: u32 = 1 << 1;
+const DMA_CONTROL_SELECT: u32 = 1 << 3;
+
+const FILE_NAME_LENGTH: usize = 56;
+/// Additional kernel command line options (e.g. '-fw_cfg name=opt/hhutosr/cmdline,string=loglevel=debug').
+const BOOT_OPTIONS_FILE: &str = "opt/hhutosr/cmdline";
+
+/// The selected item and its read offset are shared by all users of the interface.
+static ACCESS: Mutex<()> = Mutex::new(());
 
-#[allow(dead_code)]
 #[repr(u16)]
 enum Selector {
     Signature = 0x0000,
     Id = 0x0001,
-    RootDirectory = 0x0019,
+    FileDirectory = 0x0019,
+}
+
+/// Exit codes for `debug_exit()`. QEMU exits with `(code << 1) | 1`, so they result in the exit status 33, 35 and 37,
+/// which cannot be confused with QEMU's own failure status 1.
+#[repr(u8)]
+pub enum ExitCode {
+    Success = 0x10,
+    TestFailure = 0x11,
+    Panic = 0x12,
+}
+
+/// Entry of the fw_cfg file directory (e.g. 'etc/e820' or files passed with '-fw_cfg name=opt/...').
+pub struct FwCfgFile {
+    name: String,
+    size: usize,
+    selector: u16,
+}
+
+/// Descriptor of a DMA transfer (all fields are big endian). The controller clears the control field, when the transfer is done.
+#[repr(C)]
+struct DmaAccess {
+    control: u32,
+    length: u32,
+    address: u64,
 }
 
 pub fn is_available() -> bool {
-    let mut selector_port = PortWriteOnly::<u16>::new(SELECTOR_PORT);
+    let _access = ACCESS.lock();
+    let mut id = [0u8; 4];
+    read_item(Signature as u16, &mut id);
+
+    return &id == b"QEMU";
+}
+
+impl FwCfgFile {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+/// List all files, which QEMU provides via fw_cfg. Returns an empty list, if the interface is not available.
+pub fn files() -> Vec<FwCfgFile> {
+    if !is_available() {
+        return Vec::new();
+    }
+
+    let _access = ACCESS.lock();
+    let mut count = [0u8; 4];
+    read_item(FileDirectory as u16, &mut count);
+
+    // Directory entries follow the count (size, selector, reserved field and zero terminated name)
+    let mut files = Vec::new();
+    for _ in 0..u32::from_be_bytes(count) {
+        let mut entry = [0u8; 8 + FILE_NAME_LENGTH];
+        read_data(&mut entry);
+
+        let name = &entry[8..];
+        let length = name.iter().position(|&c| c == 0).unwrap_or(FILE_NAME_LENGTH);
+        files.push(FwCfgFile {
+            name: String::from_utf8_lossy(&name[..length]).into_owned(),
+            size: u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]) as usize,
+            selector: u16::from_be_bytes([entry[4], entry[5]]),
+        });
+    }
+
+    return files;
+}
+
+/// Read the content of the fw_cfg file called `name` (e.g. a test payload passed with '-fw_cfg name=opt/hhutosr/...,file=...').
+/// Large files are transferred via DMA, if QEMU supports it.
+pub fn read_file(name: &str) -> Option<Vec<u8>> {
+    let file = files().into_iter().find(|file| file.name == name)?;
+    let mut content = vec![0u8; file.size];
+
+    let _access = ACCESS.lock();
+    let mut features = [0u8; 4];
+    read_item(Id as u16, &mut features);
+
+    if u32::from_le_bytes(features) & FEATURE_DMA == 0 || !read_item_dma(file.selector, &mut content) {
+        read_item(file.selector, &mut content);
+    }
+
+    return Some(content);
+}
+
+/// Additional kernel command line options, which have been passed via fw_cfg.
+pub fn boot_options() -> Option<String> {
+    let options = read_file(BOOT_OPTIONS_FILE)?;
+    return Some(String::from_utf8_lossy(&options).trim_end_matches('\0').into());
+}
+
+/// Terminate QEMU via the isa-debug-exit device (see `ExitCode` for the resulting exit status).
+/// Does nothing, if the device is not present (e.g. on real hardware).
+pub fn debug_exit(code: ExitCode) {
+    unsafe { PortWriteOnly::<u8>::new(DEBUG_EXIT_PORT).write(code as u8); }
+}
+
+/// Select `selector` and read the start of the item into `buffer`. Must be called with `ACCESS` locked.
+fn read_item(selector: u16, buffer: &mut [u8]) {
+    unsafe { PortWriteOnly::<u16>::new(SELECTOR_PORT).write(selector); }
+    read_data(buffer);
+}
+
+/// Continue reading the selected item. Reading beyond its end returns zeros.
+fn read_data(buffer: &mut [u8]) {
     let mut data_port = PortReadOnly::<u8>::new(DATA_PORT);
-    let id: [u8; 4];
+    for byte in buffer.iter_mut() {
+        *byte = unsafe { data_port.read() };
+    }
+}
+
+/// Select `selector` and read the start of the item into `buffer` via DMA. Returns `false`, if QEMU reports an error.
+/// Must be called with `ACCESS` locked.
+fn read_item_dma(selector: u16, buffer: &mut [u8]) -> bool {
+    // Physical memory is identity mapped, so the descriptor and the data are placed into freshly allocated frames
+    let frames = physical::alloc((size_of::<DmaAccess>() + buffer.len()).div_ceil(PAGE_SIZE));
+    let descriptor = frames.start.start_address().as_u64();
+    let data = descriptor + size_of::<DmaAccess>() as u64;
 
+    let access = descriptor as *mut DmaAccess;
     unsafe {
-        selector_port.write(Signature as u16);
-        id = [
-            data_port.read(),
-            data_port.read(),
-            data_port.read(),
-            data_port.read(),
-        ]
+        access.write_volatile(DmaAccess {
+            control: ((selector as u32) << 16 | DMA_CONTROL_SELECT | DMA_CONTROL_READ).to_be(),
+            length: (buffer.len() as u32).to_be(),
+            address: data.to_be(),
+        });
+
+        fence(Ordering::Release);
+        PortWriteOnly::<u32>::new(DMA_ADDRESS_PORT).write(((descriptor >> 32) as u32).to_be());
+        PortWriteOnly::<u32>::new(DMA_ADDRESS_PORT + 4).write((descriptor as u32).to_be());
+    }
+
+    // QEMU completes the transfer synchronously, but the control field is checked anyway
+    let control = loop {
+        let control = u32::from_be(unsafe { ptr::addr_of!((*access).control).read_volatile() });
+        if control & !DMA_CONTROL_ERROR == 0 {
+            break control;
+        }
+
+        core::hint::spin_loop();
+    };
+
+    let success = control & DMA_CONTROL_ERROR == 0;
+    if success {
+        fence(Ordering::Acquire);
+        unsafe { ptr::copy_nonoverlapping(data as *const u8, buffer.as_mut_ptr(), buffer.len()); }
     }
 
-    return id[0] == 'Q' as u8 && id[1] == 'E' as u8 && id[2] == 'M' as u8 && id[3] == 'U' as u8;
+    unsafe { physical::free(frames); }
+    return success;
 }
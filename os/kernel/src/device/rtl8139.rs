@@ -0,0 +1,298 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ptr;
+use core::sync::atomic::Ordering::Relaxed;
+use log::info;
+use syscall::error::Errno;
+use x86_64::instructions::port::Port;
+use crate::device::pci::driver::{PciDriver, PciId};
+use crate::device::pci::{CommandFlag, PciDevice};
+use crate::fs::Result;
+use crate::interrupt::interrupt_handler::InterruptHandler;
+use crate::memory::{physical, PAGE_SIZE};
+use crate::net::{Interface, MacAddress, NetworkDevice};
+use crate::process::wait_queue::WaitQueue;
+use crate::sync::Mutex;
+use crate::{apic, interrupt_dispatcher, net, timer};
+
+/// Size of the receive ring (without the 16 bytes of slack, which the controller needs after its end).
+const RECEIVE_BUFFER_SIZE: usize = 8192;
+/// In wrap mode, frames at the end of the ring are written beyond its end instead of wrapping around,
+/// so the buffer has room for a whole frame after the ring.
+const RECEIVE_BUFFER_PAGES: usize = (RECEIVE_BUFFER_SIZE + 16 + MAX_FRAME_SIZE).div_ceil(PAGE_SIZE);
+const TRANSMIT_DESCRIPTOR_COUNT: usize = 4;
+/// Each transmit descriptor has its own buffer (the controller supports at most 1792 bytes per frame).
+const TRANSMIT_BUFFER_SIZE: usize = 2048;
+const TRANSMIT_BUFFER_PAGES: usize = (TRANSMIT_DESCRIPTOR_COUNT * TRANSMIT_BUFFER_SIZE).div_ceil(PAGE_SIZE);
+const MTU: usize = 1500;
+/// Ethernet header and payload (the frame check sequence is added by the controller).
+const MAX_FRAME_SIZE: usize = MTU + 14;
+/// Shorter frames are padded by software, since the controller does not pad them itself.
+const MIN_FRAME_SIZE: usize = 60;
+const FRAME_CHECK_SEQUENCE_SIZE: usize = 4;
+const RESET_TIMEOUT_MS: usize = 1000;
+const TRANSMIT_TIMEOUT_MS: usize = 1000;
+
+// Registers (I/O space via BAR 0)
+const MAC_ADDRESS: u16 = 0x00;
+const TRANSMIT_STATUS: u16 = 0x10;
+const TRANSMIT_ADDRESS: u16 = 0x20;
+const RECEIVE_BUFFER_START: u16 = 0x30;
+const COMMAND: u16 = 0x37;
+/// Current address of packet read (the offset, up to which the driver has read the ring, minus 16).
+const RECEIVE_READ_ADDRESS: u16 = 0x38;
+const INTERRUPT_MASK: u16 = 0x3c;
+const INTERRUPT_STATUS: u16 = 0x3e;
+const TRANSMIT_CONFIGURATION: u16 = 0x40;
+const RECEIVE_CONFIGURATION: u16 = 0x44;
+const CONFIG_1: u16 = 0x52;
+
+const COMMAND_BUFFER_EMPTY: u8 = 1 << 0;
+const COMMAND_TRANSMIT_ENABLE: u8 = 1 << 2;
+const COMMAND_RECEIVE_ENABLE: u8 = 1 << 3;
+const COMMAND_RESET: u8 = 1 << 4;
+
+const INTERRUPT_RECEIVE_OK: u16 = 1 << 0;
+const INTERRUPT_RECEIVE_ERROR: u16 = 1 << 1;
+const INTERRUPT_TRANSMIT_OK: u16 = 1 << 2;
+const INTERRUPT_TRANSMIT_ERROR: u16 = 1 << 3;
+const INTERRUPT_RECEIVE_OVERFLOW: u16 = 1 << 4;
+const INTERRUPT_FIFO_OVERFLOW: u16 = 1 << 6;
+const INTERRUPTS: u16 = INTERRUPT_RECEIVE_OK | INTERRUPT_RECEIVE_ERROR | INTERRUPT_TRANSMIT_OK | INTERRUPT_TRANSMIT_ERROR | INTERRUPT_RECEIVE_OVERFLOW | INTERRUPT_FIFO_OVERFLOW;
+
+/// Set by the controller, when it has copied the frame into its FIFO (the buffer may be reused then).
+const TRANSMIT_STATUS_OWN: u32 = 1 << 13;
+/// Maximum DMA burst size of 1024 bytes and the default inter frame gap.
+const TRANSMIT_CONFIGURATION_DEFAULT: u32 = 6 << 8 | 3 << 24;
+
+const RECEIVE_ACCEPT_PHYSICAL_MATCH: u32 = 1 << 1;
+const RECEIVE_ACCEPT_MULTICAST: u32 = 1 << 2;
+const RECEIVE_ACCEPT_BROADCAST: u32 = 1 << 3;
+const RECEIVE_WRAP: u32 = 1 << 7;
+/// Unlimited DMA burst size and no receive FIFO threshold (frames are transferred after they have been received completely).
+const RECEIVE_CONFIGURATION_DEFAULT: u32 = 7 << 8 | 7 << 13;
+
+/// Set in the header, which precedes each frame in the receive ring.
+const RECEIVE_STATUS_OK: u16 = 1 << 0;
+
+/// Realtek RTL8139 Fast Ethernet controller. In contrast to descriptor based controllers, it receives all frames into a single ring buffer,
+/// each preceded by a 4 byte header (status and length). Frames are sent from one of four buffers, which are used round robin.
+struct Rtl8139 {
+    base: u16,
+    mac_address: MacAddress,
+    /// Physical (identity mapped) addresses of the receive ring and the transmit buffers.
+    receive_buffer: u64,
+    transmit_buffers: u64,
+    /// Index of the transmit descriptor, which is used next.
+    next_transmit: Mutex<usize>,
+    /// Notified by the interrupt handler, when the controller has finished sending a frame (and released its buffer).
+    transmit_done: WaitQueue,
+}
+
+struct Rtl8139InterruptHandler {
+    controller: Arc<Rtl8139>,
+    interface: Arc<Interface>,
+    /// Offset of the next frame in the receive ring.
+    receive_offset: usize,
+}
+
+/// Driver for RTL8139 network controllers (emulated by QEMU's '-device rtl8139'), which registers them as network interfaces.
+pub static DRIVER: PciDriver = PciDriver { name: "rtl8139", ids: &[PciId::device(0x10ec, 0x8139)], probe };
+
+fn probe(device: &Arc<PciDevice>) -> Result<()> {
+    device.set_command_flag(CommandFlag::IoSpace, true);
+    device.set_command_flag(CommandFlag::BusMaster, true);
+
+    let controller = Arc::new(Rtl8139::new(device.bar(0) as u16)?);
+    let interface = net::register("eth", Arc::clone(&controller) as Arc<dyn NetworkDevice>);
+
+    let irq = device.interrupt_line();
+    interrupt_dispatcher().assign_irq(irq, Box::new(Rtl8139InterruptHandler { controller: Arc::clone(&controller), interface: Arc::clone(&interface), receive_offset: 0 }));
+    apic().allow_irq(irq);
+    controller.write_u16(INTERRUPT_MASK, INTERRUPTS);
+
+    info!("RTL8139 network controller [{}]: Interface: [{}], MAC: [{}], IRQ: [{}]", device.address(), interface.name(), controller.mac_address, irq);
+    return Ok(());
+}
+
+impl Rtl8139 {
+    fn new(base: u16) -> Result<Self> {
+        // The controller can only access the lower 4 GiB
+        let frames = physical::alloc(RECEIVE_BUFFER_PAGES + TRANSMIT_BUFFER_PAGES);
+        if frames.end.start_address().as_u64() > u32::MAX as u64 {
+            unsafe { physical::free(frames); }
+            return Err(Errno::NotSupported);
+        }
+
+        let receive_buffer = frames.start.start_address().as_u64();
+        let transmit_buffers = receive_buffer + (RECEIVE_BUFFER_PAGES * PAGE_SIZE) as u64;
+        unsafe { ptr::write_bytes(receive_buffer as *mut u8, 0, RECEIVE_BUFFER_PAGES * PAGE_SIZE); }
+
+        let mut mac_address = MacAddress::default();
+        for (index, byte) in mac_address.0.iter_mut().enumerate() {
+            *byte = unsafe { Port::<u8>::new(base + MAC_ADDRESS + index as u16).read() };
+        }
+
+        let controller = Self { base, mac_address, receive_buffer, transmit_buffers, next_transmit: Mutex::new(0), transmit_done: WaitQueue::new() };
+        controller.reset()?;
+
+        return Ok(controller);
+    }
+
+    fn reset(&self) -> Result<()> {
+        // Wake the controller up and reset it (interrupts stay masked until the interrupt handler has been assigned)
+        self.write_u8(CONFIG_1, 0);
+        self.write_u8(COMMAND, COMMAND_RESET);
+        let start = timer().read().systime_ms();
+        while self.read_u8(COMMAND) & COMMAND_RESET != 0 {
+            if timer().read().systime_ms() - start > RESET_TIMEOUT_MS {
+                return Err(Errno::IoError);
+            }
+
+            core::hint::spin_loop();
+        }
+
+        self.write_u32(RECEIVE_BUFFER_START, self.receive_buffer as u32);
+        for index in 0..TRANSMIT_DESCRIPTOR_COUNT {
+            self.write_u32(TRANSMIT_ADDRESS + 4 * index as u16, (self.transmit_buffers + (index * TRANSMIT_BUFFER_SIZE) as u64) as u32);
+        }
+
+        // Configuration registers are only writable, while receiver and transmitter are enabled
+        self.write_u8(COMMAND, COMMAND_RECEIVE_ENABLE | COMMAND_TRANSMIT_ENABLE);
+        self.write_u32(RECEIVE_CONFIGURATION, RECEIVE_CONFIGURATION_DEFAULT | RECEIVE_WRAP | RECEIVE_ACCEPT_BROADCAST | RECEIVE_ACCEPT_MULTICAST | RECEIVE_ACCEPT_PHYSICAL_MATCH);
+        self.write_u32(TRANSMIT_CONFIGURATION, TRANSMIT_CONFIGURATION_DEFAULT);
+
+        return Ok(());
+    }
+
+    fn read_u8(&self, register: u16) -> u8 {
+        return unsafe { Port::<u8>::new(self.base + register).read() };
+    }
+
+    fn write_u8(&self, register: u16, value: u8) {
+        unsafe { Port::<u8>::new(self.base + register).write(value); }
+    }
+
+    fn read_u16(&self, register: u16) -> u16 {
+        return unsafe { Port::<u16>::new(self.base + register).read() };
+    }
+
+    fn write_u16(&self, register: u16, value: u16) {
+        unsafe { Port::<u16>::new(self.base + register).write(value); }
+    }
+
+    fn read_u32(&self, register: u16) -> u32 {
+        return unsafe { Port::<u32>::new(self.base + register).read() };
+    }
+
+    fn write_u32(&self, register: u16, value: u32) {
+        unsafe { Port::<u32>::new(self.base + register).write(value); }
+    }
+}
+
+impl NetworkDevice for Rtl8139 {
+    fn mac_address(&self) -> MacAddress {
+        return self.mac_address;
+    }
+
+    fn mtu(&self) -> usize {
+        return MTU;
+    }
+
+    fn transmit(&self, frame: &[u8]) -> Result<()> {
+        if frame.len() > MAX_FRAME_SIZE {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let mut next = self.next_transmit.lock();
+        let mut status_register = TRANSMIT_STATUS + 4 * *next as u16;
+
+        // The buffer of the descriptor may still be in use by the previous frame, which has been sent with it
+        // (all descriptors are owned by the driver after a reset). The lock is released while waiting, so that other senders are not blocked.
+        while self.read_u32(status_register) & TRANSMIT_STATUS_OWN == 0 {
+            drop(next);
+            if !self.transmit_done.wait_until(|| self.read_u32(status_register) & TRANSMIT_STATUS_OWN != 0, Some(TRANSMIT_TIMEOUT_MS)) {
+                return Err(Errno::IoError);
+            }
+
+            // Another sender may have used the descriptor in the meantime
+            next = self.next_transmit.lock();
+            status_register = TRANSMIT_STATUS + 4 * *next as u16;
+        }
+
+        let buffer = self.transmit_buffers + (*next * TRANSMIT_BUFFER_SIZE) as u64;
+        let length = frame.len().max(MIN_FRAME_SIZE);
+        unsafe {
+            ptr::copy_nonoverlapping(frame.as_ptr(), buffer as *mut u8, frame.len());
+            ptr::write_bytes((buffer as *mut u8).add(frame.len()), 0, length - frame.len());
+        }
+
+        // Writing the length clears the own bit and starts the transmission
+        self.write_u32(status_register, length as u32);
+        *next = (*next + 1) % TRANSMIT_DESCRIPTOR_COUNT;
+
+        return Ok(());
+    }
+}
+
+impl Rtl8139InterruptHandler {
+    /// Pass all frames in the receive ring to the interface.
+    fn receive_frames(&mut self) {
+        let controller = &self.controller;
+        while controller.read_u8(COMMAND) & COMMAND_BUFFER_EMPTY == 0 {
+            let header = unsafe { ((controller.receive_buffer + self.receive_offset as u64) as *const u32).read_volatile() };
+            let status = header as u16;
+            let length = (header >> 16) as usize;
+
+            if status & RECEIVE_STATUS_OK == 0 || !(FRAME_CHECK_SEQUENCE_SIZE..=MAX_FRAME_SIZE + FRAME_CHECK_SEQUENCE_SIZE).contains(&length) {
+                // The ring is out of sync, which can only be fixed by restarting the receiver
+                self.restart_receiver();
+                return;
+            }
+
+            let data = (controller.receive_buffer + self.receive_offset as u64 + 4) as *const u8;
+            let mut frame = Vec::with_capacity(length - FRAME_CHECK_SEQUENCE_SIZE);
+            unsafe {
+                ptr::copy_nonoverlapping(data, frame.as_mut_ptr(), length - FRAME_CHECK_SEQUENCE_SIZE);
+                frame.set_len(length - FRAME_CHECK_SEQUENCE_SIZE);
+            }
+            self.interface.receive(frame);
+
+            // Frames are aligned to 4 bytes. The read address lags 16 bytes behind (as specified by the data sheet).
+            self.receive_offset = ((self.receive_offset + length + 4 + 3) & !3) % RECEIVE_BUFFER_SIZE;
+            controller.write_u16(RECEIVE_READ_ADDRESS, (self.receive_offset as u16).wrapping_sub(16));
+        }
+    }
+
+    fn restart_receiver(&mut self) {
+        let controller = &self.controller;
+        controller.write_u8(COMMAND, COMMAND_TRANSMIT_ENABLE);
+        controller.write_u8(COMMAND, COMMAND_RECEIVE_ENABLE | COMMAND_TRANSMIT_ENABLE);
+        controller.write_u32(RECEIVE_CONFIGURATION, RECEIVE_CONFIGURATION_DEFAULT | RECEIVE_WRAP | RECEIVE_ACCEPT_BROADCAST | RECEIVE_ACCEPT_MULTICAST | RECEIVE_ACCEPT_PHYSICAL_MATCH);
+        controller.write_u32(RECEIVE_BUFFER_START, controller.receive_buffer as u32);
+        self.receive_offset = 0;
+    }
+}
+
+impl InterruptHandler for Rtl8139InterruptHandler {
+    fn trigger(&mut self) {
+        // The interrupt line may be shared with other devices
+        let status = self.controller.read_u16(INTERRUPT_STATUS) & INTERRUPTS;
+        if status == 0 {
+            return;
+        }
+
+        // Status bits are cleared by writing 1
+        self.controller.write_u16(INTERRUPT_STATUS, status);
+        if status & (INTERRUPT_TRANSMIT_OK | INTERRUPT_TRANSMIT_ERROR) != 0 {
+            self.controller.transmit_done.notify_all_from_interrupt();
+        }
+        if status & (INTERRUPT_RECEIVE_ERROR | INTERRUPT_RECEIVE_OVERFLOW | INTERRUPT_FIFO_OVERFLOW) != 0 {
+            self.interface.statistics().dropped_frames.fetch_add(1, Relaxed);
+        }
+        if status & (INTERRUPT_RECEIVE_OK | INTERRUPT_RECEIVE_OVERFLOW) != 0 {
+            self.receive_frames();
+        }
+    }
+}
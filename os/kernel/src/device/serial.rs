@@ -1,15 +1,22 @@
 use crate::device::serial::ComPort::{Com1, Com2, Com3, Com4};
 use crate::interrupt::interrupt_dispatcher::InterruptVector;
+use crate::device::terminal;
+use crate::device::terminal::Terminal;
 use crate::interrupt::interrupt_handler::InterruptHandler;
 use stream::{InputStream, OutputStream};
 use alloc::boxed::Box;
 use alloc::string::String;
+use core::fmt;
 use log::info;
 use nolock::queues::mpmc::bounded::scq::{Receiver, Sender};
 use nolock::queues::{mpmc, DequeueError};
 use spin::Once;
+use syscall::ioctl::WindowSize;
 use x86_64::instructions::port::Port;
 use crate::{apic, interrupt_dispatcher, serial_port};
+use crate::fs::devfs::Device;
+use crate::fs::Result;
+use crate::sync::Mutex;
 
 #[allow(dead_code)]
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -119,6 +126,22 @@ pub enum BaudRate {
 pub struct SerialPort {
     port: ComPort,
     buffer: Once<(Receiver<u8>, Sender<u8>)>,
+    /// Byte taken from the buffer by `has_data()`, which is returned by the next read.
+    pending: Mutex<Option<u8>>,
+}
+
+/// Terminal on top of a serial port (e.g. for using the shell via 'qemu -nographic' or a serial cable).
+/// Line endings and backspace are translated, since the remote side usually sends raw input. Echo is done by the line discipline (see `tty`).
+pub struct SerialTerminal {
+    serial: &'static SerialPort,
+    /// The size of the remote terminal is unknown, so it has to be set by an application (see `IoctlRequest::SetWindowSize`).
+    window_size: Mutex<WindowSize>,
+}
+
+/// '/dev/ttyS0': Raw access to the serial port (without echo and line ending translation).
+/// Reading blocks, until at least one byte has been received and returns after a line ending or when the buffer is full.
+pub struct SerialDevice {
+    serial: &'static SerialPort,
 }
 
 struct SerialInterruptHandler {
@@ -131,6 +154,35 @@ impl SerialInterruptHandler {
     }
 }
 
+/// Port used for logging, before the heap and the logger are available.
+static EARLY_SERIAL: Once<SerialPort> = Once::new();
+
+/// Allocation-free writer for the early serial port. Does nothing, if no serial port is available.
+pub struct EarlySerialWriter;
+
+/// Search for a serial port and initialize it for writing.
+/// Does not need the heap, so it can be called right after entering long mode.
+pub fn init_early_serial() {
+    let port = [Com1, Com2, Com3, Com4].into_iter().find(|port| check_port(*port));
+    if let Some(port) = port {
+        EARLY_SERIAL.call_once(|| {
+            let mut serial = SerialPort::new(port);
+            serial.init_write_only();
+            serial
+        });
+    }
+}
+
+impl fmt::Write for EarlySerialWriter {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        if let Some(serial) = EARLY_SERIAL.get() {
+            OutputStream::write_str(serial, string);
+        }
+
+        Ok(())
+    }
+}
+
 pub fn check_port(port: ComPort) -> bool {
     let mut scratch = Port::<u8>::new(port as u16 + 7);
 
@@ -173,6 +225,10 @@ impl OutputStream for SerialPort {
 
 impl InputStream for SerialPort {
     fn read_byte(&self) -> i16 {
+        if let Some(byte) = self.pending.lock().take() {
+            return byte as i16;
+        }
+
         loop {
             if let Some(buffer) = self.buffer.get() {
                 match buffer.0.try_dequeue() {
@@ -187,6 +243,102 @@ impl InputStream for SerialPort {
     }
 }
 
+impl BaudRate {
+    /// Get the baud rate for a given number of bits per second (only common rates are supported).
+    pub fn from_rate(rate: u32) -> Option<BaudRate> {
+        return match rate {
+            115200 => Some(BaudRate::Baud115200),
+            57600 => Some(BaudRate::Baud57600),
+            38400 => Some(BaudRate::Baud38400),
+            19200 => Some(BaudRate::Baud19200),
+            9600 => Some(BaudRate::Baud9600),
+            4800 => Some(BaudRate::Baud4800),
+            2400 => Some(BaudRate::Baud2400),
+            _ => None
+        };
+    }
+}
+
+impl SerialTerminal {
+    pub const fn new(serial: &'static SerialPort) -> Self {
+        Self { serial, window_size: Mutex::new(WindowSize { columns: 80, rows: 24 }) }
+    }
+}
+
+impl OutputStream for SerialTerminal {
+    fn write_byte(&self, b: u8) {
+        self.serial.write_byte(b);
+    }
+
+    fn write_str(&self, string: &str) {
+        self.serial.write_str(string);
+    }
+}
+
+impl InputStream for SerialTerminal {
+    fn read_byte(&self) -> i16 {
+        let byte = match self.serial.read_byte() {
+            -1 => return -1,
+            0x0d => 0x0a, // Enter sends carriage return
+            0x7f => 0x08, // Backspace sends delete
+            byte => byte as u8
+        };
+
+        return byte as i16;
+    }
+}
+
+impl Terminal for SerialTerminal {
+    fn clear(&self) {
+        self.serial.write_str("\x1b[2J\x1b[H");
+    }
+
+    fn has_input(&self) -> bool {
+        return self.serial.has_data();
+    }
+
+    fn window_size(&self) -> WindowSize {
+        return *self.window_size.lock();
+    }
+
+    fn set_window_size(&self, size: WindowSize) -> Result<()> {
+        *self.window_size.lock() = size;
+        return Ok(());
+    }
+}
+
+impl SerialDevice {
+    pub const fn new(serial: &'static SerialPort) -> Self {
+        Self { serial }
+    }
+}
+
+impl Device for SerialDevice {
+    fn read(&self, _offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        let mut count = 0;
+        while count < buffer.len() {
+            match self.serial.read_byte() {
+                -1 => break,
+                byte => {
+                    buffer[count] = byte as u8;
+                    count += 1;
+
+                    if byte as u8 == b'\r' || byte as u8 == b'\n' {
+                        break;
+                    }
+                }
+            }
+        }
+
+        return Ok(count);
+    }
+
+    fn write(&self, _offset: usize, buffer: &[u8]) -> Result<usize> {
+        self.serial.write_str(&String::from_utf8_lossy(buffer));
+        return Ok(buffer.len());
+    }
+}
+
 impl InterruptHandler for SerialInterruptHandler {
     fn trigger(&mut self) {
         if let Some(serial) = serial_port() {
@@ -213,6 +365,8 @@ impl InterruptHandler for SerialInterruptHandler {
                     }
                 }
             }
+
+            terminal::notify_input();
         }
     }
 }
@@ -222,9 +376,24 @@ impl SerialPort {
         Self {
             port,
             buffer: Once::new(),
+            pending: Mutex::new(None),
         }
     }
 
+    pub fn port(&self) -> ComPort {
+        return self.port;
+    }
+
+    /// Check, if a received byte is available, so that reading does not block.
+    pub fn has_data(&self) -> bool {
+        let mut pending = self.pending.lock();
+        if pending.is_none() {
+            *pending = self.buffer.get().and_then(|buffer| buffer.0.try_dequeue().ok());
+        }
+
+        return pending.is_some();
+    }
+
     pub fn init(&self, buffer_cap: usize, speed: BaudRate) {
         if !check_port(self.port) {
             panic!("Serial: Port [{:?}] not found!", self.port);
@@ -1,5 +1,11 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering::Relaxed;
 use crate::device::pit;
-use crate::device::pit::Timer;
+use crate::sync::Mutex;
+use crate::{speaker, timer};
+use x86_64::instructions::interrupts;
 use x86_64::instructions::port::{Port, PortWriteOnly};
 
 pub struct Speaker {
@@ -8,6 +14,21 @@ pub struct Speaker {
     ppi_port: Port<u8>,
 }
 
+/// A tone of a melody. A frequency of 0 is a rest.
+#[derive(Copy, Clone)]
+pub struct Note {
+    pub frequency: usize,
+    pub duration_ms: usize,
+}
+
+/// Notes, which are played after the current one. Further notes are dropped, if the queue is full.
+const MELODY_CAPACITY: usize = 64;
+
+/// Only locked with interrupts disabled, since the next note is started by the timer tasklet.
+static MELODY: Mutex<VecDeque<Note>> = Mutex::new(VecDeque::new());
+/// Set, while a note is playing and a timer for starting the next one is pending (only changed, while `MELODY` is locked).
+static PLAYING: AtomicBool = AtomicBool::new(false);
+
 impl Speaker {
     pub const fn new() -> Self {
         Self {
@@ -38,10 +59,47 @@ impl Speaker {
             self.ppi_port.write(status & 0xfc);
         }
     }
+}
+
+/// Play a tone after all queued notes. Returns immediately, the speaker is turned off by a timer.
+pub fn beep(frequency: usize, duration_ms: usize) {
+    play_melody(&[Note { frequency, duration_ms }]);
+}
+
+/// Queue `notes` to be played one after another. Returns immediately, each note is stopped by a timer, which starts the next one.
+pub fn play_melody(notes: &[Note]) {
+    let idle = interrupts::without_interrupts(|| {
+        let mut melody = MELODY.lock();
+        let free = MELODY_CAPACITY - melody.len();
+        melody.extend(notes.iter().take(free));
+
+        !PLAYING.swap(true, Relaxed)
+    });
+
+    if idle {
+        play_next();
+    }
+}
+
+/// Start the next note of the queue (or turn the speaker off, if the melody is finished) and schedule a timer for its end.
+fn play_next() {
+    let note = interrupts::without_interrupts(|| {
+        let mut melody = MELODY.lock();
+        let note = melody.pop_front();
+        let mut speaker = speaker().lock();
+        match note {
+            Some(note) if note.frequency > 0 => speaker.on(note.frequency),
+            Some(_) => speaker.off(),
+            None => {
+                speaker.off();
+                PLAYING.store(false, Relaxed);
+            }
+        }
+
+        note
+    });
 
-    pub fn play(&mut self, freq: usize, duration_ms: usize) {
-        self.on(freq);
-        Timer::wait(duration_ms);
-        self.off();
+    if let Some(note) = note {
+        timer::schedule(note.duration_ms, Box::new(play_next));
     }
 }
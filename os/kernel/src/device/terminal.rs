@@ -1,11 +1,91 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use stream::{InputStream, OutputStream};
 use core::fmt::Write;
 use core::ops::Deref;
 use core::{fmt, ptr};
-use crate::terminal;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering::{Acquire, Release};
+use syscall::error::Errno;
+use syscall::file::{FileType, OpenFlags, PollEvents};
+use syscall::input::Modifiers;
+use syscall::signal::Signal;
+use syscall::ioctl::{IoctlRequest, KeyRepeat, KeyboardLayout, TerminalMode, Termios, TermiosFlags, WindowSize};
+use crate::fs::{File, Metadata, Result};
+use crate::fs::devfs::Device;
+use crate::fs::poll::Pollable;
+use crate::device::tty;
+use crate::interrupt::deferred;
+use crate::process::process::find_process;
+use crate::process::wait_queue::{WaitQueue, Waiter};
+use crate::sync::Mutex;
+use crate::{console, terminal};
 
 pub trait Terminal: OutputStream + InputStream {
     fn clear(&self);
+
+    /// Check, if input is available, so that `read_byte()` does not block.
+    fn has_input(&self) -> bool;
+
+    fn window_size(&self) -> WindowSize;
+
+    /// Only supported by terminals, whose size is determined by the remote side (e.g. serial terminals).
+    fn set_window_size(&self, _size: WindowSize) -> Result<()> {
+        return Err(Errno::NotSupported);
+    }
+
+    /// Only supported by terminals with a keyboard.
+    fn keyboard_layout(&self) -> Result<KeyboardLayout> {
+        return Err(Errno::NotSupported);
+    }
+
+    fn set_keyboard_layout(&self, _layout: KeyboardLayout) -> Result<()> {
+        return Err(Errno::NotSupported);
+    }
+
+    fn keyboard_modifiers(&self) -> Result<Modifiers> {
+        return Err(Errno::NotSupported);
+    }
+
+    fn key_repeat(&self) -> Result<KeyRepeat> {
+        return Err(Errno::NotSupported);
+    }
+
+    fn set_key_repeat(&self, _repeat: KeyRepeat) -> Result<()> {
+        return Err(Errno::NotSupported);
+    }
+}
+
+/// Number of virtual consoles, which are switched with Alt+F1 to Alt+F4 (see `crate::switch_console()`).
+/// Console 0 is used by the kernel (e.g. for log messages) and the serial terminal replaces all consoles.
+pub const CONSOLE_COUNT: usize = 4;
+
+/// Number of remote consoles, which follow the virtual consoles and are used by remote shell sessions (see `net::telnet`).
+pub const REMOTE_CONSOLE_COUNT: usize = 2;
+
+/// Number of all consoles, which have a line discipline (see `tty`) and can be attached to processes.
+pub const TERMINAL_COUNT: usize = CONSOLE_COUNT + REMOTE_CONSOLE_COUNT;
+
+/// User processes attached to each console, with the foreground process (receiving `Signal::Interrupt` on Ctrl+C) being the last one.
+/// Applications are attached to the console of their standard input, when they are started, so the foreground process
+/// is the one started last, which is not running in the background anymore once it exits.
+static FOREGROUND: [Mutex<Vec<usize>>; TERMINAL_COUNT] = [const { Mutex::new(Vec::new()) }; TERMINAL_COUNT];
+
+/// Threads polling for terminal input (see `notify_input()`).
+static INPUT_WAIT_QUEUE: WaitQueue = WaitQueue::new();
+static INPUT_NOTIFICATION_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Called by the interrupt handlers of input devices, after data has been received.
+/// Wait queues can not be accessed from interrupt handlers, so the waiting threads are woken up by the kernel worker thread.
+pub fn notify_input() {
+    if !INPUT_NOTIFICATION_PENDING.swap(true, Acquire) {
+        deferred::schedule_work(Box::new(|| {
+            INPUT_NOTIFICATION_PENDING.store(false, Release);
+            INPUT_WAIT_QUEUE.notify_all();
+        }));
+    }
 }
 
 // Implementation of the 'core::fmt::Write' trait for our Terminal
@@ -18,6 +98,174 @@ impl Write for dyn Terminal {
     }
 }
 
+/// Add the user process `process_id` to the processes attached to `console`, making it the foreground process.
+/// Background processes are added in front of all others, so that they only become the foreground process, if no other process is attached.
+pub fn attach(console: usize, process_id: usize, background: bool) {
+    let mut processes = FOREGROUND[console].lock();
+    if background {
+        processes.insert(0, process_id);
+    } else {
+        processes.push(process_id);
+    }
+}
+
+/// Called, when the process `process_id` exits.
+pub fn detach(process_id: usize) {
+    for processes in FOREGROUND.iter() {
+        processes.lock().retain(|id| *id != process_id);
+    }
+}
+
+/// Raise `Signal::Interrupt` for the foreground process of `console` (if there is one).
+pub fn interrupt_foreground(console: usize) {
+    let process_id = FOREGROUND[console].lock().last().copied();
+    if let Some(process) = process_id.and_then(find_process) {
+        process.raise(Signal::Interrupt);
+    }
+}
+
+/// Raise `Signal::Hangup` for all processes attached to `console` (e.g. after the connection of a remote session has been closed).
+pub fn hangup(console: usize) {
+    let process_ids = FOREGROUND[console].lock().clone();
+    for process in process_ids.into_iter().filter_map(find_process) {
+        process.raise(Signal::Hangup);
+    }
+}
+
+/// A console as file, used for the standard descriptors of each process.
+/// Input is passed through the line discipline of the console (see `tty`), which handles echo and line editing.
+pub struct TerminalFile {
+    console: usize,
+}
+
+/// '/dev/tty' and '/dev/ttyN': A console as device node. Opening it returns a `TerminalFile`, so that applications
+/// started with it as standard input are attached to the console (e.g. services started by the init process).
+pub struct TerminalDevice {
+    console: usize,
+}
+
+impl TerminalFile {
+    pub const fn new(console: usize) -> Self {
+        Self { console }
+    }
+
+    pub fn console(&self) -> usize {
+        self.console
+    }
+}
+
+impl TerminalDevice {
+    pub const fn new(console: usize) -> Self {
+        Self { console }
+    }
+}
+
+impl File for TerminalFile {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+        return Ok(tty::read(self.console, buffer));
+    }
+
+    fn write(&self, buffer: &[u8]) -> Result<usize> {
+        console(self.console).write_str(&String::from_utf8_lossy(buffer));
+        return Ok(buffer.len());
+    }
+
+    fn stat(&self) -> Result<Metadata> {
+        return Ok(Metadata { inode: 0, typ: FileType::CharDevice, size: 0, created_ms: 0, modified_ms: 0 });
+    }
+
+    fn ioctl(&self, request: usize, arg: usize) -> Result<usize> {
+        return ioctl(self.console, request, arg);
+    }
+}
+
+impl Pollable for TerminalFile {
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        // Registered before checking, so that input arriving in between is not missed
+        if let Some(waiter) = waiter.filter(|_| events.contains(PollEvents::READABLE)) {
+            INPUT_WAIT_QUEUE.register(waiter);
+        }
+
+        let mut ready = PollEvents::WRITABLE;
+        if tty::has_input(self.console) {
+            ready |= PollEvents::READABLE;
+        }
+
+        return ready;
+    }
+}
+
+impl Device for TerminalDevice {
+    fn read(&self, _offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        return Ok(tty::read(self.console, buffer));
+    }
+
+    fn write(&self, _offset: usize, buffer: &[u8]) -> Result<usize> {
+        console(self.console).write_str(&String::from_utf8_lossy(buffer));
+        return Ok(buffer.len());
+    }
+
+    fn ioctl(&self, request: usize, arg: usize) -> Result<usize> {
+        return ioctl(self.console, request, arg);
+    }
+
+    fn open(&self, _flags: OpenFlags) -> Result<Option<Arc<dyn File>>> {
+        return Ok(Some(Arc::new(TerminalFile::new(self.console))));
+    }
+}
+
+/// Control requests, shared by `TerminalFile` and `TerminalDevice`. Pointer arguments point into the calling process.
+fn ioctl(console: usize, request: usize, arg: usize) -> Result<usize> {
+    let terminal = crate::console(console);
+    return match IoctlRequest::try_from(request) {
+        Ok(IoctlRequest::GetWindowSize) => {
+            unsafe { (arg as *mut WindowSize).write(terminal.window_size()); }
+            Ok(0)
+        }
+        Ok(IoctlRequest::SetWindowSize) => terminal.set_window_size(unsafe { (arg as *const WindowSize).read() }).map(|_| 0),
+        Ok(IoctlRequest::GetTerminalMode) => {
+            let canonical = tty::termios(console).flags.contains(TermiosFlags::CANONICAL);
+            Ok(if canonical { TerminalMode::Canonical } else { TerminalMode::Raw } as usize)
+        }
+        Ok(IoctlRequest::SetTerminalMode) => {
+            // Only the flags are changed, so that custom control characters are kept
+            let flags = match TerminalMode::try_from(arg).map_err(|_| Errno::InvalidArgument)? {
+                TerminalMode::Canonical => Termios::canonical().flags,
+                TerminalMode::Raw => Termios::raw().flags
+            };
+
+            tty::set_termios(console, Termios { flags, ..tty::termios(console) });
+            Ok(0)
+        }
+        Ok(IoctlRequest::GetTermios) => {
+            unsafe { (arg as *mut Termios).write(tty::termios(console)); }
+            Ok(0)
+        }
+        Ok(IoctlRequest::SetTermios) => {
+            let termios = unsafe { (arg as *const Termios).read() };
+            if TermiosFlags::from_bits(termios.flags.bits()).is_none() {
+                return Err(Errno::InvalidArgument);
+            }
+
+            tty::set_termios(console, termios);
+            Ok(0)
+        }
+        Ok(IoctlRequest::GetKeyboardLayout) => terminal.keyboard_layout().map(|layout| layout as usize),
+        Ok(IoctlRequest::SetKeyboardLayout) => {
+            let layout = KeyboardLayout::try_from(arg).map_err(|_| Errno::InvalidArgument)?;
+            terminal.set_keyboard_layout(layout).map(|_| 0)
+        }
+        Ok(IoctlRequest::GetKeyboardModifiers) => terminal.keyboard_modifiers().map(|modifiers| modifiers.bits() as usize),
+        Ok(IoctlRequest::GetKeyRepeat) => {
+            let repeat = terminal.key_repeat()?;
+            unsafe { (arg as *mut KeyRepeat).write(repeat); }
+            Ok(0)
+        }
+        Ok(IoctlRequest::SetKeyRepeat) => terminal.set_key_repeat(unsafe { (arg as *const KeyRepeat).read() }).map(|_| 0),
+        _ => Err(Errno::NotATerminal)
+    };
+}
+
 // Provide macros like in the 'io' module of Rust
 // The $crate variable ensures that the macro also works
 // from outside the 'std' crate.
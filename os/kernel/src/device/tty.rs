@@ -0,0 +1,271 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering::Relaxed;
+use syscall::ioctl::{Termios, TermiosFlags};
+use crate::device::terminal;
+use crate::device::terminal::{Terminal, TERMINAL_COUNT};
+use crate::sync::Mutex;
+
+/// Interrupt character (Ctrl+C), handled if `TermiosFlags::SIGNALS` is set.
+const INTERRUPT: u8 = 0x03;
+
+/// Line discipline of each console, sitting between the terminal (providing the typed bytes) and the processes reading from it.
+static LINE_DISCIPLINES: [Mutex<LineDiscipline>; TERMINAL_COUNT] = [const { Mutex::new(LineDiscipline::new()) }; TERMINAL_COUNT];
+
+/// Copy of the flags of each console, which can be read by interrupt handlers (see `signals_enabled()`).
+static FLAGS: [AtomicU32; TERMINAL_COUNT] = [const { AtomicU32::new(Termios::canonical().flags.bits()) }; TERMINAL_COUNT];
+
+/// Editing state of a terminal in canonical mode and input, which is ready to be read.
+pub struct LineDiscipline {
+    termios: Termios,
+    /// Line, which is being edited (only used in canonical mode).
+    line: Vec<u8>,
+    /// Input, which can be read.
+    ready: VecDeque<u8>,
+    /// Set by the end of file character on an empty line, so that the next read returns 0.
+    end_of_file: bool,
+}
+
+impl LineDiscipline {
+    pub const fn new() -> Self {
+        Self { termios: Termios::canonical(), line: Vec::new(), ready: VecDeque::new(), end_of_file: false }
+    }
+
+    fn flags(&self) -> TermiosFlags {
+        return self.termios.flags;
+    }
+
+    /// Process a byte typed in canonical mode.
+    pub fn input(&mut self, console: usize, terminal: &dyn Terminal, byte: u8) {
+        let termios = self.termios;
+        let echo = termios.flags.contains(TermiosFlags::ECHO);
+
+        match byte {
+            b'\n' => {
+                self.line.push(byte);
+                self.ready.extend(self.line.drain(..));
+                if echo {
+                    terminal.write_byte(byte);
+                }
+            }
+            INTERRUPT if termios.flags.contains(TermiosFlags::SIGNALS) => {
+                self.discard(terminal);
+                terminal::interrupt_foreground(console);
+            }
+            // Disabled control characters are set to 0, so they must not match a typed 0
+            0 => self.append(terminal, byte, echo),
+            byte if byte == termios.end_of_file => {
+                if self.line.is_empty() {
+                    self.end_of_file = true;
+                } else {
+                    self.ready.extend(self.line.drain(..));
+                }
+            }
+            byte if byte == termios.erase => {
+                self.erase_char(terminal, echo);
+            }
+            byte if byte == termios.word_erase => {
+                while self.line.last() == Some(&b' ') {
+                    self.erase_char(terminal, echo);
+                }
+                while self.line.last().is_some_and(|byte| *byte != b' ') {
+                    self.erase_char(terminal, echo);
+                }
+            }
+            byte if byte == termios.kill => {
+                while self.erase_char(terminal, echo) {}
+            }
+            byte => self.append(terminal, byte, echo)
+        }
+    }
+
+    fn append(&mut self, terminal: &dyn Terminal, byte: u8, echo: bool) {
+        self.line.push(byte);
+        if echo {
+            echo_byte(terminal, byte);
+        }
+    }
+
+    /// Remove the last character (which may consist of several UTF-8 bytes) from the line and from the screen.
+    fn erase_char(&mut self, terminal: &dyn Terminal, echo: bool) -> bool {
+        let mut width = 0;
+        while let Some(byte) = self.line.pop() {
+            // Continuation bytes are removed together with the first byte of the character
+            if byte & 0xc0 != 0x80 {
+                width = if is_control(byte) { 2 } else { 1 };
+                break;
+            }
+        }
+
+        if echo {
+            for _ in 0..width {
+                terminal.write_str("\x08 \x08");
+            }
+        }
+
+        return width > 0;
+    }
+
+    /// Drop all input, which has not been read yet (e.g. after Ctrl+C).
+    fn discard(&mut self, terminal: &dyn Terminal) {
+        self.line.clear();
+        self.ready.clear();
+        if self.flags().contains(TermiosFlags::ECHO) {
+            terminal.write_str("^C");
+        }
+    }
+
+    /// Copy ready input into `buffer`, stopping after a line break in canonical mode.
+    /// Returns `None`, if nothing can be read yet.
+    pub fn take(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        if self.end_of_file {
+            self.end_of_file = false;
+            return Some(0);
+        }
+        if self.ready.is_empty() {
+            return None;
+        }
+
+        let canonical = self.flags().contains(TermiosFlags::CANONICAL);
+        let mut count = 0;
+        while count < buffer.len() {
+            match self.ready.pop_front() {
+                Some(byte) => {
+                    buffer[count] = byte;
+                    count += 1;
+
+                    if canonical && byte == b'\n' {
+                        break;
+                    }
+                }
+                None => break
+            }
+        }
+
+        return Some(count);
+    }
+}
+
+pub fn termios(console: usize) -> Termios {
+    return LINE_DISCIPLINES[console].lock().termios;
+}
+
+/// Apply new settings. A line, which is being edited when leaving canonical mode, becomes readable.
+pub fn set_termios(console: usize, termios: Termios) {
+    let mut line_discipline = LINE_DISCIPLINES[console].lock();
+    if !termios.flags.contains(TermiosFlags::CANONICAL) {
+        let line = line_discipline.line.drain(..).collect::<Vec<u8>>();
+        line_discipline.ready.extend(line);
+    }
+
+    line_discipline.termios = termios;
+    FLAGS[console].store(termios.flags.bits(), Relaxed);
+}
+
+/// Restore the default settings and drop all unread input (e.g. before a console is reused by another remote session).
+pub fn reset(console: usize) {
+    *LINE_DISCIPLINES[console].lock() = LineDiscipline::new();
+    FLAGS[console].store(Termios::canonical().flags.bits(), Relaxed);
+}
+
+/// Check, if Ctrl+C should raise `Signal::Interrupt`. Called by the keyboard interrupt handler.
+pub fn signals_enabled(console: usize) -> bool {
+    return TermiosFlags::from_bits_truncate(FLAGS[console].load(Relaxed)).contains(TermiosFlags::SIGNALS);
+}
+
+/// Discard unread input and raise `Signal::Interrupt` for the foreground process of `console`.
+pub fn interrupt(console: usize) {
+    LINE_DISCIPLINES[console].lock().discard(crate::console(console));
+    terminal::interrupt_foreground(console);
+}
+
+/// Read from `console`, blocking until at least one byte is available. In canonical mode, reading returns after a line break
+/// or when `buffer` is full and in raw mode, as soon as no more input is available.
+pub fn read(console: usize, buffer: &mut [u8]) -> usize {
+    let terminal = crate::console(console);
+    loop {
+        let mut line_discipline = LINE_DISCIPLINES[console].lock();
+        if !line_discipline.flags().contains(TermiosFlags::CANONICAL) {
+            drop(line_discipline);
+            return read_raw(console, terminal, buffer);
+        }
+        if let Some(count) = line_discipline.take(buffer) {
+            return count;
+        }
+        drop(line_discipline);
+
+        // The lock is not held while waiting for input, so that the settings can be changed in the meantime
+        match terminal.read_byte() {
+            -1 => return 0,
+            byte => LINE_DISCIPLINES[console].lock().input(console, terminal, byte as u8)
+        }
+    }
+}
+
+/// Check, if reading from `console` would not block (a whole line must be available in canonical mode).
+/// Typed bytes are processed here, so that input is echoed while a process is polling.
+pub fn has_input(console: usize) -> bool {
+    let terminal = crate::console(console);
+    loop {
+        let line_discipline = LINE_DISCIPLINES[console].lock();
+        if !line_discipline.ready.is_empty() || line_discipline.end_of_file {
+            return true;
+        }
+        if !line_discipline.flags().contains(TermiosFlags::CANONICAL) {
+            return terminal.has_input();
+        }
+        if !terminal.has_input() {
+            return false;
+        }
+        drop(line_discipline);
+
+        match terminal.read_byte() {
+            -1 => return false,
+            byte => LINE_DISCIPLINES[console].lock().input(console, terminal, byte as u8)
+        }
+    }
+}
+
+fn read_raw(console: usize, terminal: &dyn Terminal, buffer: &mut [u8]) -> usize {
+    let mut count = LINE_DISCIPLINES[console].lock().take(buffer).unwrap_or(0);
+    while count < buffer.len() {
+        if count > 0 && !terminal.has_input() {
+            break;
+        }
+
+        let byte = match terminal.read_byte() {
+            -1 => break,
+            byte => byte as u8
+        };
+
+        let flags = LINE_DISCIPLINES[console].lock().flags();
+        if byte == INTERRUPT && flags.contains(TermiosFlags::SIGNALS) {
+            terminal::interrupt_foreground(console);
+            continue;
+        }
+        if flags.contains(TermiosFlags::ECHO) {
+            echo_byte(terminal, byte);
+        }
+
+        buffer[count] = byte;
+        count += 1;
+    }
+
+    return count;
+}
+
+fn is_control(byte: u8) -> bool {
+    return byte < 0x20 && byte != b'\t';
+}
+
+/// Control characters (e.g. starting the escape sequence of an arrow key) are echoed in caret notation,
+/// so that they are not interpreted by the terminal.
+fn echo_byte(terminal: &dyn Terminal, byte: u8) {
+    if is_control(byte) {
+        terminal.write_byte(b'^');
+        terminal.write_byte(byte + 0x40);
+    } else {
+        terminal.write_byte(byte);
+    }
+}
@@ -0,0 +1,213 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use syscall::input::{InputEvent, InputEventType, BUTTON_LEFT, BUTTON_MIDDLE, BUTTON_RIGHT, RELATIVE_WHEEL, RELATIVE_X, RELATIVE_Y};
+use crate::device::input::{self, InputDevice};
+use crate::device::usb::xhci::UsbDevice;
+use crate::device::usb::{Interface, InterruptListener, SetupPacket, REQUEST_CLASS, REQUEST_INTERFACE};
+use crate::fs::Result;
+use crate::try_ps2_devices;
+
+const CLASS_HID: u8 = 0x03;
+const SUBCLASS_BOOT: u8 = 0x01;
+const PROTOCOL_KEYBOARD: u8 = 0x01;
+const PROTOCOL_MOUSE: u8 = 0x02;
+
+// Class specific requests
+const SET_IDLE: u8 = 0x0a;
+const SET_PROTOCOL: u8 = 0x0b;
+const BOOT_PROTOCOL: u16 = 0;
+
+const KEYBOARD_REPORT_SIZE: usize = 8;
+/// Reported in all key slots, if too many keys are pressed at once.
+const USAGE_ERROR_ROLL_OVER: u8 = 0x01;
+
+/// Set 1 scancodes of the modifier keys, in the order of the bits of the modifier byte (left/right control, shift, alt and GUI).
+/// The second value tells, if the scancode is prefixed with 0xe0.
+const MODIFIER_SCANCODES: [(u8, bool); 8] = [
+    (0x1d, false), (0x2a, false), (0x38, false), (0x5b, true),
+    (0x1d, true), (0x36, false), (0x38, true), (0x5c, true),
+];
+
+/// Set 1 scancodes of the letters 'a' to 'z' (usages 0x04 to 0x1d).
+const LETTER_SCANCODES: [u8; 26] = [
+    0x1e, 0x30, 0x2e, 0x20, 0x12, 0x21, 0x22, 0x23, 0x17, 0x24, 0x25, 0x26, 0x32,
+    0x31, 0x18, 0x19, 0x10, 0x13, 0x1f, 0x14, 0x16, 0x2f, 0x11, 0x2d, 0x15, 0x2c,
+];
+
+/// Keyboard using the boot protocol, whose reports consist of a modifier byte, a reserved byte and up to 6 pressed keys.
+/// Changes between two reports are translated into set 1 scancodes, which are decoded by the keyboard of the PS/2 driver,
+/// so that USB keyboards share the decoder state (e.g. caps lock) and input device with the PS/2 keyboard.
+struct BootKeyboard {
+    report: [u8; KEYBOARD_REPORT_SIZE],
+}
+
+/// Mouse using the boot protocol, whose reports consist of the button bits, X and Y movement and optionally the wheel movement.
+struct BootMouse {
+    input: Arc<InputDevice>,
+    buttons: u8,
+}
+
+/// Bind a driver to `interface`, if it is a boot keyboard or mouse. Returns `false`, if the interface is not supported.
+pub fn attach(device: &UsbDevice, interface: &Interface) -> Result<bool> {
+    if interface.class != CLASS_HID || interface.subclass != SUBCLASS_BOOT {
+        return Ok(false);
+    }
+
+    let Some(endpoint) = interface.endpoints.iter().find(|endpoint| endpoint.is_in() && endpoint.is_interrupt()) else {
+        return Ok(false);
+    };
+
+    let listener: Box<dyn InterruptListener> = match interface.protocol {
+        PROTOCOL_KEYBOARD => Box::new(BootKeyboard { report: [0; KEYBOARD_REPORT_SIZE] }),
+        PROTOCOL_MOUSE => Box::new(BootMouse { input: input::register("USB mouse"), buttons: 0 }),
+        _ => return Ok(false)
+    };
+
+    // Report descriptors are not parsed, so the device must use the fixed boot report format.
+    // Reports are only sent on changes, since key repetition is done by the keyboard driver.
+    let request_type = REQUEST_CLASS | REQUEST_INTERFACE;
+    device.control_out(SetupPacket { request_type, request: SET_PROTOCOL, value: BOOT_PROTOCOL, index: interface.number as u16, length: 0 })?;
+    device.control_out(SetupPacket { request_type, request: SET_IDLE, value: 0, index: interface.number as u16, length: 0 })?;
+    device.listen(endpoint, listener)?;
+
+    return Ok(true);
+}
+
+impl BootKeyboard {
+    fn send(&self, scancode: u8, extended: bool, pressed: bool) {
+        // The PS/2 devices are initialized after the PCI drivers, so early key presses are dropped
+        let Some(ps2) = try_ps2_devices() else {
+            return;
+        };
+
+        if extended {
+            ps2.keyboard().receive(0xe0);
+        }
+        ps2.keyboard().receive(if pressed { scancode } else { scancode | 0x80 });
+    }
+
+    fn send_usage(&self, usage: u8, pressed: bool) {
+        if let Some((scancode, extended)) = usage_to_scancode(usage) {
+            self.send(scancode, extended, pressed);
+        }
+    }
+}
+
+impl InterruptListener for BootKeyboard {
+    fn receive(&mut self, data: &[u8]) {
+        if data.len() < KEYBOARD_REPORT_SIZE || data[2..].contains(&USAGE_ERROR_ROLL_OVER) {
+            return; // Keep the previous state, until the keys can be reported again
+        }
+
+        let mut report = [0u8; KEYBOARD_REPORT_SIZE];
+        report.copy_from_slice(&data[..KEYBOARD_REPORT_SIZE]);
+
+        for (bit, &(scancode, extended)) in MODIFIER_SCANCODES.iter().enumerate() {
+            let pressed = report[0] & (1 << bit) != 0;
+            if pressed != (self.report[0] & (1 << bit) != 0) {
+                self.send(scancode, extended, pressed);
+            }
+        }
+
+        for &usage in self.report[2..].iter().filter(|&&usage| usage != 0 && !report[2..].contains(&usage)) {
+            self.send_usage(usage, false);
+        }
+        for &usage in report[2..].iter().filter(|&&usage| usage != 0 && !self.report[2..].contains(&usage)) {
+            self.send_usage(usage, true);
+        }
+
+        self.report = report;
+    }
+}
+
+impl Drop for BootKeyboard {
+    /// Release all pressed keys, when the keyboard is disconnected (dropped with interrupts disabled by the host controller driver).
+    fn drop(&mut self) {
+        self.receive(&[0; KEYBOARD_REPORT_SIZE]);
+    }
+}
+
+impl InterruptListener for BootMouse {
+    fn receive(&mut self, data: &[u8]) {
+        if data.len() < 3 {
+            return;
+        }
+
+        let mut events = [InputEvent::new(InputEventType::Sync, 0, 0); 7];
+        let mut count = 0;
+
+        // Unlike PS/2 mice, USB mice report downward movement as positive, but upward wheel movement
+        let wheel = data.get(3).map_or(0, |&wheel| -(wheel as i8 as i32));
+        for (code, value) in [(RELATIVE_X, data[1] as i8 as i32), (RELATIVE_Y, data[2] as i8 as i32), (RELATIVE_WHEEL, wheel)] {
+            if value != 0 {
+                events[count] = InputEvent::new(InputEventType::Relative, code, value);
+                count += 1;
+            }
+        }
+
+        for (code, bit) in [(BUTTON_LEFT, 0x01), (BUTTON_RIGHT, 0x02), (BUTTON_MIDDLE, 0x04)] {
+            if data[0] & bit != self.buttons & bit {
+                events[count] = InputEvent::new(InputEventType::Key, code, (data[0] & bit != 0) as i32);
+                count += 1;
+            }
+        }
+
+        self.buttons = data[0];
+        // The last entry is left as sync event
+        self.input.push(&events[..=count]);
+    }
+}
+
+/// Translate a keyboard usage into its set 1 scancode and whether it is prefixed with 0xe0.
+fn usage_to_scancode(usage: u8) -> Option<(u8, bool)> {
+    let scancode = match usage {
+        0x04..=0x1d => LETTER_SCANCODES[(usage - 0x04) as usize],
+        0x1e..=0x27 => usage - 0x1e + 0x02, // Digits 1 to 9 and 0
+        0x28 => 0x1c, // Enter
+        0x29 => 0x01, // Escape
+        0x2a => 0x0e, // Backspace
+        0x2b => 0x0f, // Tab
+        0x2c => 0x39, // Space
+        0x2d => 0x0c, // Minus
+        0x2e => 0x0d, // Equals
+        0x2f => 0x1a, // Left bracket
+        0x30 => 0x1b, // Right bracket
+        0x31 | 0x32 => 0x2b, // Backslash and non-US hash
+        0x33 => 0x27, // Semicolon
+        0x34 => 0x28, // Apostrophe
+        0x35 => 0x29, // Grave accent
+        0x36 => 0x33, // Comma
+        0x37 => 0x34, // Period
+        0x38 => 0x35, // Slash
+        0x39 => 0x3a, // Caps lock
+        0x3a..=0x43 => usage - 0x3a + 0x3b, // F1 to F10
+        0x44 => 0x57, // F11
+        0x45 => 0x58, // F12
+        0x46 => return Some((0x37, true)), // Print screen
+        0x47 => 0x46, // Scroll lock
+        0x49 => return Some((0x52, true)), // Insert
+        0x4a => return Some((0x47, true)), // Home
+        0x4b => return Some((0x49, true)), // Page up
+        0x4c => return Some((0x53, true)), // Delete
+        0x4d => return Some((0x4f, true)), // End
+        0x4e => return Some((0x51, true)), // Page down
+        0x4f => return Some((0x4d, true)), // Right
+        0x50 => return Some((0x4b, true)), // Left
+        0x51 => return Some((0x50, true)), // Down
+        0x52 => return Some((0x48, true)), // Up
+        0x53 => 0x45, // Num lock
+        0x54 => return Some((0x35, true)), // Keypad slash
+        0x55 => 0x37, // Keypad asterisk
+        0x56 => 0x4a, // Keypad minus
+        0x57 => 0x4e, // Keypad plus
+        0x58 => return Some((0x1c, true)), // Keypad enter
+        0x59..=0x61 => [0x4f, 0x50, 0x51, 0x4b, 0x4c, 0x4d, 0x47, 0x48, 0x49][(usage - 0x59) as usize], // Keypad 1 to 9
+        0x62 => 0x52, // Keypad 0
+        0x63 => 0x53, // Keypad period
+        0x64 => 0x56, // Non-US backslash
+        0x65 => return Some((0x5d, true)), // Menu
+        _ => return None
+    };
+
+    return Some((scancode, false));
+}
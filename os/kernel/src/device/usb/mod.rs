@@ -0,0 +1,123 @@
+use alloc::vec::Vec;
+
+pub mod hid;
+pub mod xhci;
+
+// Bits of the request type of a setup packet
+pub const REQUEST_DEVICE_TO_HOST: u8 = 0x80;
+pub const REQUEST_CLASS: u8 = 0x20;
+pub const REQUEST_INTERFACE: u8 = 0x01;
+
+// Standard requests
+pub const GET_DESCRIPTOR: u8 = 0x06;
+pub const SET_CONFIGURATION: u8 = 0x09;
+
+// Descriptor types
+pub const DESCRIPTOR_DEVICE: u8 = 0x01;
+pub const DESCRIPTOR_CONFIGURATION: u8 = 0x02;
+pub const DESCRIPTOR_INTERFACE: u8 = 0x04;
+pub const DESCRIPTOR_ENDPOINT: u8 = 0x05;
+
+const ENDPOINT_DIRECTION_IN: u8 = 0x80;
+const ENDPOINT_TRANSFER_TYPE: u8 = 0x03;
+const TRANSFER_TYPE_INTERRUPT: u8 = 0x03;
+
+/// First stage of a control transfer, which describes the request.
+#[derive(Copy, Clone)]
+pub struct SetupPacket {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    pub length: u16,
+}
+
+/// Interface of a device configuration, which is handled by a class driver (e.g. `hid`).
+pub struct Interface {
+    pub number: u8,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub endpoints: Vec<Endpoint>,
+}
+
+#[derive(Copy, Clone)]
+pub struct Endpoint {
+    /// Endpoint number in the lower 4 bits and direction in bit 7 (see `Endpoint::is_in()`).
+    pub address: u8,
+    pub attributes: u8,
+    pub max_packet_size: u16,
+    /// Polling interval of interrupt endpoints (in frames for low and full speed devices, as exponent for faster devices).
+    pub interval: u8,
+}
+
+/// Receives the data of completed interrupt transfers (e.g. HID reports).
+/// Called by the interrupt handler of the host controller, so it must not block or allocate memory.
+pub trait InterruptListener: Send {
+    fn receive(&mut self, data: &[u8]);
+}
+
+impl SetupPacket {
+    pub const fn get_descriptor(typ: u8, index: u8, length: u16) -> Self {
+        Self { request_type: REQUEST_DEVICE_TO_HOST, request: GET_DESCRIPTOR, value: (typ as u16) << 8 | index as u16, index: 0, length }
+    }
+
+    /// Pack the setup packet into the 8 bytes, which are sent to the device.
+    pub fn as_u64(&self) -> u64 {
+        return self.request_type as u64 | (self.request as u64) << 8 | (self.value as u64) << 16 | (self.index as u64) << 32 | (self.length as u64) << 48;
+    }
+
+    pub fn is_device_to_host(&self) -> bool {
+        return self.request_type & REQUEST_DEVICE_TO_HOST != 0;
+    }
+}
+
+impl Endpoint {
+    pub fn number(&self) -> u8 {
+        return self.address & 0x0f;
+    }
+
+    pub fn is_in(&self) -> bool {
+        return self.address & ENDPOINT_DIRECTION_IN != 0;
+    }
+
+    pub fn is_interrupt(&self) -> bool {
+        return self.attributes & ENDPOINT_TRANSFER_TYPE == TRANSFER_TYPE_INTERRUPT;
+    }
+}
+
+/// Split a configuration descriptor (including all following descriptors) into its interfaces and their endpoints.
+/// Alternate settings of an interface are ignored.
+pub fn parse_configuration(data: &[u8]) -> Vec<Interface> {
+    let mut interfaces: Vec<Interface> = Vec::new();
+    let mut alternate = false;
+    let mut offset = 0;
+
+    while offset + 2 <= data.len() {
+        let length = data[offset] as usize;
+        if length < 2 || offset + length > data.len() {
+            break; // Malformed descriptor
+        }
+
+        let descriptor = &data[offset..offset + length];
+        match descriptor[1] {
+            DESCRIPTOR_INTERFACE if length >= 9 => {
+                alternate = descriptor[3] != 0;
+                if !alternate {
+                    interfaces.push(Interface { number: descriptor[2], class: descriptor[5], subclass: descriptor[6], protocol: descriptor[7], endpoints: Vec::new() });
+                }
+            }
+            DESCRIPTOR_ENDPOINT if length >= 7 && !alternate => {
+                if let Some(interface) = interfaces.last_mut() {
+                    let max_packet_size = u16::from_le_bytes([descriptor[4], descriptor[5]]) & 0x07ff;
+                    interface.endpoints.push(Endpoint { address: descriptor[2], attributes: descriptor[3], max_packet_size, interval: descriptor[6] });
+                }
+            }
+            _ => {}
+        }
+
+        offset += length;
+    }
+
+    return interfaces;
+}
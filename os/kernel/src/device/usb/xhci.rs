@@ -0,0 +1,966 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::Cell;
+use core::{mem, ptr, slice};
+use core::sync::atomic::{fence, AtomicU8};
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use log::{info, warn};
+use syscall::error::Errno;
+use x86_64::instructions::interrupts;
+use x86_64::structures::paging::page::PageRange;
+use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+use crate::device::pci::driver::{PciDriver, PciId};
+use crate::device::pci::{msi, CapabilityId, CommandFlag, PciDevice};
+use crate::device::pit::Timer;
+use crate::device::usb;
+use crate::device::usb::{hid, Endpoint, InterruptListener, SetupPacket, DESCRIPTOR_CONFIGURATION, DESCRIPTOR_DEVICE, SET_CONFIGURATION};
+use crate::fs::Result;
+use crate::interrupt::deferred;
+use crate::interrupt::interrupt_handler::InterruptHandler;
+use crate::memory::{physical, MemorySpace, PAGE_SIZE};
+use crate::process::process::current_process;
+use crate::process::wait_queue::WaitQueue;
+use crate::sync::Mutex;
+use crate::{apic, interrupt_dispatcher, scheduler, timer};
+
+const TRB_SIZE: usize = 16;
+/// Command, transfer and event rings fill one page each.
+const RING_SIZE: usize = PAGE_SIZE / TRB_SIZE;
+/// Completions, which have not been waited for yet. Reserved upfront, so that the interrupt handler never allocates memory.
+const COMPLETION_CAPACITY: usize = 16;
+const COMMAND_TIMEOUT_MS: usize = 5000;
+const RESET_TIMEOUT_MS: usize = 1000;
+/// Time for the firmware to release the controller (see `take_ownership()`).
+const HANDOFF_TIMEOUT_MS: usize = 1000;
+/// Time for devices to connect, after the ports have been powered on.
+const PORT_POWER_DELAY_MS: usize = 100;
+
+// Capability registers
+const CAPABILITY_LENGTH: u64 = 0x00;
+const STRUCTURAL_PARAMETERS_1: u64 = 0x04;
+const STRUCTURAL_PARAMETERS_2: u64 = 0x08;
+const CAPABILITY_PARAMETERS_1: u64 = 0x10;
+const DOORBELL_OFFSET: u64 = 0x14;
+const RUNTIME_OFFSET: u64 = 0x18;
+
+const CAPABILITY_64_BIT: u32 = 1 << 0;
+const CAPABILITY_CONTEXT_SIZE: u32 = 1 << 2;
+
+// Extended capability for the handoff from the firmware (which may emulate a PS/2 keyboard with a USB keyboard)
+const EXTENDED_CAPABILITY_LEGACY_SUPPORT: u32 = 1;
+const LEGACY_FIRMWARE_OWNED: u32 = 1 << 16;
+const LEGACY_OS_OWNED: u32 = 1 << 24;
+const LEGACY_CONTROL_STATUS: u64 = 0x04;
+/// Clears all SMI enable bits of the legacy control register and acknowledges pending SMI events.
+const LEGACY_DISABLE_SMI: u32 = 0x000e1fee;
+const LEGACY_SMI_EVENTS: u32 = 0x7 << 29;
+
+// Operational registers
+const USB_COMMAND: u64 = 0x00;
+const USB_STATUS: u64 = 0x04;
+const PAGE_SIZES: u64 = 0x08;
+const COMMAND_RING_CONTROL: u64 = 0x18;
+const DEVICE_CONTEXT_ARRAY: u64 = 0x30;
+const CONFIGURE: u64 = 0x38;
+const PORT_STATUS_BASE: u64 = 0x400;
+const PORT_REGISTERS_SIZE: u64 = 0x10;
+
+const COMMAND_RUN: u32 = 1 << 0;
+const COMMAND_RESET: u32 = 1 << 1;
+const COMMAND_INTERRUPTER_ENABLE: u32 = 1 << 2;
+const STATUS_HALTED: u32 = 1 << 0;
+const STATUS_EVENT_INTERRUPT: u32 = 1 << 3;
+const STATUS_NOT_READY: u32 = 1 << 11;
+const PAGE_SIZE_4K: u32 = 1 << 0;
+const COMMAND_RING_CYCLE: u64 = 1 << 0;
+
+// Bits of the port status and control registers
+const PORT_CONNECTED: u32 = 1 << 0;
+const PORT_ENABLED: u32 = 1 << 1;
+const PORT_RESET: u32 = 1 << 4;
+const PORT_POWER: u32 = 1 << 9;
+const PORT_SPEED_SHIFT: u32 = 10;
+const PORT_CONNECT_CHANGE: u32 = 1 << 17;
+/// Change bits are cleared by writing 1 (as well as the enabled bit, which disables the port).
+const PORT_CHANGE_BITS: u32 = 0x7f << 17;
+/// Bits, which keep their value, when written back (power, indicator and wake bits). All others are written as 0.
+const PORT_PRESERVE: u32 = PORT_POWER | 0x3 << 14 | 0x7 << 25;
+
+// Registers of the first interrupter (relative to the runtime registers)
+const INTERRUPTER: u64 = 0x20;
+const INTERRUPT_MANAGEMENT: u64 = INTERRUPTER;
+const EVENT_RING_TABLE_SIZE: u64 = INTERRUPTER + 0x08;
+const EVENT_RING_TABLE_ADDRESS: u64 = INTERRUPTER + 0x10;
+const EVENT_RING_DEQUEUE: u64 = INTERRUPTER + 0x18;
+
+const INTERRUPT_PENDING: u32 = 1 << 0;
+const INTERRUPT_ENABLE: u32 = 1 << 1;
+const EVENT_HANDLER_BUSY: u64 = 1 << 3;
+
+// TRB types
+const TRB_NORMAL: u32 = 1;
+const TRB_SETUP: u32 = 2;
+const TRB_DATA: u32 = 3;
+const TRB_STATUS: u32 = 4;
+const TRB_LINK: u32 = 6;
+const TRB_ENABLE_SLOT: u32 = 9;
+const TRB_DISABLE_SLOT: u32 = 10;
+const TRB_ADDRESS_DEVICE: u32 = 11;
+const TRB_CONFIGURE_ENDPOINT: u32 = 12;
+const TRB_EVALUATE_CONTEXT: u32 = 13;
+const TRB_TRANSFER_EVENT: u32 = 32;
+const TRB_COMMAND_COMPLETION: u32 = 33;
+const TRB_PORT_STATUS_CHANGE: u32 = 34;
+
+// Fields of the TRB control word
+const TRB_TYPE_SHIFT: u32 = 10;
+const TRB_SLOT_SHIFT: u32 = 24;
+const TRB_CYCLE: u32 = 1 << 0;
+const TRB_TOGGLE_CYCLE: u32 = 1 << 1;
+const TRB_INTERRUPT_ON_SHORT_PACKET: u32 = 1 << 2;
+const TRB_INTERRUPT_ON_COMPLETION: u32 = 1 << 5;
+const TRB_IMMEDIATE_DATA: u32 = 1 << 6;
+const TRB_DIRECTION_IN: u32 = 1 << 16;
+const SETUP_DATA_OUT: u32 = 2 << 16;
+const SETUP_DATA_IN: u32 = 3 << 16;
+
+const COMPLETION_SUCCESS: u8 = 1;
+const COMPLETION_SHORT_PACKET: u8 = 13;
+
+// Fields of the slot and endpoint contexts
+const ENDPOINT_TYPE_CONTROL: u32 = 4;
+const ENDPOINT_TYPE_INTERRUPT_IN: u32 = 7;
+/// Number of retries after transaction errors.
+const ENDPOINT_ERROR_COUNT: u32 = 3;
+const DEQUEUE_CYCLE: u64 = 1 << 0;
+/// Device context index of the default control endpoint (commands complete with endpoint 0).
+const CONTROL_ENDPOINT: u8 = 1;
+
+// Port speeds (default protocol speed ids)
+const SPEED_FULL: u8 = 1;
+const SPEED_LOW: u8 = 2;
+const SPEED_HIGH: u8 = 3;
+const SPEED_SUPER: u8 = 4;
+
+/// Addresses of the register sets of an xHCI controller (located in memory space via BAR 0).
+#[derive(Copy, Clone)]
+struct Registers {
+    base: u64,
+    operational: u64,
+    runtime: u64,
+    doorbells: u64,
+}
+
+/// Ring of TRBs, whose last entry links back to the first one (used for commands and transfers).
+struct Ring {
+    address: u64,
+    index: usize,
+    /// TRBs are owned by the controller, if their cycle bit matches this value (it is inverted on each wrap around).
+    cycle: bool,
+}
+
+/// Ring, into which the controller writes events (a single segment described by an event ring segment table).
+struct EventRing {
+    address: u64,
+    index: usize,
+    cycle: bool,
+}
+
+#[derive(Copy, Clone)]
+struct Completion {
+    /// Address of the command or transfer TRB, which has been completed.
+    trb: u64,
+    code: u8,
+    slot: u8,
+    /// Device context index of the endpoint for transfers (0 for commands).
+    endpoint: u8,
+}
+
+/// Interrupt IN endpoint, which always has a transfer pending. Completed transfers are passed on to the listener and submitted again.
+struct InterruptTransfer {
+    slot: u8,
+    endpoint: u8,
+    ring: Ring,
+    buffer: u64,
+    length: usize,
+    listener: Box<dyn InterruptListener>,
+}
+
+/// State of a root hub port. Ports are only locked briefly, so that devices can be detached, while another device is enumerated.
+enum Port {
+    Empty,
+    Enumerating,
+    Attached(UsbDevice),
+}
+
+struct Xhci {
+    registers: Registers,
+    port_count: u8,
+    /// Contexts are either 32 or 64 bytes large, depending on the controller.
+    context_size: usize,
+    /// Device context base address array (entry 0 points to the scratchpad buffers).
+    device_contexts: u64,
+    commands: Mutex<Ring>,
+    /// Devices attached to the root hub ports (indexed by port number - 1).
+    ports: Mutex<Vec<Port>>,
+    // The following fields are locked by the interrupt handler, so they must only be locked with interrupts disabled in thread context
+    events: Mutex<EventRing>,
+    completions: Mutex<Vec<Completion>>,
+    transfers: Mutex<Vec<InterruptTransfer>>,
+    /// Notified by `complete()`, which may be called by the interrupt handler.
+    completion_posted: WaitQueue,
+}
+
+/// Device attached to a root hub port, addressed by its slot. Hubs are not supported.
+pub struct UsbDevice {
+    controller: Arc<Xhci>,
+    slot: u8,
+    port: u8,
+    speed: u8,
+    /// Kept for further commands, so that only the changed contexts need to be filled in.
+    input_context: u64,
+    /// Bounce buffer for the data stage of control transfers.
+    buffer: u64,
+    control: Mutex<Ring>,
+    /// Highest device context index of all configured endpoints.
+    context_entries: AtomicU8,
+    /// Pages allocated for the device, which are freed, when it is disconnected.
+    pages: Mutex<Vec<u64>>,
+}
+
+struct XhciInterruptHandler {
+    controller: Arc<Xhci>,
+}
+
+/// Driver for USB 3 host controllers, which enumerates the devices connected to the root hub ports and binds class drivers to their interfaces.
+pub static DRIVER: PciDriver = PciDriver { name: "xhci", ids: &[PciId::class_with_prog_if(0x0c, 0x03, 0x30)], probe };
+
+fn probe(device: &Arc<PciDevice>) -> Result<()> {
+    let controller = Arc::new(Xhci::new(device)?);
+
+    // Only the first interrupter is used, so a single vector is sufficient
+    let handler = Box::new(XhciInterruptHandler { controller: Arc::clone(&controller) });
+    let interrupts = if msi::msix_table_size(device).is_some() {
+        msi::request_msix(device, 0, msi::default_target(), handler);
+        "MSI-X"
+    } else if device.find_capability(CapabilityId::Msi).is_some() {
+        msi::request_msi(device, msi::default_target(), handler);
+        "MSI"
+    } else {
+        let irq = device.interrupt_line();
+        interrupt_dispatcher().assign_irq(irq, handler);
+        apic().allow_irq(irq);
+        "INTx"
+    };
+
+    controller.start()?;
+    info!("xHCI controller [{}]: Ports: [{}], Context size: [{}], Interrupts: [{}]", device.address(), controller.port_count, controller.context_size, interrupts);
+
+    // Devices connected later are attached, when their port signals the connection (see `Xhci::port_changed()`)
+    for port in 1..=controller.port_count {
+        if let Err(err) = controller.attach(port) {
+            warn!("Failed to attach USB device at port [{}] (Error: {:?})", port, err);
+        }
+    }
+
+    return Ok(());
+}
+
+impl Registers {
+    fn read(&self, address: u64) -> u32 {
+        return unsafe { (address as *const u32).read_volatile() };
+    }
+
+    fn write(&self, address: u64, value: u32) {
+        unsafe { (address as *mut u32).write_volatile(value); }
+    }
+
+    fn write_u64(&self, address: u64, value: u64) {
+        // Written as two halves, since 64-bit accesses are not required to be supported
+        self.write(address, value as u32);
+        self.write(address + 4, (value >> 32) as u32);
+    }
+
+    fn port_status(&self, port: u8) -> u32 {
+        return self.read(self.port_address(port));
+    }
+
+    /// Write `bits` into the status and control register of `port`, while keeping the power and wake settings.
+    fn write_port_status(&self, port: u8, bits: u32) {
+        let status = self.port_status(port);
+        self.write(self.port_address(port), status & PORT_PRESERVE | bits);
+    }
+
+    fn port_address(&self, port: u8) -> u64 {
+        return self.operational + PORT_STATUS_BASE + (port - 1) as u64 * PORT_REGISTERS_SIZE;
+    }
+
+    /// Notify the controller about new TRBs for the endpoint `target` of `slot` (slot 0 is the command ring).
+    fn ring_doorbell(&self, slot: u8, target: u8) {
+        self.write(self.doorbells + slot as u64 * 4, target as u32);
+    }
+
+    /// Wait until `condition` is true for the value of the register at `address`.
+    fn wait(&self, address: u64, timeout_ms: usize, condition: impl Fn(u32) -> bool) -> Result<()> {
+        let start = timer().read().systime_ms();
+        while !condition(self.read(address)) {
+            if timer().read().systime_ms() - start > timeout_ms {
+                return Err(Errno::IoError);
+            }
+
+            core::hint::spin_loop();
+        }
+
+        return Ok(());
+    }
+}
+
+impl Ring {
+    /// Create a ring in the zeroed page at `address`.
+    fn new(address: u64) -> Self {
+        let ring = Self { address, index: 0, cycle: true };
+        write_trb(ring.trb_address(RING_SIZE - 1), address, 0, TRB_LINK << TRB_TYPE_SHIFT | TRB_TOGGLE_CYCLE);
+
+        return ring;
+    }
+
+    /// Append a TRB and hand it over to the controller by setting its cycle bit. Returns the address of the TRB.
+    fn push(&mut self, parameter: u64, status: u32, control: u32) -> u64 {
+        let trb = self.trb_address(self.index);
+        write_trb(trb, parameter, status, control | self.cycle as u32);
+
+        self.index += 1;
+        if self.index == RING_SIZE - 1 {
+            // Hand over the link TRB as well, which lets the controller continue at the start with the inverted cycle bit
+            write_trb(self.trb_address(self.index), self.address, 0, TRB_LINK << TRB_TYPE_SHIFT | TRB_TOGGLE_CYCLE | self.cycle as u32);
+            self.index = 0;
+            self.cycle = !self.cycle;
+        }
+
+        return trb;
+    }
+
+    fn trb_address(&self, index: usize) -> u64 {
+        return self.address + (index * TRB_SIZE) as u64;
+    }
+}
+
+impl EventRing {
+    /// Return parameter, status and control word of the next event, if the controller has written one.
+    fn pop(&mut self) -> Option<(u64, u32, u32)> {
+        let trb = self.dequeue_address();
+        let control = unsafe { ((trb + 12) as *const u32).read_volatile() };
+        if (control & TRB_CYCLE != 0) != self.cycle {
+            return None;
+        }
+
+        fence(Acquire);
+        let parameter = unsafe { (trb as *const u64).read_volatile() };
+        let status = unsafe { ((trb + 8) as *const u32).read_volatile() };
+
+        self.index += 1;
+        if self.index == RING_SIZE {
+            self.index = 0;
+            self.cycle = !self.cycle;
+        }
+
+        return Some((parameter, status, control));
+    }
+
+    fn dequeue_address(&self) -> u64 {
+        return self.address + (self.index * TRB_SIZE) as u64;
+    }
+}
+
+impl InterruptTransfer {
+    fn submit(&mut self, registers: &Registers) {
+        self.ring.push(self.buffer, self.length as u32, TRB_NORMAL << TRB_TYPE_SHIFT | TRB_INTERRUPT_ON_SHORT_PACKET | TRB_INTERRUPT_ON_COMPLETION);
+        registers.ring_doorbell(self.slot, self.endpoint);
+    }
+}
+
+impl Xhci {
+    fn new(device: &PciDevice) -> Result<Self> {
+        device.set_command_flag(CommandFlag::MemorySpace, true);
+        device.set_command_flag(CommandFlag::BusMaster, true);
+
+        // The locations of the other register sets are only known after reading the capability registers
+        let base = device.bar(0);
+        map_registers(base, PAGE_SIZE as u64);
+        let read = |offset: u64| unsafe { ((base + offset) as *const u32).read_volatile() };
+
+        let structural_parameters = read(STRUCTURAL_PARAMETERS_1);
+        let capability_parameters = read(CAPABILITY_PARAMETERS_1);
+        let slot_count = structural_parameters & 0xff;
+        let port_count = (structural_parameters >> 24) as u8;
+        let registers = Registers {
+            base,
+            operational: base + (read(CAPABILITY_LENGTH) & 0xff) as u64,
+            runtime: base + (read(RUNTIME_OFFSET) & !0x1f) as u64,
+            doorbells: base + (read(DOORBELL_OFFSET) & !0x03) as u64
+        };
+
+        let end = (registers.operational + PORT_STATUS_BASE + port_count as u64 * PORT_REGISTERS_SIZE)
+            .max(registers.runtime + EVENT_RING_DEQUEUE + 8)
+            .max(registers.doorbells + (slot_count as u64 + 1) * 4);
+        map_registers(base, end - base);
+
+        // Physical memory is identity mapped, so its addresses can be given to the controller directly
+        if capability_parameters & CAPABILITY_64_BIT == 0 || registers.read(registers.operational + PAGE_SIZES) & PAGE_SIZE_4K == 0 {
+            return Err(Errno::NotSupported);
+        }
+
+        take_ownership(&registers, capability_parameters);
+
+        // Stop and reset the controller
+        registers.write(registers.operational + USB_COMMAND, registers.read(registers.operational + USB_COMMAND) & !COMMAND_RUN);
+        registers.wait(registers.operational + USB_STATUS, RESET_TIMEOUT_MS, |status| status & STATUS_HALTED != 0)?;
+        registers.write(registers.operational + USB_COMMAND, COMMAND_RESET);
+        registers.wait(registers.operational + USB_COMMAND, RESET_TIMEOUT_MS, |command| command & COMMAND_RESET == 0)?;
+        registers.wait(registers.operational + USB_STATUS, RESET_TIMEOUT_MS, |status| status & STATUS_NOT_READY == 0)?;
+
+        registers.write(registers.operational + CONFIGURE, slot_count);
+        let device_contexts = alloc_zeroed_pages(1);
+        registers.write_u64(registers.operational + DEVICE_CONTEXT_ARRAY, device_contexts);
+
+        // Scratchpad buffers are memory, which the controller uses internally (not needed by QEMU, but by most real controllers)
+        let structural_parameters_2 = read(STRUCTURAL_PARAMETERS_2);
+        let scratchpad_count = ((structural_parameters_2 >> 21) & 0x1f) << 5 | (structural_parameters_2 >> 27) & 0x1f;
+        if scratchpad_count > 0 {
+            let array = alloc_zeroed_pages((scratchpad_count as usize * 8).div_ceil(PAGE_SIZE));
+            for index in 0..scratchpad_count as usize {
+                unsafe { (array as *mut u64).add(index).write(alloc_zeroed_pages(1)); }
+            }
+
+            unsafe { (device_contexts as *mut u64).write(array); }
+        }
+
+        let commands = Ring::new(alloc_zeroed_pages(1));
+        registers.write_u64(registers.operational + COMMAND_RING_CONTROL, commands.address | COMMAND_RING_CYCLE);
+
+        // The event ring segment table has a single entry (address and size of the segment)
+        let events = EventRing { address: alloc_zeroed_pages(1), index: 0, cycle: true };
+        let segment_table = alloc_zeroed_pages(1);
+        unsafe {
+            (segment_table as *mut u64).write(events.address);
+            ((segment_table + 8) as *mut u32).write(RING_SIZE as u32);
+        }
+
+        registers.write(registers.runtime + EVENT_RING_TABLE_SIZE, 1);
+        registers.write_u64(registers.runtime + EVENT_RING_DEQUEUE, events.address);
+        registers.write_u64(registers.runtime + EVENT_RING_TABLE_ADDRESS, segment_table);
+        registers.write(registers.runtime + INTERRUPT_MANAGEMENT, INTERRUPT_ENABLE);
+
+        return Ok(Self {
+            registers, port_count,
+            context_size: if capability_parameters & CAPABILITY_CONTEXT_SIZE != 0 { 64 } else { 32 },
+            device_contexts,
+            commands: Mutex::new(commands),
+            ports: Mutex::new((0..port_count).map(|_| Port::Empty).collect()),
+            events: Mutex::new(events),
+            completions: Mutex::new(Vec::with_capacity(COMPLETION_CAPACITY)),
+            transfers: Mutex::new(Vec::new()),
+            completion_posted: WaitQueue::new(),
+        });
+    }
+
+    fn start(&self) -> Result<()> {
+        let registers = &self.registers;
+        registers.write(registers.operational + USB_COMMAND, COMMAND_RUN | COMMAND_INTERRUPTER_ENABLE);
+        registers.wait(registers.operational + USB_STATUS, RESET_TIMEOUT_MS, |status| status & STATUS_HALTED == 0)?;
+
+        // Ports are only unpowered after the reset, if the controller supports port power switching
+        let mut powered = false;
+        for port in 1..=self.port_count {
+            if registers.port_status(port) & PORT_POWER == 0 {
+                registers.write_port_status(port, PORT_POWER);
+                powered = true;
+            }
+        }
+
+        // The controller is started during boot, before the scheduler is running
+        if powered {
+            if scheduler().is_initialized() {
+                scheduler().sleep(PORT_POWER_DELAY_MS);
+            } else {
+                Timer::wait(PORT_POWER_DELAY_MS);
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// Enumerate the device connected to `port` (if any and not attached yet) and bind class drivers to its interfaces.
+    fn attach(self: &Arc<Self>, port: u8) -> Result<()> {
+        {
+            let mut ports = self.ports.lock();
+            if !matches!(ports[port as usize - 1], Port::Empty) || self.registers.port_status(port) & PORT_CONNECTED == 0 {
+                return Ok(());
+            }
+
+            ports[port as usize - 1] = Port::Enumerating;
+        }
+
+        let result = self.enumerate_port(port);
+        let mut ports = self.ports.lock();
+        let enumerating = matches!(ports[port as usize - 1], Port::Enumerating);
+        match result {
+            Ok(device) if enumerating => {
+                ports[port as usize - 1] = Port::Attached(device);
+                Ok(())
+            }
+            Ok(device) => {
+                // Disconnected during enumeration (see `detach()`)
+                drop(ports);
+                self.release(device);
+                Ok(())
+            }
+            Err(err) => {
+                if enumerating {
+                    ports[port as usize - 1] = Port::Empty;
+                }
+
+                Err(err)
+            }
+        }
+    }
+
+    /// Enable `port`, address its device and configure it. Must be called without holding `ports`, since it waits for commands.
+    fn enumerate_port(self: &Arc<Self>, port: u8) -> Result<UsbDevice> {
+        // USB 2 devices are enabled by resetting their port, while USB 3 devices are enabled after link training
+        if self.registers.port_status(port) & PORT_ENABLED == 0 {
+            self.registers.write_port_status(port, PORT_RESET);
+            self.registers.wait(self.registers.port_address(port), RESET_TIMEOUT_MS, |status| status & PORT_RESET == 0)?;
+            if self.registers.port_status(port) & PORT_ENABLED == 0 {
+                return Err(Errno::IoError);
+            }
+        }
+
+        let speed = ((self.registers.port_status(port) >> PORT_SPEED_SHIFT) & 0x0f) as u8;
+        let slot = self.command(0, TRB_ENABLE_SLOT << TRB_TYPE_SHIFT)?.slot;
+        let device = UsbDevice::new(Arc::clone(self), slot, port, speed);
+
+        match device.enumerate() {
+            Ok(()) => Ok(device),
+            Err(err) => {
+                self.release(device);
+                Err(err)
+            }
+        }
+    }
+
+    /// Stop the transfers of the device, which was connected to `port`, and free its slot.
+    fn detach(self: &Arc<Self>, port: u8) {
+        let state = mem::replace(&mut self.ports.lock()[port as usize - 1], Port::Empty);
+        if let Port::Attached(device) = state {
+            info!("USB device at port [{}] has been disconnected", port);
+            self.release(device);
+        }
+    }
+
+    fn release(self: &Arc<Self>, device: UsbDevice) {
+        // Listeners are dropped with interrupts disabled (e.g. keyboards release all keys, see `hid::BootKeyboard`)
+        interrupts::without_interrupts(|| self.transfers.lock().retain(|transfer| transfer.slot != device.slot));
+
+        if let Err(err) = self.command(0, TRB_DISABLE_SLOT << TRB_TYPE_SHIFT | (device.slot as u32) << TRB_SLOT_SHIFT) {
+            warn!("Failed to disable USB device slot [{}] (Error: {:?})", device.slot, err);
+            return; // The controller may still access the memory of the device
+        }
+
+        unsafe { (self.device_contexts as *mut u64).add(device.slot as usize).write(0); }
+        for page in device.pages.lock().drain(..) {
+            let frame = PhysFrame::containing_address(PhysAddr::new(page));
+            unsafe { physical::free(PhysFrame::range(frame, frame + 1)); }
+        }
+    }
+
+    /// Execute a command and wait for its completion.
+    fn command(self: &Arc<Self>, parameter: u64, control: u32) -> Result<Completion> {
+        let trb = self.commands.lock().push(parameter, 0, control);
+        self.registers.ring_doorbell(0, 0);
+
+        return self.wait_for(|completion| completion.endpoint == 0 && completion.trb == trb);
+    }
+
+    /// Wait for the completion, for which `matches` is true. The event ring is checked in any case,
+    /// so that commands and control transfers also complete without interrupts (e.g. before interrupts are enabled).
+    fn wait_for(self: &Arc<Self>, matches: impl Fn(&Completion) -> bool) -> Result<Completion> {
+        let completion = Cell::new(None);
+        let completed = || interrupts::without_interrupts(|| {
+            self.process_events();
+            let mut completions = self.completions.lock();
+            match completions.iter().position(&matches) {
+                Some(index) => {
+                    completion.set(Some(completions.swap_remove(index)));
+                    true
+                }
+                None => false
+            }
+        });
+
+        if !self.completion_posted.wait_until(completed, Some(COMMAND_TIMEOUT_MS)) {
+            return Err(Errno::IoError);
+        }
+
+        let completion = completion.take().unwrap(); // Set by `completed()`, which has returned true
+        if completion.code != COMPLETION_SUCCESS && completion.code != COMPLETION_SHORT_PACKET {
+            warn!("xHCI request failed (Slot: [{}], Completion code: [{}])", completion.slot, completion.code);
+            return Err(Errno::IoError);
+        }
+
+        return Ok(completion);
+    }
+
+    /// Handle all events, which the controller has written since the last call. Must be called with interrupts disabled.
+    fn process_events(self: &Arc<Self>) {
+        let mut events = self.events.lock();
+        let mut processed = false;
+
+        while let Some((parameter, status, control)) = events.pop() {
+            processed = true;
+            let code = (status >> 24) as u8;
+            let slot = (control >> TRB_SLOT_SHIFT) as u8;
+
+            match (control >> TRB_TYPE_SHIFT) & 0x3f {
+                TRB_COMMAND_COMPLETION => self.complete(Completion { trb: parameter, code, slot, endpoint: 0 }),
+                TRB_TRANSFER_EVENT => {
+                    let endpoint = ((control >> 16) & 0x1f) as u8;
+                    if endpoint == CONTROL_ENDPOINT {
+                        self.complete(Completion { trb: parameter, code, slot, endpoint });
+                    } else {
+                        self.complete_transfer(slot, endpoint, code, (status & 0xffffff) as usize);
+                    }
+                }
+                TRB_PORT_STATUS_CHANGE => self.port_changed((parameter >> 24) as u8),
+                _ => {}
+            }
+        }
+
+        // Advancing the dequeue pointer also clears the busy flag, which allows the controller to raise the next interrupt
+        if processed {
+            self.registers.write_u64(self.registers.runtime + EVENT_RING_DEQUEUE, events.dequeue_address() | EVENT_HANDLER_BUSY);
+        }
+    }
+
+    fn complete(&self, completion: Completion) {
+        let mut completions = self.completions.lock();
+        if completions.len() == COMPLETION_CAPACITY {
+            completions.remove(0); // Oldest completion has not been waited for (e.g. after a timeout)
+        }
+
+        completions.push(completion);
+        self.completion_posted.notify_all_from_interrupt();
+    }
+
+    fn complete_transfer(&self, slot: u8, endpoint: u8, code: u8, residual: usize) {
+        let mut transfers = self.transfers.lock();
+        let Some(transfer) = transfers.iter_mut().find(|transfer| transfer.slot == slot && transfer.endpoint == endpoint) else {
+            return;
+        };
+
+        // Errors halt the endpoint, so no further transfers are submitted (e.g. if the device has been disconnected)
+        if code == COMPLETION_SUCCESS || code == COMPLETION_SHORT_PACKET {
+            let data = unsafe { slice::from_raw_parts(transfer.buffer as *const u8, transfer.length.saturating_sub(residual)) };
+            transfer.listener.receive(data);
+            transfer.submit(&self.registers);
+        }
+    }
+
+    /// Acknowledge the changes of `port` and attach or detach its device, if it has been connected or disconnected.
+    fn port_changed(self: &Arc<Self>, port: u8) {
+        if port == 0 || port > self.port_count {
+            return;
+        }
+
+        let status = self.registers.port_status(port);
+        self.registers.write_port_status(port, status & PORT_CHANGE_BITS);
+        if status & PORT_CONNECT_CHANGE == 0 {
+            return; // E.g. reset completed during `attach()`
+        }
+
+        // Enumeration waits for commands, so it is done by the kernel worker thread
+        let controller = Arc::clone(self);
+        if status & PORT_CONNECTED != 0 {
+            deferred::schedule_work(Box::new(move || {
+                if let Err(err) = controller.attach(port) {
+                    warn!("Failed to attach USB device at port [{}] (Error: {:?})", port, err);
+                }
+            }));
+        } else {
+            deferred::schedule_work(Box::new(move || controller.detach(port)));
+        }
+    }
+}
+
+impl UsbDevice {
+    fn new(controller: Arc<Xhci>, slot: u8, port: u8, speed: u8) -> Self {
+        let mut pages = Vec::new();
+        let mut alloc_page = || {
+            let page = alloc_zeroed_pages(1);
+            pages.push(page);
+            page
+        };
+
+        let output_context = alloc_page();
+        let input_context = alloc_page();
+        let buffer = alloc_page();
+        let control = Ring::new(alloc_page());
+        unsafe { (controller.device_contexts as *mut u64).add(slot as usize).write(output_context); }
+
+        return Self { controller, slot, port, speed, input_context, buffer, control: Mutex::new(control), context_entries: AtomicU8::new(CONTROL_ENDPOINT), pages: Mutex::new(pages) };
+    }
+
+    /// Assign an address to the device, read its descriptors, select its first configuration and bind class drivers to its interfaces.
+    fn enumerate(&self) -> Result<()> {
+        let max_packet_size = match self.speed {
+            SPEED_LOW | SPEED_FULL => 8,
+            SPEED_HIGH => 64,
+            _ => 512
+        };
+
+        let control_ring = self.control.lock().address;
+        self.set_input_control(1 << 0 | 1 << CONTROL_ENDPOINT);
+        let slot_context = self.context(0);
+        let endpoint_context = self.context(CONTROL_ENDPOINT);
+        unsafe {
+            slot_context.write_volatile((self.speed as u32) << 20 | (CONTROL_ENDPOINT as u32) << 27);
+            slot_context.add(1).write_volatile((self.port as u32) << 16);
+            endpoint_context.add(1).write_volatile(ENDPOINT_ERROR_COUNT << 1 | ENDPOINT_TYPE_CONTROL << 3 | max_packet_size << 16);
+            (endpoint_context.add(2) as *mut u64).write_volatile(control_ring | DEQUEUE_CYCLE);
+            endpoint_context.add(4).write_volatile(8); // Average TRB length
+        }
+        self.controller.command(self.input_context, TRB_ADDRESS_DEVICE << TRB_TYPE_SHIFT | (self.slot as u32) << TRB_SLOT_SHIFT)?;
+
+        // The maximum packet size of the control endpoint is part of the first 8 bytes of the device descriptor,
+        // which can be read with the default size (it is given as exponent for USB 3 devices)
+        let mut descriptor = [0u8; 18];
+        self.control_in(SetupPacket::get_descriptor(DESCRIPTOR_DEVICE, 0, 8), &mut descriptor[..8])?;
+        let actual_packet_size = if self.speed == SPEED_SUPER { 1 << descriptor[7].min(9) } else { descriptor[7] as u32 };
+        if actual_packet_size != 0 && actual_packet_size != max_packet_size {
+            self.set_input_control(1 << CONTROL_ENDPOINT);
+            unsafe { endpoint_context.add(1).write_volatile(ENDPOINT_ERROR_COUNT << 1 | ENDPOINT_TYPE_CONTROL << 3 | actual_packet_size << 16); }
+            self.controller.command(self.input_context, TRB_EVALUATE_CONTEXT << TRB_TYPE_SHIFT | (self.slot as u32) << TRB_SLOT_SHIFT)?;
+        }
+
+        self.control_in(SetupPacket::get_descriptor(DESCRIPTOR_DEVICE, 0, 18), &mut descriptor)?;
+        let vendor_id = u16::from_le_bytes([descriptor[8], descriptor[9]]);
+        let product_id = u16::from_le_bytes([descriptor[10], descriptor[11]]);
+
+        // The configuration descriptor is followed by the interface and endpoint descriptors (the total length is part of the header)
+        let mut header = [0u8; 9];
+        self.control_in(SetupPacket::get_descriptor(DESCRIPTOR_CONFIGURATION, 0, 9), &mut header)?;
+        let total_length = (u16::from_le_bytes([header[2], header[3]]) as usize).clamp(header.len(), PAGE_SIZE);
+        let mut configuration = vec![0u8; total_length];
+        self.control_in(SetupPacket::get_descriptor(DESCRIPTOR_CONFIGURATION, 0, total_length as u16), &mut configuration)?;
+        self.control_out(SetupPacket { request_type: 0, request: SET_CONFIGURATION, value: header[5] as u16, index: 0, length: 0 })?;
+
+        info!("USB device at port [{}]: Vendor: [0x{:04x}], Product: [0x{:04x}], Speed: [{}]", self.port, vendor_id, product_id, speed_name(self.speed));
+
+        for interface in usb::parse_configuration(&configuration) {
+            match hid::attach(self, &interface) {
+                Ok(true) => {}
+                Ok(false) => info!("No driver for USB interface [{}] (Class: [0x{:02x}], Subclass: [0x{:02x}], Protocol: [0x{:02x}])",
+                    interface.number, interface.class, interface.subclass, interface.protocol),
+                Err(err) => warn!("Failed to attach driver for USB interface [{}] (Error: {:?})", interface.number, err)
+            }
+        }
+
+        return Ok(());
+    }
+
+    pub fn speed(&self) -> u8 {
+        return self.speed;
+    }
+
+    /// Execute a control transfer with a data stage from the device into `data` (at most one page).
+    pub fn control_in(&self, setup: SetupPacket, data: &mut [u8]) -> Result<()> {
+        if !setup.is_device_to_host() || setup.length as usize > data.len() || data.len() > PAGE_SIZE {
+            return Err(Errno::InvalidArgument);
+        }
+
+        self.control_transfer(setup)?;
+        unsafe { ptr::copy_nonoverlapping(self.buffer as *const u8, data.as_mut_ptr(), setup.length as usize); }
+
+        return Ok(());
+    }
+
+    /// Execute a control transfer without data stage (e.g. to set the configuration).
+    pub fn control_out(&self, setup: SetupPacket) -> Result<()> {
+        if setup.is_device_to_host() || setup.length != 0 {
+            return Err(Errno::InvalidArgument);
+        }
+
+        return self.control_transfer(setup);
+    }
+
+    fn control_transfer(&self, setup: SetupPacket) -> Result<()> {
+        let length = setup.length as u32;
+        let device_to_host = setup.is_device_to_host();
+
+        {
+            let mut ring = self.control.lock();
+            let transfer_type = match (length, device_to_host) {
+                (0, _) => 0,
+                (_, true) => SETUP_DATA_IN,
+                (_, false) => SETUP_DATA_OUT
+            };
+            ring.push(setup.as_u64(), 8, TRB_SETUP << TRB_TYPE_SHIFT | TRB_IMMEDIATE_DATA | transfer_type);
+
+            if length > 0 {
+                ring.push(self.buffer, length, TRB_DATA << TRB_TYPE_SHIFT | if device_to_host { TRB_DIRECTION_IN } else { 0 });
+            }
+
+            // The status stage has the opposite direction of the data stage (or is an IN stage without data stage)
+            let status_direction = if length > 0 && device_to_host { 0 } else { TRB_DIRECTION_IN };
+            ring.push(0, 0, TRB_STATUS << TRB_TYPE_SHIFT | TRB_INTERRUPT_ON_COMPLETION | status_direction);
+        }
+
+        // Control transfers of a device are executed one at a time, so any completion of its control endpoint belongs to this transfer
+        // (errors in the data stage complete the transfer early)
+        self.controller.registers.ring_doorbell(self.slot, CONTROL_ENDPOINT);
+        self.controller.wait_for(|completion| completion.slot == self.slot && completion.endpoint == CONTROL_ENDPOINT)?;
+
+        return Ok(());
+    }
+
+    /// Configure the interrupt IN `endpoint` and pass the data of each completed transfer to `listener`.
+    pub fn listen(&self, endpoint: &Endpoint, listener: Box<dyn InterruptListener>) -> Result<()> {
+        if !endpoint.is_in() || !endpoint.is_interrupt() || endpoint.max_packet_size as usize > PAGE_SIZE {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let index = endpoint.number() * 2 + 1;
+        let ring = Ring::new(self.alloc_page());
+        let buffer = self.alloc_page();
+        let max_packet_size = endpoint.max_packet_size as u32;
+
+        // The interval is given as exponent in units of 125 µs (low and full speed devices specify it in frames of 1 ms)
+        let interval = match self.speed {
+            SPEED_LOW | SPEED_FULL => ((endpoint.interval.max(1) as u32 * 8).ilog2()).clamp(3, 10),
+            _ => (endpoint.interval.clamp(1, 16) - 1) as u32
+        };
+
+        let context_entries = self.context_entries.fetch_max(index, Relaxed).max(index);
+        self.set_input_control(1 << 0 | 1 << index);
+        let slot_context = self.context(0);
+        let endpoint_context = self.context(index);
+        unsafe {
+            slot_context.write_volatile(slot_context.read_volatile() & !(0x1f << 27) | (context_entries as u32) << 27);
+            endpoint_context.write_volatile(interval << 16);
+            endpoint_context.add(1).write_volatile(ENDPOINT_ERROR_COUNT << 1 | ENDPOINT_TYPE_INTERRUPT_IN << 3 | max_packet_size << 16);
+            (endpoint_context.add(2) as *mut u64).write_volatile(ring.address | DEQUEUE_CYCLE);
+            // Average TRB length and maximum payload per service interval
+            endpoint_context.add(4).write_volatile(max_packet_size << 16 | max_packet_size);
+        }
+        self.controller.command(self.input_context, TRB_CONFIGURE_ENDPOINT << TRB_TYPE_SHIFT | (self.slot as u32) << TRB_SLOT_SHIFT)?;
+
+        let transfer = InterruptTransfer { slot: self.slot, endpoint: index, ring, buffer, length: max_packet_size as usize, listener };
+        interrupts::without_interrupts(|| {
+            let mut transfers = self.controller.transfers.lock();
+            transfers.push(transfer);
+            transfers.last_mut().unwrap().submit(&self.controller.registers);
+        });
+
+        return Ok(());
+    }
+
+    /// Set the add flags of the input control context (bit 0 for the slot context, bit n for the device context index n).
+    fn set_input_control(&self, add: u32) {
+        unsafe {
+            let control = self.input_context as *mut u32;
+            control.write_volatile(0);
+            control.add(1).write_volatile(add);
+        }
+    }
+
+    /// Address of the slot context (index 0) or an endpoint context (device context index) in the input context.
+    fn context(&self, index: u8) -> *mut u32 {
+        return (self.input_context + ((index as usize + 1) * self.controller.context_size) as u64) as *mut u32;
+    }
+
+    fn alloc_page(&self) -> u64 {
+        let page = alloc_zeroed_pages(1);
+        self.pages.lock().push(page);
+
+        return page;
+    }
+}
+
+impl InterruptHandler for XhciInterruptHandler {
+    fn trigger(&mut self) {
+        let registers = &self.controller.registers;
+        if registers.read(registers.operational + USB_STATUS) & STATUS_EVENT_INTERRUPT == 0 {
+            return; // The legacy interrupt line may be shared with other devices
+        }
+
+        // Both flags are cleared by writing 1
+        registers.write(registers.operational + USB_STATUS, STATUS_EVENT_INTERRUPT);
+        registers.write(registers.runtime + INTERRUPT_MANAGEMENT, INTERRUPT_ENABLE | INTERRUPT_PENDING);
+        self.controller.process_events();
+    }
+}
+
+/// Request the controller from the firmware, which may use it for legacy keyboard emulation, and disable its system management interrupts.
+fn take_ownership(registers: &Registers, capability_parameters: u32) {
+    let mut offset = ((capability_parameters >> 16) << 2) as u64;
+    while offset != 0 {
+        let address = registers.base + offset;
+        let capability = registers.read(address);
+
+        if capability & 0xff == EXTENDED_CAPABILITY_LEGACY_SUPPORT {
+            if capability & LEGACY_FIRMWARE_OWNED != 0 {
+                registers.write(address, capability | LEGACY_OS_OWNED);
+                if registers.wait(address, HANDOFF_TIMEOUT_MS, |value| value & LEGACY_FIRMWARE_OWNED == 0).is_err() {
+                    warn!("xHCI controller has not been released by the firmware -> Taking it over anyway");
+                    registers.write(address, registers.read(address) & !LEGACY_FIRMWARE_OWNED);
+                }
+            }
+
+            let control = registers.read(address + LEGACY_CONTROL_STATUS);
+            registers.write(address + LEGACY_CONTROL_STATUS, control & LEGACY_DISABLE_SMI | LEGACY_SMI_EVENTS);
+            return;
+        }
+
+        // The offset of the next capability is given in double words
+        offset = match (capability >> 8) & 0xff {
+            0 => 0,
+            next => offset + (next << 2) as u64
+        };
+    }
+}
+
+fn speed_name(speed: u8) -> &'static str {
+    return match speed {
+        SPEED_LOW => "Low",
+        SPEED_FULL => "Full",
+        SPEED_HIGH => "High",
+        SPEED_SUPER => "Super",
+        _ => "Unknown"
+    };
+}
+
+fn write_trb(address: u64, parameter: u64, status: u32, control: u32) {
+    unsafe {
+        (address as *mut u64).write_volatile(parameter);
+        ((address + 8) as *mut u32).write_volatile(status);
+        // The control word contains the cycle bit, which hands the TRB over to the controller, so it is written last
+        fence(Release);
+        ((address + 12) as *mut u32).write_volatile(control);
+    }
+}
+
+fn map_registers(base: u64, size: u64) {
+    let start_page = Page::containing_address(VirtAddr::new(base));
+    let end_page = Page::containing_address(VirtAddr::new(base + size - 1)) + 1;
+    current_process().address_space().map(PageRange { start: start_page, end: end_page }, MemorySpace::Kernel, PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE);
+}
+
+fn alloc_zeroed_pages(count: usize) -> u64 {
+    let address = physical::alloc(count).start.start_address().as_u64();
+    unsafe { ptr::write_bytes(address as *mut u8, 0, count * PAGE_SIZE); }
+
+    return address;
+}
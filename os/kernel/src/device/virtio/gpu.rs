@@ -0,0 +1,370 @@
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicUsize};
+use core::sync::atomic::Ordering::{AcqRel, Relaxed, Release};
+use log::{info, warn};
+use syscall::error::Errno;
+use syscall::file::{FileType, OpenFlags, PollEvents};
+use syscall::ioctl::{Blit, DisplayMode, FramebufferInfo, IoctlRequest, Rectangle};
+use x86_64::structures::paging::frame::PhysFrameRange;
+use x86_64::structures::paging::{PageTableFlags, PhysFrame};
+use crate::device::pci::driver::{PciDriver, PciId};
+use crate::device::pci::PciDevice;
+use crate::device::virtio::{device_id, Buffer, DeviceType, VirtioDevice, Virtqueue, VENDOR_ID};
+use crate::fs::devfs::Device;
+use crate::fs::poll::Pollable;
+use crate::fs::{devfs, File, Metadata, Result};
+use crate::memory::{physical, PAGE_SIZE};
+use crate::process::wait_queue::Waiter;
+use crate::sync::Mutex;
+
+const CONTROL_QUEUE: u16 = 0;
+/// The control queue signals used buffers via this MSI-X table entry (entry 0 is reserved for configuration changes).
+const CONTROL_QUEUE_MSIX_ENTRY: u16 = 1;
+
+const SCANOUT: u32 = 0;
+const MAX_SCANOUTS: usize = 16;
+/// Resolution, which is used, if the host does not report an enabled display.
+const DEFAULT_MODE: DisplayMode = DisplayMode { width: 1024, height: 768 };
+const MAX_RESOLUTION: u32 = 8192;
+const BPP: u8 = 32;
+const BYTES_PER_PIXEL: u32 = 4;
+/// Pixel format of the resources, which matches the 32-bit format of the linear framebuffer (blue, green, red, unused).
+const FORMAT_B8G8R8X8: u32 = 2;
+
+/// Requests are built in the first half of the command page and responses are received in the second half.
+const RESPONSE_OFFSET: u64 = PAGE_SIZE as u64 / 2;
+/// Each request and response starts with a header of six 32-bit words (type, flags, fence id, context id, ring index).
+const HEADER_WORDS: usize = 6;
+const DISPLAY_WORDS: usize = 6;
+
+#[derive(Copy, Clone)]
+#[repr(u32)]
+enum Command {
+    GetDisplayInfo = 0x0100,
+    ResourceCreate2d = 0x0101,
+    ResourceUnref = 0x0102,
+    SetScanout = 0x0103,
+    ResourceFlush = 0x0104,
+    TransferToHost2d = 0x0105,
+    ResourceAttachBacking = 0x0106,
+}
+
+const RESPONSE_OK_NODATA: u32 = 0x1100;
+const RESPONSE_OK_DISPLAY_INFO: u32 = 0x1101;
+
+/// Graphics adapter (e.g. QEMU's '-device virtio-vga'), exposed as '/dev/gpuN'.
+/// The boot framebuffer stays visible, until a process sets a display mode. Its framebuffer is shown instead,
+/// until the process closes the device, which resets the adapter, so that the boot framebuffer (and the consoles) are shown again.
+pub struct VirtioGpu {
+    device: VirtioDevice,
+    control: Arc<Virtqueue>,
+    interrupts: bool,
+    state: Mutex<GpuState>,
+    /// Only one process at a time can control the display.
+    in_use: AtomicBool,
+}
+
+struct GpuState {
+    /// Page for building requests and receiving responses.
+    commands: u64,
+    resource: Option<Resource>,
+    next_resource_id: u32,
+}
+
+/// Host resource, which is shown on the display. Its content is transferred from guest memory (the backing) on each flush.
+struct Resource {
+    id: u32,
+    width: u32,
+    height: u32,
+    backing: PhysFrameRange,
+}
+
+/// '/dev/gpuN', opened by the process controlling the display.
+pub struct GpuFile {
+    gpu: Arc<VirtioGpu>,
+    /// Backings of replaced resources, which may still be mapped by the process and are only freed, when the file is dropped.
+    retired: Mutex<Vec<PhysFrameRange>>,
+}
+
+struct GpuDevice {
+    gpu: Arc<VirtioGpu>,
+}
+
+/// Driver for virtio graphics adapters, which registers them as '/dev/gpu0', '/dev/gpu1', ...
+pub static DRIVER: PciDriver = PciDriver { name: "virtio-gpu", ids: &[PciId::device(VENDOR_ID, device_id(DeviceType::Gpu))], probe };
+
+/// Number of adapters, which have been probed (used for naming their devices).
+static ADAPTER_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+fn probe(device: &Arc<PciDevice>) -> Result<()> {
+    let gpu = Arc::new(VirtioGpu::new(device)?);
+    gpu.start()?;
+    let mode = gpu.preferred_mode()?;
+    info!("Virtio graphics adapter [{}]: Preferred mode: [{}x{}], Interrupts: [{}]",
+        device.address(), mode.width, mode.height, if gpu.interrupts { "MSI-X" } else { "Polling" });
+
+    // The adapter is started again, when it is opened
+    gpu.device.reset()?;
+
+    let name = format!("gpu{}", ADAPTER_COUNT.fetch_add(1, Relaxed));
+    return devfs::register(&name, FileType::CharDevice, Arc::new(GpuDevice { gpu }));
+}
+
+impl VirtioGpu {
+    fn new(pci: &Arc<PciDevice>) -> Result<Self> {
+        let device = VirtioDevice::new(pci)?;
+        let control = Arc::new(Virtqueue::new(CONTROL_QUEUE));
+        let interrupts = device.request_interrupt(CONTROL_QUEUE_MSIX_ENTRY, &control);
+
+        let commands = physical::alloc(1).start.start_address().as_u64();
+        let state = GpuState { commands, resource: None, next_resource_id: 1 };
+
+        return Ok(Self { device, control, interrupts, state: Mutex::new(state), in_use: AtomicBool::new(false) });
+    }
+
+    /// Initialize the adapter (no optional features, like 3D acceleration, are used).
+    fn start(&self) -> Result<()> {
+        self.device.initialize(0)?;
+        self.device.enable_queue(&self.control, if self.interrupts { Some(CONTROL_QUEUE_MSIX_ENTRY) } else { None })?;
+        self.device.start();
+
+        return Ok(());
+    }
+
+    /// Resolution of the first enabled display, as reported by the host (e.g. the size of QEMU's window).
+    fn preferred_mode(&self) -> Result<DisplayMode> {
+        let state = self.state.lock();
+        let response = self.command(&state, Command::GetDisplayInfo, &[], HEADER_WORDS + MAX_SCANOUTS * DISPLAY_WORDS, RESPONSE_OK_DISPLAY_INFO)?;
+
+        // Each display is described by its rectangle (x, y, width, height), an enabled flag and further flags
+        let mode = response[HEADER_WORDS..].chunks_exact(DISPLAY_WORDS)
+            .find(|display| display[4] != 0 && display[2] != 0 && display[3] != 0)
+            .map(|display| DisplayMode { width: display[2], height: display[3] });
+
+        return Ok(mode.unwrap_or(DEFAULT_MODE));
+    }
+
+    /// Show a new black framebuffer with the given resolution. Returns the backing of the replaced framebuffer, which must be freed by the caller.
+    fn set_mode(&self, mode: DisplayMode) -> Result<Option<PhysFrameRange>> {
+        if mode.width == 0 || mode.height == 0 || mode.width > MAX_RESOLUTION || mode.height > MAX_RESOLUTION {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let mut state = self.state.lock();
+        let size = (mode.width * mode.height * BYTES_PER_PIXEL) as usize;
+        let backing = physical::alloc(size.div_ceil(PAGE_SIZE));
+        let address = backing.start.start_address().as_u64();
+        unsafe { ptr::write_bytes(address as *mut u8, 0, size); }
+
+        let id = state.next_resource_id;
+        state.next_resource_id += 1;
+
+        let result = self.command(&state, Command::ResourceCreate2d, &[id, FORMAT_B8G8R8X8, mode.width, mode.height], HEADER_WORDS, RESPONSE_OK_NODATA)
+            .and_then(|_| self.command(&state, Command::ResourceAttachBacking, &[id, 1, address as u32, (address >> 32) as u32, size as u32, 0], HEADER_WORDS, RESPONSE_OK_NODATA))
+            .and_then(|_| self.command(&state, Command::SetScanout, &[0, 0, mode.width, mode.height, SCANOUT, id], HEADER_WORDS, RESPONSE_OK_NODATA));
+        if let Err(err) = result {
+            self.command(&state, Command::ResourceUnref, &[id, 0], HEADER_WORDS, RESPONSE_OK_NODATA).ok();
+            unsafe { physical::free(backing); }
+            return Err(err);
+        }
+
+        // The old resource is not shown anymore and can be destroyed (which also detaches its backing)
+        let old = state.resource.replace(Resource { id, width: mode.width, height: mode.height, backing });
+        if let Some(old) = &old {
+            self.command(&state, Command::ResourceUnref, &[old.id, 0], HEADER_WORDS, RESPONSE_OK_NODATA)?;
+        }
+
+        drop(state);
+        self.flush(None)?;
+
+        return Ok(old.map(|old| old.backing));
+    }
+
+    /// Copy `area` (or the whole framebuffer) from the backing to the host and update the display.
+    fn flush(&self, area: Option<Rectangle>) -> Result<()> {
+        let state = self.state.lock();
+        let resource = state.resource.as_ref().ok_or(Errno::InvalidArgument)?;
+
+        let area = area.unwrap_or(Rectangle { x: 0, y: 0, width: resource.width, height: resource.height });
+        if area.x >= resource.width || area.y >= resource.height {
+            return Ok(());
+        }
+
+        let width = area.width.min(resource.width - area.x);
+        let height = area.height.min(resource.height - area.y);
+        let offset = (area.y * resource.width + area.x) as u64 * BYTES_PER_PIXEL as u64;
+        let rect = [area.x, area.y, width, height];
+
+        self.command(&state, Command::TransferToHost2d, &[rect[0], rect[1], rect[2], rect[3], offset as u32, (offset >> 32) as u32, resource.id, 0], HEADER_WORDS, RESPONSE_OK_NODATA)?;
+        self.command(&state, Command::ResourceFlush, &[rect[0], rect[1], rect[2], rect[3], resource.id, 0], HEADER_WORDS, RESPONSE_OK_NODATA)?;
+
+        return Ok(());
+    }
+
+    /// Copy the source area of `blit` to its target position and show the result. The 2D command set has no copy command,
+    /// so the pixels are moved in the backing and only the target area is transferred to the host (saving the application a copy and a flush).
+    fn blit(&self, blit: Blit) -> Result<()> {
+        let state = self.state.lock();
+        let resource = state.resource.as_ref().ok_or(Errno::InvalidArgument)?;
+
+        let source = blit.source;
+        if source.x >= resource.width || source.y >= resource.height || blit.x >= resource.width || blit.y >= resource.height {
+            return Ok(());
+        }
+
+        let width = source.width.min(resource.width - source.x).min(resource.width - blit.x);
+        let height = source.height.min(resource.height - source.y).min(resource.height - blit.y);
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+
+        // Rows are copied bottom-up, if the target lies below the source, so that overlapping rows are read before they are overwritten
+        let pitch = (resource.width * BYTES_PER_PIXEL) as usize;
+        let pixels = resource.backing.start.start_address().as_u64() as *mut u8;
+        for row in 0..height {
+            let row = if blit.y > source.y { height - 1 - row } else { row };
+            let from = (source.y + row) as usize * pitch + (source.x * BYTES_PER_PIXEL) as usize;
+            let to = (blit.y + row) as usize * pitch + (blit.x * BYTES_PER_PIXEL) as usize;
+            unsafe { ptr::copy(pixels.add(from), pixels.add(to), (width * BYTES_PER_PIXEL) as usize); }
+        }
+
+        drop(state);
+        return self.flush(Some(Rectangle { x: blit.x, y: blit.y, width, height }));
+    }
+
+    fn info(&self) -> Result<FramebufferInfo> {
+        let current = self.state.lock().resource.as_ref().map(|resource| DisplayMode { width: resource.width, height: resource.height });
+        let mode = match current {
+            Some(mode) => mode,
+            None => self.preferred_mode()?
+        };
+
+        return Ok(FramebufferInfo { width: mode.width, height: mode.height, pitch: mode.width * BYTES_PER_PIXEL, bpp: BPP });
+    }
+
+    /// Send a request with the given `arguments` (following the header) and return the first `response_words` words of the response,
+    /// if it has the type `expected`. The command page is protected by the lock of `state`.
+    fn command(&self, state: &GpuState, command: Command, arguments: &[u32], response_words: usize, expected: u32) -> Result<Vec<u32>> {
+        let request = state.commands as *mut u32;
+        let response = (state.commands + RESPONSE_OFFSET) as *mut u32;
+
+        unsafe {
+            ptr::write_bytes(request, 0, HEADER_WORDS);
+            request.write(command as u32);
+            ptr::copy_nonoverlapping(arguments.as_ptr(), request.add(HEADER_WORDS), arguments.len());
+        }
+
+        let request_length = ((HEADER_WORDS + arguments.len()) * size_of::<u32>()) as u32;
+        let response_length = (response_words * size_of::<u32>()) as u32;
+        self.control.execute(&[
+            Buffer { address: request as u64, length: request_length, writable: false },
+            Buffer { address: response as u64, length: response_length, writable: true },
+        ])?;
+
+        let response = unsafe { core::slice::from_raw_parts(response, response_words) };
+        if response[0] != expected {
+            return Err(Errno::IoError);
+        }
+
+        return Ok(response.to_vec());
+    }
+
+    /// Reset the adapter, so that the boot framebuffer is shown again. Returns the backing of the current framebuffer, which must be freed by the caller.
+    fn stop(&self) -> Option<PhysFrameRange> {
+        if let Err(err) = self.device.reset() {
+            warn!("Failed to reset virtio graphics adapter [{}] (Error: {:?})", self.device.pci().address(), err);
+        }
+
+        return self.state.lock().resource.take().map(|resource| resource.backing);
+    }
+}
+
+impl Drop for GpuFile {
+    fn drop(&mut self) {
+        let backing = self.gpu.stop();
+        for frames in self.retired.lock().drain(..).chain(backing) {
+            unsafe { physical::free(frames); }
+        }
+
+        self.gpu.in_use.store(false, Release);
+    }
+}
+
+impl File for GpuFile {
+    /// The framebuffer is only accessible by mapping it (see `mmap()`).
+    fn read(&self, _buffer: &mut [u8]) -> Result<usize> {
+        return Err(Errno::NotSupported);
+    }
+
+    fn write(&self, _buffer: &[u8]) -> Result<usize> {
+        return Err(Errno::NotSupported);
+    }
+
+    fn stat(&self) -> Result<Metadata> {
+        return Ok(Metadata { inode: ptr::from_ref(self) as u64, typ: FileType::CharDevice, size: 0, created_ms: 0, modified_ms: 0 });
+    }
+
+    fn ioctl(&self, request: usize, arg: usize) -> Result<usize> {
+        return match IoctlRequest::try_from(request) {
+            Ok(IoctlRequest::GetFramebufferInfo) => {
+                unsafe { (arg as *mut FramebufferInfo).write(self.gpu.info()?); }
+                Ok(0)
+            }
+            Ok(IoctlRequest::SetDisplayMode) => {
+                if let Some(old) = self.gpu.set_mode(unsafe { (arg as *const DisplayMode).read() })? {
+                    self.retired.lock().push(old);
+                }
+                Ok(0)
+            }
+            Ok(IoctlRequest::FlushFramebuffer) => {
+                let area = if arg == 0 { None } else { Some(unsafe { (arg as *const Rectangle).read() }) };
+                self.gpu.flush(area)?;
+                Ok(0)
+            }
+            Ok(IoctlRequest::BlitFramebuffer) => {
+                self.gpu.blit(unsafe { (arg as *const Blit).read() })?;
+                Ok(0)
+            }
+            _ => Err(Errno::NotATerminal)
+        };
+    }
+
+    /// The framebuffer of the current display mode can be mapped (the upper left pixel is at offset 0).
+    /// It is located in main memory, so that drawing into it is as fast as drawing into any other buffer.
+    fn mmap(&self, offset: usize, length: usize) -> Result<(PhysFrameRange, PageTableFlags)> {
+        let state = self.gpu.state.lock();
+        let backing = state.resource.as_ref().ok_or(Errno::InvalidArgument)?.backing;
+        let size = (backing.end - backing.start) as usize * PAGE_SIZE;
+        if offset % PAGE_SIZE != 0 || offset >= size || length > size - offset {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let start = backing.start + (offset / PAGE_SIZE) as u64;
+        return Ok((PhysFrame::range(start, start + length.div_ceil(PAGE_SIZE) as u64), PageTableFlags::empty()));
+    }
+}
+
+impl Pollable for GpuFile {
+    fn poll(&self, events: PollEvents, _waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        return events & PollEvents::WRITABLE;
+    }
+}
+
+impl Device for GpuDevice {
+    fn open(&self, _flags: OpenFlags) -> Result<Option<Arc<dyn File>>> {
+        if self.gpu.in_use.swap(true, AcqRel) {
+            return Err(Errno::Busy);
+        }
+
+        if let Err(err) = self.gpu.start() {
+            self.gpu.in_use.store(false, Release);
+            return Err(err);
+        }
+
+        return Ok(Some(Arc::new(GpuFile { gpu: Arc::clone(&self.gpu), retired: Mutex::new(Vec::new()) })));
+    }
+}
@@ -0,0 +1,426 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::ptr;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering::Relaxed;
+use syscall::error::Errno;
+use x86_64::structures::paging::page::PageRange;
+use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::VirtAddr;
+use crate::device::pci::{msi, CapabilityId, CommandFlag, PciDevice};
+use crate::interrupt::interrupt_handler::InterruptHandler;
+use crate::memory::{physical, MemorySpace, PAGE_SIZE};
+use crate::process::process::current_process;
+use crate::process::wait_queue::WaitQueue;
+use crate::sync::Mutex;
+use crate::{scheduler, timer};
+
+pub mod gpu;
+pub mod rng;
+
+pub const VENDOR_ID: u16 = 0x1af4;
+/// Modern devices have the id 0x1040 + device type (transitional devices with ids below have a legacy interface as well).
+const MODERN_DEVICE_ID_BASE: u16 = 0x1040;
+
+const REQUEST_TIMEOUT_MS: usize = 5000;
+/// Maximum number of entries per queue (the descriptor table and both rings of a queue fit into one page).
+const MAX_QUEUE_SIZE: u16 = 64;
+const NO_VECTOR: u16 = 0xffff;
+
+// Types of the vendor specific capabilities, which locate the configuration structures in the memory BARs
+const CAPABILITY_COMMON: u8 = 1;
+const CAPABILITY_NOTIFY: u8 = 2;
+const CAPABILITY_DEVICE: u8 = 4;
+
+// Offsets in the vendor specific capabilities
+const CAPABILITY_TYPE: u8 = 0x03;
+const CAPABILITY_BAR: u8 = 0x04;
+const CAPABILITY_OFFSET: u8 = 0x08;
+const CAPABILITY_LENGTH: u8 = 0x0c;
+const CAPABILITY_NOTIFY_MULTIPLIER: u8 = 0x10;
+
+// Offsets in the common configuration structure
+const DEVICE_FEATURE_SELECT: u64 = 0x00;
+const DEVICE_FEATURE: u64 = 0x04;
+const DRIVER_FEATURE_SELECT: u64 = 0x08;
+const DRIVER_FEATURE: u64 = 0x0c;
+const CONFIG_MSIX_VECTOR: u64 = 0x10;
+const DEVICE_STATUS: u64 = 0x14;
+const QUEUE_SELECT: u64 = 0x16;
+const QUEUE_SIZE: u64 = 0x18;
+const QUEUE_MSIX_VECTOR: u64 = 0x1a;
+const QUEUE_ENABLE: u64 = 0x1c;
+const QUEUE_NOTIFY_OFFSET: u64 = 0x1e;
+const QUEUE_DESCRIPTORS: u64 = 0x20;
+const QUEUE_DRIVER: u64 = 0x28;
+const QUEUE_DEVICE: u64 = 0x30;
+
+const STATUS_ACKNOWLEDGE: u8 = 1 << 0;
+const STATUS_DRIVER: u8 = 1 << 1;
+const STATUS_DRIVER_OK: u8 = 1 << 2;
+const STATUS_FEATURES_OK: u8 = 1 << 3;
+const STATUS_FAILED: u8 = 1 << 7;
+
+/// Offered by all devices, which implement the modern interface (the legacy interface is not supported).
+const FEATURE_VERSION_1: u64 = 1 << 32;
+
+// Layout of a queue page: Descriptor table, driver ring ("available") and device ring ("used")
+const DESCRIPTOR_SIZE: u64 = 16;
+const DRIVER_RING: u64 = 0x400;
+const DEVICE_RING: u64 = 0x800;
+const DEVICE_RING_ENTRY_SIZE: u64 = 8;
+
+const DESCRIPTOR_NEXT: u16 = 1 << 0;
+const DESCRIPTOR_WRITE: u16 = 1 << 1;
+
+pub type Result<T> = core::result::Result<T, Errno>;
+
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(u16)]
+pub enum DeviceType {
+    Network = 1,
+    Block = 2,
+    Entropy = 4,
+    Gpu = 16,
+    Input = 18,
+}
+
+/// Memory mapped configuration structure, located via a vendor specific capability.
+#[derive(Copy, Clone)]
+struct Registers {
+    base: u64,
+}
+
+/// Device, which is accessed via the virtio 1.x PCI transport (e.g. QEMU's '-device virtio-gpu-pci').
+pub struct VirtioDevice {
+    pci: Arc<PciDevice>,
+    common: Registers,
+    notify: Registers,
+    /// Distance between the notification registers of the queues in bytes.
+    notify_multiplier: u32,
+    config: Registers,
+}
+
+/// Buffer of a request, which is read or written by the device.
+/// Physical memory is identity mapped, so the addresses of buffers in kernel memory can be given to the device directly.
+#[derive(Copy, Clone)]
+pub struct Buffer {
+    pub address: u64,
+    pub length: u32,
+    /// The device writes into the buffer (e.g. the response of a request), instead of reading it.
+    pub writable: bool,
+}
+
+/// Split virtqueue. Like the queues of the NVMe driver, requests are executed one at a time.
+pub struct Virtqueue {
+    state: Mutex<QueueState>,
+    /// Set, if the device signals used buffers via an interrupt (otherwise, the queue is polled).
+    interrupts: AtomicBool,
+    /// Notified by the interrupt handler, when the device has used a buffer.
+    buffer_used: WaitQueue,
+}
+
+struct QueueState {
+    index: u16,
+    size: u16,
+    address: u64,
+    /// Address of the register, the queue index is written to, for notifying the device about new requests.
+    notify: u64,
+    next_driver_index: u16,
+    next_device_index: u16,
+    /// Set, if the device has not used the last request in time. Its descriptors are only reused, after the device has used it.
+    timed_out: bool,
+}
+
+struct VirtioInterruptHandler {
+    queue: Arc<Virtqueue>,
+}
+
+/// PCI device id of modern devices of the given type (for matching drivers, see `PciId::device()`).
+pub const fn device_id(typ: DeviceType) -> u16 {
+    return MODERN_DEVICE_ID_BASE + typ as u16;
+}
+
+impl Registers {
+    fn read_u8(&self, offset: u64) -> u8 {
+        return unsafe { ((self.base + offset) as *const u8).read_volatile() };
+    }
+
+    fn read_u16(&self, offset: u64) -> u16 {
+        return unsafe { ((self.base + offset) as *const u16).read_volatile() };
+    }
+
+    fn read_u32(&self, offset: u64) -> u32 {
+        return unsafe { ((self.base + offset) as *const u32).read_volatile() };
+    }
+
+    fn write_u8(&self, offset: u64, value: u8) {
+        unsafe { ((self.base + offset) as *mut u8).write_volatile(value); }
+    }
+
+    fn write_u16(&self, offset: u64, value: u16) {
+        unsafe { ((self.base + offset) as *mut u16).write_volatile(value); }
+    }
+
+    fn write_u32(&self, offset: u64, value: u32) {
+        unsafe { ((self.base + offset) as *mut u32).write_volatile(value); }
+    }
+
+    fn write_u64(&self, offset: u64, value: u64) {
+        // 64-bit registers may be written as two 32-bit halves
+        self.write_u32(offset, value as u32);
+        self.write_u32(offset + 4, (value >> 32) as u32);
+    }
+}
+
+impl VirtioDevice {
+    /// Locate and map the configuration structures of `device`. The device is not reset or initialized yet (see `initialize()`).
+    pub fn new(device: &Arc<PciDevice>) -> Result<Self> {
+        device.set_command_flag(CommandFlag::MemorySpace, true);
+        device.set_command_flag(CommandFlag::BusMaster, true);
+
+        let mut common = None;
+        let mut notify = None;
+        let mut notify_multiplier = 0;
+        let mut config = None;
+
+        // Only the first capability of each type is used, as recommended by the specification
+        for capability in device.find_capabilities(CapabilityId::VendorSpecific) {
+            let bar = device.read_u8(capability + CAPABILITY_BAR);
+            if bar > 5 {
+                continue;
+            }
+
+            let base = device.bar(bar) + device.read_u32(capability + CAPABILITY_OFFSET) as u64;
+            let length = device.read_u32(capability + CAPABILITY_LENGTH) as u64;
+            let registers = Some(Registers { base });
+
+            match device.read_u8(capability + CAPABILITY_TYPE) {
+                CAPABILITY_COMMON if common.is_none() => common = registers,
+                CAPABILITY_NOTIFY if notify.is_none() => {
+                    notify = registers;
+                    notify_multiplier = device.read_u32(capability + CAPABILITY_NOTIFY_MULTIPLIER);
+                }
+                CAPABILITY_DEVICE if config.is_none() => config = registers,
+                _ => continue
+            }
+
+            if length > 0 {
+                map_registers(base, length);
+            }
+        }
+
+        return match (common, notify, config) {
+            (Some(common), Some(notify), Some(config)) => Ok(Self { pci: Arc::clone(device), common, notify, notify_multiplier, config }),
+            _ => Err(Errno::NotSupported) // Legacy device without modern interface
+        };
+    }
+
+    pub fn pci(&self) -> &Arc<PciDevice> {
+        return &self.pci;
+    }
+
+    /// Reset the device and negotiate the given device specific `features`. Returns the features, supported by both sides.
+    /// Afterward, the queues must be set up with `enable_queue()`, before the device is started with `start()`.
+    pub fn initialize(&self, features: u64) -> Result<u64> {
+        self.reset()?;
+        self.common.write_u8(DEVICE_STATUS, STATUS_ACKNOWLEDGE);
+        self.common.write_u8(DEVICE_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        self.common.write_u32(DEVICE_FEATURE_SELECT, 0);
+        let mut device_features = self.common.read_u32(DEVICE_FEATURE) as u64;
+        self.common.write_u32(DEVICE_FEATURE_SELECT, 1);
+        device_features |= (self.common.read_u32(DEVICE_FEATURE) as u64) << 32;
+        if device_features & FEATURE_VERSION_1 == 0 {
+            self.common.write_u8(DEVICE_STATUS, STATUS_FAILED);
+            return Err(Errno::NotSupported);
+        }
+
+        let features = device_features & (features | FEATURE_VERSION_1);
+        self.common.write_u32(DRIVER_FEATURE_SELECT, 0);
+        self.common.write_u32(DRIVER_FEATURE, features as u32);
+        self.common.write_u32(DRIVER_FEATURE_SELECT, 1);
+        self.common.write_u32(DRIVER_FEATURE, (features >> 32) as u32);
+
+        // The device clears the bit again, if it does not accept the features
+        self.common.write_u8(DEVICE_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK);
+        if self.common.read_u8(DEVICE_STATUS) & STATUS_FEATURES_OK == 0 {
+            self.common.write_u8(DEVICE_STATUS, STATUS_FAILED);
+            return Err(Errno::NotSupported);
+        }
+
+        self.common.write_u16(CONFIG_MSIX_VECTOR, NO_VECTOR);
+        return Ok(features & !FEATURE_VERSION_1);
+    }
+
+    /// Set up `queue` (with its index given at creation) after `initialize()`.
+    /// If `msix_entry` is given, the device signals used buffers via this MSI-X table entry (see `request_interrupt()`).
+    pub fn enable_queue(&self, queue: &Virtqueue, msix_entry: Option<u16>) -> Result<()> {
+        let mut state = queue.state.lock();
+        self.common.write_u16(QUEUE_SELECT, state.index);
+
+        let size = self.common.read_u16(QUEUE_SIZE).min(MAX_QUEUE_SIZE);
+        if size == 0 {
+            return Err(Errno::NotFound);
+        }
+
+        unsafe { ptr::write_bytes(state.address as *mut u8, 0, PAGE_SIZE); }
+        state.size = size;
+        state.next_driver_index = 0;
+        state.next_device_index = 0;
+        state.timed_out = false;
+        state.notify = self.notify.base + self.common.read_u16(QUEUE_NOTIFY_OFFSET) as u64 * self.notify_multiplier as u64;
+
+        self.common.write_u16(QUEUE_SIZE, size);
+        self.common.write_u64(QUEUE_DESCRIPTORS, state.address);
+        self.common.write_u64(QUEUE_DRIVER, state.address + DRIVER_RING);
+        self.common.write_u64(QUEUE_DEVICE, state.address + DEVICE_RING);
+
+        // The device sets the vector to NO_VECTOR, if it cannot use it
+        self.common.write_u16(QUEUE_MSIX_VECTOR, msix_entry.unwrap_or(NO_VECTOR));
+        if msix_entry.is_some() && self.common.read_u16(QUEUE_MSIX_VECTOR) == NO_VECTOR {
+            return Err(Errno::NotSupported);
+        }
+
+        self.common.write_u16(QUEUE_ENABLE, 1);
+        return Ok(());
+    }
+
+    /// Let the device process requests, after all queues have been set up.
+    pub fn start(&self) {
+        self.common.write_u8(DEVICE_STATUS, self.common.read_u8(DEVICE_STATUS) | STATUS_DRIVER_OK);
+    }
+
+    /// Reset the device, so that it stops using all queues and buffers.
+    pub fn reset(&self) -> Result<()> {
+        self.common.write_u8(DEVICE_STATUS, 0);
+
+        let start = timer().read().systime_ms();
+        while self.common.read_u8(DEVICE_STATUS) != 0 {
+            if timer().read().systime_ms() - start > REQUEST_TIMEOUT_MS {
+                return Err(Errno::IoError);
+            }
+
+            core::hint::spin_loop();
+        }
+
+        return Ok(());
+    }
+
+    /// Assign an interrupt handler to MSI-X table entry `entry`, which signals used buffers of `queue`.
+    /// Returns false, if the device does not support MSI-X or the entry does not exist (the queue is polled in this case).
+    pub fn request_interrupt(&self, entry: u16, queue: &Arc<Virtqueue>) -> bool {
+        let handler = Box::new(VirtioInterruptHandler { queue: Arc::clone(queue) });
+        let assigned = msi::request_msix(&self.pci, entry, msi::default_target(), handler).is_some();
+        queue.interrupts.store(assigned, Relaxed);
+
+        return assigned;
+    }
+
+    /// Read a field of the device specific configuration structure.
+    pub fn config_u32(&self, offset: u64) -> u32 {
+        return self.config.read_u32(offset);
+    }
+
+    pub fn write_config_u32(&self, offset: u64, value: u32) {
+        self.config.write_u32(offset, value);
+    }
+}
+
+impl Virtqueue {
+    /// Allocate the memory for the queue with the given index. It is set up by `VirtioDevice::enable_queue()`.
+    pub fn new(index: u16) -> Self {
+        let address = physical::alloc(1).start.start_address().as_u64();
+        let state = QueueState { index, size: 0, address, notify: 0, next_driver_index: 0, next_device_index: 0, timed_out: false };
+
+        return Self { state: Mutex::new(state), interrupts: AtomicBool::new(false), buffer_used: WaitQueue::new() };
+    }
+
+    /// Give the chained `buffers` to the device and wait, until it has processed them.
+    /// Returns the number of bytes, the device has written into the writable buffers.
+    /// Fails with `Errno::Busy`, if the device has still not used a request, which has timed out before.
+    pub fn execute(&self, buffers: &[Buffer]) -> Result<usize> {
+        let mut state = self.state.lock();
+        if buffers.is_empty() || buffers.len() > state.size as usize {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let device_ring = state.address + DEVICE_RING;
+        let device_index = || unsafe { ((device_ring + 2) as *const u16).read_volatile() };
+        if state.timed_out {
+            if device_index() == state.next_device_index {
+                return Err(Errno::Busy);
+            }
+
+            // The device has finally used the request, so its entry in the device ring is skipped and its descriptors can be reused
+            state.next_device_index = state.next_device_index.wrapping_add(1);
+            state.timed_out = false;
+        }
+
+        // Only one request is in flight, so the chain always starts at the first descriptor
+        for (index, buffer) in buffers.iter().enumerate() {
+            let mut flags = if buffer.writable { DESCRIPTOR_WRITE } else { 0 };
+            if index + 1 < buffers.len() {
+                flags |= DESCRIPTOR_NEXT;
+            }
+
+            unsafe {
+                let descriptor = (state.address + index as u64 * DESCRIPTOR_SIZE) as *mut u8;
+                (descriptor as *mut u64).write_volatile(buffer.address);
+                (descriptor.add(8) as *mut u32).write_volatile(buffer.length);
+                (descriptor.add(12) as *mut u16).write_volatile(flags);
+                (descriptor.add(14) as *mut u16).write_volatile(index as u16 + 1);
+            }
+        }
+
+        // Add the chain to the driver ring, before its index is incremented (the device only reads entries below the index)
+        let driver_index = state.next_driver_index;
+        unsafe {
+            let ring = (state.address + DRIVER_RING) as *mut u16;
+            ring.add(2 + (driver_index % state.size) as usize).write_volatile(0);
+            ring.add(1).write_volatile(driver_index.wrapping_add(1));
+        }
+        state.next_driver_index = driver_index.wrapping_add(1);
+
+        unsafe { (state.notify as *mut u16).write_volatile(state.index); }
+
+        // Like in the NVMe driver, the thread blocks until the interrupt handler signals a used buffer (queues without interrupts are polled)
+        let next_device_index = state.next_device_index;
+        let used = || device_index() != next_device_index;
+        let used = if self.interrupts.load(Relaxed) {
+            self.buffer_used.wait_until(used, Some(REQUEST_TIMEOUT_MS))
+        } else {
+            let start = timer().read().systime_ms();
+            while !used() && timer().read().systime_ms() - start <= REQUEST_TIMEOUT_MS {
+                scheduler().switch_thread();
+            }
+
+            used()
+        };
+
+        if !used {
+            state.timed_out = true;
+            return Err(Errno::IoError);
+        }
+
+        let entry = device_ring + 4 + (state.next_device_index % state.size) as u64 * DEVICE_RING_ENTRY_SIZE;
+        let length = unsafe { ((entry + 4) as *const u32).read_volatile() };
+        state.next_device_index = state.next_device_index.wrapping_add(1);
+
+        return Ok(length as usize);
+    }
+}
+
+impl InterruptHandler for VirtioInterruptHandler {
+    fn trigger(&mut self) {
+        // With MSI-X, the interrupt status register does not need to be read for acknowledging the interrupt
+        self.queue.buffer_used.notify_all_from_interrupt();
+    }
+}
+
+fn map_registers(base: u64, size: u64) {
+    let start_page = Page::containing_address(VirtAddr::new(base));
+    let end_page = Page::containing_address(VirtAddr::new(base + size - 1)) + 1;
+    current_process().address_space().map(PageRange { start: start_page, end: end_page }, MemorySpace::Kernel, PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE);
+}
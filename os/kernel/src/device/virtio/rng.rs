@@ -0,0 +1,86 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::slice;
+use log::{info, warn};
+use crate::device::pci::driver::{PciDriver, PciId};
+use crate::device::pci::PciDevice;
+use crate::device::virtio::{device_id, Buffer, DeviceType, VirtioDevice, Virtqueue, VENDOR_ID};
+use crate::fs::Result;
+use crate::interrupt::deferred;
+use crate::memory::physical;
+use crate::sync::Mutex;
+use crate::{random, timer};
+
+const REQUEST_QUEUE: u16 = 0;
+/// The request queue signals used buffers via this MSI-X table entry (entry 0 is reserved for configuration changes).
+const REQUEST_QUEUE_MSIX_ENTRY: u16 = 1;
+/// Transitional entropy devices (QEMU's default for '-device virtio-rng-pci' on the PC machine) use the legacy device id,
+/// but offer the modern interface as well.
+const TRANSITIONAL_DEVICE_ID: u16 = 0x1005;
+
+/// Number of bytes requested from the device each time.
+const ENTROPY_BYTES: u32 = 64;
+/// Interval, in which further entropy is mixed into the kernel CSPRNG (after the initial request during probing).
+const RESEED_INTERVAL_MS: usize = 30000;
+
+/// Entropy device (e.g. QEMU's '-device virtio-rng-pci'), which feeds the kernel random number generator (see `random`).
+/// Useful in virtual machines, whose CPU model does not provide RDRAND or RDSEED.
+pub struct VirtioRng {
+    queue: Arc<Virtqueue>,
+    interrupts: bool,
+    /// Physical address of the page, the device writes the random bytes into.
+    buffer: Mutex<u64>,
+}
+
+/// Driver for virtio entropy devices, which mixes their output into the kernel random number generator.
+pub static DRIVER: PciDriver = PciDriver {
+    name: "virtio-rng",
+    ids: &[PciId::device(VENDOR_ID, device_id(DeviceType::Entropy)), PciId::device(VENDOR_ID, TRANSITIONAL_DEVICE_ID)],
+    probe
+};
+
+fn probe(device: &Arc<PciDevice>) -> Result<()> {
+    let rng = Arc::new(VirtioRng::new(device)?);
+    let count = rng.add_entropy()?;
+    info!("Virtio entropy device [{}]: Received [{}] bytes, Interrupts: [{}]",
+        device.address(), count, if rng.interrupts { "MSI-X" } else { "Polling" });
+
+    // Requests may block, so they are executed by the worker thread instead of the timer tasklet
+    timer::schedule_periodic(RESEED_INTERVAL_MS, Box::new(move || {
+        let rng = Arc::clone(&rng);
+        deferred::schedule_work(Box::new(move || {
+            if let Err(err) = rng.add_entropy() {
+                warn!("Failed to read from virtio entropy device (Error: {:?})", err);
+            }
+        }));
+    }));
+
+    return Ok(());
+}
+
+impl VirtioRng {
+    fn new(pci: &Arc<PciDevice>) -> Result<Self> {
+        let device = VirtioDevice::new(pci)?;
+        let queue = Arc::new(Virtqueue::new(REQUEST_QUEUE));
+        let interrupts = device.request_interrupt(REQUEST_QUEUE_MSIX_ENTRY, &queue);
+
+        // The device has no features and no configuration, so it only needs a single queue
+        device.initialize(0)?;
+        device.enable_queue(&queue, if interrupts { Some(REQUEST_QUEUE_MSIX_ENTRY) } else { None })?;
+        device.start();
+
+        let buffer = physical::alloc(1).start.start_address().as_u64();
+        return Ok(Self { queue, interrupts, buffer: Mutex::new(buffer) });
+    }
+
+    /// Request random bytes from the device and mix them into the kernel random number generator.
+    /// Returns the number of bytes, the device has provided.
+    fn add_entropy(&self) -> Result<usize> {
+        let buffer = self.buffer.lock();
+        let count = self.queue.execute(&[Buffer { address: *buffer, length: ENTROPY_BYTES, writable: true }])?;
+        let data = unsafe { slice::from_raw_parts(*buffer as *const u8, count.min(ENTROPY_BYTES as usize)) };
+        random::add_entropy(data);
+
+        return Ok(count);
+    }
+}
@@ -0,0 +1,139 @@
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use syscall::error::Errno;
+use syscall::file::FileType;
+use crate::fs::{Inode, Result, MAX_NAME_LENGTH};
+use crate::sync::RwLock;
+
+/// Cached directory entry, connecting a name in the directory tree to its inode.
+/// Looked up children are kept in their parent, so that resolving frequently used paths does not hit the filesystem.
+pub struct Dentry {
+    name: String,
+    inode: Arc<dyn Inode>,
+    /// None for the root of the directory tree.
+    /// The root of a mounted filesystem uses the parent of its mount point, so that '..' leaves the filesystem.
+    parent: Option<Weak<Dentry>>,
+    children: RwLock<BTreeMap<String, Arc<Dentry>>>,
+    /// Root of the filesystem mounted on top of this entry, which hides the original contents.
+    mounted: RwLock<Option<Arc<Dentry>>>,
+}
+
+impl Dentry {
+    pub fn new_root(inode: Arc<dyn Inode>) -> Arc<Self> {
+        return Arc::new(Self { name: String::from("/"), inode, parent: None, children: RwLock::new(BTreeMap::new()), mounted: RwLock::new(None) });
+    }
+
+    fn new(name: &str, inode: Arc<dyn Inode>, parent: Option<Weak<Dentry>>) -> Arc<Self> {
+        return Arc::new(Self { name: name.to_string(), inode, parent, children: RwLock::new(BTreeMap::new()), mounted: RwLock::new(None) });
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn inode(&self) -> Arc<dyn Inode> {
+        Arc::clone(&self.inode)
+    }
+
+    pub fn parent(self: &Arc<Self>) -> Arc<Dentry> {
+        return match self.parent.as_ref().and_then(|parent| parent.upgrade()) {
+            Some(parent) => parent,
+            None => Arc::clone(self)
+        };
+    }
+
+    /// Check, if this entry is `other` or one of its ancestors (following '..' from `other` up to the root).
+    pub fn is_ancestor_of(self: &Arc<Self>, other: &Arc<Dentry>) -> bool {
+        let mut dentry = Arc::clone(other);
+        loop {
+            if Arc::ptr_eq(self, &dentry) {
+                return true;
+            }
+
+            let parent = dentry.parent();
+            if Arc::ptr_eq(&parent, &dentry) {
+                return false;
+            }
+
+            dentry = parent;
+        }
+    }
+
+    pub fn is_directory(&self) -> bool {
+        return self.inode.metadata().typ == FileType::Directory;
+    }
+
+    /// Mount the filesystem with the root inode `root` on this entry.
+    pub fn mount(&self, root: Arc<dyn Inode>) -> Result<()> {
+        let mut mounted = self.mounted.write();
+        if mounted.is_some() {
+            return Err(Errno::Busy);
+        }
+
+        mounted.replace(Dentry::new(&self.name, root, self.parent.clone()));
+        return Ok(());
+    }
+
+    /// Remove the filesystem mounted on this entry, so that the original contents are visible again.
+    pub fn unmount(&self) -> Result<()> {
+        return self.mounted.write().take().map(|_| ()).ok_or(Errno::InvalidArgument);
+    }
+
+    /// Follow mount points, so that the root of the topmost mounted filesystem is returned.
+    pub fn follow_mounts(self: &Arc<Self>) -> Arc<Dentry> {
+        let mut dentry = Arc::clone(self);
+        loop {
+            let mounted = dentry.mounted.read().clone();
+            match mounted {
+                Some(root) => dentry = root,
+                None => return dentry
+            }
+        }
+    }
+
+    /// Resolve a single path component, using the cache if possible.
+    pub fn lookup(self: &Arc<Self>, name: &str) -> Result<Arc<Dentry>> {
+        match name {
+            "" | "." => return Ok(Arc::clone(self)),
+            ".." => return Ok(self.parent().follow_mounts()),
+            _ => {}
+        }
+
+        if name.len() > MAX_NAME_LENGTH {
+            return Err(Errno::NameTooLong);
+        }
+
+        if let Some(child) = self.children.read().get(name) {
+            return Ok(child.follow_mounts());
+        }
+
+        let inode = self.inode.lookup(name)?;
+        let child = Dentry::new(name, inode, Some(Arc::downgrade(self)));
+        self.children.write().insert(name.to_string(), Arc::clone(&child));
+
+        return Ok(child.follow_mounts());
+    }
+
+    /// Add a newly created inode to the cache.
+    pub fn insert(self: &Arc<Self>, name: &str, inode: Arc<dyn Inode>) -> Arc<Dentry> {
+        let child = Dentry::new(name, inode, Some(Arc::downgrade(self)));
+        self.children.write().insert(name.to_string(), Arc::clone(&child));
+
+        return child;
+    }
+
+    /// Remove a child from the cache (e.g. after it has been deleted).
+    /// Fails with `Errno::Busy`, if another filesystem is mounted on the child.
+    pub fn invalidate(&self, name: &str) -> Result<()> {
+        let mut children = self.children.write();
+        if let Some(child) = children.get(name) {
+            if child.mounted.read().is_some() {
+                return Err(Errno::Busy);
+            }
+        }
+
+        children.remove(name);
+        return Ok(());
+    }
+}
@@ -0,0 +1,215 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering::Relaxed;
+use log::info;
+use syscall::error::Errno;
+use syscall::file::{FileType, OpenFlags, PollEvents};
+use x86_64::structures::paging::frame::PhysFrameRange;
+use x86_64::structures::paging::PageTableFlags;
+use crate::fs::{DirEntry, File, FileSystem, Inode, Metadata, Result};
+use crate::process::wait_queue::Waiter;
+use crate::sync::RwLock;
+
+/// Interface between drivers and the VFS. Reads, writes and control requests on a device node in '/dev' are passed to its driver.
+/// Character devices usually ignore `offset`, while block devices use it as byte position on the device.
+pub trait Device: Send + Sync {
+    fn read(&self, _offset: usize, _buffer: &mut [u8]) -> Result<usize> {
+        return Err(Errno::NotSupported);
+    }
+
+    fn write(&self, _offset: usize, _buffer: &[u8]) -> Result<usize> {
+        return Err(Errno::NotSupported);
+    }
+
+    fn ioctl(&self, _request: usize, _arg: usize) -> Result<usize> {
+        return Err(Errno::NotATerminal);
+    }
+
+    /// Size in bytes (0 for character devices).
+    fn size(&self) -> usize {
+        return 0;
+    }
+
+    /// Write buffered data through to the hardware (see `Inode::sync()`).
+    fn sync(&self) -> Result<()> {
+        return Ok(());
+    }
+
+    /// Devices, whose reads block (e.g. input devices), must register `waiter` while no data is available (see `Pollable::poll()`).
+    fn poll(&self, events: PollEvents, _waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        return events & (PollEvents::READABLE | PollEvents::WRITABLE);
+    }
+
+    /// Physical memory backing `length` bytes at `offset` (e.g. video memory) and the caching flags for mapping it into user space (see `Inode::mmap()`).
+    fn mmap(&self, _offset: usize, _length: usize) -> Result<(PhysFrameRange, PageTableFlags)> {
+        return Err(Errno::NoDevice);
+    }
+
+    /// Devices with state per opened file (e.g. a queue for each reader) return their own file (see `Inode::open()`).
+    fn open(&self, _flags: OpenFlags) -> Result<Option<Arc<dyn File>>> {
+        return Ok(None);
+    }
+}
+
+/// Filesystem, exposing all registered devices (mounted at '/dev').
+pub struct Devfs;
+
+struct DeviceNode {
+    number: u64,
+    typ: FileType,
+    device: Arc<dyn Device>,
+}
+
+/// The root directory (with an empty path) or a subdirectory, grouping devices of the same kind (e.g. 'input').
+struct DevfsDirectory {
+    path: String,
+    number: u64,
+}
+
+struct DeviceInode {
+    number: u64,
+    typ: FileType,
+    device: Arc<dyn Device>,
+}
+
+/// Devices by their path relative to '/dev' (e.g. 'null' or 'input/event0').
+static DEVICES: RwLock<BTreeMap<String, DeviceNode>> = RwLock::new(BTreeMap::new());
+/// Subdirectories and their inode numbers, created on demand by `register()` (they are kept, when they become empty).
+static DIRECTORIES: RwLock<BTreeMap<String, u64>> = RwLock::new(BTreeMap::new());
+/// Inode number 1 is used by the root directory.
+static NEXT_INODE: AtomicU64 = AtomicU64::new(2);
+
+/// Make a device available as '/dev/<name>'. `typ` must be either `FileType::CharDevice` or `FileType::BlockDevice`.
+/// The name may contain subdirectories (e.g. 'input/event0'), which are created as needed.
+pub fn register(name: &str, typ: FileType, device: Arc<dyn Device>) -> Result<()> {
+    if typ != FileType::CharDevice && typ != FileType::BlockDevice {
+        return Err(Errno::InvalidArgument);
+    }
+    if name.split('/').any(|component| component.is_empty() || component == "." || component == "..") {
+        return Err(Errno::InvalidArgument);
+    }
+
+    let mut devices = DEVICES.write();
+    let mut directories = DIRECTORIES.write();
+    if devices.contains_key(name) || directories.contains_key(name) {
+        return Err(Errno::AlreadyExists);
+    }
+
+    let mut parent = name;
+    while let Some((directory, _)) = parent.rsplit_once('/') {
+        if devices.contains_key(directory) {
+            return Err(Errno::NotADirectory);
+        }
+
+        directories.entry(directory.to_string()).or_insert_with(|| NEXT_INODE.fetch_add(1, Relaxed));
+        parent = directory;
+    }
+
+    devices.insert(name.to_string(), DeviceNode { number: NEXT_INODE.fetch_add(1, Relaxed), typ, device });
+    info!("Registered device [/dev/{}] ({:?})", name, typ);
+
+    return Ok(());
+}
+
+/// Remove a device (e.g. after it has been unplugged). Files, that are still opened, keep the driver alive.
+pub fn unregister(name: &str) -> Result<()> {
+    return match DEVICES.write().remove(name) {
+        Some(_) => Ok(()),
+        None => Err(Errno::NotFound)
+    };
+}
+
+impl FileSystem for Devfs {
+    fn name(&self) -> &'static str {
+        "devfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        return Arc::new(DevfsDirectory { path: String::new(), number: 1 });
+    }
+}
+
+impl DevfsDirectory {
+    fn child_path(&self, name: &str) -> String {
+        return if self.path.is_empty() { name.to_string() } else { format!("{}/{}", self.path, name) };
+    }
+
+    /// Return the name of `path` inside this directory, if it is a direct child.
+    fn child_name<'a>(&self, path: &'a str) -> Option<&'a str> {
+        let name = if self.path.is_empty() { path } else { path.strip_prefix(self.path.as_str())?.strip_prefix('/')? };
+        return if name.contains('/') { None } else { Some(name) };
+    }
+}
+
+impl Inode for DevfsDirectory {
+    fn metadata(&self) -> Metadata {
+        let size = self.readdir().map_or(0, |entries| entries.len());
+        return Metadata { inode: self.number, typ: FileType::Directory, size, created_ms: 0, modified_ms: 0 };
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let path = self.child_path(name);
+        if let Some(node) = DEVICES.read().get(&path) {
+            return Ok(Arc::new(DeviceInode { number: node.number, typ: node.typ, device: Arc::clone(&node.device) }));
+        }
+
+        return match DIRECTORIES.read().get(&path) {
+            Some(&number) => Ok(Arc::new(DevfsDirectory { path, number })),
+            None => Err(Errno::NotFound)
+        };
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        let directories = DIRECTORIES.read().iter()
+            .filter_map(|(path, &number)| self.child_name(path).map(|name| DirEntry { name: name.to_string(), inode: number, typ: FileType::Directory }))
+            .collect::<Vec<DirEntry>>();
+        let devices = DEVICES.read().iter()
+            .filter_map(|(path, node)| self.child_name(path).map(|name| DirEntry { name: name.to_string(), inode: node.number, typ: node.typ }))
+            .collect::<Vec<DirEntry>>();
+
+        return Ok(directories.into_iter().chain(devices).collect());
+    }
+}
+
+impl Inode for DeviceInode {
+    fn metadata(&self) -> Metadata {
+        return Metadata { inode: self.number, typ: self.typ, size: self.device.size(), created_ms: 0, modified_ms: 0 };
+    }
+
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        return self.device.read(offset, buffer);
+    }
+
+    fn write_at(&self, offset: usize, buffer: &[u8]) -> Result<usize> {
+        return self.device.write(offset, buffer);
+    }
+
+    fn truncate(&self, _size: usize) -> Result<()> {
+        // Opening a device with `OpenFlags::TRUNCATE` is allowed, but has no effect
+        return Ok(());
+    }
+
+    fn ioctl(&self, request: usize, arg: usize) -> Result<usize> {
+        return self.device.ioctl(request, arg);
+    }
+
+    fn sync(&self) -> Result<()> {
+        return self.device.sync();
+    }
+
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        return self.device.poll(events, waiter);
+    }
+
+    fn mmap(&self, offset: usize, length: usize) -> Result<(PhysFrameRange, PageTableFlags)> {
+        return self.device.mmap(offset, length);
+    }
+
+    fn open(&self, flags: OpenFlags) -> Result<Option<Arc<dyn File>>> {
+        return self.device.open(flags);
+    }
+}
@@ -0,0 +1,90 @@
+use alloc::sync::Arc;
+use core::ptr;
+use syscall::error::Errno;
+use syscall::file::{FileType, PollEvents};
+use crate::fs::{poll, File, Metadata, Result};
+use crate::fs::poll::Pollable;
+use crate::process::wait_queue::{WaitQueue, Waiter};
+use crate::sync::Mutex;
+
+/// Largest value of the counter (like on Linux, so that the maximum value of a u64 is never reached).
+const MAX_VALUE: u64 = u64::MAX - 1;
+
+/// Counter for signaling events between threads and processes (like Linux's eventfd), which can be waited for with `poll()`.
+/// Writing an 8-byte value adds it to the counter, blocking while the counter would exceed its maximum.
+/// Reading blocks while the counter is 0 and returns its value as 8 bytes, resetting it to 0
+/// (in semaphore mode, reading returns 1 and decrements the counter instead).
+pub struct EventCounter {
+    value: Mutex<u64>,
+    semaphore: bool,
+    readable: WaitQueue,
+    writable: WaitQueue,
+}
+
+impl EventCounter {
+    pub fn new(initial: u64, semaphore: bool) -> Arc<Self> {
+        return Arc::new(Self { value: Mutex::new(initial.min(MAX_VALUE)), semaphore, readable: WaitQueue::new(), writable: WaitQueue::new() });
+    }
+}
+
+impl File for EventCounter {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+        if buffer.len() < size_of::<u64>() {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let mut value = self.value.lock();
+        while *value == 0 {
+            self.readable.wait(value);
+            value = self.value.lock();
+        }
+
+        let result = if self.semaphore { 1 } else { *value };
+        *value -= result;
+        drop(value);
+        self.writable.notify_all();
+
+        buffer[..size_of::<u64>()].copy_from_slice(&result.to_ne_bytes());
+        return Ok(size_of::<u64>());
+    }
+
+    fn write(&self, buffer: &[u8]) -> Result<usize> {
+        let addend = u64::from_ne_bytes(buffer.get(..size_of::<u64>()).ok_or(Errno::InvalidArgument)?.try_into().unwrap());
+        if addend > MAX_VALUE {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let mut value = self.value.lock();
+        while *value > MAX_VALUE - addend {
+            self.writable.wait(value);
+            value = self.value.lock();
+        }
+
+        *value += addend;
+        drop(value);
+        if addend > 0 {
+            self.readable.notify_all();
+        }
+
+        return Ok(size_of::<u64>());
+    }
+
+    fn stat(&self) -> Result<Metadata> {
+        return Ok(Metadata { inode: ptr::from_ref(self) as u64, typ: FileType::CharDevice, size: 0, created_ms: 0, modified_ms: 0 });
+    }
+}
+
+impl Pollable for EventCounter {
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        let value = self.value.lock();
+        let mut ready = PollEvents::empty();
+        if *value > 0 {
+            ready |= PollEvents::READABLE;
+        }
+        if *value < MAX_VALUE {
+            ready |= PollEvents::WRITABLE;
+        }
+
+        return poll::ready_or_register(ready, events, waiter, &[(PollEvents::READABLE, &self.readable), (PollEvents::WRITABLE, &self.writable)]);
+    }
+}
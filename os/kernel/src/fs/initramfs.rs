@@ -0,0 +1,223 @@
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::str::from_utf8;
+use log::warn;
+use syscall::error::Errno;
+use syscall::file::FileType;
+use crate::fs::{DirEntry, FileSystem, Inode, Metadata, Result};
+
+const BLOCK_SIZE: usize = 512;
+
+/// Read-only filesystem, backed by a ustar archive in memory (the initial ramdisk).
+/// The archive is parsed once and file contents are read directly from it, without copying.
+pub struct Initramfs {
+    nodes: Arc<Vec<Node>>,
+}
+
+struct Node {
+    typ: FileType,
+    data: &'static [u8],
+    children: BTreeMap<String, usize>,
+}
+
+struct InitramfsInode {
+    nodes: Arc<Vec<Node>>,
+    index: usize,
+}
+
+/// Header of a file inside a ustar archive (see POSIX 'pax' specification).
+struct Header<'a> {
+    block: &'a [u8],
+}
+
+impl Initramfs {
+    pub fn new(archive: &'static [u8]) -> Self {
+        let mut nodes = Vec::from([Node::new(FileType::Directory, &[])]);
+        let mut offset = 0;
+
+        while offset + BLOCK_SIZE <= archive.len() {
+            let header = Header { block: &archive[offset..offset + BLOCK_SIZE] };
+            if header.is_end() {
+                break;
+            }
+            if !header.is_valid() {
+                warn!("Initramfs: Invalid header at offset [{}]", offset);
+                break;
+            }
+
+            let size = header.size();
+            let data_start = offset + BLOCK_SIZE;
+            if data_start + size > archive.len() {
+                warn!("Initramfs: File [{}] exceeds archive", header.path());
+                break;
+            }
+
+            let typ = match header.typeflag() {
+                b'0' | b'\0' => Some(FileType::Regular),
+                b'5' => Some(FileType::Directory),
+                _ => None // Links and special files are not supported
+            };
+
+            if let Some(typ) = typ {
+                Initramfs::insert(&mut nodes, &header.path(), typ, &archive[data_start..data_start + size]);
+            }
+
+            offset = data_start + size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+        }
+
+        return Self { nodes: Arc::new(nodes) };
+    }
+
+    /// Add a node for `path`, creating missing parent directories on the way.
+    fn insert(nodes: &mut Vec<Node>, path: &str, typ: FileType, data: &'static [u8]) {
+        let mut components = path.split('/').filter(|component| !component.is_empty() && *component != ".").peekable();
+        let mut current = 0;
+
+        while let Some(name) = components.next() {
+            let last = components.peek().is_none();
+            current = match nodes[current].children.get(name) {
+                Some(index) => *index,
+                None => {
+                    let index = nodes.len();
+                    nodes.push(if last { Node::new(typ, data) } else { Node::new(FileType::Directory, &[]) });
+                    nodes[current].children.insert(name.to_string(), index);
+
+                    index
+                }
+            };
+        }
+    }
+
+    pub fn file_count(&self) -> usize {
+        return self.nodes.iter().filter(|node| node.typ == FileType::Regular).count();
+    }
+}
+
+impl FileSystem for Initramfs {
+    fn name(&self) -> &'static str {
+        "initramfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        return Arc::new(InitramfsInode { nodes: Arc::clone(&self.nodes), index: 0 });
+    }
+}
+
+impl Node {
+    fn new(typ: FileType, data: &'static [u8]) -> Self {
+        Self { typ, data, children: BTreeMap::new() }
+    }
+}
+
+impl InitramfsInode {
+    fn node(&self) -> &Node {
+        &self.nodes[self.index]
+    }
+}
+
+impl Inode for InitramfsInode {
+    fn metadata(&self) -> Metadata {
+        let node = self.node();
+        return Metadata { inode: self.index as u64 + 1, typ: node.typ, size: node.data.len(), created_ms: 0, modified_ms: 0 };
+    }
+
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        let data = self.node().data;
+        if offset >= data.len() {
+            return Ok(0);
+        }
+
+        let count = buffer.len().min(data.len() - offset);
+        buffer[..count].copy_from_slice(&data[offset..offset + count]);
+
+        return Ok(count);
+    }
+
+    fn write_at(&self, _offset: usize, _buffer: &[u8]) -> Result<usize> {
+        return Err(Errno::ReadOnly);
+    }
+
+    fn truncate(&self, _size: usize) -> Result<()> {
+        return Err(Errno::ReadOnly);
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let node = self.node();
+        if node.typ != FileType::Directory {
+            return Err(Errno::NotADirectory);
+        }
+
+        return match node.children.get(name) {
+            Some(index) => Ok(Arc::new(InitramfsInode { nodes: Arc::clone(&self.nodes), index: *index })),
+            None => Err(Errno::NotFound)
+        };
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        let node = self.node();
+        if node.typ != FileType::Directory {
+            return Err(Errno::NotADirectory);
+        }
+
+        return Ok(node.children.iter()
+            .map(|(name, index)| DirEntry { name: name.clone(), inode: *index as u64 + 1, typ: self.nodes[*index].typ })
+            .collect());
+    }
+
+    fn create(&self, _name: &str, _typ: FileType) -> Result<Arc<dyn Inode>> {
+        return Err(Errno::ReadOnly);
+    }
+
+    fn unlink(&self, _name: &str) -> Result<()> {
+        return Err(Errno::ReadOnly);
+    }
+}
+
+impl Header<'_> {
+    fn is_end(&self) -> bool {
+        return self.block.iter().all(|byte| *byte == 0);
+    }
+
+    /// Check the magic value ('ustar') and the checksum, which is calculated with the checksum field filled with spaces.
+    fn is_valid(&self) -> bool {
+        if &self.block[257..262] != b"ustar" {
+            return false;
+        }
+
+        let checksum = self.block.iter().enumerate()
+            .map(|(index, byte)| if (148..156).contains(&index) { b' ' as usize } else { *byte as usize })
+            .sum::<usize>();
+
+        return checksum == parse_octal(&self.block[148..156]);
+    }
+
+    fn path(&self) -> String {
+        let name = field_str(&self.block[0..100]);
+        let prefix = field_str(&self.block[345..500]);
+
+        return if prefix.is_empty() { name.to_string() } else { prefix.to_string() + "/" + name };
+    }
+
+    fn size(&self) -> usize {
+        return parse_octal(&self.block[124..136]);
+    }
+
+    fn typeflag(&self) -> u8 {
+        return self.block[156];
+    }
+}
+
+/// Fields are terminated by a null byte, unless they use their full length.
+fn field_str(field: &[u8]) -> &str {
+    let length = field.iter().position(|byte| *byte == 0).unwrap_or(field.len());
+    return from_utf8(&field[..length]).unwrap_or("");
+}
+
+fn parse_octal(field: &[u8]) -> usize {
+    return field.iter()
+        .skip_while(|byte| **byte == b' ')
+        .take_while(|byte| (b'0'..=b'7').contains(*byte))
+        .fold(0, |value, byte| value * 8 + (byte - b'0') as usize);
+}
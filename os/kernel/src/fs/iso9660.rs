@@ -0,0 +1,327 @@
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use syscall::error::Errno;
+use syscall::file::FileType;
+use crate::block::queue::RequestQueue;
+use crate::block_cache;
+use crate::fs::{DirEntry, FileSystem, Inode, Metadata, Result};
+
+/// ISO9660 uses 2048 byte sectors, independent of the block size of the underlying device.
+const SECTOR_SIZE: usize = 2048;
+/// The first 16 sectors are reserved for boot code (e.g. El Torito), followed by the volume descriptors.
+const VOLUME_DESCRIPTOR_START: usize = 16;
+const VOLUME_DESCRIPTOR_PRIMARY: u8 = 1;
+const VOLUME_DESCRIPTOR_TERMINATOR: u8 = 255;
+const DIRECTORY_FLAG: u8 = 0x02;
+
+/// Read-only filesystem for CD images (ISO9660 with Rock Ridge extensions for long, case-sensitive names).
+/// Directories are parsed on each lookup, relying on the block cache to keep repeated accesses cheap.
+pub struct Iso9660 {
+    volume: Arc<Volume>,
+    root: Record,
+}
+
+struct Volume {
+    queue: Arc<RequestQueue>,
+    /// Number of bytes to skip at the beginning of each system use area (announced by the 'SP' entry of the root directory).
+    susp_skip: Option<usize>,
+}
+
+/// Parsed directory record, describing a file or directory.
+#[derive(Clone)]
+struct Record {
+    name: String,
+    /// Byte offset of the file contents on the device.
+    extent: usize,
+    size: usize,
+    directory: bool,
+    /// Byte offset of the record itself (unique for each file, used as inode number).
+    position: usize,
+}
+
+struct IsoInode {
+    volume: Arc<Volume>,
+    record: Record,
+}
+
+impl Iso9660 {
+    /// Read the volume descriptors from `queue`. Fails with `InvalidArgument`, if the device does not contain an ISO9660 filesystem.
+    pub fn new(queue: Arc<RequestQueue>) -> Result<Self> {
+        let mut volume = Volume { queue, susp_skip: None };
+        let mut descriptor = vec![0; SECTOR_SIZE];
+        let mut sector = VOLUME_DESCRIPTOR_START;
+
+        loop {
+            volume.read(sector * SECTOR_SIZE, &mut descriptor).map_err(|_| Errno::InvalidArgument)?;
+            if &descriptor[1..6] != b"CD001" || descriptor[0] == VOLUME_DESCRIPTOR_TERMINATOR {
+                return Err(Errno::InvalidArgument);
+            }
+            if descriptor[0] == VOLUME_DESCRIPTOR_PRIMARY {
+                break;
+            }
+
+            sector += 1;
+        }
+
+        if read_u16(&descriptor, 128) as usize != SECTOR_SIZE {
+            return Err(Errno::NotSupported); // Other logical block sizes are allowed by the standard, but never used in practice
+        }
+
+        let root = Record::parse(&descriptor[156..190], sector * SECTOR_SIZE + 156, false).ok_or(Errno::InvalidArgument)?;
+
+        // Rock Ridge is detected by the 'SP' entry in the system use area of the first record ('.') of the root directory
+        let mut first = [0u8; 255];
+        volume.read(root.extent, &mut first)?;
+        let length = first[0] as usize;
+        if length >= 34 + 7 && &first[34..36] == b"SP" && first[38..40] == [0xbe, 0xef] {
+            volume.susp_skip = Some(first[40] as usize);
+        }
+
+        return Ok(Self { volume: Arc::new(volume), root });
+    }
+
+    pub fn has_rock_ridge(&self) -> bool {
+        return self.volume.susp_skip.is_some();
+    }
+}
+
+impl FileSystem for Iso9660 {
+    fn name(&self) -> &'static str {
+        "iso9660"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        return Arc::new(IsoInode { volume: Arc::clone(&self.volume), record: self.root.clone() });
+    }
+
+    /// The filesystem is read-only, but the device may still have cached blocks written via '/dev'.
+    fn sync(&self) -> Result<()> {
+        return block_cache().flush_device(&self.volume.queue);
+    }
+}
+
+impl Volume {
+    /// Read `buffer.len()` bytes at byte `offset` via the block cache.
+    fn read(&self, mut offset: usize, buffer: &mut [u8]) -> Result<()> {
+        let block_size = self.queue.block_size();
+        let mut done = 0;
+
+        while done < buffer.len() {
+            let block_offset = offset % block_size;
+            let count = (block_size - block_offset).min(buffer.len() - done);
+            block_cache().read(&self.queue, (offset / block_size) as u64, block_offset, &mut buffer[done..done + count])?;
+
+            done += count;
+            offset += count;
+        }
+
+        return Ok(());
+    }
+
+    /// Parse all records of a directory, skipping the entries for '.' and '..'.
+    fn read_directory(&self, directory: &Record) -> Result<Vec<Record>> {
+        let mut data = vec![0; directory.size];
+        self.read(directory.extent, &mut data)?;
+
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            let length = data[offset] as usize;
+            if length == 0 {
+                // Records do not cross sector boundaries, so the rest of the sector is padded with zeros
+                offset = (offset / SECTOR_SIZE + 1) * SECTOR_SIZE;
+                continue;
+            }
+            if offset + length > data.len() {
+                break;
+            }
+            if length < 34 {
+                // Too short for the fixed part of a record (corrupted image)
+                offset += length;
+                continue;
+            }
+
+            let raw = &data[offset..offset + length];
+            let special = raw[32] == 1 && (raw[33] == 0 || raw[33] == 1);
+            if !special {
+                if let Some(mut record) = Record::parse(raw, directory.extent + offset, self.susp_skip.is_some()) {
+                    if self.susp_skip.is_some() {
+                        if let Some(name) = self.rock_ridge_name(raw)? {
+                            record.name = name;
+                        }
+                    }
+
+                    records.push(record);
+                }
+            }
+
+            offset += length;
+        }
+
+        return Ok(records);
+    }
+
+    /// Search the system use area of a record for Rock Ridge 'NM' entries (following 'CE' continuation areas).
+    fn rock_ridge_name(&self, raw: &[u8]) -> Result<Option<String>> {
+        let name_length = raw[32] as usize;
+        let start = 33 + name_length + (1 - name_length % 2) + self.susp_skip.unwrap_or(0);
+        if start >= raw.len() {
+            return Ok(None);
+        }
+
+        let mut area = Vec::from(&raw[start..]);
+        let mut name: Option<Vec<u8>> = None;
+        let mut continuations = 0;
+
+        loop {
+            let mut continuation = None;
+            let mut offset = 0;
+            while offset + 4 <= area.len() {
+                let length = area[offset + 2] as usize;
+                if length < 4 || offset + length > area.len() {
+                    break;
+                }
+
+                let entry = &area[offset..offset + length];
+                match &entry[0..2] {
+                    b"NM" if length >= 5 => {
+                        if entry[4] & 0x06 == 0 { // Entries flagged as '.' or '..' are ignored
+                            name.get_or_insert_with(Vec::new).extend_from_slice(&entry[5..]);
+                        }
+                    }
+                    b"CE" if length >= 28 => {
+                        // Continuation areas do not cross a block boundary, which also limits the allocation for corrupted images
+                        let (block_offset, area_length) = (read_u32(entry, 12) as usize, read_u32(entry, 20) as usize);
+                        if block_offset + area_length <= SECTOR_SIZE {
+                            continuation = Some((read_u32(entry, 4) as usize * SECTOR_SIZE + block_offset, area_length));
+                        }
+                    }
+                    b"ST" => break,
+                    _ => {}
+                }
+
+                offset += length;
+            }
+
+            // Continue with the continuation area (limited, so that a corrupted image can not cause an endless loop)
+            match continuation {
+                Some((position, length)) if continuations < 8 => {
+                    area = vec![0; length];
+                    self.read(position, &mut area)?;
+                    continuations += 1;
+                }
+                _ => break
+            }
+        }
+
+        return Ok(name.map(|name| String::from_utf8_lossy(&name).to_string()));
+    }
+}
+
+impl Record {
+    fn parse(raw: &[u8], position: usize, rock_ridge: bool) -> Option<Self> {
+        if raw.len() < 34 {
+            return None;
+        }
+
+        let name_length = raw[32] as usize;
+        if raw.len() < 33 + name_length {
+            return None;
+        }
+
+        let directory = raw[25] & DIRECTORY_FLAG != 0;
+        let mut name = String::from_utf8_lossy(&raw[33..33 + name_length]).to_string();
+        if !directory {
+            // Strip the version number (e.g. 'FILE.TXT;1') and the trailing dot of names without extension
+            if let Some(index) = name.find(';') {
+                name.truncate(index);
+            }
+            if name.ends_with('.') {
+                name.pop();
+            }
+        }
+
+        // Plain ISO9660 names are upper case, which is not what users expect (Rock Ridge names are kept as they are)
+        if !rock_ridge {
+            name = name.to_lowercase();
+        }
+
+        return Some(Self { name, extent: read_u32(raw, 2) as usize * SECTOR_SIZE, size: read_u32(raw, 10) as usize, directory, position });
+    }
+
+    fn typ(&self) -> FileType {
+        return if self.directory { FileType::Directory } else { FileType::Regular };
+    }
+}
+
+impl Inode for IsoInode {
+    fn metadata(&self) -> Metadata {
+        return Metadata { inode: self.record.position as u64, typ: self.record.typ(), size: self.record.size, created_ms: 0, modified_ms: 0 };
+    }
+
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        if self.record.directory {
+            return Err(Errno::IsADirectory);
+        }
+        if offset >= self.record.size {
+            return Ok(0);
+        }
+
+        let count = buffer.len().min(self.record.size - offset);
+        self.volume.read(self.record.extent + offset, &mut buffer[..count])?;
+
+        return Ok(count);
+    }
+
+    fn write_at(&self, _offset: usize, _buffer: &[u8]) -> Result<usize> {
+        return Err(Errno::ReadOnly);
+    }
+
+    fn truncate(&self, _size: usize) -> Result<()> {
+        return Err(Errno::ReadOnly);
+    }
+
+    fn sync(&self) -> Result<()> {
+        return block_cache().flush_device(&self.volume.queue);
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        if !self.record.directory {
+            return Err(Errno::NotADirectory);
+        }
+
+        return match self.volume.read_directory(&self.record)?.into_iter().find(|record| record.name == name) {
+            Some(record) => Ok(Arc::new(IsoInode { volume: Arc::clone(&self.volume), record })),
+            None => Err(Errno::NotFound)
+        };
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        if !self.record.directory {
+            return Err(Errno::NotADirectory);
+        }
+
+        return Ok(self.volume.read_directory(&self.record)?.into_iter()
+            .map(|record| DirEntry { inode: record.position as u64, typ: record.typ(), name: record.name })
+            .collect());
+    }
+
+    fn create(&self, _name: &str, _typ: FileType) -> Result<Arc<dyn Inode>> {
+        return Err(Errno::ReadOnly);
+    }
+
+    fn unlink(&self, _name: &str) -> Result<()> {
+        return Err(Errno::ReadOnly);
+    }
+}
+
+/// Numbers are stored in both byte orders, so the little endian part is used.
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    return u16::from_le_bytes([data[offset], data[offset + 1]]);
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    return u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]);
+}
@@ -0,0 +1,60 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use syscall::error::Errno;
+use crate::fs::{Inode, Result};
+use crate::process::wait_queue::WaitQueue;
+use crate::sync::Mutex;
+
+/// Advisory whole-file locks (like `flock()`). Locks are held by open files (see `vfs::OpenFile`),
+/// so they are shared by all descriptors referring to the same open file and released, once it is closed.
+static LOCKS: Mutex<Vec<FileLock>> = Mutex::new(Vec::new());
+
+/// Notified, whenever a lock has been released.
+static RELEASED: WaitQueue = WaitQueue::new();
+
+struct FileLock {
+    inode: Arc<dyn Inode>,
+    /// Address of the open file, holding the lock.
+    owner: usize,
+    exclusive: bool,
+}
+
+/// Acquire a lock on `inode` for `owner`, waiting for conflicting locks to be released (unless `blocking` is false).
+/// A lock already held by `owner` is converted, which is not atomic (like `flock()`),
+/// so that two owners upgrading their shared locks at the same time do not deadlock.
+pub fn lock(inode: &Arc<dyn Inode>, owner: usize, exclusive: bool, blocking: bool) -> Result<()> {
+    let mut locks = LOCKS.lock();
+    if remove(&mut locks, inode, owner) {
+        RELEASED.notify_all();
+    }
+
+    while locks.iter().any(|lock| Arc::ptr_eq(&lock.inode, inode) && (exclusive || lock.exclusive)) {
+        if !blocking {
+            return Err(Errno::WouldBlock);
+        }
+
+        RELEASED.wait(locks);
+        locks = LOCKS.lock();
+    }
+
+    locks.push(FileLock { inode: Arc::clone(inode), owner, exclusive });
+    return Ok(());
+}
+
+/// Release the lock on `inode` held by `owner` (does nothing, if there is no such lock).
+pub fn unlock(inode: &Arc<dyn Inode>, owner: usize) {
+    let mut locks = LOCKS.lock();
+    if remove(&mut locks, inode, owner) {
+        RELEASED.notify_all();
+    }
+}
+
+fn remove(locks: &mut Vec<FileLock>, inode: &Arc<dyn Inode>, owner: usize) -> bool {
+    return match locks.iter().position(|lock| lock.owner == owner && Arc::ptr_eq(&lock.inode, inode)) {
+        Some(index) => {
+            locks.swap_remove(index);
+            true
+        }
+        None => false
+    };
+}
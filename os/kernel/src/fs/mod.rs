@@ -0,0 +1,248 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use syscall::error::Errno;
+use syscall::file::{DirectoryEntry, FileStatus, FileType, LockOperation, OpenFlags, PollEvents};
+use crate::block;
+use crate::fs::devfs::Devfs;
+use crate::fs::iso9660::Iso9660;
+use crate::fs::poll::Pollable;
+use crate::fs::procfs::Procfs;
+use crate::fs::tmpfs::Tmpfs;
+use crate::net::unix::Endpoint;
+use crate::process::wait_queue::Waiter;
+use x86_64::structures::paging::frame::PhysFrameRange;
+use x86_64::structures::paging::PageTableFlags;
+
+pub mod dentry;
+pub mod devfs;
+pub mod eventfd;
+pub mod initramfs;
+pub mod iso9660;
+pub mod lock;
+pub mod mqueue;
+pub mod pipe;
+pub mod poll;
+pub mod procfs;
+pub mod timerfd;
+pub mod tmpfs;
+pub mod vfs;
+pub mod watch;
+
+pub type Result<T> = core::result::Result<T, Errno>;
+
+/// Maximum length of a single path component.
+pub const MAX_NAME_LENGTH: usize = 255;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Metadata {
+    /// Number of the inode, unique inside its filesystem.
+    pub inode: u64,
+    pub typ: FileType,
+    pub size: usize,
+    /// Timestamps in milliseconds since boot.
+    pub created_ms: usize,
+    pub modified_ms: usize,
+}
+
+/// Metadata is passed to user space as `FileStatus` (see `sys_stat()`).
+impl From<Metadata> for FileStatus {
+    fn from(metadata: Metadata) -> Self {
+        Self { inode: metadata.inode, typ: metadata.typ, size: metadata.size, created_ms: metadata.created_ms, modified_ms: metadata.modified_ms }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    pub name: String,
+    pub inode: u64,
+    pub typ: FileType,
+}
+
+impl From<DirEntry> for DirectoryEntry {
+    fn from(entry: DirEntry) -> Self {
+        Self::new(entry.inode, entry.typ, &entry.name)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum SeekFrom {
+    Start(usize),
+    Current(isize),
+    End(isize),
+}
+
+/// Create a filesystem of type `typ` (as returned by `FileSystem::name()`), e.g. to be mounted by a user program.
+/// `source` is the block device (e.g. 'ata0' or '/dev/ata0') for filesystems stored on a disk and ignored otherwise.
+pub fn new_filesystem(typ: &str, source: &str) -> Result<Arc<dyn FileSystem>> {
+    return match typ {
+        "iso9660" => {
+            let device = block::device(source.strip_prefix("/dev/").unwrap_or(source)).ok_or(Errno::NotFound)?;
+            Ok(Arc::new(Iso9660::new(device)?))
+        }
+        "tmpfs" => Ok(Arc::new(Tmpfs::new(tmpfs::DEFAULT_SIZE))),
+        "devfs" => Ok(Arc::new(Devfs)),
+        "procfs" => Ok(Arc::new(Procfs)),
+        _ => Err(Errno::NoDevice)
+    };
+}
+
+/// A filesystem, that can be mounted into the VFS (see `vfs::mount()`).
+pub trait FileSystem: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn root(&self) -> Arc<dyn Inode>;
+
+    /// Write all modified data (e.g. dirty blocks in the block cache) back to the underlying device.
+    /// Called before the filesystem is unmounted. Filesystems on a block device must override this
+    /// (see `BlockCache::flush_device()`), while filesystems in memory have nothing to write back.
+    fn sync(&self) -> Result<()> {
+        return Ok(());
+    }
+}
+
+/// A file, directory or other object inside a filesystem.
+/// Operations, which are not supported by an inode, return an error by default.
+/// Filesystems may downcast other inodes (via `Any`) to check, if they belong to the same filesystem (e.g. for `rename()`).
+pub trait Inode: Any + Send + Sync {
+    fn metadata(&self) -> Metadata;
+
+    fn read_at(&self, _offset: usize, _buffer: &mut [u8]) -> Result<usize> {
+        return Err(Errno::NotSupported);
+    }
+
+    fn write_at(&self, _offset: usize, _buffer: &[u8]) -> Result<usize> {
+        return Err(Errno::NotSupported);
+    }
+
+    fn truncate(&self, _size: usize) -> Result<()> {
+        return Err(Errno::NotSupported);
+    }
+
+    /// The pipe, which is shared by all openers of a FIFO (only for `FileType::Fifo`).
+    fn pipe(&self) -> Result<Arc<pipe::Pipe>> {
+        return Err(Errno::NotSupported);
+    }
+
+    /// The endpoint, via which clients connect to the socket bound to this node (only for `FileType::Socket`).
+    fn socket(&self) -> Result<Arc<Endpoint>> {
+        return Err(Errno::NotSupported);
+    }
+
+    /// Find the child called `name` (only for directories).
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>> {
+        return Err(Errno::NotADirectory);
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        return Err(Errno::NotADirectory);
+    }
+
+    /// Create a new child called `name` (only for directories).
+    fn create(&self, _name: &str, _typ: FileType) -> Result<Arc<dyn Inode>> {
+        return Err(Errno::NotSupported);
+    }
+
+    /// Remove the child called `name` (only for directories).
+    fn unlink(&self, _name: &str) -> Result<()> {
+        return Err(Errno::NotSupported);
+    }
+
+    /// Move the child `old_name` to `new_parent` (a directory of the same filesystem) as `new_name`.
+    /// An existing entry called `new_name` is replaced.
+    fn rename(&self, _old_name: &str, _new_parent: &Arc<dyn Inode>, _new_name: &str) -> Result<()> {
+        return Err(Errno::NotSupported);
+    }
+
+    fn ioctl(&self, _request: usize, _arg: usize) -> Result<usize> {
+        return Err(Errno::NotATerminal);
+    }
+
+    /// Write modified data of this inode back to the underlying device (like `fsync()`).
+    /// Like `FileSystem::sync()`, this must be overridden by filesystems on a block device.
+    fn sync(&self) -> Result<()> {
+        return Ok(());
+    }
+
+    /// Readiness of an opened inode (see `Pollable::poll()`). Regular files never block, so they are always ready.
+    fn poll(&self, events: PollEvents, _waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        return events & (PollEvents::READABLE | PollEvents::WRITABLE);
+    }
+
+    /// Physical memory backing `length` bytes at `offset` and the caching flags for mapping it into user space (see `sys_map_file()`).
+    /// Only device memory (e.g. of a framebuffer) can be mapped, since file contents are not kept in page frames.
+    fn mmap(&self, _offset: usize, _length: usize) -> Result<(PhysFrameRange, PageTableFlags)> {
+        return Err(Errno::NoDevice);
+    }
+
+    /// Called by `vfs::open()`. Inodes, which need state for each opened file (e.g. input devices with a queue per reader),
+    /// return their own file. Otherwise, the inode is opened as `vfs::OpenFile`.
+    fn open(&self, _flags: OpenFlags) -> Result<Option<Arc<dyn File>>> {
+        return Ok(None);
+    }
+}
+
+/// An opened file. Regular files are opened as `vfs::OpenFile`, which keeps track of the file offset,
+/// but other kernel objects (e.g. pipes) can also be accessed via this interface, without being part of a filesystem.
+/// System calls for specific objects may downcast a file (via `Any`) to check its type (e.g. for watchers).
+pub trait File: Pollable + Any + Send + Sync {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize>;
+
+    fn write(&self, buffer: &[u8]) -> Result<usize>;
+
+    fn seek(&self, _position: SeekFrom) -> Result<usize> {
+        return Err(Errno::IllegalSeek);
+    }
+
+    /// Read at `offset` without using or changing the file offset (like `pread()`).
+    fn read_at(&self, _offset: usize, _buffer: &mut [u8]) -> Result<usize> {
+        return Err(Errno::IllegalSeek);
+    }
+
+    /// Write at `offset` without using or changing the file offset (like `pwrite()`).
+    fn write_at(&self, _offset: usize, _buffer: &[u8]) -> Result<usize> {
+        return Err(Errno::IllegalSeek);
+    }
+
+    fn truncate(&self, _size: usize) -> Result<()> {
+        return Err(Errno::InvalidArgument);
+    }
+
+    fn stat(&self) -> Result<Metadata>;
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        return Err(Errno::NotADirectory);
+    }
+
+    /// Read up to `count` entries of the directory, starting with the entry at `index` (see `ReadDirectory` system call).
+    fn readdir_from(&self, index: usize, count: usize) -> Result<Vec<DirEntry>> {
+        return Ok(self.readdir()?.into_iter().skip(index).take(count).collect());
+    }
+
+    fn ioctl(&self, _request: usize, _arg: usize) -> Result<usize> {
+        return Err(Errno::NotATerminal);
+    }
+
+    /// Acquire or release an advisory lock on the whole file (see `lock::lock()`).
+    fn lock(&self, _operation: LockOperation) -> Result<()> {
+        return Err(Errno::InvalidArgument);
+    }
+
+    /// Write modified data of the file back to the underlying device, before returning (like `fsync()`).
+    fn sync(&self) -> Result<()> {
+        return Err(Errno::InvalidArgument);
+    }
+
+    /// See `Inode::mmap()`.
+    fn mmap(&self, _offset: usize, _length: usize) -> Result<(PhysFrameRange, PageTableFlags)> {
+        return Err(Errno::NoDevice);
+    }
+
+    /// Object, which keeps the memory returned by `mmap()` valid, while it is mapped (see `sys_map_file()`).
+    /// By default, the file itself is kept open instead. Files, which must be released, once their last descriptor is closed
+    /// (e.g. windows), return an object, that only owns the mapped memory.
+    fn mapped_memory(&self) -> Option<Arc<dyn Send + Sync>> {
+        return None;
+    }
+}
@@ -0,0 +1,198 @@
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ptr;
+use syscall::error::Errno;
+use syscall::file::{FileType, OpenFlags, PollEvents};
+use syscall::mqueue::{QueueAttributes, MAX_CAPACITY, MAX_MESSAGE_SIZE, MAX_PRIORITY};
+use crate::fs::{File, Metadata, Result};
+use crate::fs::poll;
+use crate::fs::poll::Pollable;
+use crate::process::wait_queue::{WaitQueue, Waiter};
+use crate::sync::Mutex;
+use crate::timer;
+
+/// Longest name of a message queue (without the leading '/').
+const MAX_NAME_LENGTH: usize = 255;
+
+/// All message queues, which have not been unlinked yet.
+static QUEUES: Mutex<BTreeMap<String, Arc<MessageQueue>>> = Mutex::new(BTreeMap::new());
+
+/// Named queue of messages, which are received by priority (and in the order they have been sent within the same priority).
+/// Unlike a pipe, messages keep their boundaries and the queue persists without any open descriptors, until it is unlinked.
+pub struct MessageQueue {
+    attributes: QueueAttributes,
+    state: Mutex<QueueState>,
+    readable: WaitQueue,
+    writable: WaitQueue,
+    created_ms: usize,
+}
+
+struct QueueState {
+    /// One queue of messages per priority.
+    messages: [VecDeque<Vec<u8>>; MAX_PRIORITY + 1],
+    count: usize,
+    modified_ms: usize,
+}
+
+/// Descriptor of a message queue. Reading and writing receive and send messages with priority 0.
+pub struct MessageQueueFile {
+    queue: Arc<MessageQueue>,
+    flags: OpenFlags,
+}
+
+/// Open the message queue called `name` (e.g. '/jobs'). With `OpenFlags::CREATE`, it is created with `attributes`
+/// (or default attributes, if `None`), unless it already exists. `OpenFlags::READ` and `OpenFlags::WRITE` allow receiving and sending.
+pub fn open(name: &str, flags: OpenFlags, attributes: Option<QueueAttributes>) -> Result<Arc<MessageQueueFile>> {
+    if !flags.intersects(OpenFlags::READ_WRITE) || flags.intersects(OpenFlags::TRUNCATE | OpenFlags::APPEND | OpenFlags::DIRECTORY) {
+        return Err(Errno::InvalidArgument);
+    }
+
+    let name = validate_name(name)?;
+    let mut queues = QUEUES.lock();
+    let queue = match queues.get(name) {
+        Some(_) if flags.contains(OpenFlags::CREATE | OpenFlags::EXCLUSIVE) => return Err(Errno::AlreadyExists),
+        Some(queue) => Arc::clone(queue),
+        None if flags.contains(OpenFlags::CREATE) => {
+            let queue = Arc::new(MessageQueue::new(attributes.unwrap_or_default())?);
+            queues.insert(String::from(name), Arc::clone(&queue));
+            queue
+        }
+        None => return Err(Errno::NotFound)
+    };
+
+    return Ok(Arc::new(MessageQueueFile { queue, flags }));
+}
+
+/// Remove the name of a message queue. Open descriptors can still be used, but opening it again creates a new queue.
+pub fn unlink(name: &str) -> Result<()> {
+    let name = validate_name(name)?;
+    return QUEUES.lock().remove(name).map(|_| ()).ok_or(Errno::NotFound);
+}
+
+/// Names consist of a slash followed by at least one character (like on POSIX systems). Returns the name without the slash.
+fn validate_name(name: &str) -> Result<&str> {
+    let name = name.strip_prefix('/').ok_or(Errno::InvalidArgument)?;
+    if name.is_empty() || name.contains('/') {
+        return Err(Errno::InvalidArgument);
+    }
+    if name.len() > MAX_NAME_LENGTH {
+        return Err(Errno::NameTooLong);
+    }
+
+    return Ok(name);
+}
+
+impl MessageQueue {
+    fn new(attributes: QueueAttributes) -> Result<Self> {
+        if !(1..=MAX_CAPACITY).contains(&attributes.capacity) || !(1..=MAX_MESSAGE_SIZE).contains(&attributes.message_size) {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let now = timer().read().systime_ms();
+        let state = QueueState { messages: core::array::from_fn(|_| VecDeque::new()), count: 0, modified_ms: now };
+
+        return Ok(Self { attributes, state: Mutex::new(state), readable: WaitQueue::new(), writable: WaitQueue::new(), created_ms: now });
+    }
+
+    /// Queue a copy of `message`, blocking as long as the queue is full.
+    fn send(&self, message: &[u8], priority: usize) -> Result<()> {
+        if priority > MAX_PRIORITY {
+            return Err(Errno::InvalidArgument);
+        }
+        if message.len() > self.attributes.message_size {
+            return Err(Errno::MessageTooLong);
+        }
+
+        let mut state = self.state.lock();
+        while state.count >= self.attributes.capacity {
+            self.writable.wait(state);
+            state = self.state.lock();
+        }
+
+        state.messages[priority].push_back(Vec::from(message));
+        state.count += 1;
+        state.modified_ms = timer().read().systime_ms();
+
+        drop(state);
+        self.readable.notify_all();
+
+        return Ok(());
+    }
+
+    /// Remove the oldest message with the highest priority and copy it into `buffer`, blocking as long as the queue is empty.
+    /// Returns the length and the priority of the message. The buffer must be able to hold messages of the maximum size.
+    fn receive(&self, buffer: &mut [u8]) -> Result<(usize, usize)> {
+        if buffer.len() < self.attributes.message_size {
+            return Err(Errno::MessageTooLong);
+        }
+
+        let mut state = self.state.lock();
+        while state.count == 0 {
+            self.readable.wait(state);
+            state = self.state.lock();
+        }
+
+        let priority = state.messages.iter().rposition(|messages| !messages.is_empty()).unwrap();
+        let message = state.messages[priority].pop_front().unwrap();
+        state.count -= 1;
+        drop(state);
+        self.writable.notify_all();
+
+        buffer[..message.len()].copy_from_slice(&message);
+        return Ok((message.len(), priority));
+    }
+}
+
+impl MessageQueueFile {
+    /// Send `message` (fails with `Errno::BadDescriptor`, if the queue has not been opened for writing).
+    pub fn send(&self, message: &[u8], priority: usize) -> Result<()> {
+        if !self.flags.contains(OpenFlags::WRITE) {
+            return Err(Errno::BadDescriptor);
+        }
+
+        return self.queue.send(message, priority);
+    }
+
+    /// Receive a message (fails with `Errno::BadDescriptor`, if the queue has not been opened for reading).
+    pub fn receive(&self, buffer: &mut [u8]) -> Result<(usize, usize)> {
+        if !self.flags.contains(OpenFlags::READ) {
+            return Err(Errno::BadDescriptor);
+        }
+
+        return self.queue.receive(buffer);
+    }
+}
+
+impl File for MessageQueueFile {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+        return self.receive(buffer).map(|(length, _)| length);
+    }
+
+    fn write(&self, buffer: &[u8]) -> Result<usize> {
+        return self.send(buffer, 0).map(|_| buffer.len());
+    }
+
+    /// The size is the number of queued messages.
+    fn stat(&self) -> Result<Metadata> {
+        let state = self.queue.state.lock();
+        return Ok(Metadata { inode: ptr::from_ref(self.queue.as_ref()) as u64, typ: FileType::CharDevice, size: state.count, created_ms: self.queue.created_ms, modified_ms: state.modified_ms });
+    }
+}
+
+impl Pollable for MessageQueueFile {
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        let state = self.queue.state.lock();
+        let mut ready = PollEvents::empty();
+        if state.count > 0 {
+            ready |= PollEvents::READABLE;
+        }
+        if state.count < self.queue.attributes.capacity {
+            ready |= PollEvents::WRITABLE;
+        }
+
+        // Checked while holding the lock, so that no message sent or received between checking and registering is missed
+        return poll::ready_or_register(ready, events, waiter, &[(PollEvents::READABLE, &self.queue.readable), (PollEvents::WRITABLE, &self.queue.writable)]);
+    }
+}
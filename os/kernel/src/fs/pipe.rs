@@ -0,0 +1,250 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::ptr;
+use syscall::error::Errno;
+use syscall::file::{FileType, OpenFlags, PollEvents};
+use crate::fs::{File, Metadata, Result};
+use crate::fs::poll;
+use crate::fs::poll::Pollable;
+use crate::process::wait_queue::{WaitQueue, Waiter};
+use crate::sync::Mutex;
+use crate::timer;
+
+/// Number of bytes, that can be written into a pipe without being read.
+pub const PIPE_CAPACITY: usize = 4096;
+
+/// Unidirectional channel between a reading and a writing end (see `pipe()`).
+/// Reading from an empty pipe blocks until data is written, or returns 0 (EOF) once all writing ends are closed.
+/// Writing into a full pipe blocks until data is read, or fails with `Errno::BrokenPipe` once all reading ends are closed.
+pub struct Pipe {
+    state: Mutex<PipeState>,
+    readable: WaitQueue,
+    writable: WaitQueue,
+    created_ms: usize,
+}
+
+struct PipeState {
+    buffer: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+    /// Number of times each end has been opened, so that opening a FIFO can detect a peer, which is already gone again.
+    reader_opens: usize,
+    writer_opens: usize,
+    modified_ms: usize,
+}
+
+pub struct PipeReader {
+    pipe: Arc<Pipe>,
+}
+
+pub struct PipeWriter {
+    pipe: Arc<Pipe>,
+}
+
+/// Create a new pipe and return its reading and writing end.
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let pipe = Arc::new(Pipe::new());
+    return (PipeReader::new(Arc::clone(&pipe)), PipeWriter::new(pipe));
+}
+
+/// Open the pipe of a FIFO either for reading or for writing (opening it for both is not supported).
+/// Like on other systems, this blocks until the FIFO has been opened for the other direction as well.
+pub fn open_fifo(pipe: Arc<Pipe>, flags: OpenFlags) -> Result<Arc<dyn File>> {
+    let (reader_opens, writer_opens) = {
+        let state = pipe.state.lock();
+        (state.reader_opens, state.writer_opens)
+    };
+
+    return if flags.contains(OpenFlags::READ_WRITE) {
+        Err(Errno::InvalidArgument)
+    } else if flags.contains(OpenFlags::READ) {
+        let reader = PipeReader::new(Arc::clone(&pipe));
+        pipe.wait_until(&pipe.readable, |state| state.writers > 0 || state.writer_opens != writer_opens);
+        Ok(Arc::new(reader))
+    } else if flags.contains(OpenFlags::WRITE) {
+        let writer = PipeWriter::new(Arc::clone(&pipe));
+        pipe.wait_until(&pipe.writable, |state| state.readers > 0 || state.reader_opens != reader_opens);
+        Ok(Arc::new(writer))
+    } else {
+        Err(Errno::InvalidArgument)
+    };
+}
+
+impl Pipe {
+    pub fn new() -> Self {
+        let now = timer().read().systime_ms();
+        let state = PipeState { buffer: VecDeque::with_capacity(PIPE_CAPACITY), readers: 0, writers: 0, reader_opens: 0, writer_opens: 0, modified_ms: now };
+
+        return Self { state: Mutex::new(state), readable: WaitQueue::new(), writable: WaitQueue::new(), created_ms: now };
+    }
+
+    fn wait_until(&self, queue: &WaitQueue, condition: impl Fn(&PipeState) -> bool) {
+        let mut state = self.state.lock();
+        while !condition(&state) {
+            queue.wait(state);
+            state = self.state.lock();
+        }
+    }
+
+    fn metadata(&self) -> Metadata {
+        // Pipes are not part of a filesystem, so their address is used as inode number
+        let state = self.state.lock();
+        return Metadata { inode: ptr::from_ref(self) as u64, typ: FileType::Fifo, size: state.buffer.len(), created_ms: self.created_ms, modified_ms: state.modified_ms };
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+
+        let mut state = self.state.lock();
+        while state.buffer.is_empty() {
+            if state.writers == 0 {
+                return Ok(0);
+            }
+
+            self.readable.wait(state);
+            state = self.state.lock();
+        }
+
+        let count = buffer.len().min(state.buffer.len());
+        for (target, byte) in buffer.iter_mut().zip(state.buffer.drain(..count)) {
+            *target = byte;
+        }
+
+        drop(state);
+        self.writable.notify_all();
+
+        return Ok(count);
+    }
+
+    /// Write the whole buffer, blocking as long as the pipe is full.
+    /// If all readers are gone in the meantime, the number of bytes written so far is returned.
+    fn write(&self, buffer: &[u8]) -> Result<usize> {
+        let mut written = 0;
+
+        while written < buffer.len() {
+            let mut state = self.state.lock();
+            if state.readers == 0 {
+                return if written == 0 { Err(Errno::BrokenPipe) } else { Ok(written) };
+            }
+
+            let free = PIPE_CAPACITY - state.buffer.len();
+            if free == 0 {
+                self.writable.wait(state);
+                continue;
+            }
+
+            let count = free.min(buffer.len() - written);
+            state.buffer.extend(&buffer[written..written + count]);
+            state.modified_ms = timer().read().systime_ms();
+            written += count;
+
+            drop(state);
+            self.readable.notify_all();
+        }
+
+        return Ok(written);
+    }
+}
+
+impl PipeReader {
+    /// Open a new reading end of `pipe`.
+    pub fn new(pipe: Arc<Pipe>) -> Self {
+        {
+            let mut state = pipe.state.lock();
+            state.readers += 1;
+            state.reader_opens += 1;
+        }
+
+        pipe.writable.notify_all(); // Writers may be waiting for a FIFO to be opened
+        return Self { pipe };
+    }
+}
+
+impl PipeWriter {
+    /// Open a new writing end of `pipe`.
+    pub fn new(pipe: Arc<Pipe>) -> Self {
+        {
+            let mut state = pipe.state.lock();
+            state.writers += 1;
+            state.writer_opens += 1;
+        }
+
+        pipe.readable.notify_all(); // Readers may be waiting for a FIFO to be opened
+        return Self { pipe };
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.pipe.state.lock().readers -= 1;
+        self.pipe.writable.notify_all(); // Blocked writers need to fail with `BrokenPipe`
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.pipe.state.lock().writers -= 1;
+        self.pipe.readable.notify_all(); // Blocked readers need to see EOF
+    }
+}
+
+impl File for PipeReader {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+        return self.pipe.read(buffer);
+    }
+
+    fn write(&self, _buffer: &[u8]) -> Result<usize> {
+        return Err(Errno::BadDescriptor);
+    }
+
+    fn stat(&self) -> Result<Metadata> {
+        return Ok(self.pipe.metadata());
+    }
+}
+
+impl File for PipeWriter {
+    fn read(&self, _buffer: &mut [u8]) -> Result<usize> {
+        return Err(Errno::BadDescriptor);
+    }
+
+    fn write(&self, buffer: &[u8]) -> Result<usize> {
+        return self.pipe.write(buffer);
+    }
+
+    fn stat(&self) -> Result<Metadata> {
+        return Ok(self.pipe.metadata());
+    }
+}
+
+impl Pollable for PipeReader {
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        let state = self.pipe.state.lock();
+        let mut ready = PollEvents::empty();
+        if !state.buffer.is_empty() {
+            ready |= PollEvents::READABLE;
+        }
+        if state.writers == 0 {
+            ready |= PollEvents::HANG_UP;
+        }
+
+        // Checked while holding the lock, so that no write between checking and registering is missed
+        return poll::ready_or_register(ready, events, waiter, &[(PollEvents::READABLE | PollEvents::HANG_UP, &self.pipe.readable)]);
+    }
+}
+
+impl Pollable for PipeWriter {
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        let state = self.pipe.state.lock();
+        let mut ready = PollEvents::empty();
+        if state.buffer.len() < PIPE_CAPACITY {
+            ready |= PollEvents::WRITABLE;
+        }
+        if state.readers == 0 {
+            ready |= PollEvents::ERROR;
+        }
+
+        return poll::ready_or_register(ready, events, waiter, &[(PollEvents::WRITABLE | PollEvents::ERROR, &self.pipe.writable)]);
+    }
+}
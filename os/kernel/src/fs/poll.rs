@@ -0,0 +1,64 @@
+use alloc::sync::Arc;
+use syscall::file::{PollDescriptor, PollEvents};
+use crate::fs::File;
+use crate::process::wait_queue::{WaitQueue, Waiter};
+use crate::{scheduler, timer};
+
+/// Files, whose readiness for reading and writing can be waited for with `poll()`.
+pub trait Pollable {
+    /// Return the events, which are currently ready (`ERROR`, `HANG_UP` and `INVALID` may always be returned).
+    /// If none of `events` is ready and a `waiter` is given, it must be registered in a wait queue,
+    /// which is notified as soon as the readiness may have changed.
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<Waiter>>) -> PollEvents;
+}
+
+/// Events, that are reported even if they have not been requested.
+const ALWAYS_REPORTED: PollEvents = PollEvents::ERROR.union(PollEvents::HANG_UP).union(PollEvents::INVALID);
+
+/// Common last step of `Pollable::poll()` for objects, which notify a wait queue per event, when the event may have become ready
+/// (e.g. `&[(PollEvents::READABLE, &self.readable)]`). Unless one of `events` (or an event, that is always reported) is `ready`,
+/// `waiter` is registered in the queues of the requested (and the always reported) events. Returns `ready`.
+/// Must be called while holding the lock, that protects the readiness, so that no change between checking and registering is missed.
+pub fn ready_or_register(ready: PollEvents, events: PollEvents, waiter: Option<&Arc<Waiter>>, queues: &[(PollEvents, &WaitQueue)]) -> PollEvents {
+    if let Some(waiter) = waiter.filter(|_| !ready.intersects(events | ALWAYS_REPORTED)) {
+        for (_, queue) in queues.iter().filter(|(event, _)| (events | ALWAYS_REPORTED).intersects(*event)) {
+            queue.register(waiter);
+        }
+    }
+
+    return ready;
+}
+
+/// Wait until at least one of the given files is ready or `timeout_ms` milliseconds have passed (`None` waits forever).
+/// `files` contains the file for each descriptor (`None` for descriptors, that are not open).
+/// The ready events are stored in the descriptors and the number of ready descriptors is returned.
+pub fn poll(descriptors: &mut [PollDescriptor], files: &[Option<Arc<dyn File>>], timeout_ms: Option<usize>) -> usize {
+    let deadline = timeout_ms.map(|timeout| timer().read().systime_ns().saturating_add(timeout.saturating_mul(1000000)));
+
+    loop {
+        let waiter = Waiter::new();
+        let mut ready_count = 0;
+
+        for (descriptor, file) in descriptors.iter_mut().zip(files) {
+            // Once a file is ready, the call does not block, so that registering further waiters is unnecessary
+            let waiter = if ready_count == 0 { Some(&waiter) } else { None };
+            descriptor.ready = match file {
+                Some(file) => file.poll(descriptor.events, waiter) & (descriptor.events | ALWAYS_REPORTED),
+                None => PollEvents::INVALID
+            };
+
+            if !descriptor.ready.is_empty() {
+                ready_count += 1;
+            }
+        }
+
+        let remaining_ns = deadline.map(|deadline| deadline.saturating_sub(timer().read().systime_ns()));
+        if ready_count > 0 || remaining_ns == Some(0) {
+            waiter.wake(); // Woken waiters are removed from the wait queues, they have been registered in (see `WaitQueue::register()`)
+            return ready_count;
+        }
+
+        scheduler().block_on(&waiter, remaining_ns);
+        waiter.wake();
+    }
+}
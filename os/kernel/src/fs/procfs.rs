@@ -0,0 +1,273 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::Ordering::Relaxed;
+use syscall::error::Errno;
+use syscall::file::FileType;
+use crate::cpu::features;
+use crate::fs::{vfs, DirEntry, FileSystem, Inode, Metadata, Result};
+use crate::memory::{physical, PAGE_SIZE};
+use crate::net;
+use crate::net::inet::route;
+use crate::process::fd_table::MAX_OPEN_FILES;
+use crate::process::process::{find_process, processes, Process};
+use crate::{allocator, interrupt_dispatcher, smbios, timer};
+
+/// Synthetic filesystem, exposing information about processes and the kernel as text files (mounted at '/proc').
+/// File contents are generated on each read, so that user programs (e.g. 'ps' or 'free') always see the current state.
+pub struct Procfs;
+
+/// Inode numbers of per-process entries are derived from the process id, so that they stay stable between lookups.
+const PROCESS_INODE_SHIFT: u64 = 16;
+const FD_INODE_OFFSET: u64 = 0x100;
+
+#[derive(Copy, Clone)]
+enum Directory {
+    Root,
+    Process(usize),
+    FileDescriptors(usize),
+}
+
+struct ProcDirectory {
+    directory: Directory,
+}
+
+struct ProcFile {
+    number: u64,
+    generate: Box<dyn Fn() -> Result<String> + Send + Sync>,
+}
+
+/// Function generating the content of a file.
+type Generator = fn() -> Result<String>;
+
+/// Files in the root directory, that are not related to a process.
+const ROOT_FILES: [(&str, Generator); 8] = [
+    ("cpuinfo", cpuinfo),
+    ("hwinfo", hwinfo),
+    ("meminfo", meminfo),
+    ("interrupts", interrupts),
+    ("uptime", uptime),
+    ("mounts", mounts),
+    ("interfaces", interfaces),
+    ("routes", routes),
+];
+
+const PROCESS_FILES: [&str; 2] = ["status", "maps"];
+
+impl FileSystem for Procfs {
+    fn name(&self) -> &'static str {
+        "procfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        return Arc::new(ProcDirectory { directory: Directory::Root });
+    }
+}
+
+impl ProcDirectory {
+    fn number(&self) -> u64 {
+        return match self.directory {
+            Directory::Root => 1,
+            Directory::Process(pid) => process_inode(pid),
+            Directory::FileDescriptors(pid) => process_inode(pid) + 1 + PROCESS_FILES.len() as u64
+        };
+    }
+
+    fn entries(&self) -> Result<Vec<DirEntry>> {
+        let entries = match self.directory {
+            Directory::Root => ROOT_FILES.iter().enumerate()
+                .map(|(index, (name, _))| DirEntry { name: name.to_string(), inode: 2 + index as u64, typ: FileType::Regular })
+                .chain(processes().iter()
+                    .map(|process| DirEntry { name: process.id().to_string(), inode: process_inode(process.id()), typ: FileType::Directory }))
+                .collect(),
+            Directory::Process(pid) => {
+                process(pid)?;
+                PROCESS_FILES.iter().enumerate()
+                    .map(|(index, name)| DirEntry { name: name.to_string(), inode: process_inode(pid) + 1 + index as u64, typ: FileType::Regular })
+                    .chain([DirEntry { name: "fd".to_string(), inode: process_inode(pid) + 1 + PROCESS_FILES.len() as u64, typ: FileType::Directory }])
+                    .collect()
+            }
+            Directory::FileDescriptors(pid) => process(pid)?.files().lock().descriptors().iter()
+                .map(|(fd, _)| DirEntry { name: fd.to_string(), inode: process_inode(pid) + FD_INODE_OFFSET + *fd as u64, typ: FileType::Regular })
+                .collect()
+        };
+
+        return Ok(entries);
+    }
+}
+
+impl Inode for ProcDirectory {
+    fn metadata(&self) -> Metadata {
+        let size = self.entries().map_or(0, |entries| entries.len());
+        return Metadata { inode: self.number(), typ: FileType::Directory, size, created_ms: 0, modified_ms: 0 };
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let inode: Arc<dyn Inode> = match self.directory {
+            Directory::Root => {
+                if let Some(index) = ROOT_FILES.iter().position(|(file, _)| *file == name) {
+                    let generate = ROOT_FILES[index].1;
+                    Arc::new(ProcFile { number: 2 + index as u64, generate: Box::new(generate) })
+                } else {
+                    let pid = name.parse::<usize>().map_err(|_| Errno::NotFound)?;
+                    process(pid)?;
+                    Arc::new(ProcDirectory { directory: Directory::Process(pid) })
+                }
+            }
+            Directory::Process(pid) => match name {
+                "status" => Arc::new(ProcFile { number: process_inode(pid) + 1, generate: Box::new(move || status(pid)) }),
+                "maps" => Arc::new(ProcFile { number: process_inode(pid) + 2, generate: Box::new(move || maps(pid)) }),
+                "fd" => Arc::new(ProcDirectory { directory: Directory::FileDescriptors(pid) }),
+                _ => return Err(Errno::NotFound)
+            },
+            Directory::FileDescriptors(pid) => {
+                let fd = name.parse::<usize>().map_err(|_| Errno::NotFound)?;
+                if fd >= MAX_OPEN_FILES {
+                    return Err(Errno::NotFound);
+                }
+
+                process(pid)?.files().lock().get(fd).map_err(|_| Errno::NotFound)?;
+                Arc::new(ProcFile { number: process_inode(pid) + FD_INODE_OFFSET + fd as u64, generate: Box::new(move || file_descriptor(pid, fd)) })
+            }
+        };
+
+        return Ok(inode);
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        return self.entries();
+    }
+}
+
+impl Inode for ProcFile {
+    fn metadata(&self) -> Metadata {
+        let size = (self.generate)().map_or(0, |content| content.len());
+        return Metadata { inode: self.number, typ: FileType::Regular, size, created_ms: 0, modified_ms: 0 };
+    }
+
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        let content = (self.generate)()?;
+        let data = content.as_bytes();
+        if offset >= data.len() {
+            return Ok(0);
+        }
+
+        let count = buffer.len().min(data.len() - offset);
+        buffer[..count].copy_from_slice(&data[offset..offset + count]);
+
+        return Ok(count);
+    }
+
+    fn write_at(&self, _offset: usize, _buffer: &[u8]) -> Result<usize> {
+        return Err(Errno::AccessDenied);
+    }
+}
+
+fn process_inode(pid: usize) -> u64 {
+    return (pid as u64 + 1) << PROCESS_INODE_SHIFT;
+}
+
+fn process(pid: usize) -> Result<Arc<Process>> {
+    return find_process(pid).ok_or(Errno::NotFound);
+}
+
+fn cpuinfo() -> Result<String> {
+    return Ok(features::cpu_info().describe());
+}
+
+fn hwinfo() -> Result<String> {
+    return smbios::hardware_info().map(|info| info.describe()).ok_or(Errno::NotFound);
+}
+
+fn meminfo() -> Result<String> {
+    let total = physical::total_frame_count() * PAGE_SIZE / 1024;
+    let free = physical::free_frame_count() * PAGE_SIZE / 1024;
+    let heap_total = allocator().size() / 1024;
+    let heap_used = allocator().used() / 1024;
+
+    return Ok(format!("MemTotal:  {:>10} kB\nMemFree:   {:>10} kB\nMemUsed:   {:>10} kB\nHeapTotal: {:>10} kB\nHeapUsed:  {:>10} kB\nHeapFree:  {:>10} kB\n",
+        total, free, total.saturating_sub(free), heap_total, heap_used, heap_total.saturating_sub(heap_used)));
+}
+
+fn interrupts() -> Result<String> {
+    return Ok(interrupt_dispatcher().statistics());
+}
+
+fn uptime() -> Result<String> {
+    let ms = timer().read().systime_ms();
+    return Ok(format!("{}.{:02}\n", ms / 1000, (ms % 1000) / 10));
+}
+
+/// One line per mounted filesystem with source, mount point and filesystem type.
+fn mounts() -> Result<String> {
+    let mut mounts = String::new();
+    for (source, path, typ) in vfs::mounts() {
+        writeln!(mounts, "{} {} {}", source, path, typ).unwrap();
+    }
+
+    return Ok(mounts);
+}
+
+/// Address, MTU and statistics of each network interface.
+fn interfaces() -> Result<String> {
+    let mut text = String::new();
+    for interface in net::interfaces() {
+        let statistics = interface.statistics();
+        let address = interface.ipv4().map_or(String::from("unconfigured"), |cidr| cidr.to_string());
+
+        writeln!(text, "{}: inet {} ether {} mtu {}", interface.name(), address, interface.mac_address(), interface.mtu()).unwrap();
+        writeln!(text, "    RX packets {} bytes {}", statistics.received_frames.load(Relaxed), statistics.received_bytes.load(Relaxed)).unwrap();
+        writeln!(text, "    TX packets {} bytes {}", statistics.transmitted_frames.load(Relaxed), statistics.transmitted_bytes.load(Relaxed)).unwrap();
+        writeln!(text, "    dropped {}", statistics.dropped_frames.load(Relaxed)).unwrap();
+    }
+
+    return Ok(text);
+}
+
+/// Routing table with one route per line (most specific first) and the name servers.
+fn routes() -> Result<String> {
+    let mut text = format!("{:<18} {:<16} {}\n", "Destination", "Gateway", "Interface");
+    for route in route::routes() {
+        let destination = if route.destination.prefix_length() == 0 { String::from("default") } else { route.destination.to_string() };
+        let gateway = route.gateway.map_or(String::from("*"), |gateway| gateway.to_string());
+        writeln!(text, "{:<18} {:<16} {}", destination, gateway, route.interface.name()).unwrap();
+    }
+
+    for server in net::dns_servers() {
+        writeln!(text, "nameserver {}", server).unwrap();
+    }
+
+    return Ok(text);
+}
+
+fn status(pid: usize) -> Result<String> {
+    let process = process(pid)?;
+    let mut status = format!("Pid:   {}\n", pid);
+
+    for area in process.memory_areas() {
+        let size = (area.end() - area.start()) as usize / 1024;
+        writeln!(status, "Vm{:?}:{:>8} kB", area.typ(), size).unwrap();
+    }
+
+    writeln!(status, "Files: {}", process.files().lock().descriptors().len()).unwrap();
+    return Ok(status);
+}
+
+fn maps(pid: usize) -> Result<String> {
+    let mut maps = String::new();
+    for area in process(pid)?.memory_areas() {
+        writeln!(maps, "{:016x}-{:016x} {:?}", area.start().as_u64(), area.end().as_u64(), area.typ()).unwrap();
+    }
+
+    return Ok(maps);
+}
+
+fn file_descriptor(pid: usize, fd: usize) -> Result<String> {
+    let file = process(pid)?.files().lock().get(fd).map_err(|_| Errno::NotFound)?;
+    let metadata = file.stat()?;
+
+    return Ok(format!("Type:  {:?}\nInode: {}\nSize:  {}\n", metadata.typ, metadata.inode, metadata.size));
+}
@@ -0,0 +1,143 @@
+use alloc::boxed::Box;
+use alloc::sync::{Arc, Weak};
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
+use core::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, Release};
+use syscall::error::Errno;
+use syscall::file::{FileType, PollEvents};
+use crate::fs::{poll, File, Metadata, Result};
+use crate::fs::poll::Pollable;
+use crate::interrupt::deferred;
+use crate::process::wait_queue::{WaitQueue, Waiter};
+use crate::sync::Mutex;
+use crate::timer;
+use crate::timer::TimerHandle;
+
+/// Timer, whose expirations can be waited for with `poll()` (like Linux's timerfd). It is armed with `set()`.
+/// Reading blocks until the timer has expired at least once and returns the number of expirations since the last read as 8 bytes.
+pub struct TimerFile {
+    /// Referenced by the timer callbacks, which must not keep the file open.
+    this: Weak<TimerFile>,
+    expirations: AtomicU64,
+    /// Incremented each time the timer is set, so that callbacks of a previous setting, which are already running, are ignored.
+    generation: AtomicUsize,
+    armed: Mutex<Option<ArmedTimer>>,
+    /// Timer callbacks must not notify wait queues themselves, so readers are notified by the kernel worker thread.
+    notification_pending: AtomicBool,
+    readable: WaitQueue,
+}
+
+struct ArmedTimer {
+    handle: TimerHandle,
+    expires_ms: Arc<AtomicUsize>,
+    interval_ms: usize,
+}
+
+impl TimerFile {
+    pub fn new() -> Arc<Self> {
+        return Arc::new_cyclic(|this| Self { this: Weak::clone(this), expirations: AtomicU64::new(0), generation: AtomicUsize::new(0),
+            armed: Mutex::new(None), notification_pending: AtomicBool::new(false), readable: WaitQueue::new() });
+    }
+
+    /// Arm the timer to expire after `initial_ms` milliseconds and then every `interval_ms` milliseconds (if `interval_ms` is not 0).
+    /// An `initial_ms` value of 0 disarms the timer. Expirations of the previous setting, which have not been read yet, are discarded.
+    /// Returns the remaining time and interval of the previous setting.
+    pub fn set(&self, initial_ms: usize, interval_ms: usize) -> (usize, usize) {
+        let mut armed = self.armed.lock();
+        let now = timer().read().systime_ms();
+        let generation = self.generation.fetch_add(1, AcqRel) + 1;
+        self.expirations.store(0, Release);
+
+        let old = match armed.take() {
+            Some(previous) => {
+                previous.handle.cancel();
+                (previous.expires_ms.load(Relaxed).saturating_sub(now), previous.interval_ms)
+            }
+            None => (0, 0)
+        };
+
+        if initial_ms > 0 {
+            let file = Weak::clone(&self.this);
+            let expires_ms = Arc::new(AtomicUsize::new(now + initial_ms));
+            let next_expiry = Arc::clone(&expires_ms);
+
+            let callback = Box::new(move || {
+                if let Some(file) = file.upgrade() {
+                    if file.generation.load(Acquire) == generation {
+                        next_expiry.fetch_add(interval_ms, Relaxed);
+                        file.expire();
+                    }
+                }
+            });
+
+            let handle = if interval_ms > 0 {
+                timer::schedule_periodic_delayed(initial_ms, interval_ms, callback)
+            } else {
+                timer::schedule(initial_ms, callback)
+            };
+
+            armed.replace(ArmedTimer { handle, expires_ms, interval_ms });
+        }
+
+        return old;
+    }
+
+    fn expire(self: Arc<Self>) {
+        self.expirations.fetch_add(1, AcqRel);
+
+        if !self.notification_pending.swap(true, Acquire) {
+            deferred::schedule_work(Box::new(move || {
+                self.notification_pending.store(false, Release);
+                self.readable.notify_all();
+            }));
+        }
+    }
+}
+
+impl Drop for TimerFile {
+    fn drop(&mut self) {
+        if let Some(timer) = self.armed.lock().take() {
+            timer.handle.cancel();
+        }
+    }
+}
+
+impl File for TimerFile {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+        if buffer.len() < size_of::<u64>() {
+            return Err(Errno::InvalidArgument);
+        }
+
+        loop {
+            let count = self.expirations.swap(0, AcqRel);
+            if count > 0 {
+                buffer[..size_of::<u64>()].copy_from_slice(&count.to_ne_bytes());
+                return Ok(size_of::<u64>());
+            }
+
+            self.readable.wait_until(|| self.expirations.load(Acquire) > 0, None);
+        }
+    }
+
+    fn write(&self, _buffer: &[u8]) -> Result<usize> {
+        return Err(Errno::InvalidArgument);
+    }
+
+    fn stat(&self) -> Result<Metadata> {
+        return Ok(Metadata { inode: ptr::from_ref(self) as u64, typ: FileType::CharDevice, size: 0, created_ms: 0, modified_ms: 0 });
+    }
+}
+
+impl Pollable for TimerFile {
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        let ready = if self.expirations.load(Acquire) > 0 { PollEvents::READABLE } else { PollEvents::empty() };
+        let ready = poll::ready_or_register(ready, events, waiter, &[(PollEvents::READABLE, &self.readable)]);
+
+        // Expirations are counted without a lock, so an expiration between checking and registering is detected here
+        if ready.is_empty() && self.expirations.load(Acquire) > 0 {
+            return PollEvents::READABLE;
+        }
+
+        return ready;
+    }
+}
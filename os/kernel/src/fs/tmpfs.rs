@@ -0,0 +1,337 @@
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::sync::atomic::{AtomicU64, AtomicUsize};
+use core::sync::atomic::Ordering::Relaxed;
+use syscall::error::Errno;
+use syscall::file::FileType;
+use crate::fs::{DirEntry, FileSystem, Inode, Metadata, Result};
+use crate::fs::pipe::Pipe;
+use crate::net::unix::Endpoint;
+use crate::sync::{Mutex, RwLock};
+use crate::timer;
+
+/// Size limit of a tmpfs, if none is given (e.g. when mounted by a user program).
+pub const DEFAULT_SIZE: usize = 4 * 1024 * 1024;
+
+/// Writable filesystem, that keeps all files in memory (mounted at '/tmp').
+/// The size of all file contents together is limited, so that user programs can not use up the kernel heap.
+pub struct Tmpfs {
+    root: Arc<TmpfsInode>,
+}
+
+/// State shared by all inodes of a filesystem instance.
+struct TmpfsState {
+    size_limit: usize,
+    used: AtomicUsize,
+    next_inode: AtomicU64,
+}
+
+struct TmpfsInode {
+    number: u64,
+    state: Arc<TmpfsState>,
+    content: RwLock<Content>,
+    times: Mutex<Times>,
+}
+
+enum Content {
+    File(Vec<u8>),
+    Directory(BTreeMap<String, Arc<TmpfsInode>>),
+    Fifo(Arc<Pipe>),
+    Socket(Arc<Endpoint>),
+}
+
+#[derive(Copy, Clone)]
+struct Times {
+    created_ms: usize,
+    modified_ms: usize,
+}
+
+impl Tmpfs {
+    pub fn new(size_limit: usize) -> Self {
+        let state = Arc::new(TmpfsState { size_limit, used: AtomicUsize::new(0), next_inode: AtomicU64::new(1) });
+        return Self { root: TmpfsInode::new(&state, FileType::Directory) };
+    }
+
+    /// Number of bytes used by file contents.
+    pub fn used(&self) -> usize {
+        return self.root.state.used.load(Relaxed);
+    }
+
+    pub fn size_limit(&self) -> usize {
+        return self.root.state.size_limit;
+    }
+}
+
+impl FileSystem for Tmpfs {
+    fn name(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        return Arc::clone(&self.root) as Arc<dyn Inode>;
+    }
+}
+
+impl TmpfsState {
+    /// Account for `size` additional bytes. Fails, if the size limit would be exceeded.
+    fn reserve(&self, size: usize) -> Result<()> {
+        return self.used.fetch_update(Relaxed, Relaxed, |used| {
+            let new_used = used.checked_add(size)?;
+            if new_used <= self.size_limit { Some(new_used) } else { None }
+        }).map(|_| ()).map_err(|_| Errno::NoSpace);
+    }
+
+    fn release(&self, size: usize) {
+        self.used.fetch_sub(size, Relaxed);
+    }
+}
+
+impl TmpfsInode {
+    fn new(state: &Arc<TmpfsState>, typ: FileType) -> Arc<Self> {
+        let now = timer().read().systime_ms();
+        let content = match typ {
+            FileType::Directory => Content::Directory(BTreeMap::new()),
+            FileType::Fifo => Content::Fifo(Arc::new(Pipe::new())),
+            FileType::Socket => Content::Socket(Arc::new(Endpoint::new())),
+            _ => Content::File(Vec::new())
+        };
+
+        return Arc::new(Self { number: state.next_inode.fetch_add(1, Relaxed), state: Arc::clone(state), content: RwLock::new(content),
+            times: Mutex::new(Times { created_ms: now, modified_ms: now }) });
+    }
+
+    fn touch(&self) {
+        self.times.lock().modified_ms = timer().read().systime_ms();
+    }
+
+    fn typ(&self) -> FileType {
+        return match *self.content.read() {
+            Content::File(_) => FileType::Regular,
+            Content::Directory(_) => FileType::Directory,
+            Content::Fifo(_) => FileType::Fifo,
+            Content::Socket(_) => FileType::Socket
+        };
+    }
+
+    fn is_empty_directory(&self) -> bool {
+        return match &*self.content.read() {
+            Content::Directory(children) => children.is_empty(),
+            _ => false
+        };
+    }
+}
+
+impl Drop for TmpfsInode {
+    fn drop(&mut self) {
+        // The contents of a deleted file are released, once it is not opened anymore
+        if let Content::File(data) = &*self.content.read() {
+            self.state.release(data.len());
+        }
+    }
+}
+
+impl Inode for TmpfsInode {
+    fn metadata(&self) -> Metadata {
+        let times = *self.times.lock();
+        let (typ, size) = match &*self.content.read() {
+            Content::File(data) => (FileType::Regular, data.len()),
+            Content::Directory(children) => (FileType::Directory, children.len()),
+            Content::Fifo(_) => (FileType::Fifo, 0),
+            Content::Socket(_) => (FileType::Socket, 0)
+        };
+
+        return Metadata { inode: self.number, typ, size, created_ms: times.created_ms, modified_ms: times.modified_ms };
+    }
+
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        return match &*self.content.read() {
+            Content::File(data) => {
+                if offset >= data.len() {
+                    return Ok(0);
+                }
+
+                let count = buffer.len().min(data.len() - offset);
+                buffer[..count].copy_from_slice(&data[offset..offset + count]);
+                Ok(count)
+            }
+            Content::Directory(_) => Err(Errno::IsADirectory),
+            Content::Fifo(_) | Content::Socket(_) => Err(Errno::IllegalSeek)
+        };
+    }
+
+    fn write_at(&self, offset: usize, buffer: &[u8]) -> Result<usize> {
+        match &mut *self.content.write() {
+            Content::File(data) => {
+                let end = offset.checked_add(buffer.len()).ok_or(Errno::FileTooLarge)?;
+                if end > data.len() {
+                    self.state.reserve(end - data.len())?;
+                    data.resize(end, 0);
+                }
+
+                data[offset..end].copy_from_slice(buffer);
+            }
+            Content::Directory(_) => return Err(Errno::IsADirectory),
+            Content::Fifo(_) | Content::Socket(_) => return Err(Errno::IllegalSeek)
+        }
+
+        self.touch();
+        return Ok(buffer.len());
+    }
+
+    fn truncate(&self, size: usize) -> Result<()> {
+        match &mut *self.content.write() {
+            Content::File(data) => {
+                if size > data.len() {
+                    self.state.reserve(size - data.len())?;
+                } else {
+                    self.state.release(data.len() - size);
+                }
+
+                data.resize(size, 0);
+            }
+            Content::Directory(_) => return Err(Errno::IsADirectory),
+            Content::Fifo(_) | Content::Socket(_) => return Err(Errno::IllegalSeek)
+        }
+
+        self.touch();
+        return Ok(());
+    }
+
+    fn pipe(&self) -> Result<Arc<Pipe>> {
+        return match &*self.content.read() {
+            Content::Fifo(pipe) => Ok(Arc::clone(pipe)),
+            _ => Err(Errno::InvalidArgument)
+        };
+    }
+
+    fn socket(&self) -> Result<Arc<Endpoint>> {
+        return match &*self.content.read() {
+            Content::Socket(endpoint) => Ok(Arc::clone(endpoint)),
+            _ => Err(Errno::InvalidArgument)
+        };
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        return match &*self.content.read() {
+            Content::Directory(children) => match children.get(name) {
+                Some(child) => Ok(Arc::clone(child) as Arc<dyn Inode>),
+                None => Err(Errno::NotFound)
+            },
+            _ => Err(Errno::NotADirectory)
+        };
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        return match &*self.content.read() {
+            Content::Directory(children) => Ok(children.iter()
+                .map(|(name, child)| DirEntry { name: name.clone(), inode: child.number, typ: child.typ() })
+                .collect()),
+            _ => Err(Errno::NotADirectory)
+        };
+    }
+
+    fn create(&self, name: &str, typ: FileType) -> Result<Arc<dyn Inode>> {
+        if !matches!(typ, FileType::Regular | FileType::Directory | FileType::Fifo | FileType::Socket) {
+            return Err(Errno::NotSupported);
+        }
+
+        let child = match &mut *self.content.write() {
+            Content::Directory(children) => {
+                if children.contains_key(name) {
+                    return Err(Errno::AlreadyExists);
+                }
+
+                let child = TmpfsInode::new(&self.state, typ);
+                children.insert(name.to_string(), Arc::clone(&child));
+                child
+            }
+            _ => return Err(Errno::NotADirectory)
+        };
+
+        self.touch();
+        return Ok(child as Arc<dyn Inode>);
+    }
+
+    fn unlink(&self, name: &str) -> Result<()> {
+        match &mut *self.content.write() {
+            Content::Directory(children) => {
+                let child = children.get(name).ok_or(Errno::NotFound)?;
+                if child.typ() == FileType::Directory && !child.is_empty_directory() {
+                    return Err(Errno::NotEmpty);
+                }
+
+                children.remove(name);
+            }
+            _ => return Err(Errno::NotADirectory)
+        }
+
+        self.touch();
+        return Ok(());
+    }
+
+    fn rename(&self, old_name: &str, new_parent: &Arc<dyn Inode>, new_name: &str) -> Result<()> {
+        let new_parent = (new_parent.as_ref() as &dyn Any).downcast_ref::<TmpfsInode>().ok_or(Errno::CrossDevice)?;
+        if !Arc::ptr_eq(&self.state, &new_parent.state) {
+            return Err(Errno::CrossDevice);
+        }
+
+        if self.number == new_parent.number {
+            let mut content = self.content.write();
+            let children = match &mut *content {
+                Content::Directory(children) => children,
+                _ => return Err(Errno::NotADirectory)
+            };
+
+            let child = children.get(old_name).ok_or(Errno::NotFound)?;
+            if let Some(target) = children.get(new_name) {
+                check_replace(child, target)?;
+            }
+
+            let child = children.remove(old_name).unwrap();
+            children.insert(new_name.to_string(), child);
+        } else {
+            // Always lock the directory with the lower inode number first to avoid deadlocks
+            let (mut old_content, mut new_content) = if self.number < new_parent.number {
+                let old_content = self.content.write();
+                (old_content, new_parent.content.write())
+            } else {
+                let new_content = new_parent.content.write();
+                (self.content.write(), new_content)
+            };
+
+            let (old_children, new_children) = match (&mut *old_content, &mut *new_content) {
+                (Content::Directory(old_children), Content::Directory(new_children)) => (old_children, new_children),
+                _ => return Err(Errno::NotADirectory)
+            };
+
+            let child = old_children.get(old_name).ok_or(Errno::NotFound)?;
+            if let Some(target) = new_children.get(new_name) {
+                check_replace(child, target)?;
+            }
+
+            let child = old_children.remove(old_name).unwrap();
+            new_children.insert(new_name.to_string(), child);
+            new_parent.touch();
+        }
+
+        self.touch();
+        return Ok(());
+    }
+}
+
+/// Check, if `target` may be replaced by `source` during a rename.
+fn check_replace(source: &Arc<TmpfsInode>, target: &Arc<TmpfsInode>) -> Result<()> {
+    if Arc::ptr_eq(source, target) {
+        return Ok(());
+    }
+
+    return match (source.typ(), target.typ()) {
+        (FileType::Directory, FileType::Directory) => if target.is_empty_directory() { Ok(()) } else { Err(Errno::NotEmpty) },
+        (FileType::Directory, _) => Err(Errno::NotADirectory),
+        (_, FileType::Directory) => Err(Errno::IsADirectory),
+        _ => Ok(())
+    };
+}
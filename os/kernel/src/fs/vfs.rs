@@ -0,0 +1,526 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use log::{info, warn};
+use syscall::error::Errno;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering::Relaxed;
+use syscall::file::{FileType, LockOperation, OpenFlags, PollEvents, WatchEvents};
+use crate::fs::{DirEntry, File, FileSystem, Inode, Metadata, Result, SeekFrom};
+use crate::fs::dentry::Dentry;
+use crate::fs::{lock, pipe, watch};
+use crate::fs::poll::Pollable;
+use crate::net::unix::Endpoint;
+use crate::process::wait_queue::Waiter;
+use crate::sync::{Mutex, RwLock};
+use crate::block_cache;
+use x86_64::structures::paging::frame::PhysFrameRange;
+use x86_64::structures::paging::PageTableFlags;
+
+/// Root of the directory tree (None, until a filesystem has been mounted at '/').
+static ROOT: RwLock<Option<Arc<Dentry>>> = RwLock::new(None);
+/// All mounted filesystems in the order, they have been mounted in.
+/// Path resolution follows the mount points recorded in the directory entries (see `Dentry::follow_mounts()`).
+static MOUNTS: RwLock<Vec<Mount>> = RwLock::new(Vec::new());
+/// Source of `OpenFile::lock_owner`.
+static NEXT_LOCK_OWNER: AtomicUsize = AtomicUsize::new(1);
+
+struct Mount {
+    /// Device or name, the filesystem has been created from (e.g. 'ata0' or 'tmpfs').
+    source: String,
+    /// Normalized path of the mount point.
+    path: String,
+    fs: Arc<dyn FileSystem>,
+    /// Entry, the filesystem has been mounted on (None for the root filesystem).
+    point: Option<Arc<Dentry>>,
+}
+
+/// Regular file, directory or device node, opened via `open()`.
+/// Descriptors referring to the same `OpenFile` (e.g. after `dup()`) share its offset and flags.
+pub struct OpenFile {
+    inode: Arc<dyn Inode>,
+    flags: OpenFlags,
+    offset: Mutex<usize>,
+    /// Identifies the owner of locks held by this open file (see `lock::lock()`).
+    /// In contrast to the address of the open file, it is never reused by another open file.
+    lock_owner: usize,
+    /// Entries of a directory, which is read in chunks (see `readdir_from()`).
+    listing: Mutex<Vec<DirEntry>>,
+}
+
+/// Mount `fs`, created from `source`, at `path`. The first filesystem must be mounted at '/'.
+/// Mounting on top of another mount point hides the previously mounted filesystem, until the new one is unmounted.
+pub fn mount(source: &str, path: &str, fs: Arc<dyn FileSystem>) -> Result<()> {
+    let path = normalize(path)?;
+    let mut mounts = MOUNTS.write();
+
+    let point = if path == "/" && ROOT.read().is_none() {
+        ROOT.write().replace(Dentry::new_root(fs.root()));
+        None
+    } else {
+        let mount_point = resolve(&path)?;
+        if !mount_point.is_directory() {
+            return Err(Errno::NotADirectory);
+        }
+
+        mount_point.mount(fs.root())?;
+        Some(mount_point)
+    };
+
+    info!("Mounted [{}] from [{}] at [{}]", fs.name(), source, path);
+    mounts.push(Mount { source: source.to_string(), path, fs, point });
+
+    return Ok(());
+}
+
+/// Unmount the filesystem, that has been mounted last at `path`.
+/// Modified data is written back first (see `FileSystem::sync()`).
+/// Fails with `Errno::Busy` for the root filesystem and if other filesystems are mounted inside it.
+/// Files, which are still open, stay usable, but can not be reached via the directory tree anymore.
+pub fn unmount(path: &str) -> Result<()> {
+    let path = normalize(path)?;
+    let mut mounts = MOUNTS.write();
+    let index = mounts.iter().rposition(|mount| mount.path == path).ok_or(Errno::InvalidArgument)?;
+
+    let prefix = if path == "/" { String::from("/") } else { format!("{}/", path) };
+    if mounts[index + 1..].iter().any(|mount| mount.path.starts_with(&prefix)) {
+        return Err(Errno::Busy);
+    }
+
+    let mount = &mounts[index];
+    match &mount.point {
+        Some(point) => {
+            // Nothing is unmounted, if writing back fails, so that no data is lost
+            mount.fs.sync()?;
+            point.unmount()?;
+        }
+        None => return Err(Errno::Busy)
+    }
+
+    let mount = mounts.remove(index);
+    info!("Unmounted [{}] from [{}]", mount.fs.name(), mount.path);
+
+    return Ok(());
+}
+
+/// List all mounted filesystems as source, mount point and filesystem name.
+pub fn mounts() -> Vec<(String, String, &'static str)> {
+    return MOUNTS.read().iter()
+        .map(|mount| (mount.source.clone(), mount.path.clone(), mount.fs.name()))
+        .collect();
+}
+
+/// Write modified data of all mounted filesystems and all dirty blocks in the block cache back (like `sync()`).
+/// All filesystems are synced, even if some of them fail. The first error is returned.
+pub fn sync() -> Result<()> {
+    let mut result = Ok(());
+    for mount in MOUNTS.read().iter() {
+        if let Err(err) = mount.fs.sync() {
+            warn!("Failed to sync [{}] at [{}] (Error: {:?})", mount.fs.name(), mount.path, err);
+            result = result.and(Err(err));
+        }
+    }
+
+    return result.and(block_cache().flush());
+}
+
+/// Remove empty and '.' components from an absolute `path` (e.g. '/tmp/./dir/' becomes '/tmp/dir').
+/// Paths containing '..' are rejected, since they can not be normalized without resolving them.
+fn normalize(path: &str) -> Result<String> {
+    if !path.starts_with('/') {
+        return Err(Errno::InvalidArgument);
+    }
+
+    let mut normalized = String::new();
+    for component in path.split('/').filter(|component| !component.is_empty() && *component != ".") {
+        if component == ".." {
+            return Err(Errno::InvalidArgument);
+        }
+
+        normalized.push('/');
+        normalized.push_str(component);
+    }
+
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+
+    return Ok(normalized);
+}
+
+pub fn open(path: &str, flags: OpenFlags) -> Result<Arc<dyn File>> {
+    let inode = match resolve(path) {
+        Ok(dentry) => {
+            if flags.contains(OpenFlags::CREATE | OpenFlags::EXCLUSIVE) {
+                return Err(Errno::AlreadyExists);
+            }
+
+            dentry.inode()
+        }
+        Err(Errno::NotFound) if flags.contains(OpenFlags::CREATE) => {
+            let (parent, name) = resolve_parent(path)?;
+            let inode = parent.inode().create(&name, FileType::Regular)?;
+            parent.insert(&name, Arc::clone(&inode));
+            watch::notify(&parent.inode(), WatchEvents::CREATE, &name);
+
+            inode
+        }
+        Err(err) => return Err(err)
+    };
+
+    let typ = inode.metadata().typ;
+    if typ == FileType::Directory && flags.intersects(OpenFlags::WRITE | OpenFlags::TRUNCATE) {
+        return Err(Errno::IsADirectory);
+    }
+    if typ != FileType::Directory && flags.contains(OpenFlags::DIRECTORY) {
+        return Err(Errno::NotADirectory);
+    }
+
+    if typ == FileType::Fifo {
+        return pipe::open_fifo(inode.pipe()?, flags);
+    }
+    if typ == FileType::Socket {
+        // Socket nodes can only be connected to (see `net::unix`)
+        return Err(Errno::NoDevice);
+    }
+    if let Some(file) = inode.open(flags)? {
+        return Ok(file);
+    }
+
+    if flags.contains(OpenFlags::TRUNCATE) && flags.contains(OpenFlags::WRITE) {
+        inode.truncate(0)?;
+        watch::notify(&inode, WatchEvents::MODIFY, "");
+    }
+
+    return Ok(Arc::new(OpenFile::new(inode, flags)));
+}
+
+pub fn read(file: &Arc<dyn File>, buffer: &mut [u8]) -> Result<usize> {
+    return file.read(buffer);
+}
+
+pub fn write(file: &Arc<dyn File>, buffer: &[u8]) -> Result<usize> {
+    return file.write(buffer);
+}
+
+/// Close a file. The underlying object is released, once all references to it are gone.
+pub fn close(file: Arc<dyn File>) {
+    drop(file);
+}
+
+/// Read the whole file at `path` into memory (e.g. for loading applications).
+pub fn read_all(path: &str) -> Result<Vec<u8>> {
+    let file = open(path, OpenFlags::READ)?;
+    let mut data = vec![0; file.stat()?.size];
+
+    let mut offset = 0;
+    while offset < data.len() {
+        match file.read(&mut data[offset..])? {
+            0 => break,
+            count => offset += count
+        }
+    }
+
+    data.truncate(offset);
+    return Ok(data);
+}
+
+pub fn mkdir(path: &str) -> Result<()> {
+    let (parent, name) = resolve_parent(path)?;
+    let inode = parent.inode().create(&name, FileType::Directory)?;
+    parent.insert(&name, inode);
+    watch::notify(&parent.inode(), WatchEvents::CREATE, &name);
+
+    return Ok(());
+}
+
+/// Create a FIFO (named pipe) at `path`. Processes opening it for reading and writing are connected by the same pipe.
+pub fn mkfifo(path: &str) -> Result<()> {
+    let (parent, name) = resolve_parent(path)?;
+    let inode = parent.inode().create(&name, FileType::Fifo)?;
+    parent.insert(&name, inode);
+    watch::notify(&parent.inode(), WatchEvents::CREATE, &name);
+
+    return Ok(());
+}
+
+/// Create a socket node at `path` and return its endpoint, via which clients connect to the socket bound to it.
+/// Fails with `Errno::AddressInUse`, if `path` already exists (even if no socket is bound to it anymore).
+pub fn mksocket(path: &str) -> Result<Arc<Endpoint>> {
+    let (parent, name) = resolve_parent(path)?;
+    let inode = parent.inode().create(&name, FileType::Socket).map_err(|err| match err {
+        Errno::AlreadyExists => Errno::AddressInUse,
+        err => err
+    })?;
+
+    let endpoint = inode.socket()?;
+    parent.insert(&name, inode);
+    watch::notify(&parent.inode(), WatchEvents::CREATE, &name);
+
+    return Ok(endpoint);
+}
+
+/// The endpoint of the socket node at `path`. Fails with `Errno::ConnectionRefused`, if `path` is not a socket node.
+pub fn socket_endpoint(path: &str) -> Result<Arc<Endpoint>> {
+    let inode = resolve(path)?.inode();
+    if inode.metadata().typ != FileType::Socket {
+        return Err(Errno::ConnectionRefused);
+    }
+
+    return inode.socket();
+}
+
+/// Remove the file at `path` (use `rmdir()` for directories).
+pub fn unlink(path: &str) -> Result<()> {
+    let dentry = resolve(path)?;
+    if dentry.is_directory() {
+        return Err(Errno::IsADirectory);
+    }
+
+    return remove(path, &dentry.inode());
+}
+
+/// Remove the empty directory at `path`.
+pub fn rmdir(path: &str) -> Result<()> {
+    let dentry = resolve(path)?;
+    if !dentry.is_directory() {
+        return Err(Errno::NotADirectory);
+    }
+
+    return remove(path, &dentry.inode());
+}
+
+/// Remove the entry for `inode` at `path` from its parent directory (see `unlink()` and `rmdir()`).
+fn remove(path: &str, inode: &Arc<dyn Inode>) -> Result<()> {
+    let (parent, name) = resolve_parent(path)?;
+    parent.invalidate(&name)?;
+    parent.inode().unlink(&name)?;
+
+    watch::notify(&parent.inode(), WatchEvents::DELETE, &name);
+    watch::notify(inode, WatchEvents::DELETE, "");
+
+    return Ok(());
+}
+
+/// Move the file or directory at `old_path` to `new_path`. Both paths must be located in the same filesystem.
+pub fn rename(old_path: &str, new_path: &str) -> Result<()> {
+    let source = resolve(old_path)?;
+    let (old_parent, old_name) = resolve_parent(old_path)?;
+    let (new_parent, new_name) = resolve_parent(new_path)?;
+
+    // A directory can not be moved into itself. The resolved entries are compared,
+    // since the paths may be spelled differently (e.g. '//a' and '/./a/b').
+    if source.is_ancestor_of(&new_parent) {
+        return Err(Errno::InvalidArgument);
+    }
+
+    old_parent.invalidate(&old_name)?;
+    new_parent.invalidate(&new_name)?;
+    old_parent.inode().rename(&old_name, &new_parent.inode(), &new_name)?;
+
+    watch::notify(&old_parent.inode(), WatchEvents::DELETE, &old_name);
+    watch::notify(&new_parent.inode(), WatchEvents::CREATE, &new_name);
+
+    return Ok(());
+}
+
+pub fn readdir(path: &str) -> Result<Vec<DirEntry>> {
+    return resolve(path)?.inode().readdir();
+}
+
+pub fn stat(path: &str) -> Result<Metadata> {
+    return Ok(resolve(path)?.inode().metadata());
+}
+
+/// Walk the directory tree along an absolute `path` and return the cached entry for its last component.
+pub fn resolve(path: &str) -> Result<Arc<Dentry>> {
+    if !path.starts_with('/') {
+        return Err(Errno::InvalidArgument);
+    }
+
+    let root = ROOT.read().clone().ok_or(Errno::NotFound)?;
+    let mut dentry = root.follow_mounts();
+    for component in path.split('/').filter(|component| !component.is_empty()) {
+        if !dentry.is_directory() {
+            return Err(Errno::NotADirectory);
+        }
+
+        dentry = dentry.lookup(component)?;
+    }
+
+    return Ok(dentry);
+}
+
+/// Resolve the directory containing the last component of `path` and return it together with the name of that component.
+pub fn resolve_parent(path: &str) -> Result<(Arc<Dentry>, String)> {
+    let path = path.trim_end_matches('/');
+    let (parent, name) = path.rsplit_once('/').ok_or(Errno::InvalidArgument)?;
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(Errno::InvalidArgument);
+    }
+
+    let parent = resolve(if parent.is_empty() { "/" } else { parent })?;
+    if !parent.is_directory() {
+        return Err(Errno::NotADirectory);
+    }
+
+    return Ok((parent, name.to_string()));
+}
+
+impl OpenFile {
+    pub fn new(inode: Arc<dyn Inode>, flags: OpenFlags) -> Self {
+        Self { inode, flags, offset: Mutex::new(0), lock_owner: NEXT_LOCK_OWNER.fetch_add(1, Relaxed), listing: Mutex::new(Vec::new()) }
+    }
+
+    pub fn flags(&self) -> OpenFlags {
+        self.flags
+    }
+
+    fn check_readable(&self) -> Result<()> {
+        if !self.flags.contains(OpenFlags::READ) {
+            return Err(Errno::BadDescriptor);
+        }
+        if self.inode.metadata().typ == FileType::Directory {
+            return Err(Errno::IsADirectory);
+        }
+
+        return Ok(());
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if !self.flags.contains(OpenFlags::WRITE) {
+            return Err(Errno::BadDescriptor);
+        }
+
+        return Ok(());
+    }
+}
+
+impl File for OpenFile {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+        self.check_readable()?;
+
+        let mut offset = self.offset.lock();
+        let count = self.inode.read_at(*offset, buffer)?;
+        *offset += count;
+
+        return Ok(count);
+    }
+
+    fn write(&self, buffer: &[u8]) -> Result<usize> {
+        self.check_writable()?;
+
+        let mut offset = self.offset.lock();
+        if self.flags.contains(OpenFlags::APPEND) {
+            *offset = self.inode.metadata().size;
+        }
+
+        let count = self.inode.write_at(*offset, buffer)?;
+        *offset += count;
+
+        watch::notify(&self.inode, WatchEvents::MODIFY, "");
+        return Ok(count);
+    }
+
+    fn seek(&self, position: SeekFrom) -> Result<usize> {
+        let mut offset = self.offset.lock();
+        let new_offset = match position {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::Current(delta) => offset.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.inode.metadata().size.checked_add_signed(delta)
+        };
+
+        *offset = new_offset.ok_or(Errno::InvalidArgument)?;
+        return Ok(*offset);
+    }
+
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        self.check_readable()?;
+        return self.inode.read_at(offset, buffer);
+    }
+
+    fn write_at(&self, offset: usize, buffer: &[u8]) -> Result<usize> {
+        self.check_writable()?;
+        let count = self.inode.write_at(offset, buffer)?;
+
+        watch::notify(&self.inode, WatchEvents::MODIFY, "");
+        return Ok(count);
+    }
+
+    fn truncate(&self, size: usize) -> Result<()> {
+        self.check_writable()?;
+        if self.inode.metadata().typ != FileType::Regular {
+            return Err(Errno::InvalidArgument);
+        }
+
+        self.inode.truncate(size)?;
+
+        watch::notify(&self.inode, WatchEvents::MODIFY, "");
+        return Ok(());
+    }
+
+    fn stat(&self) -> Result<Metadata> {
+        return Ok(self.inode.metadata());
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        return self.inode.readdir();
+    }
+
+    /// The entries are read from the inode once, when reading starts at index 0, and later chunks are taken from this listing
+    /// (like the position of `getdents()`), so that reading a whole directory in chunks does not list it again for each chunk.
+    fn readdir_from(&self, index: usize, count: usize) -> Result<Vec<DirEntry>> {
+        let mut listing = self.listing.lock();
+        if index == 0 {
+            *listing = self.inode.readdir()?;
+        }
+
+        return Ok(listing.iter().skip(index).take(count).cloned().collect());
+    }
+
+    fn ioctl(&self, request: usize, arg: usize) -> Result<usize> {
+        return self.inode.ioctl(request, arg);
+    }
+
+    fn sync(&self) -> Result<()> {
+        return self.inode.sync();
+    }
+
+    /// Mappings are always writable, so the file must be opened for writing.
+    fn mmap(&self, offset: usize, length: usize) -> Result<(PhysFrameRange, PageTableFlags)> {
+        if !self.flags.contains(OpenFlags::WRITE) {
+            return Err(Errno::AccessDenied);
+        }
+
+        return self.inode.mmap(offset, length);
+    }
+
+    fn lock(&self, operation: LockOperation) -> Result<()> {
+        let blocking = !operation.contains(LockOperation::NON_BLOCKING);
+        return match operation.difference(LockOperation::NON_BLOCKING) {
+            LockOperation::SHARED => lock::lock(&self.inode, self.lock_owner, false, blocking),
+            LockOperation::EXCLUSIVE => lock::lock(&self.inode, self.lock_owner, true, blocking),
+            LockOperation::UNLOCK => {
+                lock::unlock(&self.inode, self.lock_owner);
+                Ok(())
+            }
+            _ => Err(Errno::InvalidArgument)
+        };
+    }
+}
+
+/// Locks are released, once the last descriptor referring to the open file has been closed.
+impl Drop for OpenFile {
+    fn drop(&mut self) {
+        lock::unlock(&self.inode, self.lock_owner);
+    }
+}
+
+/// Regular files are always ready, but devices may block (e.g. input devices without pending data).
+impl Pollable for OpenFile {
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        return self.inode.poll(events, waiter);
+    }
+}
@@ -0,0 +1,167 @@
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ptr;
+use syscall::error::Errno;
+use syscall::file::{FileType, PollEvents, WatchEvent, WatchEvents};
+use crate::fs::{File, Inode, Metadata, Result};
+use crate::fs::poll;
+use crate::fs::poll::Pollable;
+use crate::process::wait_queue::{WaitQueue, Waiter};
+use crate::sync::{Mutex, RwLock};
+
+/// Maximum number of events queued per watcher. Further events are dropped and reported as `WatchEvents::OVERFLOW`.
+const MAX_QUEUED_EVENTS: usize = 256;
+
+/// All existing watchers, which are notified about changes by the VFS (see `notify()`).
+static WATCHERS: RwLock<Vec<Weak<Watcher>>> = RwLock::new(Vec::new());
+
+/// Readable descriptor, delivering `WatchEvent` records for changes to the watched files and directories.
+/// Reading blocks, until at least one event is available.
+pub struct Watcher {
+    state: Mutex<WatcherState>,
+    readable: WaitQueue,
+}
+
+struct WatcherState {
+    watches: Vec<Watch>,
+    events: VecDeque<WatchEvent>,
+    next_id: usize,
+    overflow: bool,
+}
+
+struct Watch {
+    id: usize,
+    inode: Arc<dyn Inode>,
+    events: WatchEvents,
+}
+
+/// Create a new watcher without any watches.
+pub fn create() -> Arc<Watcher> {
+    let state = WatcherState { watches: Vec::new(), events: VecDeque::new(), next_id: 1, overflow: false };
+    let watcher = Arc::new(Watcher { state: Mutex::new(state), readable: WaitQueue::new() });
+
+    let mut watchers = WATCHERS.write();
+    watchers.retain(|watcher| watcher.strong_count() > 0);
+    watchers.push(Arc::downgrade(&watcher));
+
+    return watcher;
+}
+
+/// Report `event` for `inode` to all watchers. `name` is the affected entry, if `inode` is a directory.
+/// Called by the VFS for all changes made through it, so that it works for every writable filesystem.
+pub fn notify(inode: &Arc<dyn Inode>, event: WatchEvents, name: &str) {
+    let watchers = WATCHERS.read();
+    for watcher in watchers.iter().filter_map(Weak::upgrade) {
+        watcher.notify(inode, event, name);
+    }
+}
+
+impl Watcher {
+    /// Watch `inode` for `events` and return the id of the new watch.
+    /// Watching the same inode again replaces the events of the existing watch.
+    pub fn add(&self, inode: Arc<dyn Inode>, events: WatchEvents) -> Result<usize> {
+        if events.is_empty() || events.contains(WatchEvents::OVERFLOW) {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let mut state = self.state.lock();
+        if let Some(watch) = state.watches.iter_mut().find(|watch| Arc::ptr_eq(&watch.inode, &inode)) {
+            watch.events = events;
+            return Ok(watch.id);
+        }
+
+        let id = state.next_id;
+        state.next_id += 1;
+        state.watches.push(Watch { id, inode, events });
+
+        return Ok(id);
+    }
+
+    pub fn remove(&self, id: usize) -> Result<()> {
+        let mut state = self.state.lock();
+        let index = state.watches.iter().position(|watch| watch.id == id).ok_or(Errno::InvalidArgument)?;
+        state.watches.remove(index);
+
+        return Ok(());
+    }
+
+    fn notify(&self, inode: &Arc<dyn Inode>, event: WatchEvents, name: &str) {
+        let mut state = self.state.lock();
+        let index = match state.watches.iter().position(|watch| Arc::ptr_eq(&watch.inode, inode)) {
+            Some(index) => index,
+            None => return
+        };
+
+        let watch = &state.watches[index];
+        let (id, interested) = (watch.id, watch.events.intersects(event));
+
+        // A removed file can not change anymore, so its watch is dropped (releasing the inode)
+        if event == WatchEvents::DELETE && name.is_empty() {
+            state.watches.remove(index);
+        }
+        if !interested {
+            return;
+        }
+
+        if state.events.len() >= MAX_QUEUED_EVENTS {
+            state.overflow = true;
+        } else {
+            state.events.push_back(WatchEvent::new(id, event, name));
+        }
+
+        drop(state);
+        self.readable.notify_all();
+    }
+}
+
+impl File for Watcher {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+        if buffer.len() < size_of::<WatchEvent>() {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let mut state = self.state.lock();
+        while state.events.is_empty() && !state.overflow {
+            self.readable.wait(state);
+            state = self.state.lock();
+        }
+
+        let mut count = 0;
+        for chunk in buffer.chunks_exact_mut(size_of::<WatchEvent>()) {
+            let event = if state.overflow {
+                // Reported first, since it is unknown which events have been lost
+                state.overflow = false;
+                WatchEvent::new(0, WatchEvents::OVERFLOW, "")
+            } else {
+                match state.events.pop_front() {
+                    Some(event) => event,
+                    None => break
+                }
+            };
+
+            unsafe { ptr::copy_nonoverlapping(ptr::from_ref(&event) as *const u8, chunk.as_mut_ptr(), size_of::<WatchEvent>()); }
+            count += size_of::<WatchEvent>();
+        }
+
+        return Ok(count);
+    }
+
+    fn write(&self, _buffer: &[u8]) -> Result<usize> {
+        return Err(Errno::BadDescriptor);
+    }
+
+    fn stat(&self) -> Result<Metadata> {
+        return Ok(Metadata { inode: ptr::from_ref(self) as u64, typ: FileType::CharDevice, size: 0, created_ms: 0, modified_ms: 0 });
+    }
+}
+
+impl Pollable for Watcher {
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        let state = self.state.lock();
+        let ready = if !state.events.is_empty() || state.overflow { PollEvents::READABLE } else { PollEvents::empty() };
+
+        return poll::ready_or_register(ready, events, waiter, &[(PollEvents::READABLE, &self.readable)]);
+    }
+}
@@ -0,0 +1,97 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use crate::sync::Mutex;
+use x86_64::instructions::interrupts;
+use crate::process::thread::Thread;
+use crate::process::wait_queue::{WaitQueue, Waiter};
+use crate::scheduler;
+
+/// Bottom half of an interrupt handler, executed at the end of interrupt dispatching with interrupts enabled.
+/// Tasklets run before returning to the interrupted thread and thus must not block.
+/// Scheduling a tasklet that is already pending has no effect, so it is safe to schedule it on every interrupt.
+pub struct Tasklet {
+    scheduled: AtomicBool,
+    func: fn()
+}
+
+/// Both queues (and the worker's wait queue) are only locked with interrupts disabled, so that interrupt handlers can always acquire them.
+static TASKLETS: Mutex<VecDeque<&'static Tasklet>> = Mutex::new(VecDeque::new());
+static WORK_QUEUE: Mutex<VecDeque<Box<dyn FnOnce() + Send>>> = Mutex::new(VecDeque::new());
+static WORK_AVAILABLE: WaitQueue = WaitQueue::new();
+static TASKLETS_RUNNING: AtomicBool = AtomicBool::new(false);
+
+impl Tasklet {
+    pub const fn new(func: fn()) -> Self {
+        Self { scheduled: AtomicBool::new(false), func }
+    }
+
+    pub fn schedule(&'static self) {
+        if !self.scheduled.swap(true, Acquire) {
+            interrupts::without_interrupts(|| TASKLETS.lock().push_back(self));
+        }
+    }
+}
+
+/// Queue `work` to be executed by the kernel worker thread.
+/// In contrast to tasklets, work items run in thread context and may block (e.g. sleep or wait for I/O).
+pub fn schedule_work(work: Box<dyn FnOnce() + Send>) {
+    interrupts::without_interrupts(|| {
+        WORK_QUEUE.lock().push_back(work);
+        WORK_AVAILABLE.notify_all_from_interrupt();
+    });
+}
+
+/// Start the kernel worker thread, which executes all work queued via `schedule_work()`.
+pub fn init() {
+    scheduler().ready(Thread::new_kernel_thread(Box::new(|| {
+        loop {
+            // The queue is checked and the waiter registered without interruption, so no notification is lost
+            let waiter = Waiter::new();
+            let work = interrupts::without_interrupts(|| {
+                let work = WORK_QUEUE.lock().pop_front();
+                if work.is_none() {
+                    WORK_AVAILABLE.register(&waiter);
+                }
+
+                work
+            });
+
+            match work {
+                Some(work) => work(),
+                None => scheduler().block_on(&waiter, None)
+            }
+        }
+    })));
+}
+
+/// Called by the interrupt dispatcher with interrupts disabled, after the end of interrupt has been signaled.
+/// Interrupts are enabled while each tasklet is running, so that they may be interrupted by the next top half.
+/// Nested interrupts do not run tasklets themselves, but leave them to the outermost invocation.
+/// Thus, the running thread is not preempted, until all tasklets have run. Otherwise, no tasklets would run at all (including
+/// expired kernel timers), as long as the thread is waiting in the ready queue.
+pub fn run_tasklets() {
+    if TASKLETS_RUNNING.swap(true, Acquire) {
+        return;
+    }
+
+    scheduler().disable_preemption();
+
+    loop {
+        let tasklet = match TASKLETS.lock().pop_front() {
+            Some(tasklet) => tasklet,
+            None => break
+        };
+
+        // Reset before running, so that the tasklet can be scheduled again by interrupts occurring while it is running
+        tasklet.scheduled.store(false, Relaxed);
+
+        interrupts::enable();
+        (tasklet.func)();
+        interrupts::disable();
+    }
+
+    TASKLETS_RUNNING.store(false, Release);
+    scheduler().enable_preemption();
+}
@@ -0,0 +1,291 @@
+use core::{fmt, ptr};
+use core::arch::asm;
+use core::fmt::{Display, Formatter};
+use syscall::signal::Signal;
+use x86_64::instructions::interrupts;
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::VirtAddr;
+use crate::debug::{profiler, watchdog};
+use crate::interrupt::interrupt_dispatcher::InterruptVector;
+use crate::memory::PAGE_SIZE;
+use crate::process::core_dump;
+use crate::process::core_dump::Registers;
+use crate::process::process::current_process;
+use crate::scheduler;
+
+/// Entry of the interrupt stack table in the TSS, which is used by the double fault handler.
+/// It runs on its own stack, so that it still works, if the double fault has been caused by an overflowing kernel stack.
+pub const DOUBLE_FAULT_STACK_INDEX: u16 = 0;
+const DOUBLE_FAULT_STACK_SIZE: usize = 4 * PAGE_SIZE;
+
+#[repr(align(16))]
+struct DoubleFaultStack([u8; DOUBLE_FAULT_STACK_SIZE]);
+static mut DOUBLE_FAULT_STACK: DoubleFaultStack = DoubleFaultStack([0; DOUBLE_FAULT_STACK_SIZE]);
+
+/// Pushed by the entry stubs of exceptions, for which the CPU does not push an error code (`push -1`).
+const NO_ERROR_CODE: u64 = u64::MAX;
+
+/// General purpose registers saved by `exception_common()` (in reverse push order).
+#[repr(C)]
+struct SavedRegisters {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    r11: u64,
+    r10: u64,
+    r9: u64,
+    r8: u64,
+    rbp: u64,
+    rdi: u64,
+    rsi: u64,
+    rdx: u64,
+    rcx: u64,
+    rbx: u64,
+    rax: u64,
+}
+
+/// Stack layout at the time `handle_exception()` is called: The saved registers, the vector and error code
+/// pushed by the entry stub (or the CPU) and the interrupt stack frame pushed by the CPU.
+#[repr(C)]
+struct ExceptionFrame {
+    registers: SavedRegisters,
+    vector: u64,
+    error: u64,
+    frame: InterruptStackFrame,
+}
+
+/// Everything we know about an exception at the time it occurs.
+/// Formatting does not allocate memory, so that exceptions can be reported, even if the heap is locked or corrupted.
+struct ExceptionReport<'a> {
+    frame: &'a InterruptStackFrame,
+    registers: Option<&'a SavedRegisters>,
+    index: u8,
+    error: Option<u64>,
+}
+
+/// Entry stub for an exception, that pushes its vector (and a dummy error code, if the CPU does not push one)
+/// and continues in `exception_common()`.
+macro_rules! exception_entry {
+    ($vector:literal) => {{
+        #[naked]
+        unsafe extern "C" fn entry() {
+            asm!(
+            "push -1",
+            "push {vector}",
+            "jmp {common}",
+            vector = const $vector,
+            common = sym exception_common,
+            options(noreturn)
+            );
+        }
+
+        VirtAddr::from_ptr(entry as *const ())
+    }};
+    ($vector:literal, error_code) => {{
+        #[naked]
+        unsafe extern "C" fn entry() {
+            asm!(
+            "push {vector}",
+            "jmp {common}",
+            vector = const $vector,
+            common = sym exception_common,
+            options(noreturn)
+            );
+        }
+
+        VirtAddr::from_ptr(entry as *const ())
+    }};
+}
+
+/// Install the entry stubs for all CPU exceptions, except the non-maskable interrupt, in `idt`.
+/// Reserved vectors are left empty, since the CPU never raises them.
+pub fn install_handlers(idt: &mut InterruptDescriptorTable) {
+    unsafe {
+        idt.divide_error.set_handler_addr(exception_entry!(0));
+        idt.debug.set_handler_addr(exception_entry!(1));
+        idt.breakpoint.set_handler_addr(exception_entry!(3));
+        idt.overflow.set_handler_addr(exception_entry!(4));
+        idt.bound_range_exceeded.set_handler_addr(exception_entry!(5));
+        idt.invalid_opcode.set_handler_addr(exception_entry!(6));
+        idt.device_not_available.set_handler_addr(exception_entry!(7));
+        idt.double_fault.set_handler_addr(exception_entry!(8, error_code)).set_stack_index(DOUBLE_FAULT_STACK_INDEX);
+        idt.invalid_tss.set_handler_addr(exception_entry!(10, error_code));
+        idt.segment_not_present.set_handler_addr(exception_entry!(11, error_code));
+        idt.stack_segment_fault.set_handler_addr(exception_entry!(12, error_code));
+        idt.general_protection_fault.set_handler_addr(exception_entry!(13, error_code));
+        idt.page_fault.set_handler_addr(exception_entry!(14, error_code));
+        idt.x87_floating_point.set_handler_addr(exception_entry!(16));
+        idt.alignment_check.set_handler_addr(exception_entry!(17, error_code));
+        idt.machine_check.set_handler_addr(exception_entry!(18));
+        idt.simd_floating_point.set_handler_addr(exception_entry!(19));
+        idt.virtualization.set_handler_addr(exception_entry!(20));
+        idt.cp_protection_exception.set_handler_addr(exception_entry!(21, error_code));
+        idt.hv_injection_exception.set_handler_addr(exception_entry!(28));
+        idt.vmm_communication_exception.set_handler_addr(exception_entry!(29, error_code));
+        idt.security_exception.set_handler_addr(exception_entry!(30, error_code));
+    }
+}
+
+/// Common part of all exception entry stubs. All general purpose registers are saved,
+/// so that they can be included in the exception report and core dumps.
+#[naked]
+unsafe extern "C" fn exception_common() {
+    asm!(
+    "push rax",
+    "push rbx",
+    "push rcx",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push rbp",
+    "push r8",
+    "push r9",
+    "push r10",
+    "push r11",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+
+    // Stack is 16-byte aligned here (CPU aligns it before pushing the 5 qword interrupt frame, plus error code, vector and 15 registers)
+    "mov rdi, rsp",
+    "call {}",
+
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop r11",
+    "pop r10",
+    "pop r9",
+    "pop r8",
+    "pop rbp",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop rcx",
+    "pop rbx",
+    "pop rax",
+    "add rsp, 16", // Vector and error code
+    "iretq",
+    sym handle_exception,
+    options(noreturn)
+    );
+}
+
+extern "C" fn handle_exception(frame: *mut ExceptionFrame) {
+    let exception = unsafe { frame.as_ref().unwrap() };
+    let frame = &exception.frame;
+    let registers = &exception.registers;
+    let index = exception.vector as u8;
+    let error = if exception.error == NO_ERROR_CODE { None } else { Some(exception.error) };
+
+    // Exceptions in user mode only terminate the faulting process (machine checks and double faults are always fatal)
+    let user = frame.code_segment & 0x03 == 0x03;
+    if user && index != InterruptVector::MachineCheck as u8 && index != InterruptVector::DoubleFault as u8 {
+        terminate_process(frame, registers, index);
+    }
+
+    // Kernel stack overflows often end in a double fault, so they are reported instead, if they are detected
+    if index == InterruptVector::DoubleFault as u8 && !scheduler().is_locked() {
+        let thread = scheduler().current_thread();
+        if !thread.stacks_locked() {
+            thread.check_kernel_stack();
+        }
+    }
+
+    panic!("{}", ExceptionReport { frame, registers: Some(registers), index, error });
+}
+
+/// Top of the stack, used by the double fault handler (see `DOUBLE_FAULT_STACK_INDEX`).
+pub fn double_fault_stack_end() -> VirtAddr {
+    let start = VirtAddr::from_ptr(ptr::addr_of!(DOUBLE_FAULT_STACK));
+    return start + DOUBLE_FAULT_STACK_SIZE as u64;
+}
+
+/// Write a core file for the current process and exit the current thread with the signal matching the exception (does not return).
+fn terminate_process(frame: &InterruptStackFrame, saved: &SavedRegisters, index: u8) -> ! {
+    let (signal, fault_address) = match InterruptVector::try_from(index) {
+        Ok(InterruptVector::PageFault) => (Signal::SegmentationFault, Some(Cr2::read_raw())),
+        Ok(InterruptVector::GeneralProtectionFault | InterruptVector::StackSegmentFault | InterruptVector::SegmentNotPresent) => (Signal::SegmentationFault, None),
+        Ok(InterruptVector::InvalidOpcode) => (Signal::IllegalInstruction, None),
+        Ok(InterruptVector::DivisionByZero | InterruptVector::X87FloatingPointException | InterruptVector::SimdFloatingPointException) => (Signal::FloatingPointException, None),
+        Ok(InterruptVector::Debug | InterruptVector::Breakpoint) => (Signal::Trap, None),
+        Ok(InterruptVector::AlignmentCheck) => (Signal::BusError, None),
+        _ => (Signal::Kill, None)
+    };
+
+    // We came from user mode, so no locks are held and interrupts can be enabled for writing the core file
+    interrupts::enable();
+
+    let registers = Registers { r15: saved.r15, r14: saved.r14, r13: saved.r13, r12: saved.r12, rbp: saved.rbp, rbx: saved.rbx,
+        r11: saved.r11, r10: saved.r10, r9: saved.r9, r8: saved.r8, rax: saved.rax, rcx: saved.rcx,
+        rdx: saved.rdx, rsi: saved.rsi, rdi: saved.rdi, rip: frame.instruction_pointer.as_u64(), cs: frame.code_segment,
+        rflags: frame.cpu_flags, rsp: frame.stack_pointer.as_u64(), ss: frame.stack_segment, ..Registers::default() };
+    let process = current_process();
+    core_dump::dump(&process, signal, &registers, fault_address);
+    drop(process); // Manually decrease reference count, because exit() will never return
+
+    scheduler().exit();
+    panic!("Terminated thread has been scheduled again!");
+}
+
+pub fn handle_nmi(frame: InterruptStackFrame, index: u8, error: Option<u64>) {
+    if profiler::handle_nmi(&frame) {
+        return;
+    }
+
+    if !watchdog::check(&frame) {
+        // The NMI is not entered through `exception_common()`, so the general purpose registers are unknown
+        panic!("{}", ExceptionReport { frame: &frame, registers: None, index, error });
+    }
+}
+
+impl Display for ExceptionReport<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match InterruptVector::try_from(self.index) {
+            Ok(vector) => writeln!(f, "CPU Exception: [{} - {:?}]", self.index, vector)?,
+            Err(_) => writeln!(f, "CPU Exception: [{} - Reserved]", self.index)?
+        }
+
+        if let Some(error) = self.error {
+            write!(f, "Error code: [0x{:04x}]", error)?;
+
+            if self.index == InterruptVector::PageFault as u8 {
+                let flags = PageFaultErrorCode::from_bits_truncate(error);
+                writeln!(f, " {:?}", flags)?;
+                writeln!(f, "Address: [0x{:016x}] ({} while {} in {} mode)", Cr2::read_raw(),
+                         if flags.contains(PageFaultErrorCode::PROTECTION_VIOLATION) { "Protection violation" } else { "Page not present" },
+                         if flags.contains(PageFaultErrorCode::INSTRUCTION_FETCH) { "fetching instruction" } else if flags.contains(PageFaultErrorCode::CAUSED_BY_WRITE) { "writing" } else { "reading" },
+                         if flags.contains(PageFaultErrorCode::USER_MODE) { "user" } else { "kernel" })?;
+            } else if error != 0 && self.index != InterruptVector::DoubleFault as u8 && self.index != InterruptVector::AlignmentCheck as u8 {
+                // Selector error code (e.g. general protection or segment not present)
+                let table = match (error >> 1) & 0x03 {
+                    0 => "GDT",
+                    2 => "LDT",
+                    _ => "IDT"
+                };
+
+                writeln!(f, " (Table: [{}], Index: [{}]{})", table, (error >> 3) & 0x1fff, if error & 0x01 != 0 { ", External" } else { "" })?;
+            } else {
+                writeln!(f)?;
+            }
+        }
+
+        let frame = self.frame;
+        writeln!(f, "RIP: [0x{:016x}], CS: [0x{:04x}], RFLAGS: [0x{:016x}]", frame.instruction_pointer.as_u64(), frame.code_segment, frame.cpu_flags)?;
+        writeln!(f, "RSP: [0x{:016x}], SS: [0x{:04x}]", frame.stack_pointer.as_u64(), frame.stack_segment)?;
+
+        if let Some(registers) = self.registers {
+            writeln!(f, "RAX: [0x{:016x}], RBX: [0x{:016x}], RCX: [0x{:016x}], RDX: [0x{:016x}]", registers.rax, registers.rbx, registers.rcx, registers.rdx)?;
+            writeln!(f, "RSI: [0x{:016x}], RDI: [0x{:016x}], RBP: [0x{:016x}], R8:  [0x{:016x}]", registers.rsi, registers.rdi, registers.rbp, registers.r8)?;
+            writeln!(f, "R9:  [0x{:016x}], R10: [0x{:016x}], R11: [0x{:016x}], R12: [0x{:016x}]", registers.r9, registers.r10, registers.r11, registers.r12)?;
+            writeln!(f, "R13: [0x{:016x}], R14: [0x{:016x}], R15: [0x{:016x}]", registers.r13, registers.r14, registers.r15)?;
+        }
+
+        write!(f, "CR0: [0x{:016x}], CR2: [0x{:016x}], CR3: [0x{:016x}], CR4: [0x{:016x}]",
+               Cr0::read_raw(), Cr2::read_raw(), Cr3::read_raw().0.start_address().as_u64(), Cr4::read_raw())
+    }
+}
@@ -1,13 +1,22 @@
+use crate::interrupt::deferred;
+use crate::interrupt::exception;
+use crate::interrupt::exception::handle_nmi;
 use crate::interrupt::interrupt_handler::InterruptHandler;
+use crate::process::signal;
 use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::ops::Deref;
 use core::ptr;
-use spin::Mutex;
-use x86_64::registers::control::Cr2;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering::Relaxed;
+use crate::sync::Mutex;
+use x86_64::instructions::interrupts;
 use x86_64::set_general_handler;
 use x86_64::structures::idt::InterruptStackFrame;
-use crate::{apic, idt, interrupt_dispatcher};
+use crate::{apic, idt, interrupt_dispatcher, random};
 
 #[repr(u8)]
 #[derive(PartialEq, PartialOrd, Copy, Clone, Debug)]
@@ -169,19 +178,38 @@ impl TryFrom<u8> for InterruptVector {
 
 const MAX_VECTORS: usize = 256;
 
-pub struct InterruptDispatcher {
-    int_vectors: Vec<Mutex<Vec<Box<dyn InterruptHandler>>>>,
+/// Vectors, which are handed out at runtime (e.g. for message signaled interrupts).
+/// Vectors below are used for exceptions and IO APIC interrupts, while vectors above are used by the remapped PICs and the local APIC.
+pub const DYNAMIC_VECTOR_START: u8 = 0x40;
+pub const DYNAMIC_VECTOR_END: u8 = 0xe0;
+
+/// Handlers with a higher priority are triggered first, when multiple handlers share a vector.
+pub const DEFAULT_PRIORITY: u8 = 128;
+
+/// Returned when assigning a handler and needed to remove it again (e.g. on driver teardown).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HandlerId(usize);
+
+struct HandlerEntry {
+    id: HandlerId,
+    priority: u8,
+    handler: Box<dyn InterruptHandler>,
 }
 
-unsafe impl Send for InterruptDispatcher {}
-unsafe impl Sync for InterruptDispatcher {}
+pub struct InterruptDispatcher {
+    int_vectors: Vec<Mutex<Vec<HandlerEntry>>>,
+    int_counts: Vec<AtomicUsize>,
+    dynamic_vectors: Mutex<Vec<bool>>,
+    next_handler_id: AtomicUsize,
+}
 
 pub fn setup_idt() {
     let mut idt = idt().lock();
 
-    set_general_handler!(&mut idt, handle_exception, 0..31);
+    // Exceptions enter through stubs saving all general purpose registers, the double fault handler runs on its own stack
+    exception::install_handlers(&mut idt);
     set_general_handler!(&mut idt, handle_interrupt, 32..255);
-    set_general_handler!(&mut idt, handle_page_fault, 14);
+    set_general_handler!(&mut idt, handle_nmi, 2);
 
     unsafe {
         // We need to obtain a static reference to the IDT for the following operation.
@@ -192,55 +220,146 @@ pub fn setup_idt() {
     }
 }
 
-fn handle_exception(frame: InterruptStackFrame, index: u8, error: Option<u64>) {
-    panic!("CPU Exception: [{} - {:?}]\nError code: [{:?}]\n{:?}", index, InterruptVector::try_from(index).unwrap(), error, frame);
-}
-
-fn handle_page_fault(frame: InterruptStackFrame, _index: u8, error: Option<u64>) {
-    panic!("Page Fault!\nError code: [{:?}]\nAddress: [{:0>16x}]\n{:?}", error, Cr2::read(), frame);
-}
-
-fn handle_interrupt(_frame: InterruptStackFrame, index: u8, _error: Option<u64>) {
+fn handle_interrupt(frame: InterruptStackFrame, index: u8, _error: Option<u64>) {
     interrupt_dispatcher().dispatch(index);
+
+    // Returning to user mode (RPL 3) -> Deliver pending signals
+    if frame.code_segment & 3 == 3 {
+        signal::deliver_on_interrupt_return();
+    }
 }
 
 impl InterruptDispatcher {
     pub fn new() -> Self {
-        let mut int_vectors = Vec::<Mutex<Vec<Box<dyn InterruptHandler>>>>::new();
+        let mut int_vectors = Vec::<Mutex<Vec<HandlerEntry>>>::new();
+        let mut int_counts = Vec::<AtomicUsize>::new();
         for _ in 0..MAX_VECTORS {
             int_vectors.push(Mutex::new(Vec::new()));
+            int_counts.push(AtomicUsize::new(0));
         }
 
-        return Self { int_vectors };
+        let dynamic_vectors = Mutex::new(vec![false; (DYNAMIC_VECTOR_END - DYNAMIC_VECTOR_START) as usize]);
+        return Self { int_vectors, int_counts, dynamic_vectors, next_handler_id: AtomicUsize::new(1) };
+    }
+
+    pub fn assign(&self, vector: InterruptVector, handler: Box<dyn InterruptHandler>) -> HandlerId {
+        return self.assign_vector(vector as u8, DEFAULT_PRIORITY, handler);
     }
 
-    pub fn assign(&self, vector: InterruptVector, handler: Box<dyn InterruptHandler>) {
-        match self.int_vectors.get(vector as usize) {
-            Some(vec) => vec.lock().push(handler),
-            None => panic!("Assigning interrupt handler to illegal vector number {}!", vector as u8)
+    /// Assign a handler by legacy ISA IRQ number (compatibility layer for drivers, which do not know their interrupt vector).
+    /// The IO APIC delivers legacy IRQs to the vectors starting at `InterruptVector::Pit`, regardless of interrupt source overrides.
+    pub fn assign_irq(&self, irq: u8, handler: Box<dyn InterruptHandler>) -> HandlerId {
+        if irq as usize >= MAX_VECTORS - InterruptVector::Pit as usize {
+            panic!("Assigning interrupt handler to illegal IRQ number {}!", irq);
         }
+
+        return self.assign_vector(InterruptVector::Pit as u8 + irq, DEFAULT_PRIORITY, handler);
+    }
+
+    /// Add `handler` to the handlers of `vector`. Multiple handlers may share a vector (e.g. for shared legacy IRQs),
+    /// in which case they are triggered in order of descending `priority`.
+    pub fn assign_vector(&self, vector: u8, priority: u8, handler: Box<dyn InterruptHandler>) -> HandlerId {
+        let id = HandlerId(self.next_handler_id.fetch_add(1, Relaxed));
+
+        // Interrupts are disabled, so that the handler list is never modified, while it is used by dispatch()
+        interrupts::without_interrupts(|| {
+            let mut handlers = self.int_vectors[vector as usize].lock();
+            let index = handlers.iter().position(|entry| entry.priority < priority).unwrap_or(handlers.len());
+            handlers.insert(index, HandlerEntry { id, priority, handler });
+        });
+
+        return id;
+    }
+
+    /// Remove a previously assigned handler and return it (e.g. on driver teardown).
+    pub fn unassign(&self, vector: u8, id: HandlerId) -> Option<Box<dyn InterruptHandler>> {
+        return interrupts::without_interrupts(|| {
+            let mut handlers = self.int_vectors[vector as usize].lock();
+            let index = handlers.iter().position(|entry| entry.id == id)?;
+            return Some(handlers.remove(index).handler);
+        });
+    }
+
+    /// Reserve a free vector from the dynamic range and assign `handler` to it.
+    pub fn allocate_vector(&self, handler: Box<dyn InterruptHandler>) -> u8 {
+        let vector = interrupts::without_interrupts(|| {
+            let mut dynamic_vectors = self.dynamic_vectors.lock();
+            let index = dynamic_vectors.iter().position(|used| !used).expect("Interrupt Dispatcher: No free interrupt vectors left!");
+            dynamic_vectors[index] = true;
+
+            return DYNAMIC_VECTOR_START + index as u8;
+        });
+
+        self.assign_vector(vector, DEFAULT_PRIORITY, handler);
+        return vector;
+    }
+
+    /// Remove all handlers from a dynamically allocated vector and make it available again.
+    /// The device must not signal the vector anymore.
+    pub fn free_vector(&self, vector: u8) {
+        if vector < DYNAMIC_VECTOR_START || vector >= DYNAMIC_VECTOR_END {
+            panic!("Interrupt Dispatcher: Vector [{}] has not been allocated dynamically!", vector);
+        }
+
+        interrupts::without_interrupts(|| {
+            self.int_vectors[vector as usize].lock().clear();
+            self.dynamic_vectors.lock()[(vector - DYNAMIC_VECTOR_START) as usize] = false;
+        });
+    }
+
+    /// Number of times, the given vector has been dispatched.
+    pub fn interrupt_count(&self, vector: u8) -> usize {
+        return self.int_counts[vector as usize].load(Relaxed);
+    }
+
+    /// Statistics for all vectors, that have handlers assigned or have occurred at least once (similar to `/proc/interrupts`).
+    pub fn statistics(&self) -> String {
+        let mut statistics = String::from("Vector  Count       Handlers  Name\n");
+
+        for vector in 0..MAX_VECTORS {
+            let count = self.int_counts[vector].load(Relaxed);
+            let handlers = interrupts::without_interrupts(|| self.int_vectors[vector].lock().len());
+            if count == 0 && handlers == 0 {
+                continue;
+            }
+
+            let name = match InterruptVector::try_from(vector as u8) {
+                Ok(vector) => format!("{:?}", vector),
+                Err(_) if vector >= DYNAMIC_VECTOR_START as usize && vector < DYNAMIC_VECTOR_END as usize => String::from("Dynamic"),
+                Err(_) => String::from("Unknown")
+            };
+
+            statistics.push_str(format!("0x{:02x}    {:<10}  {:<8}  {}\n", vector, count, handlers, name).as_str());
+        }
+
+        return statistics;
     }
 
     pub fn dispatch(&self, interrupt: u8) {
+        crate::trace_function!();
         let handler_vec_mutex = self.int_vectors.get(interrupt as usize).unwrap_or_else(|| panic!("Interrupt Dispatcher: No handler vec assigned for interrupt [{}]!", interrupt));
         let mut handler_vec = handler_vec_mutex.try_lock();
         while handler_vec.is_none() {
             // We have to force unlock inside the interrupt handler, or else the system will hang forever.
-            // While this might be unsafe, it is extremely unlikely that we destroy something here, since we only need read access to the vectors.
-            // The only scenario, in which something might break, is when two or more drivers are trying to assign an interrupt handler to the same vector,
-            // while an interrupt for that vector occurs.
+            // Handler lists are only modified with interrupts disabled, so the lock can only be held by a handler, that has been interrupted by an exception.
             unsafe { handler_vec_mutex.force_unlock(); }
             handler_vec = handler_vec_mutex.try_lock();
         }
 
+        self.int_counts[interrupt as usize].fetch_add(1, Relaxed);
+        random::add_interrupt_timing(interrupt);
+
         if handler_vec.iter().is_empty() {
             panic!("Interrupt Dispatcher: No handler registered for interrupt [{}]!", interrupt);
         }
 
-        for handler in handler_vec.unwrap().iter_mut() {
-            handler.trigger();
+        for entry in handler_vec.unwrap().iter_mut() {
+            entry.handler.trigger();
         }
 
         apic().end_of_interrupt();
+
+        // Execute bottom halves, which have been scheduled by the interrupt handlers
+        deferred::run_tasklets();
     }
 }
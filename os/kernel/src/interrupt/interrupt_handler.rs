@@ -1,3 +1,3 @@
-pub trait InterruptHandler {
+pub trait InterruptHandler: Send {
     fn trigger(&mut self);
 }
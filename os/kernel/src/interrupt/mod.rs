@@ -1,2 +1,4 @@
+pub mod deferred;
+pub mod exception;
 pub mod interrupt_dispatcher;
 pub mod interrupt_handler;
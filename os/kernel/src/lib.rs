@@ -11,27 +11,37 @@
 #![allow(internal_features)]
 #![no_std]
 
+use crate::debug::{backtrace, gdb};
+use crate::debug::PanicWriter;
 use crate::device::apic::Apic;
+use crate::device::qemu_cfg;
+use crate::device::qemu_cfg::ExitCode;
+use crate::device::pci::PciBus;
 use crate::device::lfb_terminal::{CursorThread, LFBTerminal};
 use crate::device::pit::Timer;
 use crate::device::ps2::PS2;
 use crate::device::serial;
-use crate::device::serial::{BaudRate, ComPort, SerialPort};
+use crate::device::serial::{BaudRate, ComPort, SerialPort, SerialTerminal};
 use crate::device::speaker::Speaker;
-use crate::device::terminal::Terminal;
+use crate::device::terminal::{Terminal, CONSOLE_COUNT};
+use crate::net::telnet;
+use graphic::font::Font;
 use crate::memory::alloc::{AcpiHandler, KernelAllocator};
+use crate::block::cache::BlockCache;
 use crate::interrupt::interrupt_dispatcher::InterruptDispatcher;
 use crate::log::Logger;
 use crate::process::scheduler::Scheduler;
 use crate::process::thread::Thread;
 use alloc::boxed::Box;
-use core::fmt::Arguments;
+use alloc::vec::Vec;
+use core::fmt::Write;
 use core::panic::PanicInfo;
-use ::log::{Level, Log, Record};
+use core::sync::atomic::{AtomicBool, AtomicUsize};
+use core::sync::atomic::Ordering::Relaxed;
 use acpi::AcpiTables;
 use multiboot2::ModuleTag;
-use spin::{Mutex, Once, RwLock};
-use tar_no_std::TarArchiveRef;
+use spin::Once;
+use crate::sync::{Mutex, RwLock};
 use uefi::table::{Runtime, SystemTable};
 use x86_64::structures::gdt::GlobalDescriptorTable;
 use x86_64::structures::idt::InterruptDescriptorTable;
@@ -45,12 +55,25 @@ extern crate alloc;
 
 #[macro_use]
 pub mod device;
+pub mod bench;
+pub mod block;
 pub mod boot;
+pub mod cpu;
+pub mod debug;
+pub mod fs;
 pub mod interrupt;
 pub mod memory;
+pub mod net;
 pub mod log;
 pub mod syscall;
 pub mod process;
+pub mod random;
+pub mod smbios;
+pub mod symbols;
+pub mod sync;
+pub mod timer;
+#[cfg(feature = "test")]
+pub mod test;
 
 pub mod built_info {
     // The file has been placed there by the build script.
@@ -59,20 +82,22 @@ pub mod built_info {
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    if terminal_initialized() {
-        println!("Panic: {}", info);
-    } else {
-        let record = Record::builder()
-            .level(Level::Error)
-            .file(Some("panic"))
-            .args(*info.message().unwrap_or(&Arguments::new_const(&["A panic occurred!"])))
-            .build();
-
-        unsafe { logger().force_unlock() };
-        let log = logger().lock();
-        unsafe { logger().force_unlock() }; // log() also calls logger().lock()
-        log.log(&record);
-    }
+    let rbp = backtrace::frame_pointer();
+
+    #[cfg(feature = "test")]
+    let exit_code = if test::report_failure() { ExitCode::TestFailure } else { ExitCode::Panic };
+    #[cfg(not(feature = "test"))]
+    let exit_code = ExitCode::Panic;
+
+    let _ = writeln!(PanicWriter, "Panic: {}", info);
+    let _ = writeln!(PanicWriter, "Backtrace:");
+    backtrace::print(&mut PanicWriter, rbp);
+
+    // Give an attached debugger the chance to inspect the panicking thread
+    gdb::breakpoint();
+
+    // Let QEMU exit with a failure status, so that automated test runs can detect panics
+    qemu_cfg::debug_exit(exit_code);
 
     loop {}
 }
@@ -95,7 +120,8 @@ static TSS: Mutex<TaskStateSegment> = Mutex::new(TaskStateSegment::new());
 static IDT: Mutex<InterruptDescriptorTable> = Mutex::new(InterruptDescriptorTable::new());
 static EFI_SYSTEM_TABLE: Once<EfiSystemTable> = Once::new();
 static ACPI_TABLES: Once<Mutex<AcpiTables<AcpiHandler>>> = Once::new();
-static INIT_RAMDISK: Once<TarArchiveRef> = Once::new();
+static INIT_RAMDISK: Once<&'static [u8]> = Once::new();
+static BLOCK_CACHE: BlockCache = BlockCache::new(block::cache::DEFAULT_CAPACITY);
 
 #[global_allocator]
 static ALLOCATOR: KernelAllocator = KernelAllocator::new();
@@ -107,8 +133,13 @@ static APIC: Once<Apic> = Once::new();
 static TIMER: RwLock<Timer> = RwLock::new(Timer::new());
 static SPEAKER: Mutex<Speaker> = Mutex::new(Speaker::new());
 static SERIAL_PORT: Once<SerialPort> = Once::new();
-static TERMINAL: Once<LFBTerminal> = Once::new();
+static CONSOLES: Once<Vec<LFBTerminal>> = Once::new();
+static ACTIVE_CONSOLE: AtomicUsize = AtomicUsize::new(0);
+/// Cleared, while the compositor shows windows instead of the active console (see `set_consoles_visible()`).
+static CONSOLES_VISIBLE: AtomicBool = AtomicBool::new(true);
+static SERIAL_TERMINAL: Once<SerialTerminal> = Once::new();
 static PS2: Once<PS2> = Once::new();
+static PCI: Once<PciBus> = Once::new();
 
 pub fn init_efi_system_table(table: SystemTable<Runtime>) {
     EFI_SYSTEM_TABLE.call_once(|| EfiSystemTable::new(table));
@@ -132,7 +163,7 @@ pub fn init_apic() {
     APIC.call_once(|| Apic::new());
 }
 
-pub fn init_serial_port() {
+pub fn init_serial_port(speed: BaudRate) {
     let mut serial: Option<SerialPort> = None;
     if serial::check_port(ComPort::Com1) {
         serial = Some(SerialPort::new(ComPort::Com1));
@@ -145,30 +176,49 @@ pub fn init_serial_port() {
     }
 
     if serial.is_some() {
-        serial.as_mut().unwrap().init(128, BaudRate::Baud115200);
+        serial.as_mut().unwrap().init(128, speed);
         SERIAL_PORT.call_once(|| serial.unwrap());
     }
 }
 
 pub fn init_terminal(buffer: *mut u8, pitch: u32, width: u32, height: u32, bpp: u8) {
-    TERMINAL.call_once(|| LFBTerminal::new(buffer, pitch, width, height, bpp));
+    CONSOLES.call_once(|| (0..CONSOLE_COUNT).map(|console| LFBTerminal::new(console, buffer, pitch, width, height, bpp)).collect());
 
     scheduler().ready(Thread::new_kernel_thread(Box::new(|| {
-        let mut cursor_thread = CursorThread::new(&TERMINAL.get().unwrap());
+        let mut cursor_thread = CursorThread::new(CONSOLES.get().unwrap());
         cursor_thread.run();
     })));
 }
 
+/// Use the serial port instead of the framebuffer as terminal (needs an initialized serial port).
+pub fn init_serial_terminal() {
+    let serial = serial_port().expect("Trying to initialize serial terminal without serial port!");
+    SERIAL_TERMINAL.call_once(|| SerialTerminal::new(serial));
+}
+
 pub fn init_keyboard() {
     PS2.call_once(|| {
         let mut ps2 = PS2::new();
-        ps2.init_controller().unwrap_or_else(|err| panic!("Failed to initialize PS2 controller (Error: {:?})", err));
-        ps2.init_keyboard().unwrap_or_else(|err| panic!("Failed to initialize PS2 keyboard (Error: {:?})", err));
+        // Machines without PS/2 controller (or keyboard) can still be used with a USB keyboard (see `device::usb::hid`)
+        if let Err(err) = ps2.init_controller() {
+            ::log::warn!("Failed to initialize PS2 controller (Error: {:?})", err);
+            return ps2;
+        }
+        if let Err(err) = ps2.init_keyboard() {
+            ::log::warn!("No PS2 keyboard available (Error: {:?})", err);
+        }
+        if let Err(err) = ps2.init_mouse() {
+            ::log::warn!("No PS2 mouse available (Error: {:?})", err);
+        }
 
         return ps2;
     });
 }
 
+pub fn init_pci() {
+    PCI.call_once(|| PciBus::scan());
+}
+
 pub fn init_initrd(module: &ModuleTag) {
     INIT_RAMDISK.call_once(|| {
         let initrd_frames = PhysFrameRange {
@@ -177,13 +227,16 @@ pub fn init_initrd(module: &ModuleTag) {
         };
         unsafe { memory::physical::reserve(initrd_frames); }
 
-        let initrd_bytes = unsafe { core::slice::from_raw_parts(module.start_address() as *const u8, (module.end_address() - module.start_address()) as usize) };
-        return TarArchiveRef::new(initrd_bytes);
+        return unsafe { core::slice::from_raw_parts(module.start_address() as *const u8, (module.end_address() - module.start_address()) as usize) };
     });
 }
 
 pub fn terminal_initialized() -> bool {
-    return TERMINAL.get().is_some();
+    return CONSOLES.get().is_some() || SERIAL_TERMINAL.get().is_some();
+}
+
+pub fn serial_terminal_enabled() -> bool {
+    return SERIAL_TERMINAL.get().is_some();
 }
 
 pub fn gdt() -> &'static Mutex<GlobalDescriptorTable> {
@@ -209,8 +262,13 @@ pub fn efi_system_table() -> Option<&'static SystemTable<Runtime>> {
     };
 }
 
-pub fn initrd() -> &'static TarArchiveRef<'static> {
-    return &INIT_RAMDISK.get().expect("Trying to access initial ramdisk before initialization!");
+/// Contents of the initial ramdisk (a ustar archive), which is mounted as root filesystem during boot.
+pub fn initrd() -> &'static [u8] {
+    return INIT_RAMDISK.get().expect("Trying to access initial ramdisk before initialization!");
+}
+
+pub fn block_cache() -> &'static BlockCache {
+    return &BLOCK_CACHE;
 }
 
 pub fn allocator() -> &'static KernelAllocator {
@@ -247,14 +305,85 @@ pub fn serial_port() -> Option<&'static SerialPort> {
     return SERIAL_PORT.get();
 }
 
+/// The first console, which is used by the kernel.
 pub fn terminal() -> &'static dyn Terminal {
-    return TERMINAL.get().expect("Trying to access terminal before initialization!");
+    return console(0);
+}
+
+/// The virtual console with the number `index` (see `CONSOLE_COUNT`), or the serial terminal, if it is enabled.
+/// Further indices refer to the terminals of remote shell sessions (see `terminal::REMOTE_CONSOLE_COUNT`).
+pub fn console(index: usize) -> &'static dyn Terminal {
+    if index >= CONSOLE_COUNT {
+        return telnet::terminal(index - CONSOLE_COUNT);
+    }
+
+    if let Some(serial_terminal) = SERIAL_TERMINAL.get() {
+        return serial_terminal;
+    }
+
+    return &CONSOLES.get().expect("Trying to access terminal before initialization!")[index];
+}
+
+pub fn active_console() -> usize {
+    return ACTIVE_CONSOLE.load(Relaxed);
+}
+
+/// Show the virtual console with the number `index` and direct keyboard input to it (does nothing for the serial terminal).
+pub fn switch_console(index: usize) {
+    let consoles = match CONSOLES.get() {
+        Some(consoles) if index < consoles.len() && !serial_terminal_enabled() => consoles,
+        _ => return
+    };
+
+    // While windows are shown, the new console is only drawn, once they are gone
+    let previous = ACTIVE_CONSOLE.swap(index, Relaxed);
+    if previous != index {
+        consoles[previous].set_active(false);
+        consoles[index].set_active(CONSOLES_VISIBLE.load(Relaxed));
+    }
+}
+
+/// Scroll the active virtual console `lines` back into its scrollback buffer (or forward for negative values).
+pub fn scroll_console(lines: isize) {
+    if let Some(consoles) = CONSOLES.get() {
+        if !serial_terminal_enabled() {
+            consoles[active_console()].scroll_back(lines);
+        }
+    }
+}
+
+/// Stop drawing the active virtual console (e.g. while the compositor shows windows) or redraw it.
+pub fn set_consoles_visible(visible: bool) {
+    CONSOLES_VISIBLE.store(visible, Relaxed);
+    if let Some(consoles) = CONSOLES.get() {
+        if !serial_terminal_enabled() {
+            consoles[active_console()].set_active(visible);
+        }
+    }
+}
+
+/// Switch all virtual consoles to `font` (does nothing for the serial terminal).
+pub fn set_console_font(font: Font) {
+    if let Some(consoles) = CONSOLES.get() {
+        if !serial_terminal_enabled() {
+            consoles.iter().for_each(|console| console.set_font(font.clone()));
+        }
+    }
 }
 
 pub fn ps2_devices() -> &'static PS2 {
     return PS2.get().expect("Trying to access keyboard before initialization!");
 }
 
+/// Access the PS/2 devices from drivers, which may be initialized earlier (e.g. USB keyboards).
+pub fn try_ps2_devices() -> Option<&'static PS2> {
+    return PS2.get();
+}
+
+pub fn pci_bus() -> &'static PciBus {
+    return PCI.get().expect("Trying to access PCI bus before initialization!");
+}
+
 #[no_mangle]
 pub extern "C" fn tss_set_rsp0(rsp0: u64) {
     tss().lock().privilege_stack_table[0] = VirtAddr::new(rsp0);
@@ -1,31 +1,50 @@
-use crate::device::serial;
-use crate::device::serial::ComPort;
-use crate::device::serial::SerialPort;
-use crate::{allocator, logger, timer};
+use crate::device::serial::EarlySerialWriter;
+use crate::{logger, timer};
 use graphic::ansi;
 use stream::OutputStream;
 use alloc::boxed::Box;
 use alloc::format;
-use alloc::string::ToString;
+use alloc::string::{String, ToString};
 use alloc::vec::Vec;
+use core::fmt;
+use core::fmt::Write;
 use core::ops::Deref;
 use core::ptr;
-use log::{Level, LevelFilter, Metadata, Record, SetLoggerError};
+use core::str::FromStr;
+use log::{Level, LevelFilter, Metadata, ParseLevelError, Record, SetLoggerError};
 use crate::built_info;
 
+/// Size of the in-memory kernel log. Once it is full, the oldest messages are overwritten.
+const LOG_BUFFER_SIZE: usize = 0x10000;
+
+/// Prefix of all module paths inside the kernel, which is omitted in level filters (e.g. 'memory::virtual').
+const TARGET_PREFIX: &str = "kernel::";
+
 pub struct Logger {
-    level: Level,
+    level: LevelFilter,
+    filters: Vec<(String, LevelFilter)>,
     streams: Vec<Box<&'static dyn OutputStream>>,
-    serial: Option<SerialPort>,
+    buffer: LogBuffer,
+}
+
+/// Ring buffer, keeping all log messages (without colors) since boot, so that they can be read later via `dmesg`.
+/// It does not need the heap, so that early boot messages are recorded as well.
+struct LogBuffer {
+    data: [u8; LOG_BUFFER_SIZE],
+    start: usize,
+    len: usize,
 }
 
 impl log::Log for Logger {
+    /// Only compares against the global maximum level (an atomic maintained by `set_level()`), instead of locking the logger,
+    /// which may already be locked by the caller. Messages of modules with a lower level are filtered out by `log()`.
     fn enabled(&self, metadata: &Metadata) -> bool {
-        return metadata.level() <= self.level;
+        return metadata.level() <= log::max_level();
     }
 
     fn log(&self, record: &Record) {
-        if !self.enabled(record.metadata()) {
+        let mut logger = logger().lock();
+        if !logger.is_enabled(record.metadata()) {
             return;
         }
 
@@ -33,33 +52,17 @@ impl log::Log for Logger {
         let file = record.file().unwrap_or("unknown").split('/').rev().next().unwrap_or("unknown");
         let line = record.line().unwrap_or(0);
 
-        let mut logger = logger().lock();
-        if logger.streams.is_empty() {
-            if let Some(serial) = logger.serial.as_mut() {
-                serial.write_str(ansi::FOREGROUND_CYAN);
-                serial.write_str("[0.000]");
-                serial.write_str(ansi_color(level));
-                serial.write_str("[");
-                serial.write_str(level_token(level));
-                serial.write_str("]");
-                serial.write_str(ansi::FOREGROUND_DEFAULT);
-                serial.write_str("[");
-                serial.write_str(file);
-                serial.write_str("] ");
-
-                if allocator().is_initialized() {
-                    serial.write_str(record.args().to_string().as_str());
-                } else {
-                    serial.write_str(record.args().as_str().unwrap_or_else(|| { "Formatted messages are not supported before heap initialization!" }));
-                }
+        let systime = timer().try_read().map_or(0, |timer| timer.systime_ms());
+        let seconds = systime / 1000;
+        let fraction = systime % 1000;
 
-                serial.write_str("\n");
-            }
-        } else {
-            let systime = timer().read().systime_ms();
-            let seconds = systime / 1000;
-            let fraction = systime % 1000;
+        let _ = write!(logger.buffer, "[{}.{:0>3}][{}][{}@{:0>3}] {}\n", seconds, fraction, level_token(level), file, line, record.args());
 
+        if logger.streams.is_empty() {
+            // No output streams registered yet (e.g. during early boot) -> Write to serial port without allocating memory
+            let _ = write!(EarlySerialWriter, "{}[{}.{:0>3}]{}[{}]{}[{}@{:0>3}] {}\n", ansi::FOREGROUND_CYAN, seconds, fraction,
+                           ansi_color(level), level_token(level), ansi::FOREGROUND_DEFAULT, file, line, record.args());
+        } else {
             let string = format!("{}[{}.{:0>3}]{}[{}]{}[{}@{:0>3}] {}\n", ansi::FOREGROUND_CYAN, seconds, fraction,
                                  ansi_color(level), level_token(level),ansi::FOREGROUND_DEFAULT, file, line, record.args());
 
@@ -75,9 +78,10 @@ impl log::Log for Logger {
 impl Logger {
     pub const fn new() -> Self {
         Self {
-            level: Level::Info,
+            level: LevelFilter::Info,
+            filters: Vec::new(),
             streams: Vec::new(),
-            serial: None,
+            buffer: LogBuffer::new(),
         }
     }
 
@@ -87,28 +91,78 @@ impl Logger {
         } // The caller needed to call logger().lock() in order to call init()
         let mut logger = logger().lock();
 
-        if serial::check_port(ComPort::Com1) {
-            logger.serial = Some(SerialPort::new(ComPort::Com1))
-        } else if serial::check_port(ComPort::Com2) {
-            logger.serial = Some(SerialPort::new(ComPort::Com2))
-        } else if serial::check_port(ComPort::Com3) {
-            logger.serial = Some(SerialPort::new(ComPort::Com3))
-        } else if serial::check_port(ComPort::Com4) {
-            logger.serial = Some(SerialPort::new(ComPort::Com4))
+        if built_info::PROFILE == "debug" {
+            logger.level = LevelFilter::Debug;
         }
 
-        if logger.serial.is_some() {
-            logger.serial.as_mut().unwrap().init_write_only();
+        unsafe {
+            let logger_ref = ptr::from_ref(logger.deref()).as_ref().unwrap();
+            return log::set_logger(logger_ref).map(|()| log::set_max_level(logger.level));
         }
+    }
 
-        if built_info::PROFILE == "debug" {
-            logger.level = Level::Debug;
+    /// Set the log level for all modules below `target` (e.g. 'memory::virtual') or the default level, if `target` is None.
+    /// Needs the heap to be initialized, if a target is given.
+    pub fn set_level(&mut self, target: Option<&str>, level: LevelFilter) {
+        match target {
+            Some(target) => {
+                let target = target.strip_prefix(TARGET_PREFIX).unwrap_or(target);
+                match self.filters.iter_mut().find(|(filter, _)| filter == target) {
+                    Some(filter) => filter.1 = level,
+                    None => self.filters.push((target.to_string(), level))
+                }
+            }
+            None => self.level = level
         }
 
-        unsafe {
-            let logger_ref = ptr::from_ref(logger.deref()).as_ref().unwrap();
-            return log::set_logger(logger_ref).map(|()| log::set_max_level(LevelFilter::Debug));
+        // Messages above the global maximum level are discarded by the log macros, before even formatting them
+        let max_level = self.filters.iter().map(|(_, level)| *level).fold(self.level, |max, level| max.max(level));
+        log::set_max_level(max_level);
+    }
+
+    /// Apply a comma separated list of level settings (e.g. 'info,memory::virtual=trace').
+    /// Entries without a target change the default level. Nothing is changed, if one of the entries is invalid.
+    pub fn configure(&mut self, spec: &str) -> Result<(), ParseLevelError> {
+        let mut settings = Vec::new();
+        for entry in spec.split(',').map(|entry| entry.trim()).filter(|entry| !entry.is_empty()) {
+            match entry.split_once('=') {
+                Some((target, level)) => settings.push((Some(target.trim()), LevelFilter::from_str(level.trim())?)),
+                None => settings.push((None, LevelFilter::from_str(entry)?))
+            }
+        }
+
+        for (target, level) in settings {
+            self.set_level(target, level);
         }
+
+        Ok(())
+    }
+
+    /// Get the current level settings in the format accepted by `configure()`.
+    pub fn level_settings(&self) -> String {
+        let mut settings = self.level.as_str().to_lowercase();
+        for (target, level) in &self.filters {
+            settings.push_str(&format!(",{}={}", target, level.as_str().to_lowercase()));
+        }
+
+        return settings;
+    }
+
+    /// Check the message level against the most specific filter matching the message's module.
+    fn is_enabled(&self, metadata: &Metadata) -> bool {
+        let target = metadata.target().strip_prefix(TARGET_PREFIX).unwrap_or(metadata.target());
+        let mut level = self.level;
+        let mut match_len = 0;
+
+        for (filter, filter_level) in &self.filters {
+            let matches = target.starts_with(filter.as_str()) && (target.len() == filter.len() || target[filter.len()..].starts_with("::"));
+            if matches && filter.len() > match_len {
+                level = *filter_level;
+                match_len = filter.len();
+            }
+        }
+
+        return metadata.level() <= level;
     }
 
     pub fn register(&mut self, stream: &'static dyn OutputStream) {
@@ -120,6 +174,55 @@ impl Logger {
             !ptr::addr_eq(ptr::from_ref(*element.as_ref()), ptr::from_ref(stream))
         });
     }
+
+    /// Get all recorded log messages (oldest first).
+    pub fn kernel_log(&self) -> String {
+        return self.buffer.contents();
+    }
+}
+
+impl LogBuffer {
+    const fn new() -> Self {
+        Self { data: [0; LOG_BUFFER_SIZE], start: 0, len: 0 }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len < LOG_BUFFER_SIZE {
+            self.data[(self.start + self.len) % LOG_BUFFER_SIZE] = byte;
+            self.len += 1;
+        } else {
+            // Buffer is full -> Overwrite oldest byte
+            self.data[self.start] = byte;
+            self.start = (self.start + 1) % LOG_BUFFER_SIZE;
+        }
+    }
+
+    fn contents(&self) -> String {
+        let mut bytes = Vec::with_capacity(self.len);
+        for i in 0..self.len {
+            bytes.push(self.data[(self.start + i) % LOG_BUFFER_SIZE]);
+        }
+
+        // If old messages have been overwritten, the first line is probably incomplete
+        let mut content = bytes.as_slice();
+        if self.len == LOG_BUFFER_SIZE {
+            if let Some(line_end) = content.iter().position(|byte| *byte == b'\n') {
+                content = &content[line_end + 1..];
+            }
+        }
+
+        return String::from_utf8_lossy(content).into_owned();
+    }
+}
+
+impl Write for LogBuffer {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        for byte in string.bytes() {
+            self.push(byte);
+        }
+
+        Ok(())
+    }
 }
 
 fn ansi_color(level: Level) -> &'static str {
@@ -2,6 +2,7 @@ use acpi::PhysicalMapping;
 use core::alloc::{AllocError, Allocator, GlobalAlloc, Layout};
 use core::ptr::NonNull;
 use linked_list_allocator::LockedHeap;
+use x86_64::instructions::interrupts;
 use x86_64::PhysAddr;
 use x86_64::structures::paging::frame::PhysFrameRange;
 use x86_64::structures::paging::PhysFrame;
@@ -29,6 +30,20 @@ impl KernelAllocator {
     pub fn is_initialized(&self) -> bool {
         return self.heap.lock().size() > 0;
     }
+
+    pub fn is_locked(&self) -> bool {
+        return self.heap.is_locked();
+    }
+
+    /// Size of the kernel heap in bytes.
+    pub fn size(&self) -> usize {
+        return interrupts::without_interrupts(|| self.heap.lock().size());
+    }
+
+    /// Number of bytes currently allocated on the kernel heap.
+    pub fn used(&self) -> usize {
+        return interrupts::without_interrupts(|| self.heap.lock().used());
+    }
 }
 
 unsafe impl Allocator for KernelAllocator {
@@ -37,7 +52,8 @@ unsafe impl Allocator for KernelAllocator {
             return Ok(NonNull::slice_from_raw_parts(layout.dangling(), 0));
         }
 
-        match self.heap.lock().allocate_first_fit(layout) {
+        // Interrupts are disabled while the heap is locked, since interrupt handlers (e.g. kernel timer callbacks) may allocate memory
+        match interrupts::without_interrupts(|| self.heap.lock().allocate_first_fit(layout)) {
             Ok(ptr) => Ok(NonNull::slice_from_raw_parts(ptr, layout.size())),
             Err(()) => Err(AllocError),
         }
@@ -45,21 +61,20 @@ unsafe impl Allocator for KernelAllocator {
 
     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
         if layout.size() != 0 {
-            self.heap.lock().deallocate(ptr, layout);
+            interrupts::without_interrupts(|| self.heap.lock().deallocate(ptr, layout));
         }
     }
 }
 
 unsafe impl GlobalAlloc for KernelAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        return self.heap.lock()
-            .allocate_first_fit(layout)
+        return interrupts::without_interrupts(|| self.heap.lock().allocate_first_fit(layout))
             .ok()
             .map_or(core::ptr::null_mut(), |allocation| allocation.as_ptr());
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        self.heap.lock().deallocate(NonNull::new_unchecked(ptr), layout);
+        interrupts::without_interrupts(|| self.heap.lock().deallocate(NonNull::new_unchecked(ptr), layout));
     }
 }
 
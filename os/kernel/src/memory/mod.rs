@@ -1,11 +1,14 @@
 pub mod alloc;
+pub mod pat;
 pub mod physical;
 pub mod r#virtual;
 
 #[derive(Clone, Copy)]
 pub enum MemorySpace {
     Kernel,
-    User
+    User,
+    /// Physical memory of a device, mapped into user space. Unlike user memory, its page frames are not freed on unmapping.
+    Device
 }
 
 pub const PAGE_SIZE: usize = 0x1000;
\ No newline at end of file
@@ -0,0 +1,25 @@
+use raw_cpuid::CpuId;
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::paging::PageTableFlags;
+
+const IA32_PAT: u32 = 0x277;
+const WRITE_COMBINING: u64 = 0x01;
+
+/// Page table flags selecting write-combining (see `init()`). Writes are collected and sent to memory in bursts,
+/// which is much faster than uncached writes for memory, which is only written (e.g. framebuffers).
+/// Without PAT support, these flags select write-through, which is still correct.
+pub const WRITE_COMBINING_FLAGS: PageTableFlags = PageTableFlags::WRITE_THROUGH;
+
+/// Replace the write-through entry of the page attribute table (PAT1, selected by `PageTableFlags::WRITE_THROUGH`)
+/// with write-combining. Must be called before mapping memory with `WRITE_COMBINING_FLAGS`.
+pub fn init() {
+    if !CpuId::new().get_feature_info().is_some_and(|info| info.has_pat()) {
+        return;
+    }
+
+    let mut pat = Msr::new(IA32_PAT);
+    unsafe {
+        let entries = pat.read();
+        pat.write((entries & !(0xff << 8)) | (WRITE_COMBINING << 8));
+    }
+}
@@ -1,9 +1,12 @@
+use alloc::collections::BTreeMap;
 use alloc::format;
 use alloc::string::String;
 use core::cell::{Cell};
 use core::fmt::{Debug, Formatter};
 use core::ptr;
-use spin::{Mutex};
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering::Relaxed;
+use crate::sync::Mutex;
 use spin::once::Once;
 use x86_64::PhysAddr;
 use x86_64::structures::paging::frame::PhysFrameRange;
@@ -12,6 +15,21 @@ use crate::memory::PAGE_SIZE;
 
 static PAGE_FRAME_ALLOCATOR: Mutex<PageFrameListAllocator> = Mutex::new(PageFrameListAllocator::new());
 static PHYS_LIMIT: Once<Mutex<Cell<PhysFrame>>> = Once::new();
+static TOTAL_FRAME_COUNT: AtomicUsize = AtomicUsize::new(0);
+/// Number of additional references to page frames, which are mapped more than once (e.g. the code of shared libraries).
+/// Frames without an entry have a single owner.
+static SHARED_FRAMES: Mutex<BTreeMap<PhysFrame, usize>> = Mutex::new(BTreeMap::new());
+
+/// With the 'frame_debug' feature (enabled in debug builds), allocated page frames are tracked in a bitmap (one bit per frame), so that freeing
+/// a frame, which is not allocated (e.g. a double free), is detected instead of corrupting the free list.
+/// Only the frames below `MAX_TRACKED_FRAMES` (4 GiB) are tracked.
+#[cfg(any(feature = "frame_debug", debug_assertions))]
+const MAX_TRACKED_FRAMES: usize = 1 << 20;
+#[cfg(any(feature = "frame_debug", debug_assertions))]
+static ALLOCATED_FRAMES: Mutex<[u64; MAX_TRACKED_FRAMES / 64]> = Mutex::new([0; MAX_TRACKED_FRAMES / 64]);
+/// Freed page frames are filled with this byte, so that using them after freeing them is likely to crash early.
+#[cfg(any(feature = "frame_debug", debug_assertions))]
+const POISON: u8 = 0xa5;
 
 /// Insert an available memory regions obtained during the boot process.
 pub unsafe fn insert(mut region: PhysFrameRange) {
@@ -32,18 +50,92 @@ pub unsafe fn insert(mut region: PhysFrameRange) {
         current_limit.swap(&Cell::new(region.end));
     }
 
-    free(region);
+    TOTAL_FRAME_COUNT.fetch_add((region.end - region.start) as usize, Relaxed);
+    PAGE_FRAME_ALLOCATOR.lock().free_block(region); // Not allocated before, so `free()` would report a double free
 }
 
 /// Allocate `frame_count` contiguous page frames in either kernel or user space, depending on `space`.
 pub fn alloc(frame_count: usize) -> PhysFrameRange {
-    PAGE_FRAME_ALLOCATOR.lock().alloc_block(frame_count)
+    crate::trace_function!();
+    let mut allocator = PAGE_FRAME_ALLOCATOR.lock();
+    let frames = allocator.alloc_block(frame_count);
+
+    #[cfg(any(feature = "frame_debug", debug_assertions))]
+    for frame in tracked(frames) {
+        if set_allocated(frame, true) {
+            panic!("PageFrameAllocator: Frame [0x{:x}] has been allocated twice (The free list is corrupted)!", frame.start_address().as_u64());
+        }
+    }
+
+    return frames;
 }
 
 /// Free `frame_count` contiguous page frames starting at `addr`.
 /// Unsafe because invalid parameters may break the list allocator.
+/// With the 'frame_debug' feature (or in debug builds), freeing frames, which are not allocated, panics with the location of the caller
+/// and freed frames are poisoned (see `POISON`).
+#[track_caller]
 pub unsafe fn free(frames: PhysFrameRange) {
-    PAGE_FRAME_ALLOCATOR.lock().free_block(frames);
+    crate::trace_function!();
+    let mut allocator = PAGE_FRAME_ALLOCATOR.lock();
+
+    #[cfg(any(feature = "frame_debug", debug_assertions))]
+    {
+        for frame in tracked(frames) {
+            if !set_allocated(frame, false) {
+                panic!("PageFrameAllocator: Freeing frame [0x{:x}] of [0x{:x} - 0x{:x}], which is not allocated (Double free?), called from [{}]!",
+                       frame.start_address().as_u64(), frames.start.start_address().as_u64(), frames.end.start_address().as_u64(), core::panic::Location::caller());
+            }
+        }
+
+        ptr::write_bytes(frames.start.start_address().as_u64() as *mut u8, POISON, (frames.end - frames.start) as usize * PAGE_SIZE);
+    }
+
+    allocator.free_block(frames);
+}
+
+/// The frames of `frames`, which are tracked in `ALLOCATED_FRAMES`.
+#[cfg(any(feature = "frame_debug", debug_assertions))]
+fn tracked(frames: PhysFrameRange) -> PhysFrameRange {
+    let limit = PhysFrame::containing_address(PhysAddr::new((MAX_TRACKED_FRAMES * PAGE_SIZE) as u64));
+    return PhysFrameRange { start: frames.start.min(limit), end: frames.end.min(limit) };
+}
+
+/// Mark `frame` as allocated or free and return, if it has been allocated before.
+#[cfg(any(feature = "frame_debug", debug_assertions))]
+fn set_allocated(frame: PhysFrame, allocated: bool) -> bool {
+    let index = (frame.start_address().as_u64() as usize) / PAGE_SIZE;
+    let mut bitmap = ALLOCATED_FRAMES.lock();
+    let word = &mut bitmap[index / 64];
+    let was_allocated = *word & (1 << (index % 64)) != 0;
+
+    if allocated {
+        *word |= 1 << (index % 64);
+    } else {
+        *word &= !(1 << (index % 64));
+    }
+
+    return was_allocated;
+}
+
+/// Add a reference to an allocated page frame, so that it is only freed by the last call to `release()`.
+pub fn share(frame: PhysFrame) {
+    *SHARED_FRAMES.lock().entry(frame).or_insert(0) += 1;
+}
+
+/// Drop a reference to a page frame and free it, if it is not shared anymore.
+/// Unsafe because the frame must not be used by the caller afterward.
+#[track_caller]
+pub unsafe fn release(frame: PhysFrame) {
+    let mut shared_frames = SHARED_FRAMES.lock();
+    match shared_frames.get_mut(&frame) {
+        Some(1) => { shared_frames.remove(&frame); }
+        Some(references) => *references -= 1,
+        None => {
+            drop(shared_frames);
+            free(PhysFrameRange { start: frame, end: frame + 1 });
+        }
+    }
 }
 
 /// Permanently reserve a block of free memory.
@@ -56,6 +148,16 @@ pub fn phys_limit() -> PhysFrame {
     return PHYS_LIMIT.get().unwrap().lock().get();
 }
 
+/// Get the amount of currently available page frames.
+pub fn free_frame_count() -> usize {
+    return PAGE_FRAME_ALLOCATOR.lock().free_frame_count();
+}
+
+/// Get the amount of page frames, that have been inserted during the boot process (including reserved ones).
+pub fn total_frame_count() -> usize {
+    return TOTAL_FRAME_COUNT.load(Relaxed);
+}
+
 /// Get a dump of the current free list.
 pub fn dump() -> String {
     format!("{:?}", PAGE_FRAME_ALLOCATOR.lock())
@@ -110,6 +212,19 @@ impl PageFrameListAllocator {
         Self { head: PageFrameNode::new(0) }
     }
 
+    /// Sum up the frame counts of all free blocks.
+    fn free_frame_count(&self) -> usize {
+        let mut available: usize = 0;
+
+        let mut current = &self.head;
+        while let Some(block) = &current.next {
+            available += block.frame_count;
+            current = current.next.as_ref().unwrap();
+        }
+
+        return available;
+    }
+
     /// Insert a new block, sorted ascending by its memory address.
     unsafe fn insert(&mut self, frames: PhysFrameRange) {
         let mut new_block = PageFrameNode::new((frames.end - frames.start) as usize);
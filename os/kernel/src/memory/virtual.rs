@@ -1,17 +1,29 @@
 use alloc::sync::Arc;
 use core::cmp::min;
-use core::ptr;
-use log::debug;
-use spin::RwLock;
+use core::{mem, ptr};
+use core::sync::atomic::{AtomicU64, AtomicUsize};
+use core::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed};
+use log::trace;
+use crate::sync::RwLock;
 use x86_64::structures::paging::{Page, PageTable, PageTableFlags, PageTableIndex, PhysFrame};
+use x86_64::structures::paging::page_table::PageTableEntry;
 use x86_64::{PhysAddr, VirtAddr};
-use x86_64::registers::control::{Cr3, Cr3Flags};
+use x86_64::instructions::interrupts;
+use x86_64::registers::control::{Cr3, Cr3Flags, Cr4, Cr4Flags};
 use x86_64::structures::paging::frame::PhysFrameRange;
 use x86_64::structures::paging::page::PageRange;
 use crate::memory::{MemorySpace, PAGE_SIZE, physical};
 use crate::memory::physical::phys_limit;
 use crate::process::process::kernel_process;
 
+/// Page table entries are only changed atomically, so that mapping, unmapping and translating pages only needs `root_table`
+/// as a reader and threads of the same process can do so concurrently (e.g. when handling page faults):
+/// - Missing page tables are installed with a compare-exchange (see `next_level_table()`).
+/// - Unmapping clears entries with an atomic swap, so that each page frame is released exactly once.
+/// - Page tables are only freed, while holding `root_table` as a writer (see `free_empty_tables()`).
+///   So a table reached while holding it as a reader stays valid, until the lock is released.
+///
+/// Mapping the same pages concurrently is still up to the caller (processes serialize it via their memory areas).
 pub struct AddressSpace {
     root_table: RwLock<*mut PageTable>,
     depth: usize
@@ -23,23 +35,80 @@ pub struct VirtualMemoryArea {
     typ: VmaType
 }
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum VmaType {
-    Code, Heap, Stack
+    Code, Heap, Stack,
+    /// Segments of a shared library (see `process::loader`). Its read-only pages may be shared with other processes.
+    Library,
+    /// Physical memory of a device (e.g. video memory), mapped via `sys_map_file()`. It is not freed, when the process exits.
+    Device
 }
 
+/// Available page table bit, marking level 1 entries, whose page frame belongs to the address space (user memory).
+/// These frames are released, when the page is unmapped or the address space is dropped,
+/// while identity mapped kernel memory and device memory are left alone.
+const OWNED_FRAME: PageTableFlags = PageTableFlags::BIT_9;
+
+/// Page tables are accessed through the mapping of all physical memory at `offset`, instead of dereferencing their
+/// physical addresses. So the page table code does not depend on physical memory being identity mapped.
+#[derive(Copy, Clone)]
+pub struct PageTableWalker {
+    offset: u64
+}
+
+/// Virtual address, at which all physical memory is mapped (0, as long as the kernel identity maps physical memory).
+pub const PHYSICAL_MEMORY_OFFSET: u64 = 0;
+const PAGE_TABLES: PageTableWalker = PageTableWalker::new(PHYSICAL_MEMORY_OFFSET);
+
 unsafe impl Send for AddressSpace {}
 unsafe impl Sync for AddressSpace {}
 
+/// Number of page table levels of all address spaces (5 with LA57, see `init_paging()`).
+static PAGING_DEPTH: AtomicUsize = AtomicUsize::new(4);
+
+extern "C" {
+    /// Switch between 4-level and 5-level paging and load `root_table` (see 'boot.asm').
+    fn switch_paging_levels(root_table: u64, five_levels: u32);
+}
+
+/// Decide on the number of page table levels, before the kernel address space is created.
+/// Virtual addresses stay 48-bit canonical addresses with 5 levels as well (`VirtAddr` only accepts these),
+/// so only entry 0 (lower half) and entry 511 (higher half, due to sign extension) of the level 5 table are used.
+pub fn init_paging(five_levels: bool) {
+    PAGING_DEPTH.store(if five_levels { 5 } else { 4 }, Relaxed);
+}
+
+pub fn paging_depth() -> usize {
+    return PAGING_DEPTH.load(Relaxed);
+}
+
+/// Load the kernel address space for the first time. If the firmware has set up paging with a different number of levels,
+/// paging is reconfigured on the fly, which works in both directions.
+pub fn load_kernel_address_space(address_space: &AddressSpace) {
+    let five_levels = address_space.depth == 5;
+    if Cr4::read().contains(Cr4Flags::L5_PAGING) == five_levels {
+        address_space.load();
+        return;
+    }
+
+    // Paging is disabled while switching, so the root table, the switching code and the stack are accessed by their physical addresses in 32-bit mode
+    let root_table = address_space.page_table_address().as_u64();
+    let stack = ptr::addr_of!(root_table) as u64;
+    assert!(root_table < 0x1_0000_0000, "AddressSpace: Root table of the kernel must be located below 4 GiB to switch paging levels!");
+    assert!((switch_paging_levels as *const () as u64) < 0x1_0000_0000, "AddressSpace: Kernel code must be located below 4 GiB to switch paging levels!");
+    assert!(stack < 0x1_0000_0000, "AddressSpace: Kernel stack must be located below 4 GiB to switch paging levels!");
+    interrupts::without_interrupts(|| unsafe { switch_paging_levels(root_table, five_levels as u32) });
+}
+
 pub fn create_address_space() -> Arc<AddressSpace> {
-    debug!("Page frame allocator before address space creation:\n{}", physical::dump());
+    trace!("Page frame allocator before address space creation:\n{}", physical::dump());
     match kernel_process() {
         Some(kernel_process) => { // Create user address space
             let kernel_space = AddressSpace::from_other(&kernel_process.address_space());
             Arc::new(kernel_space)
         }
         None => { // Create kernel address space
-            let address_space = AddressSpace::new(4);
+            let address_space = AddressSpace::new(paging_depth());
             let max_phys_addr = phys_limit().start_address();
             let range = PageRange { start: Page::containing_address(VirtAddr::zero()), end: Page::containing_address(VirtAddr::new(max_phys_addr.as_u64())) };
 
@@ -60,7 +129,7 @@ impl Drop for AddressSpace {
         let root_table = unsafe { root_table_guard.as_mut().unwrap() };
 
         AddressSpace::drop_table(root_table, depth);
-        debug!("Page frame allocator after address space drop:\n{}", physical::dump());
+        trace!("Page frame allocator after address space drop:\n{}", physical::dump());
     }
 }
 
@@ -101,13 +170,36 @@ impl VirtualMemoryArea {
     }
 }
 
+impl PageTableWalker {
+    pub const fn new(offset: u64) -> Self {
+        Self { offset }
+    }
+
+    /// Access the page table at `addr` for the lifetime chosen by the caller.
+    /// Unsafe because the caller must make sure, that `addr` points to a page table, which is not freed while it is accessed,
+    /// and that changes are made atomically, as long as other threads may access it (see `AddressSpace`).
+    pub unsafe fn table<'a>(&self, addr: PhysAddr) -> &'a PageTable {
+        return unsafe { ((addr.as_u64() + self.offset) as *const PageTable).as_ref().unwrap() };
+    }
+
+    /// Access the page table at `addr` exclusively (e.g. a new table or while holding `AddressSpace::root_table` as a writer).
+    /// Unsafe for the same reasons as `table()`, and because no other reference to the table may exist at the same time.
+    pub unsafe fn table_mut<'a>(&self, addr: PhysAddr) -> &'a mut PageTable {
+        return unsafe { ((addr.as_u64() + self.offset) as *mut PageTable).as_mut().unwrap() };
+    }
+
+    pub fn physical_address(&self, table: *const PageTable) -> PhysAddr {
+        return PhysAddr::new(table as u64 - self.offset);
+    }
+}
+
 impl AddressSpace {
     pub fn new(depth: usize) -> Self {
         let table_addr = physical::alloc(1).start;
-        let root_table = table_addr.start_address().as_u64() as *mut PageTable;
-        unsafe { root_table.as_mut().unwrap().zero(); }
+        let root_table = unsafe { PAGE_TABLES.table_mut(table_addr.start_address()) };
+        root_table.zero();
 
-        Self { root_table: RwLock::new(root_table), depth }
+        Self { root_table: RwLock::new(ptr::from_mut(root_table)), depth }
     }
 
     pub fn from_other(other: &AddressSpace) -> Self {
@@ -134,240 +226,269 @@ impl AddressSpace {
         // We cannot use the lock here, because this function is called by the scheduler.
         // This is still safe, since we only return an address and not a reference.
         let root_table = unsafe { self.root_table.as_mut_ptr().read() };
-        PhysAddr::new(root_table as u64)
+        PAGE_TABLES.physical_address(root_table)
     }
 
     pub fn map(&self, pages: PageRange, space: MemorySpace, flags: PageTableFlags) {
+        crate::trace_function!();
         let depth = self.depth;
-        let root_table_guard = self.root_table.write();
-        let root_table = unsafe { root_table_guard.as_mut().unwrap() };
+        let root_table_guard = self.root_table.read();
+        let root_table = unsafe { root_table_guard.as_ref().unwrap() };
         let frames = PhysFrameRange { start: PhysFrame::from_start_address(PhysAddr::zero()).unwrap(), end: PhysFrame::from_start_address(PhysAddr::zero()).unwrap() };
 
         AddressSpace::map_in_table(root_table, frames, pages, space, flags, depth);
     }
 
     pub fn map_physical(&self, frames: PhysFrameRange, pages: PageRange, space: MemorySpace, flags: PageTableFlags) {
+        crate::trace_function!();
         let depth = self.depth;
-        let root_table_guard = self.root_table.write();
-        let root_table = unsafe { root_table_guard.as_mut().unwrap() };
+        let root_table_guard = self.root_table.read();
+        let root_table = unsafe { root_table_guard.as_ref().unwrap() };
 
         assert_eq!(frames.end - frames.start, pages.end - pages.start);
         AddressSpace::map_in_table(root_table, frames, pages, space, flags, depth);
     }
 
+    /// Map each page to its frame with its own flags (e.g. the discontiguous frames of an ELF file).
+    /// The page tables are walked from the root only once for consecutive pages sharing a level 1 table,
+    /// instead of once per page as with `map_physical()`.
+    pub fn map_iter(&self, space: MemorySpace, mappings: impl Iterator<Item = (PhysFrame, Page, PageTableFlags)>) {
+        crate::trace_function!();
+        let depth = self.depth;
+        let root_table_guard = self.root_table.read();
+        let root_table = unsafe { root_table_guard.as_ref().unwrap() };
+        let mut current_table: Option<(u64, &PageTable)> = None; // Level 1 table and the number of the 2 MiB area it covers
+
+        for (frame, page, flags) in mappings {
+            let area = page.start_address().as_u64() / (PAGE_SIZE as u64 * 512);
+            let table = match current_table {
+                Some((current_area, table)) if current_area == area => table,
+                _ => {
+                    let table = AddressSpace::level_one_table(root_table, page, flags, depth);
+                    current_table = Some((area, table));
+                    table
+                }
+            };
+
+            let entry = &table[page_table_index(page.start_address(), 1)];
+            match space {
+                MemorySpace::User => store_entry(entry, frame.start_address(), flags | OWNED_FRAME),
+                MemorySpace::Kernel | MemorySpace::Device => store_entry(entry, frame.start_address(), flags)
+            }
+        }
+    }
+
     pub fn translate(&self, addr: VirtAddr) -> Option<PhysAddr> {
         let depth = self.depth;
         let root_table_guard = self.root_table.read();
-        let root_table = unsafe { root_table_guard.as_mut().unwrap() };
+        let root_table = unsafe { root_table_guard.as_ref().unwrap() };
 
         AddressSpace::translate_in_table(root_table, addr, depth)
     }
 
     pub fn unmap(&self, pages: PageRange) {
+        crate::trace_function!();
         let depth = self.depth;
-        let root_table_guard = self.root_table.read();
-        let root_table = unsafe { root_table_guard.as_mut().unwrap() };
 
-        AddressSpace::unmap_in_table(root_table, pages, depth);
+        {
+            let root_table_guard = self.root_table.read();
+            let root_table = unsafe { root_table_guard.as_ref().unwrap() };
+            AddressSpace::unmap_in_table(root_table, pages, depth);
+        }
+
+        // Page tables, which have become empty, may only be freed while no other thread walks them
+        let root_table_guard = self.root_table.write();
+        let root_table = unsafe { root_table_guard.as_mut().unwrap() };
+        AddressSpace::free_empty_tables(root_table, pages, depth);
     }
 
     fn copy_table(source: &PageTable, target: &mut PageTable, level: usize) {
         if level > 1 { // On all levels larger than 1, we allocate new page frames
             for (index, target_entry) in target.iter_mut().enumerate() {
-                let source_entry = &source[index];
+                let source_entry = load_entry(&source[index]);
                 if source_entry.is_unused() { // Skip empty entries
                     continue;
                 }
 
                 let phys_frame = physical::alloc(1).start;
-                let flags = source[index].flags();
-                target_entry.set_frame(phys_frame, flags);
+                target_entry.set_frame(phys_frame, source_entry.flags());
 
-                let next_level_source = unsafe { (source_entry.addr().as_u64() as *mut PageTable).as_mut().unwrap() };
-                let next_level_target = unsafe { (target_entry.addr().as_u64() as *mut PageTable).as_mut().unwrap() };
+                let next_level_source = unsafe { PAGE_TABLES.table(source_entry.addr()) };
+                let next_level_target = unsafe { PAGE_TABLES.table_mut(target_entry.addr()) };
                 AddressSpace::copy_table(next_level_source, next_level_target, level - 1);
             }
-        } else { // Only on the last level, we create a 1:1 copy of the page table
+        } else { // Only on the last level, we create a 1:1 copy of the page table (the frames still belong to the source)
             for (index, target_entry) in target.iter_mut().enumerate() {
-                let source_entry = &source[index];
-                target_entry.set_addr(source_entry.addr(), source_entry.flags());
+                let source_entry = load_entry(&source[index]);
+                target_entry.set_addr(source_entry.addr(), source_entry.flags() - OWNED_FRAME);
             }
         }
     }
 
-    fn map_in_table(table: &mut PageTable, mut frames: PhysFrameRange, mut pages: PageRange, space: MemorySpace, flags: PageTableFlags, level: usize) -> usize {
-        let mut total_allocated_pages: usize = 0;
-        let start_index = usize::from(page_table_index(pages.start.start_address(), level));
-
-        if level > 1 { // Calculate next level page table until level == 1
-            for entry in table.iter_mut().skip(start_index) {
-                let next_level_table;
-                if entry.is_unused() { // Entry is empty -> Allocate new page frame
-                    let phys_frame = physical::alloc(1).start;
-                    entry.set_frame(phys_frame, flags);
-
-                    next_level_table = unsafe { (entry.addr().as_u64() as *mut PageTable).as_mut().unwrap() };
-                    next_level_table.zero();
-                } else {
-                    next_level_table = unsafe { (entry.addr().as_u64() as *mut PageTable).as_mut().unwrap() };
-                }
-
-                let allocated_pages = AddressSpace::map_in_table(next_level_table, frames, pages, space, flags, level - 1);
-                pages = PageRange { start: pages.start + allocated_pages as u64, end: pages.end };
-                total_allocated_pages += allocated_pages;
+    fn map_in_table(table: &PageTable, frames: PhysFrameRange, pages: PageRange, space: MemorySpace, flags: PageTableFlags, level: usize) {
+        let mut start = pages.start;
 
-                if frames.end > frames.start {
-                    frames = PhysFrameRange { start: frames.start + allocated_pages as u64, end: frames.end };
-                }
+        for entry in table.iter().skip(usize::from(page_table_index(start.start_address(), level))) {
+            if start >= pages.end {
+                break;
+            }
 
-                if pages.start >= pages.end {
-                    break;
+            let end = start + min(pages.end - start, pages_in_entry(start, level));
+            let offset = start - pages.start;
+
+            if level > 1 { // Calculate next level page table until level == 1
+                // Access rights are restricted by the entries of the mapped pages only, since restrictions on a higher level
+                // would apply to all pages below (e.g. a read-only code segment would make the data next to it read-only)
+                let next_level_table = AddressSpace::next_level_table(entry, PageTableFlags::PRESENT | PageTableFlags::WRITABLE | (flags & PageTableFlags::USER_ACCESSIBLE));
+                let next_level_frames = if frames.is_empty() { frames } else { PhysFrameRange { start: frames.start + offset, end: frames.end } };
+                AddressSpace::map_in_table(next_level_table, next_level_frames, PageRange { start, end }, space, flags, level - 1);
+            } else { // Reached level 1 page table
+                match space {
+                    MemorySpace::Kernel => store_entry(entry, PhysAddr::new(start.start_address().as_u64()), flags),
+                    MemorySpace::User if frames.is_empty() => AddressSpace::map_user(entry, flags | OWNED_FRAME),
+                    MemorySpace::User => store_entry(entry, (frames.start + offset).start_address(), flags | OWNED_FRAME),
+                    MemorySpace::Device => store_entry(entry, (frames.start + offset).start_address(), flags)
                 }
             }
-        } else { // Reached level 1 page table
-            total_allocated_pages += match space {
-                MemorySpace::Kernel => AddressSpace::identity_map_kernel(table, pages, flags),
-                MemorySpace::User => {
-                    if frames.start == frames.end {
-                        AddressSpace::map_user(table, pages, flags)
-                    } else {
-                        AddressSpace::map_user_physical(table, frames, pages, flags)
-                    }
+
+            start = end;
+        }
+    }
+
+    /// Get the page table referenced by `entry`. If there is none, a new table is installed with a compare-exchange,
+    /// so that concurrent mappings in the same area agree on one table (the loser frees its table again).
+    fn next_level_table(entry: &PageTableEntry, flags: PageTableFlags) -> &PageTable {
+        let mut current = load_entry(entry);
+        if current.is_unused() {
+            let phys_frame = physical::alloc(1).start;
+            unsafe { PAGE_TABLES.table_mut(phys_frame.start_address()) }.zero();
+
+            let new = phys_frame.start_address().as_u64() | flags.bits();
+            match atomic_entry(entry).compare_exchange(0, new, AcqRel, Acquire) {
+                Ok(_) => current.set_frame(phys_frame, flags),
+                Err(_) => {
+                    unsafe { physical::free(PhysFrameRange { start: phys_frame, end: phys_frame + 1 }); }
+                    current = load_entry(entry);
                 }
             }
         }
 
-        return total_allocated_pages;
+        return unsafe { PAGE_TABLES.table(current.addr()) };
     }
 
-    fn unmap_in_table(table: &mut PageTable, mut pages: PageRange, level: usize) -> usize {
-        let mut total_freed_pages: usize = 0;
-        let start_index = usize::from(page_table_index(pages.start.start_address(), level));
+    /// Walk from `table` (on level `depth`) down to the level 1 table containing `page`, installing missing tables on the way.
+    fn level_one_table(mut table: &PageTable, page: Page, flags: PageTableFlags, depth: usize) -> &PageTable {
+        for level in (2..=depth).rev() {
+            let entry = &table[page_table_index(page.start_address(), level)];
+            table = AddressSpace::next_level_table(entry, PageTableFlags::PRESENT | PageTableFlags::WRITABLE | (flags & PageTableFlags::USER_ACCESSIBLE));
+        }
 
-        if level > 1 { // Calculate next level page table until level == 1
-            for entry in table.iter_mut().skip(start_index) {
-                if entry.is_unused() {
-                    continue;
-                }
+        return table;
+    }
 
-                let next_level_table = unsafe { (entry.addr().as_u64() as *mut PageTable).as_mut().unwrap() };
-                let freed_pages = AddressSpace::unmap_in_table(next_level_table, pages, level - 1);
-                pages = PageRange { start: pages.start + freed_pages as u64, end: pages.end };
-                total_freed_pages += freed_pages;
+    /// Map a newly allocated page frame, unless another thread has mapped the page in the meantime.
+    fn map_user(entry: &PageTableEntry, flags: PageTableFlags) {
+        let phys_frame = physical::alloc(1).start;
+        let new = phys_frame.start_address().as_u64() | flags.bits();
 
-                if AddressSpace::is_table_empty(next_level_table) {
-                    let table_frame = PhysFrame::from_start_address(entry.addr()).unwrap();
-                    unsafe { physical::free(PhysFrameRange { start: table_frame, end: table_frame + 1 }); }
-                    entry.set_unused();
-                }
+        if atomic_entry(entry).compare_exchange(0, new, AcqRel, Acquire).is_err() {
+            unsafe { physical::free(PhysFrameRange { start: phys_frame, end: phys_frame + 1 }); }
+        }
+    }
+
+    fn unmap_in_table(table: &PageTable, pages: PageRange, level: usize) {
+        let mut start = pages.start;
+
+        for entry in table.iter().skip(usize::from(page_table_index(start.start_address(), level))) {
+            if start >= pages.end {
+                break;
+            }
 
-                if pages.start >= pages.end {
-                    break;
+            let end = start + min(pages.end - start, pages_in_entry(start, level));
+
+            if level > 1 { // Calculate next level page table until level == 1
+                let next_level_entry = load_entry(entry);
+                if !next_level_entry.is_unused() {
+                    let next_level_table = unsafe { PAGE_TABLES.table(next_level_entry.addr()) };
+                    AddressSpace::unmap_in_table(next_level_table, PageRange { start, end }, level - 1);
+                }
+            } else { // Reached level 1 page table (the entry is cleared atomically, so that a frame is only released once)
+                let old = unsafe { mem::transmute::<u64, PageTableEntry>(atomic_entry(entry).swap(0, AcqRel)) };
+                if old.flags().contains(OWNED_FRAME) {
+                    let frame = PhysFrame::from_start_address(old.addr()).unwrap();
+                    unsafe { physical::release(frame); }
                 }
             }
-        } else { // Reached level 1 page table
-            let start_index = usize::from(page_table_index(pages.start.start_address(), 1));
-            let free_count = min((pages.end - pages.start) as usize, 512 - start_index);
 
-            for (count, entry) in table.iter_mut().skip(start_index).enumerate() {
-                if count >= free_count {
-                    break;
+            start = end;
+        }
+    }
+
+    /// Free the page tables in the area of `pages`, which do not contain any mappings anymore.
+    /// Requires exclusive access to the page tables (see `AddressSpace`).
+    fn free_empty_tables(table: &mut PageTable, pages: PageRange, level: usize) {
+        let mut start = pages.start;
+
+        for entry in table.iter_mut().skip(usize::from(page_table_index(start.start_address(), level))) {
+            if start >= pages.end {
+                break;
+            }
+
+            let end = start + min(pages.end - start, pages_in_entry(start, level));
+
+            if !entry.is_unused() {
+                let next_level_table = unsafe { PAGE_TABLES.table_mut(entry.addr()) };
+                if level > 2 {
+                    AddressSpace::free_empty_tables(next_level_table, PageRange { start, end }, level - 1);
                 }
 
-                if !entry.is_unused() {
-                    let frame = PhysFrame::from_start_address(entry.addr()).unwrap();
-                    unsafe { physical::free(PhysFrameRange { start: frame, end: frame + 1 }); }
+                if AddressSpace::is_table_empty(next_level_table) {
+                    let table_frame = PhysFrame::from_start_address(entry.addr()).unwrap();
+                    unsafe { physical::free(PhysFrameRange { start: table_frame, end: table_frame + 1 }); }
                     entry.set_unused();
                 }
             }
 
-            return free_count;
+            start = end;
         }
-
-        return total_freed_pages;
     }
 
+    /// Free all page tables (on all levels) and release the frames of the mapped user memory.
     fn drop_table(table: &mut PageTable, level: usize) {
-        if level > 1 { // Calculate next level page table until level == 1
-            for entry in table.iter_mut() {
-                if entry.addr() == PhysAddr::zero() {
-                    continue;
-                }
+        for entry in table.iter_mut() {
+            if entry.is_unused() {
+                continue;
+            }
 
-                let next_level_table = unsafe { (entry.addr().as_u64() as *mut PageTable).as_mut().unwrap() };
+            if level > 1 { // Calculate next level page table until level == 1
+                let next_level_table = unsafe { PAGE_TABLES.table_mut(entry.addr()) };
                 AddressSpace::drop_table(next_level_table, level - 1);
+            } else if entry.flags().contains(OWNED_FRAME) {
+                let frame = PhysFrame::from_start_address(entry.addr()).unwrap();
+                unsafe { physical::release(frame); }
             }
-
-            let table_frame = PhysFrame::from_start_address(PhysAddr::new(ptr::from_ref(table) as u64)).unwrap();
-            unsafe { physical::free(PhysFrameRange { start: table_frame, end: table_frame + 1 }); }
         }
+
+        let table_frame = PhysFrame::from_start_address(PAGE_TABLES.physical_address(table)).unwrap();
+        unsafe { physical::free(PhysFrameRange { start: table_frame, end: table_frame + 1 }); }
     }
 
-    fn translate_in_table(table: &mut PageTable, addr: VirtAddr, level: usize) -> Option<PhysAddr> {
+    fn translate_in_table(table: &PageTable, addr: VirtAddr, level: usize) -> Option<PhysAddr> {
         let aligned_addr = addr.align_down(PAGE_SIZE as u64);
         let index = usize::from(page_table_index(aligned_addr, level));
-        let entry = &table[index];
+        let entry = load_entry(&table[index]);
         if entry.is_unused() {
             return None;
         }
 
         if level > 1 { // Calculate next level page table until level == 1
-            let next_level_table = unsafe { (entry.addr().as_u64() as *mut PageTable).as_mut().unwrap() };
+            let next_level_table = unsafe { PAGE_TABLES.table(entry.addr()) };
             return AddressSpace::translate_in_table(next_level_table, addr, level - 1);
         } else { // Reached level 1 page table
             return Some(entry.addr() + (addr - aligned_addr));
         }
     }
 
-    fn identity_map_kernel(table: &mut PageTable, pages: PageRange, flags: PageTableFlags) -> usize {
-        let start_index = usize::from(page_table_index(pages.start.start_address(), 1));
-        let alloc_count = min((pages.end - pages.start) as usize, 512 - start_index);
-        let mut frame_addr = PhysAddr::new(pages.start.start_address().as_u64());
-
-        for (count, entry) in table.iter_mut().skip(start_index).enumerate() {
-            if count >= alloc_count {
-                break;
-            }
-
-            entry.set_addr(frame_addr, flags);
-            frame_addr = frame_addr + PAGE_SIZE;
-        }
-
-        return alloc_count;
-    }
-
-    fn map_user(table: &mut PageTable, pages: PageRange, flags: PageTableFlags) -> usize {
-        let start_index = usize::from(page_table_index(pages.start.start_address(), 1));
-        let alloc_count = min((pages.end - pages.start) as usize, 512 - start_index);
-
-        for (count, entry) in table.iter_mut().skip(start_index).enumerate() {
-            if count >= alloc_count {
-                break;
-            }
-
-            let phys_frame = physical::alloc(1).start;
-            entry.set_frame(phys_frame, flags);
-        }
-
-        return alloc_count;
-    }
-
-    fn map_user_physical(table: &mut PageTable, frames: PhysFrameRange, pages: PageRange, flags: PageTableFlags) -> usize {
-        let start_index = usize::from(page_table_index(pages.start.start_address(), 1));
-        let alloc_count = min((pages.end - pages.start) as usize, 512 - start_index);
-        let mut frame_iter = frames.into_iter().skip(start_index);
-
-        for (count, entry) in table.iter_mut().skip(start_index).enumerate() {
-            if count >= alloc_count {
-                break;
-            }
-
-            entry.set_frame(frame_iter.next().unwrap(), flags);
-        }
-
-        return alloc_count;
-    }
-
     fn is_table_empty(table: &PageTable) -> bool {
         for entry in table.iter() {
             if !entry.is_unused() {
@@ -377,4 +498,30 @@ impl AddressSpace {
 
         return true;
     }
-}
\ No newline at end of file
+}
+
+/// Number of pages from `page` to the end of the area covered by its entry in a page table of `level`.
+fn pages_in_entry(page: Page, level: usize) -> u64 {
+    let pages_per_entry = 1u64 << ((level - 1) * 9);
+    let page_number = page.start_address().as_u64() / PAGE_SIZE as u64;
+    return pages_per_entry - (page_number % pages_per_entry);
+}
+
+/// Page table entries are accessed atomically, since other threads may change them concurrently (see `AddressSpace`).
+fn atomic_entry(entry: &PageTableEntry) -> &AtomicU64 {
+    return unsafe { &*(ptr::from_ref(entry) as *const AtomicU64) };
+}
+
+fn load_entry(entry: &PageTableEntry) -> PageTableEntry {
+    return unsafe { mem::transmute::<u64, PageTableEntry>(atomic_entry(entry).load(Acquire)) };
+}
+
+/// If the page has already been mapped to another frame owned by the address space, that frame is released
+/// (otherwise it would be leaked, since the entry is the only reference to it).
+fn store_entry(entry: &PageTableEntry, addr: PhysAddr, flags: PageTableFlags) {
+    let old = unsafe { mem::transmute::<u64, PageTableEntry>(atomic_entry(entry).swap(addr.as_u64() | flags.bits(), AcqRel)) };
+    if old.flags().contains(OWNED_FRAME) && old.addr() != addr {
+        let frame = PhysFrame::from_start_address(old.addr()).unwrap();
+        unsafe { physical::release(frame); }
+    }
+}
@@ -0,0 +1,140 @@
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering::Relaxed;
+use log::info;
+use syscall::error::Errno;
+use syscall::ioctl::{decode_interface_name, IoctlRequest, INTERFACE_NAME_SIZE};
+use crate::fs::devfs::Device;
+use crate::net::{Interface, Result};
+use crate::sync::Mutex;
+use crate::{net, timer};
+
+/// Captured data, which has not been read yet, is limited to this size. Further frames are dropped, until it has been read.
+const BUFFER_CAPACITY: usize = 1024 * 1024;
+
+/// Frames are captured completely (all devices have a smaller MTU).
+const SNAPSHOT_LENGTH: usize = 65535;
+
+const MAGIC: u32 = 0xa1b2c3d4;
+const LINKTYPE_ETHERNET: u32 = 1;
+const FILE_HEADER_SIZE: usize = 24;
+const RECORD_HEADER_SIZE: usize = 16;
+
+/// '/dev/pcap': Frames captured on the network interfaces in pcap file format, which can be analyzed with Wireshark or tcpdump.
+/// Reading from the beginning yields the file header followed by all frames captured since (which are removed by reading).
+/// Capturing is started and stopped with `IoctlRequest::StartCapture` and `IoctlRequest::StopCapture`.
+pub struct PcapDevice;
+
+struct Capture {
+    /// Name of the captured interface (`None` for all interfaces).
+    interface: Option<String>,
+    /// Records (each one consisting of a header and the frame), which have not been read yet.
+    data: VecDeque<u8>,
+    captured_frames: usize,
+    dropped_frames: usize,
+}
+
+/// Checked before locking, so that sending and receiving is not slowed down while not capturing.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static CAPTURE: Mutex<Capture> = Mutex::new(Capture { interface: None, data: VecDeque::new(), captured_frames: 0, dropped_frames: 0 });
+
+/// Start capturing the frames of `interface` (or of all interfaces, if `None`). Data captured before, which has not been read yet, is discarded.
+pub fn start(interface: Option<String>) -> Result<()> {
+    if let Some(name) = &interface {
+        net::interface(name).ok_or(Errno::NoDevice)?;
+    }
+
+    let mut capture = CAPTURE.lock();
+    info!("Capturing frames on [{}]", interface.as_deref().unwrap_or("all interfaces"));
+    *capture = Capture { interface, data: VecDeque::new(), captured_frames: 0, dropped_frames: 0 };
+    ACTIVE.store(true, Relaxed);
+
+    return Ok(());
+}
+
+/// Stop capturing. Frames, which have already been captured, can still be read.
+pub fn stop() {
+    let capture = CAPTURE.lock();
+    if ACTIVE.swap(false, Relaxed) {
+        info!("Stopped capturing frames ([{}] captured, [{}] dropped)", capture.captured_frames, capture.dropped_frames);
+    }
+}
+
+/// Called by `interface` for each frame, which it sends or receives.
+pub fn tap(interface: &Interface, frame: &[u8]) {
+    if !ACTIVE.load(Relaxed) {
+        return;
+    }
+
+    let mut capture = CAPTURE.lock();
+    if !ACTIVE.load(Relaxed) || capture.interface.as_ref().is_some_and(|name| name != interface.name()) {
+        return;
+    }
+
+    let length = frame.len().min(SNAPSHOT_LENGTH);
+    if capture.data.len() + RECORD_HEADER_SIZE + length > BUFFER_CAPACITY {
+        capture.dropped_frames += 1;
+        return;
+    }
+
+    // There is no wall clock, so frames are timestamped with the time since booting
+    let now_us = timer().read().systime_ns() / 1000;
+    for field in [(now_us / 1000000) as u32, (now_us % 1000000) as u32, length as u32, frame.len() as u32] {
+        capture.data.extend(field.to_le_bytes());
+    }
+
+    capture.data.extend(&frame[..length]);
+    capture.captured_frames += 1;
+}
+
+fn file_header() -> [u8; FILE_HEADER_SIZE] {
+    let mut header = [0; FILE_HEADER_SIZE];
+    header[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+    header[4..6].copy_from_slice(&2u16.to_le_bytes()); // Version 2.4
+    header[6..8].copy_from_slice(&4u16.to_le_bytes());
+    header[16..20].copy_from_slice(&(SNAPSHOT_LENGTH as u32).to_le_bytes());
+    header[20..24].copy_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+
+    return header;
+}
+
+impl Device for PcapDevice {
+    /// Returns 0, if no frames are waiting to be read (so that copying the device ends after the captured frames).
+    fn read(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        let mut count = 0;
+        if offset < FILE_HEADER_SIZE {
+            let header = file_header();
+            count = buffer.len().min(FILE_HEADER_SIZE - offset);
+            buffer[..count].copy_from_slice(&header[offset..offset + count]);
+        }
+
+        let mut capture = CAPTURE.lock();
+        let length = (buffer.len() - count).min(capture.data.len());
+        for (target, byte) in buffer[count..count + length].iter_mut().zip(capture.data.drain(..length)) {
+            *target = byte;
+        }
+
+        return Ok(count + length);
+    }
+
+    fn ioctl(&self, request: usize, arg: usize) -> Result<usize> {
+        return match IoctlRequest::try_from(request) {
+            Ok(IoctlRequest::StartCapture) => {
+                let interface = if arg == 0 {
+                    None
+                } else {
+                    let name = unsafe { (arg as *const [u8; INTERFACE_NAME_SIZE]).read() };
+                    Some(String::from(decode_interface_name(&name).ok_or(Errno::InvalidArgument)?))
+                };
+
+                start(interface).map(|_| 0)
+            }
+            Ok(IoctlRequest::StopCapture) => {
+                stop();
+                Ok(0)
+            }
+            _ => Err(Errno::NotATerminal)
+        };
+    }
+}
@@ -0,0 +1,167 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering::Relaxed;
+use log::debug;
+use crate::net::inet::ethernet;
+use crate::net::inet::ethernet::{TYPE_ARP, TYPE_IPV4};
+use crate::net::{Interface, Ipv4Address, MacAddress, Result};
+use crate::sync::Mutex;
+use crate::timer;
+
+const PACKET_SIZE: usize = 28;
+const HARDWARE_TYPE_ETHERNET: u16 = 1;
+const OPERATION_REQUEST: u16 = 1;
+const OPERATION_REPLY: u16 = 2;
+
+/// Resolved addresses are requested again after this time, in case the host has changed its MAC address.
+const ENTRY_LIFETIME_MS: usize = 60000;
+const REQUEST_INTERVAL_MS: usize = 1000;
+/// Unanswered requests are repeated, before the packets waiting for the reply are dropped.
+const MAX_REQUESTS: usize = 3;
+const MAX_WAITING_PACKETS: usize = 16;
+
+enum Entry {
+    Resolved {
+        mac_address: MacAddress,
+        expires_ms: usize,
+    },
+    /// A request has been sent and IPv4 packets are waiting for the reply.
+    Pending {
+        interface: Arc<Interface>,
+        packets: Vec<Vec<u8>>,
+        requested_ms: usize,
+        requests: usize,
+    },
+}
+
+/// IPv4 addresses are assumed to be unique across all interfaces, so the cache is shared.
+static CACHE: Mutex<BTreeMap<Ipv4Address, Entry>> = Mutex::new(BTreeMap::new());
+
+/// Send the IPv4 `packet` to `next_hop`, which is reachable via `interface`. If its MAC address is unknown,
+/// the packet is queued and sent as soon as it has been resolved (so `Ok` does not mean, that the packet has been sent).
+pub fn send(interface: &Arc<Interface>, next_hop: Ipv4Address, packet: Vec<u8>) -> Result<()> {
+    if interface.is_loopback() {
+        return ethernet::send(interface, MacAddress::default(), TYPE_IPV4, &packet);
+    }
+    if next_hop.is_broadcast() || interface.ipv4().is_some_and(|cidr| cidr.broadcast() == next_hop) {
+        return ethernet::send(interface, MacAddress::BROADCAST, TYPE_IPV4, &packet);
+    }
+
+    let now = timer().read().systime_ms();
+    let mut cache = CACHE.lock();
+    match cache.get_mut(&next_hop) {
+        Some(Entry::Resolved { mac_address, expires_ms }) if *expires_ms > now => {
+            let mac_address = *mac_address;
+            drop(cache);
+
+            return ethernet::send(interface, mac_address, TYPE_IPV4, &packet);
+        }
+        Some(Entry::Pending { packets, .. }) => {
+            if packets.len() < MAX_WAITING_PACKETS {
+                packets.push(packet);
+            }
+
+            return Ok(());
+        }
+        _ => {
+            cache.insert(next_hop, Entry::Pending { interface: Arc::clone(interface), packets: vec![packet], requested_ms: now, requests: 1 });
+            drop(cache);
+
+            return request(interface, next_hop);
+        }
+    }
+}
+
+/// Process a received ARP packet. Replies and requests update the cache, if the sender is already known
+/// or the packet is addressed to this host (RFC 826). Requests for the address of `interface` are answered.
+pub fn receive(interface: &Arc<Interface>, packet: &[u8]) {
+    if packet.len() < PACKET_SIZE || u16::from_be_bytes([packet[0], packet[1]]) != HARDWARE_TYPE_ETHERNET
+        || u16::from_be_bytes([packet[2], packet[3]]) != TYPE_IPV4 || packet[4] != 6 || packet[5] != 4 {
+        return;
+    }
+
+    let Some(cidr) = interface.ipv4() else {
+        return;
+    };
+
+    let operation = u16::from_be_bytes([packet[6], packet[7]]);
+    let sender_mac = MacAddress(packet[8..14].try_into().unwrap());
+    let sender = Ipv4Address(packet[14..18].try_into().unwrap());
+    let target = Ipv4Address(packet[24..28].try_into().unwrap());
+    let is_target = target == cidr.address();
+
+    // Probes (e.g. for duplicate address detection) have no sender address
+    if !sender.is_unspecified() {
+        let waiting = {
+            let mut cache = CACHE.lock();
+            if is_target || cache.contains_key(&sender) {
+                let entry = Entry::Resolved { mac_address: sender_mac, expires_ms: timer().read().systime_ms() + ENTRY_LIFETIME_MS };
+                match cache.insert(sender, entry) {
+                    Some(Entry::Pending { packets, .. }) => packets,
+                    _ => Vec::new()
+                }
+            } else {
+                Vec::new()
+            }
+        };
+
+        for packet in waiting {
+            let _ = ethernet::send(interface, sender_mac, TYPE_IPV4, &packet);
+        }
+    }
+
+    if operation == OPERATION_REQUEST && is_target {
+        let reply = build_packet(OPERATION_REPLY, interface.mac_address(), cidr.address(), sender_mac, sender);
+        let _ = ethernet::send(interface, sender_mac, TYPE_ARP, &reply);
+    }
+}
+
+/// Remove expired entries and repeat unanswered requests (called periodically by the stack).
+pub fn expire(now_ms: usize) {
+    let mut repeated = Vec::new();
+    CACHE.lock().retain(|&address, entry| match entry {
+        Entry::Resolved { expires_ms, .. } => *expires_ms > now_ms,
+        Entry::Pending { interface, packets, requested_ms, requests } => {
+            if now_ms - *requested_ms < REQUEST_INTERVAL_MS {
+                true
+            } else if *requests < MAX_REQUESTS {
+                *requested_ms = now_ms;
+                *requests += 1;
+                repeated.push((Arc::clone(interface), address));
+                true
+            } else {
+                debug!("Host [{}] is unreachable via [{}], dropping [{}] packets", address, interface.name(), packets.len());
+                interface.statistics().dropped_frames.fetch_add(packets.len(), Relaxed);
+                false
+            }
+        }
+    });
+
+    for (interface, address) in repeated {
+        let _ = request(&interface, address);
+    }
+}
+
+/// Broadcast a request for the MAC address of `target`.
+fn request(interface: &Interface, target: Ipv4Address) -> Result<()> {
+    let sender = interface.ipv4().map_or(Ipv4Address::UNSPECIFIED, |cidr| cidr.address());
+    let packet = build_packet(OPERATION_REQUEST, interface.mac_address(), sender, MacAddress::default(), target);
+
+    return ethernet::send(interface, MacAddress::BROADCAST, TYPE_ARP, &packet);
+}
+
+fn build_packet(operation: u16, sender_mac: MacAddress, sender: Ipv4Address, target_mac: MacAddress, target: Ipv4Address) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(PACKET_SIZE);
+    packet.extend_from_slice(&HARDWARE_TYPE_ETHERNET.to_be_bytes());
+    packet.extend_from_slice(&TYPE_IPV4.to_be_bytes());
+    packet.extend_from_slice(&[6, 4]);
+    packet.extend_from_slice(&operation.to_be_bytes());
+    packet.extend_from_slice(&sender_mac.0);
+    packet.extend_from_slice(&sender.0);
+    packet.extend_from_slice(&target_mac.0);
+    packet.extend_from_slice(&target.0);
+
+    return packet;
+}
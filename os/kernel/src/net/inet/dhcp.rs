@@ -0,0 +1,447 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use log::info;
+use crate::net::inet::route::Route;
+use crate::net::inet::{route, udp};
+use crate::net::socket::SocketAddress;
+use crate::net::{Interface, Ipv4Address, Ipv4Cidr, MacAddress, Result};
+use crate::sync::Mutex;
+use crate::{net, random, timer};
+
+pub const CLIENT_PORT: u16 = 68;
+pub const SERVER_PORT: u16 = 67;
+
+pub const OPERATION_REQUEST: u8 = 1;
+pub const OPERATION_REPLY: u8 = 2;
+
+pub const TYPE_DISCOVER: u8 = 1;
+pub const TYPE_OFFER: u8 = 2;
+pub const TYPE_REQUEST: u8 = 3;
+pub const TYPE_ACK: u8 = 5;
+pub const TYPE_NAK: u8 = 6;
+
+const HARDWARE_TYPE_ETHERNET: u8 = 1;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Size of the fixed part of a message (up to the magic cookie).
+const FIXED_SIZE: usize = 236;
+/// Some (BOOTP) servers ignore shorter messages, so requests are padded (RFC 1542).
+const MIN_MESSAGE_SIZE: usize = 300;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS_SERVERS: u8 = 6;
+const OPTION_REQUESTED_ADDRESS: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_IDENTIFIER: u8 = 54;
+const OPTION_PARAMETER_REQUEST_LIST: u8 = 55;
+const OPTION_RENEWAL_TIME: u8 = 58;
+const OPTION_REBINDING_TIME: u8 = 59;
+const OPTION_END: u8 = 255;
+
+/// Lease time, that means 'infinite'.
+const INFINITE_LEASE: u32 = u32::MAX;
+
+/// Unanswered discovers and requests are repeated with doubled intervals (starting with 4 seconds, as suggested by RFC 2131).
+const INITIAL_RETRANSMISSION_MS: usize = 4000;
+const MAX_RETRANSMISSION_MS: usize = 64000;
+/// The client starts over with a discover, if its requests are not answered.
+const MAX_REQUESTS: usize = 4;
+/// Shortest interval between requests for renewing a lease (RFC 2131, section 4.4.5).
+const MIN_RENEWAL_RETRY_MS: usize = 60000;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum State {
+    /// Discovers are broadcast, until a server makes an offer.
+    Selecting,
+    /// The offer has been requested and the client waits for the server's acknowledgment.
+    Requesting,
+    Bound,
+    /// The lease is being extended with the server, which has granted it.
+    Renewing,
+    /// The server has not answered, so the lease is requested from any server.
+    Rebinding,
+}
+
+/// Client, which configures an interface with the address, netmask, gateway and name servers assigned by a DHCP server (RFC 2131).
+/// Messages are processed by the kernel worker thread and timeouts are checked by the periodic tick of the stack.
+struct Client {
+    interface: Arc<Interface>,
+    state: State,
+    transaction: u32,
+    /// Address offered or assigned by `server`.
+    offered: Ipv4Address,
+    server: Ipv4Address,
+    /// `None` for infinite leases.
+    lease: Option<Lease>,
+    deadline_ms: usize,
+    retransmission_ms: usize,
+    requests: usize,
+}
+
+/// Times (in milliseconds since boot), at which a lease must be renewed, rebound or given up.
+#[derive(Copy, Clone)]
+struct Lease {
+    renewal_ms: usize,
+    rebinding_ms: usize,
+    expiration_ms: usize,
+}
+
+/// Decoded DHCP message (only the fields and options used by the client).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Message {
+    pub operation: u8,
+    pub transaction: u32,
+    /// 'ciaddr': Address of a client, that is already bound (when renewing or rebinding).
+    pub client_address: Ipv4Address,
+    /// 'yiaddr': Address offered to or assigned to the client.
+    pub your_address: Ipv4Address,
+    pub mac_address: MacAddress,
+    pub typ: u8,
+    pub server: Option<Ipv4Address>,
+    pub requested_address: Option<Ipv4Address>,
+    pub subnet_mask: Option<Ipv4Address>,
+    pub router: Option<Ipv4Address>,
+    pub dns_servers: Vec<Ipv4Address>,
+    /// Lease, renewal (T1) and rebinding (T2) time in seconds.
+    pub lease_time_s: Option<u32>,
+    pub renewal_time_s: Option<u32>,
+    pub rebinding_time_s: Option<u32>,
+}
+
+static CLIENTS: Mutex<Vec<Client>> = Mutex::new(Vec::new());
+
+/// Start a client for every interface (except for loopback), that has no address yet.
+pub fn init() {
+    for interface in net::interfaces().into_iter().filter(|interface| !interface.is_loopback() && interface.ipv4().is_none()) {
+        start(interface);
+    }
+}
+
+/// Configure `interface` via DHCP. Returns immediately, the interface is configured as soon as a server has assigned an address.
+pub fn start(interface: Arc<Interface>) {
+    let mut clients = CLIENTS.lock();
+    if clients.iter().any(|client| Arc::ptr_eq(&client.interface, &interface)) {
+        return;
+    }
+
+    info!("Requesting address for [{}] via DHCP", interface.name());
+    let mut client = Client::new(interface);
+    client.discover(timer().read().systime_ms());
+    clients.push(client);
+}
+
+/// Stop configuring `interface` (e.g. because it has been configured by hand). Its address is kept.
+pub fn stop(interface: &Arc<Interface>) {
+    CLIENTS.lock().retain(|client| !Arc::ptr_eq(&client.interface, interface));
+}
+
+/// Process a message received on the client port of `interface` (called by `udp::receive()`).
+pub fn receive(interface: &Arc<Interface>, payload: &[u8]) {
+    let Some(message) = Message::parse(payload) else {
+        return;
+    };
+
+    let now = timer().read().systime_ms();
+    let mut clients = CLIENTS.lock();
+    if let Some(client) = clients.iter_mut().find(|client| Arc::ptr_eq(&client.interface, interface)) {
+        client.receive(&message, now);
+    }
+}
+
+/// Repeat unanswered messages and renew leases (called periodically by the stack).
+pub fn expire(now_ms: usize) {
+    for client in CLIENTS.lock().iter_mut().filter(|client| client.deadline_ms <= now_ms) {
+        client.on_timeout(now_ms);
+    }
+}
+
+impl Client {
+    fn new(interface: Arc<Interface>) -> Self {
+        Self {
+            interface,
+            state: State::Selecting,
+            transaction: 0,
+            offered: Ipv4Address::UNSPECIFIED,
+            server: Ipv4Address::UNSPECIFIED,
+            lease: None,
+            deadline_ms: 0,
+            retransmission_ms: INITIAL_RETRANSMISSION_MS,
+            requests: 0,
+        }
+    }
+
+    fn receive(&mut self, message: &Message, now: usize) {
+        if message.operation != OPERATION_REPLY || message.transaction != self.transaction || message.mac_address != self.interface.mac_address() {
+            return;
+        }
+
+        match (self.state, message.typ) {
+            (State::Selecting, TYPE_OFFER) if !message.your_address.is_unspecified() => {
+                let Some(server) = message.server else {
+                    return;
+                };
+
+                // The first offer is taken
+                self.offered = message.your_address;
+                self.server = server;
+                self.state = State::Requesting;
+                self.requests = 0;
+                self.retransmission_ms = INITIAL_RETRANSMISSION_MS;
+                self.request(now);
+            }
+            (State::Requesting | State::Renewing | State::Rebinding, TYPE_ACK) => self.bind(message, now),
+            (State::Requesting | State::Renewing | State::Rebinding, TYPE_NAK) => {
+                info!("DHCP server [{}] has declined the address of [{}]", message.server.unwrap_or(self.server), self.interface.name());
+                self.release();
+                self.discover(now);
+            }
+            _ => {}
+        }
+    }
+
+    fn on_timeout(&mut self, now: usize) {
+        match self.state {
+            State::Selecting => {
+                self.retransmission_ms = (self.retransmission_ms * 2).min(MAX_RETRANSMISSION_MS);
+                self.send_discover(now);
+            }
+            State::Requesting if self.requests < MAX_REQUESTS => {
+                self.retransmission_ms = (self.retransmission_ms * 2).min(MAX_RETRANSMISSION_MS);
+                self.request(now);
+            }
+            State::Requesting => self.discover(now),
+            State::Bound => {
+                self.state = State::Renewing;
+                self.request(now);
+            }
+            State::Renewing | State::Rebinding => {
+                let lease = self.lease.unwrap();
+                if now >= lease.expiration_ms {
+                    info!("DHCP lease of [{}] has expired", self.interface.name());
+                    self.release();
+                    self.discover(now);
+                } else {
+                    if now >= lease.rebinding_ms {
+                        self.state = State::Rebinding;
+                    }
+
+                    self.request(now);
+                }
+            }
+        }
+    }
+
+    /// Start over by broadcasting a discover with a new transaction id.
+    fn discover(&mut self, now: usize) {
+        self.state = State::Selecting;
+        self.transaction = random::next_u64() as u32;
+        self.retransmission_ms = INITIAL_RETRANSMISSION_MS;
+        self.send_discover(now);
+    }
+
+    fn send_discover(&mut self, now: usize) {
+        let message = Message { typ: TYPE_DISCOVER, ..self.message() };
+        let _ = self.broadcast(&message);
+        self.deadline_ms = now + self.retransmission_ms;
+    }
+
+    /// Request the offered address (when requesting) or extend the lease of the assigned address (when renewing or rebinding).
+    fn request(&mut self, now: usize) {
+        self.requests += 1;
+        let mut message = Message { typ: TYPE_REQUEST, ..self.message() };
+
+        if self.state == State::Requesting {
+            message.server = Some(self.server);
+            message.requested_address = Some(self.offered);
+            let _ = self.broadcast(&message);
+            self.deadline_ms = now + self.retransmission_ms;
+            return;
+        }
+
+        // Bound clients identify themselves by their address and wait half of the remaining time (but at least a minute) for an answer
+        message.client_address = self.offered;
+        let lease = self.lease.unwrap();
+        let limit_ms = if self.state == State::Renewing { lease.rebinding_ms } else { lease.expiration_ms };
+        self.deadline_ms = (now + (limit_ms.saturating_sub(now) / 2).max(MIN_RENEWAL_RETRY_MS)).min(limit_ms);
+
+        // Renewals go to the server, which has granted the lease, rebinding requests to any server
+        let _ = if self.state == State::Renewing {
+            udp::send(SocketAddress::new(self.offered, CLIENT_PORT), SocketAddress::new(self.server, SERVER_PORT), &message.to_bytes())
+        } else {
+            self.broadcast(&message)
+        };
+    }
+
+    /// Configure the interface with the acknowledged lease.
+    fn bind(&mut self, message: &Message, now: usize) {
+        let address = message.your_address;
+        let prefix_length = message.subnet_mask.map_or_else(|| default_prefix_length(address), |mask| mask.to_bits().leading_ones() as u8);
+        let cidr = Ipv4Cidr::new(address, prefix_length).unwrap();
+        let renewed = self.state != State::Requesting && self.interface.ipv4() == Some(cidr);
+
+        // Renewing a lease keeps the routes (including those added by hand)
+        if !renewed {
+            route::configure(&self.interface, Some(cidr));
+            if let Some(router) = message.router {
+                let _ = route::add(Route { destination: Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 0).unwrap(), gateway: Some(router), interface: Arc::clone(&self.interface) });
+            }
+        }
+        if !message.dns_servers.is_empty() {
+            net::set_dns_servers(message.dns_servers.clone());
+        }
+
+        self.offered = address;
+        self.server = message.server.unwrap_or(self.server);
+        self.state = State::Bound;
+        self.requests = 0;
+        self.lease = message.lease(now);
+        self.deadline_ms = self.lease.map_or(usize::MAX, |lease| lease.renewal_ms);
+
+        if !renewed {
+            match message.lease_time_s.filter(|&time| time != INFINITE_LEASE) {
+                Some(time) => info!("Configured [{}] via DHCP: address [{}], gateway [{:?}], lease [{}s]", self.interface.name(), cidr, message.router, time),
+                None => info!("Configured [{}] via DHCP: address [{}], gateway [{:?}], infinite lease", self.interface.name(), cidr, message.router)
+            }
+        }
+    }
+
+    /// Remove the address and the routes of the interface (e.g. after the lease has expired).
+    fn release(&mut self) {
+        route::configure(&self.interface, None);
+        self.lease = None;
+    }
+
+    fn message(&self) -> Message {
+        return Message {
+            operation: OPERATION_REQUEST,
+            transaction: self.transaction,
+            mac_address: self.interface.mac_address(),
+            ..Message::default()
+        };
+    }
+
+    /// Broadcast `message` from the unspecified address (the interface may not have an address yet).
+    fn broadcast(&self, message: &Message) -> Result<()> {
+        let source = SocketAddress::new(Ipv4Address::UNSPECIFIED, CLIENT_PORT);
+        let destination = SocketAddress::new(Ipv4Address::BROADCAST, SERVER_PORT);
+
+        return udp::send_via(&self.interface, Ipv4Address::BROADCAST, source, destination, &message.to_bytes());
+    }
+}
+
+impl Message {
+    /// Decode a message, which carries a message type option. Returns `None` for malformed messages and plain BOOTP.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < FIXED_SIZE + MAGIC_COOKIE.len() || data[1] != HARDWARE_TYPE_ETHERNET || data[2] != 6 || data[FIXED_SIZE..FIXED_SIZE + 4] != MAGIC_COOKIE {
+            return None;
+        }
+
+        let address = |offset: usize| Ipv4Address(data[offset..offset + 4].try_into().unwrap());
+        let mut message = Message {
+            operation: data[0],
+            transaction: u32::from_be_bytes(data[4..8].try_into().unwrap()),
+            client_address: address(12),
+            your_address: address(16),
+            mac_address: MacAddress(data[28..34].try_into().unwrap()),
+            ..Message::default()
+        };
+
+        let mut options = &data[FIXED_SIZE + 4..];
+        while let Some(&code) = options.first() {
+            match code {
+                OPTION_PAD => options = &options[1..],
+                OPTION_END => break,
+                _ => {
+                    let length = *options.get(1)? as usize;
+                    let value = options.get(2..2 + length)?;
+                    let first_address = || value.get(..4).map(|bytes| Ipv4Address(bytes.try_into().unwrap()));
+                    let time = || value.get(..4).map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()));
+
+                    match code {
+                        OPTION_MESSAGE_TYPE => message.typ = *value.first()?,
+                        OPTION_SUBNET_MASK => message.subnet_mask = first_address(),
+                        OPTION_ROUTER => message.router = first_address(),
+                        OPTION_DNS_SERVERS => message.dns_servers = value.chunks_exact(4).map(|bytes| Ipv4Address(bytes.try_into().unwrap())).collect(),
+                        OPTION_SERVER_IDENTIFIER => message.server = first_address(),
+                        OPTION_REQUESTED_ADDRESS => message.requested_address = first_address(),
+                        OPTION_LEASE_TIME => message.lease_time_s = time(),
+                        OPTION_RENEWAL_TIME => message.renewal_time_s = time(),
+                        OPTION_REBINDING_TIME => message.rebinding_time_s = time(),
+                        _ => {}
+                    }
+
+                    options = &options[2 + length..];
+                }
+            }
+        }
+
+        return if message.typ == 0 { None } else { Some(message) };
+    }
+
+    /// Encode the message. Requests also ask for the parameters used by the client.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(MIN_MESSAGE_SIZE);
+        data.extend_from_slice(&[self.operation, HARDWARE_TYPE_ETHERNET, 6, 0]);
+        data.extend_from_slice(&self.transaction.to_be_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&self.client_address.0);
+        data.extend_from_slice(&self.your_address.0);
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&self.mac_address.0);
+        data.resize(FIXED_SIZE, 0);
+        data.extend_from_slice(&MAGIC_COOKIE);
+
+        data.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, self.typ]);
+        let addresses = [
+            (OPTION_REQUESTED_ADDRESS, self.requested_address),
+            (OPTION_SERVER_IDENTIFIER, self.server),
+            (OPTION_SUBNET_MASK, self.subnet_mask),
+            (OPTION_ROUTER, self.router),
+        ];
+        for (code, address) in addresses.into_iter().filter_map(|(code, address)| Some((code, address?))) {
+            data.extend_from_slice(&[code, 4]);
+            data.extend_from_slice(&address.0);
+        }
+        if !self.dns_servers.is_empty() {
+            data.extend_from_slice(&[OPTION_DNS_SERVERS, (self.dns_servers.len() * 4) as u8]);
+            self.dns_servers.iter().for_each(|server| data.extend_from_slice(&server.0));
+        }
+
+        let times = [(OPTION_LEASE_TIME, self.lease_time_s), (OPTION_RENEWAL_TIME, self.renewal_time_s), (OPTION_REBINDING_TIME, self.rebinding_time_s)];
+        for (code, time) in times.into_iter().filter_map(|(code, time)| Some((code, time?))) {
+            data.extend_from_slice(&[code, 4]);
+            data.extend_from_slice(&time.to_be_bytes());
+        }
+
+        if self.operation == OPERATION_REQUEST {
+            data.extend_from_slice(&[OPTION_PARAMETER_REQUEST_LIST, 6, OPTION_SUBNET_MASK, OPTION_ROUTER, OPTION_DNS_SERVERS, OPTION_LEASE_TIME, OPTION_RENEWAL_TIME, OPTION_REBINDING_TIME]);
+        }
+
+        data.push(OPTION_END);
+        if data.len() < MIN_MESSAGE_SIZE {
+            data.resize(MIN_MESSAGE_SIZE, OPTION_PAD);
+        }
+
+        return data;
+    }
+
+    /// Renewal and rebinding times of an acknowledged lease, starting `now`. Missing times default to 50% and 87.5% of the lease time.
+    fn lease(&self, now: usize) -> Option<Lease> {
+        let lease_s = self.lease_time_s.filter(|&time| time != INFINITE_LEASE)? as usize;
+        let renewal_s = self.renewal_time_s.map_or(lease_s / 2, |time| time as usize).min(lease_s);
+        let rebinding_s = self.rebinding_time_s.map_or(lease_s * 7 / 8, |time| time as usize).clamp(renewal_s, lease_s);
+
+        return Some(Lease { renewal_ms: now + renewal_s * 1000, rebinding_ms: now + rebinding_s * 1000, expiration_ms: now + lease_s * 1000 });
+    }
+}
+
+/// Prefix length of the address class, used if the server does not send a subnet mask.
+fn default_prefix_length(address: Ipv4Address) -> u8 {
+    return match address.0[0] {
+        0..=127 => 8,
+        128..=191 => 16,
+        _ => 24
+    };
+}
@@ -0,0 +1,42 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering::Relaxed;
+use crate::net::inet::{arp, ipv4};
+use crate::net::{Interface, MacAddress, Result};
+
+pub const HEADER_SIZE: usize = 14;
+
+pub const TYPE_IPV4: u16 = 0x0800;
+pub const TYPE_ARP: u16 = 0x0806;
+
+/// Pass the payload of a received frame to the protocol given by its EtherType.
+pub fn receive(interface: &Arc<Interface>, frame: &[u8]) {
+    if frame.len() < HEADER_SIZE {
+        interface.statistics().dropped_frames.fetch_add(1, Relaxed);
+        return;
+    }
+
+    // Frames for other hosts may be received in promiscuous mode
+    let destination = MacAddress(frame[0..6].try_into().unwrap());
+    if destination != interface.mac_address() && !destination.is_broadcast() && !destination.is_multicast() {
+        return;
+    }
+
+    let payload = &frame[HEADER_SIZE..];
+    match u16::from_be_bytes([frame[12], frame[13]]) {
+        TYPE_IPV4 => ipv4::receive(interface, payload),
+        TYPE_ARP => arp::receive(interface, payload),
+        _ => {}
+    }
+}
+
+/// Send `payload` in a frame from `interface` to `destination`.
+pub fn send(interface: &Interface, destination: MacAddress, ethertype: u16, payload: &[u8]) -> Result<()> {
+    let mut frame = Vec::with_capacity(HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&destination.0);
+    frame.extend_from_slice(&interface.mac_address().0);
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+
+    return interface.transmit(&frame);
+}
@@ -0,0 +1,134 @@
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicU16;
+use core::sync::atomic::Ordering::Relaxed;
+use syscall::error::Errno;
+use syscall::file::PollEvents;
+use crate::fs::poll;
+use crate::fs::poll::Pollable;
+use crate::net::inet::ipv4::{Header, PROTOCOL_ICMP};
+use crate::net::inet::{checksum, ipv4};
+use crate::net::socket::{Socket, SocketAddress};
+use crate::net::{Ipv4Address, Result};
+use crate::process::wait_queue::{WaitQueue, Waiter};
+use crate::sync::Mutex;
+
+pub const HEADER_SIZE: usize = 8;
+pub const TYPE_ECHO_REPLY: u8 = 0;
+pub const TYPE_ECHO_REQUEST: u8 = 8;
+
+/// Replies, which have not been read yet. Further replies are dropped.
+const MAX_QUEUED_REPLIES: usize = 16;
+
+/// Socket for sending echo requests and receiving the matching replies (as used by 'ping').
+/// Like on Linux, each socket gets its own identifier, which is filled into the requests,
+/// so that replies can be told apart from those for other sockets.
+pub struct EchoSocket {
+    identifier: u16,
+    replies: Mutex<VecDeque<(Ipv4Address, Vec<u8>)>>,
+    readable: WaitQueue,
+}
+
+static SOCKETS: Mutex<Vec<Weak<EchoSocket>>> = Mutex::new(Vec::new());
+static NEXT_IDENTIFIER: AtomicU16 = AtomicU16::new(1);
+
+/// Answer echo requests and pass echo replies to the socket, which has sent the request.
+pub fn receive(header: &Header, message: &[u8]) {
+    if message.len() < HEADER_SIZE || checksum(message, 0) != 0 {
+        return;
+    }
+
+    match message[0] {
+        // Requests sent to a broadcast address are not answered, so that the host cannot be used to flood others
+        TYPE_ECHO_REQUEST if !header.destination.is_broadcast() && !header.destination.is_multicast() => {
+            let mut reply = message.to_vec();
+            reply[0] = TYPE_ECHO_REPLY;
+            reply[2..4].fill(0);
+
+            let reply_checksum = checksum(&reply, 0);
+            reply[2..4].copy_from_slice(&reply_checksum.to_be_bytes());
+            let _ = ipv4::send(header.source, PROTOCOL_ICMP, &reply);
+        }
+        TYPE_ECHO_REPLY => {
+            let identifier = u16::from_be_bytes([message[4], message[5]]);
+            let socket = {
+                let mut sockets = SOCKETS.lock();
+                sockets.retain(|socket| socket.strong_count() > 0);
+                sockets.iter().filter_map(Weak::upgrade).find(|socket| socket.identifier == identifier)
+            };
+
+            if let Some(socket) = socket {
+                socket.push_reply(header.source, message);
+            }
+        }
+        _ => {}
+    }
+}
+
+impl EchoSocket {
+    pub fn new() -> Arc<Self> {
+        let socket = Arc::new(Self { identifier: NEXT_IDENTIFIER.fetch_add(1, Relaxed), replies: Mutex::new(VecDeque::new()), readable: WaitQueue::new() });
+        SOCKETS.lock().push(Arc::downgrade(&socket));
+
+        return socket;
+    }
+
+    fn push_reply(&self, source: Ipv4Address, message: &[u8]) {
+        let mut replies = self.replies.lock();
+        if replies.len() < MAX_QUEUED_REPLIES {
+            replies.push_back((source, message.to_vec()));
+        }
+
+        drop(replies);
+        self.readable.notify_all();
+    }
+}
+
+impl Socket for EchoSocket {
+    /// `data` must be an echo request (including the ICMP header). Its identifier and checksum are filled in.
+    fn send_to(&self, data: &[u8], destination: SocketAddress) -> Result<usize> {
+        if data.len() < HEADER_SIZE || data[0] != TYPE_ECHO_REQUEST || data[1] != 0 {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let mut request = data.to_vec();
+        request[2..4].fill(0);
+        request[4..6].copy_from_slice(&self.identifier.to_be_bytes());
+
+        let request_checksum = checksum(&request, 0);
+        request[2..4].copy_from_slice(&request_checksum.to_be_bytes());
+        ipv4::send(destination.address, PROTOCOL_ICMP, &request)?;
+
+        return Ok(data.len());
+    }
+
+    /// Receive an echo reply (including the ICMP header).
+    fn receive_from(&self, buffer: &mut [u8]) -> Result<(usize, SocketAddress)> {
+        let mut replies = self.replies.lock();
+        loop {
+            if let Some((source, reply)) = replies.pop_front() {
+                let count = buffer.len().min(reply.len());
+                buffer[..count].copy_from_slice(&reply[..count]);
+
+                return Ok((count, SocketAddress::new(source, 0)));
+            }
+
+            self.readable.wait(replies);
+            replies = self.replies.lock();
+        }
+    }
+}
+
+impl Pollable for EchoSocket {
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        let replies = self.replies.lock();
+        let mut ready = PollEvents::WRITABLE;
+        if !replies.is_empty() {
+            ready |= PollEvents::READABLE;
+        }
+
+        // Checked while holding the lock, so that no reply between checking and registering is missed
+        return poll::ready_or_register(ready, events, waiter, &[(PollEvents::READABLE, &self.readable)]);
+    }
+}
@@ -0,0 +1,270 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicU16;
+use core::sync::atomic::Ordering::Relaxed;
+use syscall::error::Errno;
+use crate::net::inet::{arp, checksum, icmp, route, tcp, udp};
+use crate::net::{Interface, Ipv4Address, Result};
+use crate::sync::Mutex;
+use crate::{net, timer};
+
+pub const HEADER_SIZE: usize = 20;
+/// Largest packet (header and payload), that can be described by the total length field.
+pub const MAX_PACKET_SIZE: usize = 65535;
+
+pub const PROTOCOL_ICMP: u8 = 1;
+pub const PROTOCOL_TCP: u8 = 6;
+pub const PROTOCOL_UDP: u8 = 17;
+
+const VERSION: u8 = 4;
+const DEFAULT_TTL: u8 = 64;
+const FLAG_MORE_FRAGMENTS: u16 = 1 << 13;
+/// Fragment offsets are given in units of 8 bytes.
+const FRAGMENT_OFFSET_MASK: u16 = 0x1fff;
+
+/// Incomplete packets are dropped after this time (as recommended by RFC 791).
+const REASSEMBLY_TIMEOUT_MS: usize = 30000;
+const MAX_REASSEMBLIES: usize = 16;
+
+static NEXT_IDENTIFICATION: AtomicU16 = AtomicU16::new(1);
+static REASSEMBLER: Mutex<Reassembler> = Mutex::new(Reassembler::new());
+
+/// Header fields of a received packet, which are needed by the protocols above.
+#[derive(Copy, Clone)]
+pub struct Header {
+    pub source: Ipv4Address,
+    pub destination: Ipv4Address,
+    pub protocol: u8,
+    pub identification: u16,
+    /// Offset of the payload in the original packet (in bytes).
+    pub fragment_offset: usize,
+    pub more_fragments: bool,
+}
+
+/// Collects the fragments of packets, until they are complete.
+pub struct Reassembler {
+    packets: Vec<Reassembly>,
+}
+
+struct Reassembly {
+    header: Header,
+    payload: Vec<u8>,
+    /// Tells for each 8 byte block of the payload, whether it has been received.
+    received: Vec<bool>,
+    /// Known, once the last fragment has been received.
+    length: Option<usize>,
+    started_ms: usize,
+}
+
+/// Process a received packet. Fragments are reassembled, before the payload is passed to its protocol.
+pub fn receive(interface: &Arc<Interface>, packet: &[u8]) {
+    let Some((header, payload)) = parse(packet) else {
+        return;
+    };
+
+    // Packets for other hosts are not forwarded
+    if !accepts(interface, header.destination) {
+        return;
+    }
+
+    if header.fragment_offset == 0 && !header.more_fragments {
+        deliver(interface, &header, payload);
+        return;
+    }
+
+    let now = timer().read().systime_ms();
+    let reassembled = REASSEMBLER.lock().add(&header, payload, now);
+    if let Some(payload) = reassembled {
+        deliver(interface, &header, &payload);
+    }
+}
+
+/// Send `payload` to `destination`, choosing the interface and the source address via `route()`.
+pub fn send(destination: Ipv4Address, protocol: u8, payload: &[u8]) -> Result<()> {
+    let (interface, next_hop) = route(destination)?;
+    let source = interface.ipv4().map_or(Ipv4Address::UNSPECIFIED, |cidr| cidr.address());
+
+    return send_via(&interface, next_hop, source, destination, protocol, payload);
+}
+
+/// Send `payload` from a given `source` address (e.g. the local address of a connection) to `destination`.
+pub fn send_from(source: Ipv4Address, destination: Ipv4Address, protocol: u8, payload: &[u8]) -> Result<()> {
+    let (interface, next_hop) = route(destination)?;
+    return send_via(&interface, next_hop, source, destination, protocol, payload);
+}
+
+/// Send `payload` from `source` to `destination` via `interface`, where `next_hop` is the host the packet is passed to
+/// (`destination` itself, if it is on the same network). Payloads, which exceed the MTU, are fragmented.
+pub fn send_via(interface: &Arc<Interface>, next_hop: Ipv4Address, source: Ipv4Address, destination: Ipv4Address, protocol: u8, payload: &[u8]) -> Result<()> {
+    if payload.len() > MAX_PACKET_SIZE - HEADER_SIZE {
+        return Err(Errno::MessageTooLong);
+    }
+
+    // All fragments, except for the last one, must carry a multiple of 8 bytes
+    let max_fragment_size = (interface.mtu() - HEADER_SIZE) & !7;
+    let identification = NEXT_IDENTIFICATION.fetch_add(1, Relaxed);
+
+    let mut offset = 0;
+    loop {
+        let length = max_fragment_size.min(payload.len() - offset);
+        let more_fragments = offset + length < payload.len();
+        let flags = (offset / 8) as u16 | if more_fragments { FLAG_MORE_FRAGMENTS } else { 0 };
+
+        let mut packet = Vec::with_capacity(HEADER_SIZE + length);
+        packet.extend_from_slice(&[VERSION << 4 | (HEADER_SIZE / 4) as u8, 0]);
+        packet.extend_from_slice(&((HEADER_SIZE + length) as u16).to_be_bytes());
+        packet.extend_from_slice(&identification.to_be_bytes());
+        packet.extend_from_slice(&flags.to_be_bytes());
+        packet.extend_from_slice(&[DEFAULT_TTL, protocol, 0, 0]);
+        packet.extend_from_slice(&source.0);
+        packet.extend_from_slice(&destination.0);
+
+        let header_checksum = checksum(&packet, 0);
+        packet[10..12].copy_from_slice(&header_checksum.to_be_bytes());
+        packet.extend_from_slice(&payload[offset..offset + length]);
+
+        arp::send(interface, next_hop, packet)?;
+
+        offset += length;
+        if !more_fragments {
+            return Ok(());
+        }
+    }
+}
+
+/// Choose the interface for sending to `destination` and return it together with the next hop.
+/// Addresses of this host are reached via the loopback interface, all others via the most specific route (see `route::lookup()`).
+pub fn route(destination: Ipv4Address) -> Result<(Arc<Interface>, Ipv4Address)> {
+    let interfaces = net::interfaces();
+    let is_local = destination.is_loopback() || interfaces.iter().any(|interface| interface.ipv4().is_some_and(|cidr| cidr.address() == destination));
+    if is_local {
+        return interfaces.into_iter().find(|interface| interface.is_loopback()).map(|interface| (interface, destination)).ok_or(Errno::NetworkUnreachable);
+    }
+
+    return route::lookup(destination).ok_or(Errno::NetworkUnreachable);
+}
+
+/// Source address for packets to `destination` (the address of the interface chosen by `route()`).
+pub fn source_address(destination: Ipv4Address) -> Result<Ipv4Address> {
+    let (interface, _) = route(destination)?;
+    return interface.ipv4().map(|cidr| cidr.address()).ok_or(Errno::NetworkUnreachable);
+}
+
+/// Sum of the pseudo header, which is included in the checksum of TCP and UDP (RFC 793, section 3.1).
+pub fn pseudo_header_sum(source: Ipv4Address, destination: Ipv4Address, protocol: u8, length: usize) -> u32 {
+    let word = |bytes: &[u8]| u16::from_be_bytes([bytes[0], bytes[1]]) as u32;
+    return word(&source.0[0..2]) + word(&source.0[2..4]) + word(&destination.0[0..2]) + word(&destination.0[2..4])
+        + protocol as u32 + length as u32;
+}
+
+/// Drop incomplete packets, whose fragments have not all arrived in time (called periodically by the stack).
+pub fn expire(now_ms: usize) {
+    REASSEMBLER.lock().expire(now_ms);
+}
+
+impl Reassembler {
+    pub const fn new() -> Self {
+        Self { packets: Vec::new() }
+    }
+
+    /// Add a fragment (described by `header`) and return the payload of the whole packet, once all fragments have been received.
+    pub fn add(&mut self, header: &Header, payload: &[u8], now_ms: usize) -> Option<Vec<u8>> {
+        let start = header.fragment_offset;
+        let end = start + payload.len();
+        if end > MAX_PACKET_SIZE - HEADER_SIZE || (header.more_fragments && payload.len() % 8 != 0) {
+            return None;
+        }
+
+        let index = match self.packets.iter().position(|packet| packet.belongs_to(header)) {
+            Some(index) => index,
+            None => {
+                // The oldest packet makes room, if too many are incomplete at once
+                if self.packets.len() == MAX_REASSEMBLIES {
+                    self.packets.remove(0);
+                }
+
+                self.packets.push(Reassembly { header: *header, payload: Vec::new(), received: Vec::new(), length: None, started_ms: now_ms });
+                self.packets.len() - 1
+            }
+        };
+
+        let packet = &mut self.packets[index];
+        if packet.payload.len() < end {
+            packet.payload.resize(end, 0);
+            packet.received.resize(end.div_ceil(8), false);
+        }
+
+        // Overlapping fragments overwrite each other (the last one wins)
+        packet.payload[start..end].copy_from_slice(payload);
+        packet.received[start / 8..end.div_ceil(8)].fill(true);
+        if !header.more_fragments {
+            packet.length = Some(end);
+        }
+
+        if packet.length != Some(packet.payload.len()) || packet.received.contains(&false) {
+            return None;
+        }
+
+        return Some(self.packets.remove(index).payload);
+    }
+
+    pub fn expire(&mut self, now_ms: usize) {
+        self.packets.retain(|packet| now_ms - packet.started_ms < REASSEMBLY_TIMEOUT_MS);
+    }
+
+    pub fn incomplete_count(&self) -> usize {
+        return self.packets.len();
+    }
+}
+
+impl Reassembly {
+    fn belongs_to(&self, header: &Header) -> bool {
+        return self.header.source == header.source && self.header.destination == header.destination
+            && self.header.identification == header.identification && self.header.protocol == header.protocol;
+    }
+}
+
+/// Validate the header of `packet` and return it together with the payload.
+pub fn parse(packet: &[u8]) -> Option<(Header, &[u8])> {
+    if packet.len() < HEADER_SIZE || packet[0] >> 4 != VERSION {
+        return None;
+    }
+
+    // The header may contain options (which are ignored), but frames may be padded beyond the total length
+    let header_length = (packet[0] & 0x0f) as usize * 4;
+    let total_length = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    if header_length < HEADER_SIZE || total_length < header_length || total_length > packet.len() || checksum(&packet[..header_length], 0) != 0 {
+        return None;
+    }
+
+    let flags = u16::from_be_bytes([packet[6], packet[7]]);
+    let header = Header {
+        source: Ipv4Address(packet[12..16].try_into().unwrap()),
+        destination: Ipv4Address(packet[16..20].try_into().unwrap()),
+        protocol: packet[9],
+        identification: u16::from_be_bytes([packet[4], packet[5]]),
+        fragment_offset: (flags & FRAGMENT_OFFSET_MASK) as usize * 8,
+        more_fragments: flags & FLAG_MORE_FRAGMENTS != 0,
+    };
+
+    return Some((header, &packet[header_length..total_length]));
+}
+
+/// Check, if packets for `destination` are meant for this host. Unconfigured interfaces accept all packets
+/// (e.g. DHCP replies, which are sent to the offered address).
+fn accepts(interface: &Interface, destination: Ipv4Address) -> bool {
+    return match interface.ipv4() {
+        _ if interface.is_loopback() || destination.is_broadcast() => true,
+        Some(cidr) => destination == cidr.address() || destination == cidr.broadcast(),
+        None => true
+    };
+}
+
+fn deliver(interface: &Arc<Interface>, header: &Header, payload: &[u8]) {
+    match header.protocol {
+        PROTOCOL_ICMP => icmp::receive(header, payload),
+        PROTOCOL_TCP => tcp::receive(header, payload),
+        PROTOCOL_UDP => udp::receive(interface, header, payload),
+        _ => {}
+    }
+}
@@ -0,0 +1,111 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use syscall::error::Errno;
+use syscall::ioctl::{decode_interface_name, InterfaceAddress, IoctlRequest, RouteEntry};
+use crate::interrupt::deferred;
+use crate::net::socket::{Protocol, Socket, SocketType};
+use crate::net::inet::route::Route;
+use crate::net::{Interface, Ipv4Address, Ipv4Cidr, Result, Stack};
+use crate::{net, timer};
+
+pub mod arp;
+pub mod dhcp;
+pub mod ethernet;
+pub mod icmp;
+pub mod ipv4;
+pub mod route;
+pub mod tcp;
+pub mod udp;
+
+/// Interval, in which timeouts (ARP requests and cache entries, IPv4 reassembly, TCP retransmissions, DHCP leases) are checked.
+/// It limits the precision of retransmission timeouts, so it is kept well below their minimum.
+const TICK_INTERVAL_MS: usize = 100;
+
+/// The kernel's own protocol stack (Ethernet, ARP, IPv4, ICMP, TCP, UDP and DHCP).
+pub struct Inet;
+
+static INET: Inet = Inet;
+
+/// Install the stack and start checking its timeouts.
+pub fn init() {
+    net::set_stack(&INET);
+
+    // Timer callbacks must not block, so timeouts are handled by the kernel worker thread (like received frames)
+    timer::schedule_periodic(TICK_INTERVAL_MS, Box::new(|| deferred::schedule_work(Box::new(tick))));
+}
+
+impl Stack for Inet {
+    fn receive(&self, interface: &Arc<Interface>, frame: &[u8]) {
+        ethernet::receive(interface, frame);
+    }
+
+    fn socket(&self, typ: SocketType, protocol: Protocol) -> Result<Arc<dyn Socket>> {
+        return match (typ, protocol) {
+            (SocketType::Datagram, Protocol::Icmp) => Ok(icmp::EchoSocket::new()),
+            (SocketType::Stream, Protocol::Tcp) => Ok(tcp::TcpSocket::new()),
+            (SocketType::Datagram, Protocol::Udp) => Ok(udp::UdpSocket::new()),
+            _ => Err(Errno::NotSupported)
+        };
+    }
+
+    /// Change routes and interface addresses. Pointer arguments point into the calling process.
+    fn ioctl(&self, request: usize, arg: usize) -> Result<usize> {
+        return match IoctlRequest::try_from(request) {
+            Ok(IoctlRequest::AddRoute) => {
+                let entry = unsafe { (arg as *const RouteEntry).read() };
+                let destination = Ipv4Cidr::new(Ipv4Address(entry.destination), entry.prefix_length).ok_or(Errno::InvalidArgument)?;
+                let gateway = Some(Ipv4Address(entry.gateway)).filter(|gateway| !gateway.is_unspecified());
+                let interface = decode_interface_name(&entry.interface).and_then(net::interface).ok_or(Errno::NoDevice)?;
+
+                route::add(Route { destination, gateway, interface }).map(|_| 0)
+            }
+            Ok(IoctlRequest::DeleteRoute) => {
+                let entry = unsafe { (arg as *const RouteEntry).read() };
+                let destination = Ipv4Cidr::new(Ipv4Address(entry.destination), entry.prefix_length).ok_or(Errno::InvalidArgument)?;
+
+                route::remove(destination).map(|_| 0)
+            }
+            Ok(IoctlRequest::SetInterfaceAddress) => {
+                let address = unsafe { (arg as *const InterfaceAddress).read() };
+                let interface = decode_interface_name(&address.interface).and_then(net::interface).ok_or(Errno::NoDevice)?;
+                if interface.is_loopback() {
+                    return Err(Errno::InvalidArgument);
+                }
+
+                let cidr = match Ipv4Address(address.address) {
+                    address if address.is_unspecified() => None,
+                    ip => Some(Ipv4Cidr::new(ip, address.prefix_length).ok_or(Errno::InvalidArgument)?)
+                };
+
+                // Static addresses replace those assigned by DHCP
+                dhcp::stop(&interface);
+                route::configure(&interface, cidr);
+                Ok(0)
+            }
+            _ => Err(Errno::NotATerminal)
+        };
+    }
+}
+
+/// Internet checksum (RFC 1071) of `data`, continuing the sum `initial` (e.g. the sum of a pseudo header).
+/// Checking data, which includes its checksum, results in 0.
+pub fn checksum(data: &[u8], initial: u32) -> u16 {
+    let mut sum = initial;
+    for chunk in data.chunks(2) {
+        sum += u16::from_be_bytes([chunk[0], chunk.get(1).copied().unwrap_or(0)]) as u32;
+    }
+
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    return !(sum as u16);
+}
+
+fn tick() {
+    let now = timer().read().systime_ms();
+    arp::expire(now);
+    ipv4::expire(now);
+    tcp::expire(now);
+    dhcp::expire(now);
+}
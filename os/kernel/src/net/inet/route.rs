@@ -0,0 +1,78 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use syscall::error::Errno;
+use crate::net::{Interface, Ipv4Address, Ipv4Cidr, Result};
+use crate::sync::RwLock;
+
+/// Entry of the routing table. Packets for `destination` are sent via `interface`, either directly (on-link routes)
+/// or to `gateway`, which must be reachable via an on-link route of the same interface.
+#[derive(Clone)]
+pub struct Route {
+    /// Network (the host part is always zero), the default route is '0.0.0.0/0'.
+    pub destination: Ipv4Cidr,
+    pub gateway: Option<Ipv4Address>,
+    pub interface: Arc<Interface>,
+}
+
+/// Routes ordered by descending prefix length, so that the first match is the most specific one.
+static ROUTES: RwLock<Vec<Route>> = RwLock::new(Vec::new());
+
+/// Choose the most specific route for `destination` and return its interface together with the next hop.
+pub fn lookup(destination: Ipv4Address) -> Option<(Arc<Interface>, Ipv4Address)> {
+    return ROUTES.read().iter()
+        .find(|route| route.destination.contains(destination))
+        .map(|route| (Arc::clone(&route.interface), route.gateway.unwrap_or(destination)));
+}
+
+/// Add a route. Fails with `Errno::AlreadyExists`, if there is already a route for the same network,
+/// and with `Errno::NetworkUnreachable`, if the gateway is not on the network of the interface.
+pub fn add(route: Route) -> Result<()> {
+    let route = Route { destination: network(route.destination), ..route };
+    if let Some(gateway) = route.gateway {
+        if !route.interface.ipv4().is_some_and(|cidr| cidr.contains(gateway)) {
+            return Err(Errno::NetworkUnreachable);
+        }
+    }
+
+    let mut routes = ROUTES.write();
+    if routes.iter().any(|other| other.destination == route.destination) {
+        return Err(Errno::AlreadyExists);
+    }
+
+    let index = routes.iter().position(|other| other.destination.prefix_length() < route.destination.prefix_length()).unwrap_or(routes.len());
+    routes.insert(index, route);
+
+    return Ok(());
+}
+
+/// Remove the route for the network `destination`.
+pub fn remove(destination: Ipv4Cidr) -> Result<()> {
+    let destination = network(destination);
+    let mut routes = ROUTES.write();
+    let index = routes.iter().position(|route| route.destination == destination).ok_or(Errno::NotFound)?;
+    routes.remove(index);
+
+    return Ok(());
+}
+
+pub fn routes() -> Vec<Route> {
+    return ROUTES.read().clone();
+}
+
+/// Assign `cidr` to `interface` (or remove its address, if `None`). All routes via the interface are removed
+/// (since their gateways may not be reachable anymore) and an on-link route for the new network is added.
+pub fn configure(interface: &Arc<Interface>, cidr: Option<Ipv4Cidr>) {
+    let mut routes = ROUTES.write();
+    routes.retain(|route| !Arc::ptr_eq(&route.interface, interface));
+    interface.set_ipv4(cidr);
+    drop(routes);
+
+    if let Some(cidr) = cidr {
+        // The network may already be reachable via another interface, in which case that route is kept
+        let _ = add(Route { destination: cidr, gateway: None, interface: Arc::clone(interface) });
+    }
+}
+
+fn network(cidr: Ipv4Cidr) -> Ipv4Cidr {
+    return Ipv4Cidr::new(cidr.network(), cidr.prefix_length()).unwrap();
+}
@@ -0,0 +1,1055 @@
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::ptr;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering::Relaxed;
+use syscall::error::Errno;
+use syscall::file::PollEvents;
+use syscall::net::MAX_BACKLOG;
+use crate::fs::poll;
+use crate::fs::poll::Pollable;
+use crate::net::inet::ipv4::{Header, PROTOCOL_TCP};
+use crate::net::inet::{checksum, ipv4};
+use crate::net::socket::{Socket, SocketAddress};
+use crate::net::{Ipv4Address, Result};
+use crate::process::wait_queue::{WaitQueue, Waiter};
+use crate::sync::Mutex;
+use crate::{net, random, timer};
+
+pub const HEADER_SIZE: usize = 20;
+
+const FLAG_FIN: u8 = 0x01;
+const FLAG_SYN: u8 = 0x02;
+const FLAG_RST: u8 = 0x04;
+const FLAG_PSH: u8 = 0x08;
+const FLAG_ACK: u8 = 0x10;
+
+const OPTION_END: u8 = 0;
+const OPTION_NOP: u8 = 1;
+const OPTION_MSS: u8 = 2;
+
+/// Maximum segment size, if the peer does not announce one (RFC 879).
+const DEFAULT_MSS: usize = 536;
+/// Size of the send and the receive buffer of each connection (the receive buffer limits the announced window).
+const BUFFER_SIZE: usize = 16384;
+
+// Retransmission timeout (RFC 6298)
+const INITIAL_RTO_MS: usize = 1000;
+const MIN_RTO_MS: usize = 200;
+const MAX_RTO_MS: usize = 60000;
+/// The connection is aborted, if a segment is not acknowledged after this many retransmissions.
+/// Probes of a closed window do not count, since they are acknowledged by the peer (see `Connection::on_persist_timeout()`).
+const MAX_RETRANSMISSIONS: usize = 8;
+
+/// Maximum segment lifetime. Closed connections stay in TIME-WAIT for twice this time, so that late segments are not mistaken for a new connection.
+const MSL_MS: usize = 30000;
+/// Closed connections are dropped, if the peer does not close its side in time (like Linux's 'tcp_fin_timeout').
+const FIN_WAIT_TIMEOUT_MS: usize = 60000;
+
+const EPHEMERAL_PORT_START: u16 = 49152;
+const EPHEMERAL_PORT_COUNT: usize = 16384;
+
+static CONNECTIONS: Mutex<Vec<Arc<Connection>>> = Mutex::new(Vec::new());
+static LISTENERS: Mutex<Vec<Arc<Listener>>> = Mutex::new(Vec::new());
+static NEXT_EPHEMERAL_PORT: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum State {
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+    Closed,
+}
+
+/// Stream socket. It becomes either a listening socket (via `listen()`) or a connected socket (via `connect()` or `accept()`).
+pub struct TcpSocket {
+    state: Mutex<SocketState>,
+}
+
+enum SocketState {
+    Unbound,
+    Bound(SocketAddress),
+    Listening(Arc<Listener>),
+    Connected(Arc<Connection>),
+}
+
+/// Passive endpoint, which creates connections for incoming SYN segments.
+struct Listener {
+    local: SocketAddress,
+    backlog: usize,
+    state: Mutex<ListenerState>,
+    readable: WaitQueue,
+}
+
+struct ListenerState {
+    /// Established connections, which have not been accepted yet.
+    established: VecDeque<Arc<Connection>>,
+    /// Connections, whose handshake has not been completed yet (also limited by the backlog).
+    half_open: usize,
+}
+
+/// A connection, identified by its local and remote address. Connections stay registered after their socket has been closed,
+/// until the connection has been shut down (including TIME-WAIT).
+struct Connection {
+    local: SocketAddress,
+    remote: SocketAddress,
+    tcb: Mutex<Tcb>,
+    readable: WaitQueue,
+    writable: WaitQueue,
+}
+
+/// Transmission control block (connection state, as described by RFC 793).
+struct Tcb {
+    state: State,
+    /// Oldest unacknowledged sequence number (the first byte in `send_buffer`).
+    send_unacknowledged: u32,
+    send_next: u32,
+    send_window: usize,
+    receive_next: u32,
+    /// Data, which has not been acknowledged yet (already sent data first).
+    send_buffer: VecDeque<u8>,
+    receive_buffer: VecDeque<u8>,
+    /// Window, which has been announced in the last segment.
+    announced_window: usize,
+    mss: usize,
+    /// The socket has been closed, so that a FIN is sent after the remaining data.
+    closing: bool,
+    fin_sent: bool,
+    fin_received: bool,
+    error: Option<Errno>,
+    rto_ms: usize,
+    smoothed_rtt_ms: Option<usize>,
+    rtt_variation_ms: usize,
+    /// Sequence number and send time of the segment, which is used for measuring the round trip time.
+    rtt_sample: Option<(u32, usize)>,
+    /// Retransmission, FIN-WAIT-2 or TIME-WAIT timeout.
+    deadline_ms: Option<usize>,
+    retransmissions: usize,
+    /// Persist timeout for probing the peer's window, while it is closed and nothing is in flight (RFC 9293, section 3.8.6.1).
+    persist_deadline_ms: Option<usize>,
+    /// Doubled after each probe (like the retransmission timeout), but the connection is never aborted.
+    persist_interval_ms: usize,
+    /// Set for connections, which have been created by a listener and have not been established yet.
+    listener: Option<Weak<Listener>>,
+}
+
+struct Segment<'a> {
+    source_port: u16,
+    destination_port: u16,
+    sequence: u32,
+    acknowledgment: u32,
+    flags: u8,
+    window: u16,
+    mss: Option<usize>,
+    payload: &'a [u8],
+}
+
+/// Pass a received segment to its connection or listener. Segments, which do not belong to any of them, are answered with a reset.
+pub fn receive(header: &Header, data: &[u8]) {
+    let Some(segment) = Segment::parse(header, data) else {
+        return;
+    };
+
+    let local = SocketAddress::new(header.destination, segment.destination_port);
+    let remote = SocketAddress::new(header.source, segment.source_port);
+    let now = timer().read().systime_ms();
+
+    let connection = CONNECTIONS.lock().iter().find(|connection| connection.local == local && connection.remote == remote).cloned();
+    if let Some(connection) = connection {
+        let mut tcb = connection.tcb.lock();
+        connection.process(&mut tcb, &segment, now);
+        return;
+    }
+
+    let listener = LISTENERS.lock().iter()
+        .find(|listener| listener.local.port == local.port && (listener.local.address.is_unspecified() || listener.local.address == local.address))
+        .cloned();
+
+    match listener {
+        Some(listener) if segment.flags & (FLAG_SYN | FLAG_ACK | FLAG_RST) == FLAG_SYN => listener.open(local, remote, &segment, now),
+        _ if segment.flags & FLAG_RST == 0 => send_reset(local, remote, &segment),
+        _ => {}
+    }
+}
+
+/// Retransmit unacknowledged segments, probe closed windows and remove closed connections, whose timeout has expired
+/// (called periodically by the stack).
+pub fn expire(now_ms: usize) {
+    let connections = CONNECTIONS.lock().clone();
+    for connection in connections {
+        let mut tcb = connection.tcb.lock();
+        if tcb.deadline_ms.is_some_and(|deadline| deadline <= now_ms) {
+            connection.on_timeout(&mut tcb, now_ms);
+        }
+        if tcb.persist_deadline_ms.is_some_and(|deadline| deadline <= now_ms) {
+            connection.on_persist_timeout(&mut tcb, now_ms);
+        }
+    }
+}
+
+impl TcpSocket {
+    pub fn new() -> Arc<Self> {
+        return Arc::new(Self { state: Mutex::new(SocketState::Unbound) });
+    }
+
+    fn from_connection(connection: Arc<Connection>) -> Arc<Self> {
+        return Arc::new(Self { state: Mutex::new(SocketState::Connected(connection)) });
+    }
+
+    fn connection(&self) -> Result<Arc<Connection>> {
+        return match &*self.state.lock() {
+            SocketState::Connected(connection) => Ok(Arc::clone(connection)),
+            _ => Err(Errno::NotConnected)
+        };
+    }
+}
+
+impl Socket for TcpSocket {
+    fn bind(&self, address: SocketAddress) -> Result<()> {
+        let mut state = self.state.lock();
+        if !matches!(*state, SocketState::Unbound) {
+            return Err(Errno::InvalidArgument);
+        }
+
+        if !address.address.is_unspecified() && !net::interfaces().iter().any(|interface| interface.ipv4().is_some_and(|cidr| cidr.address() == address.address)) {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let port = match address.port {
+            0 => allocate_port()?,
+            port if is_port_in_use(port) => return Err(Errno::AddressInUse),
+            port => port
+        };
+
+        *state = SocketState::Bound(SocketAddress::new(address.address, port));
+        return Ok(());
+    }
+
+    fn connect(&self, address: SocketAddress) -> Result<()> {
+        if address.port == 0 || address.address.is_unspecified() || address.address.is_broadcast() || address.address.is_multicast() {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let connection = {
+            let mut state = self.state.lock();
+            let local_port = match &*state {
+                SocketState::Unbound => allocate_port()?,
+                SocketState::Bound(local) => local.port,
+                SocketState::Listening(_) => return Err(Errno::InvalidArgument),
+                SocketState::Connected(_) => return Err(Errno::IsConnected)
+            };
+
+            // The source address is the address of the interface, which is used for reaching the peer
+            let (interface, _) = ipv4::route(address.address)?;
+            let local_address = if address.address.is_loopback() { address.address } else { interface.ipv4().ok_or(Errno::NetworkUnreachable)?.address() };
+            let local = SocketAddress::new(local_address, local_port);
+
+            let connection = Connection::new(local, address, Tcb::new(State::SynSent, None));
+            *state = SocketState::Connected(Arc::clone(&connection));
+            connection
+        };
+
+        let now = timer().read().systime_ms();
+        let mut tcb = connection.tcb.lock();
+        CONNECTIONS.lock().push(Arc::clone(&connection));
+        let sequence = tcb.send_unacknowledged;
+        connection.send_segment(&mut tcb, FLAG_SYN, sequence, &[]);
+        tcb.deadline_ms = Some(now + tcb.rto_ms);
+
+        // Established connections are writable, failed connections are closed
+        while tcb.state == State::SynSent || tcb.state == State::SynReceived {
+            connection.writable.wait(tcb);
+            tcb = connection.tcb.lock();
+        }
+
+        return match tcb.error {
+            Some(error) => Err(error),
+            None if tcb.state == State::Closed => Err(Errno::ConnectionRefused),
+            None => Ok(())
+        };
+    }
+
+    fn listen(&self, backlog: usize) -> Result<()> {
+        let mut state = self.state.lock();
+        let local = match &*state {
+            SocketState::Unbound => SocketAddress::new(Ipv4Address::UNSPECIFIED, allocate_port()?),
+            SocketState::Bound(local) => *local,
+            SocketState::Listening(_) => return Ok(()),
+            SocketState::Connected(_) => return Err(Errno::IsConnected)
+        };
+
+        let listener = Arc::new(Listener {
+            local,
+            backlog: backlog.clamp(1, MAX_BACKLOG),
+            state: Mutex::new(ListenerState { established: VecDeque::new(), half_open: 0 }),
+            readable: WaitQueue::new(),
+        });
+
+        let mut listeners = LISTENERS.lock();
+        if listeners.iter().any(|other| other.local.port == local.port) {
+            return Err(Errno::AddressInUse);
+        }
+
+        listeners.push(Arc::clone(&listener));
+        *state = SocketState::Listening(listener);
+
+        return Ok(());
+    }
+
+    fn accept(&self) -> Result<(Arc<dyn Socket>, SocketAddress)> {
+        let listener = match &*self.state.lock() {
+            SocketState::Listening(listener) => Arc::clone(listener),
+            _ => return Err(Errno::InvalidArgument)
+        };
+
+        let mut state = listener.state.lock();
+        loop {
+            if let Some(connection) = state.established.pop_front() {
+                let remote = connection.remote;
+                return Ok((TcpSocket::from_connection(connection), remote));
+            }
+
+            listener.readable.wait(state);
+            state = listener.state.lock();
+        }
+    }
+
+    fn send(&self, data: &[u8]) -> Result<usize> {
+        return self.connection()?.send(data);
+    }
+
+    fn receive(&self, buffer: &mut [u8]) -> Result<usize> {
+        return self.connection()?.receive(buffer);
+    }
+
+    fn shutdown(&self) -> Result<()> {
+        self.connection()?.close();
+        return Ok(());
+    }
+}
+
+impl Pollable for TcpSocket {
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        return match &*self.state.lock() {
+            SocketState::Listening(listener) => listener.poll(events, waiter),
+            SocketState::Connected(connection) => connection.poll(events, waiter),
+            _ => PollEvents::HANG_UP
+        };
+    }
+}
+
+impl Drop for TcpSocket {
+    /// Closing a connected socket starts shutting down the connection (which continues in the background).
+    /// Closing a listening socket resets all connections, which have not been accepted yet.
+    fn drop(&mut self) {
+        match &*self.state.lock() {
+            SocketState::Connected(connection) => connection.close(),
+            SocketState::Listening(listener) => {
+                LISTENERS.lock().retain(|other| !Arc::ptr_eq(other, listener));
+                let established = core::mem::take(&mut listener.state.lock().established);
+                for connection in established {
+                    connection.abort();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Listener {
+    /// Create a connection for an incoming SYN and answer it (unless the backlog is full, so that the peer tries again later).
+    fn open(self: &Arc<Self>, local: SocketAddress, remote: SocketAddress, segment: &Segment, now: usize) {
+        {
+            let mut state = self.state.lock();
+            if state.established.len() + state.half_open >= self.backlog {
+                return;
+            }
+
+            state.half_open += 1;
+        }
+
+        let connection = Connection::new(local, remote, Tcb::new(State::SynReceived, Some(Arc::downgrade(self))));
+        let mut tcb = connection.tcb.lock();
+        tcb.receive_next = segment.sequence.wrapping_add(1);
+        tcb.send_window = segment.window as usize;
+        tcb.mss = segment.mss.unwrap_or(DEFAULT_MSS).min(local_mss(remote.address));
+
+        CONNECTIONS.lock().push(Arc::clone(&connection));
+        let sequence = tcb.send_unacknowledged;
+        connection.send_segment(&mut tcb, FLAG_SYN | FLAG_ACK, sequence, &[]);
+        tcb.deadline_ms = Some(now + tcb.rto_ms);
+    }
+
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        let state = self.state.lock();
+        let ready = if state.established.is_empty() { PollEvents::empty() } else { PollEvents::READABLE };
+
+        return poll::ready_or_register(ready, events, waiter, &[(PollEvents::READABLE, &self.readable)]);
+    }
+}
+
+impl Connection {
+    fn new(local: SocketAddress, remote: SocketAddress, tcb: Tcb) -> Arc<Self> {
+        return Arc::new(Self { local, remote, tcb: Mutex::new(tcb), readable: WaitQueue::new(), writable: WaitQueue::new() });
+    }
+
+    fn send(&self, data: &[u8]) -> Result<usize> {
+        let mut tcb = self.tcb.lock();
+        loop {
+            if let Some(error) = tcb.error {
+                return Err(error);
+            }
+            if !matches!(tcb.state, State::Established | State::CloseWait) || tcb.closing {
+                return Err(Errno::BrokenPipe);
+            }
+
+            let free = BUFFER_SIZE - tcb.send_buffer.len();
+            if free > 0 {
+                let count = free.min(data.len());
+                tcb.send_buffer.extend(&data[..count]);
+                self.output(&mut tcb, timer().read().systime_ms());
+
+                return Ok(count);
+            }
+
+            self.writable.wait(tcb);
+            tcb = self.tcb.lock();
+        }
+    }
+
+    fn receive(&self, buffer: &mut [u8]) -> Result<usize> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+
+        let mut tcb = self.tcb.lock();
+        loop {
+            if !tcb.receive_buffer.is_empty() {
+                let count = buffer.len().min(tcb.receive_buffer.len());
+                for (target, byte) in buffer.iter_mut().zip(tcb.receive_buffer.drain(..count)) {
+                    *target = byte;
+                }
+
+                // The peer stops sending, when the window is (almost) closed, so it must be told, that it has opened again
+                if tcb.announced_window < tcb.mss && tcb.receive_window() >= tcb.mss && tcb.state != State::Closed {
+                    self.send_ack(&mut tcb);
+                }
+
+                return Ok(count);
+            }
+
+            if tcb.fin_received {
+                return Ok(0);
+            }
+            if let Some(error) = tcb.error {
+                return Err(error);
+            }
+            if tcb.state == State::Closed {
+                return Ok(0);
+            }
+
+            self.readable.wait(tcb);
+            tcb = self.tcb.lock();
+        }
+    }
+
+    /// Send a FIN after the remaining data (called, when the socket is closed or shut down).
+    fn close(&self) {
+        let mut tcb = self.tcb.lock();
+        match tcb.state {
+            State::SynSent | State::SynReceived => self.terminate(&mut tcb, None),
+            State::Established | State::CloseWait => {
+                tcb.closing = true;
+                self.output(&mut tcb, timer().read().systime_ms());
+            }
+            _ => {}
+        }
+    }
+
+    /// Reset the connection (e.g. if it has not been accepted, before its listening socket has been closed).
+    fn abort(&self) {
+        let mut tcb = self.tcb.lock();
+        if tcb.state != State::Closed {
+            let sequence = tcb.send_next;
+            self.send_segment(&mut tcb, FLAG_RST, sequence, &[]);
+            self.terminate(&mut tcb, Some(Errno::ConnectionReset));
+        }
+    }
+
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        let tcb = self.tcb.lock();
+        let mut ready = PollEvents::empty();
+        if !tcb.receive_buffer.is_empty() || tcb.fin_received || tcb.error.is_some() {
+            ready |= PollEvents::READABLE;
+        }
+        if matches!(tcb.state, State::Established | State::CloseWait) && !tcb.closing && tcb.send_buffer.len() < BUFFER_SIZE {
+            ready |= PollEvents::WRITABLE;
+        }
+        if tcb.error.is_some() {
+            ready |= PollEvents::ERROR;
+        }
+        if tcb.state == State::Closed {
+            ready |= PollEvents::HANG_UP;
+        }
+
+        // Checked while holding the lock, so that no change between checking and registering is missed
+        return poll::ready_or_register(ready, events, waiter, &[(PollEvents::READABLE, &self.readable), (PollEvents::WRITABLE, &self.writable)]);
+    }
+
+    /// Process a segment, which belongs to this connection (following the event processing of RFC 793, section 3.9).
+    fn process(self: &Arc<Self>, tcb: &mut Tcb, segment: &Segment, now: usize) {
+        if tcb.state == State::Closed {
+            return;
+        }
+        if tcb.state == State::SynSent {
+            self.process_syn_sent(tcb, segment, now);
+            return;
+        }
+
+        if !tcb.is_acceptable(segment) {
+            if segment.flags & FLAG_RST == 0 {
+                self.send_ack(tcb);
+            }
+
+            return;
+        }
+
+        if segment.flags & FLAG_RST != 0 {
+            // Connections, which have not been accepted yet or are closing anyway, are closed silently
+            let error = match tcb.state {
+                State::SynReceived | State::Closing | State::LastAck | State::TimeWait => None,
+                _ => Some(Errno::ConnectionReset)
+            };
+
+            self.terminate(tcb, error);
+            return;
+        }
+
+        if segment.flags & FLAG_SYN != 0 {
+            // A SYN inside the window means, that the peer has forgotten the connection
+            let sequence = tcb.send_next;
+            self.send_segment(tcb, FLAG_RST, sequence, &[]);
+            self.terminate(tcb, Some(Errno::ConnectionReset));
+            return;
+        }
+
+        if segment.flags & FLAG_ACK == 0 {
+            return;
+        }
+
+        if tcb.state == State::SynReceived {
+            if !sequence_between(tcb.send_unacknowledged, segment.acknowledgment, tcb.send_next) {
+                send_reset(self.local, self.remote, segment);
+                return;
+            }
+
+            // The SYN has been acknowledged
+            tcb.state = State::Established;
+            tcb.send_unacknowledged = tcb.send_unacknowledged.wrapping_add(1);
+            tcb.retransmissions = 0;
+            tcb.deadline_ms = None;
+            if !self.hand_over_to_listener(tcb) {
+                return;
+            }
+        }
+
+        if sequence_less(tcb.send_next, segment.acknowledgment) {
+            // Acknowledges data, which has not been sent yet
+            self.send_ack(tcb);
+            return;
+        }
+
+        if sequence_less(tcb.send_unacknowledged, segment.acknowledgment) {
+            self.acknowledge(tcb, segment.acknowledgment, now);
+            if tcb.state == State::Closed {
+                return;
+            }
+        }
+
+        tcb.send_window = segment.window as usize;
+
+        let mut needs_ack = false;
+        if !segment.payload.is_empty() && matches!(tcb.state, State::Established | State::FinWait1 | State::FinWait2) {
+            needs_ack = true;
+
+            // Segments are accepted in order only (later segments are retransmitted by the peer after being acknowledged)
+            if !sequence_less(tcb.receive_next, segment.sequence) {
+                let skip = (tcb.receive_next.wrapping_sub(segment.sequence) as usize).min(segment.payload.len());
+                let data = &segment.payload[skip..];
+                let count = data.len().min(BUFFER_SIZE - tcb.receive_buffer.len());
+
+                tcb.receive_buffer.extend(&data[..count]);
+                tcb.receive_next = tcb.receive_next.wrapping_add(count as u32);
+                if count > 0 {
+                    self.readable.notify_all();
+                }
+            }
+        }
+
+        // The FIN is only accepted, once all data before it has been received
+        let fin_sequence = segment.sequence.wrapping_add(segment.payload.len() as u32);
+        if segment.flags & FLAG_FIN != 0 && fin_sequence == tcb.receive_next && !tcb.fin_received {
+            tcb.receive_next = tcb.receive_next.wrapping_add(1);
+            tcb.fin_received = true;
+            needs_ack = true;
+
+            match tcb.state {
+                State::Established => tcb.state = State::CloseWait,
+                State::FinWait1 => tcb.state = State::Closing,
+                State::FinWait2 => self.enter_time_wait(tcb, now),
+                _ => {}
+            }
+
+            self.readable.notify_all();
+        }
+
+        if needs_ack {
+            self.send_ack(tcb);
+        }
+
+        // Acknowledgments may have opened the window for further data
+        self.output(tcb, now);
+    }
+
+    fn process_syn_sent(&self, tcb: &mut Tcb, segment: &Segment, now: usize) {
+        let has_ack = segment.flags & FLAG_ACK != 0;
+        let ack_valid = has_ack && segment.acknowledgment == tcb.send_next;
+        if has_ack && !ack_valid {
+            if segment.flags & FLAG_RST == 0 {
+                send_reset(self.local, self.remote, segment);
+            }
+
+            return;
+        }
+
+        if segment.flags & FLAG_RST != 0 {
+            if ack_valid {
+                self.terminate(tcb, Some(Errno::ConnectionRefused));
+            }
+
+            return;
+        }
+
+        if segment.flags & FLAG_SYN == 0 {
+            return;
+        }
+
+        tcb.receive_next = segment.sequence.wrapping_add(1);
+        tcb.send_window = segment.window as usize;
+        tcb.mss = segment.mss.unwrap_or(DEFAULT_MSS).min(local_mss(self.remote.address));
+
+        if ack_valid {
+            tcb.state = State::Established;
+            tcb.send_unacknowledged = segment.acknowledgment;
+            tcb.retransmissions = 0;
+            tcb.deadline_ms = None;
+            if let Some((_, sent_ms)) = tcb.rtt_sample.take() {
+                tcb.update_rto(now - sent_ms);
+            }
+
+            self.send_ack(tcb);
+            self.writable.notify_all();
+        } else {
+            // Simultaneous open
+            tcb.state = State::SynReceived;
+            let sequence = tcb.send_unacknowledged;
+            self.send_segment(tcb, FLAG_SYN | FLAG_ACK, sequence, &[]);
+        }
+    }
+
+    /// Remove acknowledged data from the send buffer and update the retransmission timeout.
+    fn acknowledge(&self, tcb: &mut Tcb, acknowledgment: u32, now: usize) {
+        let acknowledged = acknowledgment.wrapping_sub(tcb.send_unacknowledged) as usize;
+        let fin_acknowledged = tcb.fin_sent && acknowledgment == tcb.send_next;
+        let data_acknowledged = (acknowledged - fin_acknowledged as usize).min(tcb.send_buffer.len());
+
+        tcb.send_buffer.drain(..data_acknowledged);
+        tcb.send_unacknowledged = acknowledgment;
+        tcb.retransmissions = 0;
+
+        // Karn's algorithm: Only segments, which have not been retransmitted, are measured
+        if let Some((sequence, sent_ms)) = tcb.rtt_sample {
+            if sequence_less(sequence, acknowledgment) {
+                tcb.update_rto(now - sent_ms);
+                tcb.rtt_sample = None;
+            }
+        }
+
+        tcb.deadline_ms = if tcb.send_unacknowledged == tcb.send_next { None } else { Some(now + tcb.rto_ms) };
+        self.writable.notify_all();
+
+        if fin_acknowledged {
+            match tcb.state {
+                State::FinWait1 => {
+                    tcb.state = State::FinWait2;
+                    tcb.deadline_ms = Some(now + FIN_WAIT_TIMEOUT_MS);
+                }
+                State::Closing => self.enter_time_wait(tcb, now),
+                State::LastAck => self.terminate(tcb, None),
+                _ => {}
+            }
+        }
+    }
+
+    /// Send as much of the buffered data as the peer's window allows, followed by a FIN, if the socket has been closed.
+    fn output(&self, tcb: &mut Tcb, now: usize) {
+        if !matches!(tcb.state, State::Established | State::CloseWait | State::FinWait1 | State::Closing | State::LastAck) || tcb.fin_sent {
+            return;
+        }
+
+        loop {
+            let offset = tcb.send_next.wrapping_sub(tcb.send_unacknowledged) as usize;
+            let length = tcb.mss.min(tcb.send_buffer.len() - offset).min(tcb.send_window.saturating_sub(offset));
+            if length == 0 {
+                break;
+            }
+
+            let payload = tcb.send_buffer.range(offset..offset + length).copied().collect::<Vec<u8>>();
+            let sequence = tcb.send_next;
+            self.send_segment(tcb, FLAG_ACK | FLAG_PSH, sequence, &payload);
+
+            if tcb.rtt_sample.is_none() {
+                tcb.rtt_sample = Some((sequence, now));
+            }
+            tcb.send_next = sequence.wrapping_add(length as u32);
+        }
+
+        let offset = tcb.send_next.wrapping_sub(tcb.send_unacknowledged) as usize;
+        if tcb.closing && offset == tcb.send_buffer.len() {
+            let sequence = tcb.send_next;
+            self.send_segment(tcb, FLAG_FIN | FLAG_ACK, sequence, &[]);
+            tcb.send_next = sequence.wrapping_add(1);
+            tcb.fin_sent = true;
+
+            match tcb.state {
+                State::Established => tcb.state = State::FinWait1,
+                State::CloseWait => tcb.state = State::LastAck,
+                _ => {}
+            }
+        }
+
+        // Unsent data, which waits for the window to open, is covered by the persist timer instead of the retransmission timer
+        let in_flight = tcb.send_next != tcb.send_unacknowledged;
+        if in_flight {
+            tcb.persist_deadline_ms = None;
+            if tcb.deadline_ms.is_none() {
+                tcb.deadline_ms = Some(now + tcb.rto_ms);
+            }
+        } else if offset < tcb.send_buffer.len() {
+            if tcb.persist_deadline_ms.is_none() {
+                tcb.persist_interval_ms = tcb.rto_ms;
+                tcb.persist_deadline_ms = Some(now + tcb.persist_interval_ms);
+            }
+        } else {
+            tcb.persist_deadline_ms = None;
+        }
+    }
+
+    fn on_timeout(&self, tcb: &mut Tcb, now: usize) {
+        tcb.deadline_ms = None;
+        match tcb.state {
+            State::FinWait2 | State::TimeWait => {
+                self.terminate(tcb, None);
+                return;
+            }
+            State::Closed => return,
+            _ => {}
+        }
+
+        if tcb.send_next == tcb.send_unacknowledged {
+            return;
+        }
+
+        tcb.retransmissions += 1;
+        if tcb.retransmissions > MAX_RETRANSMISSIONS {
+            let sequence = tcb.send_next;
+            self.send_segment(tcb, FLAG_RST, sequence, &[]);
+            self.terminate(tcb, Some(Errno::TimedOut));
+            return;
+        }
+
+        tcb.rto_ms = (tcb.rto_ms * 2).min(MAX_RTO_MS);
+        tcb.rtt_sample = None;
+
+        match tcb.state {
+            State::SynSent => {
+                let sequence = tcb.send_unacknowledged;
+                self.send_segment(tcb, FLAG_SYN, sequence, &[]);
+            }
+            State::SynReceived => {
+                let sequence = tcb.send_unacknowledged;
+                self.send_segment(tcb, FLAG_SYN | FLAG_ACK, sequence, &[]);
+            }
+            _ => {
+                // Go back to the oldest unacknowledged byte and send everything again
+                tcb.send_next = tcb.send_unacknowledged;
+                tcb.fin_sent = false;
+                self.output(tcb, now);
+            }
+        }
+
+        tcb.deadline_ms = Some(now + tcb.rto_ms);
+    }
+
+    /// Probe, whether the peer's window has opened again. The probe carries an old sequence number, so that the peer
+    /// answers it with an acknowledgment containing its current window, without accepting any data (like Linux's window probes).
+    fn on_persist_timeout(&self, tcb: &mut Tcb, now: usize) {
+        tcb.persist_deadline_ms = None;
+        if tcb.state == State::Closed || tcb.send_next != tcb.send_unacknowledged {
+            return;
+        }
+
+        let sequence = tcb.send_unacknowledged.wrapping_sub(1);
+        self.send_segment(tcb, FLAG_ACK, sequence, &[]);
+
+        tcb.persist_interval_ms = (tcb.persist_interval_ms * 2).min(MAX_RTO_MS);
+        tcb.persist_deadline_ms = Some(now + tcb.persist_interval_ms);
+    }
+
+    /// Pass a connection, which has just been established, to its listener. Returns `false`, if the listener is gone.
+    fn hand_over_to_listener(self: &Arc<Self>, tcb: &mut Tcb) -> bool {
+        let Some(listener) = tcb.listener.take().and_then(|listener| listener.upgrade()) else {
+            let sequence = tcb.send_next;
+            self.send_segment(tcb, FLAG_RST, sequence, &[]);
+            self.terminate(tcb, Some(Errno::ConnectionReset));
+            return false;
+        };
+
+        let mut state = listener.state.lock();
+        state.half_open -= 1;
+        state.established.push_back(Arc::clone(self));
+        drop(state);
+
+        listener.readable.notify_all();
+        return true;
+    }
+
+    fn enter_time_wait(&self, tcb: &mut Tcb, now: usize) {
+        tcb.state = State::TimeWait;
+        tcb.deadline_ms = Some(now + 2 * MSL_MS);
+    }
+
+    /// Close the connection immediately and remove it, waking up all threads waiting for it.
+    fn terminate(&self, tcb: &mut Tcb, error: Option<Errno>) {
+        if tcb.state == State::SynReceived {
+            if let Some(listener) = tcb.listener.take().and_then(|listener| listener.upgrade()) {
+                listener.state.lock().half_open -= 1;
+            }
+        }
+
+        tcb.state = State::Closed;
+        tcb.deadline_ms = None;
+        tcb.persist_deadline_ms = None;
+        if error.is_some() {
+            tcb.error = error;
+        }
+
+        CONNECTIONS.lock().retain(|connection| !ptr::eq(connection.as_ref(), self));
+        self.readable.notify_all();
+        self.writable.notify_all();
+    }
+
+    fn send_ack(&self, tcb: &mut Tcb) {
+        let sequence = tcb.send_next;
+        let flags = if tcb.state == State::SynReceived { FLAG_SYN | FLAG_ACK } else { FLAG_ACK };
+        let sequence = if tcb.state == State::SynReceived { tcb.send_unacknowledged } else { sequence };
+        self.send_segment(tcb, flags, sequence, &[]);
+    }
+
+    fn send_segment(&self, tcb: &mut Tcb, flags: u8, sequence: u32, payload: &[u8]) {
+        let window = tcb.receive_window();
+        tcb.announced_window = window;
+
+        let acknowledgment = if flags & FLAG_ACK != 0 { tcb.receive_next } else { 0 };
+        let mss = if flags & FLAG_SYN != 0 { Some(local_mss(self.remote.address)) } else { None };
+        transmit(self.local, self.remote, sequence, acknowledgment, flags, window as u16, mss, payload);
+    }
+}
+
+impl Tcb {
+    fn new(state: State, listener: Option<Weak<Listener>>) -> Self {
+        // The SYN is the first unacknowledged sequence number
+        let initial_sequence = random::next_u64() as u32;
+        return Self {
+            state,
+            send_unacknowledged: initial_sequence,
+            send_next: initial_sequence.wrapping_add(1),
+            send_window: 0,
+            receive_next: 0,
+            send_buffer: VecDeque::new(),
+            receive_buffer: VecDeque::new(),
+            announced_window: BUFFER_SIZE,
+            mss: DEFAULT_MSS,
+            closing: false,
+            fin_sent: false,
+            fin_received: false,
+            error: None,
+            rto_ms: INITIAL_RTO_MS,
+            smoothed_rtt_ms: None,
+            rtt_variation_ms: 0,
+            rtt_sample: Some((initial_sequence, timer().read().systime_ms())),
+            deadline_ms: None,
+            retransmissions: 0,
+            persist_deadline_ms: None,
+            persist_interval_ms: INITIAL_RTO_MS,
+            listener,
+        };
+    }
+
+    fn receive_window(&self) -> usize {
+        return (BUFFER_SIZE - self.receive_buffer.len()).min(u16::MAX as usize);
+    }
+
+    /// Check, if the segment is (at least partially) inside the receive window (RFC 793, section 3.3).
+    fn is_acceptable(&self, segment: &Segment) -> bool {
+        let length = segment.length();
+        let window = self.receive_window() as u32;
+        let in_window = |sequence: u32| sequence.wrapping_sub(self.receive_next) < window;
+
+        return match (length, window) {
+            (0, 0) => segment.sequence == self.receive_next,
+            (0, _) => in_window(segment.sequence),
+            (_, 0) => false,
+            _ => in_window(segment.sequence) || in_window(segment.sequence.wrapping_add(length - 1))
+        };
+    }
+
+    /// Update the smoothed round trip time and the retransmission timeout with a new measurement (RFC 6298).
+    fn update_rto(&mut self, rtt_ms: usize) {
+        match self.smoothed_rtt_ms {
+            None => {
+                self.smoothed_rtt_ms = Some(rtt_ms);
+                self.rtt_variation_ms = rtt_ms / 2;
+            }
+            Some(smoothed_rtt_ms) => {
+                self.rtt_variation_ms = (3 * self.rtt_variation_ms + smoothed_rtt_ms.abs_diff(rtt_ms)) / 4;
+                self.smoothed_rtt_ms = Some((7 * smoothed_rtt_ms + rtt_ms) / 8);
+            }
+        }
+
+        self.rto_ms = (self.smoothed_rtt_ms.unwrap() + 4 * self.rtt_variation_ms).clamp(MIN_RTO_MS, MAX_RTO_MS);
+    }
+}
+
+impl<'a> Segment<'a> {
+    /// Validate the checksum and parse the header (including the MSS option).
+    fn parse(header: &Header, data: &'a [u8]) -> Option<Self> {
+        if data.len() < HEADER_SIZE || checksum(data, ipv4::pseudo_header_sum(header.source, header.destination, PROTOCOL_TCP, data.len())) != 0 {
+            return None;
+        }
+
+        let data_offset = (data[12] >> 4) as usize * 4;
+        if data_offset < HEADER_SIZE || data_offset > data.len() {
+            return None;
+        }
+
+        let mut mss = None;
+        let mut options = &data[HEADER_SIZE..data_offset];
+        while let Some(&kind) = options.first() {
+            match kind {
+                OPTION_END => break,
+                OPTION_NOP => options = &options[1..],
+                _ => {
+                    let length = *options.get(1)? as usize;
+                    if length < 2 || length > options.len() {
+                        return None;
+                    }
+                    if kind == OPTION_MSS && length == 4 {
+                        mss = Some(u16::from_be_bytes([options[2], options[3]]) as usize);
+                    }
+
+                    options = &options[length..];
+                }
+            }
+        }
+
+        return Some(Self {
+            source_port: u16::from_be_bytes([data[0], data[1]]),
+            destination_port: u16::from_be_bytes([data[2], data[3]]),
+            sequence: u32::from_be_bytes(data[4..8].try_into().unwrap()),
+            acknowledgment: u32::from_be_bytes(data[8..12].try_into().unwrap()),
+            flags: data[13],
+            window: u16::from_be_bytes([data[14], data[15]]),
+            mss,
+            payload: &data[data_offset..],
+        });
+    }
+
+    /// Length in sequence numbers (SYN and FIN count as one).
+    fn length(&self) -> u32 {
+        return self.payload.len() as u32 + (self.flags & FLAG_SYN != 0) as u32 + (self.flags & FLAG_FIN != 0) as u32;
+    }
+}
+
+/// Answer a segment, which does not belong to any connection, with a reset (RFC 793, section 3.4).
+fn send_reset(local: SocketAddress, remote: SocketAddress, segment: &Segment) {
+    if segment.flags & FLAG_ACK != 0 {
+        transmit(local, remote, segment.acknowledgment, 0, FLAG_RST, 0, None, &[]);
+    } else {
+        transmit(local, remote, 0, segment.sequence.wrapping_add(segment.length()), FLAG_RST | FLAG_ACK, 0, None, &[]);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn transmit(local: SocketAddress, remote: SocketAddress, sequence: u32, acknowledgment: u32, flags: u8, window: u16, mss: Option<usize>, payload: &[u8]) {
+    let header_size = if mss.is_some() { HEADER_SIZE + 4 } else { HEADER_SIZE };
+    let mut segment = Vec::with_capacity(header_size + payload.len());
+    segment.extend_from_slice(&local.port.to_be_bytes());
+    segment.extend_from_slice(&remote.port.to_be_bytes());
+    segment.extend_from_slice(&sequence.to_be_bytes());
+    segment.extend_from_slice(&acknowledgment.to_be_bytes());
+    segment.extend_from_slice(&[((header_size / 4) as u8) << 4, flags]);
+    segment.extend_from_slice(&window.to_be_bytes());
+    segment.extend_from_slice(&[0, 0, 0, 0]);
+    if let Some(mss) = mss {
+        segment.extend_from_slice(&[OPTION_MSS, 4]);
+        segment.extend_from_slice(&(mss as u16).to_be_bytes());
+    }
+    segment.extend_from_slice(payload);
+
+    let segment_checksum = checksum(&segment, ipv4::pseudo_header_sum(local.address, remote.address, PROTOCOL_TCP, segment.len()));
+    segment[16..18].copy_from_slice(&segment_checksum.to_be_bytes());
+
+    // Lost segments are retransmitted, so errors (e.g. an unreachable network) are not reported here
+    let _ = ipv4::send_from(local.address, remote.address, PROTOCOL_TCP, &segment);
+}
+
+/// Largest segment, that fits into a packet on the interface used for reaching `address`.
+fn local_mss(address: Ipv4Address) -> usize {
+    return ipv4::route(address)
+        .map_or(DEFAULT_MSS, |(interface, _)| interface.mtu() - ipv4::HEADER_SIZE - HEADER_SIZE)
+        .min(u16::MAX as usize);
+}
+
+fn is_port_in_use(port: u16) -> bool {
+    return LISTENERS.lock().iter().any(|listener| listener.local.port == port)
+        || CONNECTIONS.lock().iter().any(|connection| connection.local.port == port);
+}
+
+fn allocate_port() -> Result<u16> {
+    for _ in 0..EPHEMERAL_PORT_COUNT {
+        let port = EPHEMERAL_PORT_START + (NEXT_EPHEMERAL_PORT.fetch_add(1, Relaxed) % EPHEMERAL_PORT_COUNT) as u16;
+        if !is_port_in_use(port) {
+            return Ok(port);
+        }
+    }
+
+    return Err(Errno::AddressInUse);
+}
+
+/// Compare sequence numbers, which may wrap around.
+fn sequence_less(a: u32, b: u32) -> bool {
+    return (a.wrapping_sub(b) as i32) < 0;
+}
+
+/// Check, if `start < sequence <= end` (with wrap around).
+fn sequence_between(start: u32, sequence: u32, end: u32) -> bool {
+    return sequence_less(start, sequence) && !sequence_less(end, sequence);
+}
@@ -0,0 +1,218 @@
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering::Relaxed;
+use syscall::error::Errno;
+use syscall::file::PollEvents;
+use crate::fs::poll;
+use crate::fs::poll::Pollable;
+use crate::net::inet::ipv4::{Header, MAX_PACKET_SIZE, PROTOCOL_UDP};
+use crate::net::inet::{checksum, dhcp, ipv4};
+use crate::net::socket::{Socket, SocketAddress};
+use crate::net::{Interface, Ipv4Address, Result};
+use crate::process::wait_queue::{WaitQueue, Waiter};
+use crate::sync::Mutex;
+use crate::net;
+
+pub const HEADER_SIZE: usize = 8;
+
+/// Datagrams, which have not been read yet. Further datagrams are dropped.
+const MAX_QUEUED_DATAGRAMS: usize = 64;
+
+const EPHEMERAL_PORT_START: u16 = 49152;
+const EPHEMERAL_PORT_COUNT: usize = 16384;
+
+/// Socket for sending and receiving datagrams. Unbound sockets are bound to a free port, when they send their first datagram.
+pub struct UdpSocket {
+    local: Mutex<Option<SocketAddress>>,
+    datagrams: Mutex<VecDeque<(SocketAddress, Vec<u8>)>>,
+    readable: WaitQueue,
+}
+
+static SOCKETS: Mutex<Vec<Weak<UdpSocket>>> = Mutex::new(Vec::new());
+static NEXT_EPHEMERAL_PORT: AtomicUsize = AtomicUsize::new(0);
+
+/// Pass a received datagram to the socket bound to its port (or to the DHCP client of `interface`).
+pub fn receive(interface: &Arc<Interface>, header: &Header, datagram: &[u8]) {
+    if datagram.len() < HEADER_SIZE {
+        return;
+    }
+
+    let length = u16::from_be_bytes([datagram[4], datagram[5]]) as usize;
+    let transmitted_checksum = u16::from_be_bytes([datagram[6], datagram[7]]);
+    if length < HEADER_SIZE || length > datagram.len() {
+        return;
+    }
+
+    // The checksum is optional (0 means, that the sender has not computed it)
+    let datagram = &datagram[..length];
+    if transmitted_checksum != 0 && checksum(datagram, ipv4::pseudo_header_sum(header.source, header.destination, PROTOCOL_UDP, length)) != 0 {
+        return;
+    }
+
+    let source = SocketAddress::new(header.source, u16::from_be_bytes([datagram[0], datagram[1]]));
+    let destination_port = u16::from_be_bytes([datagram[2], datagram[3]]);
+    let payload = &datagram[HEADER_SIZE..];
+
+    if destination_port == dhcp::CLIENT_PORT {
+        dhcp::receive(interface, payload);
+        return;
+    }
+
+    let socket = live_sockets().into_iter().find(|socket| socket.local().is_some_and(|local| {
+        local.port == destination_port && (local.address.is_unspecified() || local.address == header.destination)
+    }));
+
+    if let Some(socket) = socket {
+        socket.push_datagram(source, payload);
+    }
+}
+
+/// Send `payload` from `source` to `destination`, choosing the interface via `ipv4::route()`.
+pub fn send(source: SocketAddress, destination: SocketAddress, payload: &[u8]) -> Result<()> {
+    let (interface, next_hop) = ipv4::route(destination.address)?;
+    return send_via(&interface, next_hop, source, destination, payload);
+}
+
+/// Send `payload` from `source` to `destination` via `interface` (e.g. broadcasts from unconfigured interfaces).
+pub fn send_via(interface: &Arc<Interface>, next_hop: Ipv4Address, source: SocketAddress, destination: SocketAddress, payload: &[u8]) -> Result<()> {
+    let length = HEADER_SIZE + payload.len();
+    if length > MAX_PACKET_SIZE - ipv4::HEADER_SIZE {
+        return Err(Errno::MessageTooLong);
+    }
+
+    let mut datagram = Vec::with_capacity(length);
+    datagram.extend_from_slice(&source.port.to_be_bytes());
+    datagram.extend_from_slice(&destination.port.to_be_bytes());
+    datagram.extend_from_slice(&(length as u16).to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(payload);
+
+    // A computed checksum of 0 is transmitted as 0xffff, since 0 means 'no checksum'
+    let datagram_checksum = match checksum(&datagram, ipv4::pseudo_header_sum(source.address, destination.address, PROTOCOL_UDP, length)) {
+        0 => 0xffff,
+        sum => sum
+    };
+    datagram[6..8].copy_from_slice(&datagram_checksum.to_be_bytes());
+
+    return ipv4::send_via(interface, next_hop, source.address, destination.address, PROTOCOL_UDP, &datagram);
+}
+
+impl UdpSocket {
+    pub fn new() -> Arc<Self> {
+        let socket = Arc::new(Self { local: Mutex::new(None), datagrams: Mutex::new(VecDeque::new()), readable: WaitQueue::new() });
+        SOCKETS.lock().push(Arc::downgrade(&socket));
+
+        return socket;
+    }
+
+    fn local(&self) -> Option<SocketAddress> {
+        return *self.local.lock();
+    }
+
+    fn push_datagram(&self, source: SocketAddress, payload: &[u8]) {
+        let mut datagrams = self.datagrams.lock();
+        if datagrams.len() < MAX_QUEUED_DATAGRAMS {
+            datagrams.push_back((source, payload.to_vec()));
+        }
+
+        drop(datagrams);
+        self.readable.notify_all();
+    }
+}
+
+impl Socket for UdpSocket {
+    fn bind(&self, address: SocketAddress) -> Result<()> {
+        if self.local().is_some() {
+            return Err(Errno::InvalidArgument);
+        }
+        if !address.address.is_unspecified() && !net::interfaces().iter().any(|interface| interface.ipv4().is_some_and(|cidr| cidr.address() == address.address)) {
+            return Err(Errno::InvalidArgument);
+        }
+
+        // The lock is not held while checking the ports, since that includes this socket
+        let port = match address.port {
+            0 => allocate_port()?,
+            port if is_port_in_use(port) => return Err(Errno::AddressInUse),
+            port => port
+        };
+
+        let mut local = self.local.lock();
+        if local.is_some() {
+            return Err(Errno::InvalidArgument);
+        }
+
+        *local = Some(SocketAddress::new(address.address, port));
+        return Ok(());
+    }
+
+    fn send_to(&self, data: &[u8], destination: SocketAddress) -> Result<usize> {
+        if destination.port == 0 {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let local = match self.local() {
+            Some(local) => local,
+            None => {
+                self.bind(SocketAddress::new(Ipv4Address::UNSPECIFIED, 0))?;
+                self.local().unwrap()
+            }
+        };
+
+        let source_address = if local.address.is_unspecified() { ipv4::source_address(destination.address)? } else { local.address };
+        send(SocketAddress::new(source_address, local.port), destination, data)?;
+
+        return Ok(data.len());
+    }
+
+    fn receive_from(&self, buffer: &mut [u8]) -> Result<(usize, SocketAddress)> {
+        let mut datagrams = self.datagrams.lock();
+        loop {
+            if let Some((source, datagram)) = datagrams.pop_front() {
+                let count = buffer.len().min(datagram.len());
+                buffer[..count].copy_from_slice(&datagram[..count]);
+
+                return Ok((count, source));
+            }
+
+            self.readable.wait(datagrams);
+            datagrams = self.datagrams.lock();
+        }
+    }
+}
+
+impl Pollable for UdpSocket {
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        let datagrams = self.datagrams.lock();
+        let mut ready = PollEvents::WRITABLE;
+        if !datagrams.is_empty() {
+            ready |= PollEvents::READABLE;
+        }
+
+        // Checked while holding the lock, so that no datagram between checking and registering is missed
+        return poll::ready_or_register(ready, events, waiter, &[(PollEvents::READABLE, &self.readable)]);
+    }
+}
+
+fn live_sockets() -> Vec<Arc<UdpSocket>> {
+    let mut sockets = SOCKETS.lock();
+    sockets.retain(|socket| socket.strong_count() > 0);
+
+    return sockets.iter().filter_map(Weak::upgrade).collect();
+}
+
+fn is_port_in_use(port: u16) -> bool {
+    return port == dhcp::CLIENT_PORT || live_sockets().iter().any(|socket| socket.local().is_some_and(|local| local.port == port));
+}
+
+fn allocate_port() -> Result<u16> {
+    for _ in 0..EPHEMERAL_PORT_COUNT {
+        let port = EPHEMERAL_PORT_START + (NEXT_EPHEMERAL_PORT.fetch_add(1, Relaxed) % EPHEMERAL_PORT_COUNT) as u16;
+        if !is_port_in_use(port) {
+            return Ok(port);
+        }
+    }
+
+    return Err(Errno::AddressInUse);
+}
@@ -0,0 +1,41 @@
+use alloc::sync::{Arc, Weak};
+use spin::Once;
+use crate::net::{Interface, MacAddress, NetworkDevice, Result};
+
+/// Largest frame payload on the loopback interface (as on Linux).
+const LOOPBACK_MTU: usize = 65536;
+
+/// Software device, which passes all transmitted frames back to its own interface.
+/// Frames are queued like received frames of other devices, so that replies are not processed recursively.
+pub struct Loopback {
+    interface: Once<Weak<Interface>>,
+}
+
+impl Loopback {
+    pub const fn new() -> Self {
+        Self { interface: Once::new() }
+    }
+
+    /// Set the interface, which receives the transmitted frames (done once after registration).
+    pub fn attach(&self, interface: &Arc<Interface>) {
+        self.interface.call_once(|| Arc::downgrade(interface));
+    }
+}
+
+impl NetworkDevice for Loopback {
+    fn mac_address(&self) -> MacAddress {
+        return MacAddress::default();
+    }
+
+    fn mtu(&self) -> usize {
+        return LOOPBACK_MTU;
+    }
+
+    fn transmit(&self, frame: &[u8]) -> Result<()> {
+        if let Some(interface) = self.interface.get().and_then(Weak::upgrade) {
+            interface.receive(frame.to_vec());
+        }
+
+        return Ok(());
+    }
+}
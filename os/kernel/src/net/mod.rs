@@ -0,0 +1,357 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicUsize};
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use log::info;
+use spin::Once;
+use syscall::error::Errno;
+use x86_64::instructions::interrupts;
+use crate::interrupt::deferred;
+use crate::net::loopback::Loopback;
+use crate::net::socket::{Protocol, Socket, SocketType};
+use crate::sync::{Mutex, RwLock};
+
+pub mod capture;
+pub mod inet;
+pub mod loopback;
+pub mod socket;
+pub mod telnet;
+pub mod unix;
+
+pub type Result<T> = core::result::Result<T, Errno>;
+
+/// Received frames, which have not been processed yet. Further frames are dropped, if the protocol stack does not keep up.
+const RECEIVE_QUEUE_CAPACITY: usize = 256;
+
+/// Common interface of all network drivers (e.g. NICs or the loopback device).
+/// Devices send and receive complete Ethernet frames (without frame check sequence).
+/// Protocols do not use drivers directly, but access them via the `Interface` returned by `register()`.
+pub trait NetworkDevice: Send + Sync {
+    fn mac_address(&self) -> MacAddress;
+
+    /// Maximum size of the payload of a frame (without Ethernet header).
+    fn mtu(&self) -> usize;
+
+    /// Queue `frame` for transmission. May return, before the frame has been sent.
+    fn transmit(&self, frame: &[u8]) -> Result<()>;
+}
+
+/// Protocol stack, which processes the received frames of all interfaces and implements the sockets.
+/// The kernel's own stack is `inet`, but any other implementation (e.g. a wrapper around smoltcp)
+/// can be installed with `set_stack()` instead, without changing drivers or socket users.
+pub trait Stack: Send + Sync {
+    /// Process a frame, which has been received by `interface` (called by the kernel worker thread).
+    fn receive(&self, interface: &Arc<Interface>, frame: &[u8]);
+
+    /// Create a new socket (see `socket::socket()`).
+    fn socket(&self, typ: SocketType, protocol: Protocol) -> Result<Arc<dyn Socket>>;
+
+    /// Handle a control request, which has been issued on a socket (e.g. changing the configuration of interfaces).
+    fn ioctl(&self, _request: usize, _arg: usize) -> Result<usize> {
+        return Err(Errno::NotATerminal);
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Default)]
+pub struct MacAddress(pub [u8; 6]);
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Ipv4Address(pub [u8; 4]);
+
+/// IPv4 address of an interface together with the length of its network prefix (e.g. '10.0.2.15/24').
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Ipv4Cidr {
+    address: Ipv4Address,
+    prefix_length: u8,
+}
+
+/// Network interface, connecting a device to the protocol stack. Received frames are queued by the driver
+/// (e.g. from its interrupt handler) and processed by the kernel worker thread.
+pub struct Interface {
+    name: String,
+    device: Arc<dyn NetworkDevice>,
+    /// Locked by interrupt handlers, so it must only be locked with interrupts disabled in thread context.
+    receive_queue: Mutex<VecDeque<Vec<u8>>>,
+    processing_pending: AtomicBool,
+    ipv4: RwLock<Option<Ipv4Cidr>>,
+    statistics: Statistics,
+}
+
+#[derive(Default)]
+pub struct Statistics {
+    pub received_frames: AtomicUsize,
+    pub received_bytes: AtomicUsize,
+    pub transmitted_frames: AtomicUsize,
+    pub transmitted_bytes: AtomicUsize,
+    pub dropped_frames: AtomicUsize,
+}
+
+static INTERFACES: RwLock<Vec<Arc<Interface>>> = RwLock::new(Vec::new());
+static STACK: Once<&'static dyn Stack> = Once::new();
+/// Name servers for resolving host names (e.g. as configured by DHCP).
+static DNS_SERVERS: RwLock<Vec<Ipv4Address>> = RwLock::new(Vec::new());
+
+impl MacAddress {
+    pub const BROADCAST: MacAddress = MacAddress([0xff; 6]);
+
+    pub fn is_broadcast(&self) -> bool {
+        return *self == Self::BROADCAST;
+    }
+
+    pub fn is_multicast(&self) -> bool {
+        return self.0[0] & 0x01 != 0;
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", a, b, c, d, e, g)
+    }
+}
+
+impl fmt::Debug for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl Ipv4Address {
+    pub const UNSPECIFIED: Ipv4Address = Ipv4Address([0; 4]);
+    pub const BROADCAST: Ipv4Address = Ipv4Address([0xff; 4]);
+    pub const LOOPBACK: Ipv4Address = Ipv4Address([127, 0, 0, 1]);
+
+    pub const fn new(a: u8, b: u8, c: u8, d: u8) -> Self {
+        Self([a, b, c, d])
+    }
+
+    pub fn from_bits(bits: u32) -> Self {
+        return Self(bits.to_be_bytes());
+    }
+
+    pub fn to_bits(self) -> u32 {
+        return u32::from_be_bytes(self.0);
+    }
+
+    pub fn is_unspecified(&self) -> bool {
+        return *self == Self::UNSPECIFIED;
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        return *self == Self::BROADCAST;
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        return self.0[0] == 127;
+    }
+
+    pub fn is_multicast(&self) -> bool {
+        return self.0[0] & 0xf0 == 0xe0;
+    }
+}
+
+impl fmt::Display for Ipv4Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d] = self.0;
+        write!(f, "{}.{}.{}.{}", a, b, c, d)
+    }
+}
+
+impl fmt::Debug for Ipv4Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl Ipv4Cidr {
+    /// Returns `None`, if the prefix is longer than 32 bits.
+    pub fn new(address: Ipv4Address, prefix_length: u8) -> Option<Self> {
+        return if prefix_length <= 32 { Some(Self { address, prefix_length }) } else { None };
+    }
+
+    pub fn address(&self) -> Ipv4Address {
+        return self.address;
+    }
+
+    pub fn prefix_length(&self) -> u8 {
+        return self.prefix_length;
+    }
+
+    pub fn netmask(&self) -> Ipv4Address {
+        return Ipv4Address::from_bits(u32::MAX.checked_shl(32 - self.prefix_length as u32).unwrap_or(0));
+    }
+
+    pub fn network(&self) -> Ipv4Address {
+        return Ipv4Address::from_bits(self.address.to_bits() & self.netmask().to_bits());
+    }
+
+    /// Directed broadcast address of the network (e.g. '10.0.2.255' for '10.0.2.15/24').
+    pub fn broadcast(&self) -> Ipv4Address {
+        return Ipv4Address::from_bits(self.address.to_bits() | !self.netmask().to_bits());
+    }
+
+    pub fn contains(&self, address: Ipv4Address) -> bool {
+        return address.to_bits() & self.netmask().to_bits() == self.network().to_bits();
+    }
+}
+
+impl fmt::Display for Ipv4Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_length)
+    }
+}
+
+impl fmt::Debug for Ipv4Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Register the loopback interface ('lo' with address 127.0.0.1/8). Drivers register their interfaces, when they are probed.
+pub fn init() {
+    let loopback = Arc::new(Loopback::new());
+    let interface = register("lo", Arc::clone(&loopback) as Arc<dyn NetworkDevice>);
+    interface.set_ipv4(Ipv4Cidr::new(Ipv4Address::LOOPBACK, 8));
+    loopback.attach(&interface);
+}
+
+/// Make `device` available as interface called `prefix` followed by the next free number (e.g. 'eth0'),
+/// or as `prefix` itself for the loopback interface.
+pub fn register(prefix: &str, device: Arc<dyn NetworkDevice>) -> Arc<Interface> {
+    let mut interfaces = INTERFACES.write();
+    let name = if prefix == "lo" {
+        String::from(prefix)
+    } else {
+        let number = interfaces.iter().filter(|interface| interface.name.starts_with(prefix)).count();
+        format!("{}{}", prefix, number)
+    };
+
+    let interface = Arc::new(Interface {
+        name,
+        device,
+        receive_queue: Mutex::new(VecDeque::with_capacity(RECEIVE_QUEUE_CAPACITY)),
+        processing_pending: AtomicBool::new(false),
+        ipv4: RwLock::new(None),
+        statistics: Statistics::default(),
+    });
+
+    info!("Registered network interface [{}] (MAC: [{}], MTU: [{}])", interface.name, interface.mac_address(), interface.mtu());
+    interfaces.push(Arc::clone(&interface));
+
+    return interface;
+}
+
+pub fn interface(name: &str) -> Option<Arc<Interface>> {
+    return INTERFACES.read().iter().find(|interface| interface.name == name).cloned();
+}
+
+pub fn interfaces() -> Vec<Arc<Interface>> {
+    return INTERFACES.read().clone();
+}
+
+/// Install the protocol stack (called once during initialization, e.g. by `inet::init()`).
+/// Frames, which are received before, are dropped.
+pub fn set_stack(stack: &'static dyn Stack) {
+    STACK.call_once(|| stack);
+}
+
+pub fn stack() -> Option<&'static dyn Stack> {
+    return STACK.get().copied();
+}
+
+pub fn dns_servers() -> Vec<Ipv4Address> {
+    return DNS_SERVERS.read().clone();
+}
+
+pub fn set_dns_servers(servers: Vec<Ipv4Address>) {
+    *DNS_SERVERS.write() = servers;
+}
+
+impl Interface {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn mac_address(&self) -> MacAddress {
+        return self.device.mac_address();
+    }
+
+    pub fn mtu(&self) -> usize {
+        return self.device.mtu();
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        return self.name == "lo";
+    }
+
+    /// Configured IPv4 address (`None`, if the interface has not been configured yet, e.g. by DHCP).
+    pub fn ipv4(&self) -> Option<Ipv4Cidr> {
+        return *self.ipv4.read();
+    }
+
+    pub fn set_ipv4(&self, cidr: Option<Ipv4Cidr>) {
+        *self.ipv4.write() = cidr;
+    }
+
+    pub fn statistics(&self) -> &Statistics {
+        &self.statistics
+    }
+
+    pub fn transmit(&self, frame: &[u8]) -> Result<()> {
+        self.device.transmit(frame)?;
+        // Frames sent via the loopback interface are captured, when they are received
+        if !self.is_loopback() {
+            capture::tap(self, frame);
+        }
+
+        self.statistics.transmitted_frames.fetch_add(1, Relaxed);
+        self.statistics.transmitted_bytes.fetch_add(frame.len(), Relaxed);
+
+        return Ok(());
+    }
+
+    /// Queue a received frame for the protocol stack. May be called from interrupt handlers.
+    pub fn receive(self: &Arc<Self>, frame: Vec<u8>) {
+        let queued = interrupts::without_interrupts(|| {
+            let mut queue = self.receive_queue.lock();
+            if queue.len() == RECEIVE_QUEUE_CAPACITY {
+                return false;
+            }
+
+            queue.push_back(frame);
+            true
+        });
+
+        if !queued {
+            self.statistics.dropped_frames.fetch_add(1, Relaxed);
+            return;
+        }
+
+        if !self.processing_pending.swap(true, Acquire) {
+            let interface = Arc::clone(self);
+            deferred::schedule_work(Box::new(move || {
+                interface.processing_pending.store(false, Release);
+                interface.process_frames();
+            }));
+        }
+    }
+
+    /// Pass all queued frames to the protocol stack (called by the kernel worker thread).
+    fn process_frames(self: &Arc<Self>) {
+        while let Some(frame) = interrupts::without_interrupts(|| self.receive_queue.lock().pop_front()) {
+            self.statistics.received_frames.fetch_add(1, Relaxed);
+            self.statistics.received_bytes.fetch_add(frame.len(), Relaxed);
+            capture::tap(self, &frame);
+
+            match stack() {
+                Some(stack) => stack.receive(self, &frame),
+                None => { self.statistics.dropped_frames.fetch_add(1, Relaxed); }
+            }
+        }
+    }
+}
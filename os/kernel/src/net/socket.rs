@@ -0,0 +1,155 @@
+use alloc::sync::Arc;
+use core::any::Any;
+use core::fmt;
+use core::ptr;
+use syscall::error::Errno;
+use syscall::file::{FileType, PollEvents};
+use crate::fs::poll::Pollable;
+use crate::fs::{File, Metadata};
+use crate::net::unix::UnixSocket;
+use crate::net::{stack, Ipv4Address, Result};
+use crate::process::wait_queue::Waiter;
+
+pub use syscall::net::{Protocol, SocketType};
+
+/// IPv4 address and port of a socket (the port is unused by ICMP sockets).
+#[derive(Copy, Clone, PartialEq, Eq, Default)]
+pub struct SocketAddress {
+    pub address: Ipv4Address,
+    pub port: u16,
+}
+
+/// Socket, as implemented by the installed protocol stack (see `net::Stack`) or by `UnixSocket`.
+/// Operations, which are not supported by a socket type, return an error by default.
+/// Readiness for reading and writing can be waited for via `Pollable`.
+pub trait Socket: Pollable + Any + Send + Sync {
+    /// Assign a local address. An unspecified address accepts connections on all interfaces and port 0 chooses a free port.
+    fn bind(&self, _address: SocketAddress) -> Result<()> {
+        return Err(Errno::NotSupported);
+    }
+
+    /// Connect to `address`, blocking until the connection has been established.
+    fn connect(&self, _address: SocketAddress) -> Result<()> {
+        return Err(Errno::NotSupported);
+    }
+
+    /// Accept incoming connections on the bound address. At most `backlog` connections wait for being accepted.
+    fn listen(&self, _backlog: usize) -> Result<()> {
+        return Err(Errno::NotSupported);
+    }
+
+    /// Block until a connection has been established with a listening socket and return it together with the peer's address.
+    fn accept(&self) -> Result<(Arc<dyn Socket>, SocketAddress)> {
+        return Err(Errno::NotSupported);
+    }
+
+    /// Send data on a connected socket. Blocks, until at least a part of `data` has been queued and returns its length.
+    fn send(&self, _data: &[u8]) -> Result<usize> {
+        return Err(Errno::NotSupported);
+    }
+
+    /// Receive data on a connected socket. Blocks, until data is available and returns 0, once the peer has closed the connection.
+    fn receive(&self, _buffer: &mut [u8]) -> Result<usize> {
+        return Err(Errno::NotSupported);
+    }
+
+    /// Stop sending on a connected socket, so that the peer reads the end of the stream. Receiving is still possible.
+    fn shutdown(&self) -> Result<()> {
+        return Err(Errno::NotSupported);
+    }
+
+    /// Send `data` as a single datagram to `destination`. Returns the number of bytes sent.
+    fn send_to(&self, _data: &[u8], _destination: SocketAddress) -> Result<usize> {
+        return Err(Errno::NotSupported);
+    }
+
+    /// Block until a datagram has been received and copy it into `buffer` (truncating it, if the buffer is too small).
+    /// Returns the number of bytes copied and the sender of the datagram.
+    fn receive_from(&self, _buffer: &mut [u8]) -> Result<(usize, SocketAddress)> {
+        return Err(Errno::NotSupported);
+    }
+}
+
+/// Socket opened by a process. Reading and writing receive and send data on connected sockets.
+/// The socket is closed, when the last descriptor referring to it is closed.
+pub struct SocketFile {
+    socket: Arc<dyn Socket>,
+}
+
+impl SocketAddress {
+    pub const fn new(address: Ipv4Address, port: u16) -> Self {
+        Self { address, port }
+    }
+}
+
+impl fmt::Display for SocketAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.address, self.port)
+    }
+}
+
+impl fmt::Debug for SocketAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl From<syscall::net::SocketAddress> for SocketAddress {
+    fn from(address: syscall::net::SocketAddress) -> Self {
+        return Self::new(Ipv4Address(address.address), address.port);
+    }
+}
+
+impl From<SocketAddress> for syscall::net::SocketAddress {
+    fn from(address: SocketAddress) -> Self {
+        return Self::new(address.address.0, address.port);
+    }
+}
+
+/// Create a socket with the installed protocol stack (local sockets are available without a stack). Fails with `Errno::NotSupported`,
+/// if no stack is installed or the stack does not support the combination of type and protocol.
+pub fn socket(typ: SocketType, protocol: Protocol) -> Result<Arc<dyn Socket>> {
+    if protocol == Protocol::Unix {
+        return match typ {
+            SocketType::Stream => Ok(UnixSocket::new()),
+            _ => Err(Errno::NotSupported)
+        };
+    }
+
+    return stack().ok_or(Errno::NotSupported)?.socket(typ, protocol);
+}
+
+impl SocketFile {
+    pub fn new(socket: Arc<dyn Socket>) -> Self {
+        Self { socket }
+    }
+
+    pub fn socket(&self) -> &Arc<dyn Socket> {
+        &self.socket
+    }
+}
+
+impl File for SocketFile {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+        return self.socket.receive(buffer);
+    }
+
+    fn write(&self, buffer: &[u8]) -> Result<usize> {
+        return self.socket.send(buffer);
+    }
+
+    fn stat(&self) -> Result<Metadata> {
+        return Ok(Metadata { inode: ptr::from_ref(self) as u64, typ: FileType::Socket, size: 0, created_ms: 0, modified_ms: 0 });
+    }
+
+    /// Requests on sockets are handled by the protocol stack (independent of the type of the socket).
+    fn ioctl(&self, request: usize, arg: usize) -> Result<usize> {
+        return stack().ok_or(Errno::NotSupported)?.ioctl(request, arg);
+    }
+}
+
+impl Pollable for SocketFile {
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        return self.socket.poll(events, waiter);
+    }
+}
@@ -0,0 +1,417 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use log::{info, warn};
+use stream::{InputStream, OutputStream};
+use syscall::ioctl::WindowSize;
+use crate::device::terminal::{Terminal, CONSOLE_COUNT, REMOTE_CONSOLE_COUNT};
+use crate::device::{terminal, tty};
+use crate::fs::Result;
+use crate::net::Ipv4Address;
+use crate::net::socket::{socket, Protocol, Socket, SocketAddress, SocketType};
+use crate::process::process::load_application_on_console;
+use crate::process::thread::Thread;
+use crate::process::wait_queue::WaitQueue;
+use crate::scheduler;
+use crate::sync::Mutex;
+
+/// Port of the remote shell service.
+pub const PORT: u16 = 23;
+
+// Telnet commands (RFC 854)
+const SUBNEGOTIATION_END: u8 = 240;
+const INTERRUPT_PROCESS: u8 = 244;
+const SUBNEGOTIATION: u8 = 250;
+const WILL: u8 = 251;
+const WONT: u8 = 252;
+const DO: u8 = 253;
+const DONT: u8 = 254;
+const IAC: u8 = 255;
+
+// Telnet options
+const OPTION_ECHO: u8 = 1;
+const OPTION_SUPPRESS_GO_AHEAD: u8 = 3;
+const OPTION_WINDOW_SIZE: u8 = 31;
+
+/// Sent when a session starts: The server echoes typed characters (via the line discipline) and the client sends them
+/// one by one instead of whole lines. The client is asked to report its window size.
+const NEGOTIATION: [u8; 9] = [IAC, WILL, OPTION_ECHO, IAC, WILL, OPTION_SUPPRESS_GO_AHEAD, IAC, DO, OPTION_WINDOW_SIZE];
+
+/// Window size assumed until the client reports its own.
+const DEFAULT_WINDOW_SIZE: WindowSize = WindowSize { columns: 80, rows: 24 };
+
+/// Longer subnegotiations are not supported and truncated.
+const MAX_SUBNEGOTIATION_LENGTH: usize = 64;
+
+/// Terminal of a remote shell session, which is used as console `CONSOLE_COUNT + index` (see `crate::console()`).
+/// Output is sent to the connected client and input is received by the receiver thread of the session.
+pub struct RemoteTerminal {
+    connection: Mutex<Option<Arc<dyn Socket>>>,
+    input: Mutex<Input>,
+    readable: WaitQueue,
+    window_size: Mutex<WindowSize>,
+}
+
+struct Input {
+    data: VecDeque<u8>,
+    /// Set, when the client has closed the connection, so that reading returns end of file.
+    closed: bool,
+}
+
+/// Telnet protocol state of a session, which separates typed input from commands and option negotiation.
+pub struct Decoder {
+    state: DecoderState,
+    subnegotiation: Vec<u8>,
+}
+
+#[derive(Copy, Clone)]
+enum DecoderState {
+    Data,
+    /// Enter is sent as carriage return, followed by a line feed or a null byte, which must be skipped.
+    CarriageReturn,
+    Command,
+    Negotiation(u8),
+    Subnegotiation,
+    SubnegotiationCommand,
+}
+
+/// Result of decoding received data.
+pub struct Decoded {
+    /// Typed input with line endings converted to '\n' (like the serial terminal does).
+    pub input: Vec<u8>,
+    /// Responses to option requests of the client.
+    pub replies: Vec<u8>,
+    /// Reported by the client, if it has changed.
+    pub window_size: Option<WindowSize>,
+}
+
+static TERMINALS: [RemoteTerminal; REMOTE_CONSOLE_COUNT] = [const { RemoteTerminal::new() }; REMOTE_CONSOLE_COUNT];
+
+/// Kernel threads can not take arguments, so the index of a new session is passed to its threads via these queues.
+static STARTING_SESSIONS: Mutex<VecDeque<usize>> = Mutex::new(VecDeque::new());
+static STARTING_RECEIVERS: Mutex<VecDeque<usize>> = Mutex::new(VecDeque::new());
+
+/// Start accepting remote shell sessions on `PORT`. Each session gets a shell on its own remote console,
+/// so that the system can be used via network (e.g. when running headless). Further connections are refused,
+/// while all remote consoles are in use. Only called, if the kernel has been booted with the option 'telnet'.
+pub fn init() {
+    scheduler().ready(Thread::new_kernel_thread(Box::new(listen)));
+}
+
+/// Terminal of the remote console with the number `index` (starting at 0 for the first remote console).
+pub fn terminal(index: usize) -> &'static RemoteTerminal {
+    return &TERMINALS[index];
+}
+
+fn listen() {
+    let listener = match socket(SocketType::Stream, Protocol::Tcp) {
+        Ok(listener) => listener,
+        Err(err) => {
+            warn!("Failed to open remote shell socket (Error: {:?})", err);
+            return;
+        }
+    };
+
+    if let Err(err) = listener.bind(SocketAddress::new(Ipv4Address::UNSPECIFIED, PORT)).and_then(|_| listener.listen(REMOTE_CONSOLE_COUNT)) {
+        warn!("Failed to accept remote shell sessions on port [{}] (Error: {:?})", PORT, err);
+        return;
+    }
+
+    info!("Accepting remote shell sessions on port [{}]", PORT);
+    while let Ok((connection, remote)) = listener.accept() {
+        match TERMINALS.iter().position(|terminal| terminal.claim(&connection)) {
+            Some(index) => {
+                info!("Remote shell session from [{}] on console [{}]", remote, CONSOLE_COUNT + index + 1);
+                STARTING_SESSIONS.lock().push_back(index);
+                scheduler().ready(Thread::new_kernel_thread(Box::new(run_session)));
+            }
+            None => {
+                // The connection is closed, when it is dropped
+                let _ = connection.send(b"All remote consoles are in use!\r\n");
+            }
+        }
+    }
+}
+
+/// Start a shell on the remote console of a new session and wait for it to exit.
+fn run_session() {
+    let index = STARTING_SESSIONS.lock().pop_front().expect("Telnet: Session started without index!");
+    let terminal = &TERMINALS[index];
+    let console = CONSOLE_COUNT + index;
+
+    tty::reset(console);
+    terminal.send(&NEGOTIATION);
+
+    STARTING_RECEIVERS.lock().push_back(index);
+    let receiver = Thread::new_kernel_thread(Box::new(receive_input));
+    scheduler().ready(Rc::clone(&receiver));
+
+    match load_application_on_console("shell", console) {
+        Ok(shell) => {
+            scheduler().ready(Rc::clone(&shell));
+            shell.join();
+        }
+        Err(err) => warn!("Failed to start shell on console [{}] (Error: {:?})", console + 1, err)
+    }
+
+    // Processes left in the background must not read the input of the next session
+    terminal::hangup(console);
+    terminal.close_input();
+
+    // The receiver exits, once the client has closed its side of the connection as well
+    if let Some(connection) = terminal.connection() {
+        let _ = connection.shutdown();
+    }
+    receiver.join();
+
+    terminal.release();
+    info!("Remote shell session on console [{}] closed", console + 1);
+}
+
+/// Pass data received from the client of a session to its terminal, until the connection is closed.
+fn receive_input() {
+    let index = STARTING_RECEIVERS.lock().pop_front().expect("Telnet: Receiver started without index!");
+    let terminal = &TERMINALS[index];
+    let connection = match terminal.connection() {
+        Some(connection) => connection,
+        None => return
+    };
+
+    let mut decoder = Decoder::new();
+    let mut buffer = [0; 512];
+    loop {
+        let count = match connection.receive(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(count) => count
+        };
+
+        let decoded = decoder.decode(&buffer[..count]);
+        if !decoded.replies.is_empty() {
+            terminal.send(&decoded.replies);
+        }
+        if let Some(size) = decoded.window_size {
+            *terminal.window_size.lock() = size;
+        }
+
+        terminal.push_input(&decoded.input);
+    }
+
+    // The shell (and everything else running on the console) is terminated, when the client disconnects
+    terminal.close_input();
+    terminal::hangup(CONSOLE_COUNT + index);
+}
+
+impl RemoteTerminal {
+    const fn new() -> Self {
+        Self {
+            connection: Mutex::new(None),
+            input: Mutex::new(Input { data: VecDeque::new(), closed: true }),
+            readable: WaitQueue::new(),
+            window_size: Mutex::new(DEFAULT_WINDOW_SIZE),
+        }
+    }
+
+    /// Use this terminal for `connection`, if it is not used by another session.
+    fn claim(&self, connection: &Arc<dyn Socket>) -> bool {
+        let mut current = self.connection.lock();
+        if current.is_some() {
+            return false;
+        }
+
+        *current = Some(Arc::clone(connection));
+        *self.input.lock() = Input { data: VecDeque::new(), closed: false };
+        *self.window_size.lock() = DEFAULT_WINDOW_SIZE;
+
+        return true;
+    }
+
+    fn release(&self) {
+        self.connection.lock().take();
+    }
+
+    fn connection(&self) -> Option<Arc<dyn Socket>> {
+        return self.connection.lock().clone();
+    }
+
+    fn push_input(&self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+
+        self.input.lock().data.extend(data);
+        self.readable.notify_all();
+        terminal::notify_input();
+    }
+
+    fn close_input(&self) {
+        self.input.lock().closed = true;
+        self.readable.notify_all();
+        terminal::notify_input();
+    }
+
+    /// Send `data` completely. Errors are ignored, since the client may have disconnected in the meantime.
+    fn send(&self, data: &[u8]) {
+        let connection = match self.connection() {
+            Some(connection) => connection,
+            None => return
+        };
+
+        let mut sent = 0;
+        while sent < data.len() {
+            match connection.send(&data[sent..]) {
+                Ok(count) => sent += count,
+                Err(_) => return
+            }
+        }
+    }
+}
+
+impl OutputStream for RemoteTerminal {
+    fn write_byte(&self, b: u8) {
+        match b {
+            b'\n' => self.send(b"\r\n"),
+            IAC => self.send(&[IAC, IAC]),
+            b => self.send(&[b])
+        }
+    }
+
+    fn write_str(&self, string: &str) {
+        // Network virtual terminals expect line breaks as carriage return and line feed
+        let mut data = Vec::with_capacity(string.len());
+        for b in string.bytes() {
+            if b == b'\n' {
+                data.push(b'\r');
+            }
+            data.push(b);
+        }
+
+        self.send(&data);
+    }
+}
+
+impl InputStream for RemoteTerminal {
+    fn read_byte(&self) -> i16 {
+        let mut input = self.input.lock();
+        loop {
+            if let Some(byte) = input.data.pop_front() {
+                return byte as i16;
+            }
+            if input.closed {
+                return -1;
+            }
+
+            self.readable.wait(input);
+            input = self.input.lock();
+        }
+    }
+}
+
+impl Terminal for RemoteTerminal {
+    fn clear(&self) {
+        self.send(b"\x1b[2J\x1b[H");
+    }
+
+    fn has_input(&self) -> bool {
+        let input = self.input.lock();
+        return !input.data.is_empty() || input.closed;
+    }
+
+    fn window_size(&self) -> WindowSize {
+        return *self.window_size.lock();
+    }
+
+    fn set_window_size(&self, size: WindowSize) -> Result<()> {
+        *self.window_size.lock() = size;
+        return Ok(());
+    }
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Self { state: DecoderState::Data, subnegotiation: Vec::new() }
+    }
+
+    /// Decode data received from the client. Commands may be split across several calls.
+    pub fn decode(&mut self, data: &[u8]) -> Decoded {
+        let mut decoded = Decoded { input: Vec::new(), replies: Vec::new(), window_size: None };
+        for &byte in data {
+            self.state = match (self.state, byte) {
+                (DecoderState::Data | DecoderState::CarriageReturn, IAC) => DecoderState::Command,
+                (DecoderState::CarriageReturn, b'\n' | 0) => DecoderState::Data,
+                (DecoderState::Data | DecoderState::CarriageReturn, b'\r') => {
+                    decoded.input.push(b'\n');
+                    DecoderState::CarriageReturn
+                }
+                (DecoderState::Data | DecoderState::CarriageReturn, byte) => {
+                    // Backspace sends delete
+                    decoded.input.push(if byte == 0x7f { 0x08 } else { byte });
+                    DecoderState::Data
+                }
+                (DecoderState::Command, IAC) => {
+                    decoded.input.push(IAC);
+                    DecoderState::Data
+                }
+                (DecoderState::Command, INTERRUPT_PROCESS) => {
+                    // Handled by the line discipline like Ctrl+C
+                    decoded.input.push(0x03);
+                    DecoderState::Data
+                }
+                (DecoderState::Command, WILL | WONT | DO | DONT) => DecoderState::Negotiation(byte),
+                (DecoderState::Command, SUBNEGOTIATION) => {
+                    self.subnegotiation.clear();
+                    DecoderState::Subnegotiation
+                }
+                (DecoderState::Command, _) => DecoderState::Data,
+                (DecoderState::Negotiation(command), option) => {
+                    negotiate(command, option, &mut decoded.replies);
+                    DecoderState::Data
+                }
+                (DecoderState::Subnegotiation, IAC) => DecoderState::SubnegotiationCommand,
+                (DecoderState::Subnegotiation, byte) => {
+                    self.push_subnegotiation(byte);
+                    DecoderState::Subnegotiation
+                }
+                (DecoderState::SubnegotiationCommand, IAC) => {
+                    self.push_subnegotiation(IAC);
+                    DecoderState::Subnegotiation
+                }
+                (DecoderState::SubnegotiationCommand, SUBNEGOTIATION_END) => {
+                    decoded.window_size = self.window_size().or(decoded.window_size);
+                    DecoderState::Data
+                }
+                (DecoderState::SubnegotiationCommand, _) => DecoderState::Data
+            };
+        }
+
+        return decoded;
+    }
+
+    fn push_subnegotiation(&mut self, byte: u8) {
+        if self.subnegotiation.len() < MAX_SUBNEGOTIATION_LENGTH {
+            self.subnegotiation.push(byte);
+        }
+    }
+
+    /// Parse a window size report (RFC 1073), which consists of the option followed by the width and height.
+    fn window_size(&self) -> Option<WindowSize> {
+        return match *self.subnegotiation.as_slice() {
+            [OPTION_WINDOW_SIZE, columns_high, columns_low, rows_high, rows_low] => {
+                let size = WindowSize { columns: u16::from_be_bytes([columns_high, columns_low]), rows: u16::from_be_bytes([rows_high, rows_low]) };
+                (size.columns > 0 && size.rows > 0).then_some(size)
+            }
+            _ => None
+        };
+    }
+}
+
+/// Refuse requests for unsupported options. Supported options have already been requested by the server (see `NEGOTIATION`),
+/// so requests for them are acknowledgements, which must not be answered (otherwise, both sides would negotiate endlessly).
+fn negotiate(command: u8, option: u8, replies: &mut Vec<u8>) {
+    match command {
+        DO if option != OPTION_ECHO && option != OPTION_SUPPRESS_GO_AHEAD => replies.extend_from_slice(&[IAC, WONT, option]),
+        WILL if option != OPTION_WINDOW_SIZE && option != OPTION_SUPPRESS_GO_AHEAD => replies.extend_from_slice(&[IAC, DONT, option]),
+        _ => {}
+    }
+}
@@ -0,0 +1,208 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use syscall::error::Errno;
+use syscall::file::PollEvents;
+use syscall::net::MAX_BACKLOG;
+use crate::fs::pipe::{pipe, PipeReader, PipeWriter};
+use crate::fs::poll;
+use crate::fs::poll::Pollable;
+use crate::fs::{vfs, File};
+use crate::net::socket::{Socket, SocketAddress};
+use crate::net::Result;
+use crate::process::wait_queue::{WaitQueue, Waiter};
+use crate::sync::Mutex;
+
+/// Local stream socket (`Protocol::Unix`), which is bound to and connected via a socket node in the filesystem.
+/// Connected sockets exchange data via a pair of pipes, so they do not depend on the protocol stack.
+/// Like a TCP socket, it becomes either a listening socket (via `listen()`) or a connected socket (via `connect()` or `accept()`).
+pub struct UnixSocket {
+    state: Mutex<SocketState>,
+}
+
+enum SocketState {
+    Unbound,
+    Bound(Arc<Endpoint>),
+    Listening(Arc<Endpoint>),
+    Connected(Arc<Connection>),
+}
+
+/// Rendezvous point, which is stored in a socket node and via which clients connect to the socket listening on it.
+pub struct Endpoint {
+    state: Mutex<EndpointState>,
+    readable: WaitQueue,
+}
+
+struct EndpointState {
+    /// Maximum number of pending connections (`None`, while no socket is listening).
+    backlog: Option<usize>,
+    /// Connections, which have not been accepted yet.
+    pending: VecDeque<Connection>,
+}
+
+/// One side of a connection: The peer writes into `reader`'s pipe and reads from `writer`'s pipe.
+struct Connection {
+    reader: PipeReader,
+    /// `None` after `shutdown()`. Shared with running `send()` calls, so that shutting down does not wait for them.
+    writer: Mutex<Option<Arc<PipeWriter>>>,
+}
+
+impl UnixSocket {
+    pub fn new() -> Arc<Self> {
+        return Arc::new(Self { state: Mutex::new(SocketState::Unbound) });
+    }
+
+    fn from_connection(connection: Connection) -> Arc<Self> {
+        return Arc::new(Self { state: Mutex::new(SocketState::Connected(Arc::new(connection))) });
+    }
+
+    /// Create a socket node at `path` and bind the socket to it. Fails with `Errno::AddressInUse`, if `path` already exists
+    /// (socket nodes are not removed, when the socket is closed).
+    pub fn bind(&self, path: &str) -> Result<()> {
+        let mut state = self.state.lock();
+        if !matches!(*state, SocketState::Unbound) {
+            return Err(Errno::InvalidArgument);
+        }
+
+        *state = SocketState::Bound(vfs::mksocket(path)?);
+        return Ok(());
+    }
+
+    /// Connect to the socket listening on the socket node at `path`. Returns immediately, without waiting for the connection to be accepted.
+    /// Fails with `Errno::ConnectionRefused`, if no socket is listening or its backlog is full.
+    pub fn connect(&self, path: &str) -> Result<()> {
+        let mut state = self.state.lock();
+        match *state {
+            SocketState::Unbound => {}
+            SocketState::Connected(_) => return Err(Errno::IsConnected),
+            _ => return Err(Errno::InvalidArgument)
+        }
+
+        let connection = vfs::socket_endpoint(path)?.connect()?;
+        *state = SocketState::Connected(Arc::new(connection));
+
+        return Ok(());
+    }
+
+    fn connection(&self) -> Result<Arc<Connection>> {
+        return match &*self.state.lock() {
+            SocketState::Connected(connection) => Ok(Arc::clone(connection)),
+            _ => Err(Errno::NotConnected)
+        };
+    }
+}
+
+impl Socket for UnixSocket {
+    fn listen(&self, backlog: usize) -> Result<()> {
+        let mut state = self.state.lock();
+        let endpoint = match &*state {
+            SocketState::Bound(endpoint) => Arc::clone(endpoint),
+            SocketState::Listening(_) => return Ok(()),
+            SocketState::Connected(_) => return Err(Errno::IsConnected),
+            // There is no automatic binding like for TCP sockets, since there is no path to choose
+            SocketState::Unbound => return Err(Errno::InvalidArgument)
+        };
+
+        endpoint.state.lock().backlog = Some(backlog.clamp(1, MAX_BACKLOG));
+        *state = SocketState::Listening(endpoint);
+
+        return Ok(());
+    }
+
+    /// Local sockets have no addresses, so the returned address is unspecified.
+    fn accept(&self) -> Result<(Arc<dyn Socket>, SocketAddress)> {
+        let endpoint = match &*self.state.lock() {
+            SocketState::Listening(endpoint) => Arc::clone(endpoint),
+            _ => return Err(Errno::InvalidArgument)
+        };
+
+        let mut state = endpoint.state.lock();
+        loop {
+            if let Some(connection) = state.pending.pop_front() {
+                return Ok((UnixSocket::from_connection(connection), SocketAddress::default()));
+            }
+
+            endpoint.readable.wait(state);
+            state = endpoint.state.lock();
+        }
+    }
+
+    fn send(&self, data: &[u8]) -> Result<usize> {
+        let writer = self.connection()?.writer.lock().clone().ok_or(Errno::BrokenPipe)?;
+        return writer.write(data);
+    }
+
+    fn receive(&self, buffer: &mut [u8]) -> Result<usize> {
+        return self.connection()?.reader.read(buffer);
+    }
+
+    fn shutdown(&self) -> Result<()> {
+        self.connection()?.writer.lock().take();
+        return Ok(());
+    }
+}
+
+impl Pollable for UnixSocket {
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        return match &*self.state.lock() {
+            SocketState::Listening(endpoint) => endpoint.poll(events, waiter),
+            SocketState::Connected(connection) => connection.poll(events, waiter),
+            _ => PollEvents::HANG_UP
+        };
+    }
+}
+
+impl Drop for UnixSocket {
+    /// Closing a listening socket refuses further connections and closes all connections, which have not been accepted yet.
+    fn drop(&mut self) {
+        if let SocketState::Listening(endpoint) = &*self.state.lock() {
+            let mut state = endpoint.state.lock();
+            state.backlog = None;
+            state.pending.clear();
+        }
+    }
+}
+
+impl Endpoint {
+    pub fn new() -> Self {
+        return Self { state: Mutex::new(EndpointState { backlog: None, pending: VecDeque::new() }), readable: WaitQueue::new() };
+    }
+
+    /// Queue a new connection for the listening socket and return the client's side of it.
+    fn connect(&self) -> Result<Connection> {
+        let mut state = self.state.lock();
+        match state.backlog {
+            Some(backlog) if state.pending.len() < backlog => {}
+            _ => return Err(Errno::ConnectionRefused)
+        }
+
+        let (client_reader, server_writer) = pipe();
+        let (server_reader, client_writer) = pipe();
+        state.pending.push_back(Connection::new(server_reader, server_writer));
+        drop(state);
+        self.readable.notify_all();
+
+        return Ok(Connection::new(client_reader, client_writer));
+    }
+
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        let state = self.state.lock();
+        let ready = if state.pending.is_empty() { PollEvents::empty() } else { PollEvents::READABLE };
+
+        return poll::ready_or_register(ready, events, waiter, &[(PollEvents::READABLE, &self.readable)]);
+    }
+}
+
+impl Connection {
+    fn new(reader: PipeReader, writer: PipeWriter) -> Self {
+        Self { reader, writer: Mutex::new(Some(Arc::new(writer))) }
+    }
+
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<Waiter>>) -> PollEvents {
+        let mut ready = self.reader.poll(events, waiter);
+        if let Some(writer) = &*self.writer.lock() {
+            ready |= writer.poll(events, waiter);
+        }
+
+        return ready;
+    }
+}
@@ -0,0 +1,201 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::slice;
+use goblin::elf::header::{EM_X86_64, ET_CORE};
+use goblin::elf::note::{NT_PRPSINFO, NT_PRSTATUS};
+use goblin::elf::program_header::{PF_R, PF_W, PF_X, PT_LOAD, PT_NOTE};
+use log::{info, warn};
+use syscall::file::{OpenFlags, STDERR};
+use syscall::signal::Signal;
+use x86_64::VirtAddr;
+use crate::fs::{vfs, File, Result};
+use crate::memory::PAGE_SIZE;
+use crate::memory::r#virtual::{VirtualMemoryArea, VmaType};
+use crate::process::process::Process;
+
+/// Core files are written to '<CORE_DIRECTORY>/core.<process id>' (a tmpfs, so they are lost on reboot).
+pub const CORE_DIRECTORY: &str = "/tmp";
+
+const FILE_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+/// Size of `struct elf_prstatus` and `struct elf_prpsinfo` on x86_64 Linux, which GDB expects in the notes of a core file.
+const PRSTATUS_SIZE: usize = 336;
+const PRPSINFO_SIZE: usize = 136;
+/// Offset of the registers in `struct elf_prstatus`.
+const PRSTATUS_REGISTERS_OFFSET: usize = 112;
+
+/// Registers of a crashed thread in the order of `struct user_regs_struct` on x86_64 Linux.
+/// Registers, which are not known at the time of the crash, are 0 (e.g. the general purpose registers after an exception).
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Registers {
+    pub r15: u64, pub r14: u64, pub r13: u64, pub r12: u64, pub rbp: u64, pub rbx: u64,
+    pub r11: u64, pub r10: u64, pub r9: u64, pub r8: u64, pub rax: u64, pub rcx: u64,
+    pub rdx: u64, pub rsi: u64, pub rdi: u64, pub orig_rax: u64, pub rip: u64, pub cs: u64,
+    pub rflags: u64, pub rsp: u64, pub ss: u64, pub fs_base: u64, pub gs_base: u64,
+    pub ds: u64, pub es: u64, pub fs: u64, pub gs: u64,
+}
+
+/// Check, if a process terminated by `signal` dumps its core (like on Linux, for signals indicating a bug in the program).
+pub fn dumps_core(signal: Signal) -> bool {
+    return matches!(signal, Signal::Quit | Signal::IllegalInstruction | Signal::Trap | Signal::Abort
+        | Signal::BusError | Signal::FloatingPointException | Signal::SegmentationFault);
+}
+
+/// Report the crash of `process`, which must be the current process, on its standard error and write its core file.
+/// `fault_address` is the address, whose access has caused the crash (e.g. CR2 after a page fault).
+pub fn dump(process: &Process, signal: Signal, registers: &Registers, fault_address: Option<u64>) {
+    let command_line = process.arguments().lock().join(" ");
+    let location = match process.resolve_symbol(registers.rip) {
+        Some((name, offset)) => format!("{}+0x{:x}", name, offset),
+        None => String::from("unknown")
+    };
+
+    let mut summary = format!("Process [{}] ({}) crashed with signal [{:?}] at [0x{:016x}] ({})\n", process.id(), command_line, signal, registers.rip, location);
+    if let Some(address) = fault_address {
+        summary.push_str(&format!("Faulting address: [0x{:016x}]\n", address));
+    }
+
+    let path = format!("{}/core.{}", CORE_DIRECTORY, process.id());
+    match write(&path, process, signal, registers) {
+        Ok(()) => summary.push_str(&format!("Core dumped to [{}]\n", path)),
+        Err(err) => warn!("Failed to write core file [{}] (Error: {:?})", path, err)
+    }
+
+    info!("{}", summary.trim_end());
+    if let Ok(stderr) = process.files().lock().get(STDERR) {
+        stderr.write(summary.as_bytes()).ok();
+    }
+}
+
+fn write(path: &str, process: &Process, signal: Signal, registers: &Registers) -> Result<()> {
+    let areas = process.memory_areas().into_iter().filter(|area| area.typ() != VmaType::Device).collect::<Vec<VirtualMemoryArea>>();
+    let command_line = process.arguments().lock().clone();
+    let file = vfs::open(path, OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE)?;
+
+    file.write(&headers(process.id(), signal, registers, &command_line, &areas))?;
+
+    // Memory is read through the current address space, skipping pages that are not mapped
+    let address_space = process.address_space();
+    let zero_page = [0u8; PAGE_SIZE];
+    for area in areas.iter() {
+        let mut page = area.start().as_u64();
+        while page < area.end().as_u64() {
+            let data = if address_space.translate(VirtAddr::new(page)).is_some() {
+                unsafe { slice::from_raw_parts(page as *const u8, PAGE_SIZE) }
+            } else {
+                &zero_page
+            };
+
+            write_all(&file, data)?;
+            page += PAGE_SIZE as u64;
+        }
+    }
+
+    return Ok(());
+}
+
+fn write_all(file: &Arc<dyn File>, mut data: &[u8]) -> Result<()> {
+    while !data.is_empty() {
+        let written = file.write(data)?;
+        data = &data[written..];
+    }
+
+    return Ok(());
+}
+
+/// Build everything in front of the memory contents: The file header, the program headers (one note segment followed by
+/// a loadable segment for each memory area) and the notes. The memory contents start at the next page boundary.
+pub fn headers(process_id: usize, signal: Signal, registers: &Registers, command_line: &[String], areas: &[VirtualMemoryArea]) -> Vec<u8> {
+    let mut notes = Vec::new();
+    push_note(&mut notes, NT_PRSTATUS, &prstatus(process_id, signal, registers));
+    push_note(&mut notes, NT_PRPSINFO, &prpsinfo(process_id, command_line));
+
+    let segment_count = areas.len() + 1;
+    let notes_offset = FILE_HEADER_SIZE + segment_count * PROGRAM_HEADER_SIZE;
+    let memory_offset = (notes_offset + notes.len()).next_multiple_of(PAGE_SIZE);
+
+    let mut headers = Vec::with_capacity(memory_offset);
+    headers.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]); // 64-bit, little endian
+    headers.extend_from_slice(&ET_CORE.to_le_bytes());
+    headers.extend_from_slice(&EM_X86_64.to_le_bytes());
+    headers.extend_from_slice(&1u32.to_le_bytes());
+    headers.extend_from_slice(&0u64.to_le_bytes()); // No entry point
+    headers.extend_from_slice(&(FILE_HEADER_SIZE as u64).to_le_bytes()); // Program headers follow the file header
+    headers.extend_from_slice(&0u64.to_le_bytes()); // No section headers
+    headers.extend_from_slice(&0u32.to_le_bytes());
+    for value in [FILE_HEADER_SIZE as u16, PROGRAM_HEADER_SIZE as u16, segment_count as u16, 64, 0, 0] {
+        headers.extend_from_slice(&value.to_le_bytes());
+    }
+
+    push_program_header(&mut headers, PT_NOTE, 0, notes_offset as u64, 0, notes.len() as u64, 4);
+
+    let mut offset = memory_offset as u64;
+    for area in areas {
+        let flags = match area.typ() {
+            VmaType::Code | VmaType::Library => PF_R | PF_W | PF_X,
+            _ => PF_R | PF_W
+        };
+
+        let size = area.end().as_u64() - area.start().as_u64();
+        push_program_header(&mut headers, PT_LOAD, flags, offset, area.start().as_u64(), size, PAGE_SIZE as u64);
+        offset += size;
+    }
+
+    headers.extend_from_slice(&notes);
+    headers.resize(memory_offset, 0);
+
+    return headers;
+}
+
+fn push_program_header(buffer: &mut Vec<u8>, typ: u32, flags: u32, offset: u64, address: u64, size: u64, alignment: u64) {
+    buffer.extend_from_slice(&typ.to_le_bytes());
+    buffer.extend_from_slice(&flags.to_le_bytes());
+    for value in [offset, address, 0, size, size, alignment] {
+        buffer.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Append a note with the name 'CORE' (name and description are padded to 4 bytes).
+fn push_note(buffer: &mut Vec<u8>, typ: u32, description: &[u8]) {
+    buffer.extend_from_slice(&5u32.to_le_bytes());
+    buffer.extend_from_slice(&(description.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(&typ.to_le_bytes());
+    buffer.extend_from_slice(b"CORE\0\0\0\0");
+    buffer.extend_from_slice(description);
+    buffer.resize(buffer.len().next_multiple_of(4), 0);
+}
+
+fn prstatus(process_id: usize, signal: Signal, registers: &Registers) -> [u8; PRSTATUS_SIZE] {
+    let mut status = [0u8; PRSTATUS_SIZE];
+    status[0..4].copy_from_slice(&(signal as u32).to_le_bytes()); // si_signo
+    status[12..14].copy_from_slice(&(signal as u16).to_le_bytes()); // pr_cursig
+    status[32..36].copy_from_slice(&(process_id as u32).to_le_bytes()); // pr_pid
+
+    let r = registers;
+    let values = [r.r15, r.r14, r.r13, r.r12, r.rbp, r.rbx, r.r11, r.r10, r.r9, r.r8, r.rax, r.rcx, r.rdx, r.rsi,
+        r.rdi, r.orig_rax, r.rip, r.cs, r.rflags, r.rsp, r.ss, r.fs_base, r.gs_base, r.ds, r.es, r.fs, r.gs];
+    for (index, value) in values.iter().enumerate() {
+        let offset = PRSTATUS_REGISTERS_OFFSET + index * 8;
+        status[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    return status;
+}
+
+fn prpsinfo(process_id: usize, command_line: &[String]) -> [u8; PRPSINFO_SIZE] {
+    let mut info = [0u8; PRPSINFO_SIZE];
+    info[1] = b'R'; // pr_sname
+    info[24..28].copy_from_slice(&(process_id as u32).to_le_bytes()); // pr_pid
+
+    // pr_fname (name of the executable) and pr_psargs (command line), both null-terminated
+    let name = command_line.first().and_then(|path| path.rsplit('/').next()).unwrap_or("");
+    let arguments = command_line.join(" ");
+    let name_length = name.len().min(15);
+    let arguments_length = arguments.len().min(79);
+    info[40..40 + name_length].copy_from_slice(&name.as_bytes()[..name_length]);
+    info[56..56 + arguments_length].copy_from_slice(&arguments.as_bytes()[..arguments_length]);
+
+    return info;
+}
@@ -0,0 +1,276 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+use goblin::elf::Elf;
+use goblin::elf::header::{EM_X86_64, ET_DYN, ET_EXEC};
+use goblin::elf::program_header::{ProgramHeader, PF_W, PF_X, PT_LOAD};
+use goblin::elf::reloc::{Reloc, R_X86_64_64, R_X86_64_GLOB_DAT, R_X86_64_JUMP_SLOT, R_X86_64_NONE, R_X86_64_RELATIVE};
+use goblin::elf::section_header::{SHN_ABS, SHN_UNDEF};
+use goblin::elf::sym::{Sym, STB_GLOBAL, STB_LOCAL, STB_WEAK};
+use log::warn;
+use syscall::error::Errno;
+use x86_64::structures::paging::page::PageRange;
+use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame};
+use x86_64::VirtAddr;
+use crate::cpu::features;
+use crate::cpu::features::Feature;
+use crate::memory::r#virtual::{VirtualMemoryArea, VmaType};
+use crate::memory::{physical, MemorySpace, PAGE_SIZE};
+use crate::process::process::Process;
+use crate::process::thread::USER_STACK_ADDRESS;
+use crate::random;
+
+/// Lowest address of an application (see 'application/link.ld'). Segments must lie between this address and the user stack.
+pub const USER_SPACE_START: u64 = 0x10000000000;
+
+/// Position-independent objects are loaded at a random page in this many pages (64 GiB) above their start address.
+pub const LOAD_BASE_PAGES: u64 = 1 << 24;
+/// Largest size of a position-independent executable in memory.
+const MAX_POSITION_INDEPENDENT_SIZE: u64 = 0x10000000000;
+
+/// Read-only pages of a shared library, which are shared between all processes using it (by their offset from the load base).
+pub type SharedPages = BTreeMap<u64, PhysFrame>;
+
+/// Object (executable or shared library), whose segments have been copied into memory. Relocations are applied
+/// via the physical addresses of its pages, so they can refer to symbols of objects, which are loaded later.
+pub struct Image<'a> {
+    elf: Elf<'a>,
+    base: u64,
+    pages: BTreeMap<Page, (PhysFrame, PageTableFlags)>,
+}
+
+/// Parse the executable in `buffer` and check, that it can be loaded by `Image::load()`. Malformed binaries are rejected with
+/// `Errno::ExecFormat`: All loadable segments must lie inside the file and inside the user address range without overlapping
+/// each other and the entry point (if any) must lie inside an executable segment. Position-independent executables and shared
+/// libraries (`ET_DYN`) have addresses relative to their load base. Relocations must patch memory inside the segments.
+/// An interpreter (`PT_INTERP`) is accepted, but ignored, since the kernel acts as the runtime loader (see `process::loader`).
+pub fn parse(buffer: &[u8]) -> Result<Elf<'_>, Errno> {
+    let elf = Elf::parse(buffer).map_err(|_| Errno::ExecFormat)?;
+    if !elf.is_64 || !elf.little_endian || elf.header.e_machine != EM_X86_64 || !matches!(elf.header.e_type, ET_EXEC | ET_DYN) {
+        return Err(Errno::ExecFormat);
+    }
+
+    let mut segments = loadable_segments(&elf).peekable();
+    if segments.peek().is_none() {
+        return Err(Errno::ExecFormat);
+    }
+
+    // Addresses of position-independent executables are relative to the load base
+    let (mut previous_end, limit) = if is_position_independent(&elf) { (0, MAX_POSITION_INDEPENDENT_SIZE) } else { (USER_SPACE_START, USER_STACK_ADDRESS as u64) };
+    for segment in segments {
+        let file_end = segment.p_offset.checked_add(segment.p_filesz).ok_or(Errno::ExecFormat)?;
+        let memory_end = segment.p_vaddr.checked_add(segment.p_memsz).ok_or(Errno::ExecFormat)?;
+        if segment.p_filesz > segment.p_memsz || file_end > buffer.len() as u64 {
+            return Err(Errno::ExecFormat);
+        }
+        // Segments are sorted by address (as required by the ELF specification), so comparing with the previous one detects overlaps
+        if segment.p_vaddr < previous_end || memory_end > limit {
+            return Err(Errno::ExecFormat);
+        }
+
+        previous_end = memory_end;
+    }
+
+    // Shared libraries usually have no entry point (0)
+    let has_entry = elf.entry != 0 || !is_position_independent(&elf);
+    if has_entry && !loadable_segments(&elf).any(|segment| segment.p_flags & PF_X != 0 && segment.vm_range().contains(&(elf.entry as usize))) {
+        return Err(Errno::ExecFormat);
+    }
+
+    // Each relocation patches 8 bytes, which must lie inside a segment
+    let relocations_valid = relocations(&elf).all(|relocation| match relocation.r_type {
+        R_X86_64_NONE => true,
+        R_X86_64_RELATIVE | R_X86_64_64 | R_X86_64_GLOB_DAT | R_X86_64_JUMP_SLOT => {
+            let symbol_valid = relocation.r_type == R_X86_64_RELATIVE || (relocation.r_sym != 0 && relocation.r_sym < elf.dynsyms.len());
+            symbol_valid && loadable_segments(&elf).any(|segment| {
+                relocation.r_offset >= segment.p_vaddr && relocation.r_offset.checked_add(8).is_some_and(|end| end <= segment.p_vaddr + segment.p_memsz)
+            })
+        }
+        _ => false
+    });
+    if !relocations_valid || !elf.dynrels.is_empty() {
+        return Err(Errno::ExecFormat);
+    }
+
+    return Ok(elf);
+}
+
+/// Position-independent executables and shared libraries (`ET_DYN`) are loaded at a random address.
+pub fn is_position_independent(elf: &Elf) -> bool {
+    return elf.header.e_type == ET_DYN;
+}
+
+/// The address, at which an executable is loaded (0, if it is not position-independent and is loaded at its own addresses).
+pub fn executable_base(elf: &Elf) -> u64 {
+    return if is_position_independent(elf) { USER_SPACE_START + random_pages(LOAD_BASE_PAGES) } else { 0 };
+}
+
+/// A random offset of less than `page_count` pages (used to randomize the address space layout).
+pub fn random_pages(page_count: u64) -> u64 {
+    return (random::next_u64() % page_count) * PAGE_SIZE as u64;
+}
+
+impl<'a> Image<'a> {
+    /// Copy the loadable segments of `elf` (which must have been checked by `parse()`) into memory, with all addresses offset by `base`.
+    /// Each page gets the permissions of its segments (code is not writable and data is not executable, if the CPU supports it).
+    /// Memory beyond the part of a segment stored in the file (e.g. BSS) is zeroed. If two segments share a page, it gets the permissions of both.
+    /// Read-only pages, which are not patched by relocations, are taken from `shared` or added to it, so that they exist only once in memory.
+    pub fn load(elf: Elf<'a>, buffer: &[u8], base: u64, mut shared: Option<&mut SharedPages>) -> Self {
+        let no_execute = if features::has(Feature::Nx) { PageTableFlags::NO_EXECUTE } else { PageTableFlags::empty() };
+        let mut page_flags = BTreeMap::<Page, PageTableFlags>::new();
+        for segment in loadable_segments(&elf) {
+            for page in segment_pages(base, segment) {
+                let flags = page_flags.entry(page).or_insert(PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | no_execute);
+                if segment.p_flags & PF_W != 0 {
+                    flags.insert(PageTableFlags::WRITABLE);
+                }
+                if segment.p_flags & PF_X != 0 {
+                    flags.remove(PageTableFlags::NO_EXECUTE);
+                }
+            }
+        }
+
+        let relocated = relocations(&elf)
+            .flat_map(|relocation| [base + relocation.r_offset, base + relocation.r_offset + 7])
+            .map(|address| Page::containing_address(VirtAddr::new(address)))
+            .collect::<BTreeSet<Page>>();
+
+        let mut pages = BTreeMap::new();
+        for (page, flags) in page_flags {
+            let offset = page.start_address().as_u64() - base;
+            let shareable = !flags.contains(PageTableFlags::WRITABLE) && !relocated.contains(&page);
+            if let Some(frame) = shared.as_deref().filter(|_| shareable).and_then(|shared| shared.get(&offset)) {
+                physical::share(*frame);
+                pages.insert(page, (*frame, flags));
+                continue;
+            }
+
+            let frame = physical::alloc(1).start;
+            unsafe { (frame.start_address().as_u64() as *mut u8).write_bytes(0, PAGE_SIZE); }
+            for segment in loadable_segments(&elf) {
+                copy_segment(frame, page, base, segment, buffer);
+            }
+
+            if let Some(shared) = shared.as_deref_mut().filter(|_| shareable) {
+                physical::share(frame);
+                shared.insert(offset, frame);
+            }
+
+            pages.insert(page, (frame, flags));
+        }
+
+        return Self { elf, base, pages };
+    }
+
+    pub fn elf(&self) -> &Elf<'a> {
+        return &self.elf;
+    }
+
+    pub fn base(&self) -> u64 {
+        return self.base;
+    }
+
+    /// End of the last page (the next object may be loaded from here on).
+    pub fn end(&self) -> VirtAddr {
+        let (last_page, _) = self.pages.last_key_value().unwrap();
+        return (*last_page + 1).start_address();
+    }
+
+    /// Map all pages into the address space of `process` (as one area of type `typ` for each run of contiguous pages).
+    pub fn map(&self, process: &Process, typ: VmaType) {
+        process.address_space().map_iter(MemorySpace::User, self.pages.iter().map(|(page, (frame, flags))| (*frame, *page, *flags)));
+
+        let mut areas = Vec::<PageRange>::new();
+        for page in self.pages.keys() {
+            match areas.last_mut() {
+                Some(area) if area.end == *page => area.end += 1,
+                _ => areas.push(PageRange { start: *page, end: *page + 1 })
+            }
+        }
+
+        for area in areas {
+            process.add_vma(VirtualMemoryArea::new(area, typ));
+        }
+    }
+
+    /// Address of the global symbol `name`, if this object defines it.
+    pub fn symbol(&self, name: &str) -> Option<u64> {
+        return self.elf.dynsyms.iter()
+            .find(|symbol| symbol.st_shndx != SHN_UNDEF as usize && matches!(symbol.st_bind(), STB_GLOBAL | STB_WEAK) && self.elf.dynstrtab.get_at(symbol.st_name) == Some(name))
+            .map(|symbol| self.address(&symbol));
+    }
+
+    /// Address of the defined `symbol` (absolute symbols are not moved with the load base).
+    fn address(&self, symbol: &Sym) -> u64 {
+        return if symbol.st_shndx == SHN_ABS as usize { symbol.st_value } else { self.base + symbol.st_value };
+    }
+
+    /// Apply all relocations. Symbols (except for local ones) are looked up via `resolve`, so that the first object
+    /// defining a symbol wins (like with a flat namespace). Fails with `Errno::ExecFormat`, if a non-weak symbol is undefined.
+    pub fn relocate(&self, resolve: impl Fn(&str) -> Option<u64>) -> Result<(), Errno> {
+        for relocation in relocations(&self.elf) {
+            let addend = relocation.r_addend.unwrap_or(0);
+            let value = match relocation.r_type {
+                R_X86_64_NONE => continue,
+                R_X86_64_RELATIVE => self.base.wrapping_add_signed(addend),
+                _ => {
+                    let symbol = self.elf.dynsyms.get(relocation.r_sym).ok_or(Errno::ExecFormat)?;
+                    let name = self.elf.dynstrtab.get_at(symbol.st_name).unwrap_or("");
+                    let address = if symbol.st_bind() == STB_LOCAL { Some(self.address(&symbol)) } else { resolve(name) };
+                    let address = match address {
+                        Some(address) => address,
+                        None if symbol.st_bind() == STB_WEAK => 0,
+                        None => {
+                            warn!("Undefined symbol [{}]", name);
+                            return Err(Errno::ExecFormat);
+                        }
+                    };
+
+                    if relocation.r_type == R_X86_64_64 { address.wrapping_add_signed(addend) } else { address }
+                }
+            };
+
+            self.write(VirtAddr::new(self.base + relocation.r_offset), &value.to_le_bytes());
+        }
+
+        return Ok(());
+    }
+
+    /// Write `data` to `address` via the physical addresses of the pages, possibly crossing a page boundary.
+    fn write(&self, address: VirtAddr, data: &[u8]) {
+        for (index, byte) in data.iter().enumerate() {
+            let address = address + index as u64;
+            let (frame, _) = self.pages[&Page::containing_address(address)];
+            unsafe { ((frame.start_address().as_u64() + u64::from(address.page_offset())) as *mut u8).write(*byte); }
+        }
+    }
+}
+
+/// Copy the part of the segment's file contents, which belongs to `page`, into `frame` (physical memory is identity mapped).
+fn copy_segment(frame: PhysFrame, page: Page, base: u64, segment: &ProgramHeader, buffer: &[u8]) {
+    let start = VirtAddr::new(base + segment.p_vaddr);
+    let page_start = page.start_address().max(start);
+    let file_end = (start + segment.p_filesz).min(page.start_address() + PAGE_SIZE as u64);
+    if page_start < file_end {
+        let offset = segment.p_offset + (page_start - start);
+        let length = (file_end - page_start) as usize;
+        unsafe {
+            let target = (frame.start_address().as_u64() + (page_start - page.start_address())) as *mut u8;
+            target.copy_from(buffer.as_ptr().add(offset as usize), length);
+        }
+    }
+}
+
+fn segment_pages(base: u64, segment: &ProgramHeader) -> PageRange {
+    let start = VirtAddr::new(base + segment.p_vaddr);
+    let end = start + segment.p_memsz;
+    return Page::range(Page::containing_address(start), Page::containing_address(end - 1u64) + 1);
+}
+
+fn loadable_segments<'a>(elf: &'a Elf) -> impl Iterator<Item = &'a ProgramHeader> {
+    return elf.program_headers.iter().filter(|header| header.p_type == PT_LOAD && header.p_memsz > 0);
+}
+
+/// Relocations of the dynamic section and of the procedure linkage table (which are resolved immediately).
+fn relocations<'a>(elf: &'a Elf) -> impl Iterator<Item = Reloc> + 'a {
+    return elf.dynrelas.iter().chain(elf.pltrelocs.iter());
+}
@@ -0,0 +1,141 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use syscall::error::Errno;
+use syscall::file::DescriptorFlags;
+use crate::device::terminal::TerminalFile;
+use crate::fs::{File, Result};
+
+/// Maximum number of open files per process.
+pub const MAX_OPEN_FILES: usize = 64;
+
+/// Open files of a process, indexed by their descriptor.
+/// Cloning the table shares the open files (including their offsets) between both tables.
+#[derive(Clone)]
+pub struct FileDescriptorTable {
+    files: Vec<Option<Descriptor>>,
+}
+
+#[derive(Clone)]
+struct Descriptor {
+    file: Arc<dyn File>,
+    flags: DescriptorFlags,
+}
+
+impl FileDescriptorTable {
+    /// Create a table with the standard descriptors 0-2 (stdin, stdout and stderr) connected to the first console.
+    pub fn new() -> Self {
+        return FileDescriptorTable::with_console(0);
+    }
+
+    /// Create a table with the standard descriptors 0-2 (stdin, stdout and stderr) connected to `console`.
+    pub fn with_console(console: usize) -> Self {
+        let terminal: Arc<dyn File> = Arc::new(TerminalFile::new(console));
+        let descriptor = Descriptor { file: terminal, flags: DescriptorFlags::empty() };
+        return Self { files: Vec::from([Some(descriptor.clone()), Some(descriptor.clone()), Some(descriptor)]) };
+    }
+
+    /// Return the console, standard input is connected to (if it is connected to one).
+    pub fn console(&self) -> Option<usize> {
+        let descriptor = self.files.first()?.as_ref()?;
+        return (descriptor.file.as_ref() as &dyn Any).downcast_ref::<TerminalFile>().map(TerminalFile::console);
+    }
+
+    /// Create the table for an application started by the owner of this table.
+    /// All open files are inherited with the same descriptors, except for those marked with `DescriptorFlags::CLOSE_ON_EXEC`.
+    pub fn inherit(&self) -> Self {
+        let files = self.files.iter()
+            .map(|entry| entry.clone().filter(|descriptor| !descriptor.flags.contains(DescriptorFlags::CLOSE_ON_EXEC)))
+            .collect();
+
+        return Self { files };
+    }
+
+    /// Add a file to the table and return the lowest free descriptor.
+    pub fn insert(&mut self, file: Arc<dyn File>, flags: DescriptorFlags) -> Result<usize> {
+        let descriptor = Descriptor { file, flags };
+        if let Some(fd) = self.files.iter().position(|entry| entry.is_none()) {
+            self.files[fd] = Some(descriptor);
+            return Ok(fd);
+        }
+
+        if self.files.len() >= MAX_OPEN_FILES {
+            return Err(Errno::TooManyFiles);
+        }
+
+        self.files.push(Some(descriptor));
+        return Ok(self.files.len() - 1);
+    }
+
+    pub fn get(&self, fd: usize) -> Result<Arc<dyn File>> {
+        return self.descriptor(fd).map(|descriptor| Arc::clone(&descriptor.file));
+    }
+
+    /// List all open descriptors together with their files.
+    pub fn descriptors(&self) -> Vec<(usize, Arc<dyn File>)> {
+        return self.files.iter().enumerate()
+            .filter_map(|(fd, entry)| entry.as_ref().map(|descriptor| (fd, Arc::clone(&descriptor.file))))
+            .collect();
+    }
+
+    pub fn remove(&mut self, fd: usize) -> Result<Arc<dyn File>> {
+        return match self.files.get_mut(fd) {
+            Some(entry) => entry.take().map(|descriptor| descriptor.file).ok_or(Errno::BadDescriptor),
+            None => Err(Errno::BadDescriptor)
+        };
+    }
+
+    /// Remove all descriptors (e.g. when the process exits) and return their files,
+    /// which should be released after the table has been unlocked.
+    pub fn close_all(&mut self) -> Vec<Arc<dyn File>> {
+        return self.files.drain(..)
+            .filter_map(|entry| entry.map(|descriptor| descriptor.file))
+            .collect();
+    }
+
+    /// Let the lowest free descriptor refer to the same open file as `fd` (like `dup()`).
+    pub fn duplicate(&mut self, fd: usize) -> Result<usize> {
+        let file = self.get(fd)?;
+        return self.insert(file, DescriptorFlags::empty());
+    }
+
+    /// Let `new_fd` refer to the same open file as `fd`, closing the file previously referred to by `new_fd` (like `dup2()`).
+    /// Returns the closed file, which should be released after the table has been unlocked.
+    pub fn duplicate_to(&mut self, fd: usize, new_fd: usize, flags: DescriptorFlags) -> Result<Option<Arc<dyn File>>> {
+        let file = self.get(fd)?;
+        if new_fd >= MAX_OPEN_FILES {
+            return Err(Errno::BadDescriptor);
+        }
+        if fd == new_fd {
+            return Ok(None);
+        }
+
+        if new_fd >= self.files.len() {
+            self.files.resize(new_fd + 1, None);
+        }
+
+        let old = self.files[new_fd].replace(Descriptor { file, flags });
+        return Ok(old.map(|descriptor| descriptor.file));
+    }
+
+    pub fn flags(&self, fd: usize) -> Result<DescriptorFlags> {
+        return self.descriptor(fd).map(|descriptor| descriptor.flags);
+    }
+
+    pub fn set_flags(&mut self, fd: usize, flags: DescriptorFlags) -> Result<()> {
+        return match self.files.get_mut(fd) {
+            Some(Some(descriptor)) => {
+                descriptor.flags = flags;
+                Ok(())
+            }
+            _ => Err(Errno::BadDescriptor)
+        };
+    }
+
+    fn descriptor(&self, fd: usize) -> Result<&Descriptor> {
+        return match self.files.get(fd) {
+            Some(Some(descriptor)) => Ok(descriptor),
+            _ => Err(Errno::BadDescriptor)
+        };
+    }
+}
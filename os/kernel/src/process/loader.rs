@@ -0,0 +1,95 @@
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use goblin::elf::Elf;
+use syscall::error::Errno;
+use crate::fs::vfs;
+use crate::memory::r#virtual::VmaType;
+use crate::process::elf;
+use crate::process::elf::{Image, SharedPages, LOAD_BASE_PAGES};
+use crate::process::process::{Process, Symbol};
+use crate::sync::Mutex;
+
+/// Directory, in which shared libraries are searched (part of the initial ramdisk).
+pub const LIBRARY_DIRECTORY: &str = "/lib";
+
+/// Shared libraries are loaded one after another, starting at a random page in `LOAD_BASE_PAGES` above this address
+/// (between the heap and the files mapped by `sys_map_file()`).
+const LIBRARY_START: u64 = 0x100000000000;
+
+/// Shared libraries, which have been loaded before. Files in the initial ramdisk do not change,
+/// so their contents and read-only pages are kept and shared between all processes using them.
+static LIBRARIES: Mutex<BTreeMap<String, Arc<Library>>> = Mutex::new(BTreeMap::new());
+
+struct Library {
+    buffer: Vec<u8>,
+    shared: Mutex<SharedPages>,
+}
+
+/// Load the executable `elf` (which must have been checked by `elf::parse()`) and all shared libraries it needs
+/// (recursively, in breadth-first order) into `process`, apply their relocations and return the entry point.
+/// Symbols are looked up in the executable first and then in the libraries in the order they have been loaded.
+/// Initialization functions of libraries are not called, so the runtime must not rely on them.
+pub fn load(process: &Process, elf: Elf, buffer: &[u8]) -> Result<u64, Errno> {
+    let mut names = Vec::<String>::new();
+    let mut libraries = Vec::<Arc<Library>>::new();
+    let mut needed = elf.libraries.iter().map(|name| String::from(*name)).collect::<VecDeque<String>>();
+    while let Some(name) = needed.pop_front() {
+        if names.contains(&name) {
+            continue;
+        }
+
+        let library = library(&name)?;
+        needed.extend(elf::parse(&library.buffer)?.libraries.iter().map(|name| String::from(*name)));
+        names.push(name);
+        libraries.push(library);
+    }
+
+    let base = elf::executable_base(&elf);
+    let entry = base + elf.entry;
+    let mut images = Vec::from([Image::load(elf, buffer, base, None)]);
+    images[0].map(process, VmaType::Code);
+
+    let mut next_base = LIBRARY_START + elf::random_pages(LOAD_BASE_PAGES);
+    for library in libraries.iter() {
+        let image = Image::load(elf::parse(&library.buffer)?, &library.buffer, next_base, Some(&mut *library.shared.lock()));
+        image.map(process, VmaType::Library);
+        next_base = image.end().as_u64();
+        images.push(image);
+    }
+
+    // Pages are mapped before relocating, so that they are freed with the process, if a symbol cannot be resolved
+    for image in images.iter() {
+        image.relocate(|name| images.iter().find_map(|image| image.symbol(name)))?;
+    }
+
+    // Function symbols (e.g. for the profiler), taken from the dynamic symbols of libraries without a symbol table
+    process.set_symbols(images.iter().flat_map(|image| {
+        let (symbols, strings) = if image.elf().syms.is_empty() { (&image.elf().dynsyms, &image.elf().dynstrtab) } else { (&image.elf().syms, &image.elf().strtab) };
+        symbols.iter()
+            .filter(|symbol| symbol.is_function() && symbol.st_value != 0)
+            .filter_map(|symbol| strings.get_at(symbol.st_name).map(|name| Symbol::new(image.base() + symbol.st_value, symbol.st_size, name)))
+            .collect::<Vec<Symbol>>()
+    }).collect());
+
+    return Ok(entry);
+}
+
+/// Find the shared library called `name` in `LIBRARY_DIRECTORY` (or among the ones loaded before).
+/// Fails with `Errno::ExecFormat`, if it is not a valid position-independent object.
+fn library(name: &str) -> Result<Arc<Library>, Errno> {
+    if let Some(library) = LIBRARIES.lock().get(name) {
+        return Ok(Arc::clone(library));
+    }
+
+    let buffer = vfs::read_all(&format!("{}/{}", LIBRARY_DIRECTORY, name))?;
+    if !elf::is_position_independent(&elf::parse(&buffer)?) {
+        return Err(Errno::ExecFormat);
+    }
+
+    // Another process may have loaded the library in the meantime
+    let library = Library { buffer, shared: Mutex::new(SharedPages::new()) };
+    return Ok(Arc::clone(LIBRARIES.lock().entry(String::from(name)).or_insert_with(|| Arc::new(library))));
+}
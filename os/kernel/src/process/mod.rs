@@ -1,3 +1,9 @@
+pub mod core_dump;
+pub mod elf;
+pub mod fd_table;
+pub mod loader;
 pub mod scheduler;
 pub mod thread;
+pub mod wait_queue;
 pub mod process;
+pub mod signal;
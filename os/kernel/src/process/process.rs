@@ -1,13 +1,29 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::str::from_utf8;
 use core::sync::atomic::AtomicUsize;
 use core::sync::atomic::Ordering::Relaxed;
-use spin::RwLock;
-use crate::{memory, scheduler};
+use crate::sync::{Mutex, RwLock};
+use syscall::error::Errno;
+use syscall::signal::Signal;
+use crate::{memory, scheduler, timer};
+use crate::device::terminal;
+use crate::fs::{vfs, Result};
 use crate::memory::r#virtual::{AddressSpace, VirtualMemoryArea, VmaType};
+use crate::process::fd_table::FileDescriptorTable;
+use crate::process::signal::SignalState;
+use crate::process::thread::Thread;
+use crate::timer::TimerHandle;
 
 static PROCESSES: RwLock<Vec<Arc<Process>>> = RwLock::new(Vec::new());
 static PROCESS_ID_COUNTER: AtomicUsize = AtomicUsize::new(1);
+/// Id of the init process (0, if it has not been started), see `set_init_process()`.
+static INIT_PROCESS_ID: AtomicUsize = AtomicUsize::new(0);
 
 fn next_process_id() -> usize {
     PROCESS_ID_COUNTER.fetch_add(1, Relaxed)
@@ -27,6 +43,118 @@ pub fn kernel_process() -> Option<Arc<Process>> {
     }
 }
 
+/// Mark `process` as the init process, which starts and supervises all services (see `boot::INIT_APPLICATION`).
+pub fn set_init_process(process: &Process) {
+    INIT_PROCESS_ID.store(process.id(), Relaxed);
+}
+
+/// Return the init process, if it is running.
+pub fn init_process() -> Option<Arc<Process>> {
+    return match INIT_PROCESS_ID.load(Relaxed) {
+        0 => None,
+        id => find_process(id)
+    };
+}
+
+/// Directory, in which applications are searched, if they are not started by an absolute path.
+pub const APPLICATION_DIRECTORY: &str = "/bin";
+
+/// Environment variables of a process (names mapped to values).
+pub type Environment = BTreeMap<String, String>;
+
+/// Maximum length of the interpreter line of a script ('#!<interpreter> [<argument>]').
+const INTERPRETER_LINE_LENGTH: usize = 128;
+
+/// Load the application at `path` into a new process and return its main thread (which still needs to be readied).
+/// Names without a leading '/' are looked up in `APPLICATION_DIRECTORY`. The new process gets the command line
+/// `path` followed by `arguments` (see `Process::arguments()`).
+/// The new process inherits the open files of the current process (see `FileDescriptorTable::inherit()`),
+/// so that its standard descriptors can be redirected before starting it, and a copy of its environment.
+pub fn load_application(path: &str, arguments: &[&str]) -> Result<Rc<Thread>> {
+    let process = current_process();
+    let files = process.files().lock().inherit();
+    return load(path, arguments, files, process.environment().lock().clone(), false);
+}
+
+/// Load the application at `path` like `load_application()`, but without making it the foreground process of its console
+/// (e.g. for a job started by the shell with '&').
+pub fn load_background_application(path: &str, arguments: &[&str]) -> Result<Rc<Thread>> {
+    let process = current_process();
+    let files = process.files().lock().inherit();
+    return load(path, arguments, files, process.environment().lock().clone(), true);
+}
+
+/// Load the application at `path` like `load_application()`, but with its standard descriptors connected to `console`
+/// instead of inheriting the open files and the environment (see `terminal::TERMINAL_COUNT`).
+pub fn load_application_on_console(path: &str, console: usize) -> Result<Rc<Thread>> {
+    return load(path, &[], FileDescriptorTable::with_console(console), Environment::new(), false);
+}
+
+/// A script (starting with '#!<interpreter> [<argument>]') is run by starting the interpreter instead, with the command line
+/// '<interpreter> [<argument>] <path of the script> <arguments>'. The interpreter itself must not be a script.
+fn load(path: &str, arguments: &[&str], files: FileDescriptorTable, environment: Environment, background: bool) -> Result<Rc<Thread>> {
+    let path = if path.starts_with('/') { path.to_string() } else { format!("{}/{}", APPLICATION_DIRECTORY, path) };
+    let mut file = vfs::read_all(&path)?;
+    let mut command_line = Vec::new();
+
+    if let Some((interpreter, argument)) = interpreter(&file)? {
+        file = vfs::read_all(&interpreter)?;
+        command_line.push(interpreter);
+        command_line.extend(argument);
+    }
+
+    command_line.push(path);
+    command_line.extend(arguments.iter().map(|argument| argument.to_string()));
+
+    let console = files.console();
+    let thread = Thread::new_user_thread(&file)?;
+    *thread.process().files().lock() = files;
+    *thread.process().environment().lock() = environment;
+    *thread.process().arguments().lock() = command_line;
+
+    // The new process becomes the foreground process of its console, until it exits (unless it runs in the background)
+    if let Some(console) = console {
+        terminal::attach(console, thread.process().id(), background);
+    }
+
+    return Ok(thread);
+}
+
+/// Parse the interpreter line of a script. Returns `None`, if `file` is not a script.
+/// Like on Linux, everything after the interpreter is passed as a single argument.
+pub fn interpreter(file: &[u8]) -> Result<Option<(String, Option<String>)>> {
+    let Some(line) = file.strip_prefix(b"#!") else {
+        return Ok(None);
+    };
+
+    let length = line.iter().position(|byte| *byte == b'\n').ok_or(Errno::ExecFormat)?;
+    if length > INTERPRETER_LINE_LENGTH {
+        return Err(Errno::ExecFormat);
+    }
+
+    let line = from_utf8(&line[..length]).map_err(|_| Errno::ExecFormat)?.trim();
+    let (interpreter, argument) = match line.split_once(|c: char| c.is_ascii_whitespace()) {
+        Some((interpreter, argument)) => (interpreter, Some(argument.trim().to_string())),
+        None => (line, None)
+    };
+
+    if !interpreter.starts_with('/') {
+        return Err(Errno::ExecFormat);
+    }
+
+    return Ok(Some((interpreter.to_string(), argument.filter(|argument| !argument.is_empty()))));
+}
+
+pub fn processes() -> Vec<Arc<Process>> {
+    return PROCESSES.read().clone();
+}
+
+pub fn find_process(id: usize) -> Option<Arc<Process>> {
+    return PROCESSES.read().iter()
+        .find(|process| process.id() == id)
+        .map(|process| Arc::clone(process));
+}
+
 pub fn current_process() -> Arc<Process> {
     if PROCESSES.read().len() > 1 {
         scheduler().current_thread().process()
@@ -38,20 +166,53 @@ pub fn current_process() -> Arc<Process> {
 pub struct Process {
     id: usize,
     address_space: Arc<AddressSpace>,
-    memory_areas: RwLock<Vec<VirtualMemoryArea>>
+    memory_areas: RwLock<Vec<VirtualMemoryArea>>,
+    signals: SignalState,
+    interval_timer: Mutex<Option<IntervalTimer>>,
+    symbols: RwLock<Vec<Symbol>>,
+    files: Mutex<FileDescriptorTable>,
+    environment: Mutex<Environment>,
+    /// Command line, starting with the path of the application.
+    arguments: Mutex<Vec<String>>,
+    /// Objects owning the memory of mappings (e.g. the mapped files, see `sys_map_file()`). They are kept until the process exits,
+    /// since the memory backing a mapping (e.g. the surface of a window) must not be freed while it is mapped.
+    mappings: Mutex<Vec<Arc<dyn Send + Sync>>>
+}
+
+/// Function symbol of a user application, used to resolve addresses (e.g. by the profiler).
+pub struct Symbol {
+    address: u64,
+    size: u64,
+    name: String
+}
+
+impl Symbol {
+    pub fn new(address: u64, size: u64, name: &str) -> Self {
+        Self { address, size, name: name.to_string() }
+    }
+}
+
+/// Timer, that raises `Signal::Alarm` for its process (see `setitimer()` and `alarm()`).
+struct IntervalTimer {
+    handle: TimerHandle,
+    expires_ms: Arc<AtomicUsize>,
+    interval_ms: usize
 }
 
 impl Drop for Process {
     fn drop(&mut self) {
-        for vma in self.memory_areas.read().iter() {
-            self.address_space.unmap(vma.range());
+        if let Some(timer) = self.interval_timer.lock().take() {
+            timer.handle.cancel();
         }
     }
 }
 
 impl Process {
     fn new() -> Self {
-        Self { id: next_process_id(), address_space: memory::r#virtual::create_address_space(), memory_areas: RwLock::new(Vec::new()) }
+        Self { id: next_process_id(), address_space: memory::r#virtual::create_address_space(), memory_areas: RwLock::new(Vec::new()),
+            signals: SignalState::new(), interval_timer: Mutex::new(None), symbols: RwLock::new(Vec::new()),
+            files: Mutex::new(FileDescriptorTable::new()), environment: Mutex::new(Environment::new()),
+            arguments: Mutex::new(Vec::new()), mappings: Mutex::new(Vec::new()) }
     }
 
     pub fn id(&self) -> usize {
@@ -70,6 +231,17 @@ impl Process {
         }
     }
 
+    /// Like `add_vma()`, but fails with `Errno::AlreadyExists`, if the area overlaps with an existing one (e.g. for areas requested by applications).
+    pub fn try_add_vma(&self, new_area: VirtualMemoryArea) -> Result<()> {
+        let mut areas = self.memory_areas.write();
+        if areas.iter().any(|area| area.overlaps_with(&new_area)) {
+            return Err(Errno::AlreadyExists);
+        }
+
+        areas.push(new_area);
+        return Ok(());
+    }
+
     pub fn find_vma(&self, typ: VmaType) -> Option<VirtualMemoryArea> {
         let areas = self.memory_areas.read();
         match areas.iter().find(|area| area.typ() == typ) {
@@ -78,7 +250,104 @@ impl Process {
         }
     }
 
+    /// The area of type `typ` with the highest address (e.g. the last code segment).
+    pub fn find_last_vma(&self, typ: VmaType) -> Option<VirtualMemoryArea> {
+        let areas = self.memory_areas.read();
+        return areas.iter().filter(|area| area.typ() == typ).max_by_key(|area| area.start()).copied();
+    }
+
+    /// Check, if the addresses from `start` up to (excluding) `end` lie inside a single memory area of the process.
+    /// Must be checked, before the kernel writes to an address given by user space (e.g. the user stack pointer).
+    pub fn contains_range(&self, start: u64, end: u64) -> bool {
+        let areas = self.memory_areas.read();
+        return start < end && areas.iter().any(|area| area.start().as_u64() <= start && end <= area.end().as_u64());
+    }
+
+    /// Keep `owner` alive, until the process exits (see `mappings`).
+    pub fn add_mapping(&self, owner: Arc<dyn Send + Sync>) {
+        self.mappings.lock().push(owner);
+    }
+
+    pub fn memory_areas(&self) -> Vec<VirtualMemoryArea> {
+        return self.memory_areas.read().clone();
+    }
+
+    pub fn set_symbols(&self, mut symbols: Vec<Symbol>) {
+        symbols.sort_by_key(|symbol| symbol.address);
+        *self.symbols.write() = symbols;
+    }
+
+    /// Resolve an address inside the application to the name of the function containing it and the offset into that function.
+    pub fn resolve_symbol(&self, address: u64) -> Option<(String, u64)> {
+        return self.symbols.read().iter()
+            .find(|symbol| address >= symbol.address && address < symbol.address + symbol.size.max(1))
+            .map(|symbol| (symbol.name.clone(), address - symbol.address));
+    }
+
+    pub fn files(&self) -> &Mutex<FileDescriptorTable> {
+        &self.files
+    }
+
+    pub fn environment(&self) -> &Mutex<Environment> {
+        &self.environment
+    }
+
+    pub fn arguments(&self) -> &Mutex<Vec<String>> {
+        &self.arguments
+    }
+
+    pub fn signals(&self) -> &SignalState {
+        &self.signals
+    }
+
+    pub fn raise(&self, signal: Signal) {
+        self.signals.raise(signal);
+    }
+
+    /// Arm the interval timer of this process, which raises `Signal::Alarm` after `initial_ms` milliseconds and then every `interval_ms` milliseconds
+    /// (if `interval_ms` is not 0). An `initial_ms` value of 0 disarms the timer.
+    /// Returns the remaining time and interval of the previous timer.
+    pub fn set_interval_timer(self: &Arc<Self>, initial_ms: usize, interval_ms: usize) -> (usize, usize) {
+        let mut interval_timer = self.interval_timer.lock();
+        let now = timer().read().systime_ms();
+
+        let old = match interval_timer.take() {
+            Some(timer) => {
+                timer.handle.cancel();
+                (timer.expires_ms.load(Relaxed).saturating_sub(now), timer.interval_ms)
+            }
+            None => (0, 0)
+        };
+
+        if initial_ms > 0 {
+            let process = Arc::downgrade(self);
+            let expires_ms = Arc::new(AtomicUsize::new(now + initial_ms));
+            let next_expiry = Arc::clone(&expires_ms);
+
+            let callback = Box::new(move || {
+                if let Some(process) = process.upgrade() {
+                    process.raise(Signal::Alarm);
+                    next_expiry.fetch_add(interval_ms, Relaxed);
+                }
+            });
+
+            let handle = if interval_ms > 0 {
+                timer::schedule_periodic_delayed(initial_ms, interval_ms, callback)
+            } else {
+                timer::schedule(initial_ms, callback)
+            };
+
+            interval_timer.replace(IntervalTimer { handle, expires_ms, interval_ms });
+        }
+
+        return old;
+    }
+
     pub fn exit(&self) {
+        if let Some(timer) = self.interval_timer.lock().take() {
+            timer.handle.cancel();
+        }
+
         PROCESSES.write().retain(|process| process.id != self.id);
     }
 }
\ No newline at end of file
@@ -1,21 +1,44 @@
+use crate::device::terminal;
 use crate::process::thread::Thread;
+use crate::process::wait_queue::Waiter;
 use alloc::collections::VecDeque;
 use alloc::format;
 use alloc::rc::Rc;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
-use core::ptr;
+use core::{mem, ptr};
 use core::sync::atomic::AtomicUsize;
 use core::sync::atomic::Ordering::Relaxed;
 use smallmap::Map;
-use spin::Mutex;
+use crate::sync::Mutex;
+use x86_64::instructions::interrupts;
 use crate::{apic, scheduler, timer, tss};
 
 static THREAD_ID_COUNTER: AtomicUsize = AtomicUsize::new(1);
 
+/// Id of the running thread (0 before the scheduler has been started).
+/// Can be read without locking the scheduler (e.g. for lock debugging).
+static CURRENT_THREAD_ID: AtomicUsize = AtomicUsize::new(0);
+static CURRENT_PROCESS_ID: AtomicUsize = AtomicUsize::new(0);
+
 pub fn next_thread_id() -> usize {
     THREAD_ID_COUNTER.fetch_add(1, Relaxed)
 }
 
+pub fn current_thread_id() -> usize {
+    return CURRENT_THREAD_ID.load(Relaxed);
+}
+
+/// Id of the process, the running thread belongs to (can be read without locking, e.g. by the profiler).
+pub fn current_process_id() -> usize {
+    return CURRENT_PROCESS_ID.load(Relaxed);
+}
+
+fn set_current_ids(thread: &Thread) {
+    CURRENT_THREAD_ID.store(thread.id(), Relaxed);
+    CURRENT_PROCESS_ID.store(thread.process().id(), Relaxed);
+}
+
 struct ReadyState {
     initialized: bool,
     current_thread: Option<Rc<Thread>>,
@@ -29,9 +52,16 @@ impl ReadyState {
 }
 
 pub struct Scheduler {
-    state: Mutex<ReadyState>,
-    sleep_list: Mutex<Vec<(Rc<Thread>, usize)>>,
-    join_map: Mutex<Map<usize, Vec<Rc<Thread>>>>
+    // The state lock is released by the next thread after switching (see 'unlock_scheduler()'),
+    // so it cannot be tracked by the lock debugging and always uses a plain spinlock.
+    state: spin::Mutex<ReadyState>,
+    /// Waiters with a timeout (including sleeping threads) and their wakeup time.
+    sleep_list: Mutex<Vec<(Arc<Waiter>, usize)>>,
+    /// Waiters woken up by interrupt handlers (see `wake_up_from_interrupt()`), only locked with interrupts disabled.
+    interrupt_wakeups: spin::Mutex<Vec<Arc<Waiter>>>,
+    join_map: Mutex<Map<usize, Vec<Rc<Thread>>>>,
+    /// The running thread is not switched by `switch_thread()`, while this is greater than 0 (see `disable_preemption()`).
+    preemption_disabled: AtomicUsize,
 }
 
 unsafe impl Send for Scheduler {}
@@ -45,13 +75,32 @@ pub unsafe extern "C" fn unlock_scheduler() {
 
 impl Scheduler {
     pub fn new() -> Self {
-        Self { state: Mutex::new(ReadyState::new()), sleep_list: Mutex::new(Vec::new()), join_map: Mutex::new(Map::new()) }
+        Self { state: spin::Mutex::new(ReadyState::new()), sleep_list: Mutex::new(Vec::new()), interrupt_wakeups: spin::Mutex::new(Vec::new()), join_map: Mutex::new(Map::new()), preemption_disabled: AtomicUsize::new(0) }
     }
 
     pub fn set_init(&self) {
         self.state.lock().initialized = true;
     }
 
+    /// Check, if the first thread has been started (threads cannot block before).
+    pub fn is_initialized(&self) -> bool {
+        return self.state.lock().initialized;
+    }
+
+    pub fn is_locked(&self) -> bool {
+        return self.state.is_locked();
+    }
+
+    /// Keep the running thread on the CPU (e.g. while it runs tasklets for all threads), until `enable_preemption()` is called.
+    /// Calls may be nested. The running thread must not block in the meantime.
+    pub fn disable_preemption(&self) {
+        self.preemption_disabled.fetch_add(1, Relaxed);
+    }
+
+    pub fn enable_preemption(&self) {
+        self.preemption_disabled.fetch_sub(1, Relaxed);
+    }
+
     pub fn current_thread(&self) -> Rc<Thread> {
         let state = self.state.lock();
         return Scheduler::current(&state);
@@ -60,11 +109,13 @@ impl Scheduler {
     pub fn start(&self) {
         let mut state = self.state.lock();
         state.current_thread = state.ready_queue.pop_back();
+        set_current_ids(&Scheduler::current(&state));
 
         unsafe { Thread::start_first(state.current_thread.as_ref().expect("Scheduler: Failed to dequeue first thread!").as_ref()); }
     }
 
     pub fn ready(&self, thread: Rc<Thread>) {
+        crate::trace_function!();
         let id = thread.id();
         let mut state = self.state.lock();
         let mut join_map = self.join_map.lock();
@@ -74,26 +125,90 @@ impl Scheduler {
     }
 
     pub fn sleep(&self, ms: usize) {
+        self.sleep_ns(ms * 1000000);
+    }
+
+    /// Block the current thread for `ns` nanoseconds.
+    /// If the thread needs to be woken up before the next regular tick, the APIC timer is programmed as one-shot timer.
+    pub fn sleep_ns(&self, ns: usize) {
+        self.block_on(&Waiter::new(), Some(ns));
+    }
+
+    /// Block the current thread, until `waiter` is woken up (see `Waiter::wake()`) or `timeout_ns` nanoseconds have passed.
+    /// Returns immediately, if the waiter has already been woken up.
+    pub fn block_on(&self, waiter: &Arc<Waiter>, timeout_ns: Option<usize>) {
         let mut state = self.state.lock();
-        let thread = Scheduler::current(&state);
-        let wakeup_time = timer().read().systime_ms() + ms;
 
         { // Execute in own block, so that the lock is released automatically (block() does not return)
-            let mut sleep_list = self.sleep_list.lock();
-            sleep_list.push((thread, wakeup_time));
+            let mut waiter_state = waiter.state.lock();
+            if waiter_state.woken {
+                return;
+            }
+
+            waiter_state.thread = Some(Scheduler::current(&state));
+        }
+
+        if let Some(ns) = timeout_ns {
+            let wakeup_time = timer().read().systime_ns() + ns;
+            self.sleep_list.lock().push((Arc::clone(waiter), wakeup_time));
+
+            let apic = apic();
+            let remaining_ns = apic.timer_period_ns().saturating_sub(apic.timer_elapsed_ns());
+            if ns < remaining_ns {
+                apic.timer_one_shot_ns(ns);
+            }
         }
 
         self.block(&mut state);
+
+        if timeout_ns.is_some() {
+            // Woken up before the timeout
+            self.sleep_list.lock().retain(|entry| !Arc::ptr_eq(&entry.0, waiter));
+        }
+    }
+
+    /// Move a thread, whose waiter has been woken up, back to the ready queue.
+    /// Must not be called from an interrupt handler, since the scheduler may be locked by the interrupted thread.
+    pub fn wake_up(&self, thread: Rc<Thread>) {
+        let mut state = self.state.lock();
+        state.ready_queue.push_front(thread);
+    }
+
+    /// Wake up `waiter` from an interrupt handler, which must not lock the scheduler (the interrupted thread may hold it).
+    /// The waiter is woken up with the next tick or as soon as the next thread is chosen, whichever happens first.
+    pub fn wake_up_from_interrupt(&self, waiter: Arc<Waiter>) {
+        interrupts::without_interrupts(|| self.interrupt_wakeups.lock().push(waiter));
+    }
+
+    /// Move all threads, whose sleep time is over, to the ready queue (called by the timer interrupt handler on every tick).
+    /// Returns true, if at least one thread has been woken up.
+    pub fn check_sleeping_threads(&self) -> bool {
+        if let Some(mut state) = self.state.try_lock() {
+            if !state.initialized {
+                return false;
+            }
+
+            if let Some(mut sleep_list) = self.sleep_list.try_lock() {
+                return self.check_sleep_list(&mut state, &mut sleep_list);
+            }
+        }
+
+        return false;
     }
 
     pub fn switch_thread(&self) {
+        crate::trace_function!();
+        if self.preemption_disabled.load(Relaxed) > 0 {
+            return;
+        }
+
         if let Some(mut state) = self.state.try_lock() {
             if !state.initialized {
                 return;
             }
 
             if let Some(mut sleep_list) = self.sleep_list.try_lock() {
-                Scheduler::check_sleep_list(&mut state, &mut sleep_list);
+                self.check_sleep_list(&mut state, &mut sleep_list);
             }
 
             let current = Scheduler::current(&state);
@@ -110,6 +225,7 @@ impl Scheduler {
             let current_ptr = ptr::from_ref(current.as_ref());
             let next_ptr = ptr::from_ref(next.as_ref());
 
+            set_current_ids(&next);
             state.current_thread = Some(next);
             state.ready_queue.push_front(current);
 
@@ -118,20 +234,37 @@ impl Scheduler {
         }
     }
 
+    /// Check, if the thread `thread_id` has been readied and has not exited yet.
+    pub fn is_running(&self, thread_id: usize) -> bool {
+        return self.join_map.lock().contains_key(&thread_id);
+    }
+
     pub fn join(&self, thread_id: usize) {
         let mut state = self.state.lock();
         let thread = Scheduler::current(&state);
 
         { // Execute in own block, so that the lock is released automatically (block() does not return)
             let mut join_map = self.join_map.lock();
-            let join_list = join_map.get_mut(&thread_id).expect(format!("Scheduler: Missing join_map entry for thread id {}!", thread_id).as_str());
-            join_list.push(thread);
+            match join_map.get_mut(&thread_id) {
+                Some(join_list) => join_list.push(thread),
+                None => return // The thread has already exited
+            }
         }
 
         self.block(&mut state);
     }
 
     pub fn exit(&self) {
+        let thread = self.current_thread();
+        if !thread.is_kernel_thread() {
+            // Closing files may wake up other threads (e.g. waiting for a file lock or reading from a pipe),
+            // which requires the scheduler to be unlocked
+            let files = thread.process().files().lock().close_all();
+            drop(files);
+            terminal::detach(thread.process().id());
+        }
+        drop(thread);
+
         let mut state = self.state.lock();
         let current = Scheduler::current(&state);
 
@@ -160,7 +293,7 @@ impl Scheduler {
         { // Execute in own block, so that the lock is released automatically (block() does not return)
             let mut sleep_list = self.sleep_list.lock();
             while next_thread.is_none() {
-                Scheduler::check_sleep_list(state, &mut sleep_list);
+                self.check_sleep_list(state, &mut sleep_list);
                 next_thread = state.ready_queue.pop_back();
             }
         }
@@ -176,6 +309,7 @@ impl Scheduler {
         let current_ptr = ptr::from_ref(current.as_ref());
         let next_ptr = ptr::from_ref(next.as_ref());
 
+        set_current_ids(&next);
         state.current_thread = Some(next);
         drop(current); // Decrease Rc manually, because Thread::switch does not return
 
@@ -186,18 +320,39 @@ impl Scheduler {
         return Rc::clone(state.current_thread.as_ref().expect("Scheduler: Trying to access current thread before initialization!"));
     }
 
-    fn check_sleep_list(state: &mut ReadyState, sleep_list: &mut Vec<(Rc<Thread>, usize)>) {
+    fn check_sleep_list(&self, state: &mut ReadyState, sleep_list: &mut Vec<(Arc<Waiter>, usize)>) -> bool {
+        let mut woken_up = false;
+
+        // Waiters woken up by interrupt handlers are handled like expired timeouts
+        let interrupt_wakeups = interrupts::without_interrupts(|| mem::take(&mut *self.interrupt_wakeups.lock()));
+        sleep_list.extend(interrupt_wakeups.into_iter().map(|waiter| (waiter, 0)));
+
         if let Some(timer) = timer().try_read() {
-            let time = timer.systime_ms();
+            let time = timer.systime_ns();
 
             sleep_list.retain(|entry| {
-                if time >= entry.1 {
-                    state.ready_queue.push_front(Rc::clone(&entry.0));
-                    return false;
+                if time < entry.1 {
+                    return true;
                 }
 
-                return true;
+                // The waiter may be locked by the interrupted thread, in which case it is checked again on the next tick
+                let mut waiter = match entry.0.state.try_lock() {
+                    Some(waiter) => waiter,
+                    None => return true
+                };
+
+                // Waiters, which have already been woken up by another event, are just removed
+                waiter.woken = true;
+                if let Some(thread) = waiter.thread.take() {
+                    // Threads, that have been sleeping, are scheduled next to achieve an accurate wakeup time
+                    state.ready_queue.push_back(thread);
+                    woken_up = true;
+                }
+
+                return false;
             });
         }
+
+        return woken_up;
     }
 }
@@ -0,0 +1,191 @@
+use core::ptr;
+use core::sync::atomic::{AtomicU64, AtomicUsize};
+use core::sync::atomic::Ordering::Relaxed;
+use crate::sync::Mutex;
+use syscall::signal::{Signal, SignalDisposition, MAX_SIGNALS};
+use log::info;
+use x86_64::instructions::interrupts;
+use x86_64::structures::idt::InterruptStackFrameValue;
+use x86_64::VirtAddr;
+use crate::process::core_dump;
+use crate::process::core_dump::Registers;
+use crate::process::process::{current_process, Process};
+use crate::{scheduler, tss_get_rsp0};
+
+/// Number of registers, which are saved on the user stack by the system call handler.
+const SAVED_REGISTERS: usize = 13;
+/// Index of the saved rcx register (containing the user rip) on the user stack.
+const SAVED_RIP_INDEX: usize = 11;
+/// Size of the signal number and the return address, which are pushed onto the user stack for the signal trampoline.
+const SIGNAL_FRAME_SIZE: u64 = 16;
+
+/// Read the registers, which have been saved on the user stack by the system call handler (for a core file).
+/// Only rax (holding the return value) and rbp (which is not touched by the kernel) are not saved.
+/// If `user_rsp` does not point into the memory of `process`, only the stack pointer is known.
+pub unsafe fn saved_registers(process: &Process, user_rsp: u64) -> Registers {
+    if !process.contains_range(user_rsp, user_rsp.wrapping_add((SAVED_REGISTERS * 8) as u64)) {
+        return Registers { rsp: user_rsp, ..Registers::default() };
+    }
+
+    let saved = user_rsp as *const u64;
+    let [r15, r14, r13, r12, r11, r10, r9, r8, rsi, rdi, rdx, rcx, rbx] = saved.cast::<[u64; SAVED_REGISTERS]>().read();
+    return Registers { r15, r14, r13, r12, r11, r10, r9, r8, rsi, rdi, rdx, rcx, rbx, rip: rcx, rflags: r11,
+        rsp: user_rsp + (SAVED_REGISTERS * 8) as u64, ..Registers::default() };
+}
+
+/// Signal state of a process.
+/// Signals are raised asynchronously (e.g. by a timer) and delivered, when a thread of the process returns from a system call
+/// or from an interrupt to user mode (see `deliver_on_interrupt_return()`).
+pub struct SignalState {
+    pending: AtomicU64,
+    dispositions: Mutex<[SignalDisposition; MAX_SIGNALS]>,
+    trampoline: AtomicUsize,
+}
+
+/// Action, the kernel needs to take for a pending signal.
+pub enum SignalAction {
+    None,
+    Terminate(Signal),
+    Handle(Signal),
+}
+
+impl SignalState {
+    pub fn new() -> Self {
+        Self { pending: AtomicU64::new(0), dispositions: Mutex::new([SignalDisposition::Default; MAX_SIGNALS]), trampoline: AtomicUsize::new(0) }
+    }
+
+    pub fn raise(&self, signal: Signal) {
+        self.pending.fetch_or(1 << signal as usize, Relaxed);
+    }
+
+    pub fn is_pending(&self, signal: Signal) -> bool {
+        return self.pending.load(Relaxed) & (1 << signal as usize) != 0;
+    }
+
+    /// Set the disposition for `signal`. Handled signals are delivered by calling `trampoline` in user space.
+    /// Returns false, if the disposition of the given signal cannot be changed.
+    pub fn set_disposition(&self, signal: Signal, disposition: SignalDisposition, trampoline: usize) -> bool {
+        if signal == Signal::Kill {
+            return false;
+        }
+
+        if disposition == SignalDisposition::Handle {
+            if trampoline == 0 {
+                return false;
+            }
+
+            self.trampoline.store(trampoline, Relaxed);
+        }
+
+        self.dispositions.lock()[signal as usize] = disposition;
+        return true;
+    }
+
+    /// Remove the lowest pending signal from the pending set and decide what to do with it.
+    pub fn next_action(&self) -> SignalAction {
+        loop {
+            let pending = self.pending.load(Relaxed);
+            if pending == 0 {
+                return SignalAction::None;
+            }
+
+            let number = pending.trailing_zeros() as usize;
+            self.pending.fetch_and(!(1 << number), Relaxed);
+
+            let signal = match Signal::try_from(number) {
+                Ok(signal) => signal,
+                Err(_) => continue,
+            };
+
+            match self.dispositions.lock()[number] {
+                SignalDisposition::Ignore => continue,
+                SignalDisposition::Handle => return SignalAction::Handle(signal),
+                SignalDisposition::Default => match signal {
+                    Signal::Child => continue,
+                    _ => return SignalAction::Terminate(signal),
+                }
+            }
+        }
+    }
+
+    /// Prepare the user stack, so that the system call handler returns into the signal trampoline,
+    /// which calls the user space signal handler and then returns to the original instruction pointer.
+    /// `user_rsp` points to the registers, saved by the system call handler. The new user stack pointer is returned.
+    /// The caller must check, that the frame lies inside the user stack (see `handler_frame_action()`).
+    pub unsafe fn setup_handler_frame(&self, user_rsp: u64, signal: Signal) -> u64 {
+        let old_frame = user_rsp as *mut u64;
+        let new_frame = old_frame.sub(2);
+        let rip = old_frame.add(SAVED_RIP_INDEX).read();
+
+        // Move saved registers down by two entries, to make room for the signal number and the return address
+        ptr::copy(old_frame, new_frame, SAVED_REGISTERS);
+        new_frame.add(SAVED_REGISTERS).write(signal as u64);
+        new_frame.add(SAVED_REGISTERS + 1).write(rip);
+        new_frame.add(SAVED_RIP_INDEX).write(self.trampoline.load(Relaxed) as u64);
+
+        return new_frame as u64;
+    }
+
+    /// Prepare the user stack, so that an interrupt returns into the signal trampoline instead of the interrupted code.
+    /// Only the signal number and the interrupted instruction pointer are pushed, since the interrupt handler restores all registers itself.
+    /// The caller must check, that the frame lies inside the user stack (see `deliver_on_interrupt_return()`).
+    pub unsafe fn setup_interrupt_frame(&self, frame: &mut InterruptStackFrameValue, signal: Signal) {
+        let user_stack = (frame.stack_pointer.as_u64() as *mut u64).sub(2);
+        user_stack.write(signal as u64);
+        user_stack.add(1).write(frame.instruction_pointer.as_u64());
+
+        frame.stack_pointer = VirtAddr::new(user_stack as u64);
+        frame.instruction_pointer = VirtAddr::new(self.trampoline.load(Relaxed) as u64);
+    }
+}
+
+/// Decide what to do with the next pending signal of `process`, when returning from a system call.
+/// Besides the signal frame, the registers saved by the system call handler at `user_rsp` are moved,
+/// so all of them must lie inside the process's memory. Otherwise, the process is terminated with `Signal::SegmentationFault`,
+/// since the stack pointer is controlled by user space and may point into kernel memory.
+pub fn handler_frame_action(process: &Process, user_rsp: u64) -> SignalAction {
+    let frame_start = user_rsp.wrapping_sub(SIGNAL_FRAME_SIZE);
+    let frame_end = user_rsp.wrapping_add((SAVED_REGISTERS * 8) as u64);
+
+    return match process.signals().next_action() {
+        SignalAction::Handle(_) if !process.contains_range(frame_start, frame_end) => SignalAction::Terminate(Signal::SegmentationFault),
+        action => action
+    };
+}
+
+/// Deliver a pending signal, when an interrupt returns to user mode, so that a process receives signals without issuing
+/// system calls (e.g. `Signal::Alarm` in a computing loop). Must only be called by interrupt handlers, which have interrupted user mode.
+/// The interrupt frame has been pushed by the CPU at the top of the kernel stack (`rsp0`). Interrupts do not push an error code,
+/// so it consists of exactly one `InterruptStackFrameValue`.
+pub fn deliver_on_interrupt_return() {
+    let process = current_process();
+    let frame = unsafe { (tss_get_rsp0() as *mut InterruptStackFrameValue).sub(1).as_mut().unwrap() };
+
+    // The user stack pointer may point anywhere (e.g. into kernel memory), so the signal frame is only written into the process's memory
+    let user_rsp = frame.stack_pointer.as_u64();
+    let action = match process.signals().next_action() {
+        SignalAction::Handle(_) if !process.contains_range(user_rsp.wrapping_sub(SIGNAL_FRAME_SIZE), user_rsp) => SignalAction::Terminate(Signal::SegmentationFault),
+        action => action
+    };
+
+    match action {
+        SignalAction::None => {}
+        SignalAction::Handle(signal) => unsafe { process.signals().setup_interrupt_frame(frame, signal) },
+        SignalAction::Terminate(signal) => {
+            info!("Process [{}] has been terminated by signal [{:?}]", process.id(), signal);
+
+            // We came from user mode, so no locks are held and interrupts can be enabled for writing the core file
+            interrupts::enable();
+            if core_dump::dumps_core(signal) {
+                let registers = Registers { rip: frame.instruction_pointer.as_u64(), cs: frame.code_segment, rflags: frame.cpu_flags,
+                    rsp: frame.stack_pointer.as_u64(), ss: frame.stack_segment, ..Registers::default() };
+                core_dump::dump(&process, signal, &registers, None);
+            }
+
+            drop(process); // Manually decrease reference count, because exit() will never return
+            scheduler().exit();
+
+            panic!("Terminated thread has been scheduled again!");
+        }
+    }
+}
@@ -5,22 +5,28 @@ use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::arch::asm;
 use core::{mem, ptr};
-use goblin::elf64;
-use goblin::elf::Elf;
-use spin::Mutex;
+use syscall::error::Errno;
+use crate::sync::Mutex;
 use x86_64::structures::gdt::SegmentSelector;
 use x86_64::PrivilegeLevel::Ring3;
 use x86_64::structures::paging::{Page, PageTableFlags};
 use x86_64::structures::paging::page::PageRange;
 use x86_64::VirtAddr;
 use crate::memory::{MemorySpace, PAGE_SIZE};
-use crate::{memory, scheduler, tss};
+use crate::{scheduler, tss};
 use crate::memory::alloc::StackAllocator;
 use crate::memory::r#virtual::{VirtualMemoryArea, VmaType};
+use crate::process::{elf, loader};
 use crate::process::process::{create_process, kernel_process, Process};
 
 pub const USER_STACK_ADDRESS: usize = 0x400000000000;
 const STACK_SIZE_PAGES: usize = 64;
+/// The user stack starts at a random page in this many pages (16 GiB) above `USER_STACK_ADDRESS`.
+const STACK_RANDOMIZATION_PAGES: u64 = 1 << 22;
+/// Kernel stacks have no guard page, so an overflow silently overwrites the memory below them.
+/// Their lowest words are filled with this pattern, which is checked on each context switch (see `Thread::check_kernel_stack()`).
+pub const STACK_CANARY: u64 = 0x57AC_C0DE_CA4A_27E5;
+pub const STACK_CANARY_WORDS: usize = 64;
 
 struct Stacks {
     kernel_stack: Vec<u64, StackAllocator>,
@@ -57,35 +63,25 @@ impl Thread {
         return Rc::new(thread);
     }
 
-    #[allow(dead_code)]
-    pub fn new_user_thread(elf_buffer: &[u8]) -> Rc<Thread> {
+    /// Load the executable in `elf_buffer` (and the shared libraries it needs) into a new process and return its main thread.
+    /// Fails with `Errno::ExecFormat`, if the executable is malformed, or with `Errno::NotFound`, if a library is missing.
+    pub fn new_user_thread(elf_buffer: &[u8]) -> Result<Rc<Thread>, Errno> {
+        let elf = elf::parse(elf_buffer)?;
         let process = create_process();
         let address_space = process.address_space();
-
-        let elf = Elf::parse(elf_buffer).expect("Failed to parse application!");
-        elf.program_headers.iter()
-            .filter(|header| header.p_type == elf64::program_header::PT_LOAD)
-            .for_each(|header| {
-                let page_count = if header.p_memsz as usize % PAGE_SIZE == 0 { header.p_memsz as usize / PAGE_SIZE } else { (header.p_memsz as usize / PAGE_SIZE) + 1 };
-                let frames = memory::physical::alloc(page_count);
-                let virt_start = Page::from_start_address(VirtAddr::new(header.p_vaddr)).expect("ELF: Program section not page aligned!");
-                let pages = PageRange { start: virt_start, end: virt_start + page_count as u64 };
-
-                unsafe {
-                    let code = elf_buffer.as_ptr().offset(header.p_offset as isize);
-                    let target = frames.start.start_address().as_u64() as *mut u8;
-                    target.copy_from(code, header.p_filesz as usize);
-                    target.offset(header.p_filesz as isize).write_bytes(0, (header.p_memsz - header.p_filesz) as usize);
-                }
-
-                process.address_space().map_physical(frames, pages, MemorySpace::User, PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE);
-                process.add_vma(VirtualMemoryArea::new(pages, VmaType::Code));
-            });
+        let entry = match loader::load(&process, elf, elf_buffer) {
+            Ok(entry) => entry,
+            Err(err) => {
+                process.exit();
+                return Err(err);
+            }
+        };
 
         let kernel_stack = Vec::<u64, StackAllocator>::with_capacity_in((STACK_SIZE_PAGES * PAGE_SIZE) / 8, StackAllocator::new());
-        let user_stack_start = Page::from_start_address(VirtAddr::new(USER_STACK_ADDRESS as u64)).unwrap();
+        let user_stack_address = USER_STACK_ADDRESS as u64 + elf::random_pages(STACK_RANDOMIZATION_PAGES);
+        let user_stack_start = Page::from_start_address(VirtAddr::new(user_stack_address)).unwrap();
         let user_stack_pages = PageRange { start: user_stack_start, end: user_stack_start + STACK_SIZE_PAGES as u64 };
-        let user_stack = unsafe { Vec::from_raw_parts_in(USER_STACK_ADDRESS as *mut u64, 0, (STACK_SIZE_PAGES * PAGE_SIZE) / 8, StackAllocator::new()) };
+        let user_stack = unsafe { Vec::from_raw_parts_in(user_stack_address as *mut u64, 0, (STACK_SIZE_PAGES * PAGE_SIZE) / 8, StackAllocator::new()) };
         address_space.map(user_stack_pages, MemorySpace::User, PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE);
         process.add_vma(VirtualMemoryArea::new(user_stack_pages, VmaType::Stack));
 
@@ -93,11 +89,11 @@ impl Thread {
             id: scheduler::next_thread_id(),
             stacks: Mutex::new(Stacks::new(kernel_stack, user_stack)),
             process,
-            entry: unsafe { Box::new(mem::transmute(elf.entry as *const ())) }
+            entry: unsafe { Box::new(mem::transmute(entry as *const ())) }
         };
 
         thread.prepare_kernel_stack();
-        return Rc::new(thread);
+        return Ok(Rc::new(thread));
     }
 
     pub fn kickoff_kernel_thread() {
@@ -130,6 +126,7 @@ impl Thread {
     pub unsafe fn switch(current_ptr: *const Thread, next_ptr: *const Thread) {
         let current = current_ptr.as_ref().unwrap();
         let next = next_ptr.as_ref().unwrap();
+        current.check_kernel_stack();
         let current_rsp0 = ptr::from_ref(&current.stacks.lock().old_rsp0) as *mut u64;
         let next_rsp0 = next.stacks.lock().old_rsp0.as_u64();
         let next_rsp0_end = next.kernel_stack_addr().as_u64();
@@ -165,6 +162,20 @@ impl Thread {
         return kernel_stack_addr + stacks.kernel_stack.capacity() * 8;
     }
 
+    /// Panic with a report about the overflow, if the canary at the bottom of the kernel stack has been overwritten.
+    pub fn check_kernel_stack(&self) {
+        let stacks = self.stacks.lock();
+        let canary = &stacks.kernel_stack[..STACK_CANARY_WORDS];
+        let overwritten = overwritten_canary_bytes(canary);
+        if overwritten == 0 {
+            return;
+        }
+
+        let depth = if overwritten == STACK_CANARY_WORDS * 8 { "at least" } else { "about" };
+        panic!("Kernel stack overflow in thread [{}] of process [{}]! The stack has overflowed by {} [{}] bytes (Canary at [0x{:016x}])",
+               self.id, self.process.id(), depth, overwritten, canary.as_ptr() as u64);
+    }
+
     fn prepare_kernel_stack(&self) {
         let mut stacks = self.stacks.lock();
         let stack_addr = stacks.kernel_stack.as_ptr() as u64;
@@ -174,6 +185,8 @@ impl Thread {
             stacks.kernel_stack.push(0);
         }
 
+        stacks.kernel_stack[..STACK_CANARY_WORDS].fill(STACK_CANARY);
+
         stacks.kernel_stack[capacity - 1] = 0x00DEAD00u64; // Dummy return address
         stacks.kernel_stack[capacity - 2] = Thread::kickoff_kernel_thread as u64; // Address of 'kickoff_kernel_thread()';
         stacks.kernel_stack[capacity - 3] = 0x202; // rflags (Interrupts enabled)
@@ -229,6 +242,15 @@ impl Thread {
     }
 }
 
+/// Number of bytes at the top of `canary` (the lowest words of a kernel stack), which do not contain `STACK_CANARY` anymore.
+/// The stack grows downwards, so this is approximately the depth of an overflow (it may be deeper, if the whole canary has been overwritten).
+pub fn overwritten_canary_bytes(canary: &[u64]) -> usize {
+    return match canary.iter().position(|word| *word != STACK_CANARY) {
+        Some(lowest) => (canary.len() - lowest) * 8,
+        None => 0
+    };
+}
+
 #[naked]
 unsafe extern "C" fn thread_kernel_start(old_rsp0: u64) {
     asm!(
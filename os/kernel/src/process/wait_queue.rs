@@ -0,0 +1,145 @@
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use alloc::sync::Arc;
+use crate::process::thread::Thread;
+use x86_64::instructions::interrupts;
+use crate::{scheduler, timer};
+use crate::sync::Mutex;
+
+/// Threads waiting for a condition (e.g. data becoming available), which is signaled by another thread.
+pub struct WaitQueue {
+    waiters: Mutex<VecDeque<Arc<Waiter>>>,
+}
+
+/// A thread blocked by `Scheduler::block_on()`. The same waiter may be registered in multiple wait queues
+/// (e.g. by `poll()`), but it is only woken up once, by whichever event happens first.
+pub struct Waiter {
+    // Also accessed by the timer interrupt (see `Scheduler::check_sleep_list()`), so a plain spinlock is used
+    pub(super) state: spin::Mutex<WaiterState>,
+}
+
+pub(super) struct WaiterState {
+    pub(super) woken: bool,
+    /// Set by the scheduler, once the thread is actually blocked.
+    pub(super) thread: Option<Rc<Thread>>,
+}
+
+// Threads are only accessed while being moved to and from the scheduler
+unsafe impl Send for WaitQueue {}
+unsafe impl Sync for WaitQueue {}
+unsafe impl Send for Waiter {}
+unsafe impl Sync for Waiter {}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        return Self { waiters: Mutex::new(VecDeque::new()) };
+    }
+
+    /// Block the current thread, until it is woken up by `notify_one()` or `notify_all()`.
+    /// `guard` should protect the condition, the thread is waiting for. It is released after the thread has been enqueued,
+    /// so a notification sent after checking the condition is never lost. The condition must still be checked again after waking up.
+    pub fn wait<G>(&self, guard: G) {
+        let waiter = Waiter::new();
+        self.register(&waiter);
+        drop(guard);
+
+        scheduler().block_on(&waiter, None);
+    }
+
+    /// Block the current thread, until `condition` is true or `timeout_ms` milliseconds have passed (returns false on timeout).
+    /// The condition is checked after each notification, which may also be sent by an interrupt handler (see `notify_all_from_interrupt()`).
+    /// Before the scheduler has been started (e.g. while mounting the root filesystem), the condition is polled instead.
+    pub fn wait_until(&self, condition: impl Fn() -> bool, timeout_ms: Option<usize>) -> bool {
+        let deadline = timeout_ms.map(|timeout| timer().read().systime_ms() + timeout);
+        loop {
+            let waiter = scheduler().is_initialized().then(|| {
+                let waiter = Waiter::new();
+                interrupts::without_interrupts(|| self.register(&waiter));
+                waiter
+            });
+
+            if condition() {
+                return true;
+            }
+
+            let remaining_ms = match deadline {
+                Some(deadline) => match deadline.saturating_sub(timer().read().systime_ms()) {
+                    0 => return false,
+                    remaining => Some(remaining)
+                },
+                None => None
+            };
+
+            match waiter {
+                Some(waiter) => scheduler().block_on(&waiter, remaining_ms.map(|ms| ms * 1000000)),
+                None => core::hint::spin_loop()
+            }
+        }
+    }
+
+    /// Add `waiter` to the queue without blocking, so that the current thread can wait for multiple queues at once.
+    /// Waiters, which have already been woken up in the meantime, are removed from the queue.
+    pub fn register(&self, waiter: &Arc<Waiter>) {
+        let mut waiters = self.waiters.lock();
+        waiters.retain(|waiter| !waiter.is_woken());
+        waiters.push_back(Arc::clone(waiter));
+    }
+
+    pub fn notify_one(&self) {
+        loop {
+            let waiter = self.waiters.lock().pop_front();
+            match waiter {
+                Some(waiter) => if waiter.wake() {
+                    return;
+                },
+                None => return
+            }
+        }
+    }
+
+    pub fn notify_all(&self) {
+        let waiters = core::mem::take(&mut *self.waiters.lock());
+        for waiter in waiters {
+            waiter.wake();
+        }
+    }
+
+    /// Like `notify_all()`, but may be called from an interrupt handler, if the queue is only locked with interrupts disabled otherwise.
+    /// The threads are woken up by the scheduler shortly after (see `Scheduler::wake_up_from_interrupt()`).
+    pub fn notify_all_from_interrupt(&self) {
+        let waiters = core::mem::take(&mut *self.waiters.lock());
+        for waiter in waiters {
+            scheduler().wake_up_from_interrupt(waiter);
+        }
+    }
+}
+
+impl Waiter {
+    pub fn new() -> Arc<Self> {
+        return Arc::new(Self { state: spin::Mutex::new(WaiterState { woken: false, thread: None }) });
+    }
+
+    pub fn is_woken(&self) -> bool {
+        return self.state.lock().woken;
+    }
+
+    /// Wake up the waiting thread (or prevent it from blocking, if it is not blocked yet).
+    /// Returns false, if the waiter has already been woken up before. Must not be called from an interrupt handler.
+    pub fn wake(&self) -> bool {
+        let thread = {
+            let mut state = self.state.lock();
+            if state.woken {
+                return false;
+            }
+
+            state.woken = true;
+            state.thread.take()
+        };
+
+        if let Some(thread) = thread {
+            scheduler().wake_up(thread);
+        }
+
+        return true;
+    }
+}
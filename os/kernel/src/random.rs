@@ -0,0 +1,217 @@
+use core::arch::asm;
+use core::arch::x86_64::_rdtsc;
+use core::sync::atomic::{AtomicU32, AtomicUsize};
+use core::sync::atomic::Ordering::Relaxed;
+use log::{info, warn};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::random::RdRand;
+use crate::cpu::features;
+use crate::cpu::features::Feature;
+use crate::sync::Mutex;
+
+/// Entropy subsystem: A ChaCha20 based CSPRNG, which is seeded at boot from RDSEED/RDRAND and time stamp counter jitter
+/// and continuously reseeded with interrupt timings (see `add_interrupt_timing()`).
+/// The generator never blocks, since it has been seeded with enough entropy by `init()`.
+struct Csprng {
+    key: [u32; 8],
+    counter: u64,
+}
+
+/// ChaCha20 produces 64 bytes per block.
+const BLOCK_SIZE: usize = 64;
+/// Number of bytes generated, before interrupts are enabled again for a short moment (see `fill()`).
+const FILL_CHUNK_SIZE: usize = 512;
+/// Number of interrupts, after which the collected timings are mixed into the key.
+const RESEED_INTERRUPTS: usize = 64;
+/// Number of time stamp counter samples taken at boot.
+const JITTER_SAMPLES: usize = 256;
+const RDSEED_RETRIES: usize = 32;
+
+/// "expand 32-byte k"
+const CHACHA_CONSTANTS: [u32; 4] = [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574];
+
+/// The generator is also used in interrupt context, so it is only locked with interrupts disabled.
+static CSPRNG: Mutex<Csprng> = Mutex::new(Csprng { key: [0; 8], counter: 0 });
+/// Interrupt timings are collected without locking and mixed into the key on the next request.
+static INTERRUPT_POOL: [AtomicU32; 8] = [const { AtomicU32::new(0) }; 8];
+static INTERRUPT_SAMPLES: AtomicUsize = AtomicUsize::new(0);
+
+impl Csprng {
+    /// Mix `input` into the key by combining it with the current key and replacing the key with the next block.
+    fn mix(&mut self, input: &[u32]) {
+        for (index, word) in input.iter().enumerate() {
+            self.key[index % self.key.len()] ^= word;
+            if index % self.key.len() == self.key.len() - 1 {
+                self.rekey();
+            }
+        }
+
+        self.rekey();
+    }
+
+    /// Replace the key with the first half of the next block, so that previous output can not be reconstructed from the state.
+    fn rekey(&mut self) {
+        let mut block = [0; 16];
+        chacha20_block(&self.key, self.counter, 0, &mut block);
+        self.counter = self.counter.wrapping_add(1);
+        self.key.copy_from_slice(&block[..8]);
+    }
+
+    fn fill(&mut self, buffer: &mut [u8]) {
+        let mut block = [0; 16];
+        for chunk in buffer.chunks_mut(BLOCK_SIZE) {
+            chacha20_block(&self.key, self.counter, 0, &mut block);
+            self.counter = self.counter.wrapping_add(1);
+
+            for (bytes, word) in chunk.chunks_mut(4).zip(block.iter()) {
+                bytes.copy_from_slice(&word.to_le_bytes()[..bytes.len()]);
+            }
+        }
+
+        self.rekey();
+    }
+}
+
+/// Seed the generator with the hardware random number generators (if available) and time stamp counter jitter.
+pub fn init() {
+    // 8 words from RDSEED, 8 words from RDRAND and 2 bits of jitter per sample
+    let mut seed = [0u32; 16 + JITTER_SAMPLES / 16];
+    let mut sources = 0;
+
+    if features::has(Feature::RdSeed) {
+        for word in seed[..8].iter_mut() {
+            *word = rdseed().unwrap_or(0);
+        }
+        sources += 1;
+    }
+
+    if let Some(rdrand) = RdRand::new() {
+        for word in seed[8..16].iter_mut() {
+            *word = rdrand.get_u32().unwrap_or(0);
+        }
+        sources += 1;
+    }
+
+    // Jitter is collected from the lowest bits of the time needed for a small computation, which varies due to caches and interrupts
+    let jitter = &mut seed[16..];
+    let mut value = 0u64;
+    for sample in 0..JITTER_SAMPLES {
+        let start = unsafe { _rdtsc() };
+        for _ in 0..(start & 0xf) {
+            value = value.rotate_left(7) ^ unsafe { _rdtsc() };
+        }
+
+        let delta = unsafe { _rdtsc() }.wrapping_sub(start) ^ value;
+        jitter[sample / 16] ^= ((delta & 0x3) as u32) << ((sample % 16) * 2);
+    }
+
+    interrupts::without_interrupts(|| CSPRNG.lock().mix(&seed));
+
+    if sources == 0 {
+        warn!("No hardware random number generator available (Random numbers are only seeded with timing jitter)");
+    }
+    info!("Random number generator seeded (RDSEED: [{}], RDRAND: [{}])", features::has(Feature::RdSeed), features::has(Feature::RdRand));
+}
+
+/// Fill `buffer` with cryptographically secure random bytes.
+pub fn fill(buffer: &mut [u8]) {
+    for chunk in buffer.chunks_mut(FILL_CHUNK_SIZE) {
+        interrupts::without_interrupts(|| {
+            let mut csprng = CSPRNG.lock();
+            if INTERRUPT_SAMPLES.load(Relaxed) >= RESEED_INTERRUPTS {
+                INTERRUPT_SAMPLES.store(0, Relaxed);
+                let timings = INTERRUPT_POOL.each_ref().map(|word| word.swap(0, Relaxed));
+                csprng.mix(&timings);
+            }
+
+            csprng.fill(chunk);
+        });
+    }
+}
+
+pub fn next_u64() -> u64 {
+    let mut bytes = [0; 8];
+    fill(&mut bytes);
+
+    return u64::from_ne_bytes(bytes);
+}
+
+/// Mix data of unknown quality (e.g. written to '/dev/random') into the generator state.
+/// Mixing can never reduce the entropy of the state, so it does not matter, who provides the data.
+pub fn add_entropy(data: &[u8]) {
+    for chunk in data.chunks(32) {
+        let mut words = [0u32; 8];
+        for (word, bytes) in words.iter_mut().zip(chunk.chunks(4)) {
+            let mut padded = [0; 4];
+            padded[..bytes.len()].copy_from_slice(bytes);
+            *word = u32::from_le_bytes(padded);
+        }
+
+        interrupts::without_interrupts(|| CSPRNG.lock().mix(&words));
+    }
+}
+
+/// Called by the interrupt dispatcher for every interrupt. Only the arrival time is recorded, which is cheap and does not lock.
+pub fn add_interrupt_timing(vector: u8) {
+    let time = unsafe { _rdtsc() };
+    let index = INTERRUPT_SAMPLES.fetch_add(1, Relaxed) % INTERRUPT_POOL.len();
+    INTERRUPT_POOL[index].fetch_xor((time as u32).rotate_left(vector as u32 % 32) ^ (time >> 32) as u32, Relaxed);
+}
+
+/// Compute a ChaCha20 block with a 64-bit block counter and a 64-bit nonce (original variant by D. J. Bernstein).
+/// The counter occupies the state words 12 and 13, the nonce the words 14 and 15.
+pub fn chacha20_block(key: &[u32; 8], counter: u64, nonce: u64, output: &mut [u32; 16]) {
+    let mut state = [0u32; 16];
+    state[..4].copy_from_slice(&CHACHA_CONSTANTS);
+    state[4..12].copy_from_slice(key);
+    state[12] = counter as u32;
+    state[13] = (counter >> 32) as u32;
+    state[14] = nonce as u32;
+    state[15] = (nonce >> 32) as u32;
+
+    *output = state;
+    for _ in 0..10 {
+        // Column rounds
+        quarter_round(output, 0, 4, 8, 12);
+        quarter_round(output, 1, 5, 9, 13);
+        quarter_round(output, 2, 6, 10, 14);
+        quarter_round(output, 3, 7, 11, 15);
+        // Diagonal rounds
+        quarter_round(output, 0, 5, 10, 15);
+        quarter_round(output, 1, 6, 11, 12);
+        quarter_round(output, 2, 7, 8, 13);
+        quarter_round(output, 3, 4, 9, 14);
+    }
+
+    for (word, initial) in output.iter_mut().zip(state.iter()) {
+        *word = word.wrapping_add(*initial);
+    }
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// RDSEED may fail temporarily, if the entropy source of the CPU is exhausted.
+fn rdseed() -> Option<u32> {
+    for _ in 0..RDSEED_RETRIES {
+        let value: u32;
+        let success: u8;
+        unsafe { asm!("rdseed {0:e}", "setc {1}", out(reg) value, out(reg_byte) success, options(nomem, nostack)); }
+
+        if success != 0 {
+            return Some(value);
+        }
+
+        core::hint::spin_loop();
+    }
+
+    return None;
+}
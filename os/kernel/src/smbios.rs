@@ -0,0 +1,295 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::slice;
+use log::{info, warn};
+use spin::Once;
+use uefi::table::cfg::{SMBIOS3_GUID, SMBIOS_GUID};
+use crate::efi_system_table;
+
+/// Legacy BIOS systems place the entry point in this region (16 byte aligned).
+const LEGACY_SEARCH_START: usize = 0xf0000;
+const LEGACY_SEARCH_END: usize = 0x100000;
+
+// Structure types
+const TYPE_BIOS: u8 = 0;
+const TYPE_SYSTEM: u8 = 1;
+const TYPE_BASEBOARD: u8 = 2;
+const TYPE_PROCESSOR: u8 = 4;
+const TYPE_MEMORY_DEVICE: u8 = 17;
+const TYPE_END: u8 = 127;
+
+const PROCESSOR_SOCKET_POPULATED: u8 = 1 << 6;
+const MEMORY_SIZE_UNKNOWN: u16 = 0xffff;
+/// The size is given in the extended size field instead (for devices with 32 GiB or more).
+const MEMORY_SIZE_EXTENDED: u16 = 0x7fff;
+/// Set, if the size is given in KiB instead of MiB.
+const MEMORY_SIZE_KIB: u16 = 1 << 15;
+
+static HARDWARE_INFO: Once<HardwareInfo> = Once::new();
+
+/// Hardware inventory, as reported by the firmware via SMBIOS (shown in '/proc/hwinfo').
+/// Strings are empty, if the firmware does not provide them.
+pub struct HardwareInfo {
+    version: (u8, u8),
+    bios: Option<Bios>,
+    system: Option<Product>,
+    board: Option<Product>,
+    processors: Vec<Processor>,
+    memory_devices: Vec<MemoryDevice>,
+}
+
+struct Bios {
+    vendor: String,
+    version: String,
+    date: String,
+}
+
+/// Manufacturer and model of the system or mainboard.
+struct Product {
+    manufacturer: String,
+    name: String,
+    version: String,
+}
+
+struct Processor {
+    socket: String,
+    manufacturer: String,
+    version: String,
+    populated: bool,
+    max_speed_mhz: u16,
+    cores: u8,
+    threads: u8,
+}
+
+struct MemoryDevice {
+    locator: String,
+    /// Empty slots have a size of 0, while `None` means, that the size is unknown.
+    size_mib: Option<u32>,
+    speed_mts: u16,
+    memory_type: u8,
+    manufacturer: String,
+    part_number: String,
+}
+
+/// A structure of the SMBIOS table, consisting of the formatted area and the strings following it.
+struct Structure<'a> {
+    data: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl HardwareInfo {
+    /// Content of '/proc/hwinfo'.
+    pub fn describe(&self) -> String {
+        let mut description = String::new();
+        writeln!(description, "SMBIOS version: {}.{}", self.version.0, self.version.1).unwrap();
+
+        if let Some(bios) = &self.bios {
+            writeln!(description, "BIOS:           {} {} ({})", bios.vendor, bios.version, bios.date).unwrap();
+        }
+        if let Some(system) = &self.system {
+            writeln!(description, "System:         {} {} {}", system.manufacturer, system.name, system.version).unwrap();
+        }
+        if let Some(board) = &self.board {
+            writeln!(description, "Board:          {} {} {}", board.manufacturer, board.name, board.version).unwrap();
+        }
+
+        for processor in &self.processors {
+            if processor.populated {
+                writeln!(description, "CPU socket:     {}: {} {} ({} cores, {} threads, max. {} MHz)",
+                    processor.socket, processor.manufacturer, processor.version, processor.cores, processor.threads, processor.max_speed_mhz).unwrap();
+            } else {
+                writeln!(description, "CPU socket:     {}: Empty", processor.socket).unwrap();
+            }
+        }
+
+        for device in &self.memory_devices {
+            match device.size_mib {
+                Some(0) => writeln!(description, "Memory slot:    {}: Empty", device.locator).unwrap(),
+                size => writeln!(description, "Memory slot:    {}: {} {} ({} MT/s, {} {})", device.locator,
+                    size.map_or(String::from("Unknown size"), |size| format!("{} MiB", size)),
+                    memory_type_name(device.memory_type), device.speed_mts, device.manufacturer, device.part_number).unwrap()
+            }
+        }
+
+        return description;
+    }
+
+    /// Parse the structure table up to the end structure (or up to the first truncated structure).
+    pub fn parse(version: (u8, u8), table: &[u8]) -> Self {
+        let mut info = Self { version, bios: None, system: None, board: None, processors: Vec::new(), memory_devices: Vec::new() };
+
+        let mut offset = 0;
+        while let Some((structure, next)) = Structure::parse(table, offset) {
+            offset = next;
+            // Fields are only read, if the structure is long enough (older versions define fewer fields)
+            match structure.typ() {
+                TYPE_BIOS => info.bios = Some(Bios {
+                    vendor: structure.string(0x04),
+                    version: structure.string(0x05),
+                    date: structure.string(0x08),
+                }),
+                TYPE_SYSTEM => info.system = Some(structure.product()),
+                TYPE_BASEBOARD => info.board = Some(structure.product()),
+                TYPE_PROCESSOR => info.processors.push(Processor {
+                    socket: structure.string(0x04),
+                    manufacturer: structure.string(0x07),
+                    version: structure.string(0x10),
+                    populated: structure.byte(0x18) & PROCESSOR_SOCKET_POPULATED != 0,
+                    max_speed_mhz: structure.word(0x14),
+                    cores: structure.byte(0x23),
+                    threads: structure.byte(0x25),
+                }),
+                TYPE_MEMORY_DEVICE => info.memory_devices.push(MemoryDevice {
+                    locator: structure.string(0x10),
+                    size_mib: match structure.word(0x0c) {
+                        MEMORY_SIZE_UNKNOWN => None,
+                        MEMORY_SIZE_EXTENDED => Some(structure.dword(0x1c) & 0x7fffffff),
+                        size if size & MEMORY_SIZE_KIB != 0 => Some((size & !MEMORY_SIZE_KIB) as u32 / 1024),
+                        size => Some(size as u32),
+                    },
+                    speed_mts: structure.word(0x15),
+                    memory_type: structure.byte(0x12),
+                    manufacturer: structure.string(0x17),
+                    part_number: structure.string(0x1a),
+                }),
+                TYPE_END => break,
+                _ => {}
+            }
+        }
+
+        return info;
+    }
+}
+
+impl<'a> Structure<'a> {
+    /// Parse the structure at `offset` and return it together with the offset of the next one.
+    fn parse(table: &'a [u8], offset: usize) -> Option<(Self, usize)> {
+        let length = *table.get(offset + 1)? as usize;
+        if length < 4 || offset + length > table.len() {
+            return None;
+        }
+
+        // The string set is terminated by two zero bytes (also if it is empty)
+        let strings_start = offset + length;
+        let strings_length = table[strings_start..].windows(2).position(|bytes| bytes == [0, 0])?;
+        let structure = Self { data: &table[offset..strings_start], strings: &table[strings_start..strings_start + strings_length] };
+
+        return Some((structure, strings_start + strings_length + 2));
+    }
+
+    fn typ(&self) -> u8 {
+        return self.data[0];
+    }
+
+    fn byte(&self, offset: usize) -> u8 {
+        return self.data.get(offset).copied().unwrap_or(0);
+    }
+
+    fn word(&self, offset: usize) -> u16 {
+        return u16::from_le_bytes([self.byte(offset), self.byte(offset + 1)]);
+    }
+
+    fn dword(&self, offset: usize) -> u32 {
+        return u32::from_le_bytes([self.byte(offset), self.byte(offset + 1), self.byte(offset + 2), self.byte(offset + 3)]);
+    }
+
+    /// Look up the string, whose number (starting at 1) is stored at `offset`.
+    fn string(&self, offset: usize) -> String {
+        let number = self.byte(offset) as usize;
+        if number == 0 {
+            return String::new();
+        }
+
+        return self.strings.split(|&byte| byte == 0).nth(number - 1)
+            .map_or(String::new(), |string| String::from_utf8_lossy(string).trim().into());
+    }
+
+    fn product(&self) -> Product {
+        return Product { manufacturer: self.string(0x04), name: self.string(0x05), version: self.string(0x06) };
+    }
+}
+
+/// Locate the SMBIOS entry point (via the EFI configuration table or by searching the legacy BIOS area) and parse the structure table.
+/// Needs the kernel heap and the EFI system table (if booted via EFI).
+pub fn init() {
+    let entry_point = efi_entry_point().or_else(legacy_entry_point);
+    let Some(entry_point) = entry_point else {
+        warn!("SMBIOS entry point not found");
+        return;
+    };
+
+    let Some((version, table)) = (unsafe { parse_entry_point(entry_point) }) else {
+        warn!("Invalid SMBIOS entry point at [0x{:x}]", entry_point);
+        return;
+    };
+
+    let info = HARDWARE_INFO.call_once(|| HardwareInfo::parse(version, table));
+    info!("SMBIOS version: [{}.{}], Processor sockets: [{}], Memory devices: [{}]", version.0, version.1, info.processors.len(), info.memory_devices.len());
+    if let Some(board) = &info.board {
+        info!("Mainboard: [{} {}]", board.manufacturer, board.name);
+    }
+}
+
+pub fn hardware_info() -> Option<&'static HardwareInfo> {
+    return HARDWARE_INFO.get();
+}
+
+/// Prefer the 64-bit entry point (SMBIOS 3), which is also used, if both are present.
+fn efi_entry_point() -> Option<usize> {
+    let config_table = efi_system_table()?.config_table();
+    return config_table.iter().find(|entry| entry.guid == SMBIOS3_GUID)
+        .or_else(|| config_table.iter().find(|entry| entry.guid == SMBIOS_GUID))
+        .map(|entry| entry.address as usize);
+}
+
+fn legacy_entry_point() -> Option<usize> {
+    let region = unsafe { slice::from_raw_parts(LEGACY_SEARCH_START as *const u8, LEGACY_SEARCH_END - LEGACY_SEARCH_START) };
+    let find = |anchor: &[u8]| (0..region.len()).step_by(16).find(|&offset| region[offset..].starts_with(anchor));
+
+    return find(b"_SM3_").or_else(|| find(b"_SM_")).map(|offset| LEGACY_SEARCH_START + offset);
+}
+
+/// Validate the entry point at `address` and return the SMBIOS version and the structure table.
+/// Physical memory is identity mapped, so the entry point and the table can be accessed directly.
+unsafe fn parse_entry_point(address: usize) -> Option<((u8, u8), &'static [u8])> {
+    let header = unsafe { slice::from_raw_parts(address as *const u8, 0x20) };
+
+    let (length, version, table_address, table_length) = if header.starts_with(b"_SM3_") {
+        let table_address = u64::from_le_bytes(header[0x10..0x18].try_into().unwrap()) as usize;
+        let max_length = u32::from_le_bytes(header[0x0c..0x10].try_into().unwrap()) as usize;
+        (header[0x06] as usize, (header[0x07], header[0x08]), table_address, max_length)
+    } else if header.starts_with(b"_SM_") {
+        let table_address = u32::from_le_bytes(header[0x18..0x1c].try_into().unwrap()) as usize;
+        let table_length = u16::from_le_bytes([header[0x16], header[0x17]]) as usize;
+        (header[0x05] as usize, (header[0x06], header[0x07]), table_address, table_length)
+    } else {
+        return None;
+    };
+
+    // All bytes of the entry point add up to zero
+    let entry_point = unsafe { slice::from_raw_parts(address as *const u8, length) };
+    if length < 0x18 || entry_point.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0 {
+        return None;
+    }
+
+    return Some((version, unsafe { slice::from_raw_parts(table_address as *const u8, table_length) }));
+}
+
+fn memory_type_name(memory_type: u8) -> &'static str {
+    return match memory_type {
+        0x07 => "RAM",
+        0x12 => "DDR",
+        0x13 => "DDR2",
+        0x18 => "DDR3",
+        0x1a => "DDR4",
+        0x1b => "LPDDR",
+        0x1c => "LPDDR2",
+        0x1d => "LPDDR3",
+        0x1e => "LPDDR4",
+        0x22 => "DDR5",
+        0x23 => "LPDDR5",
+        _ => "Unknown",
+    };
+}
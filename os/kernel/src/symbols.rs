@@ -0,0 +1,58 @@
+use core::ptr;
+use core::slice;
+use core::str;
+
+/// Space for the symbol map, which is embedded into the kernel by 'symbols.sh' after linking.
+const SYMBOL_MAP_SIZE: usize = 0x100000;
+
+#[used]
+#[link_section = ".symbols"]
+static SYMBOL_MAP: [u8; SYMBOL_MAP_SIZE] = [0; SYMBOL_MAP_SIZE];
+
+extern "C" {
+    static ___SYMBOLS_START__: u8;
+    static ___SYMBOLS_END__: u8;
+}
+
+/// Find the function containing `address` and return its name together with the offset of `address` inside it.
+/// Returns None, if the address lies before the first symbol or no symbol map has been embedded.
+/// This function does not allocate memory, so that it can be used in exception and NMI handlers.
+pub fn resolve(address: u64) -> Option<(&'static str, u64)> {
+    let mut best: Option<(&'static str, u64)> = None;
+
+    // Lines are sorted by address, so we can stop at the first symbol behind the address
+    for line in symbol_map().split(|byte| *byte == b'\n') {
+        if line.len() < 18 {
+            break; // Reached end of symbol map (or map is empty)
+        }
+
+        let symbol_address = match str::from_utf8(&line[0..16]).ok().and_then(|hex| u64::from_str_radix(hex, 16).ok()) {
+            Some(symbol_address) => symbol_address,
+            None => break
+        };
+
+        if symbol_address > address {
+            break;
+        }
+
+        if let Ok(name) = str::from_utf8(&line[17..]) {
+            best = Some((name, address - symbol_address));
+        }
+    }
+
+    return best;
+}
+
+fn symbol_map() -> &'static [u8] {
+    // The map is accessed via the linker symbols, because the compiler would assume that 'SYMBOL_MAP' only contains zeros
+    unsafe {
+        let start = ptr::from_ref(&___SYMBOLS_START__);
+        let end = ptr::from_ref(&___SYMBOLS_END__);
+        let map = slice::from_raw_parts(start, end as usize - start as usize);
+
+        return match map.iter().position(|byte| *byte == 0) {
+            Some(len) => &map[..len],
+            None => map
+        };
+    }
+}
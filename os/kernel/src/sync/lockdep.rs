@@ -0,0 +1,552 @@
+use core::arch::x86_64::_rdtsc;
+use core::fmt::Write;
+use core::ops::{Deref, DerefMut};
+use core::panic::Location;
+use core::ptr;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering::Relaxed;
+use x86_64::instructions::interrupts;
+use crate::debug::{backtrace, PanicWriter};
+use crate::process::scheduler::current_thread_id;
+
+const MAX_CLASSES: usize = 128;
+const MAX_HELD_LOCKS: usize = 64;
+const MAX_DEPENDENCIES: usize = 512;
+const BACKTRACE_DEPTH: usize = 8;
+
+/// Instrumented replacement for `spin::Mutex`.
+/// All mutexes created at the same source location belong to the same lock class (e.g. the locks of all threads).
+pub struct Mutex<T> {
+    inner: spin::Mutex<T>,
+    class: &'static Location<'static>,
+}
+
+pub struct MutexGuard<'a, T> {
+    guard: spin::MutexGuard<'a, T>,
+    lock: usize,
+}
+
+/// Instrumented replacement for `spin::RwLock`.
+pub struct RwLock<T> {
+    inner: spin::RwLock<T>,
+    class: &'static Location<'static>,
+}
+
+pub struct RwLockReadGuard<'a, T> {
+    guard: spin::RwLockReadGuard<'a, T>,
+    lock: usize,
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    guard: spin::RwLockWriteGuard<'a, T>,
+    lock: usize,
+}
+
+#[derive(Copy, Clone)]
+struct Backtrace {
+    addresses: [u64; BACKTRACE_DEPTH],
+}
+
+#[derive(Copy, Clone)]
+struct LockClass {
+    location: &'static Location<'static>,
+    acquisitions: usize,
+    /// Acquisitions, that had to wait, because the lock was held by someone else
+    contentions: usize,
+    max_hold_cycles: u64,
+    total_hold_cycles: u64,
+}
+
+#[derive(Copy, Clone)]
+struct HeldLock {
+    lock: usize,
+    class: usize,
+    thread: usize,
+    shared: bool,
+    site: &'static Location<'static>,
+    since: u64,
+    backtrace: Backtrace,
+}
+
+/// Records, that a lock of class `to` has been acquired while holding a lock of class `from`.
+#[derive(Copy, Clone)]
+struct Dependency {
+    from: usize,
+    to: usize,
+    site: &'static Location<'static>,
+    backtrace: Backtrace,
+}
+
+struct LockdepState {
+    classes: [Option<LockClass>; MAX_CLASSES],
+    held: [Option<HeldLock>; MAX_HELD_LOCKS],
+    /// Bit `to` in `edges[from]` is set, if there is a dependency `from` -> `to`.
+    edges: [u128; MAX_CLASSES],
+    dependencies: [Option<Dependency>; MAX_DEPENDENCIES],
+}
+
+/// Detection is turned off, once a problem has been reported (or the tables are full),
+/// so that the panic handler can still use locks.
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Internal state is protected by a plain spinlock, which is always taken with interrupts disabled.
+static STATE: spin::Mutex<LockdepState> = spin::Mutex::new(LockdepState::new());
+
+impl<T> Mutex<T> {
+    #[track_caller]
+    pub const fn new(value: T) -> Self {
+        Self { inner: spin::Mutex::new(value), class: Location::caller() }
+    }
+
+    #[track_caller]
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        let lock = self.address();
+        let site = Location::caller();
+
+        check(lock, self.class, site, false);
+        let (guard, contended) = match self.inner.try_lock() {
+            Some(guard) => (guard, false),
+            None => (self.inner.lock(), true)
+        };
+        acquired(lock, self.class, site, false, contended);
+
+        return MutexGuard { guard, lock };
+    }
+
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let lock = self.address();
+        let guard = self.inner.try_lock()?;
+        acquired(lock, self.class, Location::caller(), false, false);
+
+        return Some(MutexGuard { guard, lock });
+    }
+
+    pub fn is_locked(&self) -> bool {
+        return self.inner.is_locked();
+    }
+
+    pub unsafe fn force_unlock(&self) {
+        forget(self.address());
+        self.inner.force_unlock();
+    }
+
+    fn address(&self) -> usize {
+        return ptr::from_ref(self) as usize;
+    }
+}
+
+impl<T> RwLock<T> {
+    #[track_caller]
+    pub const fn new(value: T) -> Self {
+        Self { inner: spin::RwLock::new(value), class: Location::caller() }
+    }
+
+    #[track_caller]
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        let lock = self.address();
+        let site = Location::caller();
+
+        check(lock, self.class, site, true);
+        let (guard, contended) = match self.inner.try_read() {
+            Some(guard) => (guard, false),
+            None => (self.inner.read(), true)
+        };
+        acquired(lock, self.class, site, true, contended);
+
+        return RwLockReadGuard { guard, lock };
+    }
+
+    #[track_caller]
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        let lock = self.address();
+        let site = Location::caller();
+
+        check(lock, self.class, site, false);
+        let (guard, contended) = match self.inner.try_write() {
+            Some(guard) => (guard, false),
+            None => (self.inner.write(), true)
+        };
+        acquired(lock, self.class, site, false, contended);
+
+        return RwLockWriteGuard { guard, lock };
+    }
+
+    #[track_caller]
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let lock = self.address();
+        let guard = self.inner.try_read()?;
+        acquired(lock, self.class, Location::caller(), true, false);
+
+        return Some(RwLockReadGuard { guard, lock });
+    }
+
+    #[track_caller]
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let lock = self.address();
+        let guard = self.inner.try_write()?;
+        acquired(lock, self.class, Location::caller(), false, false);
+
+        return Some(RwLockWriteGuard { guard, lock });
+    }
+
+    pub fn writer_count(&self) -> usize {
+        return self.inner.writer_count();
+    }
+
+    pub fn as_mut_ptr(&self) -> *mut T {
+        return self.inner.as_mut_ptr();
+    }
+
+    fn address(&self) -> usize {
+        return ptr::from_ref(self) as usize;
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        return self.guard.deref();
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        return self.guard.deref_mut();
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        released(self.lock);
+    }
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        return self.guard.deref();
+    }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        released(self.lock);
+    }
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        return self.guard.deref();
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        return self.guard.deref_mut();
+    }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        released(self.lock);
+    }
+}
+
+/// Print all currently held locks (e.g. when a lockup has been detected).
+pub fn print_held_locks(writer: &mut impl Write) {
+    // Do not wait for the state lock, since this might be called from an NMI
+    let state = match STATE.try_lock() {
+        Some(state) => state,
+        None => return
+    };
+
+    let now = unsafe { _rdtsc() };
+    for held in state.held.iter().flatten() {
+        let _ = writeln!(writer, "  Lock [0x{:x}] (Class: [{}]) held by thread [{}] since [{}] cycles, acquired at [{}]",
+                         held.lock, state.class(held.class).location, held.thread, now - held.since, held.site);
+    }
+}
+
+/// Print usage statistics for all known lock classes, sorted by total hold time.
+pub fn print_statistics(writer: &mut impl Write) {
+    // Copy the classes, so that the state is not locked while writing (the writer might use locks itself)
+    let mut classes = interrupts::without_interrupts(|| STATE.lock().classes);
+    classes.sort_unstable_by_key(|class| class.map_or(0, |class| u64::MAX - class.total_hold_cycles));
+
+    let _ = writeln!(writer, "{:>10} {:>10} {:>14} {:>14} {:>14}  Lock class", "Acquired", "Contended", "Total cycles", "Avg cycles", "Max cycles");
+    for class in classes.iter().flatten() {
+        let average = class.total_hold_cycles / class.acquisitions.max(1) as u64;
+        let _ = writeln!(writer, "{:>10} {:>10} {:>14} {:>14} {:>14}  {}", class.acquisitions, class.contentions,
+                         class.total_hold_cycles, average, class.max_hold_cycles, class.location);
+    }
+}
+
+/// Called before waiting for a lock. Detects self-deadlocks and circular waits between lock classes.
+fn check(lock: usize, class: &'static Location<'static>, site: &'static Location<'static>, shared: bool) {
+    if !ENABLED.load(Relaxed) {
+        return;
+    }
+
+    let thread = current_thread_id();
+
+    interrupts::without_interrupts(|| {
+        let mut state = STATE.lock();
+        let class = match state.class_index(class) {
+            Some(class) => class,
+            None => return disable()
+        };
+
+        // Self-deadlock: The current thread (or an interrupt handler running on top of it) already holds the lock.
+        // Recursive read locks are fine, as long as no writer is involved.
+        let held_by_thread = state.held.iter().flatten().find(|held| held.lock == lock && held.thread == thread && !(held.shared && shared)).copied();
+        if let Some(held) = held_by_thread {
+            ENABLED.store(false, Relaxed);
+
+            let mut writer = PanicWriter;
+            let _ = writeln!(writer, "\nLockdep: Thread [{}] tries to acquire lock [0x{:x}] at [{}], which it already holds!", thread, lock, site);
+            let _ = writeln!(writer, "Lock has been acquired at [{}]:", held.site);
+            held.backtrace.print(&mut writer);
+            let _ = writeln!(writer, "Current backtrace:");
+            backtrace::print(&mut writer, backtrace::frame_pointer());
+
+            drop(state);
+            panic!("Lockdep: Self-deadlock detected!");
+        }
+
+        // Record dependencies from all classes held by the current thread to the new class.
+        // Before adding a dependency `held` -> `class`, we check if the reverse direction is already known, which would allow a circular wait.
+        let held_classes = state.held.iter().flatten()
+            .filter(|held| held.thread == thread && held.class != class) // Nesting locks of the same class is not tracked
+            .fold(0u128, |classes, held| classes | (1u128 << held.class));
+
+        for from in 0..MAX_CLASSES {
+            if held_classes & (1u128 << from) == 0 || state.edges[from] & (1u128 << class) != 0 {
+                continue; // Class is not held or dependency is already known
+            }
+
+            if let Some(path) = state.find_path(class, from) {
+                ENABLED.store(false, Relaxed);
+                state.report_circular_wait(thread, from, class, site, &path);
+
+                drop(state);
+                panic!("Lockdep: Possible circular wait detected!");
+            }
+
+            state.add_dependency(from, class, site);
+        }
+    });
+}
+
+/// Called after a lock has been acquired.
+fn acquired(lock: usize, class: &'static Location<'static>, site: &'static Location<'static>, shared: bool, contended: bool) {
+    if !ENABLED.load(Relaxed) {
+        return;
+    }
+
+    let thread = current_thread_id();
+    let backtrace = Backtrace::capture();
+
+    interrupts::without_interrupts(|| {
+        let mut state = STATE.lock();
+        let class = match state.class_index(class) {
+            Some(class) => class,
+            None => return disable()
+        };
+
+        match state.held.iter_mut().find(|held| held.is_none()) {
+            Some(slot) => *slot = Some(HeldLock { lock, class, thread, shared, site, since: unsafe { _rdtsc() }, backtrace }),
+            None => return disable()
+        }
+
+        if let Some(class) = state.classes[class].as_mut() {
+            class.acquisitions += 1;
+            if contended {
+                class.contentions += 1;
+            }
+        }
+    });
+}
+
+/// Called when a guard is dropped. Only removes the entry of the current thread,
+/// since a lock might have been force unlocked and acquired by another thread in the meantime.
+fn released(lock: usize) {
+    if !ENABLED.load(Relaxed) {
+        return;
+    }
+
+    let thread = current_thread_id();
+
+    interrupts::without_interrupts(|| {
+        let mut state = STATE.lock();
+        let index = state.held.iter().position(|held| held.is_some_and(|held| held.lock == lock && held.thread == thread));
+
+        if let Some(index) = index {
+            let held = state.held[index].take().unwrap();
+            let hold_cycles = unsafe { _rdtsc() } - held.since;
+
+            if let Some(class) = state.classes[held.class].as_mut() {
+                class.max_hold_cycles = class.max_hold_cycles.max(hold_cycles);
+                class.total_hold_cycles += hold_cycles;
+            }
+        }
+    });
+}
+
+/// Called by `force_unlock()`, which may be executed by another thread than the holder.
+fn forget(lock: usize) {
+    if !ENABLED.load(Relaxed) {
+        return;
+    }
+
+    interrupts::without_interrupts(|| {
+        let mut state = STATE.lock();
+        for slot in state.held.iter_mut() {
+            if slot.is_some_and(|held| held.lock == lock) {
+                *slot = None;
+            }
+        }
+    });
+}
+
+/// Tables are full -> Further results would be unreliable.
+fn disable() {
+    ENABLED.store(false, Relaxed);
+}
+
+impl LockdepState {
+    const fn new() -> Self {
+        Self { classes: [None; MAX_CLASSES], held: [None; MAX_HELD_LOCKS], edges: [0; MAX_CLASSES], dependencies: [None; MAX_DEPENDENCIES] }
+    }
+
+    fn class(&self, index: usize) -> &LockClass {
+        return self.classes[index].as_ref().unwrap();
+    }
+
+    /// Get the index of the class for locks created at `location`, registering it if necessary.
+    fn class_index(&mut self, location: &'static Location<'static>) -> Option<usize> {
+        for (index, slot) in self.classes.iter_mut().enumerate() {
+            match slot {
+                Some(class) if ptr::eq(class.location, location) => return Some(index),
+                Some(_) => continue,
+                None => {
+                    *slot = Some(LockClass { location, acquisitions: 0, contentions: 0, max_hold_cycles: 0, total_hold_cycles: 0 });
+                    return Some(index);
+                }
+            }
+        }
+
+        return None;
+    }
+
+    fn add_dependency(&mut self, from: usize, to: usize, site: &'static Location<'static>) {
+        self.edges[from] |= 1u128 << to;
+
+        // Only the first occurrence is stored for reports
+        if let Some(slot) = self.dependencies.iter_mut().find(|dependency| dependency.is_none()) {
+            *slot = Some(Dependency { from, to, site, backtrace: Backtrace::capture() });
+        }
+    }
+
+    fn dependency(&self, from: usize, to: usize) -> Option<&Dependency> {
+        return self.dependencies.iter().flatten().find(|dependency| dependency.from == from && dependency.to == to);
+    }
+
+    /// Search for a chain of dependencies `from` -> ... -> `to` (breadth first).
+    /// Returns the classes on the path in reverse order (starting with `to`).
+    fn find_path(&self, from: usize, to: usize) -> Option<([usize; MAX_CLASSES], usize)> {
+        let mut parent = [usize::MAX; MAX_CLASSES];
+        let mut visited: u128 = 1u128 << from;
+        let mut frontier: u128 = 1u128 << from;
+
+        while frontier != 0 && visited & (1u128 << to) == 0 {
+            let mut next: u128 = 0;
+            for class in 0..MAX_CLASSES {
+                if frontier & (1u128 << class) == 0 {
+                    continue;
+                }
+
+                let new = self.edges[class] & !visited & !next;
+                for target in 0..MAX_CLASSES {
+                    if new & (1u128 << target) != 0 {
+                        parent[target] = class;
+                    }
+                }
+
+                next |= new;
+            }
+
+            visited |= next;
+            frontier = next;
+        }
+
+        if visited & (1u128 << to) == 0 {
+            return None;
+        }
+
+        let mut path = [0; MAX_CLASSES];
+        let mut len = 0;
+        let mut current = to;
+        while current != from {
+            path[len] = current;
+            len += 1;
+            current = parent[current];
+        }
+
+        path[len] = from;
+        return Some((path, len + 1));
+    }
+
+    fn report_circular_wait(&self, thread: usize, held: usize, acquiring: usize, site: &'static Location<'static>, path: &([usize; MAX_CLASSES], usize)) {
+        let mut writer = PanicWriter;
+        let (path, len) = path;
+
+        let _ = writeln!(writer, "\nLockdep: Thread [{}] acquires lock of class [{}] at [{}], while holding lock of class [{}]!",
+                         thread, self.class(acquiring).location, site, self.class(held).location);
+        if let Some(held_lock) = self.held.iter().flatten().find(|lock| lock.thread == thread && lock.class == held) {
+            let _ = writeln!(writer, "Held lock has been acquired at [{}]:", held_lock.site);
+            held_lock.backtrace.print(&mut writer);
+        }
+
+        let _ = writeln!(writer, "Current backtrace:");
+        backtrace::print(&mut writer, backtrace::frame_pointer());
+
+        // Path is stored in reverse order (from `held` back to `acquiring`)
+        let _ = writeln!(writer, "But the reverse order has already been observed:");
+        for i in (1..*len).rev() {
+            let (from, to) = (path[i], path[i - 1]);
+            let _ = writeln!(writer, "  [{}] -> [{}]", self.class(from).location, self.class(to).location);
+
+            if let Some(dependency) = self.dependency(from, to) {
+                let _ = writeln!(writer, "  Acquired at [{}]:", dependency.site);
+                dependency.backtrace.print(&mut writer);
+            }
+        }
+    }
+}
+
+impl Backtrace {
+    fn capture() -> Self {
+        let mut addresses = [0; BACKTRACE_DEPTH];
+        let mut count = 0;
+
+        backtrace::walk(backtrace::frame_pointer(), |address| {
+            if count < BACKTRACE_DEPTH {
+                addresses[count] = address;
+                count += 1;
+            }
+        });
+
+        return Self { addresses };
+    }
+
+    fn print(&self, writer: &mut impl Write) {
+        for address in self.addresses.iter().take_while(|address| **address != 0) {
+            backtrace::print_address(writer, *address);
+        }
+    }
+}
@@ -0,0 +1,11 @@
+// All kernel code should use the lock types from this module instead of using 'spin' directly.
+// With the 'lockdep' feature enabled, they are replaced by instrumented variants, which detect deadlocks.
+
+#[cfg(not(feature = "lockdep"))]
+pub use spin::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+#[cfg(feature = "lockdep")]
+pub mod lockdep;
+
+#[cfg(feature = "lockdep")]
+pub use lockdep::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
@@ -1,36 +1,77 @@
+use alloc::format;
 use alloc::rc::Rc;
-use core::ptr::slice_from_raw_parts;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::ptr::{slice_from_raw_parts, slice_from_raw_parts_mut};
 use core::str::from_utf8;
+use syscall::error::{to_syscall_result, Errno};
+use syscall::file::{DescriptorFlags, DirectoryEntry, EventCounterFlags, FileStatus, LockOperation, OpenFlags, PollDescriptor, SeekWhence, TimerFlags, WatchEvents, POLL_INFINITE};
+use syscall::mqueue::QueueAttributes;
+use syscall::net::{Protocol, SocketType};
+use syscall::process::{Argument, StartFlags};
+use syscall::signal::{Signal, SignalDisposition};
 use x86_64::structures::paging::PageTableFlags;
-use crate::{initrd, scheduler, terminal};
+use x86_64::VirtAddr;
+use crate::{scheduler, timer};
+use crate::device::power;
+use crate::random;
+use crate::fs;
+use crate::fs::{eventfd, mqueue, pipe, poll, vfs, watch, File, SeekFrom};
+use crate::fs::timerfd::TimerFile;
+use crate::fs::mqueue::MessageQueueFile;
+use crate::fs::watch::Watcher;
 use crate::memory::{MemorySpace, PAGE_SIZE};
+use crate::net::socket;
+use crate::net::socket::{Socket, SocketFile};
+use crate::net::unix::UnixSocket;
 use crate::memory::r#virtual::{VirtualMemoryArea, VmaType};
-use crate::process::process::current_process;
-use crate::process::thread::Thread;
+use crate::process::fd_table::MAX_OPEN_FILES;
+use crate::process::elf;
+use crate::process::process::{current_process, load_application, load_background_application};
 
 pub mod syscall_dispatcher;
 
+/// Start of the area, where files are mapped into user space by `sys_map_file()` (between the heap and the stack).
+const USER_MAPPING_ADDRESS: usize = 0x200000000000;
+/// The heap starts at a random page in this many pages (4 GiB) after the code.
+const HEAP_RANDOMIZATION_PAGES: u64 = 1 << 20;
+
 #[no_mangle]
-pub extern "C" fn sys_read() -> u64 {
-    let terminal = terminal();
-    match terminal.read_byte() {
-        -1 => panic!("Input stream closed!"),
-        c => c as u64
-    }
+pub extern "C" fn sys_read(fd: usize, buffer: *mut u8, length: usize) -> usize {
+    let buffer = match user_slice_mut(buffer, length) {
+        Ok(buffer) => buffer,
+        Err(err) => return to_syscall_result(Err(err))
+    };
+    let file = current_process().files().lock().get(fd);
+
+    to_syscall_result(file.and_then(|file| file.read(buffer)))
 }
 
 #[no_mangle]
-pub extern "C" fn sys_write(buffer: *const u8, length: usize) {
-    let string = from_utf8(unsafe { slice_from_raw_parts(buffer, length).as_ref().unwrap() }).unwrap();
-    let terminal = terminal();
-    terminal.write_str(string);
+pub extern "C" fn sys_write(fd: usize, buffer: *const u8, length: usize) -> usize {
+    let buffer = match user_slice(buffer, length) {
+        Ok(buffer) => buffer,
+        Err(err) => return to_syscall_result(Err(err))
+    };
+    let process = current_process();
+    let file = process.files().lock().get(fd);
+
+    let result = file.and_then(|file| file.write(buffer));
+    if result == Err(Errno::BrokenPipe) {
+        process.raise(Signal::BrokenPipe);
+    }
+
+    to_syscall_result(result)
 }
 
 #[no_mangle]
 pub extern "C" fn sys_map_user_heap(size: usize) -> usize {
     let process = current_process();
-    let code_area = process.find_vma(VmaType::Code).expect("Process does not have code area!");
-    let heap_start = code_area.end().align_up(PAGE_SIZE as u64);
+    let code_area = process.find_last_vma(VmaType::Code).expect("Process does not have code area!");
+    // The heap starts at a random distance from the code, so that its address cannot be guessed
+    let heap_start = code_area.end().align_up(PAGE_SIZE as u64) + elf::random_pages(HEAP_RANDOMIZATION_PAGES);
     let heap_area = VirtualMemoryArea::from_address(heap_start, size, VmaType::Heap);
 
     process.address_space().map(heap_area.range(), MemorySpace::User, PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE);
@@ -59,25 +100,751 @@ pub extern "C" fn sys_thread_sleep(ms: usize) {
     scheduler().sleep(ms);
 }
 
+#[no_mangle]
+pub extern "C" fn sys_thread_nanosleep(ns: usize) {
+    scheduler().sleep_ns(ns);
+}
+
 #[no_mangle]
 pub extern "C" fn sys_thread_join(id: usize) {
     scheduler().join(id);
 }
 
+/// Returns 1, if the thread `id` has been started and has not exited yet (so that it can be waited for without blocking).
+#[no_mangle]
+pub extern "C" fn sys_thread_running(id: usize) -> usize {
+    scheduler().is_running(id) as usize
+}
+
 #[no_mangle]
 pub extern "C" fn sys_thread_exit() {
     scheduler().exit();
 }
 
+/// Write the environment of the current process into `buffer` as a sequence of 'NAME=VALUE' entries, each terminated by a null byte.
+/// Returns the size of the whole environment, which may be larger than `buffer` (only the part fitting into it is written).
+#[no_mangle]
+pub extern "C" fn sys_get_environment(buffer: *mut u8, length: usize) -> usize {
+    let environment = current_process().environment().lock().iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<String>>();
+
+    to_syscall_result(write_strings(&environment, buffer, length))
+}
+
+/// Write the command line of the current process into `buffer` like `sys_get_environment()` (each argument terminated by a null byte).
 #[no_mangle]
-pub extern "C" fn sys_application_start(name_buffer: *const u8, name_length: usize) -> usize {
-    let app_name = from_utf8(unsafe { slice_from_raw_parts(name_buffer, name_length).as_ref().unwrap() }).unwrap();
-    match initrd().entries().find(|entry| entry.filename().as_str() == app_name) {
-        Some(app) => {
-            let thread = Thread::new_user_thread(app.data());
-            scheduler().ready(Rc::clone(&thread));
-            thread.id()
+pub extern "C" fn sys_get_arguments(buffer: *mut u8, length: usize) -> usize {
+    let arguments = current_process().arguments().lock().clone();
+    to_syscall_result(write_strings(&arguments, buffer, length))
+}
+
+/// Copy `strings`, each terminated by a null byte, into a user buffer (as far as they fit) and return their total size.
+/// The buffer may be null, if `length` is 0 (to query the required size).
+fn write_strings(strings: &[String], buffer: *mut u8, length: usize) -> Result<usize, Errno> {
+    let buffer = user_slice_mut(buffer, length)?;
+    let mut data = Vec::new();
+    for string in strings {
+        data.extend_from_slice(string.as_bytes());
+        data.push(0);
+    }
+
+    let count = usize::min(length, data.len());
+    buffer[..count].copy_from_slice(&data[..count]);
+
+    return Ok(data.len());
+}
+
+/// Set the environment variable `name` of the current process to `value` (or remove it, if `value_buffer` is null).
+/// Names must not be empty or contain '=' and neither names nor values may contain null bytes.
+#[no_mangle]
+pub extern "C" fn sys_set_environment(name_buffer: *const u8, name_length: usize, value_buffer: *const u8, value_length: usize) -> usize {
+    let Ok(name) = user_str(name_buffer, name_length) else {
+        return to_syscall_result(Err(Errno::InvalidArgument));
+    };
+    if name.is_empty() || name.contains(['=', '\0']) {
+        return to_syscall_result(Err(Errno::InvalidArgument));
+    }
+
+    let mut environment = current_process().environment().lock();
+    if value_buffer.is_null() {
+        environment.remove(name);
+        return to_syscall_result(Ok(0));
+    }
+
+    match user_str(value_buffer, value_length) {
+        Ok(value) if !value.contains('\0') => {
+            environment.insert(String::from(name), String::from(value));
+            to_syscall_result(Ok(0))
         }
-        None => 0
+        _ => to_syscall_result(Err(Errno::InvalidArgument))
     }
-}
\ No newline at end of file
+}
+
+/// Load the application `name` (see `load_application()`) into a new process with the command line arguments
+/// given as an array of `Argument`, start it and return the id of its main thread.
+#[no_mangle]
+pub extern "C" fn sys_application_start(name_buffer: *const u8, name_length: usize, arguments: *const Argument, argument_count: usize, flags: usize) -> usize {
+    let (Ok(app_name), Some(flags)) = (user_str(name_buffer, name_length), StartFlags::from_bits(flags)) else {
+        return to_syscall_result(Err(Errno::InvalidArgument));
+    };
+
+    let arguments = user_slice(arguments, argument_count)
+        .and_then(|arguments| arguments.iter().map(|argument| user_str(argument.address, argument.length)).collect::<Result<Vec<&str>, Errno>>());
+    let Ok(arguments) = arguments else {
+        return to_syscall_result(Err(Errno::InvalidArgument));
+    };
+
+    let thread = if flags.contains(StartFlags::BACKGROUND) { load_background_application(app_name, &arguments) } else { load_application(app_name, &arguments) };
+    to_syscall_result(thread.map(|thread| {
+        scheduler().ready(Rc::clone(&thread));
+        thread.id()
+    }))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_interval_timer(initial_ms: usize, interval_ms: usize) -> usize {
+    let (remaining_ms, _) = current_process().set_interval_timer(initial_ms, interval_ms);
+    remaining_ms
+}
+
+#[no_mangle]
+pub extern "C" fn sys_alarm(seconds: usize) -> usize {
+    let (remaining_ms, _) = current_process().set_interval_timer(seconds * 1000, 0);
+    (remaining_ms + 999) / 1000
+}
+
+#[no_mangle]
+pub extern "C" fn sys_signal_action(signal: usize, disposition: usize, trampoline: usize) -> usize {
+    let signal = match Signal::try_from(signal) {
+        Ok(signal) => signal,
+        Err(_) => return 0
+    };
+
+    let disposition = match SignalDisposition::try_from(disposition) {
+        Ok(disposition) => disposition,
+        Err(_) => return 0
+    };
+
+    current_process().signals().set_disposition(signal, disposition, trampoline) as usize
+}
+
+#[no_mangle]
+pub extern "C" fn sys_open(path_buffer: *const u8, path_length: usize, flags: usize) -> usize {
+    let path = match user_str(path_buffer, path_length) {
+        Ok(path) => path,
+        Err(err) => return to_syscall_result(Err(err))
+    };
+
+    let flags = match OpenFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => return to_syscall_result(Err(Errno::InvalidArgument))
+    };
+
+    let descriptor_flags = if flags.contains(OpenFlags::CLOSE_ON_EXEC) { DescriptorFlags::CLOSE_ON_EXEC } else { DescriptorFlags::empty() };
+    to_syscall_result(vfs::open(path, flags).and_then(|file| current_process().files().lock().insert(file, descriptor_flags)))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_close(fd: usize) -> usize {
+    let file = current_process().files().lock().remove(fd);
+    to_syscall_result(file.map(|file| {
+        vfs::close(file);
+        0
+    }))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_seek(fd: usize, offset: isize, whence: usize) -> usize {
+    let position = match SeekWhence::try_from(whence) {
+        Ok(SeekWhence::Start) if offset >= 0 => SeekFrom::Start(offset as usize),
+        Ok(SeekWhence::Current) => SeekFrom::Current(offset),
+        Ok(SeekWhence::End) => SeekFrom::End(offset),
+        _ => return to_syscall_result(Err(Errno::InvalidArgument))
+    };
+
+    let file = current_process().files().lock().get(fd);
+    to_syscall_result(file.and_then(|file| file.seek(position)))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_stat(path_buffer: *const u8, path_length: usize, status: *mut FileStatus) -> usize {
+    let metadata = user_str(path_buffer, path_length).and_then(vfs::stat);
+    to_syscall_result(metadata.map(|metadata| {
+        unsafe { status.write(FileStatus::from(metadata)); }
+        0
+    }))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_file_stat(fd: usize, status: *mut FileStatus) -> usize {
+    let file = current_process().files().lock().get(fd);
+    to_syscall_result(file.and_then(|file| file.stat()).map(|metadata| {
+        unsafe { status.write(FileStatus::from(metadata)); }
+        0
+    }))
+}
+
+/// Write up to `count` entries of the directory `fd` into `entries`, starting with the entry at `index`,
+/// and return the number of entries written (0, once all entries have been read).
+#[no_mangle]
+pub extern "C" fn sys_read_directory(fd: usize, entries: *mut DirectoryEntry, count: usize, index: usize) -> usize {
+    let entries = match user_slice_mut(entries, count) {
+        Ok(entries) => entries,
+        Err(err) => return to_syscall_result(Err(err))
+    };
+    let file = current_process().files().lock().get(fd);
+
+    to_syscall_result(file.and_then(|file| file.readdir_from(index, count)).map(|directory| {
+        let mut written = 0;
+        for (entry, slot) in directory.into_iter().zip(entries.iter_mut()) {
+            *slot = DirectoryEntry::from(entry);
+            written += 1;
+        }
+
+        written
+    }))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_make_directory(path_buffer: *const u8, path_length: usize) -> usize {
+    to_syscall_result(user_str(path_buffer, path_length).and_then(vfs::mkdir).map(|_| 0))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_remove_directory(path_buffer: *const u8, path_length: usize) -> usize {
+    to_syscall_result(user_str(path_buffer, path_length).and_then(vfs::rmdir).map(|_| 0))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_unlink(path_buffer: *const u8, path_length: usize) -> usize {
+    to_syscall_result(user_str(path_buffer, path_length).and_then(vfs::unlink).map(|_| 0))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_rename(old_path_buffer: *const u8, old_path_length: usize, new_path_buffer: *const u8, new_path_length: usize) -> usize {
+    let result = user_str(old_path_buffer, old_path_length)
+        .and_then(|old_path| user_str(new_path_buffer, new_path_length).and_then(|new_path| vfs::rename(old_path, new_path)));
+
+    to_syscall_result(result.map(|_| 0))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_truncate(fd: usize, size: usize) -> usize {
+    let file = current_process().files().lock().get(fd);
+    to_syscall_result(file.and_then(|file| file.truncate(size)).map(|_| 0))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_read_at(fd: usize, buffer: *mut u8, length: usize, offset: usize) -> usize {
+    let buffer = match user_slice_mut(buffer, length) {
+        Ok(buffer) => buffer,
+        Err(err) => return to_syscall_result(Err(err))
+    };
+    let file = current_process().files().lock().get(fd);
+
+    to_syscall_result(file.and_then(|file| file.read_at(offset, buffer)))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_write_at(fd: usize, buffer: *const u8, length: usize, offset: usize) -> usize {
+    let buffer = match user_slice(buffer, length) {
+        Ok(buffer) => buffer,
+        Err(err) => return to_syscall_result(Err(err))
+    };
+    let file = current_process().files().lock().get(fd);
+
+    to_syscall_result(file.and_then(|file| file.write_at(offset, buffer)))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_dup(fd: usize) -> usize {
+    to_syscall_result(current_process().files().lock().duplicate(fd))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_dup2(fd: usize, new_fd: usize, flags: usize) -> usize {
+    let flags = match DescriptorFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => return to_syscall_result(Err(Errno::InvalidArgument))
+    };
+
+    // The replaced file is closed after the descriptor table has been unlocked
+    let old = current_process().files().lock().duplicate_to(fd, new_fd, flags);
+    to_syscall_result(old.map(|old| {
+        if let Some(file) = old {
+            vfs::close(file);
+        }
+
+        new_fd
+    }))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_get_descriptor_flags(fd: usize) -> usize {
+    to_syscall_result(current_process().files().lock().flags(fd).map(|flags| flags.bits()))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_set_descriptor_flags(fd: usize, flags: usize) -> usize {
+    let flags = match DescriptorFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => return to_syscall_result(Err(Errno::InvalidArgument))
+    };
+
+    to_syscall_result(current_process().files().lock().set_flags(fd, flags).map(|_| 0))
+}
+
+/// Interpret a buffer passed by a user program as UTF-8 string (e.g. a path).
+fn user_str(buffer: *const u8, length: usize) -> Result<&'static str, Errno> {
+    return from_utf8(user_slice(buffer, length)?).map_err(|_| Errno::InvalidArgument);
+}
+
+/// Interpret a pointer and a number of elements, passed by a user program, as slice.
+/// A null pointer is only allowed for an empty slice (e.g. `write(fd, NULL, 0)`).
+fn user_slice<T>(buffer: *const T, length: usize) -> Result<&'static [T], Errno> {
+    return match unsafe { slice_from_raw_parts(buffer, length).as_ref() } {
+        Some(slice) => Ok(slice),
+        None if length == 0 => Ok(&[]),
+        None => Err(Errno::InvalidArgument)
+    };
+}
+
+/// Like `user_slice()`, but for buffers, which are written by the kernel.
+fn user_slice_mut<T>(buffer: *mut T, length: usize) -> Result<&'static mut [T], Errno> {
+    return match unsafe { slice_from_raw_parts_mut(buffer, length).as_mut() } {
+        Some(slice) => Ok(slice),
+        None if length == 0 => Ok(&mut []),
+        None => Err(Errno::InvalidArgument)
+    };
+}
+
+/// Create a pipe and write the descriptors of its reading and writing end into `fds`.
+#[no_mangle]
+pub extern "C" fn sys_pipe(fds: *mut [usize; 2], flags: usize) -> usize {
+    let flags = match DescriptorFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => return to_syscall_result(Err(Errno::InvalidArgument))
+    };
+
+    let (reader, writer) = pipe::pipe();
+    let mut files = current_process().files().lock();
+    let reader_fd = match files.insert(Arc::new(reader), flags) {
+        Ok(fd) => fd,
+        Err(err) => return to_syscall_result(Err(err))
+    };
+
+    let writer_fd = match files.insert(Arc::new(writer), flags) {
+        Ok(fd) => fd,
+        Err(err) => {
+            let _ = files.remove(reader_fd);
+            return to_syscall_result(Err(err));
+        }
+    };
+
+    unsafe { fds.write([reader_fd, writer_fd]); }
+    to_syscall_result(Ok(0))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_make_fifo(path_buffer: *const u8, path_length: usize) -> usize {
+    to_syscall_result(user_str(path_buffer, path_length).and_then(vfs::mkfifo).map(|_| 0))
+}
+
+/// Create an event counter with the value `initial` (see `EventCounter`) and return its descriptor.
+#[no_mangle]
+pub extern "C" fn sys_event_counter(initial: usize, flags: usize) -> usize {
+    let Some(flags) = EventCounterFlags::from_bits(flags) else {
+        return to_syscall_result(Err(Errno::InvalidArgument));
+    };
+
+    let counter = eventfd::EventCounter::new(initial as u64, flags.contains(EventCounterFlags::SEMAPHORE));
+    let descriptor_flags = if flags.contains(EventCounterFlags::CLOSE_ON_EXEC) { DescriptorFlags::CLOSE_ON_EXEC } else { DescriptorFlags::empty() };
+    to_syscall_result(current_process().files().lock().insert(counter, descriptor_flags))
+}
+
+/// Create a disarmed timer file (see `TimerFile`) and return its descriptor.
+#[no_mangle]
+pub extern "C" fn sys_timer_create(flags: usize) -> usize {
+    let Some(flags) = TimerFlags::from_bits(flags) else {
+        return to_syscall_result(Err(Errno::InvalidArgument));
+    };
+
+    let descriptor_flags = if flags.contains(TimerFlags::CLOSE_ON_EXEC) { DescriptorFlags::CLOSE_ON_EXEC } else { DescriptorFlags::empty() };
+    to_syscall_result(current_process().files().lock().insert(TimerFile::new(), descriptor_flags))
+}
+
+/// Arm (or disarm) the timer file `fd` (see `TimerFile::set()`) and return the remaining time of its previous setting.
+#[no_mangle]
+pub extern "C" fn sys_timer_set(fd: usize, initial_ms: usize, interval_ms: usize) -> usize {
+    let file = current_process().files().lock().get(fd);
+    to_syscall_result(file.and_then(|file| {
+        let (remaining_ms, _) = as_timer(&file)?.set(initial_ms, interval_ms);
+        Ok(remaining_ms)
+    }))
+}
+
+/// Wait until one of `count` descriptors is ready (see `poll::poll()`) and return the number of ready descriptors.
+#[no_mangle]
+pub extern "C" fn sys_poll(descriptors: *mut PollDescriptor, count: usize, timeout_ms: usize) -> usize {
+    if count > MAX_OPEN_FILES {
+        return to_syscall_result(Err(Errno::InvalidArgument));
+    }
+
+    // Polling no descriptors only waits for the timeout, so a null pointer is allowed in that case
+    let descriptors = match user_slice_mut(descriptors, count) {
+        Ok(descriptors) => descriptors,
+        Err(err) => return to_syscall_result(Err(err))
+    };
+    let files = {
+        let table = current_process().files().lock();
+        descriptors.iter().map(|descriptor| table.get(descriptor.fd).ok()).collect::<Vec<_>>()
+    };
+
+    let timeout_ms = if timeout_ms == POLL_INFINITE { None } else { Some(timeout_ms) };
+    to_syscall_result(Ok(poll::poll(descriptors, &files, timeout_ms)))
+}
+
+/// Pass a control request on to the driver of the file `fd` (see `IoctlRequest`).
+#[no_mangle]
+pub extern "C" fn sys_ioctl(fd: usize, request: usize, arg: usize) -> usize {
+    let file = current_process().files().lock().get(fd);
+    to_syscall_result(file.and_then(|file| file.ioctl(request, arg)))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_watch_create(flags: usize) -> usize {
+    let flags = match DescriptorFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => return to_syscall_result(Err(Errno::InvalidArgument))
+    };
+
+    to_syscall_result(current_process().files().lock().insert(watch::create(), flags))
+}
+
+/// Watch the file or directory at the given path for `events` and return the id of the watch.
+#[no_mangle]
+pub extern "C" fn sys_watch_add(fd: usize, path_buffer: *const u8, path_length: usize, events: usize) -> usize {
+    let events = match WatchEvents::from_bits(events) {
+        Some(events) => events,
+        None => return to_syscall_result(Err(Errno::InvalidArgument))
+    };
+
+    let watcher = current_process().files().lock().get(fd);
+    to_syscall_result(watcher.and_then(|watcher| {
+        let inode = vfs::resolve(user_str(path_buffer, path_length)?)?.inode();
+        as_watcher(&watcher)?.add(inode, events)
+    }))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_watch_remove(fd: usize, watch: usize) -> usize {
+    let watcher = current_process().files().lock().get(fd);
+    to_syscall_result(watcher.and_then(|watcher| as_watcher(&watcher)?.remove(watch)).map(|_| 0))
+}
+
+/// Acquire or release an advisory lock on the open file `fd` (like `flock()`).
+#[no_mangle]
+pub extern "C" fn sys_lock(fd: usize, operation: usize) -> usize {
+    let operation = match LockOperation::from_bits(operation) {
+        Some(operation) => operation,
+        None => return to_syscall_result(Err(Errno::InvalidArgument))
+    };
+
+    let file = current_process().files().lock().get(fd);
+    to_syscall_result(file.and_then(|file| file.lock(operation)).map(|_| 0))
+}
+
+/// Create a filesystem of type `fstype` from `source` (see `fs::new_filesystem()`) and mount it at `target`.
+#[no_mangle]
+pub extern "C" fn sys_mount(source_buffer: *const u8, source_length: usize, target_buffer: *const u8, target_length: usize, fstype_buffer: *const u8, fstype_length: usize) -> usize {
+    let result = user_str(source_buffer, source_length).and_then(|source| {
+        let target = user_str(target_buffer, target_length)?;
+        let fs = fs::new_filesystem(user_str(fstype_buffer, fstype_length)?, source)?;
+        vfs::mount(source, target, fs)
+    });
+
+    to_syscall_result(result.map(|_| 0))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_unmount(target_buffer: *const u8, target_length: usize) -> usize {
+    to_syscall_result(user_str(target_buffer, target_length).and_then(vfs::unmount).map(|_| 0))
+}
+
+/// Write modified data of the file `fd` back to its device (like `fsync()`).
+#[no_mangle]
+pub extern "C" fn sys_fsync(fd: usize) -> usize {
+    let file = current_process().files().lock().get(fd);
+    to_syscall_result(file.and_then(|file| file.sync()).map(|_| 0))
+}
+
+/// Map `length` bytes of the file `fd` at `offset` into the address space of the calling process and return the address of the mapping.
+/// Only device memory (e.g. of '/dev/fb0') can be mapped. The mapping is shared and stays, until the process exits.
+#[no_mangle]
+pub extern "C" fn sys_map_file(fd: usize, offset: usize, length: usize) -> usize {
+    let process = current_process();
+    let file = process.files().lock().get(fd);
+
+    to_syscall_result(file.and_then(|file| {
+        if length == 0 {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let (frames, caching_flags) = file.mmap(offset, length)?;
+
+        // Mappings are placed one after another, starting at `USER_MAPPING_ADDRESS`.
+        // The area is reserved before mapping it, so that existing areas (or a concurrent mapping) are never overwritten.
+        let start = process.memory_areas().iter()
+            .filter(|area| area.typ() == VmaType::Device)
+            .map(|area| area.end())
+            .max()
+            .unwrap_or(VirtAddr::new(USER_MAPPING_ADDRESS as u64));
+        let area = VirtualMemoryArea::from_address(start, (frames.end - frames.start) as usize * PAGE_SIZE, VmaType::Device);
+        process.try_add_vma(area)?;
+
+        process.add_mapping(file.mapped_memory().unwrap_or_else(|| Arc::new(Arc::clone(&file))));
+        process.address_space().map_physical(frames, area.range(), MemorySpace::Device, PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE | caching_flags);
+
+        Ok(start.as_u64() as usize)
+    }))
+}
+
+/// Write all modified data back and power off the system (does not return).
+/// All other user processes are asked to exit first (see `power::terminate_user_processes()`).
+#[no_mangle]
+pub extern "C" fn sys_shutdown() -> usize {
+    power::terminate_user_processes();
+    power::shutdown();
+}
+
+/// Write all modified data back and reset the system (does not return).
+/// All other user processes are asked to exit first (see `power::terminate_user_processes()`).
+#[no_mangle]
+pub extern "C" fn sys_reboot() -> usize {
+    power::terminate_user_processes();
+    power::reboot();
+}
+
+/// Fill `buffer` with cryptographically secure random bytes (like `getrandom()`). Never blocks.
+#[no_mangle]
+pub extern "C" fn sys_get_random(buffer: *mut u8, length: usize) -> usize {
+    let buffer = match user_slice_mut(buffer, length) {
+        Ok(buffer) => buffer,
+        Err(err) => return to_syscall_result(Err(err))
+    };
+    random::fill(buffer);
+
+    to_syscall_result(Ok(length))
+}
+
+/// Nanoseconds since booting (there is no wall clock).
+#[no_mangle]
+pub extern "C" fn sys_system_time() -> usize {
+    timer().read().systime_ns()
+}
+
+/// Create a socket of the given type and protocol (see `net::socket::socket()`) and return its descriptor.
+#[no_mangle]
+pub extern "C" fn sys_socket(typ: usize, protocol: usize, flags: usize) -> usize {
+    let (Ok(typ), Ok(protocol), Some(flags)) = (SocketType::try_from(typ), Protocol::try_from(protocol), DescriptorFlags::from_bits(flags)) else {
+        return to_syscall_result(Err(Errno::InvalidArgument));
+    };
+
+    let socket = socket::socket(typ, protocol);
+    to_syscall_result(socket.and_then(|socket| current_process().files().lock().insert(Arc::new(SocketFile::new(socket)), flags)))
+}
+
+/// Bind the socket `fd` to an address. For local sockets, `address` is a path of `length` bytes, at which a socket node is created.
+/// For all other sockets, it points to a `SocketAddress` and `length` is its size.
+#[no_mangle]
+pub extern "C" fn sys_bind(fd: usize, address: *const u8, length: usize) -> usize {
+    let file = current_process().files().lock().get(fd);
+    to_syscall_result(file.and_then(|file| {
+        let socket = as_socket(&file)?.socket();
+        match as_unix_socket(socket) {
+            Some(socket) => socket.bind(user_str(address, length)?),
+            None => socket.bind(user_socket_address(address, length)?)
+        }
+    }).map(|_| 0))
+}
+
+/// Connect the socket `fd` to an address (given like for `sys_bind()`). Blocks, until the connection has been established or refused.
+#[no_mangle]
+pub extern "C" fn sys_connect(fd: usize, address: *const u8, length: usize) -> usize {
+    let file = current_process().files().lock().get(fd);
+    to_syscall_result(file.and_then(|file| {
+        let socket = as_socket(&file)?.socket();
+        match as_unix_socket(socket) {
+            Some(socket) => socket.connect(user_str(address, length)?),
+            None => socket.connect(user_socket_address(address, length)?)
+        }
+    }).map(|_| 0))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_listen(fd: usize, backlog: usize) -> usize {
+    let file = current_process().files().lock().get(fd);
+    to_syscall_result(file.and_then(|file| as_socket(&file)?.socket().listen(backlog)).map(|_| 0))
+}
+
+/// Wait for a connection on the listening socket `fd` and return a new descriptor for it.
+/// The peer's address is written to `address`, unless it is null.
+#[no_mangle]
+pub extern "C" fn sys_accept(fd: usize, address: *mut syscall::net::SocketAddress, flags: usize) -> usize {
+    let Some(flags) = DescriptorFlags::from_bits(flags) else {
+        return to_syscall_result(Err(Errno::InvalidArgument));
+    };
+
+    // The descriptor table must not be locked, while waiting for a connection
+    let file = current_process().files().lock().get(fd);
+    let connection = file.and_then(|file| as_socket(&file)?.socket().accept());
+
+    to_syscall_result(connection.and_then(|(socket, remote)| {
+        if !address.is_null() {
+            unsafe { address.write(remote.into()); }
+        }
+
+        current_process().files().lock().insert(Arc::new(SocketFile::new(socket)), flags)
+    }))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_send(fd: usize, buffer: *const u8, length: usize) -> usize {
+    let buffer = match user_slice(buffer, length) {
+        Ok(buffer) => buffer,
+        Err(err) => return to_syscall_result(Err(err))
+    };
+    let file = current_process().files().lock().get(fd);
+
+    to_syscall_result(file.and_then(|file| as_socket(&file)?.socket().send(buffer)))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_receive(fd: usize, buffer: *mut u8, length: usize) -> usize {
+    let buffer = match user_slice_mut(buffer, length) {
+        Ok(buffer) => buffer,
+        Err(err) => return to_syscall_result(Err(err))
+    };
+    let file = current_process().files().lock().get(fd);
+
+    to_syscall_result(file.and_then(|file| as_socket(&file)?.socket().receive(buffer)))
+}
+
+/// Send the contents of `buffer` as a single datagram to `address`.
+#[no_mangle]
+pub extern "C" fn sys_send_to(fd: usize, buffer: *const u8, length: usize, address: *const syscall::net::SocketAddress) -> usize {
+    let buffer = match user_slice(buffer, length) {
+        Ok(buffer) => buffer,
+        Err(err) => return to_syscall_result(Err(err))
+    };
+    if address.is_null() {
+        return to_syscall_result(Err(Errno::InvalidArgument));
+    }
+    let address = unsafe { address.read() };
+    let file = current_process().files().lock().get(fd);
+
+    to_syscall_result(file.and_then(|file| as_socket(&file)?.socket().send_to(buffer, address.into())))
+}
+
+/// Receive a single datagram into `buffer` and return its (possibly truncated) length.
+/// The sender's address is written to `address`, unless it is null.
+#[no_mangle]
+pub extern "C" fn sys_receive_from(fd: usize, buffer: *mut u8, length: usize, address: *mut syscall::net::SocketAddress) -> usize {
+    let buffer = match user_slice_mut(buffer, length) {
+        Ok(buffer) => buffer,
+        Err(err) => return to_syscall_result(Err(err))
+    };
+    let file = current_process().files().lock().get(fd);
+
+    to_syscall_result(file.and_then(|file| as_socket(&file)?.socket().receive_from(buffer)).map(|(count, source)| {
+        if !address.is_null() {
+            unsafe { address.write(source.into()); }
+        }
+
+        count
+    }))
+}
+
+/// Open the message queue called `name` (see `mqueue::open()`) and return a descriptor for it.
+/// `attributes` is only used, if the queue is created (default attributes are used, if it is null).
+#[no_mangle]
+pub extern "C" fn sys_message_queue_open(name_buffer: *const u8, name_length: usize, flags: usize, attributes: *const QueueAttributes) -> usize {
+    let name = match user_str(name_buffer, name_length) {
+        Ok(name) => name,
+        Err(err) => return to_syscall_result(Err(err))
+    };
+
+    let flags = match OpenFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => return to_syscall_result(Err(Errno::InvalidArgument))
+    };
+
+    let attributes = if attributes.is_null() { None } else { Some(unsafe { attributes.read() }) };
+    let descriptor_flags = if flags.contains(OpenFlags::CLOSE_ON_EXEC) { DescriptorFlags::CLOSE_ON_EXEC } else { DescriptorFlags::empty() };
+    to_syscall_result(mqueue::open(name, flags, attributes).and_then(|queue| current_process().files().lock().insert(queue, descriptor_flags)))
+}
+
+#[no_mangle]
+pub extern "C" fn sys_message_queue_unlink(name_buffer: *const u8, name_length: usize) -> usize {
+    to_syscall_result(user_str(name_buffer, name_length).and_then(mqueue::unlink).map(|_| 0))
+}
+
+/// Send the contents of `buffer` as a single message with `priority`. Blocks, as long as the queue is full.
+#[no_mangle]
+pub extern "C" fn sys_message_queue_send(fd: usize, buffer: *const u8, length: usize, priority: usize) -> usize {
+    let buffer = match user_slice(buffer, length) {
+        Ok(buffer) => buffer,
+        Err(err) => return to_syscall_result(Err(err))
+    };
+    let file = current_process().files().lock().get(fd);
+
+    to_syscall_result(file.and_then(|file| as_message_queue(&file)?.send(buffer, priority)).map(|_| 0))
+}
+
+/// Receive the message with the highest priority into `buffer` and return its length.
+/// The priority of the message is written to `priority`, unless it is null.
+#[no_mangle]
+pub extern "C" fn sys_message_queue_receive(fd: usize, buffer: *mut u8, length: usize, priority: *mut usize) -> usize {
+    let buffer = match user_slice_mut(buffer, length) {
+        Ok(buffer) => buffer,
+        Err(err) => return to_syscall_result(Err(err))
+    };
+    let file = current_process().files().lock().get(fd);
+
+    to_syscall_result(file.and_then(|file| as_message_queue(&file)?.receive(buffer)).map(|(length, message_priority)| {
+        if !priority.is_null() {
+            unsafe { priority.write(message_priority); }
+        }
+
+        length
+    }))
+}
+
+fn as_message_queue(file: &Arc<dyn File>) -> Result<&MessageQueueFile, Errno> {
+    return (file.as_ref() as &dyn Any).downcast_ref::<MessageQueueFile>().ok_or(Errno::BadDescriptor);
+}
+
+fn as_socket(file: &Arc<dyn File>) -> Result<&SocketFile, Errno> {
+    return (file.as_ref() as &dyn Any).downcast_ref::<SocketFile>().ok_or(Errno::NotSupported);
+}
+
+fn as_unix_socket(socket: &Arc<dyn Socket>) -> Option<&UnixSocket> {
+    return (socket.as_ref() as &dyn Any).downcast_ref::<UnixSocket>();
+}
+
+fn user_socket_address(address: *const u8, length: usize) -> Result<socket::SocketAddress, Errno> {
+    if length != size_of::<syscall::net::SocketAddress>() {
+        return Err(Errno::InvalidArgument);
+    }
+
+    return Ok(unsafe { (address as *const syscall::net::SocketAddress).read_unaligned() }.into());
+}
+
+fn as_watcher(file: &Arc<dyn File>) -> Result<&Watcher, Errno> {
+    return (file.as_ref() as &dyn Any).downcast_ref::<Watcher>().ok_or(Errno::InvalidArgument);
+}
+
+fn as_timer(file: &Arc<dyn File>) -> Result<&TimerFile, Errno> {
+    return (file.as_ref() as &dyn Any).downcast_ref::<TimerFile>().ok_or(Errno::InvalidArgument);
+}
@@ -1,11 +1,15 @@
 use core::arch::asm;
+use log::info;
 use x86_64::registers::control::{Efer, EferFlags};
 use x86_64::registers::model_specific::{LStar, Star};
 use x86_64::structures::gdt::SegmentSelector;
 use x86_64::{PrivilegeLevel, VirtAddr};
 use syscall::NUM_SYSCALLS;
-use crate::syscall::{sys_write, sys_thread_exit, sys_thread_sleep, sys_thread_switch, sys_process_id, sys_thread_id, sys_read, sys_map_user_heap, sys_thread_join, sys_application_start};
-
+use crate::syscall::{sys_write, sys_thread_exit, sys_thread_sleep, sys_thread_switch, sys_process_id, sys_thread_id, sys_read, sys_map_user_heap, sys_thread_join, sys_application_start, sys_thread_nanosleep, sys_interval_timer, sys_alarm, sys_signal_action, sys_open, sys_close, sys_seek, sys_stat, sys_file_stat, sys_make_directory, sys_remove_directory, sys_unlink, sys_rename, sys_truncate, sys_read_at, sys_write_at, sys_dup, sys_dup2, sys_get_descriptor_flags, sys_set_descriptor_flags, sys_pipe, sys_make_fifo, sys_poll, sys_ioctl, sys_watch_create, sys_watch_add, sys_watch_remove, sys_lock, sys_mount, sys_unmount, sys_fsync, sys_map_file, sys_shutdown, sys_reboot, sys_get_random, sys_socket, sys_bind, sys_connect, sys_listen, sys_accept, sys_send, sys_receive, sys_send_to, sys_receive_from, sys_message_queue_open, sys_message_queue_unlink, sys_message_queue_send, sys_message_queue_receive, sys_event_counter, sys_system_time, sys_thread_running, sys_get_environment, sys_set_environment, sys_read_directory, sys_get_arguments, sys_timer_create, sys_timer_set};
+use crate::process::core_dump;
+use crate::process::signal;
+use crate::process::signal::{saved_registers, SignalAction};
+use crate::scheduler;
 
 pub fn init() {
     // Enable system call extensions
@@ -50,7 +54,64 @@ impl SyscallTable {
                 sys_thread_sleep as *const _,
                 sys_thread_join as *const _,
                 sys_thread_exit as *const _,
-                sys_application_start as *const _
+                sys_application_start as *const _,
+                sys_thread_nanosleep as *const _,
+                sys_interval_timer as *const _,
+                sys_alarm as *const _,
+                sys_signal_action as *const _,
+                sys_open as *const _,
+                sys_close as *const _,
+                sys_seek as *const _,
+                sys_stat as *const _,
+                sys_file_stat as *const _,
+                sys_make_directory as *const _,
+                sys_remove_directory as *const _,
+                sys_unlink as *const _,
+                sys_rename as *const _,
+                sys_truncate as *const _,
+                sys_read_at as *const _,
+                sys_write_at as *const _,
+                sys_dup as *const _,
+                sys_dup2 as *const _,
+                sys_get_descriptor_flags as *const _,
+                sys_set_descriptor_flags as *const _,
+                sys_pipe as *const _,
+                sys_make_fifo as *const _,
+                sys_poll as *const _,
+                sys_ioctl as *const _,
+                sys_watch_create as *const _,
+                sys_watch_add as *const _,
+                sys_watch_remove as *const _,
+                sys_lock as *const _,
+                sys_mount as *const _,
+                sys_unmount as *const _,
+                sys_fsync as *const _,
+                sys_map_file as *const _,
+                sys_shutdown as *const _,
+                sys_reboot as *const _,
+                sys_get_random as *const _,
+                sys_socket as *const _,
+                sys_bind as *const _,
+                sys_connect as *const _,
+                sys_listen as *const _,
+                sys_accept as *const _,
+                sys_send as *const _,
+                sys_receive as *const _,
+                sys_send_to as *const _,
+                sys_receive_from as *const _,
+                sys_message_queue_open as *const _,
+                sys_message_queue_unlink as *const _,
+                sys_message_queue_send as *const _,
+                sys_message_queue_receive as *const _,
+                sys_event_counter as *const _,
+                sys_system_time as *const _,
+                sys_thread_running as *const _,
+                sys_get_environment as *const _,
+                sys_set_environment as *const _,
+                sys_read_directory as *const _,
+                sys_get_arguments as *const _,
+                sys_timer_create as *const _,
+                sys_timer_set as *const _
             ],
         }
     }
@@ -63,7 +124,8 @@ unsafe impl Sync for SyscallTable {}
 #[no_mangle]
 // This functions does not take any parameters per its declaration,
 // but in reality, it takes at least the system call ID in rax
-// and may take additional parameters for the system call in rdi, rsi and rdx.
+// and may take additional parameters for the system call in rdi, rsi, rdx, r10, r8 and r9
+// (r10 is used instead of rcx, since rcx is overwritten by the syscall instruction).
 unsafe extern "C" fn syscall_handler() {
     asm!(
     // We are now in ring 0, but still on the user stack
@@ -92,13 +154,16 @@ unsafe extern "C" fn syscall_handler() {
     "mov r12, rdx", // Save third parameter in r12
     "call tss_get_rsp0", // Get kernel rsp (returned in rax)
     "mov rbx, rax", // Save kernel rsp in rbx
-    "mov rcx, rsp", // Save user rsp in rcx
+    "mov r11, rsp", // Save user rsp in r11 (eflags have already been saved on the user stack)
+    "mov r9, [r11 + 48]", // Restore sixth parameter from the user stack
+    "mov r8, [r11 + 56]", // Restore fifth parameter from the user stack
+    "mov rcx, [r11 + 40]", // Restore fourth parameter (passed in r10) from the user stack
     "mov rdx, r12", // Restore third parameter
     "mov rsi, r13", // Restore second parameter
     "mov rdi, r14", // Restore first parameter
     "mov rax, r15", // Restore system call ID
     "mov rsp, rbx", // Switch to kernel stack
-    "push rcx", // Save user rsp on stack
+    "push r11", // Save user rsp on stack
     "sti",
 
     // Check if system call ID is in bounds
@@ -108,6 +173,13 @@ unsafe extern "C" fn syscall_handler() {
     // Call system call handler, corresponding to ID (in rax)
     "call syscall_disp",
 
+    // Deliver pending signals (may modify the registers saved on the user stack and return a new user rsp)
+    "push rax", // Save return value
+    "mov rdi, [rsp + 8]", // User rsp is located right above the return value
+    "call syscall_deliver_signals",
+    "mov [rsp + 8], rax", // Store (possibly modified) user rsp
+    "pop rax", // Restore return value
+
     // Switch to user stack (user rsp is last value on stack)
     // Disable interrupts, since we are still in Ring 0 and no interrupt handler should be called with the user stack
     "cli",
@@ -156,3 +228,24 @@ unsafe extern "C" fn syscall_abort() {
 
     panic!("System call with id [{}] does not exist!", syscall_number);
 }
+
+#[no_mangle]
+unsafe extern "C" fn syscall_deliver_signals(user_rsp: u64) -> u64 {
+    let process = scheduler().current_thread().process();
+
+    match signal::handler_frame_action(&process, user_rsp) {
+        SignalAction::None => user_rsp,
+        SignalAction::Handle(signal) => process.signals().setup_handler_frame(user_rsp, signal),
+        SignalAction::Terminate(signal) => {
+            info!("Process [{}] has been terminated by signal [{:?}]", process.id(), signal);
+            if core_dump::dumps_core(signal) {
+                core_dump::dump(&process, signal, &saved_registers(&process, user_rsp), None);
+            }
+
+            drop(process); // Manually decrease reference count, because exit() will never return
+            scheduler().exit();
+
+            panic!("Terminated thread has been scheduled again!");
+        }
+    }
+}
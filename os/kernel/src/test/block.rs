@@ -0,0 +1,63 @@
+use alloc::sync::Arc;
+use alloc::vec;
+use syscall::error::Errno;
+use crate::block::cache::BlockCache;
+use crate::block::queue::RequestQueue;
+use crate::block::ramdisk::Ramdisk;
+
+kernel_test! {
+    fn block_queue_merges_and_orders_requests() {
+        let queue = RequestQueue::new(Arc::new(Ramdisk::new(8, 512)));
+
+        // Submitted out of order, these writes are merged into a single transfer for blocks 1-3
+        let requests = [
+            queue.submit_write(3, vec![3; 512]).unwrap(),
+            queue.submit_write(1, vec![1; 512]).unwrap(),
+            queue.submit_write(2, vec![2; 512]).unwrap(),
+        ];
+
+        for request in &requests {
+            queue.wait(request).unwrap();
+            assert!(request.is_completed());
+        }
+
+        let data = queue.read(0, 5).unwrap();
+        assert_eq!(&data[..512], &[0; 512]);
+        assert_eq!(&data[512..1024], &[1; 512]);
+        assert_eq!(&data[1024..1536], &[2; 512]);
+        assert_eq!(&data[1536..2048], &[3; 512]);
+        assert_eq!(&data[2048..], &[0; 512]);
+    }
+}
+
+kernel_test! {
+    fn block_queue_rejects_invalid_ranges() {
+        let queue = RequestQueue::new(Arc::new(Ramdisk::new(8, 512)));
+
+        assert_eq!(queue.read(7, 2).err(), Some(Errno::InvalidArgument));
+        assert_eq!(queue.write(0, vec![0; 100]).err(), Some(Errno::InvalidArgument));
+        assert_eq!(queue.read(0, 0).err(), Some(Errno::InvalidArgument));
+    }
+}
+
+kernel_test! {
+    fn block_cache_write_back() {
+        let queue = Arc::new(RequestQueue::new(Arc::new(Ramdisk::new(16, 512))));
+        let cache = BlockCache::new(4 * 512);
+
+        cache.write(&queue, 3, 10, b"abc").unwrap();
+        let mut buffer = [0; 3];
+        cache.read(&queue, 3, 10, &mut buffer).unwrap();
+        assert_eq!(&buffer, b"abc");
+
+        // Dirty blocks are only written to the device on flush
+        assert_eq!(&queue.read(3, 1).unwrap()[10..13], &[0; 3]);
+        cache.flush().unwrap();
+        assert_eq!(&queue.read(3, 1).unwrap()[10..13], b"abc");
+
+        // Reading with read-ahead exceeds the capacity, so that least recently used blocks are evicted
+        cache.read(&queue, 8, 0, &mut buffer).unwrap();
+        assert!(cache.size() <= 4 * 512);
+        assert_eq!(cache.read(&queue, 16, 0, &mut buffer), Err(Errno::InvalidArgument));
+    }
+}
@@ -0,0 +1,252 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ptr;
+use stream::{InputStream, OutputStream};
+use syscall::error::Errno;
+use syscall::file::OpenFlags;
+use syscall::input::MouseButtons;
+use syscall::ioctl::{IoctlRequest, KeyboardLayout, TerminalMode, WindowGeometry, WindowSize};
+use x86_64::registers::control::{Efer, EferFlags};
+use crate::cpu::features;
+use crate::cpu::features::Feature;
+use crate::device::keymap::Keymap;
+use crate::device::ps2::decode_packet;
+use crate::device::terminal::Terminal;
+use crate::device::tty::LineDiscipline;
+use crate::fs::vfs;
+use crate::smbios::HardwareInfo;
+use crate::sync::Mutex;
+
+kernel_test! {
+    fn window_is_removed_on_close_while_mapped() {
+        let file = vfs::open("/dev/window", OpenFlags::READ | OpenFlags::WRITE).unwrap();
+        let geometry = WindowGeometry { x: 0, y: 0, width: 64, height: 32 };
+        file.ioctl(IoctlRequest::CreateWindow as usize, ptr::from_ref(&geometry) as usize).unwrap();
+        file.mmap(0, 4096).unwrap();
+
+        // The mapping only keeps the surface, which is also referenced by the window on the screen
+        let surface = file.mapped_memory().unwrap();
+        assert_eq!(Arc::strong_count(&surface), 2);
+
+        vfs::close(file);
+        assert_eq!(Arc::strong_count(&surface), 1);
+    }
+}
+
+kernel_test! {
+    fn mouse_packets_are_decoded() {
+        let event = decode_packet(&[0x09, 5, 3]).unwrap();
+        assert_eq!((event.dx, event.dy, event.wheel), (5, -3, 0));
+        assert_eq!(event.buttons, MouseButtons::LEFT);
+
+        // Sign bits of the movement values are part of the first byte, upward movement is reported as positive
+        let event = decode_packet(&[0x3e, 0xfb, 0xfe]).unwrap();
+        assert_eq!((event.dx, event.dy), (-5, 2));
+        assert_eq!(event.buttons, MouseButtons::RIGHT | MouseButtons::MIDDLE);
+
+        // The fourth byte of scroll wheel mice contains the 4 bit wheel movement
+        assert_eq!(decode_packet(&[0x08, 0, 0, 0x01]).unwrap().wheel, 1);
+        assert_eq!(decode_packet(&[0x08, 0, 0, 0x0f]).unwrap().wheel, -1);
+
+        // Packets with overflowing movement values are dropped
+        assert!(decode_packet(&[0x48, 0xff, 0]).is_none());
+        assert!(decode_packet(&[0x88, 0, 0xff]).is_none());
+    }
+}
+
+kernel_test! {
+    fn terminal_is_controlled_via_ioctl() {
+        let file = vfs::open("/dev/tty", OpenFlags::READ | OpenFlags::WRITE).unwrap();
+        let mut size = WindowSize { columns: 0, rows: 0 };
+        file.ioctl(IoctlRequest::GetWindowSize as usize, ptr::from_mut(&mut size) as usize).unwrap();
+        assert!(size.columns > 0 && size.rows > 0);
+
+        file.ioctl(IoctlRequest::SetTerminalMode as usize, TerminalMode::Raw as usize).unwrap();
+        assert_eq!(file.ioctl(IoctlRequest::GetTerminalMode as usize, 0), Ok(TerminalMode::Raw as usize));
+        file.ioctl(IoctlRequest::SetTerminalMode as usize, TerminalMode::Canonical as usize).unwrap();
+        assert_eq!(file.ioctl(IoctlRequest::GetTerminalMode as usize, 0), Ok(TerminalMode::Canonical as usize));
+        assert_eq!(file.ioctl(IoctlRequest::SetTerminalMode as usize, 42), Err(Errno::InvalidArgument));
+        vfs::close(file);
+
+        // Devices without control requests reject them
+        let file = vfs::open("/dev/null", OpenFlags::READ).unwrap();
+        assert_eq!(file.ioctl(IoctlRequest::GetWindowSize as usize, ptr::from_mut(&mut size) as usize), Err(Errno::NotATerminal));
+        vfs::close(file);
+    }
+}
+
+/// Feed scancodes (set 1) into `keymap` and collect the typed characters.
+fn type_scancodes(keymap: &mut Keymap, scancodes: &[u8]) -> String {
+    let mut typed = String::new();
+    for scancode in scancodes {
+        keymap.decode(*scancode, |c| typed.push(c));
+    }
+
+    return typed;
+}
+
+kernel_test! {
+    fn keymaps_combine_dead_keys_and_alt_gr() {
+        let mut keymap = Keymap::new(KeyboardLayout::UsInternational);
+
+        // Apostrophe (dead key) followed by 'e', space and 'x'
+        assert_eq!(type_scancodes(&mut keymap, &[0x28, 0xa8, 0x12, 0x92]), "é");
+        assert_eq!(type_scancodes(&mut keymap, &[0x28, 0xa8, 0x39, 0xb9]), "'");
+        assert_eq!(type_scancodes(&mut keymap, &[0x28, 0xa8, 0x2d, 0xad]), "'x");
+
+        // AltGr (extended right alt) + 'q' and a plain 'q' after releasing AltGr
+        assert_eq!(type_scancodes(&mut keymap, &[0xe0, 0x38, 0x10, 0x90, 0xe0, 0xb8]), "ä");
+        assert_eq!(type_scancodes(&mut keymap, &[0x10, 0x90]), "q");
+
+        // Keys without a character are sent as escape sequences
+        assert_eq!(type_scancodes(&mut keymap, &[0xe0, 0x48, 0xe0, 0xc8]), "\x1b[A");
+
+        // The US layout has no dead keys
+        keymap.set_layout(KeyboardLayout::Us);
+        assert_eq!(keymap.layout(), KeyboardLayout::Us);
+        assert_eq!(type_scancodes(&mut keymap, &[0x28, 0xa8]), "'");
+    }
+}
+
+/// Terminal without input, which records the echoed output.
+struct RecordingTerminal {
+    output: Mutex<Vec<u8>>,
+}
+
+impl OutputStream for RecordingTerminal {
+    fn write_byte(&self, b: u8) {
+        self.output.lock().push(b);
+    }
+
+    fn write_str(&self, string: &str) {
+        self.output.lock().extend_from_slice(string.as_bytes());
+    }
+}
+
+impl InputStream for RecordingTerminal {
+    fn read_byte(&self) -> i16 {
+        return -1;
+    }
+}
+
+impl Terminal for RecordingTerminal {
+    fn clear(&self) {}
+
+    fn has_input(&self) -> bool {
+        return false;
+    }
+
+    fn window_size(&self) -> WindowSize {
+        return WindowSize { columns: 80, rows: 25 };
+    }
+}
+
+kernel_test! {
+    fn line_discipline_edits_lines() {
+        let terminal = RecordingTerminal { output: Mutex::new(Vec::new()) };
+        let mut line_discipline = LineDiscipline::new();
+        let mut buffer = [0u8; 32];
+        let type_bytes = |line_discipline: &mut LineDiscipline, bytes: &[u8]| {
+            bytes.iter().for_each(|byte| line_discipline.input(0, &terminal, *byte));
+        };
+
+        // Nothing can be read before the line is complete
+        type_bytes(&mut line_discipline, b"hello\x08p");
+        assert_eq!(line_discipline.take(&mut buffer), None);
+        type_bytes(&mut line_discipline, b"\n");
+        assert_eq!(line_discipline.take(&mut buffer), Some(6));
+        assert_eq!(&buffer[..6], b"hellp\n");
+
+        // Erasing words and lines, control characters are removed together with their caret notation
+        type_bytes(&mut line_discipline, b"foo bar\x17\x01\x08x\n");
+        assert_eq!(line_discipline.take(&mut buffer), Some(6));
+        assert_eq!(&buffer[..6], b"foo x\n");
+        type_bytes(&mut line_discipline, "ä\x08abc\x15ok\n".as_bytes());
+        assert_eq!(line_discipline.take(&mut buffer), Some(3));
+        assert_eq!(&buffer[..3], b"ok\n");
+
+        // End of file passes the line without a line break and reads 0 on an empty line
+        type_bytes(&mut line_discipline, b"x\x04");
+        assert_eq!(line_discipline.take(&mut buffer), Some(1));
+        type_bytes(&mut line_discipline, b"\x04");
+        assert_eq!(line_discipline.take(&mut buffer), Some(0));
+        assert_eq!(line_discipline.take(&mut buffer), None);
+
+        let output = terminal.output.lock();
+        assert!(output.starts_with(b"hello\x08 \x08p\nfoo bar\x08 \x08\x08 \x08\x08 \x08^A\x08 \x08\x08 \x08x\n"));
+    }
+}
+
+kernel_test! {
+    fn cpu_features_are_listed_in_cpuinfo() {
+        let info = features::cpu_info();
+        let cpuinfo = String::from_utf8(vfs::read_all("/proc/cpuinfo").unwrap()).unwrap();
+        let flags = cpuinfo.lines().find_map(|line| line.strip_prefix("flags")).unwrap();
+        let flags = flags.trim_start_matches(['\t', ' ', ':']).split(' ').collect::<Vec<&str>>();
+
+        for feature in Feature::ALL {
+            assert_eq!(features::has(feature), info.has(feature));
+            assert_eq!(flags.contains(&feature.name()), info.has(feature));
+        }
+
+        // The no-execute bit is enabled during initialization, if it is supported
+        assert_eq!(Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE), info.has(Feature::Nx));
+    }
+}
+
+/// Build an SMBIOS structure of `length` bytes with the given fields, followed by its string set.
+fn smbios_structure(typ: u8, length: u8, fields: &[(usize, &[u8])], strings: &[&str]) -> Vec<u8> {
+    let mut structure = vec![0u8; length as usize];
+    structure[0] = typ;
+    structure[1] = length;
+    for (offset, value) in fields {
+        structure[*offset..*offset + value.len()].copy_from_slice(value);
+    }
+
+    for string in strings {
+        structure.extend_from_slice(string.as_bytes());
+        structure.push(0);
+    }
+    if strings.is_empty() {
+        structure.push(0);
+    }
+    structure.push(0);
+
+    return structure;
+}
+
+/// Build a DDR4 memory device structure with a speed of 3200 MT/s. The strings are locator, manufacturer and part number.
+fn smbios_memory_device(size: u16, extended_size: u32, strings: &[&str]) -> Vec<u8> {
+    let (size, speed, extended_size) = (size.to_le_bytes(), 3200u16.to_le_bytes(), extended_size.to_le_bytes());
+    let fields: [(usize, &[u8]); 7] = [(0x0c, &size), (0x10, &[1]), (0x12, &[0x1a]), (0x15, &speed), (0x17, &[2]), (0x1a, &[3]), (0x1c, &extended_size)];
+    return smbios_structure(17, 0x22, &fields, strings);
+}
+
+kernel_test! {
+    fn smbios_tables_are_parsed() {
+        let bios = smbios_structure(0, 0x12, &[(0x04, &[1, 2]), (0x08, &[3])], &["Vendor", "1.0", "01/01/2024"]);
+        // 2048 KiB, an empty slot and 32 GiB given in the extended size field
+        let memory_device = smbios_memory_device(0x8000 | 2048, 0, &["DIMM 0", "Acme", "P1"]);
+        let mut table = [bios.as_slice(), memory_device.as_slice()].concat();
+        table.extend(smbios_memory_device(0, 0, &["DIMM 1"]));
+        table.extend(smbios_memory_device(0x7fff, 32768, &["DIMM 2", "Acme", "P1"]));
+        table.extend(smbios_structure(127, 4, &[], &[]));
+        // Structures after the end structure are ignored
+        table.extend(smbios_structure(1, 8, &[(0x04, &[1])], &["Ignored"]));
+
+        let description = HardwareInfo::parse((3, 0), &table).describe();
+        assert_eq!(description.lines().collect::<Vec<&str>>(), [
+            "SMBIOS version: 3.0",
+            "BIOS:           Vendor 1.0 (01/01/2024)",
+            "Memory slot:    DIMM 0: 2 MiB DDR4 (3200 MT/s, Acme P1)",
+            "Memory slot:    DIMM 1: Empty",
+            "Memory slot:    DIMM 2: 32768 MiB DDR4 (3200 MT/s, Acme P1)",
+        ]);
+
+        // Parsing stops at a structure, which exceeds the table
+        let truncated = [bios.as_slice(), &memory_device[..0x10]].concat();
+        assert_eq!(HardwareInfo::parse((3, 0), &truncated).describe().lines().count(), 2);
+    }
+}
@@ -0,0 +1,354 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use syscall::error::Errno;
+use core::mem::size_of;
+use syscall::file::{FileType, LockOperation, OpenFlags, PollDescriptor, PollEvents, WatchEvent, WatchEvents, WATCH_NAME_LENGTH};
+use syscall::mqueue::QueueAttributes;
+use crate::fs::{mqueue, pipe, poll, watch, File, FileSystem, SeekFrom};
+use crate::fs::eventfd::EventCounter;
+use crate::fs::poll::Pollable;
+use crate::fs::timerfd::TimerFile;
+use crate::fs::tmpfs::Tmpfs;
+use crate::fs::vfs;
+use crate::fs::vfs::OpenFile;
+use crate::timer;
+
+kernel_test! {
+    fn tmpfs_create_write_read() {
+        let fs = Tmpfs::new(4096);
+        let file = fs.root().create("file", FileType::Regular).unwrap();
+
+        assert_eq!(file.write_at(2, b"abc").unwrap(), 3);
+        assert_eq!(file.metadata().size, 5);
+        assert_eq!(fs.used(), 5);
+
+        let mut buffer = [0xff; 8];
+        assert_eq!(file.read_at(0, &mut buffer).unwrap(), 5);
+        assert_eq!(&buffer[..5], b"\0\0abc");
+        assert_eq!(file.read_at(5, &mut buffer).unwrap(), 0);
+    }
+}
+
+kernel_test! {
+    fn tmpfs_size_limit() {
+        let fs = Tmpfs::new(8);
+        let file = fs.root().create("file", FileType::Regular).unwrap();
+
+        assert_eq!(file.write_at(0, b"12345678").unwrap(), 8);
+        assert_eq!(file.write_at(8, b"9"), Err(Errno::NoSpace));
+
+        file.truncate(4).unwrap();
+        assert_eq!(fs.used(), 4);
+
+        // Space is released, once a deleted file is not referenced anymore
+        fs.root().unlink("file").unwrap();
+        drop(file);
+        assert_eq!(fs.used(), 0);
+    }
+}
+
+kernel_test! {
+    fn tmpfs_directories_and_rename() {
+        let fs = Tmpfs::new(4096);
+        let root = fs.root();
+        let directory = root.create("directory", FileType::Directory).unwrap();
+        directory.create("file", FileType::Regular).unwrap();
+
+        assert_eq!(root.create("directory", FileType::Directory).err(), Some(Errno::AlreadyExists));
+        assert_eq!(root.unlink("directory"), Err(Errno::NotEmpty));
+
+        directory.rename("file", &root, "moved").unwrap();
+        assert_eq!(directory.lookup("file").err(), Some(Errno::NotFound));
+        assert_eq!(root.lookup("moved").unwrap().metadata().typ, FileType::Regular);
+
+        let names = root.readdir().unwrap().into_iter().map(|entry| entry.name).collect::<alloc::vec::Vec<_>>();
+        assert_eq!(names, ["directory", "moved"]);
+
+        root.unlink("directory").unwrap();
+        assert_eq!(root.lookup("directory").err(), Some(Errno::NotFound));
+    }
+}
+
+kernel_test! {
+    fn open_file_offsets() {
+        let fs = Tmpfs::new(4096);
+        let inode = fs.root().create("file", FileType::Regular).unwrap();
+        let file: Arc<dyn File> = Arc::new(OpenFile::new(Arc::clone(&inode), OpenFlags::READ_WRITE));
+        let shared = Arc::clone(&file); // Like a duplicated descriptor
+
+        // Positional writes and reads do not move the offset
+        assert_eq!(file.write_at(4, b"data").unwrap(), 4);
+        assert_eq!(file.seek(SeekFrom::Current(0)).unwrap(), 0);
+
+        let mut buffer = [0; 4];
+        assert_eq!(file.read_at(4, &mut buffer).unwrap(), 4);
+        assert_eq!(&buffer, b"data");
+
+        // The offset is shared between all references to an open file
+        assert_eq!(file.write(b"abcd").unwrap(), 4);
+        assert_eq!(shared.read(&mut buffer).unwrap(), 4);
+        assert_eq!(&buffer, b"data");
+
+        // Appending ignores the offset of the file
+        let append = OpenFile::new(Arc::clone(&inode), OpenFlags::WRITE | OpenFlags::APPEND);
+        append.seek(SeekFrom::Start(0)).unwrap();
+        append.write(b"!").unwrap();
+        assert_eq!(inode.metadata().size, 9);
+
+        file.truncate(2).unwrap();
+        assert_eq!(inode.metadata().size, 2);
+        assert_eq!(OpenFile::new(inode, OpenFlags::READ).truncate(0), Err(Errno::BadDescriptor));
+    }
+}
+
+kernel_test! {
+    fn pipe_eof_and_broken_pipe() {
+        let (reader, writer) = pipe::pipe();
+        assert_eq!(writer.write(b"hello").unwrap(), 5);
+        assert_eq!(reader.stat().unwrap().size, 5);
+
+        let mut buffer = [0; 8];
+        assert_eq!(reader.read(&mut buffer[..3]).unwrap(), 3);
+        assert_eq!(&buffer[..3], b"hel");
+
+        // Remaining data can still be read after the writer is gone, followed by EOF
+        drop(writer);
+        assert_eq!(reader.read(&mut buffer).unwrap(), 2);
+        assert_eq!(&buffer[..2], b"lo");
+        assert_eq!(reader.read(&mut buffer).unwrap(), 0);
+
+        let (reader, writer) = pipe::pipe();
+        drop(reader);
+        assert_eq!(writer.write(b"data"), Err(Errno::BrokenPipe));
+    }
+}
+
+kernel_test! {
+    fn poll_pipe() {
+        let (reader, writer) = pipe::pipe();
+        let files: [Option<Arc<dyn File>>; 3] = [Some(Arc::new(reader)), Some(Arc::new(writer)), None];
+        let mut descriptors = [PollDescriptor::new(0, PollEvents::READABLE), PollDescriptor::new(1, PollEvents::WRITABLE), PollDescriptor::new(2, PollEvents::READABLE)];
+
+        assert_eq!(poll::poll(&mut descriptors[..1], &files[..1], Some(0)), 0);
+        assert_eq!(poll::poll(&mut descriptors, &files, Some(0)), 2);
+        assert_eq!(descriptors[0].ready, PollEvents::empty());
+        assert_eq!(descriptors[1].ready, PollEvents::WRITABLE);
+        assert_eq!(descriptors[2].ready, PollEvents::INVALID);
+
+        files[1].as_ref().unwrap().write(b"data").unwrap();
+        assert_eq!(poll::poll(&mut descriptors[..1], &files[..1], None), 1);
+        assert_eq!(descriptors[0].ready, PollEvents::READABLE);
+    }
+}
+
+kernel_test! {
+    fn watch_events() {
+        let fs = Tmpfs::new(4096);
+        let root = fs.root();
+        let inode = root.create("file", FileType::Regular).unwrap();
+
+        let watcher = watch::create();
+        let directory_watch = watcher.add(Arc::clone(&root), WatchEvents::CREATE | WatchEvents::DELETE).unwrap();
+        let file_watch = watcher.add(Arc::clone(&inode), WatchEvents::MODIFY).unwrap();
+        assert_eq!(watcher.poll(PollEvents::READABLE, None), PollEvents::empty());
+
+        // Writing through an open file is reported by the VFS
+        OpenFile::new(Arc::clone(&inode), OpenFlags::WRITE).write(b"data").unwrap();
+        watch::notify(&root, WatchEvents::DELETE, "file");
+        watch::notify(&root, WatchEvents::MODIFY, "other"); // Not watched
+
+        let mut buffer = [0u8; 3 * size_of::<WatchEvent>()];
+        assert_eq!(watcher.read(&mut buffer).unwrap(), 2 * size_of::<WatchEvent>());
+
+        let events = buffer.chunks_exact(size_of::<WatchEvent>()).map(|chunk| unsafe { chunk.as_ptr().cast::<WatchEvent>().read_unaligned() }).collect::<alloc::vec::Vec<_>>();
+        assert_eq!((events[0].watch, events[0].events, events[0].name()), (file_watch, WatchEvents::MODIFY, ""));
+        assert_eq!((events[1].watch, events[1].events, events[1].name()), (directory_watch, WatchEvents::DELETE, "file"));
+
+        watcher.remove(directory_watch).unwrap();
+        assert_eq!(watcher.remove(directory_watch), Err(Errno::InvalidArgument));
+    }
+}
+
+kernel_test! {
+    fn watch_event_names_are_truncated_at_char_boundaries() {
+        // 'ä' takes two bytes, so it does not fit completely
+        let name = "a".repeat(WATCH_NAME_LENGTH - 1) + "ä";
+        let event = WatchEvent::new(0, WatchEvents::CREATE, &name);
+        assert_eq!(event.name_length, WATCH_NAME_LENGTH - 1);
+        assert_eq!(event.name(), &name[..WATCH_NAME_LENGTH - 1]);
+    }
+}
+
+kernel_test! {
+    fn directories_are_read_in_chunks() {
+        let fs = Tmpfs::new(4096);
+        let root = fs.root();
+        for name in ["a", "b", "c"] {
+            root.create(name, FileType::Regular).unwrap();
+        }
+
+        let directory = OpenFile::new(Arc::clone(&root), OpenFlags::READ);
+        let total = directory.readdir().unwrap().len();
+        assert_eq!(directory.readdir_from(0, 2).unwrap().len(), 2);
+
+        // Later chunks continue the listing taken at index 0, even if the directory changes in the meantime
+        root.create("d", FileType::Regular).unwrap();
+        assert_eq!(directory.readdir_from(2, total).unwrap().len(), total - 2);
+        assert!(directory.readdir_from(total, 2).unwrap().is_empty());
+
+        // Starting again at index 0 lists the directory again
+        assert_eq!(directory.readdir_from(0, total + 1).unwrap().len(), total + 1);
+    }
+}
+
+kernel_test! {
+    fn file_locks() {
+        let fs = Tmpfs::new(4096);
+        let inode = fs.root().create("file", FileType::Regular).unwrap();
+        let first = OpenFile::new(Arc::clone(&inode), OpenFlags::READ);
+        let second = OpenFile::new(Arc::clone(&inode), OpenFlags::READ);
+
+        first.lock(LockOperation::SHARED).unwrap();
+        second.lock(LockOperation::SHARED | LockOperation::NON_BLOCKING).unwrap();
+        assert_eq!(first.lock(LockOperation::EXCLUSIVE | LockOperation::NON_BLOCKING), Err(Errno::WouldBlock));
+
+        // Closing an open file releases its lock
+        drop(second);
+        first.lock(LockOperation::EXCLUSIVE | LockOperation::NON_BLOCKING).unwrap();
+
+        let third = OpenFile::new(inode, OpenFlags::READ);
+        assert_eq!(third.lock(LockOperation::SHARED | LockOperation::NON_BLOCKING), Err(Errno::WouldBlock));
+        first.lock(LockOperation::UNLOCK).unwrap();
+        third.lock(LockOperation::SHARED | LockOperation::NON_BLOCKING).unwrap();
+
+        assert_eq!(third.lock(LockOperation::SHARED | LockOperation::EXCLUSIVE), Err(Errno::InvalidArgument));
+    }
+}
+
+kernel_test! {
+    fn message_queues_deliver_by_priority() {
+        let flags = OpenFlags::READ_WRITE | OpenFlags::CREATE | OpenFlags::EXCLUSIVE;
+        let queue = mqueue::open("/test", flags, Some(QueueAttributes::new(3, 8))).unwrap();
+        assert_eq!(mqueue::open("/test", flags, None).err(), Some(Errno::AlreadyExists));
+        assert_eq!(mqueue::open("test", OpenFlags::READ, None).err(), Some(Errno::InvalidArgument));
+        assert_eq!(queue.poll(PollEvents::READABLE, None), PollEvents::WRITABLE);
+
+        queue.send(b"low", 0).unwrap();
+        queue.send(b"high", 5).unwrap();
+        queue.send(b"low 2", 0).unwrap();
+        assert_eq!(queue.send(b"too long!", 0), Err(Errno::MessageTooLong));
+        assert_eq!(queue.poll(PollEvents::WRITABLE, None), PollEvents::READABLE);
+
+        // Messages keep their boundaries and leave the queue by priority, then in order of sending
+        let mut buffer = [0u8; 8];
+        assert_eq!(queue.receive(&mut buffer), Ok((4, 5)));
+        assert_eq!(&buffer[..4], b"high");
+        assert_eq!(queue.receive(&mut buffer), Ok((3, 0)));
+        assert_eq!(queue.read(&mut buffer), Ok(5));
+        assert_eq!(&buffer[..5], b"low 2");
+        assert_eq!(queue.receive(&mut buffer[..4]), Err(Errno::MessageTooLong));
+
+        // Unlinking only removes the name
+        let reader = mqueue::open("/test", OpenFlags::READ, None).unwrap();
+        assert_eq!(reader.send(b"data", 0), Err(Errno::BadDescriptor));
+        mqueue::unlink("/test").unwrap();
+        assert_eq!(mqueue::open("/test", OpenFlags::READ, None).err(), Some(Errno::NotFound));
+        queue.write(b"data").unwrap();
+        assert_eq!(reader.receive(&mut buffer), Ok((4, 0)));
+    }
+}
+
+kernel_test! {
+    fn event_counters_add_up_signals() {
+        let counter = EventCounter::new(0, false);
+        assert_eq!(counter.poll(PollEvents::READABLE, None), PollEvents::WRITABLE);
+        assert_eq!(counter.write(&[0; 4]), Err(Errno::InvalidArgument));
+
+        counter.write(&2u64.to_ne_bytes()).unwrap();
+        counter.write(&3u64.to_ne_bytes()).unwrap();
+        assert!(counter.poll(PollEvents::READABLE, None).contains(PollEvents::READABLE));
+
+        let mut value = [0u8; 8];
+        assert_eq!(counter.read(&mut value), Ok(8));
+        assert_eq!(u64::from_ne_bytes(value), 5);
+        assert!(!counter.poll(PollEvents::READABLE, None).contains(PollEvents::READABLE));
+
+        // In semaphore mode, each read consumes a single signal
+        let semaphore = EventCounter::new(2, true);
+        semaphore.read(&mut value).unwrap();
+        assert_eq!(u64::from_ne_bytes(value), 1);
+        semaphore.read(&mut value).unwrap();
+        assert_eq!(semaphore.poll(PollEvents::READABLE | PollEvents::WRITABLE, None), PollEvents::WRITABLE);
+    }
+}
+
+kernel_test! {
+    fn timer_files_count_expirations() {
+        let timer_file = TimerFile::new();
+        assert_eq!(timer_file.poll(PollEvents::READABLE, None), PollEvents::empty());
+        assert_eq!(timer_file.write(&1u64.to_ne_bytes()), Err(Errno::InvalidArgument));
+
+        timer_file.set(10, 10);
+        let deadline = timer().read().systime_ms() + 1000;
+        while timer_file.poll(PollEvents::READABLE, None).is_empty() {
+            assert!(timer().read().systime_ms() < deadline, "Timer file has not expired");
+            core::hint::spin_loop();
+        }
+
+        let mut value = [0u8; 8];
+        assert_eq!(timer_file.read(&mut value), Ok(8));
+        assert!(u64::from_ne_bytes(value) >= 1);
+
+        // Setting the timer again returns the previous setting and discards unread expirations
+        let (remaining_ms, interval_ms) = timer_file.set(0, 0);
+        assert!(remaining_ms <= 10);
+        assert_eq!(interval_ms, 10);
+        assert_eq!(timer_file.poll(PollEvents::READABLE, None), PollEvents::empty());
+    }
+}
+
+kernel_test! {
+    fn mounted_filesystems_hide_and_restore_directories() {
+        let path = "/tmp/mount-test";
+        vfs::mkdir(path).unwrap();
+        vfs::close(vfs::open("/tmp/mount-test/hidden", OpenFlags::WRITE | OpenFlags::CREATE).unwrap());
+
+        vfs::mount("test", path, Arc::new(Tmpfs::new(4096))).unwrap();
+        assert_eq!(vfs::stat("/tmp/mount-test/hidden").err(), Some(Errno::NotFound));
+        let mounts = String::from_utf8(vfs::read_all("/proc/mounts").unwrap()).unwrap();
+        assert!(mounts.lines().any(|line| line == "test /tmp/mount-test tmpfs"));
+
+        // Filesystems, which contain other mount points, can not be unmounted
+        vfs::mkdir("/tmp/mount-test/nested").unwrap();
+        vfs::mount("nested", "/tmp/mount-test/nested", Arc::new(Tmpfs::new(4096))).unwrap();
+        assert_eq!(vfs::unmount(path), Err(Errno::Busy));
+        vfs::unmount("/tmp/mount-test/nested").unwrap();
+
+        vfs::unmount(path).unwrap();
+        assert_eq!(vfs::unmount(path), Err(Errno::InvalidArgument));
+        assert!(vfs::stat("/tmp/mount-test/hidden").is_ok());
+        assert!(!vfs::mounts().iter().any(|(_, mount_point, _)| mount_point == path));
+
+        vfs::unlink("/tmp/mount-test/hidden").unwrap();
+        vfs::rmdir(path).unwrap();
+    }
+}
+
+kernel_test! {
+    fn directories_can_not_be_moved_into_themselves() {
+        vfs::mkdir("/tmp/rename-test").unwrap();
+        vfs::mkdir("/tmp/rename-test/inner").unwrap();
+
+        // Differently spelled paths resolve to the same directory
+        for (old_path, new_path) in [("/tmp/rename-test", "/tmp/rename-test/moved"), ("//tmp/rename-test", "/tmp/rename-test/moved"),
+            ("/tmp/./rename-test", "/tmp/rename-test/inner/moved"), ("/tmp/rename-test/", "/tmp/rename-test/inner/../moved")] {
+            assert_eq!(vfs::rename(old_path, new_path), Err(Errno::InvalidArgument));
+        }
+
+        vfs::rename("/tmp/rename-test/inner", "/tmp/rename-test/moved").unwrap();
+        assert!(vfs::stat("/tmp/rename-test/moved").is_ok());
+
+        vfs::rmdir("/tmp/rename-test/moved").unwrap();
+        vfs::rmdir("/tmp/rename-test").unwrap();
+    }
+}
@@ -0,0 +1,289 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeSet;
+use alloc::rc::Rc;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering::Relaxed;
+use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::structures::paging::page::PageRange;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::memory::{MemorySpace, PAGE_SIZE, physical};
+use crate::memory::r#virtual::{AddressSpace, PageTableWalker, PHYSICAL_MEMORY_OFFSET};
+use crate::process::process::kernel_process;
+use crate::process::thread::Thread;
+use crate::scheduler;
+use crate::sync::Mutex;
+
+/// Start of the region used for test mappings (far above the identity mapped physical memory).
+const TEST_REGION: u64 = 0x0000_1000_0000_0000;
+
+/// Threads mapping pages into the same address space at once (see `concurrent_mappings_share_page_tables()`).
+const MAPPING_THREADS: usize = 4;
+/// Pages mapped by all threads together (covered by four level 1 tables).
+const CONCURRENT_PAGES: u64 = 4 * 512;
+
+/// Kernel threads can not capture anything, so the address space and the index of each thread are passed via statics.
+static CONCURRENT_ADDRESS_SPACE: Mutex<Option<Arc<AddressSpace>>> = Mutex::new(None);
+static NEXT_MAPPING_THREAD: AtomicUsize = AtomicUsize::new(0);
+
+fn test_pages(count: u64) -> PageRange {
+    let start = Page::from_start_address(VirtAddr::new(TEST_REGION)).unwrap();
+    return PageRange { start, end: start + count };
+}
+
+kernel_test! {
+    fn address_space_map_translate_unmap() {
+        let address_space = AddressSpace::new(4);
+        let pages = test_pages(4);
+
+        address_space.map(pages, MemorySpace::User, PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE);
+        for page in pages {
+            let phys_addr = address_space.translate(page.start_address() + 0x123u64).expect("Mapped page is not translatable!");
+            assert_eq!(phys_addr.as_u64() % PAGE_SIZE as u64, 0x123);
+        }
+
+        address_space.unmap(pages);
+        for page in pages {
+            assert!(address_space.translate(page.start_address()).is_none());
+        }
+    }
+}
+
+kernel_test! {
+    fn address_space_with_five_levels() {
+        let free_before = physical::free_frame_count();
+
+        {
+            let address_space = AddressSpace::new(5);
+            let pages = test_pages(4);
+
+            address_space.map(pages, MemorySpace::User, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+            // Root table (level 5), one page table on each of the levels 4, 3, 2 and 1 and the 4 pages
+            assert_eq!(physical::free_frame_count(), free_before - 1 - 4 - 4);
+            for page in pages {
+                assert!(address_space.translate(page.start_address()).is_some());
+            }
+
+            address_space.unmap(pages);
+            assert!(address_space.translate(pages.start.start_address()).is_none());
+        }
+
+        assert_eq!(physical::free_frame_count(), free_before);
+    }
+}
+
+kernel_test! {
+    fn address_space_map_physical() {
+        let address_space = AddressSpace::new(4);
+        let frames = physical::alloc(2);
+        let pages = test_pages(2);
+
+        address_space.map_physical(frames, pages, MemorySpace::User, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+        assert_eq!(address_space.translate(pages.start.start_address()), Some(frames.start.start_address()));
+        assert_eq!(address_space.translate((pages.start + 1).start_address()), Some((frames.start + 1).start_address()));
+
+        // Unmapping frees the underlying frames
+        address_space.unmap(pages);
+        assert!(address_space.translate(pages.start.start_address()).is_none());
+    }
+}
+
+kernel_test! {
+    fn address_space_releases_frames() {
+        let free_before = physical::free_frame_count();
+
+        {
+            let address_space = AddressSpace::new(4);
+            let pages = test_pages(16);
+
+            address_space.map(pages, MemorySpace::User, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+            // 16 pages plus page tables on levels 3, 2 and 1 (the root table has been allocated before)
+            assert_eq!(physical::free_frame_count(), free_before - 1 - 16 - 3);
+
+            address_space.unmap(pages);
+        }
+
+        assert_eq!(physical::free_frame_count(), free_before);
+    }
+}
+
+kernel_test! {
+    fn page_table_walker_reaches_mapped_page() {
+        let address_space = AddressSpace::new(4);
+        let pages = test_pages(1);
+        address_space.map(pages, MemorySpace::User, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+
+        let walker = PageTableWalker::new(PHYSICAL_MEMORY_OFFSET);
+        let mut table = unsafe { walker.table(address_space.page_table_address()) };
+        assert_eq!(walker.physical_address(table), address_space.page_table_address());
+        for index in [pages.start.p4_index(), pages.start.p3_index(), pages.start.p2_index()] {
+            table = unsafe { walker.table(table[index].addr()) };
+        }
+
+        assert_eq!(Some(table[pages.start.p1_index()].addr()), address_space.translate(pages.start.start_address()));
+        address_space.unmap(pages);
+    }
+}
+
+kernel_test! {
+    fn address_space_map_iter() {
+        let address_space = AddressSpace::new(4);
+        let frames = physical::alloc(3);
+        // Discontiguous and crossing a level 1 table boundary (the last page is in the next 2 MiB area)
+        let pages = [test_pages(1).start + 511, test_pages(1).start + 510, test_pages(1).start + 512];
+        let mappings = frames.zip(pages.iter().copied()).map(|(frame, page)| (frame, page, PageTableFlags::PRESENT | PageTableFlags::WRITABLE));
+
+        address_space.map_iter(MemorySpace::User, mappings);
+        for (frame, page) in frames.zip(pages.iter()) {
+            assert_eq!(address_space.translate(page.start_address()), Some(frame.start_address()));
+        }
+
+        address_space.unmap(PageRange { start: test_pages(1).start + 510, end: test_pages(1).start + 513 });
+    }
+}
+
+kernel_test! {
+    fn concurrent_mappings_share_page_tables() {
+        let address_space = Arc::new(AddressSpace::new(4));
+        CONCURRENT_ADDRESS_SPACE.lock().replace(Arc::clone(&address_space));
+        NEXT_MAPPING_THREAD.store(0, Relaxed);
+
+        // Each thread maps every fourth page on its own and yields in between, so that the threads race for installing the same page tables
+        let threads = (0..MAPPING_THREADS).map(|_| Thread::new_kernel_thread(Box::new(|| {
+            let address_space = CONCURRENT_ADDRESS_SPACE.lock().clone().unwrap();
+            let index = NEXT_MAPPING_THREAD.fetch_add(1, Relaxed);
+            for page in test_pages(CONCURRENT_PAGES).skip(index).step_by(MAPPING_THREADS) {
+                address_space.map(PageRange { start: page, end: page + 1 }, MemorySpace::User, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+                scheduler().switch_thread();
+            }
+        }))).collect::<Vec<Rc<Thread>>>();
+
+        // The stacks of the threads are allocated already and only freed, when the threads are dropped
+        let free_before = physical::free_frame_count();
+        for thread in threads.iter() {
+            scheduler().ready(Rc::clone(thread));
+        }
+        for thread in threads.iter() {
+            thread.join();
+        }
+
+        // Tables on levels 3 and 2, four level 1 tables and the pages (tables installed by multiple threads have been freed again)
+        assert_eq!(physical::free_frame_count(), free_before - 2 - 4 - CONCURRENT_PAGES as usize);
+        let frames = test_pages(CONCURRENT_PAGES)
+            .map(|page| address_space.translate(page.start_address()).expect("Concurrently mapped page is not translatable!"))
+            .collect::<BTreeSet<PhysAddr>>();
+        assert_eq!(frames.len(), CONCURRENT_PAGES as usize);
+
+        CONCURRENT_ADDRESS_SPACE.lock().take();
+        address_space.unmap(test_pages(CONCURRENT_PAGES));
+    }
+}
+
+kernel_test! {
+    fn mapping_a_page_again_releases_its_frame() {
+        let free_before = physical::free_frame_count();
+
+        {
+            let address_space = AddressSpace::new(4);
+            let pages = test_pages(1);
+            address_space.map(pages, MemorySpace::User, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+
+            let frames = physical::alloc(1);
+            address_space.map_iter(MemorySpace::User, frames.map(|frame| (frame, pages.start, PageTableFlags::PRESENT | PageTableFlags::WRITABLE)));
+            assert_eq!(address_space.translate(pages.start.start_address()), Some(frames.start.start_address()));
+
+            address_space.unmap(pages);
+        }
+
+        // The frame mapped first has been released, when the page was mapped again
+        assert_eq!(physical::free_frame_count(), free_before);
+    }
+}
+
+kernel_test! {
+    fn unmap_skips_missing_page_tables() {
+        let free_before = physical::free_frame_count();
+
+        {
+            // Two pages 1 GiB apart (so in different level 2 tables), unmapped in one call
+            let address_space = AddressSpace::new(4);
+            let first = test_pages(1);
+            let second = PageRange { start: first.start + (1 << 18), end: first.start + (1 << 18) + 1 };
+            address_space.map(first, MemorySpace::User, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+            address_space.map(second, MemorySpace::User, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+
+            address_space.unmap(PageRange { start: first.start, end: second.end });
+            assert!(address_space.translate(first.start.start_address()).is_none());
+            assert!(address_space.translate(second.start.start_address()).is_none());
+
+            // Only the root table is left
+            assert_eq!(physical::free_frame_count(), free_before - 1);
+        }
+
+        assert_eq!(physical::free_frame_count(), free_before);
+    }
+}
+
+kernel_test! {
+    fn dropped_address_space_releases_user_frames() {
+        let free_before = physical::free_frame_count();
+        let device_frames = physical::alloc(2);
+
+        {
+            // Without unmapping anything, as on process exit
+            let address_space = AddressSpace::from_other(&kernel_process().unwrap().address_space());
+            address_space.map(test_pages(16), MemorySpace::User, PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE);
+            let device_pages = PageRange { start: test_pages(16).end, end: test_pages(18).end };
+            address_space.map_physical(device_frames, device_pages, MemorySpace::Device, PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE);
+        }
+
+        // Device memory is left alone, while user memory and all page tables (including the copied kernel tables) are freed
+        assert_eq!(physical::free_frame_count(), free_before - 2);
+        unsafe { physical::free(device_frames); }
+        assert_eq!(physical::free_frame_count(), free_before);
+    }
+}
+
+kernel_test! {
+    fn physical_alloc_free_accounting() {
+        let free_before = physical::free_frame_count();
+
+        let frames = physical::alloc(8);
+        assert_eq!(frames.end - frames.start, 8);
+        assert_eq!(physical::free_frame_count(), free_before - 8);
+
+        let single = physical::alloc(1);
+        assert_eq!(physical::free_frame_count(), free_before - 9);
+
+        unsafe {
+            physical::free(frames);
+            physical::free(single);
+        }
+
+        assert_eq!(physical::free_frame_count(), free_before);
+    }
+}
+
+kernel_test! {
+    fn shared_frames_are_freed_by_last_address_space() {
+        let free_before = physical::free_frame_count();
+        let frames = physical::alloc(1);
+        physical::share(frames.start);
+
+        {
+            let first = AddressSpace::new(4);
+            let second = AddressSpace::new(4);
+            first.map_physical(frames, test_pages(1), MemorySpace::User, PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE);
+            second.map_physical(frames, test_pages(1), MemorySpace::User, PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE);
+
+            // The frame is still mapped by the second address space
+            first.unmap(test_pages(1));
+            assert_eq!(second.translate(test_pages(1).start.start_address()), Some(frames.start.start_address()));
+
+            second.unmap(test_pages(1));
+        }
+
+        assert_eq!(physical::free_frame_count(), free_before);
+    }
+}
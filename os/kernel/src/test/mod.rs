@@ -0,0 +1,100 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::mem::size_of;
+use core::ptr;
+use core::slice;
+use core::sync::atomic::AtomicPtr;
+use core::sync::atomic::Ordering::Relaxed;
+use crate::debug::SerialWriter;
+use crate::device::qemu_cfg;
+use crate::device::qemu_cfg::ExitCode;
+
+/// Register a function as kernel test. The test fails, if it panics.
+/// All registered tests are collected in the '.kernel_tests' section and executed by `run()`.
+macro_rules! kernel_test {
+    ($(#[$meta:meta])* fn $name:ident() $body:block) => {
+        $(#[$meta])* fn $name() $body
+
+        const _: () = {
+            #[used]
+            #[link_section = ".kernel_tests"]
+            static TEST_CASE: $crate::test::TestCase = $crate::test::TestCase {
+                name: concat!(module_path!(), "::", stringify!($name)),
+                func: $name
+            };
+        };
+    };
+}
+
+mod block;
+mod device;
+mod fs;
+mod memory;
+mod net;
+mod process;
+mod random;
+
+const FILTER_FILE: &str = "opt/hhutosr/tests";
+
+pub struct TestCase {
+    pub name: &'static str,
+    pub func: fn(),
+}
+
+/// Name of the currently running test, so that the panic handler can report it.
+static CURRENT_TEST: AtomicPtr<TestCase> = AtomicPtr::new(ptr::null_mut());
+
+extern "C" {
+    static ___KERNEL_TESTS_START__: u8;
+    static ___KERNEL_TESTS_END__: u8;
+}
+
+fn test_cases() -> &'static [TestCase] {
+    unsafe {
+        let start = ptr::from_ref(&___KERNEL_TESTS_START__);
+        let end = ptr::from_ref(&___KERNEL_TESTS_END__);
+        let count = (end as usize - start as usize) / size_of::<TestCase>();
+
+        return slice::from_raw_parts(start as *const TestCase, count);
+    }
+}
+
+/// Run all registered tests, report the results over the serial port and exit QEMU.
+/// Tests run in a kernel thread after the scheduler has been started, so they may start and join threads of their own.
+/// A failing test panics, so the run is aborted at the first failure (see `report_failure()`).
+/// Tests can be selected by passing a part of their name via fw_cfg (e.g. '-fw_cfg name=opt/hhutosr/tests,string=fs::').
+pub fn run() {
+    let filter = qemu_cfg::read_file(FILTER_FILE).map(|filter| String::from_utf8_lossy(&filter).trim().to_string());
+    let tests: Vec<&TestCase> = test_cases().iter()
+        .filter(|test| filter.as_ref().map_or(true, |filter| test.name.contains(filter.as_str())))
+        .collect();
+    let _ = writeln!(SerialWriter, "Running [{}] kernel tests", tests.len());
+
+    for &test in &tests {
+        let _ = write!(SerialWriter, "{} ... ", test.name);
+        CURRENT_TEST.store(ptr::from_ref(test).cast_mut(), Relaxed);
+
+        (test.func)();
+
+        CURRENT_TEST.store(ptr::null_mut(), Relaxed);
+        let _ = writeln!(SerialWriter, "[ok]");
+    }
+
+    let _ = writeln!(SerialWriter, "Test result: [ok] ([{}] passed)", tests.len());
+    qemu_cfg::debug_exit(ExitCode::Success);
+
+    loop {}
+}
+
+/// Called by the panic handler. Marks the currently running test (if any) as failed and returns `true` in that case.
+pub fn report_failure() -> bool {
+    let test = CURRENT_TEST.load(Relaxed);
+    if let Some(test) = unsafe { test.as_ref() } {
+        let _ = writeln!(SerialWriter, "[failed]");
+        let _ = writeln!(SerialWriter, "Test result: [failed] (in [{}])", test.name);
+        return true;
+    }
+
+    return false;
+}
@@ -0,0 +1,444 @@
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Once;
+use syscall::ioctl::WindowSize;
+use syscall::error::Errno;
+use syscall::file::{OpenFlags, PollEvents};
+use crate::fs::poll::Pollable;
+use crate::fs::vfs;
+use crate::{net, timer};
+use crate::net::inet::{arp, checksum, tcp};
+use crate::net::inet::dhcp::{Message, OPERATION_REPLY, OPERATION_REQUEST, TYPE_ACK, TYPE_REQUEST};
+use crate::net::inet::ipv4::{pseudo_header_sum, Header, Reassembler, PROTOCOL_ICMP, PROTOCOL_TCP};
+use crate::net::inet::route;
+use crate::net::inet::route::Route;
+use crate::net::inet::tcp::TcpSocket;
+use crate::net::socket::{Socket, SocketAddress};
+use crate::net::telnet::Decoder;
+use crate::net::unix::UnixSocket;
+use crate::net::{Ipv4Address, Ipv4Cidr, MacAddress, NetworkDevice, Result};
+use crate::sync::Mutex;
+
+// TCP connections are tested against a peer on a documentation network (RFC 5737), which is simulated by the test
+const TCP_LOCAL_ADDRESS: Ipv4Address = Ipv4Address::new(192, 0, 2, 1);
+const TCP_PEER: SocketAddress = SocketAddress::new(Ipv4Address::new(192, 0, 2, 2), 40000);
+const TCP_PEER_MAC: MacAddress = MacAddress([0x02, 0, 0, 0, 0, 2]);
+/// Initial sequence number of the peer.
+const TCP_PEER_SEQUENCE: u32 = 1000;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_ACK: u8 = 0x10;
+
+static TCP_TEST_DEVICE: Once<Arc<RecordingDevice>> = Once::new();
+
+/// Network device, which records all transmitted frames instead of sending them.
+#[derive(Default)]
+struct RecordingDevice {
+    frames: Mutex<Vec<Vec<u8>>>,
+}
+
+/// TCP segment, which has been sent to the simulated peer.
+struct SentSegment {
+    sequence: u32,
+    acknowledgment: u32,
+    flags: u8,
+    payload: Vec<u8>,
+}
+
+impl NetworkDevice for RecordingDevice {
+    fn mac_address(&self) -> MacAddress {
+        return MacAddress([0x02, 0, 0, 0, 0, 1]);
+    }
+
+    fn mtu(&self) -> usize {
+        return 1500;
+    }
+
+    fn transmit(&self, frame: &[u8]) -> Result<()> {
+        self.frames.lock().push(frame.to_vec());
+        return Ok(());
+    }
+}
+
+impl RecordingDevice {
+    /// Remove all recorded frames and return the TCP segments among them.
+    fn sent_segments(&self) -> Vec<SentSegment> {
+        return self.frames.lock().drain(..)
+            .filter(|frame| frame.len() >= 34 && frame[12..14] == [0x08, 0x00] && frame[23] == PROTOCOL_TCP)
+            .map(|frame| {
+                let segment = &frame[14 + (frame[14] & 0x0f) as usize * 4..];
+                SentSegment {
+                    sequence: u32::from_be_bytes(segment[4..8].try_into().unwrap()),
+                    acknowledgment: u32::from_be_bytes(segment[8..12].try_into().unwrap()),
+                    flags: segment[13],
+                    payload: segment[(segment[12] >> 4) as usize * 4..].to_vec(),
+                }
+            })
+            .collect();
+    }
+}
+
+/// Register the interface of the simulated peer's network (once for all tests) and return its device.
+fn tcp_test_device() -> Arc<RecordingDevice> {
+    return Arc::clone(TCP_TEST_DEVICE.call_once(|| {
+        let device = Arc::new(RecordingDevice::default());
+        let interface = net::register("rec", Arc::clone(&device) as Arc<dyn NetworkDevice>);
+        route::configure(&interface, Ipv4Cidr::new(TCP_LOCAL_ADDRESS, 24));
+
+        // An ARP request from the peer makes its MAC address known, so that segments are sent without waiting for a reply
+        let mut request = vec![0, 1, 0x08, 0x00, 6, 4, 0, 1];
+        request.extend_from_slice(&TCP_PEER_MAC.0);
+        request.extend_from_slice(&TCP_PEER.address.0);
+        request.extend_from_slice(&[0; 6]);
+        request.extend_from_slice(&TCP_LOCAL_ADDRESS.0);
+        arp::receive(&interface, &request);
+
+        device
+    }))
+}
+
+/// Pass a segment from the simulated peer to the local `port`.
+fn receive_tcp_segment(port: u16, sequence: u32, acknowledgment: u32, flags: u8, window: u16, payload: &[u8]) {
+    let mut segment = Vec::new();
+    segment.extend_from_slice(&TCP_PEER.port.to_be_bytes());
+    segment.extend_from_slice(&port.to_be_bytes());
+    segment.extend_from_slice(&sequence.to_be_bytes());
+    segment.extend_from_slice(&acknowledgment.to_be_bytes());
+    segment.extend_from_slice(&[0x50, flags]);
+    segment.extend_from_slice(&window.to_be_bytes());
+    segment.extend_from_slice(&[0, 0, 0, 0]);
+    segment.extend_from_slice(payload);
+
+    let sum = checksum(&segment, pseudo_header_sum(TCP_PEER.address, TCP_LOCAL_ADDRESS, PROTOCOL_TCP, segment.len()));
+    segment[16..18].copy_from_slice(&sum.to_be_bytes());
+
+    let header = Header { source: TCP_PEER.address, destination: TCP_LOCAL_ADDRESS, protocol: PROTOCOL_TCP, identification: 0, fragment_offset: 0, more_fragments: false };
+    tcp::receive(&header, &segment);
+}
+
+/// Complete a handshake between a listening socket on `port` and the simulated peer, which announces `window`.
+/// Returns the accepted connection and the next sequence number of the local side.
+fn accept_tcp_connection(device: &RecordingDevice, port: u16, window: u16) -> (Arc<dyn Socket>, u32) {
+    let listener = TcpSocket::new();
+    listener.bind(SocketAddress::new(TCP_LOCAL_ADDRESS, port)).unwrap();
+    listener.listen(1).unwrap();
+
+    receive_tcp_segment(port, TCP_PEER_SEQUENCE, 0, TCP_SYN, window, &[]);
+    let syn_ack = device.sent_segments().pop().unwrap();
+    assert_eq!(syn_ack.flags, TCP_SYN | TCP_ACK);
+    assert_eq!(syn_ack.acknowledgment, TCP_PEER_SEQUENCE + 1);
+
+    let local_sequence = syn_ack.sequence.wrapping_add(1);
+    receive_tcp_segment(port, TCP_PEER_SEQUENCE + 1, local_sequence, TCP_ACK, window, &[]);
+    let (connection, remote) = listener.accept().unwrap();
+    assert!(remote == TCP_PEER);
+
+    return (connection, local_sequence);
+}
+
+fn fragment_header(fragment_offset: usize, more_fragments: bool) -> Header {
+    return Header {
+        source: Ipv4Address::new(10, 0, 2, 2),
+        destination: Ipv4Address::new(10, 0, 2, 15),
+        protocol: PROTOCOL_ICMP,
+        identification: 42,
+        fragment_offset,
+        more_fragments,
+    };
+}
+
+kernel_test! {
+    fn internet_checksum_matches_rfc1071() {
+        // Example from RFC 1071, section 3 (the sum is 0xddf2)
+        let data = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+        assert_eq!(checksum(&data, 0), !0xddf2);
+
+        // Odd lengths are padded with zero and data including its checksum sums up to zero
+        assert_eq!(checksum(&[0x12, 0x34, 0x56], 0), !0x6834);
+        let mut packet = [0x45, 0x00, 0x00, 0x1c, 0x00, 0x01, 0x00, 0x00, 0x40, 0x01, 0x00, 0x00, 10, 0, 2, 15, 10, 0, 2, 2];
+        let sum = checksum(&packet, 0);
+        packet[10..12].copy_from_slice(&sum.to_be_bytes());
+        assert_eq!(checksum(&packet, 0), 0);
+    }
+}
+
+kernel_test! {
+    fn ipv4_reassembles_fragments_in_any_order() {
+        let payload: [u8; 40] = core::array::from_fn(|i| i as u8);
+        let mut reassembler = Reassembler::new();
+
+        // The last fragment arrives first and the second fragment is duplicated
+        assert_eq!(reassembler.add(&fragment_header(32, false), &payload[32..], 0), None);
+        assert_eq!(reassembler.add(&fragment_header(16, true), &payload[16..32], 0), None);
+        assert_eq!(reassembler.add(&fragment_header(16, true), &payload[16..32], 0), None);
+        assert_eq!(reassembler.incomplete_count(), 1);
+        assert_eq!(reassembler.add(&fragment_header(0, true), &payload[..16], 0).as_deref(), Some(&payload[..]));
+        assert_eq!(reassembler.incomplete_count(), 0);
+    }
+}
+
+kernel_test! {
+    fn ipv4_drops_incomplete_packets_after_timeout() {
+        let mut reassembler = Reassembler::new();
+
+        // Fragments (except for the last one) must be a multiple of 8 bytes long
+        assert_eq!(reassembler.add(&fragment_header(0, true), &[0; 12], 0), None);
+        assert_eq!(reassembler.incomplete_count(), 0);
+
+        assert_eq!(reassembler.add(&fragment_header(0, true), &[0; 8], 0), None);
+        reassembler.expire(10000);
+        assert_eq!(reassembler.incomplete_count(), 1);
+        reassembler.expire(30000);
+        assert_eq!(reassembler.incomplete_count(), 0);
+
+        // The missing first fragment cannot complete the expired packet anymore
+        assert_eq!(reassembler.add(&fragment_header(8, false), &[0; 8], 30000), None);
+    }
+}
+
+kernel_test! {
+    fn ipv4_cidr_computes_network_and_broadcast() {
+        let cidr = Ipv4Cidr::new(Ipv4Address::new(10, 0, 2, 15), 24).unwrap();
+        assert_eq!(cidr.netmask(), Ipv4Address::new(255, 255, 255, 0));
+        assert_eq!(cidr.network(), Ipv4Address::new(10, 0, 2, 0));
+        assert_eq!(cidr.broadcast(), Ipv4Address::new(10, 0, 2, 255));
+        assert!(cidr.contains(Ipv4Address::new(10, 0, 2, 2)));
+        assert!(!cidr.contains(Ipv4Address::new(10, 0, 3, 2)));
+
+        let default = Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 0).unwrap();
+        assert!(default.contains(Ipv4Address::new(192, 168, 1, 1)));
+        assert!(Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 33).is_none());
+    }
+}
+
+kernel_test! {
+    fn tcp_checksum_includes_pseudo_header() {
+        let source = Ipv4Address::new(10, 0, 2, 15);
+        let destination = Ipv4Address::new(10, 0, 2, 2);
+        assert_eq!(pseudo_header_sum(source, destination, PROTOCOL_TCP, 20), 0x0a00 + 0x020f + 0x0a00 + 0x0202 + 6 + 20);
+
+        // SYN from port 49152 to port 80 (the checksum is only valid together with the addresses, it has been computed for)
+        let mut segment = [0xc0, 0x00, 0x00, 0x50, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x02, 0x40, 0x00, 0, 0, 0, 0];
+        let sum = checksum(&segment, pseudo_header_sum(source, destination, PROTOCOL_TCP, segment.len()));
+        segment[16..18].copy_from_slice(&sum.to_be_bytes());
+        assert_eq!(checksum(&segment, pseudo_header_sum(source, destination, PROTOCOL_TCP, segment.len())), 0);
+        assert_ne!(checksum(&segment, pseudo_header_sum(destination, Ipv4Address::new(10, 0, 2, 3), PROTOCOL_TCP, segment.len())), 0);
+    }
+}
+
+kernel_test! {
+    fn dhcp_messages_survive_encoding() {
+        let request = Message {
+            operation: OPERATION_REQUEST,
+            transaction: 0x12345678,
+            mac_address: MacAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]),
+            typ: TYPE_REQUEST,
+            server: Some(Ipv4Address::new(10, 0, 2, 2)),
+            requested_address: Some(Ipv4Address::new(10, 0, 2, 15)),
+            ..Message::default()
+        };
+
+        // Requests are padded to the minimum size of BOOTP messages
+        let data = request.to_bytes();
+        assert_eq!(data.len(), 300);
+        assert_eq!(Message::parse(&data), Some(request));
+
+        let ack = Message {
+            operation: OPERATION_REPLY,
+            your_address: Ipv4Address::new(10, 0, 2, 15),
+            typ: TYPE_ACK,
+            subnet_mask: Some(Ipv4Address::new(255, 255, 255, 0)),
+            router: Some(Ipv4Address::new(10, 0, 2, 2)),
+            dns_servers: vec![Ipv4Address::new(10, 0, 2, 3)],
+            lease_time_s: Some(86400),
+            ..Message::default()
+        };
+
+        assert_eq!(Message::parse(&ack.to_bytes()), Some(ack));
+
+        // Truncated messages and messages without type (plain BOOTP) are rejected
+        assert!(Message::parse(&data[..200]).is_none());
+        assert!(Message::parse(&Message { typ: 0, ..Message::default() }.to_bytes()).is_none());
+    }
+}
+
+kernel_test! {
+    fn routes_prefer_the_longest_prefix() {
+        // Documentation networks (RFC 5737) are routed via the loopback interface, so that no real route is affected
+        let loopback = net::interface("lo").unwrap();
+        let network = Ipv4Cidr::new(Ipv4Address::new(198, 51, 100, 0), 24).unwrap();
+        let subnet = Ipv4Cidr::new(Ipv4Address::new(198, 51, 100, 128), 25).unwrap();
+        let gateway = Ipv4Address::new(127, 0, 0, 2);
+
+        route::add(Route { destination: network, gateway: None, interface: loopback.clone() }).unwrap();
+        route::add(Route { destination: subnet, gateway: Some(gateway), interface: loopback.clone() }).unwrap();
+
+        let (_, next_hop) = route::lookup(Ipv4Address::new(198, 51, 100, 200)).unwrap();
+        assert_eq!(next_hop, gateway);
+        let (interface, next_hop) = route::lookup(Ipv4Address::new(198, 51, 100, 1)).unwrap();
+        assert_eq!(next_hop, Ipv4Address::new(198, 51, 100, 1));
+        assert_eq!(interface.name(), "lo");
+
+        // Routes are identified by their network and gateways must be on the network of the interface
+        let host_bits_set = Ipv4Cidr::new(Ipv4Address::new(198, 51, 100, 7), 24).unwrap();
+        assert_eq!(route::add(Route { destination: host_bits_set, gateway: None, interface: loopback.clone() }).err(), Some(Errno::AlreadyExists));
+        let unreachable = Route { destination: Ipv4Cidr::new(Ipv4Address::new(203, 0, 113, 0), 24).unwrap(), gateway: Some(Ipv4Address::new(10, 9, 9, 9)), interface: loopback };
+        assert_eq!(route::add(unreachable).err(), Some(Errno::NetworkUnreachable));
+
+        route::remove(subnet).unwrap();
+        route::remove(host_bits_set).unwrap();
+        assert_eq!(route::remove(network).err(), Some(Errno::NotFound));
+    }
+}
+
+kernel_test! {
+    fn telnet_commands_are_stripped_from_input() {
+        let mut decoder = Decoder::new();
+
+        // 'ls' and Enter, interleaved with a request for the terminal type (24), which is refused
+        let decoded = decoder.decode(&[b'l', 255, 253, 24, b's', b'\r', 0]);
+        assert_eq!(decoded.input, b"ls\n");
+        assert_eq!(decoded.replies, [255, 252, 24]);
+        assert!(decoded.window_size.is_none());
+
+        // Window size report (split across two calls), an escaped 0xff and backspace (sent as delete)
+        let decoded = decoder.decode(&[255, 250, 31, 0, 120]);
+        assert!(decoded.input.is_empty());
+        let decoded = decoder.decode(&[0, 40, 255, 240, 255, 255, 0x7f, b'\r', b'\n']);
+        assert_eq!(decoded.input, [0xff, 0x08, b'\n']);
+        assert_eq!(decoded.window_size, Some(WindowSize { columns: 120, rows: 40 }));
+        assert!(decoded.replies.is_empty());
+    }
+}
+
+kernel_test! {
+    fn unix_sockets_connect_via_socket_nodes() {
+        let path = "/tmp/unix-socket-test";
+        let server = UnixSocket::new();
+        server.bind(path).unwrap();
+        assert_eq!(UnixSocket::new().bind(path), Err(Errno::AddressInUse));
+        assert_eq!(UnixSocket::new().connect(path), Err(Errno::ConnectionRefused));
+        assert_eq!(vfs::open(path, OpenFlags::READ).err(), Some(Errno::NoDevice));
+
+        // Connecting does not wait for the connection to be accepted, but the backlog is limited
+        server.listen(1).unwrap();
+        let client = UnixSocket::new();
+        client.connect(path).unwrap();
+        assert_eq!(UnixSocket::new().connect(path), Err(Errno::ConnectionRefused));
+        assert!(server.poll(PollEvents::READABLE, None).contains(PollEvents::READABLE));
+
+        let (connection, _) = server.accept().unwrap();
+        let mut buffer = [0u8; 8];
+        client.send(b"ping").unwrap();
+        assert_eq!(connection.receive(&mut buffer), Ok(4));
+        assert_eq!(&buffer[..4], b"ping");
+        connection.send(b"pong").unwrap();
+        assert_eq!(client.receive(&mut buffer), Ok(4));
+        assert_eq!(&buffer[..4], b"pong");
+
+        // Shutting down ends the peer's stream, closing the peer breaks it
+        client.shutdown().unwrap();
+        assert_eq!(connection.receive(&mut buffer), Ok(0));
+        drop(client);
+        assert_eq!(connection.send(b"data"), Err(Errno::BrokenPipe));
+        drop(server);
+        vfs::unlink(path).unwrap();
+    }
+}
+
+kernel_test! {
+    fn tcp_connection_passes_through_all_states() {
+        let device = tcp_test_device();
+        let (connection, sequence) = accept_tcp_connection(&device, 7001, 1024);
+        let peer_sequence = TCP_PEER_SEQUENCE + 1;
+
+        // Received data is acknowledged and sent data carries the next sequence number
+        let mut buffer = [0u8; 8];
+        receive_tcp_segment(7001, peer_sequence, sequence, TCP_ACK, 1024, b"ping");
+        assert_eq!(connection.receive(&mut buffer), Ok(4));
+        assert_eq!(&buffer[..4], b"ping");
+        assert_eq!(device.sent_segments().last().unwrap().acknowledgment, peer_sequence + 4);
+
+        assert_eq!(connection.send(b"pong"), Ok(4));
+        let sent = device.sent_segments().pop().unwrap();
+        assert_eq!((sent.sequence, sent.payload.as_slice()), (sequence, &b"pong"[..]));
+        receive_tcp_segment(7001, peer_sequence + 4, sequence.wrapping_add(4), TCP_ACK, 1024, &[]);
+
+        // Active close: FIN-WAIT-1, FIN-WAIT-2 (after the FIN has been acknowledged), TIME-WAIT (after the peer's FIN)
+        connection.shutdown().unwrap();
+        let fin = device.sent_segments().pop().unwrap();
+        assert_eq!((fin.flags & TCP_FIN, fin.sequence), (TCP_FIN, sequence.wrapping_add(4)));
+        assert!(!connection.poll(PollEvents::WRITABLE, None).contains(PollEvents::WRITABLE));
+        receive_tcp_segment(7001, peer_sequence + 4, sequence.wrapping_add(5), TCP_ACK, 1024, &[]);
+        receive_tcp_segment(7001, peer_sequence + 4, sequence.wrapping_add(5), TCP_FIN | TCP_ACK, 1024, &[]);
+        assert_eq!(device.sent_segments().pop().unwrap().acknowledgment, peer_sequence + 5);
+        assert_eq!(connection.receive(&mut buffer), Ok(0));
+
+        // The connection is closed, once TIME-WAIT has expired
+        assert!(!connection.poll(PollEvents::empty(), None).contains(PollEvents::HANG_UP));
+        tcp::expire(timer().read().systime_ms() + 120000);
+        assert!(connection.poll(PollEvents::empty(), None).contains(PollEvents::HANG_UP));
+    }
+}
+
+kernel_test! {
+    fn tcp_resets_connections_to_closed_ports() {
+        let device = tcp_test_device();
+        receive_tcp_segment(7002, TCP_PEER_SEQUENCE, 0, TCP_SYN, 1024, &[]);
+
+        let reset = device.sent_segments().pop().unwrap();
+        assert_eq!(reset.flags, TCP_RST | TCP_ACK);
+        assert_eq!(reset.acknowledgment, TCP_PEER_SEQUENCE + 1);
+    }
+}
+
+kernel_test! {
+    fn tcp_aborts_after_unacknowledged_retransmissions() {
+        let device = tcp_test_device();
+        let (connection, sequence) = accept_tcp_connection(&device, 7003, 1024);
+        connection.send(b"data").unwrap();
+        device.sent_segments();
+
+        // Each timeout (at most 60 seconds) retransmits the data, until the connection is given up
+        let mut now = timer().read().systime_ms();
+        for _ in 0..8 {
+            now += 120000;
+            tcp::expire(now);
+            let sent = device.sent_segments().pop().unwrap();
+            assert_eq!((sent.sequence, sent.payload.as_slice()), (sequence, &b"data"[..]));
+        }
+
+        tcp::expire(now + 120000);
+        assert_eq!(device.sent_segments().pop().unwrap().flags & TCP_RST, TCP_RST);
+        assert_eq!(connection.receive(&mut [0u8; 4]), Err(Errno::TimedOut));
+    }
+}
+
+kernel_test! {
+    fn tcp_probes_closed_windows_without_aborting() {
+        let device = tcp_test_device();
+        let (connection, sequence) = accept_tcp_connection(&device, 7004, 0);
+
+        // Data is not sent into a closed window, but probes (with an old sequence number) are sent indefinitely
+        assert_eq!(connection.send(b"data"), Ok(4));
+        assert!(device.sent_segments().is_empty());
+
+        let mut now = timer().read().systime_ms();
+        for _ in 0..16 {
+            now += 120000;
+            tcp::expire(now);
+            let probe = device.sent_segments().pop().unwrap();
+            assert_eq!((probe.sequence, probe.flags), (sequence.wrapping_sub(1), TCP_ACK));
+            assert!(probe.payload.is_empty());
+        }
+        assert!(!connection.poll(PollEvents::empty(), None).intersects(PollEvents::ERROR | PollEvents::HANG_UP));
+
+        // The data is sent, as soon as the peer announces an open window
+        receive_tcp_segment(7004, TCP_PEER_SEQUENCE + 1, sequence, TCP_ACK, 1024, &[]);
+        let sent = device.sent_segments().pop().unwrap();
+        assert_eq!((sent.sequence, sent.payload.as_slice()), (sequence, &b"data"[..]));
+    }
+}
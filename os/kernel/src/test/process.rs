@@ -0,0 +1,329 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use goblin::elf::Elf;
+use goblin::elf::header::ET_CORE;
+use goblin::elf::note::{Note, NT_PRPSINFO, NT_PRSTATUS};
+use goblin::elf::program_header::{PT_LOAD, PT_NOTE};
+use syscall::error::Errno;
+use syscall::signal::{Signal, SignalDisposition};
+use x86_64::VirtAddr;
+use crate::memory::PAGE_SIZE;
+use crate::memory::r#virtual::{VirtualMemoryArea, VmaType};
+use crate::process::{core_dump, elf, loader, process, signal, thread};
+use crate::process::core_dump::Registers;
+use crate::process::elf::{Image, USER_SPACE_START};
+use crate::process::signal::SignalAction;
+use crate::process::thread::{STACK_CANARY, STACK_CANARY_WORDS};
+
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+/// Build a minimal executable with the given loadable segments (flags, file offset, address, size in the file and in memory).
+fn executable(entry: u64, segments: &[(u32, u64, u64, u64, u64)]) -> Vec<u8> {
+    let mut elf = Vec::new();
+    elf.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    elf.extend_from_slice(&2u16.to_le_bytes()); // Executable
+    elf.extend_from_slice(&62u16.to_le_bytes()); // x86_64
+    elf.extend_from_slice(&1u32.to_le_bytes());
+    elf.extend_from_slice(&entry.to_le_bytes());
+    elf.extend_from_slice(&64u64.to_le_bytes()); // Program headers follow the file header
+    elf.extend_from_slice(&0u64.to_le_bytes());
+    elf.extend_from_slice(&0u32.to_le_bytes());
+    for value in [64u16, 56, segments.len() as u16, 64, 0, 0] {
+        elf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    for (flags, offset, address, file_size, memory_size) in segments {
+        elf.extend_from_slice(&1u32.to_le_bytes()); // PT_LOAD
+        elf.extend_from_slice(&flags.to_le_bytes());
+        for value in [*offset, *address, *address, *file_size, *memory_size, 0x1000] {
+            elf.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    elf.resize(0x2000, 0x90);
+    return elf;
+}
+
+/// Like `executable()`, but position-independent (addresses are relative to the load base).
+fn position_independent(entry: u64, segments: &[(u32, u64, u64, u64, u64)]) -> Vec<u8> {
+    let mut elf = executable(entry, segments);
+    elf[16..18].copy_from_slice(&3u16.to_le_bytes()); // Shared object
+
+    return elf;
+}
+
+/// Build a minimal shared library with a dynamic section (addresses are equal to file offsets). It defines the symbols
+/// `absolute` (local, absolute 0x1234), `value` (data at 0x1000) and `function` (code at 0x10) and needs `external`.
+/// Its global offset table at 0x1008 refers to `value`, `absolute`, `function + 4` and `external` (via the PLT).
+fn shared_object() -> Vec<u8> {
+    let mut elf = position_independent(0, &[(PF_R | PF_X, 0, 0, 0x1000, 0x1000), (PF_R | PF_W, 0x1000, 0x1000, 0x28, 0x28)]);
+    let mut write = |offset: usize, data: &[u8]| elf[offset..offset + data.len()].copy_from_slice(data);
+
+    // Dynamic segment as third program header
+    write(56, &3u16.to_le_bytes());
+    write(176, &2u32.to_le_bytes()); // PT_DYNAMIC
+    write(180, &(PF_R | PF_W).to_le_bytes());
+    for (index, value) in [0x100u64, 0x100, 0x100, 0xc0, 0xc0, 8].iter().enumerate() {
+        write(184 + index * 8, &value.to_le_bytes());
+    }
+
+    // Dynamic entries (hash table, strings, symbols, relocations and PLT relocations)
+    let dynamic = [(4u64, 0x200u64), (5, 0x300), (6, 0x240), (10, 34), (11, 24), (7, 0x400), (8, 72), (9, 24), (23, 0x480), (2, 24), (20, 7), (0, 0)];
+    for (index, (tag, value)) in dynamic.iter().enumerate() {
+        write(0x100 + index * 16, &tag.to_le_bytes());
+        write(0x108 + index * 16, &value.to_le_bytes());
+    }
+
+    // Hash table with one bucket (only the number of symbols is used)
+    write(0x200, &[1, 0, 0, 0, 5, 0, 0, 0]);
+    write(0x208, &[0; 24]);
+
+    // Symbols (name, info, section, value) and their names
+    let symbols = [(0u32, 0u8, 0u16, 0u64), (16, 0x00, 0xfff1, 0x1234), (1, 0x11, 1, 0x1000), (7, 0x12, 1, 0x10), (25, 0x10, 0, 0)];
+    for (index, (name, info, section, value)) in symbols.iter().enumerate() {
+        let symbol = 0x240 + index * 24;
+        write(symbol, &name.to_le_bytes());
+        write(symbol + 4, &[*info, 0]);
+        write(symbol + 6, &section.to_le_bytes());
+        write(symbol + 8, &value.to_le_bytes());
+        write(symbol + 16, &0u64.to_le_bytes());
+    }
+    write(0x300, b"\0value\0function\0absolute\0external\0");
+
+    // Relocations (offset, symbol, type, addend): GLOB_DAT, GLOB_DAT, 64 and JUMP_SLOT
+    let relocations = [(0x1008u64, 2u64, 6u64, 0i64), (0x1010, 1, 6, 0), (0x1018, 3, 1, 4), (0x1020, 4, 7, 0)];
+    for (index, (offset, symbol, typ, addend)) in relocations.iter().enumerate() {
+        let relocation = if index < 3 { 0x400 + index * 24 } else { 0x480 };
+        write(relocation, &offset.to_le_bytes());
+        write(relocation + 8, &((symbol << 32) | typ).to_le_bytes());
+        write(relocation + 16, &addend.to_le_bytes());
+    }
+
+    return elf;
+}
+
+/// Build a minimal position-independent executable with a dynamic section, whose only relocations are relative ones:
+/// The data at 0x1000 refers to the code at 0x10 (the entry point) and the data at 0x1008 refers to the data at 0x1000.
+fn relocatable_executable() -> Vec<u8> {
+    let mut elf = position_independent(0x10, &[(PF_R | PF_X, 0, 0, 0x1000, 0x1000), (PF_R | PF_W, 0x1000, 0x1000, 0x10, 0x10)]);
+    let mut write = |offset: usize, data: &[u8]| elf[offset..offset + data.len()].copy_from_slice(data);
+
+    // Dynamic segment as third program header
+    write(56, &3u16.to_le_bytes());
+    write(176, &2u32.to_le_bytes()); // PT_DYNAMIC
+    write(180, &(PF_R | PF_W).to_le_bytes());
+    for (index, value) in [0x100u64, 0x100, 0x100, 0x90, 0x90, 8].iter().enumerate() {
+        write(184 + index * 8, &value.to_le_bytes());
+    }
+
+    // Dynamic entries (hash table, strings, symbols and relocations)
+    let dynamic = [(4u64, 0x200u64), (5, 0x300), (6, 0x240), (10, 1), (11, 24), (7, 0x400), (8, 48), (9, 24), (0, 0)];
+    for (index, (tag, value)) in dynamic.iter().enumerate() {
+        write(0x100 + index * 16, &tag.to_le_bytes());
+        write(0x108 + index * 16, &value.to_le_bytes());
+    }
+
+    // Hash table with one bucket, null symbol and empty string table
+    write(0x200, &[1, 0, 0, 0, 1, 0, 0, 0]);
+    write(0x208, &[0; 8]);
+    write(0x240, &[0; 24]);
+    write(0x300, b"\0");
+
+    // Relocations (offset, type, addend): RELATIVE, RELATIVE
+    for (index, (offset, typ, addend)) in [(0x1000u64, 8u64, 0x10i64), (0x1008, 8, 0x1000)].iter().enumerate() {
+        write(0x400 + index * 24, &offset.to_le_bytes());
+        write(0x408 + index * 24, &typ.to_le_bytes());
+        write(0x410 + index * 24, &addend.to_le_bytes());
+    }
+
+    return elf;
+}
+
+kernel_test! {
+    fn elf_loader_accepts_valid_executables() {
+        let code = (PF_R | PF_X, 0, USER_SPACE_START, 0x1000, 0x1000);
+        let data = (PF_R | PF_W, 0x1000, USER_SPACE_START + 0x1000, 0x800, 0x3000); // Including BSS
+        assert!(elf::parse(&executable(USER_SPACE_START + 0x100, &[code, data])).is_ok());
+
+        // Segments may share a page, as long as they do not overlap
+        let shared = (PF_R | PF_W, 0x800, USER_SPACE_START + 0x800, 0x10, 0x10);
+        assert!(elf::parse(&executable(USER_SPACE_START, &[(PF_R | PF_X, 0, USER_SPACE_START, 0x800, 0x800), shared])).is_ok());
+    }
+}
+
+kernel_test! {
+    fn elf_loader_rejects_malformed_executables() {
+        let code = (PF_R | PF_X, 0, USER_SPACE_START, 0x1000, 0x1000);
+        assert_eq!(elf::parse(&[0x7f, b'E', b'L', b'F']).err(), Some(Errno::ExecFormat));
+
+        // Entry point outside of the code or inside a segment, which is not executable
+        assert_eq!(elf::parse(&executable(USER_SPACE_START + 0x1000, &[code])).err(), Some(Errno::ExecFormat));
+        let data = (PF_R | PF_W, 0x1000, USER_SPACE_START + 0x1000, 0x1000, 0x1000);
+        assert_eq!(elf::parse(&executable(USER_SPACE_START + 0x1000, &[code, data])).err(), Some(Errno::ExecFormat));
+
+        // Segments outside of the file, in kernel space or overlapping each other
+        let truncated = (PF_R | PF_X, 0x1000, USER_SPACE_START, 0x2000, 0x2000);
+        assert_eq!(elf::parse(&executable(USER_SPACE_START, &[truncated])).err(), Some(Errno::ExecFormat));
+        let kernel = (PF_R | PF_X, 0, 0x100000, 0x1000, 0x1000);
+        assert_eq!(elf::parse(&executable(0x100000, &[kernel])).err(), Some(Errno::ExecFormat));
+        let overlapping = (PF_R | PF_W, 0x1000, USER_SPACE_START + 0x800, 0x1000, 0x1000);
+        assert_eq!(elf::parse(&executable(USER_SPACE_START, &[code, overlapping])).err(), Some(Errno::ExecFormat));
+
+        // More data in the file than in memory
+        let inverted = (PF_R | PF_X, 0, USER_SPACE_START, 0x1000, 0x800);
+        assert_eq!(elf::parse(&executable(USER_SPACE_START, &[inverted])).err(), Some(Errno::ExecFormat));
+    }
+}
+
+kernel_test! {
+    fn elf_loader_accepts_position_independent_executables() {
+        let code = (PF_R | PF_X, 0, 0, 0x1000, 0x1000);
+        let data = (PF_R | PF_W, 0x1000, 0x1000, 0x800, 0x2000);
+        let buffer = position_independent(0x100, &[code, data]);
+        assert!(elf::is_position_independent(&elf::parse(&buffer).unwrap()));
+
+        // The same addresses are not valid in an executable, which is loaded at a fixed address
+        assert_eq!(elf::parse(&executable(0x100, &[code, data])).err(), Some(Errno::ExecFormat));
+        assert!(!elf::is_position_independent(&elf::parse(&executable(USER_SPACE_START, &[(PF_R | PF_X, 0, USER_SPACE_START, 0x1000, 0x1000)])).unwrap()));
+
+        // Overlapping segments are rejected as well
+        let overlapping = (PF_R | PF_W, 0x1000, 0x800, 0x1000, 0x1000);
+        assert_eq!(elf::parse(&position_independent(0x100, &[code, overlapping])).err(), Some(Errno::ExecFormat));
+    }
+}
+
+kernel_test! {
+    fn position_independent_executables_are_relocated_at_random_bases() {
+        let buffer = relocatable_executable();
+        let mut bases = Vec::new();
+
+        for _ in 0..2 {
+            let process = process::create_process();
+            let base = loader::load(&process, elf::parse(&buffer).unwrap(), &buffer).unwrap() - 0x10;
+            let read = |address: u64| {
+                let address = process.address_space().translate(VirtAddr::new(address)).unwrap();
+                unsafe { (address.as_u64() as *const u64).read() }
+            };
+
+            assert_eq!(read(base + 0x1000), base + 0x10);
+            assert_eq!(read(base + 0x1008), base + 0x1000);
+            bases.push(base);
+            process.exit();
+        }
+
+        // The base is chosen from 2^24 pages, so loading the executable twice at the same address is practically impossible
+        assert_ne!(bases[0], bases[1]);
+    }
+}
+
+kernel_test! {
+    fn elf_loader_relocates_shared_objects() {
+        const EXTERNAL: u64 = 0x12345678;
+        let buffer = shared_object();
+        let elf = elf::parse(&buffer).unwrap();
+        assert!(elf::is_position_independent(&elf));
+        assert_eq!((elf.dynrelas.len(), elf.pltrelocs.len()), (3, 1));
+
+        let process = process::create_process();
+        let base = USER_SPACE_START + 0x100000;
+        let image = Image::load(elf, &buffer, base, None);
+        image.map(&process, VmaType::Library);
+
+        // Local symbols are not visible to other objects
+        assert_eq!(image.symbol("value"), Some(base + 0x1000));
+        assert_eq!(image.symbol("function"), Some(base + 0x10));
+        assert_eq!(image.symbol("absolute"), None);
+
+        // Relocating fails, as long as a (non-weak) symbol is undefined
+        assert_eq!(image.relocate(|name| image.symbol(name)).err(), Some(Errno::ExecFormat));
+        image.relocate(|name| if name == "external" { Some(EXTERNAL) } else { image.symbol(name) }).unwrap();
+
+        let read = |offset: u64| {
+            let address = process.address_space().translate(VirtAddr::new(base + offset)).unwrap();
+            unsafe { (address.as_u64() as *const u64).read() }
+        };
+        assert_eq!(read(0x1008), base + 0x1000);
+        assert_eq!(read(0x1010), 0x1234);
+        assert_eq!(read(0x1018), base + 0x14);
+        assert_eq!(read(0x1020), EXTERNAL);
+
+        process.exit();
+    }
+}
+
+kernel_test! {
+    fn script_interpreter_line_is_parsed() {
+        assert_eq!(process::interpreter(b"#!/bin/shell\necho Hello\n"), Ok(Some((String::from("/bin/shell"), None))));
+        assert_eq!(process::interpreter(b"#! /bin/shell -a -b\n"), Ok(Some((String::from("/bin/shell"), Some(String::from("-a -b"))))));
+        assert_eq!(process::interpreter(&[0x7f, b'E', b'L', b'F']), Ok(None));
+
+        // The interpreter must be given by an absolute path and the line must be terminated
+        assert_eq!(process::interpreter(b"#!shell\n"), Err(Errno::ExecFormat));
+        assert_eq!(process::interpreter(b"#!/bin/shell"), Err(Errno::ExecFormat));
+    }
+}
+
+kernel_test! {
+    fn core_file_describes_registers_and_memory() {
+        let areas = [VirtualMemoryArea::from_address(VirtAddr::new(USER_SPACE_START), 0x2000, VmaType::Code),
+            VirtualMemoryArea::from_address(VirtAddr::new(USER_SPACE_START + 0x10000), 0x1000, VmaType::Stack)];
+        let registers = Registers { rip: USER_SPACE_START + 0x123, ..Registers::default() };
+        let headers = core_dump::headers(7, Signal::SegmentationFault, &registers, &[String::from("/bin/hello")], &areas);
+        assert_eq!(headers.len() % PAGE_SIZE, 0);
+
+        let core = Elf::parse(&headers).unwrap();
+        assert_eq!(core.header.e_type, ET_CORE);
+        assert_eq!(core.program_headers.iter().map(|header| header.p_type).collect::<Vec<u32>>(), [PT_NOTE, PT_LOAD, PT_LOAD]);
+
+        // Memory contents follow the headers in the order of the areas
+        let code = &core.program_headers[1];
+        let stack = &core.program_headers[2];
+        assert_eq!((code.p_offset, code.p_vaddr, code.p_filesz), (headers.len() as u64, USER_SPACE_START, 0x2000));
+        assert_eq!((stack.p_offset, stack.p_vaddr, stack.p_filesz), (headers.len() as u64 + 0x2000, USER_SPACE_START + 0x10000, 0x1000));
+
+        // The instruction pointer is the 17th register in the status note and the name of the executable is part of the process info
+        let notes = core.iter_note_headers(&headers).unwrap().collect::<Result<Vec<Note>, _>>().unwrap();
+        assert_eq!(notes.iter().map(|note| (note.name, note.n_type)).collect::<Vec<(&str, u32)>>(), [("CORE", NT_PRSTATUS), ("CORE", NT_PRPSINFO)]);
+        assert_eq!(notes[0].desc[112 + 16 * 8..112 + 17 * 8], (USER_SPACE_START + 0x123).to_le_bytes());
+        assert_eq!(&notes[1].desc[40..46], b"hello\0");
+    }
+}
+
+kernel_test! {
+    fn kernel_stack_overflow_depth_is_measured() {
+        let mut canary = [STACK_CANARY; STACK_CANARY_WORDS];
+        assert_eq!(thread::overwritten_canary_bytes(&canary), 0);
+
+        // The stack grows downwards, so an overflow overwrites the canary from its top
+        canary[STACK_CANARY_WORDS - 1] = 0;
+        assert_eq!(thread::overwritten_canary_bytes(&canary), 8);
+        canary[STACK_CANARY_WORDS - 10] = 0x1234;
+        assert_eq!(thread::overwritten_canary_bytes(&canary), 80);
+        canary[0] = 0;
+        assert_eq!(thread::overwritten_canary_bytes(&canary), STACK_CANARY_WORDS * 8);
+    }
+}
+
+kernel_test! {
+    fn signal_frames_are_only_written_to_process_memory() {
+        let process = process::create_process();
+        let stack = USER_SPACE_START + 0x10000;
+        process.add_vma(VirtualMemoryArea::from_address(VirtAddr::new(stack), 0x1000, VmaType::Stack));
+        assert!(process.contains_range(stack, stack + 0x1000));
+        assert!(!process.contains_range(stack - 8, stack + 8));
+        assert!(process.signals().set_disposition(Signal::Alarm, SignalDisposition::Handle, USER_SPACE_START as usize));
+
+        process.signals().raise(Signal::Alarm);
+        assert!(matches!(signal::handler_frame_action(&process, stack + 0x800), SignalAction::Handle(Signal::Alarm)));
+
+        // The user stack pointer may point into kernel memory or too close to the end of the stack for the signal frame
+        for user_rsp in [0x100000, stack + 8] {
+            process.signals().raise(Signal::Alarm);
+            assert!(matches!(signal::handler_frame_action(&process, user_rsp), SignalAction::Terminate(Signal::SegmentationFault)));
+        }
+
+        process.exit();
+    }
+}
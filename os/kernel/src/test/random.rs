@@ -0,0 +1,30 @@
+use crate::random;
+
+kernel_test! {
+    fn chacha20_block_matches_rfc7539() {
+        // Test vector from RFC 7539, section 2.3.2 (32-bit counter 1, nonce 00:00:00:09:00:00:00:4a:00:00:00:00)
+        let key = [0x03020100, 0x07060504, 0x0b0a0908, 0x0f0e0d0c, 0x13121110, 0x17161514, 0x1b1a1918, 0x1f1e1d1c];
+        let mut block = [0; 16];
+        random::chacha20_block(&key, 0x09000000_00000001, 0x4a000000, &mut block);
+
+        assert_eq!(block, [
+            0xe4e7f110, 0x15593bd1, 0x1fdd0f50, 0xc47120a3,
+            0xc7f4d1c7, 0x0368c033, 0x9aaa2204, 0x4e6cd4c3,
+            0x466482d2, 0x09aa9f07, 0x05d7c214, 0xa2028bd9,
+            0xd19c12b5, 0xb94e16de, 0xe883d0cb, 0x4e3c50a2
+        ]);
+    }
+}
+
+kernel_test! {
+    fn random_fill_never_repeats() {
+        let mut first = [0u8; 100];
+        let mut second = [0u8; 100];
+        random::fill(&mut first);
+        random::fill(&mut second);
+
+        assert_ne!(first, [0; 100]);
+        assert_ne!(first, second);
+        assert_ne!(random::next_u64(), random::next_u64());
+    }
+}
@@ -0,0 +1,82 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering::Relaxed;
+use spin::Once;
+use crate::sync::Mutex;
+use x86_64::instructions::interrupts;
+use crate::timer;
+use crate::timer::wheel::{TimerEntry, TimerWheel};
+
+pub mod wheel;
+
+static TIMER_WHEEL: Once<Mutex<TimerWheel>> = Once::new();
+
+/// Handle to a scheduled timer, which can be used to cancel it.
+/// Dropping the handle does not cancel the timer.
+#[derive(Clone)]
+pub struct TimerHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl TimerHandle {
+    /// Cancel the timer. The callback will not be executed anymore, unless it is already running.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        return self.cancelled.load(Relaxed);
+    }
+}
+
+/// Execute `callback` once after `delay_ms` milliseconds.
+/// Callbacks are executed by the timer tasklet (with interrupts enabled), but must not block.
+pub fn schedule(delay_ms: usize, callback: Box<dyn FnMut() + Send>) -> TimerHandle {
+    return insert(delay_ms, None, callback);
+}
+
+/// Execute `callback` every `interval_ms` milliseconds, until the returned handle is cancelled.
+/// Callbacks are executed by the timer tasklet (with interrupts enabled), but must not block.
+pub fn schedule_periodic(interval_ms: usize, callback: Box<dyn FnMut() + Send>) -> TimerHandle {
+    return schedule_periodic_delayed(interval_ms, interval_ms, callback);
+}
+
+/// Execute `callback` after `delay_ms` milliseconds and then every `interval_ms` milliseconds, until the returned handle is cancelled.
+/// Callbacks are executed by the timer tasklet (with interrupts enabled), but must not block.
+pub fn schedule_periodic_delayed(delay_ms: usize, interval_ms: usize, callback: Box<dyn FnMut() + Send>) -> TimerHandle {
+    if interval_ms == 0 {
+        panic!("Timer: Periodic timer with an interval of 0 ms!");
+    }
+
+    return insert(delay_ms, Some(interval_ms), callback);
+}
+
+/// Called by the timer tasklet after each system tick to execute all expired timers.
+pub fn process_expired(now_ms: usize) {
+    let expired = interrupts::without_interrupts(|| timer_wheel().lock().advance(now_ms));
+
+    // Callbacks are executed without holding the lock, so that they may schedule new timers
+    for entry in expired {
+        if let Some(rearmed) = entry.fire() {
+            interrupts::without_interrupts(|| timer_wheel().lock().insert(rearmed));
+        }
+    }
+}
+
+fn insert(delay_ms: usize, period_ms: Option<usize>, callback: Box<dyn FnMut() + Send>) -> TimerHandle {
+    let cancelled = Arc::new(AtomicBool::new(false));
+
+    // Interrupts are disabled, while the wheel is locked, because the timer tasklet may interrupt any thread and needs to lock it as well
+    interrupts::without_interrupts(|| {
+        let mut wheel = timer_wheel().lock();
+        let expires_ms = wheel.current_ms() + delay_ms;
+        wheel.insert(TimerEntry::new(expires_ms, period_ms, Arc::clone(&cancelled), callback));
+    });
+
+    return TimerHandle { cancelled };
+}
+
+fn timer_wheel() -> &'static Mutex<TimerWheel> {
+    return TIMER_WHEEL.call_once(|| Mutex::new(TimerWheel::new(timer().read().systime_ms())));
+}
@@ -0,0 +1,144 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering::Relaxed;
+
+const LEVELS: usize = 4;
+const SLOT_BITS: usize = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const SLOT_MASK: usize = SLOTS - 1;
+
+pub struct TimerEntry {
+    expires_ms: usize,
+    period_ms: Option<usize>,
+    cancelled: Arc<AtomicBool>,
+    callback: Box<dyn FnMut() + Send>,
+}
+
+/// Hierarchical timer wheel with a resolution of one millisecond.
+/// Each of the `LEVELS` levels consists of `SLOTS` slots, with every slot on level `n` covering `SLOTS^n` milliseconds.
+/// Timers, which expire too far in the future for the highest level, are kept in an overflow list.
+/// When the lower level wraps around, the entries of the next slot in the higher level are cascaded down.
+pub struct TimerWheel {
+    current_ms: usize,
+    levels: Vec<Vec<Vec<TimerEntry>>>,
+    overflow: Vec<TimerEntry>,
+}
+
+impl TimerEntry {
+    pub fn new(expires_ms: usize, period_ms: Option<usize>, cancelled: Arc<AtomicBool>, callback: Box<dyn FnMut() + Send>) -> Self {
+        Self { expires_ms, period_ms, cancelled, callback }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        return self.cancelled.load(Relaxed);
+    }
+
+    /// Execute the callback and return the entry again, if it needs to be rearmed (periodic timer).
+    pub fn fire(mut self) -> Option<TimerEntry> {
+        if self.is_cancelled() {
+            return None;
+        }
+
+        (self.callback)();
+
+        return match self.period_ms {
+            Some(period) if !self.is_cancelled() => {
+                self.expires_ms += period;
+                Some(self)
+            }
+            _ => None,
+        };
+    }
+}
+
+impl TimerWheel {
+    pub fn new(current_ms: usize) -> Self {
+        let mut levels = Vec::with_capacity(LEVELS);
+        for _ in 0..LEVELS {
+            let mut slots = Vec::with_capacity(SLOTS);
+            for _ in 0..SLOTS {
+                slots.push(Vec::new());
+            }
+
+            levels.push(slots);
+        }
+
+        Self { current_ms, levels, overflow: Vec::new() }
+    }
+
+    pub fn current_ms(&self) -> usize {
+        return self.current_ms;
+    }
+
+    pub fn insert(&mut self, mut entry: TimerEntry) {
+        // Timers, that are already due, fire on the next tick
+        if entry.expires_ms <= self.current_ms {
+            entry.expires_ms = self.current_ms + 1;
+        }
+
+        let delta = entry.expires_ms - self.current_ms;
+        for level in 0..LEVELS {
+            if delta < 1 << (SLOT_BITS * (level + 1)) {
+                let slot = (entry.expires_ms >> (SLOT_BITS * level)) & SLOT_MASK;
+                self.levels[level][slot].push(entry);
+                return;
+            }
+        }
+
+        self.overflow.push(entry);
+    }
+
+    /// Advance the wheel up to `now_ms` and return all timers, that have expired in the meantime.
+    /// The callbacks are not executed here, so that the caller can run them without holding the lock on the wheel.
+    pub fn advance(&mut self, now_ms: usize) -> Vec<TimerEntry> {
+        let mut expired = Vec::new();
+
+        while self.current_ms < now_ms {
+            self.current_ms += 1;
+            self.cascade(1);
+
+            let slot = self.current_ms & SLOT_MASK;
+            for entry in mem::take(&mut self.levels[0][slot]) {
+                if entry.is_cancelled() {
+                    continue;
+                }
+
+                if entry.expires_ms <= self.current_ms {
+                    expired.push(entry);
+                } else {
+                    self.insert(entry);
+                }
+            }
+        }
+
+        return expired;
+    }
+
+    /// Move the entries of the current slot on `level` down to the lower levels, if all levels below have wrapped around.
+    fn cascade(&mut self, level: usize) {
+        if (self.current_ms >> (SLOT_BITS * (level - 1))) & SLOT_MASK != 0 {
+            return;
+        }
+
+        if level == LEVELS {
+            for entry in mem::take(&mut self.overflow) {
+                self.insert(entry);
+            }
+
+            return;
+        }
+
+        let slot = (self.current_ms >> (SLOT_BITS * level)) & SLOT_MASK;
+        let entries = mem::take(&mut self.levels[level][slot]);
+        self.cascade(level + 1);
+
+        for entry in entries {
+            if !entry.is_cancelled() {
+                self.insert(entry);
+            }
+        }
+    }
+}
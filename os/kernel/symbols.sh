@@ -0,0 +1,33 @@
+#!/bin/bash
+
+# Embed a symbol map (address -> name) into the '.symbols' section of the linked kernel.
+# The section is reserved by 'src/symbols.rs' with a fixed size, so that updating it does not change the kernel layout.
+# Each line has the format '<16 hex digits address> <demangled name>', sorted by address and terminated by a null byte.
+
+readonly KERNEL="${1}"
+readonly SYMBOL_FILE="${KERNEL}.symbols"
+
+if [[ ! -f "${KERNEL}" ]]; then
+  echo "Kernel '${KERNEL}' not found!"
+  exit 1
+fi
+
+readonly SECTION_SIZE=$(printf "%d" "0x$(objdump -h "${KERNEL}" | awk '$2 == ".symbols" { print $3 }')")
+if [[ "${SECTION_SIZE}" -eq 0 ]]; then
+  echo "Kernel '${KERNEL}' has no '.symbols' section!"
+  exit 1
+fi
+
+# Only code symbols are relevant for backtraces
+nm --defined-only --demangle --numeric-sort "${KERNEL}" | awk '$2 ~ /^[tTwW]$/ { address = $1; $1 = ""; $2 = ""; sub(/^ +/, ""); print address " " $0 }' > "${SYMBOL_FILE}" || exit 1
+
+# Truncate symbol map at the last complete line, if it does not fit into the section (one byte is reserved for the terminating null byte)
+if [[ $(wc -c < "${SYMBOL_FILE}") -ge ${SECTION_SIZE} ]]; then
+  echo "Warning: Symbol map does not fit into '.symbols' section and will be truncated!"
+  head -c $((SECTION_SIZE - 1)) "${SYMBOL_FILE}" | sed '$d' > "${SYMBOL_FILE}.tmp" || exit 1
+  mv "${SYMBOL_FILE}.tmp" "${SYMBOL_FILE}" || exit 1
+fi
+
+truncate -s "${SECTION_SIZE}" "${SYMBOL_FILE}" || exit 1
+objcopy --update-section .symbols="${SYMBOL_FILE}" "${KERNEL}" || exit 1
+rm -f "${SYMBOL_FILE}" || exit 1
@@ -1,4 +1,6 @@
 #![no_std]
+#![feature(naked_functions)]
 
 pub mod process;
-pub mod thread;
\ No newline at end of file
+pub mod signal;
+pub mod thread;
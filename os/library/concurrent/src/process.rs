@@ -1,4 +1,5 @@
-use syscall::{syscall0, SystemCall};
+use syscall::{syscall0, syscall1, syscall2, SystemCall};
+use syscall::error::{from_syscall_result, Errno};
 
 pub struct Process {
     id: usize
@@ -17,4 +18,26 @@ impl Process {
 pub fn current() -> Process {
     let id = syscall0(SystemCall::ProcessId);
     Process::new(id)
-}
\ No newline at end of file
+}
+
+/// Deliver `Signal::Alarm` to the current process after `seconds` (0 cancels a pending alarm).
+/// Returns the number of seconds remaining until a previously scheduled alarm would have been delivered.
+pub fn alarm(seconds: usize) -> usize {
+    return syscall1(SystemCall::Alarm, seconds);
+}
+
+/// Deliver `Signal::Alarm` to the current process after `initial_ms` and every `interval_ms` afterwards.
+/// Returns the milliseconds remaining on the previously configured interval timer.
+pub fn set_interval_timer(initial_ms: usize, interval_ms: usize) -> usize {
+    return syscall2(SystemCall::IntervalTimer, initial_ms, interval_ms);
+}
+
+/// Write all modified data back and power off the system. Only returns, if the system could not be powered off.
+pub fn shutdown() -> Result<(), Errno> {
+    return from_syscall_result(syscall0(SystemCall::Shutdown)).map(|_| ());
+}
+
+/// Write all modified data back and reset the system. Only returns, if the system could not be reset.
+pub fn reboot() -> Result<(), Errno> {
+    return from_syscall_result(syscall0(SystemCall::Reboot)).map(|_| ());
+}
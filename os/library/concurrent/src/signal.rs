@@ -0,0 +1,94 @@
+use core::arch::asm;
+use core::mem;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering::Relaxed;
+use syscall::{syscall3, SystemCall};
+use syscall::signal::{SignalDisposition, MAX_SIGNALS};
+
+pub use syscall::signal::Signal;
+
+const NO_HANDLER: AtomicUsize = AtomicUsize::new(0);
+static HANDLERS: [AtomicUsize; MAX_SIGNALS] = [NO_HANDLER; MAX_SIGNALS];
+
+/// Register a handler function, which is called each time `signal` is delivered to this process.
+pub fn set_handler(signal: Signal, handler: fn(Signal)) -> bool {
+    HANDLERS[signal as usize].store(handler as usize, Relaxed);
+    return signal_action(signal, SignalDisposition::Handle);
+}
+
+pub fn ignore(signal: Signal) -> bool {
+    return signal_action(signal, SignalDisposition::Ignore);
+}
+
+/// Restore the default action for `signal` (e.g. terminating the process on `Signal::Alarm`).
+pub fn reset(signal: Signal) -> bool {
+    return signal_action(signal, SignalDisposition::Default);
+}
+
+fn signal_action(signal: Signal, disposition: SignalDisposition) -> bool {
+    let trampoline = signal_trampoline as *const () as usize;
+    return syscall3(SystemCall::SignalAction, signal as usize, disposition as usize, trampoline) != 0;
+}
+
+extern "C" fn dispatch_signal(number: usize) {
+    let signal = Signal::try_from(number).expect("Signal: Kernel delivered an invalid signal number!");
+    let handler = HANDLERS[number].load(Relaxed);
+
+    if handler != 0 {
+        let handler: fn(Signal) = unsafe { mem::transmute(handler) };
+        handler(signal);
+    }
+}
+
+/// Entry point for signal delivery. The kernel returns from a system call or an interrupt to this function,
+/// after pushing the interrupted instruction pointer and the signal number onto the user stack.
+#[naked]
+unsafe extern "C" fn signal_trampoline() {
+    asm!(
+    // Save flags and all caller-saved registers (including the system call return value in rax) and rbx, which holds the unaligned stack pointer
+    "pushfq",
+    "push rax",
+    "push rcx",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push r8",
+    "push r9",
+    "push r10",
+    "push r11",
+    "push rbx",
+
+    // Signal number is located above the saved registers and flags
+    "mov rdi, [rsp + 88]",
+
+    // The interrupted code may have any stack alignment, but the handler expects a 16 byte aligned stack (System V ABI)
+    "mov rbx, rsp",
+    "and rsp, -16",
+
+    // Signals may interrupt any instruction, so the SSE registers (caller-saved as well) are saved too
+    "sub rsp, 512",
+    "fxsave64 [rsp]",
+    "call {}",
+    "fxrstor64 [rsp]",
+    "mov rsp, rbx",
+
+    // Restore registers and flags
+    "pop rbx",
+    "pop r11",
+    "pop r10",
+    "pop r9",
+    "pop r8",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop rcx",
+    "pop rax",
+    "popfq",
+
+    // Skip signal number (lea does not alter flags) and return to interrupted code
+    "lea rsp, [rsp + 8]",
+    "ret",
+    sym dispatch_signal,
+    options(noreturn)
+    );
+}
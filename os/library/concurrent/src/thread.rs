@@ -1,4 +1,6 @@
-use syscall::{syscall0, syscall1, syscall2, SystemCall};
+use syscall::{syscall0, syscall1, syscall5, SystemCall};
+use syscall::error::{from_syscall_result, Errno};
+use syscall::process::{Argument, StartFlags};
 
 pub struct Thread {
     id: usize
@@ -16,6 +18,11 @@ impl Thread {
     pub fn join(&self) {
         syscall1(SystemCall::ThreadJoin, self.id);
     }
+
+    /// Check, if the thread has not exited yet (without blocking like `join()`).
+    pub fn is_running(&self) -> bool {
+        return syscall1(SystemCall::ThreadRunning, self.id) != 0;
+    }
 }
 
 pub fn current() -> Thread {
@@ -33,14 +40,22 @@ pub fn sleep(ms: usize) {
     syscall1(SystemCall::ThreadSleep, ms);
 }
 
+#[allow(dead_code)]
+pub fn nanosleep(ns: usize) {
+    syscall1(SystemCall::ThreadNanosleep, ns);
+}
+
 pub fn exit() -> ! {
     syscall0(SystemCall::ThreadExit);
     panic!("System call 'ThreadExit' has returned!")
 }
 
-pub fn start_application(name: &str) -> Option<Thread> {
-    match syscall2(SystemCall::ApplicationStart, name.as_bytes().as_ptr() as usize, name.len()) {
-        0 => None,
-        id => Some(Thread::new(id))
-    }
+/// Start the application `name` with the command line arguments `arguments` (following `name`) in a new process,
+/// which inherits the open files, and return its main thread.
+/// With `StartFlags::BACKGROUND`, it does not become the foreground process of the console.
+/// Fails with `Errno::NotFound`, if there is no such application, or with `Errno::ExecFormat`, if it is not a valid executable.
+pub fn start_application(name: &str, arguments: &[Argument], flags: StartFlags) -> Result<Thread, Errno> {
+    let result = syscall5(SystemCall::ApplicationStart, name.as_bytes().as_ptr() as usize, name.len(), arguments.as_ptr() as usize, arguments.len(), flags.bits());
+    let id = from_syscall_result(result)?;
+    return Ok(Thread::new(id));
 }
@@ -1557,3 +1557,26 @@ pub fn fg_24bit_color(color: Color) -> String {
 pub fn bg_24bit_color(color: Color) -> String {
     return format!("\x1b[48;2;{};{};{}m", color.red, color.green, color.blue);
 }
+
+pub const CLEAR_SCREEN: &str = "\x1b[2J";
+pub const CLEAR_LINE: &str = "\x1b[2K";
+pub const CURSOR_HOME: &str = "\x1b[H";
+pub const CURSOR_SAVE: &str = "\x1b7";
+pub const CURSOR_RESTORE: &str = "\x1b8";
+pub const CURSOR_HIDE: &str = "\x1b[?25l";
+pub const CURSOR_SHOW: &str = "\x1b[?25h";
+
+/// Input sequences sent by the terminal for keys without a character.
+pub const KEY_UP: &str = "\x1b[A";
+pub const KEY_DOWN: &str = "\x1b[B";
+pub const KEY_RIGHT: &str = "\x1b[C";
+pub const KEY_LEFT: &str = "\x1b[D";
+pub const KEY_HOME: &str = "\x1b[H";
+pub const KEY_END: &str = "\x1b[F";
+pub const KEY_PAGE_UP: &str = "\x1b[5~";
+pub const KEY_PAGE_DOWN: &str = "\x1b[6~";
+
+/// Move the cursor to `row` and `column` (both starting at 1).
+pub fn cursor_position(row: u16, column: u16) -> String {
+    return format!("\x1b[{};{}H", row, column);
+}
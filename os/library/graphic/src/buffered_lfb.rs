@@ -1,10 +1,17 @@
+use crate::color::Color;
+use crate::font::Font;
 use crate::lfb::LFB;
 use alloc::vec::Vec;
+use core::cmp::{max, min};
 
+/// Framebuffer with a back buffer in main memory. Drawing operations go to the back buffer and remember the changed area,
+/// so that `flush()` only copies this area (row by row) to video memory, which is much slower to access than main memory.
 pub struct BufferedLFB {
     buffer: Vec<u8>,
     lfb: LFB,
     target_lfb: LFB,
+    /// Area, which has been changed since the last flush (start x, start y, end x, end y in pixels).
+    dirty: Option<(u32, u32, u32, u32)>,
 }
 
 impl BufferedLFB {
@@ -12,10 +19,12 @@ impl BufferedLFB {
         let buffer = Vec::with_capacity((lfb.height() * lfb.pitch()) as usize);
         let raw_buffer = buffer.as_ptr() as *mut u8;
 
-        Self { buffer, lfb: LFB::new(raw_buffer, lfb.pitch(), lfb.width(), lfb.height(), lfb.bpp()), target_lfb: lfb }
+        Self { buffer, lfb: LFB::new(raw_buffer, lfb.pitch(), lfb.width(), lfb.height(), lfb.bpp()), target_lfb: lfb, dirty: None }
     }
 
+    /// Direct access to the back buffer. The whole screen is copied by the next flush, since the changed area is unknown.
     pub fn lfb(&mut self) -> &mut LFB {
+        self.invalidate();
         &mut self.lfb
     }
 
@@ -23,7 +32,69 @@ impl BufferedLFB {
         &mut self.target_lfb
     }
 
+    pub fn draw_char(&mut self, font: &Font, x: u32, y: u32, fg_color: &Color, bg_color: &Color, c: char) -> bool {
+        let drawn = self.lfb.draw_glyph(font, x, y, fg_color, bg_color, c);
+        if drawn {
+            self.mark_dirty(x, y, font.width(), font.height());
+        }
+
+        return drawn;
+    }
+
+    pub fn fill_rect(&mut self, x: u32, y: u32, width: u32, height: u32, color: &Color) {
+        self.lfb.fill_rect(x, y, width, height, color);
+        self.mark_dirty(x, y, width, height);
+    }
+
+    /// Scrolling changes every pixel, so the whole screen is copied by the next flush.
+    /// Several scrolled lines are still flushed at once, if flushing is deferred until all output has been drawn.
+    pub fn scroll_up(&mut self, lines: u32) {
+        self.lfb.scroll_up(lines);
+        self.invalidate();
+    }
+
+    /// Mark the given area as changed (e.g. to remove something drawn directly to the screen with the next flush).
+    pub fn mark_dirty(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        let end_x = min(x.saturating_add(width), self.lfb.width());
+        let end_y = min(y.saturating_add(height), self.lfb.height());
+        if x >= end_x || y >= end_y {
+            return;
+        }
+
+        self.dirty = match self.dirty {
+            Some((dirty_x, dirty_y, dirty_end_x, dirty_end_y)) => Some((min(x, dirty_x), min(y, dirty_y), max(end_x, dirty_end_x), max(end_y, dirty_end_y))),
+            None => Some((x, y, end_x, end_y))
+        };
+    }
+
+    /// Mark the whole screen as changed.
+    pub fn invalidate(&mut self) {
+        self.dirty = Some((0, 0, self.lfb.width(), self.lfb.height()));
+    }
+
+    /// Copy the changed area to the screen.
     pub fn flush(&mut self) {
-        unsafe { self.target_lfb.buffer().copy_from(self.buffer.as_ptr(), (self.lfb.height() * self.lfb.pitch()) as usize); }
+        let (x, y, end_x, end_y) = match self.dirty.take() {
+            Some(dirty) => dirty,
+            None => return
+        };
+
+        let pitch = self.lfb.pitch() as usize;
+        let bytes_per_pixel = (self.lfb.bpp() as usize).div_ceil(8);
+        let offset = x as usize * bytes_per_pixel;
+        let length = (end_x - x) as usize * bytes_per_pixel;
+
+        unsafe {
+            if x == 0 && end_x == self.lfb.width() {
+                // Whole rows are copied at once
+                let start = y as usize * pitch;
+                self.target_lfb.buffer().add(start).copy_from_nonoverlapping(self.buffer.as_ptr().add(start), (end_y - y) as usize * pitch);
+            } else {
+                for row in y as usize..end_y as usize {
+                    let start = row * pitch + offset;
+                    self.target_lfb.buffer().add(start).copy_from_nonoverlapping(self.buffer.as_ptr().add(start), length);
+                }
+            }
+        }
     }
 }
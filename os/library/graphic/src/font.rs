@@ -0,0 +1,190 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use font8x8::{UnicodeFonts, BASIC_FONTS, BLOCK_FONTS, BOX_FONTS, GREEK_FONTS, HIRAGANA_FONTS, LATIN_FONTS, MISC_FONTS, SGA_FONTS};
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_HAS_TABLE: u8 = 0x02;
+const PSF1_HEADER_SIZE: usize = 4;
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HAS_TABLE: u32 = 0x01;
+
+/// Bitmap font for drawing text on a framebuffer (see `LFB::draw_glyph()`).
+/// Either the built-in font (8x8 glyphs of `font8x8` with doubled rows) or a PC Screen Font (PSF1/PSF2), e.g. loaded from the initramfs.
+/// Each pixel of a glyph can be scaled (e.g. for using an 8x16 font as 16x32 font on high-resolution framebuffers).
+#[derive(Clone)]
+pub struct Font {
+    width: u32,
+    height: u32,
+    scale: u32,
+    glyphs: Glyphs,
+}
+
+#[derive(Clone)]
+enum Glyphs {
+    Builtin,
+    Psf {
+        bitmaps: Vec<u8>,
+        bytes_per_row: usize,
+        glyph_size: usize,
+        /// Glyph index of each character. Fonts without unicode table map character codes directly to glyph indices.
+        index: BTreeMap<char, usize>,
+    },
+}
+
+pub enum Glyph<'a> {
+    Builtin([u8; 8]),
+    Bitmap(&'a [u8]),
+}
+
+impl Font {
+    /// The built-in 8x16 font, scaled by `scale` (e.g. 2 for 16x32). Panics, if `scale` is 0.
+    pub const fn builtin(scale: u32) -> Self {
+        assert!(scale > 0, "Font: Scale must not be 0!");
+        Self { width: 8 * scale, height: 16 * scale, scale, glyphs: Glyphs::Builtin }
+    }
+
+    /// Parse a PC Screen Font (version 1 or 2). Returns `None`, if `data` is not a valid PSF font.
+    pub fn from_psf(data: &[u8]) -> Option<Self> {
+        if data.starts_with(&PSF2_MAGIC) {
+            return Font::from_psf2(data);
+        } else if data.starts_with(&PSF1_MAGIC) {
+            return Font::from_psf1(data);
+        }
+
+        return None;
+    }
+
+    /// Return the same font with each pixel scaled by `scale`. Panics, if `scale` is 0.
+    pub fn scaled(mut self, scale: u32) -> Self {
+        assert!(scale > 0, "Font: Scale must not be 0!");
+        self.width = self.width / self.scale * scale;
+        self.height = self.height / self.scale * scale;
+        self.scale = scale;
+
+        return self;
+    }
+
+    /// Width of each glyph in pixels (including scaling).
+    pub fn width(&self) -> u32 {
+        return self.width;
+    }
+
+    /// Height of each glyph in pixels (including scaling).
+    pub fn height(&self) -> u32 {
+        return self.height;
+    }
+
+    pub fn glyph(&self, c: char) -> Option<Glyph<'_>> {
+        return match &self.glyphs {
+            Glyphs::Builtin => builtin_glyph(c).map(Glyph::Builtin),
+            Glyphs::Psf { bitmaps, glyph_size, index, .. } => {
+                let index = *index.get(&c)?;
+                bitmaps.get(index * glyph_size..(index + 1) * glyph_size).map(Glyph::Bitmap)
+            }
+        };
+    }
+
+    /// Check, if the pixel at (`x`, `y`) of `glyph` is set. The coordinates include scaling.
+    pub fn is_set(&self, glyph: &Glyph, x: u32, y: u32) -> bool {
+        let (x, y) = ((x / self.scale) as usize, (y / self.scale) as usize);
+        return match (glyph, &self.glyphs) {
+            // Each row of the 8x8 glyphs is drawn twice, so that the characters are not too wide
+            (Glyph::Builtin(bitmap), _) => bitmap[y / 2] & (1 << x) != 0,
+            (Glyph::Bitmap(bitmap), Glyphs::Psf { bytes_per_row, .. }) => bitmap[y * bytes_per_row + x / 8] & (0x80 >> (x % 8)) != 0,
+            (Glyph::Bitmap(_), Glyphs::Builtin) => false
+        };
+    }
+
+    fn from_psf1(data: &[u8]) -> Option<Self> {
+        let mode = *data.get(2)?;
+        let height = *data.get(3)? as usize;
+        let count = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+        let bitmaps = data.get(PSF1_HEADER_SIZE..PSF1_HEADER_SIZE + count * height)?;
+
+        // The unicode table contains little endian code points for each glyph, terminated by 0xffff
+        // Sequences of combining characters start with 0xfffe and are not supported
+        let mut index = BTreeMap::new();
+        if mode & PSF1_MODE_HAS_TABLE != 0 {
+            let mut glyph = 0;
+            let mut in_sequence = false;
+            for entry in data[PSF1_HEADER_SIZE + count * height..].chunks_exact(2) {
+                match u16::from_le_bytes([entry[0], entry[1]]) {
+                    0xffff => {
+                        glyph += 1;
+                        in_sequence = false;
+                    }
+                    0xfffe => in_sequence = true,
+                    code if !in_sequence => {
+                        if let Some(c) = char::from_u32(code as u32) {
+                            index.entry(c).or_insert(glyph);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        } else {
+            index = direct_index(count);
+        }
+
+        let glyphs = Glyphs::Psf { bitmaps: bitmaps.to_vec(), bytes_per_row: 1, glyph_size: height, index };
+        return Some(Self { width: 8, height: height as u32, scale: 1, glyphs });
+    }
+
+    fn from_psf2(data: &[u8]) -> Option<Self> {
+        let field = |index: usize| -> Option<u32> {
+            let bytes = data.get(index * 4..(index + 1) * 4)?;
+            return Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+        };
+
+        let header_size = field(2)? as usize;
+        let flags = field(3)?;
+        let count = field(4)? as usize;
+        let glyph_size = field(5)? as usize;
+        let height = field(6)?;
+        let width = field(7)?;
+
+        let bytes_per_row = (width as usize).div_ceil(8);
+        if width == 0 || height == 0 || glyph_size < bytes_per_row * height as usize {
+            return None;
+        }
+
+        let table_start = header_size + count * glyph_size;
+        let bitmaps = data.get(header_size..table_start)?;
+
+        // The unicode table contains UTF-8 encoded characters for each glyph, terminated by 0xff
+        // Sequences of combining characters start with 0xfe and are not supported
+        let mut index = BTreeMap::new();
+        if flags & PSF2_HAS_TABLE != 0 {
+            let table = &data[table_start..];
+            for (glyph, entry) in table.split(|byte| *byte == 0xff).take(count).enumerate() {
+                let characters = entry.split(|byte| *byte == 0xfe).next().unwrap_or(&[]);
+                if let Ok(characters) = core::str::from_utf8(characters) {
+                    for c in characters.chars() {
+                        index.entry(c).or_insert(glyph);
+                    }
+                }
+            }
+        } else {
+            index = direct_index(count);
+        }
+
+        let glyphs = Glyphs::Psf { bitmaps: bitmaps.to_vec(), bytes_per_row, glyph_size, index };
+        return Some(Self { width, height, scale: 1, glyphs });
+    }
+}
+
+fn direct_index(count: usize) -> BTreeMap<char, usize> {
+    return (0..count).filter_map(|code| char::from_u32(code as u32).map(|c| (c, code))).collect();
+}
+
+fn builtin_glyph(c: char) -> Option<[u8; 8]> {
+    return BASIC_FONTS.get(c)
+        .or_else(|| LATIN_FONTS.get(c))
+        .or_else(|| BLOCK_FONTS.get(c))
+        .or_else(|| BOX_FONTS.get(c))
+        .or_else(|| MISC_FONTS.get(c))
+        .or_else(|| GREEK_FONTS.get(c))
+        .or_else(|| HIRAGANA_FONTS.get(c))
+        .or_else(|| SGA_FONTS.get(c));
+}
@@ -1,8 +1,6 @@
 use crate::color::Color;
-use font8x8::{
-    UnicodeFonts, BASIC_FONTS, BLOCK_FONTS, BOX_FONTS, GREEK_FONTS, HIRAGANA_FONTS, LATIN_FONTS,
-    MISC_FONTS, SGA_FONTS,
-};
+use core::cmp::min;
+use crate::font::Font;
 
 pub struct LFB {
     buffer: *mut u8,
@@ -17,6 +15,7 @@ pub struct LFB {
 unsafe impl Send for LFB {}
 unsafe impl Sync for LFB {}
 
+/// Character size of the built-in font (see `Font::builtin()`).
 pub const CHAR_HEIGHT: u32 = 16;
 pub const CHAR_WIDTH: u32 = 8;
 
@@ -62,65 +61,49 @@ impl LFB {
         unsafe { (self.pixel_drawer)(self.buffer, self.pitch, x, y, color) };
     }
 
+    /// Only the first row is drawn pixel by pixel, while the other rows are copied from it.
+    /// This reads from the buffer, so it should not be used directly on video memory.
     pub fn fill_rect(&self, x: u32, y: u32, width: u32, height: u32, color: &Color) {
-        let end_x = x + width;
-        let end_y = y + height;
+        let end_x = min(x.saturating_add(width), self.width);
+        let end_y = min(y.saturating_add(height), self.height);
+        if x >= end_x || y >= end_y {
+            return;
+        }
 
-        for i in y..end_y {
-            for j in x..end_x {
-                self.draw_pixel(j, i, color);
-            }
+        for j in x..end_x {
+            self.draw_pixel(j, y, color);
         }
-    }
 
-    pub fn draw_char(&self, x: u32, y: u32, fg_color: &Color, bg_color: &Color, c: char) -> bool {
-        let mut glyph = BASIC_FONTS.get(c);
-        if glyph.is_none() {
-            glyph = LATIN_FONTS.get(c);
-            if glyph.is_none() {
-                glyph = BLOCK_FONTS.get(c);
-                if glyph.is_none() {
-                    glyph = BOX_FONTS.get(c);
-                    if glyph.is_none() {
-                        glyph = MISC_FONTS.get(c);
-                        if glyph.is_none() {
-                            glyph = GREEK_FONTS.get(c);
-                            if glyph.is_none() {
-                                glyph = HIRAGANA_FONTS.get(c);
-                                if glyph.is_none() {
-                                    glyph = SGA_FONTS.get(c);
-                                }
-                            }
-                        }
-                    }
-                }
+        let bytes_per_pixel = (self.bpp as u32).div_ceil(8);
+        let length = ((end_x - x) * bytes_per_pixel) as usize;
+        unsafe {
+            let first_row = self.buffer.add((y * self.pitch + x * bytes_per_pixel) as usize);
+            for i in 1..end_y - y {
+                first_row.add((i * self.pitch) as usize).copy_from_nonoverlapping(first_row, length);
             }
         }
+    }
 
-        if let Some(bitmap) = glyph {
-            let mut x_offset = 0;
-            let mut y_offset = 0;
-
-            for row in &bitmap {
-                for col in 0..8 {
-                    let color = match *row & 1 << col {
-                        0 => bg_color,
-                        _ => fg_color,
-                    };
+    /// Draw a character with the built-in 8x16 font. Returns false, if the font does not contain the character.
+    pub fn draw_char(&self, x: u32, y: u32, fg_color: &Color, bg_color: &Color, c: char) -> bool {
+        return self.draw_glyph(&Font::builtin(1), x, y, fg_color, bg_color, c);
+    }
 
-                    self.draw_pixel(x + x_offset, y + y_offset, color);
-                    self.draw_pixel(x + x_offset, y + y_offset + 1, color);
-                    x_offset += 1;
-                }
+    /// Draw a character with `font`. Returns false, if the font does not contain the character.
+    pub fn draw_glyph(&self, font: &Font, x: u32, y: u32, fg_color: &Color, bg_color: &Color, c: char) -> bool {
+        let glyph = match font.glyph(c) {
+            Some(glyph) => glyph,
+            None => return false
+        };
 
-                x_offset = 0;
-                y_offset += 2;
+        for y_offset in 0..font.height() {
+            for x_offset in 0..font.width() {
+                let color = if font.is_set(&glyph, x_offset, y_offset) { fg_color } else { bg_color };
+                self.draw_pixel(x + x_offset, y + y_offset, color);
             }
-
-            return true;
         }
 
-        return false;
+        return true;
     }
 
     pub fn clear(&self) {
@@ -5,4 +5,6 @@ extern crate alloc;
 pub mod ansi;
 pub mod buffered_lfb;
 pub mod color;
+pub mod font;
 pub mod lfb;
+pub mod surface;
@@ -0,0 +1,197 @@
+use crate::color::Color;
+use crate::lfb::{LFB, CHAR_HEIGHT, CHAR_WIDTH};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::{max, min};
+
+/// Offscreen drawing surface (back buffer) in the pixel format of the framebuffer.
+/// Applications draw a whole frame into a surface and copy it to the screen with `present()`,
+/// so that half-drawn frames are never visible (no flickering). Only the area changed since the last `present()` is copied.
+pub struct Surface {
+    buffer: Vec<u8>,
+    lfb: LFB,
+    /// Area, which has been changed since the last present (start x, start y, end x, end y in pixels).
+    dirty: Option<(u32, u32, u32, u32)>,
+}
+
+unsafe impl Send for Surface {}
+unsafe impl Sync for Surface {}
+
+impl Surface {
+    /// Create a black surface. `bpp` should match the framebuffer (see `FramebufferInfo`), since pixels are not converted by `present()`.
+    pub fn new(width: u32, height: u32, bpp: u8) -> Self {
+        let pitch = width * (bpp as u32).div_ceil(8);
+        let mut buffer = vec![0u8; (pitch * height) as usize];
+        let lfb = LFB::new(buffer.as_mut_ptr(), pitch, width, height, bpp);
+
+        Self { buffer, lfb, dirty: Some((0, 0, width, height)) }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.lfb.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.lfb.height()
+    }
+
+    pub fn pitch(&self) -> u32 {
+        self.lfb.pitch()
+    }
+
+    pub fn bpp(&self) -> u8 {
+        self.lfb.bpp()
+    }
+
+    /// Raw pixel data (`pitch()` bytes per row).
+    pub fn data(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    pub fn clear(&mut self, color: &Color) {
+        self.fill_rect(0, 0, self.width(), self.height(), color);
+    }
+
+    /// Pixels outside the surface are ignored by all drawing functions.
+    pub fn draw_pixel(&mut self, x: i32, y: i32, color: &Color) {
+        if x < 0 || y < 0 {
+            return;
+        }
+
+        self.lfb.draw_pixel(x as u32, y as u32, color);
+        self.mark_dirty(x as u32, y as u32, 1, 1);
+    }
+
+    /// Draw a line from (`x0`, `y0`) to (`x1`, `y1`) (both included), using Bresenham's algorithm.
+    pub fn draw_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: &Color) {
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let step_x = if x0 < x1 { 1 } else { -1 };
+        let step_y = if y0 < y1 { 1 } else { -1 };
+
+        let (mut x, mut y) = (x0, y0);
+        let mut error = dx + dy;
+        loop {
+            self.draw_pixel(x, y, color);
+            if x == x1 && y == y1 {
+                break;
+            }
+
+            let error2 = 2 * error;
+            if error2 >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if error2 <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    /// Draw the outline of a rectangle.
+    pub fn draw_rect(&mut self, x: u32, y: u32, width: u32, height: u32, color: &Color) {
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        self.fill_rect(x, y, width, 1, color);
+        self.fill_rect(x, y + height - 1, width, 1, color);
+        self.fill_rect(x, y, 1, height, color);
+        self.fill_rect(x + width - 1, y, 1, height, color);
+    }
+
+    pub fn fill_rect(&mut self, x: u32, y: u32, width: u32, height: u32, color: &Color) {
+        self.lfb.fill_rect(x, y, width, height, color);
+        self.mark_dirty(x, y, width, height);
+    }
+
+    /// Draw a character with the built-in 8x16 font. Returns false, if the font does not contain the character.
+    pub fn draw_char(&mut self, x: u32, y: u32, fg_color: &Color, bg_color: &Color, c: char) -> bool {
+        let drawn = self.lfb.draw_char(x, y, fg_color, bg_color, c);
+        if drawn {
+            self.mark_dirty(x, y, CHAR_WIDTH, CHAR_HEIGHT);
+        }
+
+        return drawn;
+    }
+
+    pub fn draw_string(&mut self, x: u32, y: u32, fg_color: &Color, bg_color: &Color, string: &str) {
+        for (index, c) in string.chars().enumerate() {
+            self.draw_char(x + index as u32 * CHAR_WIDTH, y, fg_color, bg_color, c);
+        }
+    }
+
+    /// Copy the whole `source` surface to (`x`, `y`), clipping it at the borders. Both surfaces must have the same pixel format.
+    pub fn blit(&mut self, source: &Surface, x: u32, y: u32) {
+        assert_eq!(self.bpp(), source.bpp(), "Surface: Blitting between different pixel formats is not supported!");
+        self.blit_data(&source.buffer, source.width(), source.height(), source.pitch(), x, y);
+    }
+
+    /// Like `blit()`, but the source is raw pixel data with `width` x `height` pixels and `pitch` bytes per row
+    /// in the pixel format of this surface (e.g. the shared memory of a window).
+    pub fn blit_data(&mut self, data: &[u8], width: u32, height: u32, pitch: u32, x: u32, y: u32) {
+        if x >= self.width() || y >= self.height() {
+            return;
+        }
+
+        let bytes_per_pixel = (self.bpp() as usize).div_ceil(8);
+        let width = min(width, self.width() - x);
+        let height = min(height, self.height() - y);
+        let length = width as usize * bytes_per_pixel;
+
+        for row in 0..height as usize {
+            let source_start = row * pitch as usize;
+            let target_start = (y as usize + row) * self.pitch() as usize + x as usize * bytes_per_pixel;
+            self.buffer[target_start..target_start + length].copy_from_slice(&data[source_start..source_start + length]);
+        }
+
+        self.mark_dirty(x, y, width, height);
+    }
+
+    /// Mark the given area as changed, so that it is copied by the next `present()`.
+    pub fn mark_dirty(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        let end_x = min(x.saturating_add(width), self.width());
+        let end_y = min(y.saturating_add(height), self.height());
+        if x >= end_x || y >= end_y {
+            return;
+        }
+
+        self.dirty = match self.dirty {
+            Some((dirty_x, dirty_y, dirty_end_x, dirty_end_y)) => Some((min(x, dirty_x), min(y, dirty_y), max(end_x, dirty_end_x), max(end_y, dirty_end_y))),
+            None => Some((x, y, end_x, end_y))
+        };
+    }
+
+    /// Copy the changed area to `target` (e.g. the framebuffer), which must have the same pixel format.
+    /// The surface is placed in the upper left corner and clipped, if it is larger than the target.
+    /// There is no synchronization with the display refresh, so tearing may still occur, but no half-drawn frames are shown.
+    pub fn present(&mut self, target: &LFB) {
+        assert_eq!(self.bpp(), target.bpp(), "Surface: Presenting to a framebuffer with a different pixel format is not supported!");
+        self.present_with(target.width(), target.height(), target.pitch(), |offset, data| {
+            unsafe { target.buffer().add(offset).copy_from_nonoverlapping(data.as_ptr(), data.len()); }
+        });
+    }
+
+    /// Like `present()`, but `write` is called for each changed row with the byte offset in the target and the pixel data
+    /// (e.g. for writing to '/dev/fb0' with a target of `target_width` x `target_height` pixels and `target_pitch` bytes per row).
+    pub fn present_with(&mut self, target_width: u32, target_height: u32, target_pitch: u32, mut write: impl FnMut(usize, &[u8])) {
+        let (x, y, end_x, end_y) = match self.dirty.take() {
+            Some(dirty) => dirty,
+            None => return
+        };
+
+        let end_x = min(end_x, target_width);
+        let end_y = min(end_y, target_height);
+        if x >= end_x || y >= end_y {
+            return;
+        }
+
+        let bytes_per_pixel = (self.bpp() as usize).div_ceil(8);
+        let length = (end_x - x) as usize * bytes_per_pixel;
+        for row in y as usize..end_y as usize {
+            let start = row * self.pitch() as usize + x as usize * bytes_per_pixel;
+            write(row * target_pitch as usize + x as usize * bytes_per_pixel, &self.buffer[start..start + length]);
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use core::mem::{size_of, MaybeUninit};
+use syscall::{syscall1, syscall2, syscall3, syscall4, syscall6, SystemCall};
+use syscall::error::{from_syscall_result, Errno};
+use syscall::file::{DescriptorFlags, DirectoryEntry, EventCounterFlags, FileStatus, LockOperation, OpenFlags, PollDescriptor, SeekWhence, WatchEvent, WatchEvents, POLL_INFINITE};
+use syscall::input::{InputEvent, Modifiers};
+use syscall::ioctl::{AudioFormat, AudioVolume, Blit, DisplayMode, FramebufferInfo, IoctlRequest, KeyRepeat, KeyboardLayout, Rectangle, TerminalMode, Termios, WindowGeometry, WindowSize};
+
+/// Open the file at `path` and return its descriptor.
+pub fn open(path: &str, flags: OpenFlags) -> Result<usize, Errno> {
+    return from_syscall_result(syscall3(SystemCall::Open, path.as_bytes().as_ptr() as usize, path.len(), flags.bits()));
+}
+
+pub fn read(fd: usize, buffer: &mut [u8]) -> Result<usize, Errno> {
+    return from_syscall_result(syscall3(SystemCall::Read, fd, buffer.as_mut_ptr() as usize, buffer.len()));
+}
+
+pub fn write(fd: usize, buffer: &[u8]) -> Result<usize, Errno> {
+    return from_syscall_result(syscall3(SystemCall::Write, fd, buffer.as_ptr() as usize, buffer.len()));
+}
+
+pub fn close(fd: usize) -> Result<(), Errno> {
+    return from_syscall_result(syscall1(SystemCall::Close, fd)).map(|_| ());
+}
+
+/// Move the offset of `fd` relative to `whence` and return the new offset.
+pub fn seek(fd: usize, offset: isize, whence: SeekWhence) -> Result<usize, Errno> {
+    return from_syscall_result(syscall3(SystemCall::Seek, fd, offset as usize, whence as usize));
+}
+
+/// Read at `offset` without changing the offset of `fd`.
+pub fn read_at(fd: usize, buffer: &mut [u8], offset: usize) -> Result<usize, Errno> {
+    return from_syscall_result(syscall4(SystemCall::ReadAt, fd, buffer.as_mut_ptr() as usize, buffer.len(), offset));
+}
+
+/// Write at `offset` without changing the offset of `fd` (also, if it has been opened with `OpenFlags::APPEND`).
+pub fn write_at(fd: usize, buffer: &[u8], offset: usize) -> Result<usize, Errno> {
+    return from_syscall_result(syscall4(SystemCall::WriteAt, fd, buffer.as_ptr() as usize, buffer.len(), offset));
+}
+
+/// Set the size of the regular file `fd` to `size`, cutting off data or filling it up with zeros.
+pub fn truncate(fd: usize, size: usize) -> Result<(), Errno> {
+    return from_syscall_result(syscall2(SystemCall::Truncate, fd, size)).map(|_| ());
+}
+
+/// Get the metadata (type, size, timestamps) of the file at `path`.
+pub fn stat(path: &str) -> Result<FileStatus, Errno> {
+    let mut status = MaybeUninit::<FileStatus>::uninit();
+    from_syscall_result(syscall3(SystemCall::Stat, path.as_bytes().as_ptr() as usize, path.len(), status.as_mut_ptr() as usize))?;
+
+    return Ok(unsafe { status.assume_init() });
+}
+
+/// Read the entries of the directory `fd`, starting with the entry at `index`, into `entries` and return the number of entries read
+/// (0, once all entries have been read).
+pub fn read_directory(fd: usize, entries: &mut [DirectoryEntry], index: usize) -> Result<usize, Errno> {
+    return from_syscall_result(syscall4(SystemCall::ReadDirectory, fd, entries.as_mut_ptr() as usize, entries.len(), index));
+}
+
+/// Get the metadata of the open file `fd`.
+pub fn fstat(fd: usize) -> Result<FileStatus, Errno> {
+    let mut status = MaybeUninit::<FileStatus>::uninit();
+    from_syscall_result(syscall2(SystemCall::FileStat, fd, status.as_mut_ptr() as usize))?;
+
+    return Ok(unsafe { status.assume_init() });
+}
+
+pub fn mkdir(path: &str) -> Result<(), Errno> {
+    return from_syscall_result(syscall2(SystemCall::MakeDirectory, path.as_bytes().as_ptr() as usize, path.len())).map(|_| ());
+}
+
+/// Create a FIFO (named pipe) at `path`, which connects processes opening it for reading and for writing.
+/// Opening one end blocks until the other end has been opened as well.
+pub fn mkfifo(path: &str) -> Result<(), Errno> {
+    return from_syscall_result(syscall2(SystemCall::MakeFifo, path.as_bytes().as_ptr() as usize, path.len())).map(|_| ());
+}
+
+/// Remove the empty directory at `path`.
+pub fn rmdir(path: &str) -> Result<(), Errno> {
+    return from_syscall_result(syscall2(SystemCall::RemoveDirectory, path.as_bytes().as_ptr() as usize, path.len())).map(|_| ());
+}
+
+/// Remove the file at `path` (use `rmdir()` for directories).
+pub fn unlink(path: &str) -> Result<(), Errno> {
+    return from_syscall_result(syscall2(SystemCall::Unlink, path.as_bytes().as_ptr() as usize, path.len())).map(|_| ());
+}
+
+/// Move the file or directory at `old_path` to `new_path` (both must be located in the same filesystem).
+pub fn rename(old_path: &str, new_path: &str) -> Result<(), Errno> {
+    return from_syscall_result(syscall4(SystemCall::Rename, old_path.as_bytes().as_ptr() as usize, old_path.len(),
+        new_path.as_bytes().as_ptr() as usize, new_path.len())).map(|_| ());
+}
+
+/// Return a new descriptor (the lowest free one), referring to the same open file as `fd`.
+pub fn dup(fd: usize) -> Result<usize, Errno> {
+    return from_syscall_result(syscall1(SystemCall::Dup, fd));
+}
+
+/// Let `new_fd` refer to the same open file as `fd` (closing the file previously opened as `new_fd`).
+/// This way, the standard descriptors can be redirected before starting an application.
+pub fn dup2(fd: usize, new_fd: usize, flags: DescriptorFlags) -> Result<usize, Errno> {
+    return from_syscall_result(syscall3(SystemCall::Dup2, fd, new_fd, flags.bits()));
+}
+
+pub fn descriptor_flags(fd: usize) -> Result<DescriptorFlags, Errno> {
+    return from_syscall_result(syscall1(SystemCall::GetDescriptorFlags, fd)).map(DescriptorFlags::from_bits_truncate);
+}
+
+pub fn set_descriptor_flags(fd: usize, flags: DescriptorFlags) -> Result<(), Errno> {
+    return from_syscall_result(syscall2(SystemCall::SetDescriptorFlags, fd, flags.bits())).map(|_| ());
+}
+
+/// Mount a new filesystem of type `fstype` (e.g. 'tmpfs' or 'iso9660') at the directory `target`.
+/// `source` is the block device (e.g. '/dev/ata0') for filesystems stored on a disk and only informational otherwise.
+/// All mounted filesystems are listed in '/proc/mounts'.
+pub fn mount(source: &str, target: &str, fstype: &str) -> Result<(), Errno> {
+    return from_syscall_result(syscall6(SystemCall::Mount, source.as_bytes().as_ptr() as usize, source.len(),
+        target.as_bytes().as_ptr() as usize, target.len(), fstype.as_bytes().as_ptr() as usize, fstype.len())).map(|_| ());
+}
+
+pub fn unmount(target: &str) -> Result<(), Errno> {
+    return from_syscall_result(syscall2(SystemCall::Unmount, target.as_bytes().as_ptr() as usize, target.len())).map(|_| ());
+}
+
+/// Write modified data of the file `fd` back to its device, before returning.
+/// Otherwise, data written to a disk is only written back periodically by the kernel.
+pub fn fsync(fd: usize) -> Result<(), Errno> {
+    return from_syscall_result(syscall1(SystemCall::Fsync, fd)).map(|_| ());
+}
+
+/// Acquire or release an advisory lock on the whole file `fd`. The lock is shared by all descriptors referring to the same open file
+/// and released automatically, when the file is closed (at the latest, when the process exits).
+pub fn lock(fd: usize, operation: LockOperation) -> Result<(), Errno> {
+    return from_syscall_result(syscall2(SystemCall::Lock, fd, operation.bits())).map(|_| ());
+}
+
+/// Create a pipe and return the descriptors of its reading and writing end.
+/// Data written into the second descriptor can be read from the first one (e.g. by an application started with the descriptor as stdin).
+pub fn pipe(flags: DescriptorFlags) -> Result<(usize, usize), Errno> {
+    let mut fds = [0usize; 2];
+    from_syscall_result(syscall2(SystemCall::Pipe, fds.as_mut_ptr() as usize, flags.bits()))?;
+
+    return Ok((fds[0], fds[1]));
+}
+
+/// Create an event counter with the value `initial` and return its descriptor. Writing 8 bytes adds their value to the counter,
+/// reading 8 bytes waits for a value other than 0 and returns it, resetting the counter (see `EventCounterFlags::SEMAPHORE`).
+pub fn event_counter(initial: u64, flags: EventCounterFlags) -> Result<usize, Errno> {
+    return from_syscall_result(syscall2(SystemCall::EventCounter, initial as usize, flags.bits()));
+}
+
+/// Signal an event counter by adding `value` to it.
+pub fn signal_event(fd: usize, value: u64) -> Result<(), Errno> {
+    return write(fd, &value.to_ne_bytes()).map(|_| ());
+}
+
+/// Wait for an event counter to become non-zero and return its value (or 1 in semaphore mode).
+pub fn wait_event(fd: usize) -> Result<u64, Errno> {
+    let mut value = [0u8; 8];
+    read(fd, &mut value)?;
+
+    return Ok(u64::from_ne_bytes(value));
+}
+
+/// Wait until at least one of the descriptors is ready for the requested events or `timeout_ms` milliseconds have passed
+/// (`None` waits forever). The ready events are stored in each descriptor and the number of ready descriptors is returned.
+pub fn poll(descriptors: &mut [PollDescriptor], timeout_ms: Option<usize>) -> Result<usize, Errno> {
+    return from_syscall_result(syscall3(SystemCall::Poll, descriptors.as_mut_ptr() as usize, descriptors.len(), timeout_ms.unwrap_or(POLL_INFINITE)));
+}
+
+/// Pass a control request on to the driver of the file `fd`. The meaning of `arg` and the result depend on the request.
+pub fn ioctl(fd: usize, request: IoctlRequest, arg: usize) -> Result<usize, Errno> {
+    return from_syscall_result(syscall3(SystemCall::Ioctl, fd, request as usize, arg));
+}
+
+/// Get the number of columns and rows of the terminal `fd`.
+pub fn window_size(fd: usize) -> Result<WindowSize, Errno> {
+    let mut size = MaybeUninit::<WindowSize>::uninit();
+    ioctl(fd, IoctlRequest::GetWindowSize, size.as_mut_ptr() as usize)?;
+
+    return Ok(unsafe { size.assume_init() });
+}
+
+pub fn set_terminal_mode(fd: usize, mode: TerminalMode) -> Result<(), Errno> {
+    return ioctl(fd, IoctlRequest::SetTerminalMode, mode as usize).map(|_| ());
+}
+
+/// Get the line discipline settings of the terminal `fd`.
+pub fn termios(fd: usize) -> Result<Termios, Errno> {
+    let mut termios = MaybeUninit::<Termios>::uninit();
+    ioctl(fd, IoctlRequest::GetTermios, termios.as_mut_ptr() as usize)?;
+
+    return Ok(unsafe { termios.assume_init() });
+}
+
+pub fn set_termios(fd: usize, termios: Termios) -> Result<(), Errno> {
+    return ioctl(fd, IoctlRequest::SetTermios, &termios as *const Termios as usize).map(|_| ());
+}
+
+pub fn keyboard_layout(fd: usize) -> Result<KeyboardLayout, Errno> {
+    let layout = ioctl(fd, IoctlRequest::GetKeyboardLayout, 0)?;
+    return KeyboardLayout::try_from(layout).map_err(|_| Errno::InvalidArgument);
+}
+
+pub fn set_keyboard_layout(fd: usize, layout: KeyboardLayout) -> Result<(), Errno> {
+    return ioctl(fd, IoctlRequest::SetKeyboardLayout, layout as usize).map(|_| ());
+}
+
+/// Get the modifier keys, which are held down, and the active lock keys of the keyboard, belonging to the terminal `fd`.
+pub fn keyboard_modifiers(fd: usize) -> Result<Modifiers, Errno> {
+    return ioctl(fd, IoctlRequest::GetKeyboardModifiers, 0).map(|bits| Modifiers::from_bits_truncate(bits as u8));
+}
+
+pub fn key_repeat(fd: usize) -> Result<KeyRepeat, Errno> {
+    let mut repeat = MaybeUninit::<KeyRepeat>::uninit();
+    ioctl(fd, IoctlRequest::GetKeyRepeat, repeat.as_mut_ptr() as usize)?;
+
+    return Ok(unsafe { repeat.assume_init() });
+}
+
+pub fn set_key_repeat(fd: usize, repeat: KeyRepeat) -> Result<(), Errno> {
+    return ioctl(fd, IoctlRequest::SetKeyRepeat, &repeat as *const KeyRepeat as usize).map(|_| ());
+}
+
+/// Get the resolution and pixel format of a framebuffer device (e.g. '/dev/fb0').
+pub fn framebuffer_info(fd: usize) -> Result<FramebufferInfo, Errno> {
+    let mut info = MaybeUninit::<FramebufferInfo>::uninit();
+    ioctl(fd, IoctlRequest::GetFramebufferInfo, info.as_mut_ptr() as usize)?;
+
+    return Ok(unsafe { info.assume_init() });
+}
+
+/// Switch the graphics device `fd` (e.g. '/dev/gpu0') to `mode` and show its framebuffer, until `fd` is closed.
+/// Before a mode is set, `framebuffer_info()` returns the preferred mode of the display.
+pub fn set_display_mode(fd: usize, mode: DisplayMode) -> Result<(), Errno> {
+    return ioctl(fd, IoctlRequest::SetDisplayMode, &mode as *const DisplayMode as usize).map(|_| ());
+}
+
+/// Show the changes, which have been drawn into `area` of the framebuffer of the graphics device `fd` (or the whole framebuffer, if `None`).
+pub fn flush_framebuffer(fd: usize, area: Option<Rectangle>) -> Result<(), Errno> {
+    let arg = area.as_ref().map_or(0, |area| area as *const Rectangle as usize);
+    return ioctl(fd, IoctlRequest::FlushFramebuffer, arg).map(|_| ());
+}
+
+/// Copy the `source` area of the framebuffer of the graphics device `fd` to (`x`, `y`) and show the result.
+/// Faster than drawing the area again, e.g. for scrolling, since the pixels are neither copied nor flushed by the application.
+pub fn blit_framebuffer(fd: usize, source: Rectangle, x: u32, y: u32) -> Result<(), Errno> {
+    let blit = Blit { source, x, y };
+    return ioctl(fd, IoctlRequest::BlitFramebuffer, &blit as *const Blit as usize).map(|_| ());
+}
+
+/// Create a window with the given position and size on an opened '/dev/window'. Its title is set by writing to `fd`.
+/// Input events for the window are read from `fd` (see `read_input_event()`), while it has the focus.
+pub fn create_window(fd: usize, geometry: WindowGeometry) -> Result<(), Errno> {
+    return ioctl(fd, IoctlRequest::CreateWindow, &geometry as *const WindowGeometry as usize).map(|_| ());
+}
+
+/// Show the changes, which have been drawn into the surface of the window `fd` (mapped with `map_file()`).
+pub fn present_window(fd: usize) -> Result<(), Errno> {
+    return ioctl(fd, IoctlRequest::PresentWindow, 0).map(|_| ());
+}
+
+/// Get the format of the samples, which are written to the audio device `fd` (e.g. '/dev/audio0').
+pub fn audio_format(fd: usize) -> Result<AudioFormat, Errno> {
+    let mut format = MaybeUninit::<AudioFormat>::uninit();
+    ioctl(fd, IoctlRequest::GetAudioFormat, format.as_mut_ptr() as usize)?;
+
+    return Ok(unsafe { format.assume_init() });
+}
+
+/// Play the following samples written to the audio device `fd` in `format`. Fails with `Errno::InvalidArgument`, if the device does not support it.
+pub fn set_audio_format(fd: usize, format: AudioFormat) -> Result<(), Errno> {
+    return ioctl(fd, IoctlRequest::SetAudioFormat, &format as *const AudioFormat as usize).map(|_| ());
+}
+
+pub fn audio_volume(fd: usize) -> Result<AudioVolume, Errno> {
+    let mut volume = MaybeUninit::<AudioVolume>::uninit();
+    ioctl(fd, IoctlRequest::GetAudioVolume, volume.as_mut_ptr() as usize)?;
+
+    return Ok(unsafe { volume.assume_init() });
+}
+
+pub fn set_audio_volume(fd: usize, volume: AudioVolume) -> Result<(), Errno> {
+    return ioctl(fd, IoctlRequest::SetAudioVolume, &volume as *const AudioVolume as usize).map(|_| ());
+}
+
+/// Map `length` bytes of the file `fd` at `offset` (a multiple of the page size) into memory and return a pointer to the mapping.
+/// Only device memory can be mapped (e.g. '/dev/fb0', which must be opened for writing). The mapping stays, until the process exits.
+pub fn map_file(fd: usize, offset: usize, length: usize) -> Result<*mut u8, Errno> {
+    return from_syscall_result(syscall3(SystemCall::MapFile, fd, offset, length)).map(|address| address as *mut u8);
+}
+
+/// Create a watcher and return its descriptor. Events for the watched files are read from it (see `read_watch_event()`).
+pub fn watch_create(flags: DescriptorFlags) -> Result<usize, Errno> {
+    return from_syscall_result(syscall1(SystemCall::WatchCreate, flags.bits()));
+}
+
+/// Watch the file or directory at `path` for `events` and return the id of the watch.
+/// For directories, events are reported for their entries, including the name of the affected entry.
+pub fn watch_add(fd: usize, path: &str, events: WatchEvents) -> Result<usize, Errno> {
+    return from_syscall_result(syscall4(SystemCall::WatchAdd, fd, path.as_bytes().as_ptr() as usize, path.len(), events.bits()));
+}
+
+pub fn watch_remove(fd: usize, watch: usize) -> Result<(), Errno> {
+    return from_syscall_result(syscall2(SystemCall::WatchRemove, fd, watch)).map(|_| ());
+}
+
+/// Block until the next event is available on the watcher `fd` and return it.
+pub fn read_watch_event(fd: usize) -> Result<WatchEvent, Errno> {
+    let mut event = MaybeUninit::<WatchEvent>::uninit();
+    let buffer = unsafe { core::slice::from_raw_parts_mut(event.as_mut_ptr() as *mut u8, size_of::<WatchEvent>()) };
+    read(fd, buffer)?;
+
+    return Ok(unsafe { event.assume_init() });
+}
+
+/// Block until the next event is available on the input device `fd` (opened from '/dev/input/eventN' or a window) and return it.
+pub fn read_input_event(fd: usize) -> Result<InputEvent, Errno> {
+    let mut event = MaybeUninit::<InputEvent>::uninit();
+    let buffer = unsafe { core::slice::from_raw_parts_mut(event.as_mut_ptr() as *mut u8, size_of::<InputEvent>()) };
+    read(fd, buffer)?;
+
+    return Ok(unsafe { event.assume_init() });
+}
@@ -1,4 +1,8 @@
 #![no_std]
 
+pub mod file;
 pub mod write;
-pub mod read;
\ No newline at end of file
+pub mod read;
+pub mod random;
+pub mod net;
+pub mod mqueue;
\ No newline at end of file
@@ -0,0 +1,31 @@
+use core::ptr;
+use syscall::{syscall2, syscall4, SystemCall};
+use syscall::error::{from_syscall_result, Errno};
+use syscall::file::OpenFlags;
+use syscall::mqueue::QueueAttributes;
+
+/// Open the message queue called `name` (e.g. '/jobs') and return its descriptor. With `OpenFlags::CREATE`, a missing queue
+/// is created with `attributes` (or default attributes, if `None`). `OpenFlags::READ` and `OpenFlags::WRITE` allow receiving and sending.
+pub fn open(name: &str, flags: OpenFlags, attributes: Option<QueueAttributes>) -> Result<usize, Errno> {
+    let attributes = attributes.as_ref().map_or(ptr::null(), ptr::from_ref);
+    return from_syscall_result(syscall4(SystemCall::MessageQueueOpen, name.as_ptr() as usize, name.len(), flags.bits(), attributes as usize));
+}
+
+/// Remove the message queue called `name`. Processes, which have already opened it, can still use it.
+pub fn unlink(name: &str) -> Result<(), Errno> {
+    return from_syscall_result(syscall2(SystemCall::MessageQueueUnlink, name.as_ptr() as usize, name.len())).map(|_| ());
+}
+
+/// Send `message` to the queue `fd` with `priority` (0 to `MAX_PRIORITY`). Blocks, as long as the queue is full.
+pub fn send(fd: usize, message: &[u8], priority: usize) -> Result<(), Errno> {
+    return from_syscall_result(syscall4(SystemCall::MessageQueueSend, fd, message.as_ptr() as usize, message.len(), priority)).map(|_| ());
+}
+
+/// Receive the oldest message with the highest priority from the queue `fd` and return its length and priority.
+/// Blocks, as long as the queue is empty. `buffer` must be able to hold a message of the queue's maximum message size.
+pub fn receive(fd: usize, buffer: &mut [u8]) -> Result<(usize, usize), Errno> {
+    let mut priority = 0usize;
+    let length = from_syscall_result(syscall4(SystemCall::MessageQueueReceive, fd, buffer.as_mut_ptr() as usize, buffer.len(), ptr::from_mut(&mut priority) as usize))?;
+
+    return Ok((length, priority));
+}
@@ -0,0 +1,93 @@
+use core::mem::MaybeUninit;
+use syscall::{syscall2, syscall3, syscall4, SystemCall};
+use syscall::error::{from_syscall_result, Errno};
+use syscall::file::DescriptorFlags;
+use syscall::ioctl::{InterfaceAddress, IoctlRequest, RouteEntry, INTERFACE_NAME_SIZE};
+use syscall::net::{Protocol, SocketAddress, SocketType};
+use crate::file::ioctl;
+
+/// Create a socket and return its descriptor (e.g. `SocketType::Stream` with `Protocol::Tcp`).
+pub fn socket(typ: SocketType, protocol: Protocol, flags: DescriptorFlags) -> Result<usize, Errno> {
+    return from_syscall_result(syscall3(SystemCall::Socket, typ as usize, protocol as usize, flags.bits()));
+}
+
+/// Assign a local address to the socket `fd`. Port 0 chooses a free port.
+pub fn bind(fd: usize, address: SocketAddress) -> Result<(), Errno> {
+    return from_syscall_result(syscall3(SystemCall::Bind, fd, &address as *const SocketAddress as usize, size_of::<SocketAddress>())).map(|_| ());
+}
+
+/// Bind the local socket `fd` (see `Protocol::Unix`) to a new socket node at `path`.
+pub fn bind_unix(fd: usize, path: &str) -> Result<(), Errno> {
+    return from_syscall_result(syscall3(SystemCall::Bind, fd, path.as_ptr() as usize, path.len())).map(|_| ());
+}
+
+/// Connect the socket `fd` to `address`. Blocks, until the connection has been established.
+pub fn connect(fd: usize, address: SocketAddress) -> Result<(), Errno> {
+    return from_syscall_result(syscall3(SystemCall::Connect, fd, &address as *const SocketAddress as usize, size_of::<SocketAddress>())).map(|_| ());
+}
+
+/// Connect the local socket `fd` to the socket listening on the socket node at `path`.
+pub fn connect_unix(fd: usize, path: &str) -> Result<(), Errno> {
+    return from_syscall_result(syscall3(SystemCall::Connect, fd, path.as_ptr() as usize, path.len())).map(|_| ());
+}
+
+/// Accept connections on the socket `fd`, keeping at most `backlog` of them waiting for `accept()`.
+pub fn listen(fd: usize, backlog: usize) -> Result<(), Errno> {
+    return from_syscall_result(syscall2(SystemCall::Listen, fd, backlog)).map(|_| ());
+}
+
+/// Wait for a connection on the listening socket `fd` and return its descriptor together with the peer's address.
+pub fn accept(fd: usize, flags: DescriptorFlags) -> Result<(usize, SocketAddress), Errno> {
+    let mut address = MaybeUninit::<SocketAddress>::uninit();
+    let connection = from_syscall_result(syscall3(SystemCall::Accept, fd, address.as_mut_ptr() as usize, flags.bits()))?;
+
+    return Ok((connection, unsafe { address.assume_init() }));
+}
+
+/// Send data on the connected socket `fd` and return how much of it has been queued.
+pub fn send(fd: usize, data: &[u8]) -> Result<usize, Errno> {
+    return from_syscall_result(syscall3(SystemCall::Send, fd, data.as_ptr() as usize, data.len()));
+}
+
+/// Receive data from the connected socket `fd`. Returns 0, once the peer has closed the connection.
+pub fn receive(fd: usize, buffer: &mut [u8]) -> Result<usize, Errno> {
+    return from_syscall_result(syscall3(SystemCall::Receive, fd, buffer.as_mut_ptr() as usize, buffer.len()));
+}
+
+/// Send `data` as a single datagram from the socket `fd` to `address`.
+pub fn send_to(fd: usize, data: &[u8], address: SocketAddress) -> Result<usize, Errno> {
+    return from_syscall_result(syscall4(SystemCall::SendTo, fd, data.as_ptr() as usize, data.len(), &address as *const SocketAddress as usize));
+}
+
+/// Receive a single datagram on the socket `fd` and return its length (truncated to the size of `buffer`) together with the sender's address.
+pub fn receive_from(fd: usize, buffer: &mut [u8]) -> Result<(usize, SocketAddress), Errno> {
+    let mut address = MaybeUninit::<SocketAddress>::uninit();
+    let count = from_syscall_result(syscall4(SystemCall::ReceiveFrom, fd, buffer.as_mut_ptr() as usize, buffer.len(), address.as_mut_ptr() as usize))?;
+
+    return Ok((count, unsafe { address.assume_init() }));
+}
+
+/// Add a route to the routing table of the kernel (`fd` may be any socket).
+pub fn add_route(fd: usize, route: RouteEntry) -> Result<(), Errno> {
+    return ioctl(fd, IoctlRequest::AddRoute, &route as *const RouteEntry as usize).map(|_| ());
+}
+
+/// Remove the route for the destination network of `route` (`fd` may be any socket).
+pub fn delete_route(fd: usize, route: RouteEntry) -> Result<(), Errno> {
+    return ioctl(fd, IoctlRequest::DeleteRoute, &route as *const RouteEntry as usize).map(|_| ());
+}
+
+/// Configure the address of an interface by hand, stopping its DHCP client (`fd` may be any socket).
+pub fn set_interface_address(fd: usize, address: InterfaceAddress) -> Result<(), Errno> {
+    return ioctl(fd, IoctlRequest::SetInterfaceAddress, &address as *const InterfaceAddress as usize).map(|_| ());
+}
+
+/// Start capturing the frames of `interface` (all interfaces, if `None`) on the opened '/dev/pcap' `fd`.
+pub fn start_capture(fd: usize, interface: Option<[u8; INTERFACE_NAME_SIZE]>) -> Result<(), Errno> {
+    let arg = interface.as_ref().map_or(0, |name| name as *const [u8; INTERFACE_NAME_SIZE] as usize);
+    return ioctl(fd, IoctlRequest::StartCapture, arg).map(|_| ());
+}
+
+pub fn stop_capture(fd: usize) -> Result<(), Errno> {
+    return ioctl(fd, IoctlRequest::StopCapture, 0).map(|_| ());
+}
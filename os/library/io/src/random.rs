@@ -0,0 +1,7 @@
+use syscall::{syscall2, SystemCall};
+use syscall::error::{from_syscall_result, Errno};
+
+/// Fill `buffer` with cryptographically secure random bytes from the kernel (like `getrandom()`).
+pub fn get_random(buffer: &mut [u8]) -> Result<usize, Errno> {
+    return from_syscall_result(syscall2(SystemCall::GetRandom, buffer.as_mut_ptr() as usize, buffer.len()));
+}
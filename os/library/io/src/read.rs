@@ -1,5 +1,85 @@
-use syscall::{syscall0, SystemCall};
+use syscall::file::STDIN;
+use crate::file;
 
 pub fn read() -> char {
-    char::from_u32(syscall0(SystemCall::Read) as u32).unwrap()
-}
\ No newline at end of file
+    let mut buffer = [0u8; 1];
+    match file::read(STDIN, &mut buffer) {
+        Ok(1) => char::from(buffer[0]),
+        _ => panic!("Failed to read from standard input!")
+    }
+}
+
+/// Key without a character, which the terminal sends as an escape sequence (see `keymap::escape_sequence()` in the kernel).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EscapeKey {
+    Up,
+    Down,
+    Right,
+    Left,
+    Home,
+    End,
+    Delete,
+    PageUp,
+    PageDown,
+    Unknown,
+}
+
+/// Read a single byte from the standard input (e.g. in raw mode). Returns `None` at the end of the input.
+pub fn read_byte() -> Option<u8> {
+    let mut buffer = [0u8; 1];
+    return match file::read(STDIN, &mut buffer) {
+        Ok(1) => Some(buffer[0]),
+        _ => None
+    };
+}
+
+/// Decode a UTF-8 character, whose first byte is `first` (the remaining bytes are read from the standard input).
+pub fn read_utf8(first: u8) -> Option<char> {
+    let length = match first {
+        0x00..=0x7f => 1,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return None
+    };
+
+    let mut bytes = [first, 0, 0, 0];
+    for byte in bytes.iter_mut().take(length).skip(1) {
+        *byte = read_byte()?;
+    }
+
+    return core::str::from_utf8(&bytes[..length]).ok()?.chars().next();
+}
+
+/// Read the rest of an escape sequence, after the escape character has been read. Returns `None` at the end of the input.
+pub fn read_escape_sequence() -> Option<EscapeKey> {
+    if !matches!(read_byte()?, b'[' | b'O') {
+        return Some(EscapeKey::Unknown);
+    }
+
+    let mut parameter = 0usize;
+    loop {
+        let key = match read_byte()? {
+            byte @ b'0'..=b'9' => {
+                parameter = parameter.saturating_mul(10).saturating_add((byte - b'0') as usize);
+                continue;
+            }
+            b'A' => EscapeKey::Up,
+            b'B' => EscapeKey::Down,
+            b'C' => EscapeKey::Right,
+            b'D' => EscapeKey::Left,
+            b'H' => EscapeKey::Home,
+            b'F' => EscapeKey::End,
+            b'~' => match parameter {
+                3 => EscapeKey::Delete,
+                5 => EscapeKey::PageUp,
+                6 => EscapeKey::PageDown,
+                _ => EscapeKey::Unknown
+            },
+            0x40..=0x7e => EscapeKey::Unknown,
+            _ => continue
+        };
+
+        return Some(key);
+    }
+}
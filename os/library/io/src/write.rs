@@ -1,7 +1,8 @@
 use core::fmt;
 use core::fmt::Write;
 use spin::Mutex;
-use syscall::{syscall2, SystemCall};
+use syscall::file::STDOUT;
+use crate::file;
 
 #[macro_export]
 macro_rules! print {
@@ -12,8 +13,8 @@ macro_rules! print {
 
 #[macro_export]
 macro_rules! println {
-    ($fmt:expr) => (print!(concat!($fmt, "\n")));
-    ($fmt:expr, $($arg:tt)*) => (print!(concat!($fmt, "\n"), $($arg)*));
+    ($fmt:expr) => ($crate::print!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => ($crate::print!(concat!($fmt, "\n"), $($arg)*));
 }
 
 static WRITER: Mutex<Writer> = Mutex::new(Writer::new());
@@ -32,7 +33,9 @@ impl Writer {
 
 impl Write for Writer {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        syscall2(SystemCall::Write, s.as_bytes().as_ptr() as usize, s.len());
-        return Ok(());
+        return match file::write(STDOUT, s.as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(fmt::Error)
+        };
     }
 }
\ No newline at end of file
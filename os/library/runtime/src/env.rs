@@ -0,0 +1,57 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ptr;
+use syscall::{syscall2, syscall4, SystemCall};
+use syscall::error::{from_syscall_result, Errno};
+
+/// Environment variables of the current process, sorted by name (like `std::env::vars()`).
+/// Applications started by the current process inherit a copy of them.
+pub fn vars() -> Vec<(String, String)> {
+    return read_strings(|buffer| syscall2(SystemCall::GetEnvironment, buffer.as_mut_ptr() as usize, buffer.len())).iter()
+        .filter_map(|entry| entry.split_once('='))
+        .map(|(name, value)| (String::from(name), String::from(value)))
+        .collect();
+}
+
+/// Command line of the current process, starting with the path of the application (like `std::env::args()`).
+/// A script gets the path of its interpreter first, followed by the path of the script.
+pub fn args() -> Vec<String> {
+    return read_strings(|buffer| syscall2(SystemCall::GetArguments, buffer.as_mut_ptr() as usize, buffer.len()));
+}
+
+pub fn var(name: &str) -> Option<String> {
+    return vars().into_iter().find(|(key, _)| key == name).map(|(_, value)| value);
+}
+
+/// Fails with `Errno::InvalidArgument`, if `name` is empty or contains '=' or if one of the strings contains a null byte.
+pub fn set_var(name: &str, value: &str) -> Result<(), Errno> {
+    let result = syscall4(SystemCall::SetEnvironment, name.as_ptr() as usize, name.len(), value.as_ptr() as usize, value.len());
+    return from_syscall_result(result).map(|_| ());
+}
+
+pub fn remove_var(name: &str) -> Result<(), Errno> {
+    let result = syscall4(SystemCall::SetEnvironment, name.as_ptr() as usize, name.len(), ptr::null::<u8>() as usize, 0);
+    return from_syscall_result(result).map(|_| ());
+}
+
+/// Read null-terminated strings with `call`, which fills a buffer and returns the size needed for all of them.
+fn read_strings(call: impl Fn(&mut [u8]) -> usize) -> Vec<String> {
+    let mut buffer = vec![0u8; 256];
+    loop {
+        let length = from_syscall_result(call(&mut buffer)).unwrap_or(0);
+        if length <= buffer.len() {
+            buffer.truncate(length);
+            break;
+        }
+
+        // The strings do not fit into the buffer (they may also have grown in the meantime, so the call is repeated)
+        buffer.resize(length, 0);
+    }
+
+    let Some(strings) = buffer.strip_suffix(&[0]) else {
+        return Vec::new();
+    };
+
+    return strings.split(|byte| *byte == 0).map(|string| String::from_utf8_lossy(string).into_owned()).collect();
+}
@@ -0,0 +1,149 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::slice;
+use io::file;
+use syscall::error::Errno;
+use syscall::file::{DescriptorFlags, DirectoryEntry, FileStatus, OpenFlags, SeekWhence};
+
+pub use io::file::{mkdir, rename, rmdir, stat, unlink};
+
+/// Open file, which is closed, when it is dropped (like `std::fs::File`).
+pub struct File {
+    fd: usize
+}
+
+impl File {
+    /// Open an existing file for reading.
+    pub fn open(path: &str) -> Result<Self, Errno> {
+        return Self::with_flags(path, OpenFlags::READ);
+    }
+
+    /// Open a file for writing, creating it, if it does not exist, and truncating it otherwise.
+    pub fn create(path: &str) -> Result<Self, Errno> {
+        return Self::with_flags(path, OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE);
+    }
+
+    pub fn with_flags(path: &str, flags: OpenFlags) -> Result<Self, Errno> {
+        return file::open(path, flags).map(|fd| Self { fd });
+    }
+
+    /// Take ownership of the open descriptor `fd` (e.g. one of the standard descriptors).
+    pub fn from_descriptor(fd: usize) -> Self {
+        return Self { fd };
+    }
+
+    pub fn descriptor(&self) -> usize {
+        return self.fd;
+    }
+
+    /// Give up ownership of the descriptor without closing it.
+    pub fn into_descriptor(self) -> usize {
+        let fd = self.fd;
+        core::mem::forget(self);
+
+        return fd;
+    }
+
+    pub fn read(&self, buffer: &mut [u8]) -> Result<usize, Errno> {
+        return file::read(self.fd, buffer);
+    }
+
+    pub fn write(&self, buffer: &[u8]) -> Result<usize, Errno> {
+        return file::write(self.fd, buffer);
+    }
+
+    /// Write all of `buffer`, even if the file accepts less data at once (e.g. a pipe).
+    pub fn write_all(&self, mut buffer: &[u8]) -> Result<(), Errno> {
+        while !buffer.is_empty() {
+            match self.write(buffer)? {
+                0 => return Err(Errno::IoError),
+                count => buffer = &buffer[count..]
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// Read until the end of the file and append the data to `buffer`. Returns the number of bytes read.
+    pub fn read_to_end(&self, buffer: &mut Vec<u8>) -> Result<usize, Errno> {
+        let start = buffer.len();
+        let mut chunk = [0u8; 512];
+        loop {
+            match self.read(&mut chunk)? {
+                0 => return Ok(buffer.len() - start),
+                count => buffer.extend_from_slice(&chunk[..count])
+            }
+        }
+    }
+
+    pub fn seek(&self, offset: isize, whence: SeekWhence) -> Result<usize, Errno> {
+        return file::seek(self.fd, offset, whence);
+    }
+
+    pub fn stat(&self) -> Result<FileStatus, Errno> {
+        return file::fstat(self.fd);
+    }
+
+    pub fn sync(&self) -> Result<(), Errno> {
+        return file::fsync(self.fd);
+    }
+
+    /// Map `length` bytes of the file, starting at `offset`, into memory (like `mmap()`). The mapping stays valid,
+    /// until the process exits (even after the file has been closed).
+    pub fn map(&self, offset: usize, length: usize) -> Result<&'static mut [u8], Errno> {
+        let address = file::map_file(self.fd, offset, length)?;
+        return Ok(unsafe { slice::from_raw_parts_mut(address, length) });
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        file::close(self.fd).ok();
+    }
+}
+
+impl fmt::Write for File {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        return self.write_all(s.as_bytes()).map_err(|_| fmt::Error);
+    }
+}
+
+/// Create a pipe and return its read and write end. Both ends are not inherited by applications started afterward
+/// (otherwise the reader would not see the end of the data, while an unrelated application keeps the write end open).
+/// To connect an application to a pipe, duplicate the end onto one of its standard descriptors with `dup2()`.
+pub fn pipe() -> Result<(File, File), Errno> {
+    let (reader, writer) = file::pipe(DescriptorFlags::CLOSE_ON_EXEC)?;
+    return Ok((File::from_descriptor(reader), File::from_descriptor(writer)));
+}
+
+/// Read the whole file at `path`.
+pub fn read(path: &str) -> Result<Vec<u8>, Errno> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+
+    return Ok(data);
+}
+
+/// Read the whole file at `path`, which must contain UTF-8 text (fails with `Errno::InvalidArgument` otherwise).
+pub fn read_to_string(path: &str) -> Result<String, Errno> {
+    return String::from_utf8(read(path)?).map_err(|_| Errno::InvalidArgument);
+}
+
+/// Read all entries of the directory at `path` (like `std::fs::read_dir()`).
+pub fn read_dir(path: &str) -> Result<Vec<DirectoryEntry>, Errno> {
+    let directory = File::with_flags(path, OpenFlags::READ | OpenFlags::DIRECTORY)?;
+    let mut entries = Vec::new();
+    let mut chunk = [DirectoryEntry::empty(); 16];
+    loop {
+        match file::read_directory(directory.descriptor(), &mut chunk, entries.len())? {
+            0 => return Ok(entries),
+            count => entries.extend_from_slice(&chunk[..count])
+        }
+    }
+}
+
+/// Replace the contents of the file at `path` with `data` (creating it, if it does not exist).
+pub fn write(path: &str, data: &[u8]) -> Result<(), Errno> {
+    return File::create(path)?.write_all(data);
+}
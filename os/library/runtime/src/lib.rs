@@ -1,31 +1,12 @@
 #![no_std]
 
-use core::panic::PanicInfo;
-use linked_list_allocator::LockedHeap;
-use concurrent::thread;
-use io::{print, println};
-use syscall::{syscall1, SystemCall};
+extern crate alloc;
 
-extern {
-    fn main();
-}
+pub mod env;
+pub mod fs;
+pub mod process;
+pub mod time;
 
-const HEAP_SIZE: usize = 0x100000;
-
-#[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
-
-#[panic_handler]
-fn panic(info: &PanicInfo) -> ! {
-    println!("Panic: {}!", info);
-    thread::exit();
-}
-
-#[no_mangle]
-extern "C" fn entry() {
-    let heap_start = syscall1(SystemCall::MapUserHeap, HEAP_SIZE) as *mut u8;
-    unsafe { ALLOCATOR.lock().init(heap_start, HEAP_SIZE); }
-
-    unsafe { main(); }
-    thread::exit();
-}
\ No newline at end of file
+// Built for hhuTOSr only, so that applications can also be built for the host, where unit tests run with the standard library
+#[cfg(target_os = "none")]
+mod start;
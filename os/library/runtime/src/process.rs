@@ -0,0 +1,52 @@
+use alloc::vec::Vec;
+use concurrent::thread;
+use concurrent::thread::Thread;
+use syscall::error::Errno;
+use syscall::process::{Argument, StartFlags};
+
+pub use concurrent::process::{current, Process};
+
+/// Application running in another process, which has been started by `spawn()`.
+pub struct Child {
+    thread: Thread
+}
+
+/// Start the application at `path` (names without a leading '/' are searched in '/bin') in a new process.
+/// Its command line consists of `path` followed by `arguments` (see `env::args()`). Scripts starting with '#!<interpreter>' are run by the interpreter.
+/// There is no `fork()` and `exec()`: The new process always runs a new application, but it inherits all open files
+/// (except for those marked with `DescriptorFlags::CLOSE_ON_EXEC`), so the standard descriptors can be redirected before starting it.
+pub fn spawn(path: &str, arguments: &[&str]) -> Result<Child, Errno> {
+    return start(path, arguments, StartFlags::empty());
+}
+
+/// Start an application like `spawn()`, but in the background (it does not receive `Signal::Interrupt`, when Ctrl+C is pressed).
+pub fn spawn_background(path: &str, arguments: &[&str]) -> Result<Child, Errno> {
+    return start(path, arguments, StartFlags::BACKGROUND);
+}
+
+fn start(path: &str, arguments: &[&str], flags: StartFlags) -> Result<Child, Errno> {
+    let arguments = arguments.iter().map(|argument| Argument::new(argument)).collect::<Vec<Argument>>();
+    return thread::start_application(path, &arguments, flags).map(|thread| Child { thread });
+}
+
+impl Child {
+    /// Id of the main thread of the application.
+    pub fn id(&self) -> usize {
+        return self.thread.id();
+    }
+
+    /// Check, if the application is still running (without waiting for it).
+    pub fn is_running(&self) -> bool {
+        return self.thread.is_running();
+    }
+
+    /// Wait until the main thread of the application has exited.
+    pub fn wait(self) {
+        self.thread.join();
+    }
+}
+
+/// Exit the current process (closing all of its open files).
+pub fn exit() -> ! {
+    thread::exit();
+}
@@ -0,0 +1,34 @@
+use core::time::Duration;
+use concurrent::thread;
+use syscall::{syscall0, SystemCall};
+
+/// Point in time, measured by a monotonic clock, which starts at boot (like `std::time::Instant`).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant {
+    ns: u64
+}
+
+impl Instant {
+    pub fn now() -> Self {
+        return Self { ns: syscall0(SystemCall::SystemTime) as u64 };
+    }
+
+    /// Time since `earlier` (zero, if `earlier` is later than this instant).
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        return Duration::from_nanos(self.ns.saturating_sub(earlier.ns));
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        return Instant::now().duration_since(*self);
+    }
+}
+
+/// Time since booting.
+pub fn uptime() -> Duration {
+    return Duration::from_nanos(Instant::now().ns);
+}
+
+/// Block the current thread for at least `duration`.
+pub fn sleep(duration: Duration) {
+    thread::nanosleep(duration.as_nanos() as usize);
+}
@@ -0,0 +1,103 @@
+/// Error codes, shared between kernel and user space (compatible to the POSIX numbering on x86_64 Linux).
+#[repr(usize)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[allow(dead_code)]
+pub enum Errno {
+    NotPermitted = 1,
+    NotFound = 2,
+    IoError = 5,
+    /// The file is not a valid executable.
+    ExecFormat = 8,
+    BadDescriptor = 9,
+    WouldBlock = 11,
+    OutOfMemory = 12,
+    AccessDenied = 13,
+    Busy = 16,
+    AlreadyExists = 17,
+    CrossDevice = 18,
+    NoDevice = 19,
+    NotADirectory = 20,
+    IsADirectory = 21,
+    InvalidArgument = 22,
+    TooManyFiles = 24,
+    NotATerminal = 25,
+    FileTooLarge = 27,
+    NoSpace = 28,
+    IllegalSeek = 29,
+    ReadOnly = 30,
+    BrokenPipe = 32,
+    NameTooLong = 36,
+    NotSupported = 38,
+    NotEmpty = 39,
+    MessageTooLong = 90,
+    AddressInUse = 98,
+    NetworkUnreachable = 101,
+    ConnectionReset = 104,
+    IsConnected = 106,
+    NotConnected = 107,
+    TimedOut = 110,
+    ConnectionRefused = 111,
+    HostUnreachable = 113,
+}
+
+impl TryFrom<usize> for Errno {
+    type Error = ();
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Errno::NotPermitted),
+            2 => Ok(Errno::NotFound),
+            5 => Ok(Errno::IoError),
+            8 => Ok(Errno::ExecFormat),
+            9 => Ok(Errno::BadDescriptor),
+            11 => Ok(Errno::WouldBlock),
+            12 => Ok(Errno::OutOfMemory),
+            13 => Ok(Errno::AccessDenied),
+            16 => Ok(Errno::Busy),
+            17 => Ok(Errno::AlreadyExists),
+            18 => Ok(Errno::CrossDevice),
+            19 => Ok(Errno::NoDevice),
+            20 => Ok(Errno::NotADirectory),
+            21 => Ok(Errno::IsADirectory),
+            22 => Ok(Errno::InvalidArgument),
+            24 => Ok(Errno::TooManyFiles),
+            25 => Ok(Errno::NotATerminal),
+            27 => Ok(Errno::FileTooLarge),
+            28 => Ok(Errno::NoSpace),
+            29 => Ok(Errno::IllegalSeek),
+            30 => Ok(Errno::ReadOnly),
+            32 => Ok(Errno::BrokenPipe),
+            36 => Ok(Errno::NameTooLong),
+            38 => Ok(Errno::NotSupported),
+            39 => Ok(Errno::NotEmpty),
+            90 => Ok(Errno::MessageTooLong),
+            98 => Ok(Errno::AddressInUse),
+            101 => Ok(Errno::NetworkUnreachable),
+            104 => Ok(Errno::ConnectionReset),
+            106 => Ok(Errno::IsConnected),
+            107 => Ok(Errno::NotConnected),
+            110 => Ok(Errno::TimedOut),
+            111 => Ok(Errno::ConnectionRefused),
+            113 => Ok(Errno::HostUnreachable),
+            _ => Err(()),
+        }
+    }
+}
+
+/// File related system calls return errors as negated error codes (e.g. `-2` for `Errno::NotFound`).
+pub fn to_syscall_result(result: Result<usize, Errno>) -> usize {
+    return match result {
+        Ok(value) => value,
+        Err(errno) => (-(errno as isize)) as usize
+    };
+}
+
+/// Convert the return value of a file related system call back into a `Result`.
+pub fn from_syscall_result(value: usize) -> Result<usize, Errno> {
+    let signed = value as isize;
+    if signed >= 0 {
+        return Ok(value);
+    }
+
+    return Err(Errno::try_from(signed.unsigned_abs()).unwrap_or(Errno::IoError));
+}
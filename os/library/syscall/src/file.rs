@@ -0,0 +1,235 @@
+use bitflags::bitflags;
+
+/// Descriptors, which are opened for each process and connected to the terminal.
+pub const STDIN: usize = 0;
+pub const STDOUT: usize = 1;
+pub const STDERR: usize = 2;
+
+bitflags! {
+    /// Flags for opening a file, shared between kernel and user space.
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub struct OpenFlags: usize {
+        const READ = 0x01;
+        const WRITE = 0x02;
+        const READ_WRITE = Self::READ.bits() | Self::WRITE.bits();
+        /// Create the file, if it does not exist.
+        const CREATE = 0x04;
+        /// Fail, if the file already exists (only in combination with `CREATE`).
+        const EXCLUSIVE = 0x08;
+        /// Set the size of the file to 0 after opening it.
+        const TRUNCATE = 0x10;
+        /// Move the file offset to the end of the file before each write.
+        const APPEND = 0x20;
+        /// Fail, if the path does not point to a directory.
+        const DIRECTORY = 0x40;
+        /// Set `DescriptorFlags::CLOSE_ON_EXEC` for the new descriptor.
+        const CLOSE_ON_EXEC = 0x80;
+    }
+}
+
+bitflags! {
+    /// Flags of a single descriptor (not shared with other descriptors referring to the same open file).
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub struct DescriptorFlags: usize {
+        /// Do not pass the descriptor on to applications started by the process.
+        const CLOSE_ON_EXEC = 0x01;
+    }
+}
+
+#[repr(usize)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FileType {
+    Regular = 0,
+    Directory = 1,
+    CharDevice = 2,
+    BlockDevice = 3,
+    Fifo = 4,
+    Symlink = 5,
+    Socket = 6,
+}
+
+/// Metadata of a file, as returned by the `Stat` and `FileStat` system calls.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct FileStatus {
+    pub inode: u64,
+    pub typ: FileType,
+    pub size: usize,
+    /// Timestamps in milliseconds since boot.
+    pub created_ms: usize,
+    pub modified_ms: usize,
+}
+
+/// Maximum length of the name in a `DirectoryEntry` in bytes (longer names are cut off).
+pub const MAX_NAME_LENGTH: usize = 255;
+
+/// Entry of a directory, as returned by the `ReadDirectory` system call.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct DirectoryEntry {
+    pub inode: u64,
+    pub typ: FileType,
+    name_length: usize,
+    name: [u8; MAX_NAME_LENGTH],
+}
+
+impl DirectoryEntry {
+    pub const fn empty() -> Self {
+        return Self { inode: 0, typ: FileType::Regular, name_length: 0, name: [0; MAX_NAME_LENGTH] };
+    }
+
+    pub fn new(inode: u64, typ: FileType, name: &str) -> Self {
+        let mut length = usize::min(name.len(), MAX_NAME_LENGTH);
+        while !name.is_char_boundary(length) {
+            length -= 1;
+        }
+
+        let mut entry = Self { inode, typ, name_length: length, name: [0; MAX_NAME_LENGTH] };
+        entry.name[..length].copy_from_slice(&name.as_bytes()[..length]);
+        return entry;
+    }
+
+    pub fn name(&self) -> &str {
+        return core::str::from_utf8(&self.name[..self.name_length]).unwrap_or("");
+    }
+}
+
+/// Reference point for the `Seek` system call.
+#[repr(usize)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SeekWhence {
+    Start = 0,
+    Current = 1,
+    End = 2,
+}
+
+impl TryFrom<usize> for SeekWhence {
+    type Error = ();
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(SeekWhence::Start),
+            1 => Ok(SeekWhence::Current),
+            2 => Ok(SeekWhence::End),
+            _ => Err(()),
+        }
+    }
+}
+
+bitflags! {
+    /// Events for the `Poll` system call (see `PollDescriptor`).
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub struct PollEvents: usize {
+        /// Reading does not block.
+        const READABLE = 0x01;
+        /// Writing does not block.
+        const WRITABLE = 0x04;
+        /// All readers of a pipe are gone, so that writing fails (always reported).
+        const ERROR = 0x08;
+        /// All writers of a pipe are gone, so that reading returns end of file (always reported).
+        const HANG_UP = 0x10;
+        /// The descriptor is not open (always reported).
+        const INVALID = 0x20;
+    }
+}
+
+/// Timeout for waiting with the `Poll` system call until an event occurs.
+pub const POLL_INFINITE: usize = usize::MAX;
+
+/// A descriptor to be watched by the `Poll` system call, which sets `ready` to the events that occurred.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct PollDescriptor {
+    pub fd: usize,
+    pub events: PollEvents,
+    pub ready: PollEvents,
+}
+
+impl PollDescriptor {
+    pub fn new(fd: usize, events: PollEvents) -> Self {
+        Self { fd, events, ready: PollEvents::empty() }
+    }
+}
+
+bitflags! {
+    /// Events reported by a watch descriptor (see `WatchEvent`).
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub struct WatchEvents: usize {
+        /// A file has been created in (or moved into) the watched directory.
+        const CREATE = 0x01;
+        /// The watched file has been written to or truncated.
+        const MODIFY = 0x02;
+        /// A file has been removed from (or moved out of) the watched directory, or the watched file itself has been removed.
+        const DELETE = 0x04;
+        /// Events have been lost, because they were not read fast enough (reported without a watch).
+        const OVERFLOW = 0x80;
+    }
+}
+
+/// Maximum length of the name in a `WatchEvent`.
+pub const WATCH_NAME_LENGTH: usize = 255;
+
+/// Record read from a watch descriptor. Reading always returns whole records.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct WatchEvent {
+    /// Id of the watch, as returned when adding it.
+    pub watch: usize,
+    pub events: WatchEvents,
+    /// Name of the affected file inside a watched directory (empty for events on the watched file itself).
+    pub name_length: usize,
+    pub name: [u8; WATCH_NAME_LENGTH],
+}
+
+impl WatchEvent {
+    /// Longer names are truncated to `WATCH_NAME_LENGTH` bytes (at a character boundary, so that the name stays valid UTF-8).
+    pub fn new(watch: usize, events: WatchEvents, name: &str) -> Self {
+        let mut length = name.len().min(WATCH_NAME_LENGTH);
+        while !name.is_char_boundary(length) {
+            length -= 1;
+        }
+
+        let mut event = Self { watch, events, name_length: length, name: [0; WATCH_NAME_LENGTH] };
+        event.name[..length].copy_from_slice(&name.as_bytes()[..length]);
+        event
+    }
+
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.name_length]).unwrap_or("")
+    }
+}
+
+bitflags! {
+    /// Operations for advisory whole-file locks (see `Lock` system call).
+    /// Either `SHARED`, `EXCLUSIVE` or `UNLOCK` must be given.
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub struct LockOperation: usize {
+        /// Acquire a shared lock, which can be held by multiple open files at once.
+        const SHARED = 0x01;
+        /// Acquire an exclusive lock, which can only be held by one open file.
+        const EXCLUSIVE = 0x02;
+        /// Fail with `Errno::WouldBlock` instead of waiting for a conflicting lock to be released.
+        const NON_BLOCKING = 0x04;
+        const UNLOCK = 0x08;
+    }
+}
+
+bitflags! {
+    /// Flags for creating an event counter (see `EventCounter` system call).
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub struct EventCounterFlags: usize {
+        /// Set `DescriptorFlags::CLOSE_ON_EXEC` for the new descriptor.
+        const CLOSE_ON_EXEC = 0x01;
+        /// Reading decrements the counter by one, instead of returning and resetting its value.
+        const SEMAPHORE = 0x02;
+    }
+}
+
+bitflags! {
+    /// Flags for creating a timer file (see `TimerCreate` system call).
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub struct TimerFlags: usize {
+        /// Set `DescriptorFlags::CLOSE_ON_EXEC` for the new descriptor.
+        const CLOSE_ON_EXEC = 0x01;
+    }
+}
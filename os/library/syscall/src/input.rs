@@ -0,0 +1,82 @@
+use bitflags::bitflags;
+
+bitflags! {
+    /// Mouse buttons, which are held down.
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub struct MouseButtons: u8 {
+        const LEFT = 0x01;
+        const RIGHT = 0x02;
+        const MIDDLE = 0x04;
+    }
+}
+
+/// Record read from '/dev/mouse'. Reading always returns whole records.
+/// Movement is relative to the previous event and uses screen coordinates (positive `dy` moves down).
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct MouseEvent {
+    pub dx: i16,
+    pub dy: i16,
+    /// Positive values scroll down (always 0 for mice without a scroll wheel).
+    pub wheel: i8,
+    pub buttons: MouseButtons,
+}
+
+bitflags! {
+    /// Modifier keys, which are held down, and lock keys, which are active.
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub struct Modifiers: u8 {
+        const SHIFT = 0x01;
+        const CTRL = 0x02;
+        const ALT = 0x04;
+        const ALT_GR = 0x08;
+        const META = 0x10;
+        const CAPS_LOCK = 0x20;
+        const NUM_LOCK = 0x40;
+    }
+}
+
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum InputEventType {
+    /// Marks the end of a group of events, which belong together (e.g. the movement and buttons of a mouse packet).
+    Sync = 0,
+    /// A key or button has been pressed (value 1), released (value 0) or is held down and repeated (value 2).
+    Key = 1,
+    /// Relative movement along an axis (e.g. of a mouse or scroll wheel).
+    Relative = 2,
+    /// Absolute position on an axis (e.g. of the mouse pointer inside a window).
+    Absolute = 3,
+}
+
+/// Record read from '/dev/input/eventN' (modeled after Linux evdev). Reading always returns whole records.
+/// Key codes follow the Linux numbering, which matches scancode set 1 for keys without an extended prefix.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct InputEvent {
+    /// Time in milliseconds since boot.
+    pub time_ms: u64,
+    pub typ: InputEventType,
+    pub code: u16,
+    pub value: i32,
+}
+
+/// Codes of `InputEventType::Relative` events. Like for `MouseEvent`, positive values move right and down (or scroll down).
+pub const RELATIVE_X: u16 = 0x00;
+pub const RELATIVE_Y: u16 = 0x01;
+pub const RELATIVE_WHEEL: u16 = 0x08;
+
+/// Codes of `InputEventType::Absolute` events. Windows receive the position of the pointer relative to the upper left corner of their surface.
+pub const ABSOLUTE_X: u16 = 0x00;
+pub const ABSOLUTE_Y: u16 = 0x01;
+
+/// Codes of `InputEventType::Key` events for mouse buttons.
+pub const BUTTON_LEFT: u16 = 0x110;
+pub const BUTTON_RIGHT: u16 = 0x111;
+pub const BUTTON_MIDDLE: u16 = 0x112;
+
+impl InputEvent {
+    pub fn new(typ: InputEventType, code: u16, value: i32) -> Self {
+        Self { time_ms: 0, typ, code, value }
+    }
+}
@@ -0,0 +1,328 @@
+use bitflags::bitflags;
+
+/// Requests for the `Ioctl` system call, which are passed on to the driver of the opened file.
+/// Drivers return `Errno::NotATerminal` for requests, they do not support.
+#[repr(usize)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IoctlRequest {
+    /// Write the size of the terminal into the `WindowSize`, the argument points to.
+    GetWindowSize = 0x5401,
+    /// Set the size of the terminal to the `WindowSize`, the argument points to
+    /// (only for terminals, whose size is determined by the remote side, like serial terminals).
+    SetWindowSize = 0x5402,
+    /// Return the current `TerminalMode`.
+    GetTerminalMode = 0x5403,
+    /// Switch to the `TerminalMode`, given as argument.
+    SetTerminalMode = 0x5404,
+    /// Write the `Termios` settings of the terminal into the struct, the argument points to.
+    GetTermios = 0x5405,
+    /// Apply the `Termios` settings, the argument points to. Input, which has not been read yet, is kept.
+    SetTermios = 0x5406,
+    /// Return the current `KeyboardLayout`.
+    GetKeyboardLayout = 0x4b00,
+    /// Switch to the `KeyboardLayout`, given as argument (only for terminals with a keyboard).
+    SetKeyboardLayout = 0x4b01,
+    /// Return the `Modifiers` (see `input::Modifiers`), which are currently active.
+    GetKeyboardModifiers = 0x4b02,
+    /// Write the `KeyRepeat` settings into the struct, the argument points to.
+    GetKeyRepeat = 0x4b03,
+    /// Apply the `KeyRepeat` settings, the argument points to.
+    SetKeyRepeat = 0x4b04,
+    /// Write the `FramebufferInfo` into the struct, the argument points to (also the format of the surface of a window).
+    GetFramebufferInfo = 0x4600,
+    /// Switch a graphics device (e.g. '/dev/gpu0') to the `DisplayMode`, the argument points to, and show its framebuffer.
+    /// The previous framebuffer contents are lost and the framebuffer needs to be mapped again.
+    SetDisplayMode = 0x4601,
+    /// Show the changes in the `Rectangle` of the framebuffer of a graphics device, the argument points to (the whole framebuffer, if 0).
+    FlushFramebuffer = 0x4602,
+    /// Copy an area of the framebuffer of a graphics device within the framebuffer and show the result (the argument points to a `Blit`).
+    /// Overlapping areas are allowed (e.g. for scrolling). Both areas are clipped to the framebuffer.
+    BlitFramebuffer = 0x4603,
+    /// Create a window on an opened '/dev/window' with the `WindowGeometry`, the argument points to.
+    /// Its surface can then be mapped with `map_file()` and the window receives input events, while it has the focus.
+    CreateWindow = 0x5700,
+    /// Show the changes, which have been drawn into the surface of the window.
+    PresentWindow = 0x5701,
+    /// Write the `AudioFormat` of an audio device (e.g. '/dev/audio0') into the struct, the argument points to.
+    GetAudioFormat = 0x4100,
+    /// Play the following samples in the `AudioFormat`, the argument points to. Samples, which have already been written, are played at the new rate.
+    SetAudioFormat = 0x4101,
+    /// Write the `AudioVolume` of an audio device into the struct, the argument points to.
+    GetAudioVolume = 0x4102,
+    /// Apply the `AudioVolume`, the argument points to.
+    SetAudioVolume = 0x4103,
+    /// Add the `RouteEntry`, the argument points to, to the routing table (on any socket).
+    AddRoute = 0x890b,
+    /// Remove the route for the destination network of the `RouteEntry`, the argument points to (on any socket).
+    DeleteRoute = 0x890c,
+    /// Apply the `InterfaceAddress`, the argument points to, replacing the on-link route of the interface (on any socket).
+    /// An unspecified address removes the address and all routes via the interface.
+    SetInterfaceAddress = 0x8916,
+    /// Start capturing frames on '/dev/pcap'. The argument points to the name of the interface (see `encode_interface_name()`)
+    /// or is 0 for capturing on all interfaces. Captured frames, which have not been read yet, are discarded.
+    StartCapture = 0x8940,
+    /// Stop capturing frames on '/dev/pcap'. Frames, which have already been captured, can still be read.
+    StopCapture = 0x8941,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WindowSize {
+    pub columns: u16,
+    pub rows: u16,
+}
+
+/// Shortcut for the most common `Termios` settings.
+#[repr(usize)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TerminalMode {
+    /// Input is echoed and reading returns after a line break.
+    Canonical = 0,
+    /// Input is not echoed and reading returns as soon as at least one character is available.
+    Raw = 1,
+}
+
+bitflags! {
+    /// Behaviour of the line discipline of a terminal (see `Termios`).
+    #[repr(transparent)]
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub struct TermiosFlags: u32 {
+        /// Echo input (control characters in caret notation, e.g. '^A').
+        const ECHO = 0x01;
+        /// Collect input into lines, which can be edited with the control characters in `Termios`, before passing them to the reader.
+        /// Otherwise, reading returns as soon as at least one byte is available.
+        const CANONICAL = 0x02;
+        /// Ctrl+C raises `Signal::Interrupt` for the foreground process, instead of being read as character.
+        const SIGNALS = 0x04;
+    }
+}
+
+/// Settings of the line discipline of a terminal (like `termios` on Unix).
+/// The control characters are only used in canonical mode and can be disabled by setting them to 0.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Termios {
+    pub flags: TermiosFlags,
+    /// Remove the last character of the line (Backspace).
+    pub erase: u8,
+    /// Remove the last word of the line (Ctrl+W).
+    pub word_erase: u8,
+    /// Remove the whole line (Ctrl+U).
+    pub kill: u8,
+    /// Pass the line to the reader without a line break. On an empty line, reading returns 0 (end of file) (Ctrl+D).
+    pub end_of_file: u8,
+}
+
+impl Termios {
+    pub const fn canonical() -> Self {
+        Self { flags: TermiosFlags::ECHO.union(TermiosFlags::CANONICAL).union(TermiosFlags::SIGNALS), erase: 0x08, word_erase: 0x17, kill: 0x15, end_of_file: 0x04 }
+    }
+
+    pub const fn raw() -> Self {
+        Self { flags: TermiosFlags::empty(), ..Termios::canonical() }
+    }
+}
+
+/// Layouts for translating key presses into characters. Accents are typed with dead keys (e.g. '^' followed by 'a' for 'â').
+#[repr(usize)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KeyboardLayout {
+    /// German layout with 105 keys. Dead keys: '^', '´', '`'.
+    German = 0,
+    /// US layout with 104 keys. No dead keys.
+    Us = 1,
+    /// US layout with dead keys for accents ('\'', '"', '`', '^', '~') and umlauts typed with AltGr (e.g. AltGr+q for 'ä').
+    UsInternational = 2,
+    /// UK layout with 105 keys. No dead keys.
+    Uk = 3,
+    /// French AZERTY layout. Dead keys: '^', '¨'.
+    French = 4,
+}
+
+/// Keys held down are repeated after `delay_ms` milliseconds, `rate` times per second (0 disables key repeat).
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct KeyRepeat {
+    pub delay_ms: u32,
+    pub rate: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct FramebufferInfo {
+    pub width: u32,
+    pub height: u32,
+    /// Bytes per line.
+    pub pitch: u32,
+    /// Bits per pixel.
+    pub bpp: u8,
+}
+
+/// Resolution of a graphics device. Framebuffers of graphics devices always have 32 bits per pixel.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DisplayMode {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rectangle {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Copy of the `source` area of a framebuffer to the position (`x`, `y`), see `IoctlRequest::BlitFramebuffer`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Blit {
+    pub source: Rectangle,
+    pub x: u32,
+    pub y: u32,
+}
+
+/// Position of a window (the upper left corner of its title bar) on the screen and size of its surface in pixels.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WindowGeometry {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Format of the samples written to an audio device. Samples are signed and little endian, the channels of a frame are interleaved.
+/// The supported formats depend on the device (AC'97 controllers only support 16-bit stereo samples at 8 - 48 kHz).
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AudioFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bits_per_sample: u16,
+}
+
+/// Volume of the left and right channel in percent (0 mutes a channel).
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AudioVolume {
+    pub left: u8,
+    pub right: u8,
+}
+
+/// Maximum length of an interface name, including the terminating zero (like `IFNAMSIZ` on Linux).
+pub const INTERFACE_NAME_SIZE: usize = 16;
+
+/// Route to the network `destination/prefix_length` (the default route has prefix length 0).
+/// Packets are sent via the interface called `interface` to `gateway` or directly to their destination, if `gateway` is unspecified.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RouteEntry {
+    pub destination: [u8; 4],
+    pub prefix_length: u8,
+    pub gateway: [u8; 4],
+    pub interface: [u8; INTERFACE_NAME_SIZE],
+}
+
+/// IPv4 address and network prefix length of the interface called `interface`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct InterfaceAddress {
+    pub interface: [u8; INTERFACE_NAME_SIZE],
+    pub address: [u8; 4],
+    pub prefix_length: u8,
+}
+
+impl TryFrom<usize> for IoctlRequest {
+    type Error = ();
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        match value {
+            0x5401 => Ok(IoctlRequest::GetWindowSize),
+            0x5402 => Ok(IoctlRequest::SetWindowSize),
+            0x5403 => Ok(IoctlRequest::GetTerminalMode),
+            0x5404 => Ok(IoctlRequest::SetTerminalMode),
+            0x5405 => Ok(IoctlRequest::GetTermios),
+            0x5406 => Ok(IoctlRequest::SetTermios),
+            0x4b00 => Ok(IoctlRequest::GetKeyboardLayout),
+            0x4b01 => Ok(IoctlRequest::SetKeyboardLayout),
+            0x4b02 => Ok(IoctlRequest::GetKeyboardModifiers),
+            0x4b03 => Ok(IoctlRequest::GetKeyRepeat),
+            0x4b04 => Ok(IoctlRequest::SetKeyRepeat),
+            0x4600 => Ok(IoctlRequest::GetFramebufferInfo),
+            0x4601 => Ok(IoctlRequest::SetDisplayMode),
+            0x4602 => Ok(IoctlRequest::FlushFramebuffer),
+            0x4603 => Ok(IoctlRequest::BlitFramebuffer),
+            0x5700 => Ok(IoctlRequest::CreateWindow),
+            0x5701 => Ok(IoctlRequest::PresentWindow),
+            0x4100 => Ok(IoctlRequest::GetAudioFormat),
+            0x4101 => Ok(IoctlRequest::SetAudioFormat),
+            0x4102 => Ok(IoctlRequest::GetAudioVolume),
+            0x4103 => Ok(IoctlRequest::SetAudioVolume),
+            0x890b => Ok(IoctlRequest::AddRoute),
+            0x890c => Ok(IoctlRequest::DeleteRoute),
+            0x8916 => Ok(IoctlRequest::SetInterfaceAddress),
+            0x8940 => Ok(IoctlRequest::StartCapture),
+            0x8941 => Ok(IoctlRequest::StopCapture),
+            _ => Err(()),
+        }
+    }
+}
+
+impl TryFrom<usize> for TerminalMode {
+    type Error = ();
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(TerminalMode::Canonical),
+            1 => Ok(TerminalMode::Raw),
+            _ => Err(()),
+        }
+    }
+}
+
+impl KeyboardLayout {
+    pub const ALL: [KeyboardLayout; 5] = [KeyboardLayout::German, KeyboardLayout::Us, KeyboardLayout::UsInternational, KeyboardLayout::Uk, KeyboardLayout::French];
+
+    /// Short name, as used by the shell (e.g. 'de').
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyboardLayout::German => "de",
+            KeyboardLayout::Us => "us",
+            KeyboardLayout::UsInternational => "us-intl",
+            KeyboardLayout::Uk => "uk",
+            KeyboardLayout::French => "fr",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        KeyboardLayout::ALL.into_iter().find(|layout| layout.name() == name)
+    }
+}
+
+impl TryFrom<usize> for KeyboardLayout {
+    type Error = ();
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        KeyboardLayout::ALL.into_iter().find(|layout| *layout as usize == value).ok_or(())
+    }
+}
+
+/// Encode an interface name for `RouteEntry` and `InterfaceAddress` (fails, if it is too long).
+pub fn encode_interface_name(name: &str) -> Option<[u8; INTERFACE_NAME_SIZE]> {
+    if name.len() >= INTERFACE_NAME_SIZE {
+        return None;
+    }
+
+    let mut encoded = [0; INTERFACE_NAME_SIZE];
+    encoded[..name.len()].copy_from_slice(name.as_bytes());
+    Some(encoded)
+}
+
+/// Decode a zero terminated interface name (fails, if it is not valid UTF-8).
+pub fn decode_interface_name(name: &[u8; INTERFACE_NAME_SIZE]) -> Option<&str> {
+    let length = name.iter().position(|&byte| byte == 0).unwrap_or(INTERFACE_NAME_SIZE);
+    core::str::from_utf8(&name[..length]).ok()
+}
@@ -1,7 +1,16 @@
 #![no_std]
 
 use core::arch::asm;
-use crate::SystemCall::ApplicationStart;
+use crate::SystemCall::TimerSet;
+
+pub mod error;
+pub mod file;
+pub mod input;
+pub mod ioctl;
+pub mod mqueue;
+pub mod net;
+pub mod process;
+pub mod signal;
 
 #[repr(usize)]
 #[allow(dead_code)]
@@ -15,10 +24,67 @@ pub enum SystemCall {
     ThreadSleep,
     ThreadJoin,
     ThreadExit,
-    ApplicationStart
+    ApplicationStart,
+    ThreadNanosleep,
+    IntervalTimer,
+    Alarm,
+    SignalAction,
+    Open,
+    Close,
+    Seek,
+    Stat,
+    FileStat,
+    MakeDirectory,
+    RemoveDirectory,
+    Unlink,
+    Rename,
+    Truncate,
+    ReadAt,
+    WriteAt,
+    Dup,
+    Dup2,
+    GetDescriptorFlags,
+    SetDescriptorFlags,
+    Pipe,
+    MakeFifo,
+    Poll,
+    Ioctl,
+    WatchCreate,
+    WatchAdd,
+    WatchRemove,
+    Lock,
+    Mount,
+    Unmount,
+    Fsync,
+    MapFile,
+    Shutdown,
+    Reboot,
+    GetRandom,
+    Socket,
+    Bind,
+    Connect,
+    Listen,
+    Accept,
+    Send,
+    Receive,
+    SendTo,
+    ReceiveFrom,
+    MessageQueueOpen,
+    MessageQueueUnlink,
+    MessageQueueSend,
+    MessageQueueReceive,
+    EventCounter,
+    SystemTime,
+    ThreadRunning,
+    GetEnvironment,
+    SetEnvironment,
+    ReadDirectory,
+    GetArguments,
+    TimerCreate,
+    TimerSet
 }
 
-pub const NUM_SYSCALLS: usize = ApplicationStart as usize + 1;
+pub const NUM_SYSCALLS: usize = TimerSet as usize + 1;
 
 #[inline(always)]
 pub fn syscall0(call: SystemCall) -> usize {
@@ -95,3 +161,72 @@ pub fn syscall3(call: SystemCall, arg1: usize, arg2: usize, arg3: usize) -> usiz
 
     return ret;
 }
+
+#[inline(always)]
+#[allow(dead_code)]
+pub fn syscall4(call: SystemCall, arg1: usize, arg2: usize, arg3: usize, arg4: usize) -> usize {
+    let ret: usize;
+
+    unsafe {
+        asm!(
+        "syscall",
+        inlateout("rax") call as usize => ret,
+        in("rdi") arg1,
+        in("rsi") arg2,
+        in("rdx") arg3,
+        in("r10") arg4,
+        out("rcx") _,
+        out("r11") _,
+        options(preserves_flags, nostack)
+        );
+    }
+
+    return ret;
+}
+
+#[inline(always)]
+#[allow(dead_code)]
+pub fn syscall5(call: SystemCall, arg1: usize, arg2: usize, arg3: usize, arg4: usize, arg5: usize) -> usize {
+    let ret: usize;
+
+    unsafe {
+        asm!(
+        "syscall",
+        inlateout("rax") call as usize => ret,
+        in("rdi") arg1,
+        in("rsi") arg2,
+        in("rdx") arg3,
+        in("r10") arg4,
+        in("r8") arg5,
+        out("rcx") _,
+        out("r11") _,
+        options(preserves_flags, nostack)
+        );
+    }
+
+    return ret;
+}
+
+#[inline(always)]
+#[allow(dead_code)]
+pub fn syscall6(call: SystemCall, arg1: usize, arg2: usize, arg3: usize, arg4: usize, arg5: usize, arg6: usize) -> usize {
+    let ret: usize;
+
+    unsafe {
+        asm!(
+        "syscall",
+        inlateout("rax") call as usize => ret,
+        in("rdi") arg1,
+        in("rsi") arg2,
+        in("rdx") arg3,
+        in("r10") arg4,
+        in("r8") arg5,
+        in("r9") arg6,
+        out("rcx") _,
+        out("r11") _,
+        options(preserves_flags, nostack)
+        );
+    }
+
+    return ret;
+}
@@ -0,0 +1,28 @@
+/// Messages with a higher priority are received before all messages with a lower priority (0 to `MAX_PRIORITY`).
+pub const MAX_PRIORITY: usize = 31;
+
+/// Upper limits for the attributes of a message queue.
+pub const MAX_CAPACITY: usize = 256;
+pub const MAX_MESSAGE_SIZE: usize = 8192;
+
+/// Attributes of a message queue, which are set when it is created by the `MessageQueueOpen` system call.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct QueueAttributes {
+    /// Maximum number of messages in the queue. Sending into a full queue blocks.
+    pub capacity: usize,
+    /// Maximum size of a single message. Receiving requires a buffer of at least this size.
+    pub message_size: usize,
+}
+
+impl QueueAttributes {
+    pub const fn new(capacity: usize, message_size: usize) -> Self {
+        Self { capacity, message_size }
+    }
+}
+
+impl Default for QueueAttributes {
+    fn default() -> Self {
+        return Self::new(16, 1024);
+    }
+}
@@ -0,0 +1,64 @@
+/// Type of a socket for the `Socket` system call.
+#[repr(usize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SocketType {
+    /// Reliable, connection oriented byte stream (e.g. TCP).
+    Stream = 1,
+    /// Unreliable, connectionless messages (e.g. UDP or ICMP echo).
+    Datagram = 2,
+}
+
+/// Protocol of a socket for the `Socket` system call (numbered like the IPv4 protocol field, except for `Unix`).
+#[repr(usize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    /// Echo requests and replies (like the ping sockets of Linux).
+    Icmp = 1,
+    Tcp = 6,
+    Udp = 17,
+    /// Local stream sockets, which are bound to and connected via paths in the filesystem instead of addresses.
+    Unix = 0x100,
+}
+
+/// IPv4 address and port, as passed to and returned by the socket system calls.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SocketAddress {
+    pub address: [u8; 4],
+    pub port: u16,
+}
+
+/// Largest number of connections, that may wait for being accepted by a listening socket.
+pub const MAX_BACKLOG: usize = 128;
+
+impl TryFrom<usize> for SocketType {
+    type Error = ();
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(SocketType::Stream),
+            2 => Ok(SocketType::Datagram),
+            _ => Err(()),
+        }
+    }
+}
+
+impl TryFrom<usize> for Protocol {
+    type Error = ();
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Protocol::Icmp),
+            6 => Ok(Protocol::Tcp),
+            17 => Ok(Protocol::Udp),
+            0x100 => Ok(Protocol::Unix),
+            _ => Err(()),
+        }
+    }
+}
+
+impl SocketAddress {
+    pub const fn new(address: [u8; 4], port: u16) -> Self {
+        Self { address, port }
+    }
+}
@@ -0,0 +1,29 @@
+use bitflags::bitflags;
+
+bitflags! {
+    /// Flags for starting an application (see `SystemCall::ApplicationStart`).
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub struct StartFlags: usize {
+        /// Do not make the new process the foreground process of its console, so that it does not receive `Signal::Interrupt` on Ctrl+C.
+        const BACKGROUND = 0x01;
+    }
+}
+
+/// Command line argument of an application, passed to `SystemCall::ApplicationStart` as an array of these
+/// (the string does not need to be terminated by a null byte).
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Argument {
+    pub address: *const u8,
+    pub length: usize,
+}
+
+impl Argument {
+    pub fn new(argument: &str) -> Self {
+        return Self { address: argument.as_ptr(), length: argument.len() };
+    }
+}
+
+/// Message queue of the init process, which accepts the messages "shutdown" and "reboot" (see `SystemCall::MessageQueueOpen`).
+/// The services are stopped in an orderly way, before the system is powered off or reset.
+pub const INIT_CONTROL_QUEUE: &str = "/initctl";
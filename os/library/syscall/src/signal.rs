@@ -0,0 +1,72 @@
+/// Signal numbers, shared between kernel and user space (compatible to the POSIX numbering on x86_64 Linux).
+#[repr(usize)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[allow(dead_code)]
+pub enum Signal {
+    Hangup = 1,
+    Interrupt = 2,
+    Quit = 3,
+    IllegalInstruction = 4,
+    Trap = 5,
+    Abort = 6,
+    BusError = 7,
+    FloatingPointException = 8,
+    Kill = 9,
+    User1 = 10,
+    SegmentationFault = 11,
+    User2 = 12,
+    BrokenPipe = 13,
+    Alarm = 14,
+    Terminate = 15,
+    Child = 17,
+}
+
+pub const MAX_SIGNALS: usize = 32;
+
+/// Dispositions, which can be passed to the `SignalAction` system call.
+#[repr(usize)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SignalDisposition {
+    Default = 0,
+    Ignore = 1,
+    Handle = 2,
+}
+
+impl TryFrom<usize> for Signal {
+    type Error = ();
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Signal::Hangup),
+            2 => Ok(Signal::Interrupt),
+            3 => Ok(Signal::Quit),
+            4 => Ok(Signal::IllegalInstruction),
+            5 => Ok(Signal::Trap),
+            6 => Ok(Signal::Abort),
+            7 => Ok(Signal::BusError),
+            8 => Ok(Signal::FloatingPointException),
+            9 => Ok(Signal::Kill),
+            10 => Ok(Signal::User1),
+            11 => Ok(Signal::SegmentationFault),
+            12 => Ok(Signal::User2),
+            13 => Ok(Signal::BrokenPipe),
+            14 => Ok(Signal::Alarm),
+            15 => Ok(Signal::Terminate),
+            17 => Ok(Signal::Child),
+            _ => Err(()),
+        }
+    }
+}
+
+impl TryFrom<usize> for SignalDisposition {
+    type Error = ();
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(SignalDisposition::Default),
+            1 => Ok(SignalDisposition::Ignore),
+            2 => Ok(SignalDisposition::Handle),
+            _ => Err(()),
+        }
+    }
+}
@@ -6,9 +6,12 @@ readonly CONST_QEMU_CPU="qemu64"
 readonly CONST_QEMU_MACHINE_PC_KVM="pc,accel=kvm,kernel-irqchip=split"
 readonly CONST_QEMU_DEFAULT_RAM="128M"
 readonly CONST_QEMU_BIOS_EFI="efi/OVMF.fd"
-readonly CONST_QEMU_ARGS="-boot d -vga std -rtc base=localtime -device isa-debug-exit"
-readonly CONST_QEMU_OLD_AUDIO_ARGS="-soundhw pcspk"
-readonly CONST_QEMU_NEW_AUDIO_ARGS="-audiodev id=pa,driver=pa -machine pcspk-audiodev=pa"
+readonly CONST_QEMU_DEFAULT_VGA="std"
+readonly CONST_QEMU_ARGS="-boot d -rtc base=localtime -device isa-debug-exit -device virtio-rng-pci"
+readonly CONST_QEMU_OLD_AUDIO_ARGS="-soundhw pcspk,ac97"
+readonly CONST_QEMU_NEW_AUDIO_ARGS="-audiodev id=pa,driver=pa -machine pcspk-audiodev=pa -device AC97,audiodev=pa"
+readonly CONST_QEMU_USB_INPUT_ARGS="-device qemu-xhci -device usb-kbd -device usb-mouse"
+readonly CONST_QEMU_RTL8139_NETWORK_ARGS="-netdev user,id=net0 -device rtl8139,netdev=net0"
 readonly CONST_QEMU_BOOT_DEVICE="-drive driver=raw,node-name=boot,file.driver=file,file.filename=hhuTOSr.img"
 
 QEMU_BIOS=""
@@ -16,11 +19,16 @@ QEMU_MACHINE="${CONST_QEMU_MACHINE_PC}"
 QEMU_RAM="${CONST_QEMU_DEFAULT_RAM}"
 QEMU_CPU="${CONST_QEMU_CPU}"
 QEMU_CPU_OVERWRITE="false"
+QEMU_VGA="${CONST_QEMU_DEFAULT_VGA}"
 QEMU_AUDIO_ARGS="${CONST_QEMU_NEW_AUDIO_ARGS}"
+QEMU_INPUT_ARGS=""
+QEMU_NETWORK_ARGS=""
 QEMU_BOOT_DEVICE="${CONST_QEMU_BOOT_DEVICE}"
 QEMU_ARGS="${CONST_QEMU_ARGS}"
 
+QEMU_BOOT_OPTIONS=""
 QEMU_GDB_PORT=""
+QEMU_GDB_STUB_PORT=""
 
 version_lt() {
   test "$(printf "%s\n" "$@" | sort -V | tr ' ' '\n' | head -n 1)" != "${2}"
@@ -77,6 +85,43 @@ parse_machine() {
   fi
 }
 
+parse_vga() {
+  local vga=$1
+
+  if [ "${vga}" == "std" ] || [ "${vga}" == "virtio" ]; then
+    QEMU_VGA="${vga}"
+  else
+    printf "Invalid graphics adapter '%s'!\\n" "${vga}"
+    exit 1
+  fi
+}
+
+parse_input() {
+  local input=$1
+
+  if [ "${input}" == "ps2" ]; then
+    QEMU_INPUT_ARGS=""
+  elif [ "${input}" == "usb" ]; then
+    QEMU_INPUT_ARGS="${CONST_QEMU_USB_INPUT_ARGS}"
+  else
+    printf "Invalid input devices '%s'!\\n" "${input}"
+    exit 1
+  fi
+}
+
+parse_network() {
+  local network=$1
+
+  if [ "${network}" == "none" ]; then
+    QEMU_NETWORK_ARGS=""
+  elif [ "${network}" == "rtl8139" ]; then
+    QEMU_NETWORK_ARGS="${CONST_QEMU_RTL8139_NETWORK_ARGS}"
+  else
+    printf "Invalid network card '%s'!\\n" "${network}"
+    exit 1
+  fi
+}
+
 parse_ram() {
   local memory=$1
 
@@ -90,6 +135,14 @@ parse_cpu() {
   QEMU_CPU_OVERWRITE="true"
 }
 
+parse_append() {
+  local options=$1
+
+  # Passed as file, since the qemu command line is split at spaces
+  printf "%s" "${options}" >/tmp/hhutosr-cmdline."$(id -u)"
+  QEMU_BOOT_OPTIONS="-fw_cfg name=opt/hhutosr/cmdline,file=/tmp/hhutosr-cmdline.$(id -u)"
+}
+
 parse_debug() {
   local port=$1
 
@@ -100,6 +153,16 @@ parse_debug() {
   QEMU_GDB_PORT="${port}"
 }
 
+parse_gdb_stub() {
+  local port=$1
+
+  echo "set architecture i386:x86-64
+      set disassembly-flavor intel
+      target remote 127.0.0.1:${port}" >/tmp/gdbstubcommands."$(id -u)"
+
+  QEMU_GDB_STUB_PORT="${port}"
+}
+
 parse_bios() {
   local bios=$1
 
@@ -113,12 +176,22 @@ print_usage() {
         Set the .iso or .img file, which qemu should boot (Default: hhuTOSr-towboot.img)
     -m, --machine
         Set the machine profile, which qemu should emulate ([pc] | [pc-kvm]) (Defualt: pc)
+    -v, --vga
+        Set the graphics adapter, which qemu should emulate ([std] | [virtio]) (Default: std)
+    -i, --input
+        Set the keyboard and mouse, which qemu should emulate ([ps2] | [usb]) (Default: ps2)
+    -n, --network
+        Set the network card, which qemu should emulate ([none] | [rtl8139]) (Default: none)
     -r, --ram
         Set the amount of ram, which qemu should use (e.g. 256, 1G, ...) (Default: 128M)
     -c, --cpu
-        Set the CPU model, which qemu should emulate (e.g. 486, pentium, pentium2, ...) (Default: base)
+        Set the CPU model, which qemu should emulate (e.g. 486, pentium, pentium2, qemu64,+x2apic, ...) (Default: base)
+    -a, --append
+        Set additional kernel command line options, which are passed via fw_cfg (e.g. 'loglevel=debug console=serial')
     -d, --debug
         Set the port, on which qemu should listen for GDB clients (default: disabled)
+    -g, --gdb-stub
+        Set the port, on which the kernel's GDB stub should be reachable via a second serial port (default: disabled)
     -b, --bios
         Set the BIOS file, which qemu should use (Default: Download newest OVMF)
     -h, --help
@@ -134,15 +207,30 @@ parse_args() {
     -m | --machine)
       parse_machine "$val"
       ;;
+    -v | --vga)
+      parse_vga "$val"
+      ;;
+    -i | --input)
+      parse_input "$val"
+      ;;
+    -n | --network)
+      parse_network "$val"
+      ;;
     -r | --ram)
       parse_ram "$val"
       ;;
     -c | --cpu)
       parse_cpu "$val"
       ;;
+    -a | --append)
+      parse_append "$val"
+      ;;
     -d | --debug)
       parse_debug "$val"
       ;;
+    -g | --gdb-stub)
+      parse_gdb_stub "$val"
+      ;;
     -b | --bios)
       parse_bios "$val"
       ;;
@@ -167,7 +255,11 @@ run_qemu() {
     command="${command} -machine ${QEMU_MACHINE}"
   fi
 
-  command="${command} -m ${QEMU_RAM} -cpu ${QEMU_CPU} -bios ${QEMU_BIOS} ${QEMU_ARGS} ${QEMU_BOOT_DEVICE} ${QEMU_AUDIO_ARGS}"
+  if [ -n "${QEMU_GDB_STUB_PORT}" ]; then
+    QEMU_ARGS="${QEMU_ARGS} -serial vc -serial tcp::${QEMU_GDB_STUB_PORT},server,nowait"
+  fi
+
+  command="${command} -m ${QEMU_RAM} -cpu ${QEMU_CPU} -bios ${QEMU_BIOS} -vga ${QEMU_VGA} ${QEMU_ARGS} ${QEMU_BOOT_DEVICE} ${QEMU_AUDIO_ARGS} ${QEMU_INPUT_ARGS} ${QEMU_NETWORK_ARGS} ${QEMU_BOOT_OPTIONS}"
   
   printf "Running: %s\\n" "${command}"
 
@@ -179,9 +271,32 @@ run_qemu() {
     fi
   else
     $command
+    check_exit_status $?
   fi
 }
 
+check_exit_status() {
+  local status=$1
+
+  # The kernel exits via isa-debug-exit with the status (code << 1) | 1 (see 'qemu_cfg::ExitCode')
+  case ${status} in
+  33)
+    exit 0
+    ;;
+  35)
+    printf "Kernel test failed!\\n"
+    exit 1
+    ;;
+  37)
+    printf "Kernel panicked!\\n"
+    exit 2
+    ;;
+  *)
+    exit "${status}"
+    ;;
+  esac
+}
+
 parse_args "$@"
 
 if [ -z "${QEMU_BIOS}" ]; then